@@ -0,0 +1,42 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use json_tool::buffer::{Buffer, Edit};
+use std::time::Duration;
+
+/// An array of `count` records, each with a flag to flip
+fn records(count: usize) -> String {
+    let mut text = String::from("[\n");
+    for id in 0..count {
+        text.push_str(&format!("  {{\"id\": {}, \"active\": false, \"tags\": [\"a\"]}},\n", id));
+    }
+    text.push_str("  null\n]\n");
+    text
+}
+
+fn edit_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply_edits");
+    group.measurement_time(Duration::from_secs(10));
+    group.sample_size(20);
+    
+    // Flipping every flag in one batch; ten times the replacements should
+    // take about ten times as long
+    for count in [10_000, 100_000] {
+        let text = records(count);
+        let edits: Vec<Edit> = text
+            .match_indices("false")
+            .map(|(offset, _)| Edit { file_offset: offset, old_len: 5, new_text: "true".to_string() })
+            .collect();
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::new("replace", count), &edits, |b, edits| {
+            b.iter_batched(
+                || Buffer::from_text(&text),
+                |mut buffer| black_box(buffer.apply_edits(edits).unwrap()),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    
+    group.finish();
+}
+
+criterion_group!(benches, edit_benchmark);
+criterion_main!(benches);
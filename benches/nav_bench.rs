@@ -0,0 +1,47 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use json_tool::mode::StructuralNavAction;
+use json_tool::navigation::{self, ItemPositions};
+use json_tool::{StructuralIndex, Tokenizer};
+use std::time::Duration;
+
+/// A flattened telemetry dump: one object of `keys` keys
+fn wide_object(keys: usize) -> String {
+    let mut text = String::from("{\n");
+    for n in 0..keys {
+        text.push_str(&format!("  \"host.{}.cpu\": {}{}\n", n, n % 100, if n + 1 < keys { "," } else { "" }));
+    }
+    text.push_str("}\n");
+    text
+}
+
+fn key_navigation_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("key_navigation");
+    group.measurement_time(Duration::from_secs(5));
+    
+    // A keystroke of `]l` and of `]j`: the motion and the `item n/N` shown
+    // after it. Each should cost the same at every width.
+    for keys in [2_000, 20_000, 200_000] {
+        let text = wide_object(keys);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text).tokenize_all());
+        let first = index.first_child(0).unwrap();
+        group.throughput(Throughput::Elements(1));
+        
+        for (name, action) in [("next_key", StructuralNavAction::NextKey), ("next_sibling", StructuralNavAction::NextSibling)] {
+            group.bench_function(BenchmarkId::new(name, keys), |b| {
+                let mut positions = ItemPositions::new();
+                let mut current = first;
+                b.iter(|| {
+                    let offset = index.get(current).unwrap().start;
+                    let next = navigation::target(&index, action, Some(current), offset, 1).unwrap_or(first);
+                    black_box(positions.member(&index, next, 0));
+                    current = next;
+                })
+            });
+        }
+    }
+    
+    group.finish();
+}
+
+criterion_group!(benches, key_navigation_benchmark);
+criterion_main!(benches);
@@ -1,6 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
-use json_tool::buffer::Buffer;
-use std::time::Duration;
+use json_tool::buffer::{Buffer, Prefetcher, ViewLines};
+use std::time::{Duration, Instant};
 
 fn scroll_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("scroll");
@@ -30,6 +30,21 @@ fn scroll_benchmark(c: &mut Criterion) {
         );
     }
     
+    // The same viewport drawn again with nothing changed: the renderer keeps
+    // its lines and reads none of them
+    let mut view = ViewLines::new();
+    for size in [10, 20, 40, 80].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("view_lines_unchanged", size),
+            size,
+            |b, &size| {
+                b.iter(|| {
+                    black_box(view.fetch(&mut buffer, 0, size))
+                })
+            },
+        );
+    }
+    
     group.finish();
 }
 
@@ -67,5 +82,147 @@ fn tokenizer_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, scroll_benchmark, tokenizer_benchmark);
+fn highlight_benchmark(c: &mut Criterion) {
+    use json_tool::ui::highlight::{colorize_from_index, colorize_json_line};
+    use json_tool::ui::line_cache::LineCache;
+    use json_tool::ui::theme::Theme;
+    
+    let mut group = c.benchmark_group("highlight");
+    group.measurement_time(Duration::from_secs(10));
+    
+    // An 80-row by 200-column viewport at the top of the medium fixture
+    // (tests/medium.json, from generate_test_data), or the same shape of
+    // items generated here when it hasn't been built
+    const ROWS: usize = 80;
+    const COLS: usize = 200;
+    let mut buffer = Buffer::new();
+    let text = if buffer.load_file("tests/medium.json").is_ok() {
+        buffer.get_visible_lines(0, ROWS)
+    } else {
+        let items: Vec<String> = (0..10).map(|i| format!(
+            "  {{\n    \"id\": {i},\n    \"name\": \"item_{i}\",\n    \"data\": \"{}\",\n    \"timestamp\": {},\n    \"nested\": {{\n      \"level\": 1,\n      \"value\": {},\n      \"tags\": [\"tag1\", \"tag2\", \"tag3\"]\n    }}\n  }}",
+            "x".repeat(100), i * 1000, i * 2,
+        )).collect();
+        format!("[\n{}\n]\n", items.join(",\n"))
+    };
+    let mut tokenizer = json_tool::parser::Tokenizer::new(text.clone());
+    let index = json_tool::StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+    
+    // Visible slice of each row, with its document offset
+    let mut rows = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n').take(ROWS) {
+        let content = line.trim_end_matches('\n');
+        let visible = content.char_indices().nth(COLS).map_or(content, |(i, _)| &content[..i]);
+        rows.push((visible, offset));
+        offset += line.len();
+    }
+    
+    let theme = Theme::dark();
+    group.bench_function("lexer_80x200", |b| {
+        b.iter(|| {
+            for (row, _) in &rows {
+                black_box(colorize_json_line(row, false, &theme));
+            }
+        })
+    });
+    
+    group.bench_function("index_80x200", |b| {
+        b.iter(|| {
+            for (row, offset) in &rows {
+                black_box(colorize_from_index(&index, row, *offset, false, &theme));
+            }
+        })
+    });
+    
+    // 1000 frames of a view that doesn't move: only the first one colorizes
+    let mut cache = LineCache::new();
+    let mut calls = 0;
+    for _ in 0..1000 {
+        cache.begin_frame(0);
+        for (i, (row, offset)) in rows.iter().enumerate() {
+            black_box(cache.get_or_insert_with(i, *offset, 0..row.len(), || {
+                calls += 1;
+                colorize_from_index(&index, row, *offset, false, &theme)
+            }));
+        }
+        cache.end_frame();
+    }
+    assert_eq!(calls, rows.len(), "colorizer called after the first frame");
+    
+    // Scrolling a 40-row view down a line at a time, cursor on the top row
+    // with its bracket highlighted: each frame builds spans for the row
+    // scrolled in and the one the highlight moved to, and no others
+    use json_tool::ui::line_cache::Overlay;
+    const VIEW: usize = 40;
+    let mut scroll_cache = LineCache::new();
+    let mut built = Vec::new();
+    for top in 0..rows.len() - VIEW {
+        let before = scroll_cache.built();
+        scroll_cache.begin_frame(0);
+        for (i, (row, offset)) in rows.iter().enumerate().skip(top).take(VIEW) {
+            let overlay = Overlay { marks: if i == top { vec![0] } else { Vec::new() }, ..Overlay::default() };
+            black_box(scroll_cache.drawn_or_insert_with(i, *offset, 0..row.len(), overlay, || {
+                colorize_from_index(&index, row, *offset, false, &theme)
+            }, |line| line));
+        }
+        scroll_cache.end_frame();
+        built.push(scroll_cache.built() - before);
+    }
+    assert!(built.iter().skip(1).all(|&rows| rows <= 2), "rows built per frame: {:?}", built);
+    
+    group.bench_function("cached_80x200", |b| {
+        b.iter(|| {
+            cache.begin_frame(0);
+            for (i, (row, offset)) in rows.iter().enumerate() {
+                black_box(cache.get_or_insert_with(i, *offset, 0..row.len(), || {
+                    colorize_from_index(&index, row, *offset, false, &theme)
+                }));
+            }
+            cache.end_frame();
+        })
+    });
+    
+    group.finish();
+}
+
+fn prefetch_benchmark(c: &mut Criterion) {
+    // Screen after screen of the large fixture (tests/large.json, from
+    // generate_test_data), as holding j does. Only the time spent reading
+    // each screen counts; the millisecond between frames is when the
+    // prefetcher gets ahead. Shows most with the file out of the page cache
+    // (echo 3 > /proc/sys/vm/drop_caches) or bigger than it.
+    let mut buffer = Buffer::new();
+    if buffer.load_file("tests/large.json").is_err() {
+        return;
+    }
+    const HEIGHT: usize = 50;
+    let mut group = c.benchmark_group("scroll_cold");
+    group.measurement_time(Duration::from_secs(10));
+    for prefetch in [false, true] {
+        let mut prefetcher = Prefetcher::new();
+        let mut view = ViewLines::new();
+        let mut top = 0;
+        let name = if prefetch { "prefetched" } else { "plain" };
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                let mut spent = Duration::ZERO;
+                for _ in 0..iters {
+                    top = (top + HEIGHT) % buffer.line_count().max(1);
+                    let start = Instant::now();
+                    black_box(view.fetch(&mut buffer, top, HEIGHT));
+                    spent += start.elapsed();
+                    if prefetch {
+                        prefetcher.around(&buffer, top, HEIGHT);
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                spent
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scroll_benchmark, tokenizer_benchmark, highlight_benchmark, prefetch_benchmark);
 criterion_main!(benches);
@@ -0,0 +1,76 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use json_tool::parser::scan::fast_tokens;
+use json_tool::parser::{OpenContainers, StructuralIndex, Tokenizer};
+use memmap2::Mmap;
+use std::fs::File;
+use std::time::Duration;
+
+/// The 100MB fixture from `generate_test_data`, or the same shape made up
+/// in memory when it hasn't been generated
+fn fixture() -> Vec<u8> {
+    if let Ok(file) = File::open("tests/medium.json") {
+        if let Ok(map) = unsafe { Mmap::map(&file) } {
+            return map.to_vec();
+        }
+    }
+    let record = "  {\"id\": 12345, \"name\": \"item\", \"tags\": [\"a\", \"b\"], \"price\": 19.99, \"active\": true},\n";
+    let mut text = String::from("[\n");
+    text.push_str(&record.repeat(100 * 1024 * 1024 / record.len()));
+    text.push_str("  null\n]\n");
+    text.into_bytes()
+}
+
+fn count_tokens(mut tokenizer: Tokenizer) -> usize {
+    let mut count = 0;
+    while tokenizer.next_token().is_some() {
+        count += 1;
+    }
+    count
+}
+
+fn tokenize_benchmark(c: &mut Criterion) {
+    let text = fixture();
+    let mut group = c.benchmark_group("tokenize");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    
+    // What indexing did before: copy the chunk out, then lex the copy
+    group.bench_function("copied", |b| {
+        b.iter(|| black_box(count_tokens(Tokenizer::from_bytes(text.clone()))))
+    });
+    
+    // Lexing the bytes where they are
+    group.bench_function("borrowed", |b| {
+        b.iter(|| black_box(count_tokens(Tokenizer::from_slice(&text))))
+    });
+    
+    group.finish();
+    
+    // The first indexing pass over a big chunk: tokens to nodes
+    let mut group = c.benchmark_group("index");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    
+    group.bench_function("tokenized", |b| {
+        b.iter(|| {
+            let tokens = Tokenizer::from_slice(&text).tokenize_all();
+            black_box(StructuralIndex::build_chunk(&tokens, &mut OpenContainers::default(), 0))
+        })
+    });
+    
+    // Scanning 64 bytes at a time first, the tokenizer only filling in
+    // between the structural characters
+    group.bench_function("scanned", |b| {
+        b.iter(|| {
+            let tokens = fast_tokens(&text, 0, 0).unwrap();
+            black_box(StructuralIndex::build_chunk(&tokens, &mut OpenContainers::default(), 0))
+        })
+    });
+    
+    group.finish();
+}
+
+criterion_group!(benches, tokenize_benchmark);
+criterion_main!(benches);
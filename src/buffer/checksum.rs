@@ -0,0 +1,161 @@
+//! :checksum and --checksum: the SHA-256 of the text, to record exactly
+//! what was handed on. The buffer is hashed from a `BufferSnapshot` as a save
+//! would write it, rope chunks or the file's mmap with edited lines laid
+//! over it, so nothing is copied whole and editing can go on meanwhile.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+
+use super::BufferSnapshot;
+pub use crate::ui::tasks::Progress;
+
+/// A SHA-256 and the bytes it was taken over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksum {
+    pub digest: [u8; 32],
+    pub bytes: usize,
+}
+
+impl Checksum {
+    /// The digest as `sha256sum` prints it
+    pub fn hex(&self) -> String {
+        self.digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SHA-256 {}", self.hex())
+    }
+}
+
+/// Hashes what's written through it, stopping when cancelled
+struct Hasher<'a> {
+    sha: Sha256,
+    bytes: usize,
+    progress: &'a Progress,
+}
+
+impl Write for Hasher<'_> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        if self.progress.cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Checksum cancelled"));
+        }
+        self.sha.update(bytes);
+        self.bytes += bytes.len();
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Hasher<'a> {
+    fn new(progress: &'a Progress) -> Self {
+        Self { sha: Sha256::new(), bytes: 0, progress }
+    }
+
+    fn finish(self) -> Checksum {
+        Checksum { digest: self.sha.finalize().into(), bytes: self.bytes }
+    }
+}
+
+/// The checksum of a buffer's text
+pub fn of_snapshot(snapshot: &BufferSnapshot, progress: &Progress) -> Result<Checksum> {
+    progress.total.store(snapshot.len_bytes(), Ordering::Relaxed);
+    let mut hasher = Hasher::new(progress);
+    snapshot.write_to(&mut hasher, progress)?;
+    Ok(hasher.finish())
+}
+
+/// The checksum of a file on disk, read a block at a time
+pub fn of_file(path: &Path, progress: &Progress) -> Result<Checksum> {
+    let mut file = File::open(path).with_context(|| format!("Can't open {}", path.display()))?;
+    progress.total.store(file.metadata()?.len() as usize, Ordering::Relaxed);
+    let mut hasher = Hasher::new(progress);
+    let mut block = vec![0; 1 << 20];
+    loop {
+        let read = match io::Read::read(&mut file, &mut block) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => bail!("Can't read {}: {}", path.display(), e),
+        };
+        hasher.write_all(&block[..read])?;
+        progress.done.store(hasher.bytes, Ordering::Relaxed);
+    }
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+
+    const FIXTURE: &str = "{\"name\": \"test\", \"value\": 123}\n";
+    const FIXTURE_SHA256: &str = "b776bb1fff0bb88010d52802df43917414e263aaf7f3742d004c57936e6697ca";
+
+    fn hash(buffer: &Buffer) -> Checksum {
+        of_snapshot(&buffer.snapshot().unwrap(), &Progress::default()).unwrap()
+    }
+
+    fn sha256(text: &str) -> String {
+        Checksum { digest: Sha256::digest(text.as_bytes()).into(), bytes: text.len() }.hex()
+    }
+
+    #[test]
+    fn test_rope() {
+        let path = std::env::temp_dir().join(format!("jim_test_checksum_{}.json", std::process::id()));
+        std::fs::write(&path, FIXTURE).unwrap();
+        let mut buffer = Buffer::new();
+        buffer.load_file(path.to_str().unwrap()).unwrap();
+        assert!(!buffer.is_lazy());
+        let checksum = hash(&buffer);
+        assert_eq!((checksum.hex().as_str(), checksum.bytes), (FIXTURE_SHA256, FIXTURE.len()));
+        assert_eq!(of_file(&path, &Progress::default()).unwrap(), checksum);
+        std::fs::remove_file(&path).ok();
+
+        // With an edit not yet saved
+        buffer.insert(1, "\"id\": 1, ").unwrap();
+        let edited = buffer.slice(0..buffer.len_bytes());
+        assert_eq!(hash(&buffer).hex(), sha256(&edited));
+    }
+
+    #[test]
+    fn test_lazy() {
+        let path = std::env::temp_dir().join(format!("jim_test_checksum_lazy_{}.json", std::process::id()));
+        let text = FIXTURE.repeat(11 * 1024 * 1024 / FIXTURE.len());
+        std::fs::write(&path, &text).unwrap();
+        let mut buffer = Buffer::new();
+        buffer.load_file(path.to_str().unwrap()).unwrap();
+        assert!(buffer.is_lazy());
+        let progress = Progress::default();
+        let checksum = of_snapshot(&buffer.snapshot().unwrap(), &progress).unwrap();
+        assert_eq!((checksum.hex(), checksum.bytes), (sha256(&text), text.len()));
+        assert_eq!(progress.percent(), 100);
+        assert_eq!(of_file(&path, &Progress::default()).unwrap(), checksum);
+
+        // Edited lines come from the overlay, the rest from the file
+        let at = buffer.line_to_byte_offset(1000) + 1;
+        buffer.insert(at, "\"id\": 1, ").unwrap();
+        assert!(buffer.is_lazy());
+        let mut edited = text.clone();
+        edited.insert_str(at, "\"id\": 1, ");
+        let snapshot = buffer.snapshot().unwrap();
+        assert_eq!(snapshot.len_bytes(), edited.len());
+        assert_eq!(of_snapshot(&snapshot, &Progress::default()).unwrap().hex(), sha256(&edited));
+        std::fs::remove_file(&path).ok();
+
+        // Cancelled partway
+        let progress = Progress::default();
+        progress.cancel.store(true, Ordering::Relaxed);
+        assert!(of_snapshot(&snapshot, &progress).is_err());
+    }
+}
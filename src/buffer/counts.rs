@@ -0,0 +1,123 @@
+//! Word, character and byte counts for g Ctrl-g. The text streams through
+//! `Buffer::write_range` a chunk at a time, so a lazily read file is counted
+//! straight from the mmap without being copied. Past `SCAN_LIMIT` bytes the
+//! rest isn't read: the counts are scaled up from what was, and say so.
+
+use std::io::{self, Write};
+
+use super::Buffer;
+
+/// Bytes read before the counts become estimates
+pub const SCAN_LIMIT: usize = 64 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Counts {
+    /// Runs of anything but ASCII whitespace
+    pub words: usize,
+    pub chars: usize,
+    pub bytes: usize,
+}
+
+impl Counts {
+    /// These counts for `bytes` bytes of text like the `self.bytes` read
+    fn scaled(self, bytes: usize) -> Counts {
+        let scale = |n: usize| (n as u128 * bytes as u128 / self.bytes.max(1) as u128) as usize;
+        Counts { words: scale(self.words), chars: scale(self.chars), bytes }
+    }
+}
+
+/// What g Ctrl-g reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    /// Up to and including the cursor: the word and character it's on are
+    /// counted
+    pub cursor: Counts,
+    pub total: Counts,
+    /// Words and characters were scaled up from the first `SCAN_LIMIT`
+    /// bytes rather than counted; bytes are always exact
+    pub estimated: bool,
+}
+
+/// Counts up to `offset` and in the whole buffer, reading no more than
+/// `limit` bytes
+pub fn count(buffer: &Buffer, offset: usize, limit: usize) -> Report {
+    let len = buffer.len_bytes();
+    let offset = offset.min(len);
+    let mut counter = Counter { counts: Counts::default(), in_word: false, mark: offset + 1, at_mark: None };
+    // Counting can't fail: the writer takes everything it's given
+    let _ = buffer.write_range(0..len.min(limit), &mut counter);
+    let read = counter.counts;
+    let estimated = len > read.bytes;
+    let total = if estimated { read.scaled(len) } else { read };
+    let cursor = match counter.at_mark {
+        Some(counts) => counts,
+        None if offset < read.bytes || !estimated => read,
+        None => read.scaled(offset + 1),
+    };
+    Report { cursor, total, estimated }
+}
+
+/// Counts what's written through it, noting how far they'd got at `mark`
+/// bytes
+struct Counter {
+    counts: Counts,
+    in_word: bool,
+    mark: usize,
+    at_mark: Option<Counts>,
+}
+
+impl Write for Counter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        for &byte in bytes {
+            if self.counts.bytes == self.mark && self.at_mark.is_none() {
+                self.at_mark = Some(self.counts);
+            }
+            self.counts.bytes += 1;
+            // Every byte but a UTF-8 continuation starts a character
+            if byte & 0xC0 != 0x80 {
+                self.counts.chars += 1;
+            }
+            let space = byte.is_ascii_whitespace();
+            if !space && !self.in_word {
+                self.counts.words += 1;
+            }
+            self.in_word = !space;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count() {
+        let buffer = Buffer::from_text("{\"name\": \"café\",\n  \"n\": [1, 2]}\n");
+        let report = count(&buffer, 0, SCAN_LIMIT);
+        assert!(!report.estimated);
+        assert_eq!(report.total, Counts { words: 5, chars: 32, bytes: 33 });
+        assert_eq!(report.cursor, Counts { words: 1, chars: 1, bytes: 1 });
+        // On the é: its word and it are counted
+        assert_eq!(count(&buffer, 13, SCAN_LIMIT).cursor, Counts { words: 2, chars: 14, bytes: 14 });
+        // At the end
+        assert_eq!(count(&buffer, 33, SCAN_LIMIT).cursor, report.total);
+        assert_eq!(count(&buffer, 32, SCAN_LIMIT).cursor, report.total);
+        assert_eq!(count(&Buffer::from_text(""), 0, SCAN_LIMIT).total, Counts::default());
+    }
+
+    #[test]
+    fn test_estimate() {
+        let buffer = Buffer::from_text(&"ab ".repeat(100));
+        let report = count(&buffer, 10, 30);
+        assert!(report.estimated);
+        assert_eq!(report.total, Counts { words: 100, chars: 300, bytes: 300 });
+        assert_eq!(report.cursor, Counts { words: 4, chars: 11, bytes: 11 });
+        // Beyond what was read the cursor's counts are scaled too
+        assert_eq!(count(&buffer, 149, 30).cursor, Counts { words: 50, chars: 150, bytes: 150 });
+    }
+}
@@ -7,6 +7,11 @@ pub struct Cursor {
     pub line: usize,
     /// Column number (0-indexed, character position not byte)
     pub col: usize,
+    /// Column vertical moves aim for, with the offset the last one left
+    /// the cursor at. It holds only while the cursor is still there, so
+    /// any other motion forgets it; usize::MAX (after `$`) keeps to the
+    /// end of each line.
+    pub desired_col: Option<(usize, usize)>,
 }
 
 impl Cursor {
@@ -15,6 +20,7 @@ impl Cursor {
             byte_offset: 0,
             line: 0,
             col: 0,
+            desired_col: None,
         }
     }
     
@@ -23,6 +29,7 @@ impl Cursor {
             byte_offset: 0, // Will be calculated from buffer
             line,
             col,
+            desired_col: None,
         }
     }
     
@@ -31,6 +38,7 @@ impl Cursor {
             byte_offset,
             line: 0, // Will be calculated from buffer
             col: 0,
+            desired_col: None,
         }
     }
     
@@ -53,24 +61,48 @@ impl Cursor {
         self.byte_offset = line_start_offset + col_bytes;
     }
     
+    /// Move to a byte offset, deriving line and column from the buffer
+    pub fn move_to_offset(&mut self, buffer: &crate::buffer::Buffer, offset: usize) {
+        self.byte_offset = offset;
+        (self.line, self.col) = buffer.offset_to_line_col(offset);
+    }
+    
+    /// The column a vertical move aims for: the one the last vertical move
+    /// did, if the cursor hasn't moved since, else where it is
+    pub fn wanted_col(&self) -> usize {
+        match self.desired_col {
+            Some((offset, col)) if offset == self.byte_offset => col,
+            _ => self.col,
+        }
+    }
+    
+    /// Move to `line`, as near the column vertical moves aim for as the
+    /// line allows, without forgetting that column
+    pub fn move_to_line(&mut self, buffer: &crate::buffer::Buffer, line: usize) {
+        let col = self.wanted_col();
+        self.line = line;
+        self.col = col;
+        self.sync_byte_offset(buffer);
+        self.desired_col = Some((self.byte_offset, col));
+    }
+    
     /// Move cursor to the next line
     pub fn move_down(&mut self, buffer: &crate::buffer::Buffer) {
         if self.line < buffer.line_count().saturating_sub(1) {
-            self.line = self.line.saturating_add(1);
-            self.sync_byte_offset(buffer);
+            self.move_to_line(buffer, self.line + 1);
         }
     }
     
     /// Move cursor to the previous line
     pub fn move_up(&mut self, buffer: &crate::buffer::Buffer) {
         if self.line > 0 {
-            self.line = self.line.saturating_sub(1);
-            self.sync_byte_offset(buffer);
+            self.move_to_line(buffer, self.line - 1);
         }
     }
     
     /// Move cursor right one character
     pub fn move_right(&mut self, buffer: &crate::buffer::Buffer) {
+        self.desired_col = None;
         let line_text = buffer.get_line(self.line);
         let line_len = line_text.chars().count();
         if self.col < line_len.saturating_sub(1) {
@@ -81,6 +113,7 @@ impl Cursor {
     
     /// Move cursor left one character
     pub fn move_left(&mut self, buffer: &crate::buffer::Buffer) {
+        self.desired_col = None;
         if self.col > 0 {
             self.col = self.col.saturating_sub(1);
             self.sync_byte_offset(buffer);
@@ -114,12 +147,14 @@ impl Cursor {
         self.sync_byte_offset(buffer);
     }
     
-    /// Move cursor to end of line (alias for mode handlers)
+    /// Move cursor to end of line (alias for mode handlers). Vertical
+    /// moves after it keep to the end of each line.
     pub fn move_end_of_line(&mut self, buffer: &crate::buffer::Buffer) {
         let line_text = buffer.get_line(self.line);
         let line_len = line_text.chars().count();
-        self.col = line_len.saturating_sub(1).max(0);
+        self.col = line_len.saturating_sub(1);
         self.sync_byte_offset(buffer);
+        self.desired_col = Some((self.byte_offset, usize::MAX));
     }
 }
 
@@ -132,6 +167,7 @@ impl Default for Cursor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::buffer::Buffer;
     
     #[test]
     fn test_cursor_creation() {
@@ -143,28 +179,30 @@ mod tests {
     
     #[test]
     fn test_cursor_movement() {
+        let buffer = Buffer::from_text("first line\nsecond line\n");
         let mut cursor = Cursor::new();
-        cursor.move_down();
+        cursor.move_down(&buffer);
         assert_eq!(cursor.line, 1);
         
-        cursor.move_right();
-        cursor.move_right();
+        cursor.move_right(&buffer);
+        cursor.move_right(&buffer);
         assert_eq!(cursor.col, 2);
         
-        cursor.move_up();
+        cursor.move_up(&buffer);
         assert_eq!(cursor.line, 0);
         
-        cursor.move_left();
+        cursor.move_left(&buffer);
         assert_eq!(cursor.col, 1);
     }
     
     #[test]
     fn test_cursor_boundaries() {
+        let buffer = Buffer::from_text("line\n");
         let mut cursor = Cursor::new();
-        cursor.move_up(); // Should saturate at 0
+        cursor.move_up(&buffer); // Should saturate at 0
         assert_eq!(cursor.line, 0);
         
-        cursor.move_left(); // Should saturate at 0
+        cursor.move_left(&buffer); // Should saturate at 0
         assert_eq!(cursor.col, 0);
     }
     
@@ -178,4 +216,48 @@ mod tests {
         cursor.move_to_line_end(20);
         assert_eq!(cursor.col, 19);
     }
+    
+    #[test]
+    fn test_sticky_column() {
+        let buffer = Buffer::from_text("a long first line
+short
+
+another long line
+");
+        let mut cursor = Cursor::new();
+        for _ in 0..12 {
+            cursor.move_right(&buffer);
+        }
+        cursor.move_down(&buffer);
+        assert_eq!(cursor.col, 5);
+        cursor.move_down(&buffer);
+        assert_eq!(cursor.col, 0);
+        cursor.move_down(&buffer);
+        assert_eq!((cursor.line, cursor.col), (3, 12));
+        cursor.move_up(&buffer);
+        cursor.move_up(&buffer);
+        cursor.move_up(&buffer);
+        assert_eq!((cursor.line, cursor.col), (0, 12));
+        
+        // A horizontal move sets a new column
+        cursor.move_down(&buffer);
+        cursor.move_left(&buffer);
+        cursor.move_down(&buffer);
+        cursor.move_down(&buffer);
+        assert_eq!(cursor.col, 4);
+        
+        // So does any other motion, like a jump
+        cursor.move_to_offset(&buffer, 2);
+        cursor.move_down(&buffer);
+        assert_eq!(cursor.col, 2);
+        
+        // After $ every line is left at its end
+        cursor.move_end_of_line(&buffer);
+        cursor.move_down(&buffer);
+        cursor.move_down(&buffer);
+        assert_eq!((cursor.line, cursor.col), (3, 17));
+        cursor.move_up(&buffer);
+        cursor.move_up(&buffer);
+        assert_eq!((cursor.line, cursor.col), (1, 5));
+    }
 }
@@ -1,44 +1,405 @@
+pub mod checksum;
+pub mod counts;
 pub mod cursor;
+pub mod prefetch;
+pub mod view_lines;
 
 #[cfg(test)]
 mod tests;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use memmap2::Mmap;
 use ropey::Rope;
+use std::collections::VecDeque;
 use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Write, Seek, SeekFrom};
-use std::path::PathBuf;
-use std::sync::{Arc, atomic::{AtomicU32, AtomicBool, Ordering}};
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}};
+use std::thread::JoinHandle;
+
+use crate::parser::shape::Shape;
+use crate::ui::perf;
+use crate::ui::tasks::Progress;
 
 pub use cursor::Cursor;
+pub use prefetch::Prefetcher;
+pub use view_lines::ViewLines;
 
-/// Represents a single edit operation for incremental save
+/// A replacement of `old_len` bytes at `file_offset`, for incremental save
+/// and for apply_edits
 #[derive(Debug, Clone)]
 pub struct Edit {
-    pub file_offset: usize,  // Position in original file
+    pub file_offset: usize,  // Position in original file, or in the text before the batch
     pub old_len: usize,      // Bytes replaced (0 for insert)
     pub new_text: String,    // Replacement text
 }
 
-/// Save strategy selection
-#[derive(Debug, Clone, Copy)]
-enum SaveMethod {
-    CopyOnWrite,   // Reflink + in-place edits (fastest for small edits on supported filesystems)
-    Streaming,     // Stream original + patches (good fallback for all cases)
+/// The whole text for another thread to read: the file's mmap while it's
+/// read unedited, else a copy
+pub enum SharedText {
+    Mapped(Arc<Mmap>, std::ops::Range<usize>),
+    Copied(String),
+}
+
+impl SharedText {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            SharedText::Mapped(mmap, range) => &mmap[range.clone()],
+            SharedText::Copied(text) => text.as_bytes(),
+        }
+    }
+}
+
+/// A change to the buffer contents, in bytes of the text before the change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextChange {
+    pub offset: usize,
+    pub removed: usize,
+    pub inserted: usize,
+}
+
+impl TextChange {
+    /// One change doing what `self` and then `next` did: the span both
+    /// touched, replaced. Anything kept by offset inside it is lost, so
+    /// this is only for when the changes can't be kept apart.
+    pub fn merge(self, next: TextChange) -> TextChange {
+        // The union of both spans, in the text between the two changes
+        let start = self.offset.min(next.offset);
+        let end = (self.offset + self.inserted).max(next.offset + next.removed);
+        TextChange {
+            offset: start,
+            removed: end - self.inserted + self.removed - start,
+            inserted: end - start - next.removed + next.inserted,
+        }
+    }
+
+    /// Where `offset` is after this change; one inside the text it
+    /// replaced goes to where the replacement starts
+    pub fn shift_offset(&self, offset: usize) -> usize {
+        let end = self.offset + self.removed;
+        if offset >= end {
+            offset - self.removed + self.inserted
+        } else {
+            offset.min(self.offset)
+        }
+    }
+
+    /// Where `span` is after this change, or None if the change touches it
+    pub fn shift_span(&self, span: &Range<usize>) -> Option<Range<usize>> {
+        let end = self.offset + self.removed;
+        if span.start >= end && (self.removed > 0 || span.start > self.offset) {
+            Some(self.shift_offset(span.start)..self.shift_offset(span.end))
+        } else if span.end <= self.offset {
+            Some(span.clone())
+        } else {
+            None
+        }
+    }
+}
+
+/// How a save that something waits on, like :wq, stands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveOutcome {
+    /// Still being written
+    Saving,
+    /// Written, with nothing changed since
+    Saved,
+    /// The changes aren't all on disk: why the save failed, if it did, or
+    /// else the buffer was changed while it was being written
+    Unsaved(Option<String>),
+}
+
+/// Where offsets in the text before apply_edits went. An offset inside a
+/// replaced span keeps its distance into the replacement, as far as that
+/// reaches; one at the end of a span, or at an insertion, goes after it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetMap {
+    /// The edits as changes to the text before the batch, in order
+    changes: Vec<TextChange>,
+    /// How far text after each edit moved, counting the edits before it
+    shifts: Vec<isize>,
+}
+
+impl OffsetMap {
+    pub fn map(&self, offset: usize) -> usize {
+        let i = self.changes.partition_point(|change| change.offset <= offset);
+        let Some(i) = i.checked_sub(1) else {
+            return offset;
+        };
+        let change = self.changes[i];
+        let shift = self.shifts[i];
+        if offset >= change.offset + change.removed {
+            return offset.saturating_add_signed(shift);
+        }
+        let before = shift - (change.inserted as isize - change.removed as isize);
+        change.offset.saturating_add_signed(before) + (offset - change.offset).min(change.inserted)
+    }
+
+    /// How much longer the text got; negative when it shrank
+    pub fn net(&self) -> isize {
+        self.shifts.last().copied().unwrap_or(0)
+    }
+
+    /// The edits as changes to the text before the batch
+    pub fn changes(&self) -> &[TextChange] {
+        &self.changes
+    }
+}
+
+/// Deltas the buffer holds for drain_deltas before merging the oldest
+const MAX_DELTAS: usize = 1024;
+
+/// Size of the write buffer a save streams through
+const SAVE_BUFFER_BYTES: usize = 8 * 1024 * 1024;
+
+/// A :w writing this much is asked about first
+pub const CONFIRM_SAVE_BYTES: usize = 1024 * 1024 * 1024;
+
+/// Lines of a lazily read file longer than this are split into rows, so a
+/// minified file isn't one line gigabytes long
+pub const LONG_LINE_BYTES: usize = 64 * 1024;
+
+/// Length a split line's rows are cut at: after the first comma outside a
+/// string once a row is this long, or anywhere past `MAX_ROW_BYTES`
+pub const ROW_BYTES: usize = 1024;
+const MAX_ROW_BYTES: usize = 4 * ROW_BYTES;
+
+/// What a background save writes, snapshotted when it starts
+enum SaveSource {
+    /// Small files: the whole rope
+    Rope(Rope),
+    /// Large files: lines replaced by edits, the rest read from `source`
+    Overlay {
+        source: PathBuf,
+        mmap: Option<Arc<Mmap>>,  // The buffer's mapping of `source`, to read lines from without mapping it again
+        file_size: usize,
+        line_offsets: Arc<Vec<usize>>,
+        edits: std::collections::HashMap<usize, String>,
+        shifts: Vec<(usize, isize)>,  // Each edited line, in order, and how far it and the ones before move the lines after
+    },
+}
+
+/// The buffer's text at one moment, from `Buffer::snapshot`: what a save
+/// writes, :checksum hashes and any other thread reads while editing goes
+/// on. It's tagged with the revision it was taken at, so whoever gets a
+/// result worked out from it can tell whether the text has moved on since.
+/// Offsets count the text as it is, edited lines included.
+pub struct BufferSnapshot {
+    source: SaveSource,
+    len: usize,
+    revision: u64,
+}
+
+impl BufferSnapshot {
+    /// Bytes of text
+    pub fn len_bytes(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The buffer's revision when it was taken
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn line_count(&self) -> usize {
+        match &self.source {
+            SaveSource::Rope(rope) => rope.len_lines(),
+            SaveSource::Overlay { file_size, line_offsets, .. } => match line_offsets.last() {
+                Some(&last) if last < *file_size => line_offsets.len(),
+                _ => line_offsets.len().saturating_sub(1),
+            },
+        }
+    }
+
+    /// A line with its newline, or an empty string past the end
+    pub fn get_line(&self, line_idx: usize) -> String {
+        match &self.source {
+            SaveSource::Rope(rope) if line_idx < rope.len_lines() => rope.line(line_idx).to_string(),
+            SaveSource::Rope(_) => String::new(),
+            SaveSource::Overlay { edits, .. } => match edits.get(&line_idx) {
+                Some(edited) => edited.clone(),
+                None => String::from_utf8_lossy(self.unedited_line(line_idx)).to_string(),
+            },
+        }
+    }
+
+    /// Text in `range`. An end inside a multi-byte character is moved back
+    /// to the character's start.
+    pub fn slice(&self, range: std::ops::Range<usize>) -> String {
+        let start = range.start.min(self.len);
+        let end = range.end.min(self.len);
+        if start >= end {
+            return String::new();
+        }
+        let edits = match &self.source {
+            SaveSource::Rope(rope) => {
+                let end = rope.char_to_byte(rope.byte_to_char(end));
+                return rope.byte_slice(start.min(end)..end).to_string();
+            }
+            SaveSource::Overlay { edits, .. } => edits,
+        };
+        let mut bytes = Vec::with_capacity(end - start);
+        let mut line = self.line_at(start);
+        let mut at = self.line_start(line);
+        while at < end && line < self.line_count() {
+            let text = match edits.get(&line) {
+                Some(edited) => edited.as_bytes(),
+                None => self.unedited_line(line),
+            };
+            let from = start.saturating_sub(at).min(text.len());
+            let to = (end - at).min(text.len());
+            bytes.extend_from_slice(&text[from..to]);
+            at += text.len();
+            line += 1;
+        }
+        // Only a character cut short at the end, not one invalid before it
+        if let Some(e) = std::str::from_utf8(&bytes).err().filter(|e| e.error_len().is_none()) {
+            bytes.truncate(e.valid_up_to());
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// A line as the file has it, for a large file's snapshot
+    fn unedited_line(&self, line_idx: usize) -> &[u8] {
+        let SaveSource::Overlay { mmap: Some(mmap), file_size, line_offsets, .. } = &self.source else {
+            return &[];
+        };
+        let len = (*file_size).min(mmap.len());
+        let Some(&start) = line_offsets.get(line_idx) else {
+            return &[];
+        };
+        let end = line_offsets.get(line_idx + 1).copied().unwrap_or(len).min(len);
+        &mmap[start.min(end)..end]
+    }
+
+    /// Where a line starts in the text, past the edits before it
+    fn line_start(&self, line_idx: usize) -> usize {
+        match &self.source {
+            SaveSource::Rope(rope) => rope.line_to_byte(line_idx.min(rope.len_lines())),
+            SaveSource::Overlay { file_size, line_offsets, shifts, .. } => {
+                let before = shifts.partition_point(|&(edited, _)| edited < line_idx);
+                let shift = before.checked_sub(1).map_or(0, |i| shifts[i].1);
+                line_offsets.get(line_idx).copied().unwrap_or(*file_size).saturating_add_signed(shift)
+            }
+        }
+    }
+
+    /// The line holding byte `offset` of the text
+    fn line_at(&self, offset: usize) -> usize {
+        let (mut low, mut high) = (0, self.line_count());
+        while low + 1 < high {
+            let mid = (low + high) / 2;
+            if self.line_start(mid) <= offset {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    /// Memory held beyond the buffer's own: a copy of any edited lines
+    fn footprint(&self) -> usize {
+        match &self.source {
+            SaveSource::Rope(_) => 0,
+            SaveSource::Overlay { edits, .. } => edits.values().map(String::len).sum(),
+        }
+    }
+
+    /// Stream the text to `out`, keeping `progress` up to date with how
+    /// many bytes have gone, and stopping with an error if it's cancelled.
+    /// Returns the bytes written.
+    pub fn write_to(&self, out: &mut impl Write, progress: &Progress) -> Result<usize> {
+        let report = |written: usize| {
+            progress.done.store(written, Ordering::Relaxed);
+            if progress.is_cancelled() {
+                anyhow::bail!("Cancelled");
+            }
+            Ok(())
+        };
+        let mut written = 0;
+        match &self.source {
+            SaveSource::Rope(rope) => {
+                for chunk in rope.chunks() {
+                    out.write_all(chunk.as_bytes())?;
+                    written += chunk.len();
+                    report(written)?;
+                }
+            }
+            SaveSource::Overlay { source, line_offsets, edits, .. } => {
+                // Edited lines from the snapshot, the rest straight from a
+                // fresh mapping of the source file. Every line is written,
+                // including a last one with no newline.
+                let original = unsafe { Mmap::map(&File::open(source)?)? };
+                for (line, &start) in line_offsets.iter().enumerate() {
+                    let text = match edits.get(&line) {
+                        Some(edited) => edited.as_bytes(),
+                        None => {
+                            let end = line_offsets.get(line + 1).copied().unwrap_or(original.len()).min(original.len());
+                            &original[start.min(end)..end]
+                        }
+                    };
+                    out.write_all(text)?;
+                    written += text.len();
+                    if line % 65536 == 0 {
+                        report(written)?;
+                    }
+                }
+                report(written)?;
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// What a :w would write, worked out before anything is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SavePlan {
+    /// Nothing has changed since the file was read or last saved
+    Unchanged,
+    /// `bytes` streamed from the file, with the `edited` lines laid over it
+    Overlay { bytes: usize, edited: usize },
+    /// `bytes` from the text held in memory
+    Rope { bytes: usize },
+}
+
+impl SavePlan {
+    pub fn bytes(&self) -> usize {
+        match *self {
+            SavePlan::Unchanged => 0,
+            SavePlan::Overlay { bytes, .. } | SavePlan::Rope { bytes } => bytes,
+        }
+    }
+    
+    /// Whether it's big enough to ask about first
+    pub fn is_large(&self) -> bool {
+        self.bytes() >= CONFIRM_SAVE_BYTES
+    }
 }
 
 pub struct Buffer {
     // Memory-mapped original file (stays on disk, NOT loaded to RAM)
-    mmap: Option<Mmap>,
+    mmap: Option<Arc<Mmap>>,  // Shared with the indexer reading unedited lines
     file_size: usize,
+    mapped_file: Option<File>,  // The file behind the mmap, to notice it being cut short
+    mapped_len: usize,  // How much of the mmap can be read: all of it, unless the file was truncated
+    truncated: bool,  // The file was truncated under the mmap; no edits or saves until reloaded
+    converted_from: Option<&'static str>,  // Format the file was converted to JSON from, to show read-only
     
     // Line index: byte offset of each line (fast, ~1MB per 1GB file)
-    line_offsets: Vec<usize>,
+    line_offsets: Arc<Vec<usize>>,  // Shared with a save in progress
+    split_lines: bool,  // Some entries of line_offsets start rows of a long line rather than lines
     
     // LRU cache: only recently viewed lines (8MB max)
-    line_cache: std::collections::HashMap<usize, String>,
-    cache_order: Vec<usize>,  // For LRU eviction
+    line_cache: std::collections::HashMap<usize, (String, u64)>,  // line -> (text, last use)
+    cache_order: VecDeque<(usize, u64)>,  // Uses oldest first, for LRU eviction; stale once the line is used again
+    cache_clock: u64,
     max_cache_lines: usize,   // ~1000 lines = ~8MB
     
     // Edit overlay: modified lines only
@@ -49,16 +410,29 @@ pub struct Buffer {
     use_rope: bool,  // true if file is small (<10MB) or has many edits
     
     // Save progress reporting
-    save_progress: Arc<AtomicU32>,
-    save_in_progress: Arc<AtomicBool>,
+    save_progress: Arc<Progress>,  // Of the save in progress, or the last one
+    save_job: Option<JoinHandle<()>>,  // The thread writing it
     save_pending: bool,
+    save_error: Arc<Mutex<Option<String>>>,  // Why the last background save failed, until finalize_save reports it
+    saved_file: Arc<Mutex<Option<File>>>,  // The file a finished save wrote, for finalize_save to map whatever its path now names
+    save_footprint: usize,  // Bytes a save in progress holds beyond the buffer itself
+    save_started: Option<Instant>,  // When the save in progress began
+    last_save: Option<Duration>,  // How long the last save took
     
     // Load progress reporting
-    pub load_progress: Arc<AtomicU32>,
-    pub load_in_progress: Arc<AtomicBool>,
+    pub load_progress: Arc<Progress>,
     
     path: Option<PathBuf>,
+    source_path: Option<PathBuf>,  // File the mmap was made from
     modified: bool,
+    
+    // Changes since the last drain_deltas(), for state kept by byte offset;
+    // past MAX_DELTAS the oldest are merged
+    changes: VecDeque<TextChange>,
+    
+    // Bumped by every edit and load, so callers can tell the text is unchanged
+    revision: u64,
+    shape: Option<(u64, Shape)>,  // The top-level shape and the revision it was found in
 }
 
 impl Buffer {
@@ -66,26 +440,116 @@ impl Buffer {
         Self {
             mmap: None,
             file_size: 0,
-            line_offsets: Vec::new(),
+            mapped_file: None,
+            mapped_len: 0,
+            truncated: false,
+            converted_from: None,
+            line_offsets: Arc::new(Vec::new()),
+            split_lines: false,
             line_cache: std::collections::HashMap::new(),
-            cache_order: Vec::new(),
+            cache_order: VecDeque::new(),
+            cache_clock: 0,
             max_cache_lines: 1000,  // ~8MB cache (8KB per line average)
             edits: std::collections::HashMap::new(),
             rope: None,
             use_rope: false,
-            save_progress: Arc::new(AtomicU32::new(0)),
-            save_in_progress: Arc::new(AtomicBool::new(false)),
+            save_progress: Arc::new(Progress::default()),
+            save_job: None,
             save_pending: false,
-            load_progress: Arc::new(AtomicU32::new(0)),
-            load_in_progress: Arc::new(AtomicBool::new(false)),
+            save_error: Arc::new(Mutex::new(None)),
+            saved_file: Arc::new(Mutex::new(None)),
+            save_footprint: 0,
+            save_started: None,
+            last_save: None,
+            load_progress: Arc::new(Progress::default()),
             path: None,
+            source_path: None,
             modified: false,
+            changes: VecDeque::new(),
+            revision: 0,
+            shape: None,
         }
     }
 
+    /// Create an in-memory buffer (rope mode) from text, with no backing file
+    pub fn from_text(text: &str) -> Self {
+        let mut buffer = Self::new();
+        buffer.rope = Some(Rope::from_str(text));
+        buffer.use_rope = true;
+        buffer
+    }
+
+    /// Load `path`, or start it empty as a new file, written on the
+    /// first save, when there's nothing there yet
+    pub fn open(&mut self, path: &str) -> Result<()> {
+        match std::fs::symlink_metadata(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.new_file(path);
+                Ok(())
+            }
+            _ => self.load_file(path),
+        }
+    }
+
+    /// An empty buffer for a file that doesn't exist yet
+    pub fn new_file(&mut self, path: &str) {
+        self.rope = Some(Rope::new());
+        self.use_rope = true;
+        self.line_offsets = Arc::new(Vec::new());
+        self.split_lines = false;
+        self.mmap = None;
+        self.file_size = 0;
+        self.mapped_file = None;
+        self.mapped_len = 0;
+        self.truncated = false;
+        self.converted_from = None;
+        self.path = Some(PathBuf::from(path));
+        self.source_path = None;
+        self.line_cache.clear();
+        self.cache_order.clear();
+        self.edits.clear();
+        self.changes.clear();
+        self.revision += 1;
+        self.save_progress = Arc::new(Progress::default());
+        self.save_job = None;
+        self.save_pending = false;
+        self.modified = false;
+    }
+
+    /// Close the file: the mmap and the handle behind it, the line index,
+    /// caches, edit overlay and rope all go, leaving an empty buffer with
+    /// no file name. Refused while a save is still writing, since the
+    /// buffer would then never learn whether it succeeded.
+    pub fn close(&mut self) -> Result<()> {
+        if self.is_saving() {
+            anyhow::bail!("Still saving");
+        }
+        *self = Self {
+            revision: self.revision + 1,
+            load_progress: Arc::clone(&self.load_progress),
+            ..Self::new()
+        };
+        Ok(())
+    }
+
+    /// The directory the file goes in, when it doesn't exist
+    pub fn missing_directory(&self) -> Option<PathBuf> {
+        let dir = self.path.as_ref()?.parent().filter(|dir| !dir.as_os_str().is_empty())?;
+        (!dir.exists()).then(|| dir.to_path_buf())
+    }
+
+    /// Whether the buffer has a file name but nothing has been written to
+    /// it yet
+    pub fn is_new_file(&self) -> bool {
+        self.path.is_some() && self.source_path.is_none()
+    }
+
     pub fn load_file(&mut self, path: &str) -> Result<()> {
         // Open file for memory mapping
-        let file = File::open(path)?;
+        let file = File::open(path).with_context(|| format!("Can't open {}", path))?;
+        if file.metadata()?.is_dir() {
+            anyhow::bail!("{} is a directory", path);
+        }
         
         // Memory-map the file (doesn't load into RAM)
         let mmap = unsafe { Mmap::map(&file)? };
@@ -99,68 +563,163 @@ impl Buffer {
             let rope = Rope::from_reader(mmap.as_ref())?;
             self.rope = Some(rope);
             self.use_rope = true;
-            self.line_offsets.clear();
+            self.line_offsets = Arc::new(Vec::new());
+            self.split_lines = false;
         } else {
             // Large file: build line index only (lazy loading)
-            // Show progress for files that take >1 second to index
-            self.load_in_progress.store(true, Ordering::SeqCst);
-            self.load_progress.store(0, Ordering::SeqCst);
+            self.load_progress.total.store(file_size, Ordering::Relaxed);
+            self.load_progress.done.store(0, Ordering::Relaxed);
             
-            self.line_offsets = Self::build_line_index_with_progress(
-                &mmap, 
-                &self.load_progress
-            );
+            // The line scan reads the file once from end to end; after it,
+            // reads follow the view and the prefetcher asks for what's next
+            #[cfg(unix)]
+            let _ = mmap.advise(memmap2::Advice::Sequential);
+            let offsets = Self::build_line_index_with_progress(&mmap, &self.load_progress);
+            let rows = split_long_lines(&mmap, &offsets);
+            #[cfg(unix)]
+            let _ = mmap.advise(memmap2::Advice::Normal);
+            self.split_lines = rows.len() > offsets.len();
+            self.line_offsets = Arc::new(rows);
             
-            self.load_progress.store(100, Ordering::SeqCst);
-            self.load_in_progress.store(false, Ordering::SeqCst);
+            self.load_progress.done.store(file_size, Ordering::Relaxed);
             self.use_rope = false;
             self.rope = None;
         }
         
-        self.mmap = Some(mmap);
+        self.mmap = Some(Arc::new(mmap));
         self.file_size = file_size;
+        self.mapped_file = Some(file);
+        self.mapped_len = file_size;
+        self.truncated = false;
+        self.converted_from = None;
         self.path = Some(PathBuf::from(path));
+        self.source_path = self.path.clone();
         self.line_cache.clear();
         self.cache_order.clear();
         self.edits.clear();
-        self.save_progress.store(0, Ordering::SeqCst);
-        self.save_in_progress.store(false, Ordering::SeqCst);
+        self.changes.clear();
+        self.revision += 1;
+        self.save_progress = Arc::new(Progress::default());
+        self.save_job = None;
         self.save_pending = false;
         self.modified = false;
         
         Ok(())
     }
     
-    /// Build line offset index by scanning for newlines
-    /// Returns byte offset of each line start
-    fn build_line_index(mmap: &Mmap) -> Vec<usize> {
-        let mut offsets = vec![0];  // First line starts at 0
-        
-        for (i, &byte) in mmap.iter().enumerate() {
-            if byte == b'\n' {
-                offsets.push(i + 1);  // Next line starts after \n
-            }
+    /// The part of the mmap still backed by the file: all of it, unless
+    /// the file was truncated, when touching a page past its new end would
+    /// kill the process with SIGBUS
+    fn mapped(&self) -> Option<&[u8]> {
+        self.mmap.as_deref().map(|mmap| &mmap[..self.mapped_len.min(mmap.len())])
+    }
+    
+    /// The memory map and the byte range in it of `lines`, when they're
+    /// read from the file unedited, so they can be handed to another thread
+    /// without copying them out
+    pub fn mapped_lines(&self, lines: std::ops::Range<usize>) -> Option<(Arc<Mmap>, std::ops::Range<usize>)> {
+        if self.use_rope || self.truncated || self.edits.keys().any(|line| lines.contains(line)) {
+            return None;
         }
-        
-        offsets
+        let mmap = self.mmap.as_ref()?;
+        let len = self.mapped_len.min(mmap.len());
+        let start = (*self.line_offsets.get(lines.start)?).min(len);
+        let end = self.line_offsets.get(lines.end).copied().unwrap_or(len).clamp(start, len);
+        Some((Arc::clone(mmap), start..end))
+    }
+    
+    /// All of the text, to hand to another thread
+    pub fn shared_text(&self) -> SharedText {
+        match self.mapped_lines(0..self.line_count()) {
+            Some((mmap, range)) => SharedText::Mapped(mmap, range),
+            None => SharedText::Copied(self.slice(0..self.len_bytes())),
+        }
+    }
+    
+    /// Check whether another program has truncated the file a large buffer
+    /// reads from. If it has, reads stop at the file's new end from now on
+    /// and the buffer refuses edits and saves until it's loaded again.
+    /// True only on the call that finds it.
+    pub fn check_truncated(&mut self) -> bool {
+        if self.use_rope || self.truncated {
+            return false;
+        }
+        let Some(len) = self.mapped_file.as_ref().and_then(|file| file.metadata().ok()).map(|m| m.len() as usize) else {
+            return false;
+        };
+        if len >= self.mapped_len {
+            return false;
+        }
+        self.mapped_len = len;
+        self.truncated = true;
+        true
+    }
+    
+    /// Show `text`, the JSON form of the `format` file at `path`, read-only:
+    /// edits and saves are refused, though :w FILE writes it elsewhere
+    pub fn open_converted(&mut self, path: Option<&str>, text: &str, format: &'static str) {
+        self.new_file(path.unwrap_or_default());
+        self.rope = Some(Rope::from_str(text));
+        self.path = path.map(PathBuf::from);
+        self.source_path = self.path.clone();
+        self.converted_from = Some(format);
+    }
+    
+    /// The format the file was converted from, when it's shown as JSON
+    pub fn converted_from(&self) -> Option<&'static str> {
+        self.converted_from
+    }
+    
+    /// The document's top-level shape, if it was found for the text as it
+    /// is now
+    pub fn shape(&self) -> Option<Shape> {
+        self.shape.filter(|(revision, _)| *revision == self.revision).map(|(_, shape)| shape)
+    }
+    
+    /// Keep the shape found in the text of `revision`
+    pub fn set_shape(&mut self, revision: u64, shape: Shape) {
+        self.shape = Some((revision, shape));
+    }
+    
+    /// Whether writing to `path` would put JSON over the file the buffer
+    /// was converted from
+    pub fn overwrites_converted(&self, path: &Path) -> bool {
+        let Some(source) = self.source_path.as_ref().filter(|_| self.converted_from.is_some()) else {
+            return false;
+        };
+        match (source.canonicalize(), path.canonicalize()) {
+            (Ok(source), Ok(path)) => source == path,
+            _ => source == path,
+        }
+    }
+    
+    /// Whether the file was found truncated under the buffer
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+    
+    fn check_writable(&self) -> Result<()> {
+        if self.truncated {
+            anyhow::bail!("File was truncated on disk; :e! reloads it");
+        }
+        if let Some(format) = self.converted_from {
+            anyhow::bail!("Converted from {}, so read-only (:w FILE.json writes the JSON)", format);
+        }
+        Ok(())
     }
     
     /// Build line index with progress reporting (for large files)
-    fn build_line_index_with_progress(mmap: &Mmap, progress: &Arc<AtomicU32>) -> Vec<usize> {
+    fn build_line_index_with_progress(mmap: &Mmap, progress: &Progress) -> Vec<usize> {
         let mut offsets = vec![0];
-        let total_bytes = mmap.len();
-        let mut last_progress = 0u32;
         
         for (i, &byte) in mmap.iter().enumerate() {
             if byte == b'\n' {
                 offsets.push(i + 1);
             }
             
-            // Update progress every 1% (to avoid excessive atomic writes)
-            let current_progress = ((i as f64 / total_bytes as f64) * 100.0) as u32;
-            if current_progress > last_progress {
-                progress.store(current_progress, Ordering::Relaxed);
-                last_progress = current_progress;
+            // Update progress every MB (to avoid excessive atomic writes)
+            if i % (1 << 20) == 0 {
+                progress.done.store(i, Ordering::Relaxed);
             }
         }
         
@@ -178,11 +737,22 @@ impl Buffer {
     pub fn line_count(&self) -> usize {
         if self.use_rope {
             self.rope.as_ref().map(|r| r.len_lines()).unwrap_or(0)
+        } else if self.line_offsets.last().is_some_and(|&last| last < self.file_size) {
+            // A last line with no newline after it
+            self.line_offsets.len()
         } else {
             self.line_offsets.len().saturating_sub(1)
         }
     }
     
+    /// Whether lines too long to handle whole were split into rows of
+    /// about `ROW_BYTES`. Lines then count rows: the cursor, rendering and
+    /// edits work a row at a time, and positions are best given as byte
+    /// offsets.
+    pub fn has_split_lines(&self) -> bool {
+        self.split_lines
+    }
+    
     /// Read a line lazily from mmap (with LRU cache)
     fn read_line_lazy(&mut self, line_idx: usize) -> Option<String> {
         // Check edit overlay first
@@ -191,37 +761,51 @@ impl Buffer {
         }
         
         // Check cache
-        if let Some(cached) = self.line_cache.get(&line_idx) {
-            // Move to front of LRU
-            self.cache_order.retain(|&x| x != line_idx);
-            self.cache_order.push(line_idx);
-            return Some(cached.clone());
+        self.cache_clock += 1;
+        let clock = self.cache_clock;
+        if let Some((cached, used)) = self.line_cache.get_mut(&line_idx) {
+            // Move to the back of the LRU order; the older entry goes stale
+            *used = clock;
+            let cached = cached.clone();
+            self.record_use(line_idx, clock);
+            return Some(cached);
         }
         
         // Cache miss: read from mmap
-        let mmap = self.mmap.as_ref()?;
+        let mmap = self.mapped()?;
         let start = *self.line_offsets.get(line_idx)?;
         let end = self.line_offsets.get(line_idx + 1)
             .copied()
-            .unwrap_or(mmap.len());
+            .unwrap_or(mmap.len())
+            .min(mmap.len());
         
-        let line_bytes = &mmap[start..end];
+        let line_bytes = &mmap[start.min(end)..end];
         let line = String::from_utf8_lossy(line_bytes).to_string();
         
         // Add to cache
-        self.line_cache.insert(line_idx, line.clone());
-        self.cache_order.push(line_idx);
+        self.line_cache.insert(line_idx, (line.clone(), clock));
+        self.record_use(line_idx, clock);
         
         // Evict old lines if cache too large
-        while self.cache_order.len() > self.max_cache_lines {
-            if let Some(old_idx) = self.cache_order.first().copied() {
-                self.cache_order.remove(0);
+        while self.line_cache.len() > self.max_cache_lines {
+            let Some((old_idx, used)) = self.cache_order.pop_front() else { break };
+            if self.line_cache.get(&old_idx).is_some_and(|&(_, last)| last == used) {
                 self.line_cache.remove(&old_idx);
             }
         }
         
         Some(line)
     }
+    
+    /// Note a use of a cached line in the LRU order, dropping stale entries
+    /// when they come to outnumber the live ones
+    fn record_use(&mut self, line_idx: usize, clock: u64) {
+        self.cache_order.push_back((line_idx, clock));
+        if self.cache_order.len() > 2 * self.max_cache_lines {
+            let cache = &self.line_cache;
+            self.cache_order.retain(|(idx, used)| cache.get(idx).is_some_and(|&(_, last)| last == *used));
+        }
+    }
 
     pub fn get_line(&self, line_idx: usize) -> String {
         if self.use_rope {
@@ -240,17 +824,18 @@ impl Buffer {
         }
         
         // Check cache
-        if let Some(cached) = self.line_cache.get(&line_idx) {
+        if let Some((cached, _)) = self.line_cache.get(&line_idx) {
             return cached.clone();
         }
         
         // Cache miss: read from mmap (without updating cache)
-        if let Some(mmap) = self.mmap.as_ref() {
+        if let Some(mmap) = self.mapped() {
             if let Some(&start) = self.line_offsets.get(line_idx) {
                 let end = self.line_offsets.get(line_idx + 1)
                     .copied()
-                    .unwrap_or(mmap.len());
-                return String::from_utf8_lossy(&mmap[start..end]).to_string();
+                    .unwrap_or(mmap.len())
+                    .min(mmap.len());
+                return String::from_utf8_lossy(&mmap[start.min(end)..end]).to_string();
             }
         }
         
@@ -264,8 +849,7 @@ impl Buffer {
         }
         
         // For lazy mode, use cache and update LRU
-        let line = self.read_line_lazy(line_idx).unwrap_or_default();
-        line
+        self.read_line_lazy(line_idx).unwrap_or_default()
     }
 
     pub fn get_visible_lines(&self, start_line: usize, count: usize) -> String {
@@ -312,6 +896,17 @@ impl Buffer {
         }
     }
     
+    /// Line and character column of a byte offset, walking the line once.
+    /// An offset inside a character counts as that character.
+    pub fn offset_to_line_col(&self, byte_offset: usize) -> (usize, usize) {
+        let line = self.byte_offset_to_line(byte_offset);
+        let line_start = self.line_to_byte_offset(line);
+        let text = self.get_line(line);
+        let in_line = byte_offset.saturating_sub(line_start);
+        let col = text.char_indices().take_while(|&(i, c)| i + c.len_utf8() <= in_line).count();
+        (line, col)
+    }
+    
     /// Convert byte offset to line number
     pub fn byte_offset_to_line(&self, byte_offset: usize) -> usize {
         if let Some(rope) = &self.rope {
@@ -345,25 +940,9 @@ impl Buffer {
         self.modified
     }
     
-    /// Convert large file to rope mode (needed for editing)
-    /// Called automatically on first edit
-    fn ensure_rope_mode(&mut self) -> Result<()> {
-        if self.use_rope {
-            return Ok(());  // Already in rope mode
-        }
-        
-        // Convert to rope mode for editing
-        if let Some(mmap) = &self.mmap {
-            self.rope = Some(Rope::from_reader(mmap.as_ref())?);
-            self.use_rope = true;
-            // Keep line_offsets for potential future optimizations
-        }
-        
-        Ok(())
-    }
-    
     /// Insert text at the given byte offset
     pub fn insert(&mut self, offset: usize, text: &str) -> Result<()> {
+        self.check_writable()?;
         if self.use_rope {
             // Small file: use rope directly
             let rope = self.rope.as_mut().ok_or_else(|| anyhow::anyhow!("No rope available"))?;
@@ -388,6 +967,8 @@ impl Buffer {
             }
         }
         
+        self.record(TextChange { offset, removed: 0, inserted: text.len() });
+        self.revision += 1;
         self.modified = true;
         Ok(())
     }
@@ -397,6 +978,7 @@ impl Buffer {
         if start >= end {
             return Ok(());
         }
+        self.check_writable()?;
         
         if self.use_rope {
             // Small file: use rope directly
@@ -410,7 +992,23 @@ impl Buffer {
             let start_line = self.byte_offset_to_line(start);
             let end_line = self.byte_offset_to_line(end);
             
-            if start_line == end_line {
+            if start_line != end_line && (start_line..end_line).all(|line| !self.get_line(line).ends_with('\n')) {
+                // Across rows of one split line: the first row takes what's
+                // left of them and the rest are emptied, which still adds
+                // up to the same text
+                let first = self.get_line(start_line);
+                let last = self.get_line(end_line);
+                let head = start.saturating_sub(self.line_to_byte_offset(start_line));
+                let tail = end.saturating_sub(self.line_to_byte_offset(end_line));
+                if !first.is_char_boundary(head.min(first.len())) || !last.is_char_boundary(tail.min(last.len())) {
+                    anyhow::bail!("Delete at byte {} splits a character", start);
+                }
+                let content = format!("{}{}", &first[..head.min(first.len())], &last[tail.min(last.len())..]);
+                self.edits.insert(start_line, content);
+                for line in start_line + 1..=end_line {
+                    self.edits.insert(line, String::new());
+                }
+            } else if start_line == end_line {
                 // Single line delete
                 let mut line_content = self.get_line(start_line);
                 let line_start = self.line_to_byte_offset(start_line);
@@ -426,17 +1024,168 @@ impl Buffer {
             // Multi-line deletes: convert to rope mode for complex edits
             else if self.edits.len() > 100 {
                 // Too many edits, convert to rope
-                if let Some(mmap) = &self.mmap {
-                    self.rope = Some(Rope::from_reader(mmap.as_ref())?);                    self.use_rope = true;
+                if let Some(mmap) = self.mapped() {
+                    self.rope = Some(Rope::from_reader(mmap)?);
+                    self.use_rope = true;
                     return self.delete(start, end);
                 }
             }
         }
         
+        self.record(TextChange { offset: start, removed: end - start, inserted: 0 });
+        self.revision += 1;
         self.modified = true;
         Ok(())
     }
     
+    /// Make many replacements as one edit. `edits` are in the text as it is
+    /// now, sorted and not overlapping; nothing changes unless all of them
+    /// can be made. In rope mode they're made back to front, so each one's
+    /// offset still holds; in the lazy mode each line they touch is built
+    /// again once, and none of them may span lines. The revision is bumped
+    /// once and the changes recorded front to back, as if made in order.
+    pub fn apply_edits(&mut self, edits: &[Edit]) -> Result<OffsetMap> {
+        self.check_writable()?;
+        let len = self.len_bytes();
+        let mut end = 0;
+        for edit in edits {
+            if edit.file_offset < end {
+                anyhow::bail!("Edits overlap or are out of order at byte {}", edit.file_offset);
+            }
+            end = edit.file_offset + edit.old_len;
+            if end > len {
+                anyhow::bail!("Edit at byte {} runs past the end of the buffer", edit.file_offset);
+            }
+        }
+        if edits.is_empty() {
+            return Ok(OffsetMap::default());
+        }
+        
+        if self.use_rope {
+            let rope = self.rope.as_mut().ok_or_else(|| anyhow::anyhow!("No rope available"))?;
+            let char_at = |rope: &Rope, offset: usize| {
+                let char_idx = rope.byte_to_char(offset);
+                (rope.char_to_byte(char_idx) == offset).then_some(char_idx)
+            };
+            let mut spans = Vec::with_capacity(edits.len());
+            for edit in edits {
+                match (char_at(rope, edit.file_offset), char_at(rope, edit.file_offset + edit.old_len)) {
+                    (Some(start), Some(end)) => spans.push(start..end),
+                    _ => anyhow::bail!("Edit at byte {} splits a character", edit.file_offset),
+                }
+            }
+            for (edit, span) in edits.iter().zip(spans).rev() {
+                let start = span.start;
+                rope.remove(span);
+                rope.insert(start, &edit.new_text);
+            }
+        } else {
+            // Every line's new text first, so a bad edit leaves them all be
+            let mut lines: Vec<(usize, String)> = Vec::new();
+            let mut content = String::new();
+            let mut copied = 0;
+            for edit in edits {
+                let line = self.byte_offset_to_line(edit.file_offset);
+                let line_start = self.line_to_byte_offset(line);
+                if lines.last().is_none_or(|(last, _)| *last != line) {
+                    if let Some((_, text)) = lines.last_mut() {
+                        text.push_str(&content[copied..]);
+                    }
+                    content = self.get_line(line);
+                    copied = 0;
+                    lines.push((line, String::with_capacity(content.len())));
+                }
+                let col = edit.file_offset - line_start;
+                let end = col + edit.old_len;
+                let body = content.strip_suffix('\n').unwrap_or(&content).len();
+                if end > body || !content.is_char_boundary(col) || !content.is_char_boundary(end) {
+                    anyhow::bail!("Edit is outside line {}", line + 1);
+                }
+                let (_, text) = lines.last_mut().expect("pushed above");
+                text.push_str(&content[copied..col]);
+                text.push_str(&edit.new_text);
+                copied = end;
+            }
+            if let Some((_, text)) = lines.last_mut() {
+                text.push_str(&content[copied..]);
+            }
+            self.edits.extend(lines);
+        }
+        
+        let mut map = OffsetMap { changes: Vec::with_capacity(edits.len()), shifts: Vec::with_capacity(edits.len()) };
+        let mut shift = 0isize;
+        for edit in edits {
+            let change = TextChange { offset: edit.file_offset, removed: edit.old_len, inserted: edit.new_text.len() };
+            self.record(TextChange { offset: change.offset.saturating_add_signed(shift), ..change });
+            shift += change.inserted as isize - change.removed as isize;
+            map.changes.push(change);
+            map.shifts.push(shift);
+        }
+        self.revision += 1;
+        self.modified = true;
+        Ok(map)
+    }
+    
+    /// Line and byte column of `offset` while a large file is read lazily,
+    /// where edits go to per-line overlays and the line offsets stay those
+    /// of the file on disk; None in rope mode
+    pub fn line_position(&self, offset: usize) -> Option<(usize, usize)> {
+        if self.use_rope {
+            return None;
+        }
+        let line = self.byte_offset_to_line(offset);
+        Some((line, offset.saturating_sub(self.line_to_byte_offset(line))))
+    }
+    
+    /// Replace `removed` bytes at byte column `col` of `line` with `text`,
+    /// by line rather than by offset, for undo in the lazy mode. In rope
+    /// mode the line and column are turned into an offset.
+    pub fn replace_in_line(&mut self, line: usize, col: usize, removed: usize, text: &str) -> Result<()> {
+        self.check_writable()?;
+        let offset = self.line_to_byte_offset(line) + col;
+        if self.use_rope {
+            return self.replace(offset, offset + removed, text);
+        }
+        let mut content = self.get_line(line);
+        let end = col + removed;
+        if end > content.len() || !content.is_char_boundary(col) || !content.is_char_boundary(end) {
+            anyhow::bail!("Edit is outside line {}", line + 1);
+        }
+        content.replace_range(col..end, text);
+        self.edits.insert(line, content);
+        
+        self.record(TextChange { offset, removed, inserted: text.len() });
+        self.revision += 1;
+        self.modified = true;
+        Ok(())
+    }
+    
+    /// Whether edits go to per-line overlays over a lazily read file
+    pub fn is_lazy(&self) -> bool {
+        !self.use_rope
+    }
+    
+    /// Counter bumped by every edit and load: the text is unchanged while it
+    /// stays the same
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    
+    /// Drain the changes made since the last call, oldest first. Everything
+    /// kept by byte offset (the index, folds, diagnostics) shifts by these.
+    pub fn drain_deltas(&mut self) -> Vec<TextChange> {
+        self.changes.drain(..).collect()
+    }
+    
+    fn record(&mut self, change: TextChange) {
+        if self.changes.len() == MAX_DELTAS {
+            if let (Some(first), Some(second)) = (self.changes.pop_front(), self.changes.pop_front()) {
+                self.changes.push_front(first.merge(second));
+            }
+        }
+        self.changes.push_back(change);
+    }
+    
     /// Replace text in range [start, end) with new_text
     pub fn replace(&mut self, start: usize, end: usize, new_text: &str) -> Result<()> {
         self.delete(start, end)?;
@@ -444,18 +1193,36 @@ impl Buffer {
         Ok(())
     }
     
-    /// Get a slice of text from the buffer
+    /// Put `text` in place of all of the text, as one change. A file read
+    /// lazily is read that way no more, since every line of it changes:
+    /// the text goes in a rope.
+    pub fn replace_all(&mut self, text: &str) -> Result<()> {
+        self.check_writable()?;
+        let removed = self.len_bytes();
+        self.rope = Some(Rope::from_str(text));
+        self.use_rope = true;
+        self.edits.clear();
+        self.line_cache.clear();
+        self.cache_order.clear();
+        self.record(TextChange { offset: 0, removed, inserted: text.len() });
+        self.revision += 1;
+        self.modified = true;
+        Ok(())
+    }
+    
+    /// Get a slice of text from the buffer. An end inside a multi-byte
+    /// character is moved back to the character's start.
     pub fn slice(&self, range: std::ops::Range<usize>) -> String {
         if let Some(rope) = &self.rope {
             let start = range.start.min(rope.len_bytes());
-            let end = range.end.min(rope.len_bytes());
+            let end = rope.char_to_byte(rope.byte_to_char(range.end.min(rope.len_bytes())));
             if start >= end {
                 return String::new();
             }
             rope.byte_slice(start..end).to_string()
         } else {
             // Read from mmap
-            if let Some(mmap) = &self.mmap {
+            if let Some(mmap) = self.mapped() {
                 let start = range.start.min(mmap.len());
                 let end = range.end.min(mmap.len());
                 if start >= end {
@@ -468,6 +1235,112 @@ impl Buffer {
         }
     }
     
+    /// Write the bytes in `range` to `out` straight from the rope or the
+    /// mmap, without collecting them into a String first. Returns the number
+    /// of bytes written.
+    pub fn write_range(&self, range: std::ops::Range<usize>, out: &mut impl Write) -> Result<usize> {
+        if let Some(rope) = &self.rope {
+            let start = rope.char_to_byte(rope.byte_to_char(range.start.min(rope.len_bytes())));
+            let end = rope.char_to_byte(rope.byte_to_char(range.end.min(rope.len_bytes())));
+            if start >= end {
+                return Ok(0);
+            }
+            for chunk in rope.byte_slice(start..end).chunks() {
+                out.write_all(chunk.as_bytes())?;
+            }
+            Ok(end - start)
+        } else if let Some(mmap) = self.mapped() {
+            let start = range.start.min(mmap.len());
+            let end = range.end.min(mmap.len()).max(start);
+            out.write_all(&mmap[start..end])?;
+            Ok(end - start)
+        } else {
+            Ok(0)
+        }
+    }
+    
+    /// The first and last bytes that aren't whitespace, edited lines
+    /// included, or None for a blank buffer. Only the ends are read.
+    pub fn outer_bytes(&self) -> Option<(u8, u8)> {
+        if let Some(rope) = &self.rope {
+            let first = rope.bytes().find(|byte| !byte.is_ascii_whitespace())?;
+            let last = rope.bytes_at(rope.len_bytes()).reversed().find(|byte| !byte.is_ascii_whitespace())?;
+            return Some((first, last));
+        }
+        let mmap = self.mapped()?;
+        let outer = |line: usize, from_end: bool| {
+            let bytes = match self.edits.get(&line) {
+                Some(edited) => edited.as_bytes(),
+                None => {
+                    let start = *self.line_offsets.get(line)?;
+                    let end = self.line_offsets.get(line + 1).copied().unwrap_or(mmap.len()).min(mmap.len());
+                    &mmap[start.min(end)..end]
+                }
+            };
+            let mut solid = bytes.iter().copied().filter(|byte| !byte.is_ascii_whitespace());
+            if from_end { solid.next_back() } else { solid.next() }
+        };
+        let lines = self.line_count();
+        let first = (0..lines).find_map(|line| outer(line, false))?;
+        let last = (0..lines).rev().find_map(|line| outer(line, true))?;
+        Some((first, last))
+    }
+
+    /// Byte offset of the first occurrence of `pattern` at or after `from`.
+    /// Large files are searched in the mmap without copying.
+    pub fn find(&self, pattern: &str, from: usize) -> Option<usize> {
+        let needle = pattern.as_bytes();
+        let (&first, _) = needle.split_first()?;
+        if let Some(rope) = &self.rope {
+            let from = from.min(rope.len_bytes());
+            return rope.byte_slice(from..).to_string().find(pattern).map(|at| from + at);
+        }
+        let haystack = self.mapped()?;
+        let mut at = from;
+        while at + needle.len() <= haystack.len() {
+            at += haystack[at..].iter().position(|&b| b == first)?;
+            if haystack[at..].starts_with(needle) {
+                return Some(at);
+            }
+            at += 1;
+        }
+        None
+    }
+
+    /// Byte offset of the last occurrence of `pattern` starting before
+    /// `before`
+    pub fn rfind(&self, pattern: &str, before: usize) -> Option<usize> {
+        let needle = pattern.as_bytes();
+        if needle.is_empty() {
+            return None;
+        }
+        let text;
+        let haystack = match &self.rope {
+            Some(rope) => {
+                text = rope.to_string();
+                text.as_bytes()
+            }
+            None => self.mapped()?,
+        };
+        let end = (before + needle.len() - 1).min(haystack.len());
+        haystack[..end].windows(needle.len()).rposition(|window| window == needle)
+    }
+
+    /// The nearest character boundary at or before `offset`, clamped to the
+    /// end of the text
+    pub fn floor_char_boundary(&self, offset: usize) -> usize {
+        if let Some(rope) = &self.rope {
+            return rope.char_to_byte(rope.byte_to_char(offset.min(rope.len_bytes())));
+        }
+        let Some(mmap) = self.mapped() else { return 0 };
+        let mut offset = offset.min(mmap.len());
+        // Back off UTF-8 continuation bytes
+        while offset > 0 && offset < mmap.len() && mmap[offset] & 0xC0 == 0x80 {
+            offset -= 1;
+        }
+        offset
+    }
+    
     /// Get character at byte offset
     pub fn char_at(&self, byte_offset: usize) -> Option<char> {
         if let Some(rope) = &self.rope {
@@ -478,26 +1351,28 @@ impl Buffer {
             rope.char(char_idx).into()
         } else {
             // Read from mmap
-            self.mmap.as_ref()?
+            self.mapped()?
                 .get(byte_offset)
-                .and_then(|&b| Some(b as char))
+                .map(|&b| b as char)
         }
     }
 
-    /// Map a rope byte-offset (current in-memory view) back to the
-    /// original file byte-offset (before edits).
-    /// NOTE: With edit overlay architecture, this is simplified since we don't
-    /// track byte-level edits across the entire file. For accurate offset mapping,
-    /// a piece table would be needed (future work).
-    fn rope_to_original_offset(&self, rope_off: usize) -> usize {
-        // For lazy mode with edit overlay, rope offsets are not directly
-        // mappable to original file offsets without a piece table.
-        // For now, return the offset as-is (good enough for most operations)
-        rope_off
+    /// What save would do: nothing, for a file that's there unchanged,
+    /// unless `force`d
+    pub fn save_plan(&self, force: bool) -> SavePlan {
+        let on_disk = self.path.as_ref().is_some_and(|path| path.exists());
+        if !force && !self.modified && on_disk && !self.is_new_file() {
+            return SavePlan::Unchanged;
+        }
+        match self.use_rope {
+            true => SavePlan::Rope { bytes: self.len_bytes() },
+            false => SavePlan::Overlay { bytes: self.overlay_len(), edited: self.edits.len() },
+        }
     }
     
     /// Save buffer to file using incremental write strategy
     pub fn save(&mut self) -> Result<()> {
+        self.check_writable()?;
         if let Some(path) = &self.path {
             // CRITICAL FIX: The rope-to-original-offset mapping is unreliable
             // with multiple edits. To ensure saved content matches viewport,
@@ -507,81 +1382,57 @@ impl Buffer {
             // loading entire file into memory (would crash on 2GB+ files).
             // TODO: Implement proper piece table for true incremental saves
             
-            // For large files with edits, we need to build content with overlay applied
-            let needs_overlay = !self.use_rope && !self.edits.is_empty();
-            let content_source = if needs_overlay {
-                // Build content with edits applied line-by-line
-                let mut content = String::with_capacity(self.file_size);
-                for line_num in 0..self.line_count() {
-                    content.push_str(&self.get_line(line_num));
-                }
-                Some(content)
-            } else {
-                None
-            };
+            let snapshot = self.snapshot()?;
+            self.save_footprint = SAVE_BUFFER_BYTES + snapshot.footprint();
+            let total = snapshot.len_bytes();
             
-            let rope = self.rope.clone();
             let path_clone = path.clone();
+            let temp = save_temp_path(path);
+            self.save_progress = Arc::new(Progress::default());
+            self.save_progress.total.store(total, Ordering::Relaxed);
             let progress = Arc::clone(&self.save_progress);
-            let in_progress = Arc::clone(&self.save_in_progress);
+            let save_error = Arc::clone(&self.save_error);
+            let saved_file = Arc::clone(&self.saved_file);
             
-            // Mark save as in progress
-            in_progress.store(true, Ordering::SeqCst);
             self.save_pending = true;
+            self.save_started = Some(Instant::now());
             
             // Edits are preserved in the HashMap until file is reloaded
             
             // Spawn background thread to write rope chunks
-            std::thread::spawn(move || {
+            self.save_job = Some(std::thread::spawn(move || {
+                let mut timing = perf::scoped("save");
+                timing.bytes(total);
+                let mut created = false;
                 let result = (|| -> Result<()> {
-                    progress.store(10, Ordering::SeqCst);
-                    
-                    let temp = path_clone.with_extension("tmp");
-                    let file = File::create(&temp)?;
-                    let mut writer = BufWriter::with_capacity(8 * 1024 * 1024, file);
-                    
-                    progress.store(20, Ordering::SeqCst);
-                    
-                    if let Some(content) = content_source {
-                        // Large file with edit overlay: write merged content
-                        writer.write_all(content.as_bytes())?;
-                        progress.store(90, Ordering::SeqCst);
-                    } else if let Some(rope) = rope {
-                        // Small file with rope: stream chunks
-                        let total_bytes = rope.len_bytes();
-                        let mut written = 0;
-                        
-                        for chunk in rope.chunks() {
-                            writer.write_all(chunk.as_bytes())?;
-                            written += chunk.len();
-                            
-                            // Update progress (20-90%)
-                            let pct = 20 + ((written as f64 / total_bytes as f64) * 70.0) as u32;
-                            progress.store(pct.min(90), Ordering::SeqCst);
-                        }
-                    } else {
-                        return Err(anyhow::anyhow!("No content to save"));
-                    }
-                    
-                    writer.flush()?;
-                    
-                    progress.store(90, Ordering::SeqCst);
+                    // Opened for reading too, so it can be mapped once written
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create_new(true)
+                        .open(&temp)
+                        .with_context(|| format!("Can't create {}", temp.display()))?;
+                    created = true;
+                    let mut writer = BufWriter::with_capacity(SAVE_BUFFER_BYTES, file);
+                    let written = snapshot.write_to(&mut writer, &progress).and_then(|_| Ok(writer.into_inner()?));
+                    let file = written.with_context(|| format!("Can't write {}", temp.display()))?;
                     
                     // Atomic rename
-                    std::fs::rename(temp, &path_clone)?;
-                    
-                    progress.store(100, Ordering::SeqCst);
+                    std::fs::rename(&temp, &path_clone).with_context(|| format!("Can't replace {}", path_clone.display()))?;
+                    *saved_file.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(file);
                     Ok(())
                 })();
                 
-                // If any error occurred, set progress to 0
-                if result.is_err() {
-                    progress.store(0, Ordering::SeqCst);
+                // A failed or cancelled save leaves the file as it was: the
+                // half-written temp file goes, and why is kept for finalize_save
+                if let Err(e) = result {
+                    if created {
+                        let _ = std::fs::remove_file(&temp);
+                    }
+                    let why = if progress.is_cancelled() { "Save cancelled".to_string() } else { format!("Save failed: {:#}", e) };
+                    *save_error.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(why);
                 }
-                
-                // Mark not in progress anymore
-                in_progress.store(false, Ordering::SeqCst);
-            });
+            }));
             
             Ok(())
         } else {
@@ -589,126 +1440,155 @@ impl Buffer {
         }
     }
     
-    /// Save buffer to a specific path
-    pub fn save_as(&mut self, path: &str) -> Result<()> {
-        self.path = Some(PathBuf::from(path));
-        self.save()
+    /// The text as it is now, to write out or read through on another
+    /// thread while editing goes on. A rope clone shares its nodes with the
+    /// buffer, so only nodes edited since get copied; large files keep just
+    /// their edited lines and read the rest from the source file as they go.
+    pub fn snapshot(&self) -> Result<BufferSnapshot> {
+        let needs_overlay = !self.use_rope && !self.edits.is_empty();
+        let source = match (&self.rope, &self.source_path) {
+            (Some(rope), _) if !needs_overlay => SaveSource::Rope(rope.clone()),
+            (_, Some(source)) if needs_overlay || self.mmap.is_some() => SaveSource::Overlay {
+                source: source.clone(),
+                mmap: self.mmap.clone(),
+                file_size: self.mapped_len.min(self.file_size),
+                line_offsets: Arc::clone(&self.line_offsets),
+                edits: self.edits.clone(),
+                shifts: self.overlay_shifts(),
+            },
+            _ => anyhow::bail!("No content to save"),
+        };
+        let len = match &source {
+            SaveSource::Rope(rope) => rope.len_bytes(),
+            SaveSource::Overlay { .. } => self.overlay_len(),
+        };
+        Ok(BufferSnapshot { source, len, revision: self.revision })
     }
     
-    /// Full rewrite - used when no mmap or many edits
-    fn save_full(&self, path: &PathBuf) -> Result<()> {
-        let mut file = File::create(path)?;
-        
-        // Write rope contents to file efficiently
-        if let Some(rope) = &self.rope {
-            for chunk in rope.chunks() {
-                file.write_all(chunk.as_bytes())?;
-            }
-        }
-        file.flush()?;
-        
-        Ok(())
+    /// The edited lines in order, each with how far it and those before it
+    /// have moved the text after
+    fn overlay_shifts(&self) -> Vec<(usize, isize)> {
+        let mut lines: Vec<usize> = self.edits.keys().copied().collect();
+        lines.sort_unstable();
+        let mut shift = 0isize;
+        lines
+            .into_iter()
+            .map(|line| {
+                let start = self.line_offsets.get(line).copied().unwrap_or(self.file_size).min(self.file_size);
+                let end = self.line_offsets.get(line + 1).copied().unwrap_or(self.file_size).min(self.file_size);
+                shift += self.edits[&line].len() as isize - (end - start) as isize;
+                (line, shift)
+            })
+            .collect()
     }
     
-    /// Incremental save - streams from mmap and applies edits
-    fn save_incremental(&self, path: &PathBuf) -> Result<()> {
-        // Create temp file for atomic write
-        let temp_path = path.with_extension("tmp");
-        let temp_file = File::create(&temp_path)?;
-        let mut writer = BufWriter::new(temp_file);
-        
-        // Sort edits by file offset for sequential processing
-        // For lazy mode with edits, we don't need to merge
-        // The edit overlay is already per-line and non-overlapping
-        let mut sorted_edits: Vec<Edit> = vec![];
-        sorted_edits.sort_by_key(|e| e.file_offset);
-        
-        // Merge overlapping edits
-        let merged_edits = self.merge_edits(sorted_edits);
-        
-        // Stream from mmap and apply edits
-        if let Some(ref mmap) = self.mmap {
-            let mut file_pos = 0;
-            
-            for edit in &merged_edits {
-                // Write unchanged region from original file
-                if file_pos < edit.file_offset {
-                    writer.write_all(&mmap[file_pos..edit.file_offset])?;
-                }
-                
-                // Write edited content
-                writer.write_all(edit.new_text.as_bytes())?;
-                
-                // Skip over the replaced bytes in original file
-                file_pos = edit.file_offset + edit.old_len;
-            }
-            
-            // Write remaining unchanged data
-            if file_pos < mmap.len() {
-                writer.write_all(&mmap[file_pos..])?;
-            }
-        }
-        
-        writer.flush()?;
-        drop(writer);
-        
-        // Atomic rename (safe, no data loss risk)
-        std::fs::rename(&temp_path, path)?;
-        
-        Ok(())
+    /// Bytes in the file with the edited lines laid over it
+    fn overlay_len(&self) -> usize {
+        self.edits.iter().fold(self.file_size, |len, (&line, edited)| {
+            let start = self.line_offsets.get(line).copied().unwrap_or(self.file_size).min(self.file_size);
+            let end = self.line_offsets.get(line + 1).copied().unwrap_or(self.file_size).min(self.file_size);
+            len - (end - start) + edited.len()
+        })
+    }
+
+    /// Bytes a save in progress holds beyond the buffer itself: its write
+    /// buffer and a copy of any edited lines
+    pub fn save_footprint(&self) -> Option<usize> {
+        self.is_saving().then_some(self.save_footprint)
     }
     
-    /// Merge overlapping edits in edit log
-    fn merge_edits(&self, mut edits: Vec<Edit>) -> Vec<Edit> {
-        if edits.is_empty() {
-            return edits;
-        }
-        
-        let mut merged = Vec::new();
-        let mut current = edits.remove(0);
-        
-        for edit in edits {
-            let current_end = current.file_offset + current.old_len;
-            
-            // Check if edits overlap or are adjacent
-            if edit.file_offset <= current_end {
-                // Merge edits
-                let new_end = (edit.file_offset + edit.old_len).max(current_end);
-                current.old_len = new_end - current.file_offset;
-                current.new_text.push_str(&edit.new_text);
-            } else {
-                // No overlap, push current and start new
-                merged.push(current);
-                current = edit;
+    /// How long the last save took, up to the frame that noticed it finish
+    pub fn last_save_duration(&self) -> Option<Duration> {
+        self.last_save
+    }
+    
+    /// Edited lines of a large file held in memory until the next save
+    pub fn pending_edits(&self) -> usize {
+        self.edits.len()
+    }
+
+    /// Which lines of a large file those are, in order
+    pub fn edited_lines(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = self.edits.keys().copied().collect();
+        lines.sort_unstable();
+        lines
+    }
+    
+    /// Save buffer to a specific path. The JSON form of a converted file
+    /// can be saved anywhere but over that file, and is edited as any
+    /// other JSON from then on.
+    pub fn save_as(&mut self, path: &str) -> Result<()> {
+        if let Some(format) = self.converted_from {
+            if self.overwrites_converted(Path::new(path)) {
+                anyhow::bail!("That's the {} file the JSON was converted from", format);
             }
+            self.converted_from = None;
         }
-        
-        merged.push(current);
-        merged
+        self.path = Some(PathBuf::from(path));
+        self.save()
     }
-
+    
     /// Return true when a background save is running
     pub fn is_saving(&self) -> bool {
-        self.save_in_progress.load(Ordering::SeqCst)
+        self.save_job.as_ref().is_some_and(|job| !job.is_finished())
+    }
+
+    /// Finalize a background save once it's done, and say how it went.
+    /// Unlike finalize_save alone this can't miss a failure reported
+    /// earlier, since whatever isn't saved still leaves the buffer modified.
+    pub fn save_outcome(&mut self) -> SaveOutcome {
+        if self.is_saving() {
+            return SaveOutcome::Saving;
+        }
+        match self.finalize_save() {
+            Err(e) => SaveOutcome::Unsaved(Some(e.to_string())),
+            Ok(()) if self.is_modified() => SaveOutcome::Unsaved(None),
+            Ok(()) => SaveOutcome::Saved,
+        }
     }
 
     /// Get current save progress percent (0..=100)
     pub fn save_progress_percent(&self) -> u32 {
-        self.save_progress.load(Ordering::SeqCst)
+        self.save_progress.percent() as u32
+    }
+
+    /// Progress of the save in progress, or the last one, shared with the
+    /// thread writing it: setting its cancel flag stops the save
+    pub fn save_progress(&self) -> Arc<Progress> {
+        Arc::clone(&self.save_progress)
     }
 
     /// Called by main loop to finalize after background save finishes.
-    /// This reloads the mmap to sync with saved file.
+    /// This maps the file the save wrote, the one it renamed into place,
+    /// rather than opening the path again: by now another process may have
+    /// moved it away or put something else there. A save that failed is
+    /// reported once, as an error, and leaves the buffer modified so :w can
+    /// try again.
     pub fn finalize_save(&mut self) -> Result<()> {
-        if self.save_pending && !self.save_in_progress.load(Ordering::SeqCst) {
+        if self.save_pending && !self.is_saving() {
+            self.save_job = None;
+            let failed = self.save_error.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+            if let Some(error) = failed {
+                self.save_pending = false;
+                self.save_started = None;
+                anyhow::bail!(error);
+            }
             if let Some(path) = &self.path {
                 // Re-mmap the saved file (rope already has correct content)
-                let file = File::open(path)?;
+                let saved = self.saved_file.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+                let file = match saved {
+                    Some(file) => file,
+                    None => File::open(path)?,
+                };
                 let mmap = unsafe { Mmap::map(&file)? };
-                self.mmap = Some(mmap);
+                self.mapped_len = mmap.len();
+                self.mmap = Some(Arc::new(mmap));
+                self.mapped_file = Some(file);
+                self.truncated = false;
+                self.source_path = Some(path.clone());
                 
                 // Reset progress and state
-                self.save_progress.store(0, Ordering::SeqCst);
+                self.last_save = self.save_started.take().map(|started| started.elapsed());
                 self.save_pending = false;
                 self.modified = false;
             }
@@ -718,155 +1598,6 @@ impl Buffer {
     
 
     
-    /// Save using Copy-on-Write: reflink clone + in-place edits
-    /// Fastest method when filesystem supports it (Btrfs, XFS, APFS)
-    fn save_cow_bg(
-        path: &PathBuf,
-        edits: &[Edit],
-        progress: &Arc<AtomicU32>,
-    ) -> Result<()> {
-        let temp = path.with_extension("tmp");
-        
-        progress.store(10, Ordering::SeqCst);
-        
-        // Step 1: Create reflink copy (instant, shares disk blocks)
-        reflink_copy::reflink(path, &temp)
-            .map_err(|e| anyhow::anyhow!("Reflink failed: {}", e))?;
-        
-        progress.store(30, Ordering::SeqCst);
-        
-        // Step 2: Merge overlapping edits
-        let merged = Self::merge_edits_static(edits);
-        let edit_count = merged.len();
-        
-        progress.store(40, Ordering::SeqCst);
-        
-        // Step 3: Open temp file and apply edits in-place (only modified blocks written)
-        let mut file = OpenOptions::new()
-            .write(true)
-            .open(&temp)?;
-        
-        for (idx, edit) in merged.iter().enumerate() {
-            // Seek to edit location
-            file.seek(SeekFrom::Start(edit.file_offset as u64))?;
-            
-            // Write new content
-            file.write_all(edit.new_text.as_bytes())?;
-            
-            // Update progress (40-90%)
-            let edit_progress = 40 + (idx * 50 / edit_count.max(1));
-            progress.store(edit_progress as u32, Ordering::SeqCst);
-        }
-        
-        file.flush()?;
-        drop(file);
-        
-        progress.store(95, Ordering::SeqCst);
-        
-        // Step 4: Atomic rename
-        std::fs::rename(temp, path)?;
-        
-        progress.store(100, Ordering::SeqCst);
-        Ok(())
-    }
-    
-    /// Save using streaming: read original mmap + apply edit patches
-    /// Universal method that works on all filesystems
-    fn save_streaming_bg(
-        mmap: &Mmap,
-        edits: &[Edit],
-        path: &PathBuf,
-        progress: &Arc<AtomicU32>,
-    ) -> Result<()> {
-        let temp_path = path.with_extension("tmp");
-        let temp_file = File::create(&temp_path)?;
-        let mut writer = BufWriter::with_capacity(8 * 1024 * 1024, temp_file);
-        
-        progress.store(5, Ordering::SeqCst);
-        
-        // Merge overlapping edits
-        let merged = Self::merge_edits_static(edits);
-        
-        progress.store(10, Ordering::SeqCst);
-        
-        let total = mmap.len();
-        let mut written: usize = 0;
-        
-        // Helper to update progress (10..95%)
-        let update_progress = |written_bytes: usize| {
-            let pct = 10 + ((written_bytes as f64 / total as f64) * 85.0) as u32;
-            progress.store(pct.min(95), Ordering::SeqCst);
-        };
-        
-        // Stream original bytes + edit patches
-        let mut file_pos: usize = 0;
-        for edit in &merged {
-            // Copy unchanged section from original
-            if file_pos < edit.file_offset {
-                let chunk = &mmap[file_pos..edit.file_offset];
-                writer.write_all(chunk)?;
-                written += chunk.len();
-                update_progress(written);
-            }
-            
-            // Write edited content
-            writer.write_all(edit.new_text.as_bytes())?;
-            written += edit.new_text.len();
-            update_progress(written);
-            
-            file_pos = edit.file_offset + edit.old_len;
-        }
-        
-        // Copy remaining bytes
-        if file_pos < mmap.len() {
-            let chunk = &mmap[file_pos..];
-            writer.write_all(chunk)?;
-            written += chunk.len();
-            update_progress(written);
-        }
-        
-        writer.flush()?;
-        drop(writer);
-        
-        progress.store(98, Ordering::SeqCst);
-        
-        // Atomic rename
-        std::fs::rename(&temp_path, path)?;
-        
-        progress.store(100, Ordering::SeqCst);
-        Ok(())
-    }
-    
-    /// Static helper for merging edits (used by background threads)
-    fn merge_edits_static(edits: &[Edit]) -> Vec<Edit> {
-        if edits.is_empty() {
-            return Vec::new();
-        }
-        
-        let mut sorted = edits.to_vec();
-        sorted.sort_by_key(|e| e.file_offset);
-        
-        let mut merged = Vec::new();
-        let mut current = sorted[0].clone();
-        
-        for edit in sorted.iter().skip(1) {
-            let current_end = current.file_offset + current.old_len;
-            
-            if edit.file_offset <= current_end {
-                // Overlapping: merge
-                let new_end = (edit.file_offset + edit.old_len).max(current_end);
-                current.old_len = new_end - current.file_offset;
-                current.new_text.push_str(&edit.new_text);
-            } else {
-                // Non-overlapping: push current and start new
-                merged.push(current);
-                current = edit.clone();
-            }
-        }
-        
-        merged.push(current);
-        merged
-    }
 }
 
 impl Default for Buffer {
@@ -874,3 +1605,53 @@ impl Default for Buffer {
         Self::new()
     }
 }
+
+/// A file next to `path` for a save to write before renaming it over
+/// `path`. The process id and a count keep it apart from other saves and
+/// from anything already there.
+fn save_temp_path(path: &Path) -> PathBuf {
+    static SAVES: AtomicUsize = AtomicUsize::new(0);
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}-{}.tmp", std::process::id(), SAVES.fetch_add(1, Ordering::Relaxed)));
+    path.with_file_name(name)
+}
+
+/// Line starts in `text` with lines longer than `LONG_LINE_BYTES` cut into
+/// rows. A row ends after a comma outside a string, so the rows of a
+/// minified document break between values and a chunk of them can be
+/// tokenized on its own; one long string is cut wherever it reaches
+/// `MAX_ROW_BYTES`, on a character boundary.
+fn split_long_lines(text: &[u8], offsets: &[usize]) -> Vec<usize> {
+    let mut rows = Vec::with_capacity(offsets.len());
+    for (i, &start) in offsets.iter().enumerate() {
+        rows.push(start);
+        let end = offsets.get(i + 1).copied().unwrap_or(text.len()).min(text.len());
+        if end.saturating_sub(start) <= LONG_LINE_BYTES {
+            continue;
+        }
+        // Lines never start inside a string, as strings can't hold a newline
+        let (mut row_start, mut in_string, mut escaped) = (start, false, false);
+        for at in start..end {
+            let byte = text[at];
+            if escaped {
+                escaped = false;
+            } else if in_string {
+                match byte {
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+            } else if byte == b'"' {
+                in_string = true;
+            }
+            let len = at + 1 - row_start;
+            let cut = (!in_string && byte == b',' && len >= ROW_BYTES)
+                || (len >= MAX_ROW_BYTES && text.get(at + 1).is_some_and(|&next| next & 0xC0 != 0x80));
+            if cut && at + 1 < end {
+                row_start = at + 1;
+                rows.push(row_start);
+            }
+        }
+    }
+    rows
+}
@@ -0,0 +1,143 @@
+//! Reading ahead of the view in a large file. Lines of a memory-mapped
+//! file come off the disk the first time they're touched, in the middle of
+//! drawing a frame; while scrolling through a part not read yet that's a
+//! hitch every time the view crosses into it. The prefetcher has a thread
+//! touch the pages of the screens either side of the view as soon as it
+//! moves, so they're in memory by the time they're drawn.
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+use memmap2::Mmap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use super::Buffer;
+
+/// Screens read ahead of the view, and behind it
+pub const SCREENS_AHEAD: usize = 2;
+
+/// Bytes a page-in brings in at the least
+const PAGE_SIZE: usize = 4096;
+
+/// Requests waiting for the thread; once it's this far behind, requests
+/// for views already scrolled past are dropped rather than queued
+const QUEUE_LEN: usize = 8;
+
+pub struct Prefetcher {
+    tx: Sender<(Arc<Mmap>, Range<usize>)>,
+    /// (top line, height, buffer revision) last read around
+    last: Option<(usize, usize, u64)>,
+    pages: Arc<AtomicUsize>,
+}
+
+impl Prefetcher {
+    pub fn new() -> Self {
+        let (tx, rx) = bounded(QUEUE_LEN);
+        let pages = Arc::new(AtomicUsize::new(0));
+        let worker_pages = Arc::clone(&pages);
+        // Ends when the prefetcher is dropped and the channel closes
+        thread::spawn(move || Self::worker(rx, &worker_pages));
+        Self { tx, last: None, pages }
+    }
+
+    fn worker(rx: Receiver<(Arc<Mmap>, Range<usize>)>, pages: &AtomicUsize) {
+        while let Ok((mmap, range)) = rx.recv() {
+            #[cfg(unix)]
+            let _ = mmap.advise_range(memmap2::Advice::WillNeed, range.start, range.len());
+            pages.fetch_add(touch_pages(&mmap[range]), Ordering::Relaxed);
+        }
+    }
+
+    /// Have the screens around a view of `height` lines from `top` read in,
+    /// if the view moved since last time. Lines held in memory (a small
+    /// file, or edited lines) have nothing to read.
+    pub fn around(&mut self, buffer: &Buffer, top: usize, height: usize) {
+        let key = (top, height, buffer.revision());
+        if self.last == Some(key) {
+            return;
+        }
+        self.last = Some(key);
+        let reach = height * SCREENS_AHEAD;
+        let total = buffer.line_count();
+        let after = (top + height).min(total)..(top + height + reach).min(total);
+        let before = top.saturating_sub(reach)..top.min(total);
+        for lines in [after, before] {
+            if lines.is_empty() {
+                continue;
+            }
+            if let Some(request) = buffer.mapped_lines(lines) {
+                // A full queue means the thread is behind; it'll catch up
+                // with the next move
+                let _ = self.tx.try_send(request);
+            }
+        }
+    }
+
+    /// Pages the thread has touched so far
+    pub fn pages_read(&self) -> usize {
+        self.pages.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Prefetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read a byte of each page of `bytes`, so the kernel maps them all in.
+/// Returns how many pages that was.
+fn touch_pages(bytes: &[u8]) -> usize {
+    let mut sum = 0u8;
+    for byte in bytes.iter().step_by(PAGE_SIZE) {
+        sum = sum.wrapping_add(*byte);
+    }
+    std::hint::black_box(sum);
+    bytes.len().div_ceil(PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_prefetch_around_view() {
+        let path = std::env::temp_dir().join(format!("jim_prefetch_{}.json", std::process::id()));
+        // Over the size kept in a rope, 100 bytes a line
+        let line = format!("{}\n", "x".repeat(99));
+        std::fs::write(&path, line.repeat(120_000)).unwrap();
+        let mut buffer = Buffer::new();
+        buffer.load_file(path.to_str().unwrap()).unwrap();
+
+        // Two screens after a 50-line view at the top, and none before it
+        let mut prefetcher = Prefetcher::new();
+        prefetcher.around(&buffer, 0, 50);
+        let expected = (100 * 100usize).div_ceil(PAGE_SIZE);
+        let start = Instant::now();
+        while prefetcher.pages_read() < expected && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(prefetcher.pages_read(), expected);
+
+        // The same view again asks for nothing more
+        prefetcher.around(&buffer, 0, 50);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(prefetcher.pages_read(), expected);
+
+        // A small file has nothing to read
+        let mut prefetcher = Prefetcher::new();
+        prefetcher.around(&Buffer::from_text(&line.repeat(1000)), 500, 50);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(prefetcher.pages_read(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_touch_pages() {
+        assert_eq!(touch_pages(&[]), 0);
+        assert_eq!(touch_pages(&[1; PAGE_SIZE]), 1);
+        assert_eq!(touch_pages(&vec![1; PAGE_SIZE * 3 + 1]), 4);
+    }
+}
@@ -1,5 +1,4 @@
 use std::fs;
-use std::io::Write;
 
 #[test]
 fn test_save_matches_viewport() {
@@ -14,11 +13,11 @@ fn test_save_matches_viewport() {
     buffer.load_file(test_path).unwrap();
     
     // Make some edits (simulate user typing)
-    let insert_pos = buffer.rope().len_bytes() - 1; // Before closing }
+    let insert_pos = buffer.len_bytes() - 1; // Before closing }
     buffer.insert(insert_pos, r#", "new_field": "hello""#).unwrap();
     
     // What we see in viewport
-    let viewport_content = buffer.rope().to_string();
+    let viewport_content = buffer.slice(0..buffer.len_bytes());
     
     // Save
     buffer.save().unwrap();
@@ -44,3 +43,687 @@ fn test_save_matches_viewport() {
         saved_content
     );
 }
+
+#[test]
+fn test_save_failure() {
+    use crate::buffer::Buffer;
+    
+    let dir = std::env::temp_dir().join(format!("jim_test_save_failure_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("data.json");
+    fs::write(&path, r#"{"a": 1}"#).unwrap();
+    let mut buffer = Buffer::new();
+    buffer.load_file(path.to_str().unwrap()).unwrap();
+    buffer.insert(7, "2").unwrap();
+    let edited = buffer.slice(0..buffer.len_bytes());
+    let wait = |buffer: &mut Buffer| {
+        while buffer.is_saving() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        buffer.finalize_save()
+    };
+    
+    // A directory where the file should go can't be replaced, even by
+    // root, which a read-only directory could be
+    let blocked = dir.join("blocked.json");
+    fs::create_dir_all(&blocked).unwrap();
+    fs::write(dir.join("blocked.tmp"), "not ours").unwrap();
+    buffer.save_as(blocked.to_str().unwrap()).unwrap();
+    let error = wait(&mut buffer).unwrap_err().to_string();
+    assert!(error.starts_with("Save failed: Can't replace"), "{}", error);
+    let mut left: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    left.sort();
+    assert_eq!(left, ["blocked.json", "blocked.tmp", "data.json"], "the temp file was left behind");
+    assert_eq!(fs::read_to_string(dir.join("blocked.tmp")).unwrap(), "not ours");
+    assert!(buffer.is_modified());
+    assert_eq!(buffer.slice(0..buffer.len_bytes()), edited);
+    // Reported once
+    assert!(buffer.finalize_save().is_ok());
+    
+    // Nor can a file be made in a directory that isn't there
+    buffer.save_as(dir.join("missing/data.json").to_str().unwrap()).unwrap();
+    assert!(wait(&mut buffer).unwrap_err().to_string().contains("Can't create"));
+    assert!(buffer.is_modified());
+    
+    // Trying again somewhere it can go works straight away
+    buffer.save_as(path.to_str().unwrap()).unwrap();
+    wait(&mut buffer).unwrap();
+    assert!(!buffer.is_modified());
+    assert_eq!(fs::read_to_string(&path).unwrap(), edited);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_save_outcome() {
+    use crate::buffer::{Buffer, SaveOutcome};
+    use std::time::Duration;
+    
+    let dir = std::env::temp_dir().join(format!("jim_test_save_outcome_{}", std::process::id()));
+    fs::create_dir_all(dir.join("blocked.json")).unwrap();
+    let path = dir.join("data.json");
+    fs::write(&path, r#"{"a": 1}"#).unwrap();
+    let mut buffer = Buffer::new();
+    buffer.load_file(path.to_str().unwrap()).unwrap();
+    buffer.insert(7, "2").unwrap();
+    let wait = |buffer: &mut Buffer| loop {
+        match buffer.save_outcome() {
+            SaveOutcome::Saving => std::thread::sleep(Duration::from_millis(10)),
+            outcome => return outcome,
+        }
+    };
+    
+    // Holding the error slot keeps the failing save from finishing; until
+    // it does, a :wq waiting on it mustn't go
+    let slot = std::sync::Arc::clone(&buffer.save_error);
+    let held = slot.lock().unwrap();
+    buffer.save_as(dir.join("blocked.json").to_str().unwrap()).unwrap();
+    for _ in 0..5 {
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(buffer.save_outcome(), SaveOutcome::Saving);
+        assert!(buffer.is_saving());
+    }
+    drop(held);
+    let SaveOutcome::Unsaved(Some(error)) = wait(&mut buffer) else {
+        panic!("the failed save wasn't reported");
+    };
+    assert!(error.starts_with("Save failed: Can't replace"), "{}", error);
+    
+    // A failure the main loop already reported still keeps it from quitting
+    buffer.save_as(dir.join("blocked.json").to_str().unwrap()).unwrap();
+    while buffer.is_saving() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(buffer.finalize_save().is_err());
+    assert_eq!(buffer.save_outcome(), SaveOutcome::Unsaved(None));
+    
+    buffer.save_as(path.to_str().unwrap()).unwrap();
+    assert_eq!(wait(&mut buffer), SaveOutcome::Saved);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_new_file() {
+    use crate::buffer::Buffer;
+    
+    let dir = std::env::temp_dir().join(format!("jim_test_new_file_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("new.json");
+    let mut buffer = Buffer::new();
+    buffer.open(path.to_str().unwrap()).unwrap();
+    assert!(buffer.is_new_file());
+    assert!(buffer.is_empty());
+    assert!(!buffer.is_modified());
+    assert!(!path.exists());
+    assert_eq!(buffer.missing_directory(), None);
+    
+    buffer.insert(0, "{}\n").unwrap();
+    buffer.save().unwrap();
+    while buffer.is_saving() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    buffer.finalize_save().unwrap();
+    assert!(!buffer.is_new_file());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "{}\n");
+    
+    buffer.open(dir.join("sub/new.json").to_str().unwrap()).unwrap();
+    assert_eq!(buffer.missing_directory(), Some(dir.join("sub")));
+    
+    // A directory is refused rather than read
+    let error = buffer.open(dir.to_str().unwrap()).unwrap_err().to_string();
+    assert!(error.ends_with("is a directory"), "{}", error);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_file_moved_before_finalize() {
+    use crate::buffer::Buffer;
+    
+    // Another program renames the file between the save writing it and
+    // the main loop finishing the save: the buffer keeps what it wrote
+    let dir = std::env::temp_dir().join(format!("jim_test_moved_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("data.json");
+    fs::write(&path, "[1]\n").unwrap();
+    let mut buffer = Buffer::new();
+    buffer.load_file(path.to_str().unwrap()).unwrap();
+    buffer.insert(2, ", 2").unwrap();
+    buffer.save().unwrap();
+    while buffer.is_saving() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    fs::rename(&path, dir.join("moved.json")).unwrap();
+    
+    buffer.finalize_save().unwrap();
+    assert!(!buffer.is_modified());
+    assert_eq!(buffer.slice(0..buffer.len_bytes()), "[1, 2]\n");
+    assert_eq!(fs::read_to_string(dir.join("moved.json")).unwrap(), "[1, 2]\n");
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_close() {
+    use crate::buffer::Buffer;
+    
+    let dir = std::env::temp_dir().join(format!("jim_test_close_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("data.json");
+    fs::write(&path, "[1]\n").unwrap();
+    let mut buffer = Buffer::new();
+    buffer.load_file(path.to_str().unwrap()).unwrap();
+    buffer.insert(2, ", 2").unwrap();
+    let revision = buffer.revision();
+    
+    buffer.close().unwrap();
+    assert!(buffer.path().is_none());
+    assert!(buffer.is_empty());
+    assert!(!buffer.is_modified());
+    assert!(buffer.revision() > revision);
+    // Nothing holds the file any more
+    fs::rename(&path, dir.join("moved.json")).unwrap();
+    assert!(buffer.insert(0, "{}").is_ok());
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_replace_all_lazy() {
+    use crate::buffer::{Buffer, TextChange};
+    
+    let path = std::env::temp_dir().join(format!("jim_test_replace_all_{}.json", std::process::id()));
+    let line = "{\"id\": 1, \"name\": \"test\"}\n";
+    fs::write(&path, line.repeat(11 * 1024 * 1024 / line.len())).unwrap();
+    let mut buffer = Buffer::new();
+    buffer.load_file(path.to_str().unwrap()).unwrap();
+    assert!(buffer.is_lazy());
+    buffer.insert(1, " ").unwrap();
+    buffer.drain_deltas();
+    let len = buffer.len_bytes();
+    
+    buffer.replace_all("[1,\n2]\n").unwrap();
+    assert!(!buffer.is_lazy());
+    assert_eq!(buffer.slice(0..buffer.len_bytes()), "[1,\n2]\n");
+    assert_eq!((buffer.line_count(), buffer.get_line(1).as_str()), (3, "2]\n"));
+    assert_eq!(buffer.drain_deltas(), vec![TextChange { offset: 0, removed: len, inserted: 7 }]);
+    assert!(buffer.is_modified());
+    fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_save_plan() {
+    use crate::buffer::{Buffer, SavePlan, CONFIRM_SAVE_BYTES};
+    
+    let dir = std::env::temp_dir().join(format!("jim_test_save_plan_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("data.json");
+    fs::write(&path, "[1]\n").unwrap();
+    let mut buffer = Buffer::new();
+    buffer.load_file(path.to_str().unwrap()).unwrap();
+    // Untouched: nothing to write, unless forced
+    assert_eq!(buffer.save_plan(false), SavePlan::Unchanged);
+    assert_eq!(buffer.save_plan(true), SavePlan::Rope { bytes: 4 });
+    buffer.insert(2, ", 2").unwrap();
+    assert_eq!(buffer.save_plan(false), SavePlan::Rope { bytes: 7 });
+    
+    // Written once saved
+    buffer.save().unwrap();
+    while buffer.is_saving() {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    buffer.finalize_save().unwrap();
+    assert_eq!(buffer.save_plan(false), SavePlan::Unchanged);
+    // A new file, or one deleted since, is written even unchanged
+    fs::remove_file(&path).unwrap();
+    assert_eq!(buffer.save_plan(false), SavePlan::Rope { bytes: 7 });
+    buffer.new_file(dir.join("new.json").to_str().unwrap());
+    assert_eq!(buffer.save_plan(false), SavePlan::Rope { bytes: 0 });
+    
+    // A large file streams from the file with its edited lines over it
+    let line = "{\"id\": 1, \"name\": \"test\"}\n";
+    let text = line.repeat(11 * 1024 * 1024 / line.len());
+    fs::write(&path, &text).unwrap();
+    buffer.load_file(path.to_str().unwrap()).unwrap();
+    assert!(buffer.is_lazy());
+    assert_eq!(buffer.save_plan(false), SavePlan::Unchanged);
+    buffer.insert(1, " ").unwrap();
+    buffer.insert(buffer.line_to_byte_offset(1000), "  ").unwrap();
+    let plan = buffer.save_plan(false);
+    assert_eq!(plan, SavePlan::Overlay { bytes: text.len() + 3, edited: 2 });
+    assert!(!plan.is_large());
+    assert!(SavePlan::Overlay { bytes: CONFIRM_SAVE_BYTES, edited: 2 }.is_large());
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_view_lines() {
+    use crate::buffer::{Buffer, ViewLines};
+    
+    let mut buffer = Buffer::from_text("[\n  1,\n  2\n]\n");
+    let mut view = ViewLines::new();
+    assert!(view.fetch(&mut buffer, 1, 10));
+    assert_eq!(view.get(1), Some("  1,\n"));
+    assert_eq!(view.get(3), Some("]\n"));
+    assert_eq!(view.get(0), None);
+    
+    // Nothing to read again until the view or the text changes
+    assert!(!view.fetch(&mut buffer, 1, 10));
+    assert!(view.fetch(&mut buffer, 0, 10));
+    let revision = buffer.revision();
+    buffer.insert(5, "0").unwrap();
+    assert!(buffer.revision() > revision);
+    assert!(view.fetch(&mut buffer, 0, 10));
+    assert_eq!(view.get(1), Some("  10,\n"));
+
+    // Scrolling keeps the lines still in view
+    assert!(view.fetch(&mut buffer, 1, 2));
+    assert!(view.fetch(&mut buffer, 2, 2));
+    assert_eq!((view.get(1), view.get(2), view.get(3)), (None, Some("  2\n"), Some("]\n")));
+    assert!(view.fetch(&mut buffer, 0, 3));
+    assert_eq!((view.get(0), view.get(2), view.get(3)), (Some("[\n"), Some("  2\n"), None));
+}
+
+#[test]
+fn test_save_large_file_with_edits() {
+    use crate::buffer::Buffer;
+    
+    // Big enough to be read lazily, with an edit in the overlay and a last
+    // line with no newline
+    let test_path = std::env::temp_dir().join(format!("jim_test_save_large_{}.json", std::process::id()));
+    let record = "{\"id\": 1, \"name\": \"item\"},\n";
+    let original = format!("[\n{}{{\"id\": 2}}]", record.repeat(11 * 1024 * 1024 / record.len()));
+    fs::write(&test_path, &original).unwrap();
+    
+    let mut buffer = Buffer::new();
+    buffer.load_file(test_path.to_str().unwrap()).unwrap();
+    let offset = buffer.line_to_byte_offset(1) + "{\"id\": ".len();
+    buffer.insert(offset, "4").unwrap();
+    // Unedited lines can still be read in place
+    let (map, range) = buffer.mapped_lines(2..4).unwrap();
+    assert_eq!(&map[range], record.repeat(2).as_bytes());
+    assert!(buffer.mapped_lines(0..2).is_none());
+    
+    buffer.save().unwrap();
+    assert!(buffer.save_footprint().is_some_and(|bytes| bytes < 16 * 1024 * 1024) || !buffer.is_saving());
+    while buffer.is_saving() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    buffer.finalize_save().unwrap();
+    
+    let saved = fs::read_to_string(&test_path).unwrap();
+    fs::remove_file(&test_path).ok();
+    let expected = original.replacen("{\"id\": 1", "{\"id\": 41", 1);
+    assert_eq!(saved.len(), expected.len());
+    assert!(saved == expected, "saved file differs from the edited buffer");
+}
+
+#[test]
+fn test_truncated_file() {
+    use crate::buffer::Buffer;
+    
+    // Cut a lazily read file short under the buffer: reads past the new
+    // end come back empty rather than faulting, and edits are refused
+    let test_path = std::env::temp_dir().join(format!("jim_test_truncated_{}.json", std::process::id()));
+    let record = "{\"id\": 1, \"name\": \"item\"},\n";
+    fs::write(&test_path, record.repeat(11 * 1024 * 1024 / record.len())).unwrap();
+    
+    let mut buffer = Buffer::new();
+    buffer.load_file(test_path.to_str().unwrap()).unwrap();
+    assert!(!buffer.check_truncated());
+    let lines = buffer.line_count();
+    fs::OpenOptions::new().write(true).open(&test_path).unwrap().set_len(4096).unwrap();
+    assert!(buffer.check_truncated());
+    assert!(buffer.is_truncated());
+    // Reported once
+    assert!(!buffer.check_truncated());
+    
+    assert_eq!(buffer.get_line(0), record);
+    assert_eq!(buffer.get_line(lines - 2), "");
+    let len = buffer.len_bytes();
+    assert_eq!(buffer.slice(len - 100..len), "");
+    assert!(buffer.insert(0, "x").is_err());
+    assert!(buffer.delete(0, 1).is_err());
+    assert!(buffer.save().is_err());
+    
+    // Loading it again takes the file as it is now
+    buffer.load_file(test_path.to_str().unwrap()).unwrap();
+    fs::remove_file(&test_path).ok();
+    assert!(!buffer.is_truncated());
+    assert_eq!(buffer.len_bytes(), 4096);
+    assert!(buffer.insert(0, "x").is_ok());
+}
+
+#[test]
+fn test_offset_to_line_col() {
+    use crate::buffer::Buffer;
+    
+    // Repeated characters, where matching by value stopped at the first
+    // copy, and multi-byte ones, where columns aren't bytes
+    let buffer = Buffer::from_text("{\"aa\": \"aa\"}\n[\"é\", \"日本\", 1]\n");
+    assert_eq!(buffer.offset_to_line_col(0), (0, 0));
+    assert_eq!(buffer.offset_to_line_col(2), (0, 2));
+    assert_eq!(buffer.offset_to_line_col(8), (0, 8));
+    assert_eq!(buffer.offset_to_line_col(12), (0, 12));
+    let line = buffer.line_to_byte_offset(1);
+    assert_eq!(buffer.offset_to_line_col(line), (1, 0));
+    // [ " é " , ␠ " 日 本 " , ␠ 1
+    assert_eq!(buffer.offset_to_line_col(line + "[\"é\"".len()), (1, 4));
+    assert_eq!(buffer.offset_to_line_col(line + "[\"é\", \"日本".len()), (1, 9));
+    assert_eq!(buffer.offset_to_line_col(line + "[\"é\", \"日本\", ".len()), (1, 12));
+    // Inside a character counts as that character
+    assert_eq!(buffer.offset_to_line_col(line + "[\"é\", \"".len() + 1), (1, 7));
+}
+
+#[test]
+fn test_write_range() {
+    use crate::buffer::Buffer;
+    
+    let text = "{\"a\": [\"é\", 2]}";
+    let buffer = Buffer::from_text(text);
+    let mut out = Vec::new();
+    assert_eq!(buffer.write_range(6..14, &mut out).unwrap(), 8);
+    assert_eq!(String::from_utf8(out).unwrap(), buffer.slice(6..14));
+    let mut out = Vec::new();
+    assert_eq!(buffer.write_range(20..30, &mut out).unwrap(), 0);
+}
+
+#[test]
+fn test_find() {
+    use crate::buffer::Buffer;
+    
+    let buffer = Buffer::from_text("{\"é\": \"needle\", \"b\": \"needle\"}");
+    assert_eq!(buffer.find("needle", 0), Some(8));
+    assert_eq!(buffer.find("needle", 9), Some(23));
+    assert_eq!(buffer.find("needle", 24), None);
+    assert_eq!(buffer.find("", 0), None);
+    // Backward, a match the offset falls inside counts
+    assert_eq!(buffer.rfind("needle", 40), Some(23));
+    assert_eq!(buffer.rfind("needle", 23), Some(8));
+    assert_eq!(buffer.rfind("needle", 10), Some(8));
+    assert_eq!(buffer.rfind("needle", 8), None);
+}
+
+#[test]
+fn test_merged_deltas() {
+    use crate::buffer::{Buffer, TextChange};
+    
+    // A run of changes merged into one names a span that, swapped for the
+    // same span of the final text, turns the text before the run into it
+    let mut buffer = Buffer::from_text("{\"a\": [1, 2, 3], \"b\": \"text\"}");
+    let mut texts = vec![buffer.slice(0..buffer.len_bytes())];
+    let edits = [(3, 3, "xyz"), (0, 2, ""), (20, 25, "."), (10, 10, "[]"), (5, 9, "-"), (1, 20, "q")];
+    for &(start, end, text) in &edits {
+        if start < end {
+            buffer.delete(start, end).unwrap();
+            texts.push(buffer.slice(0..buffer.len_bytes()));
+        }
+        buffer.insert(start, text).unwrap();
+        texts.push(buffer.slice(0..buffer.len_bytes()));
+    }
+    let deltas = buffer.drain_deltas();
+    assert_eq!(deltas.len(), texts.len() - 1);
+    assert!(buffer.drain_deltas().is_empty());
+    let last = texts.last().unwrap();
+    for from in 0..deltas.len() {
+        let merged = deltas[from..].iter().copied().reduce(TextChange::merge).unwrap();
+        let before = &texts[from];
+        let after = format!(
+            "{}{}{}",
+            &before[..merged.offset],
+            &last[merged.offset..merged.offset + merged.inserted],
+            &before[merged.offset + merged.removed..]
+        );
+        assert_eq!(&after, last, "merging from change {}", from);
+    }
+    
+    // Past the limit the oldest are merged rather than dropped
+    let mut buffer = Buffer::from_text("");
+    for i in 0..3000 {
+        buffer.insert(i, "x").unwrap();
+    }
+    let deltas = buffer.drain_deltas();
+    assert!(deltas.len() <= 1024);
+    assert_eq!(deltas.iter().map(|d| d.inserted - d.removed).sum::<usize>(), 3000);
+}
+
+#[test]
+fn test_shift_past_change() {
+    use crate::buffer::TextChange;
+    
+    // "abcdefgh" with "cde" replaced by "XY"
+    let change = TextChange { offset: 2, removed: 3, inserted: 2 };
+    assert_eq!(change.shift_offset(1), 1);
+    assert_eq!(change.shift_offset(2), 2);
+    assert_eq!(change.shift_offset(4), 2);
+    assert_eq!(change.shift_offset(5), 4);
+    assert_eq!(change.shift_span(&(0..2)), Some(0..2));
+    assert_eq!(change.shift_span(&(5..8)), Some(4..7));
+    assert_eq!(change.shift_span(&(1..3)), None);
+    assert_eq!(change.shift_span(&(4..6)), None);
+    
+    // An insertion drops a span it lands in or at the start of, but not one
+    // it follows
+    let change = TextChange { offset: 2, removed: 0, inserted: 3 };
+    assert_eq!(change.shift_span(&(0..2)), Some(0..2));
+    assert_eq!(change.shift_span(&(1..3)), None);
+    assert_eq!(change.shift_span(&(2..4)), None);
+    assert_eq!(change.shift_span(&(3..4)), Some(6..7));
+    assert_eq!(change.shift_offset(2), 5);
+}
+
+#[test]
+fn test_apply_edits() {
+    use crate::buffer::{Buffer, Edit, TextChange};
+    
+    let edit = |file_offset: usize, old_len: usize, new_text: &str| Edit { file_offset, old_len, new_text: new_text.to_string() };
+    let before = "[{\"ok\": false}, {\"ok\": false}, \"é\"]";
+    let mut buffer = Buffer::from_text(before);
+    let revision = buffer.revision();
+    let edits = [edit(0, 0, "\n"), edit(8, 5, "true"), edit(23, 5, "true"), edit(31, 4, "\"e\"")];
+    let map = buffer.apply_edits(&edits).unwrap();
+    let after = "\n[{\"ok\": true}, {\"ok\": true}, \"e\"]";
+    assert_eq!(buffer.slice(0..buffer.len_bytes()), after);
+    assert_eq!(buffer.revision(), revision + 1);
+    assert!(buffer.is_modified());
+    assert_eq!(map.net(), after.len() as isize - before.len() as isize);
+    // Before, inside, at the end of and past the replacements
+    assert_eq!(map.map(0), 1);
+    assert_eq!(map.map(9), 10);
+    assert_eq!(map.map(12), 13);
+    assert_eq!(map.map(13), 13);
+    assert_eq!(map.map(before.len()), after.len());
+    
+    // The deltas, made one after another, turn the old text into the new
+    let mut text = before.to_string();
+    for change in buffer.drain_deltas() {
+        let inserted = &after[change.offset..change.offset + change.inserted];
+        text.replace_range(change.offset..change.offset + change.removed, inserted);
+    }
+    assert_eq!(text, after);
+    
+    // Out of order, overlapping, past the end or inside a character: none made
+    let revision = buffer.revision();
+    assert!(buffer.apply_edits(&[edit(5, 0, "x"), edit(2, 0, "y")]).is_err());
+    assert!(buffer.apply_edits(&[edit(2, 3, "x"), edit(4, 0, "y")]).is_err());
+    assert!(buffer.apply_edits(&[edit(2, 0, "x"), edit(after.len(), 1, "y")]).is_err());
+    assert!(Buffer::from_text("\"é\"").apply_edits(&[edit(0, 0, "x"), edit(2, 1, "y")]).is_err());
+    assert_eq!(buffer.slice(0..buffer.len_bytes()), after);
+    assert_eq!(buffer.revision(), revision);
+    assert!(buffer.drain_deltas().is_empty());
+    assert_eq!(buffer.apply_edits(&[]).unwrap(), Default::default());
+    assert_eq!(buffer.revision(), revision);
+    
+    // Inserts at one offset go in the order given
+    let mut buffer = Buffer::from_text("ab");
+    buffer.apply_edits(&[edit(1, 0, "x"), edit(1, 0, "y"), edit(1, 1, "")]).unwrap();
+    assert_eq!(buffer.slice(0..buffer.len_bytes()), "axy");
+    assert_eq!(buffer.drain_deltas(), [
+        TextChange { offset: 1, removed: 0, inserted: 1 },
+        TextChange { offset: 2, removed: 0, inserted: 1 },
+        TextChange { offset: 3, removed: 1, inserted: 0 },
+    ]);
+}
+
+#[test]
+fn test_apply_edits_lazy() {
+    use crate::buffer::{Buffer, Edit};
+    
+    // Big enough to be read lazily
+    let test_path = std::env::temp_dir().join(format!("jim_test_apply_edits_{}.json", std::process::id()));
+    let record = "{\"id\": 1, \"name\": \"item\"},\n";
+    fs::write(&test_path, format!("[\n{}]", record.repeat(11 * 1024 * 1024 / record.len()))).unwrap();
+    let mut buffer = Buffer::new();
+    buffer.load_file(test_path.to_str().unwrap()).unwrap();
+    fs::remove_file(&test_path).ok();
+    assert!(buffer.is_lazy());
+    
+    fn edit(buffer: &Buffer, line: usize, col: usize, old_len: usize, new_text: &str) -> Edit {
+        Edit { file_offset: buffer.line_to_byte_offset(line) + col, old_len, new_text: new_text.to_string() }
+    }
+    let edits = [
+        edit(&buffer, 1, 7, 1, "10"),
+        edit(&buffer, 1, 19, 4, "first"),
+        edit(&buffer, 3, 1, 9, ""),
+        edit(&buffer, 1000, 7, 1, "3"),
+    ];
+    let revision = buffer.revision();
+    buffer.apply_edits(&edits).unwrap();
+    assert_eq!(buffer.revision(), revision + 1);
+    assert_eq!(buffer.get_line(1), "{\"id\": 10, \"name\": \"first\"},\n");
+    assert_eq!(buffer.get_line(2), record);
+    assert_eq!(buffer.get_line(3), "{\"name\": \"item\"},\n");
+    assert_eq!(buffer.get_line(1000), "{\"id\": 3, \"name\": \"item\"},\n");
+    assert_eq!(buffer.pending_edits(), 3);
+    
+    // Taking a newline would join lines the overlay keeps apart
+    let edits = [edit(&buffer, 4, 0, 0, "x"), edit(&buffer, 5, record.len() - 2, 2, "")];
+    assert!(buffer.apply_edits(&edits).is_err());
+    assert_eq!(buffer.get_line(4), record);
+
+    // The ends of the document come from the overlay when it has them
+    assert_eq!(buffer.outer_bytes(), Some((b'[', b']')));
+    let last = buffer.line_count() - 1;
+    buffer.apply_edits(&[edit(&buffer, last, 0, 1, "")]).unwrap();
+    assert_eq!(buffer.outer_bytes(), Some((b'[', b',')));
+}
+
+#[test]
+fn test_split_long_line() {
+    use crate::buffer::{Buffer, LONG_LINE_BYTES, ROW_BYTES};
+    use std::time::Instant;
+    
+    // 50 MB of minified JSON on one line, with a long string of multi-byte
+    // characters that has to be cut inside it
+    let test_path = std::env::temp_dir().join(format!("jim_test_split_line_{}.json", std::process::id()));
+    let record = "{\"id\":1,\"name\":\"item\",\"tags\":[\"a\",\"b\"]},";
+    let long = "é".repeat(3 * ROW_BYTES);
+    let original = format!("[\"{}\",{}{{\"id\":2}}]", long, record.repeat(50 * 1024 * 1024 / record.len()));
+    fs::write(&test_path, &original).unwrap();
+    
+    let mut buffer = Buffer::new();
+    buffer.load_file(test_path.to_str().unwrap()).unwrap();
+    assert!(buffer.has_split_lines());
+    let rows = buffer.line_count();
+    assert!(rows > original.len() / (4 * ROW_BYTES), "{} rows", rows);
+    
+    // Every row is short and they add up to the file; the long string's
+    // rows end on character boundaries
+    let started = Instant::now();
+    let mut joined = String::new();
+    for row in (0..8).chain(rows / 2..rows / 2 + 1000).chain(rows - 3..rows) {
+        let text = buffer.get_line_cached(row);
+        assert!(text.len() <= 4 * ROW_BYTES);
+        assert_eq!(buffer.line_to_byte_offset(row) + text.len(), buffer.line_to_byte_offset(row + 1));
+        if row < 8 {
+            joined.push_str(&text);
+        }
+    }
+    assert!(original.starts_with(&joined));
+    assert!(buffer.get_line(rows - 1).ends_with("{\"id\":2}]"));
+    assert!(started.elapsed().as_millis() < 200, "scrolling took {:?}", started.elapsed());
+    
+    // A small edit, and a delete across the boundary between two rows
+    let started = Instant::now();
+    let at = buffer.line_to_byte_offset(rows / 2);
+    let removed = buffer.slice(at - 5..at + 5);
+    buffer.delete(at - 5, at + 5).unwrap();
+    buffer.insert(at - 5, "\"x\"").unwrap();
+    assert!(started.elapsed().as_millis() < 200, "editing took {:?}", started.elapsed());
+    assert!(buffer.get_line(rows / 2 - 1).len() <= 4 * ROW_BYTES + 3);
+    
+    buffer.save().unwrap();
+    while buffer.is_saving() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    buffer.finalize_save().unwrap();
+    let saved = fs::read_to_string(&test_path).unwrap();
+    fs::remove_file(&test_path).ok();
+    let mut expected = original.clone();
+    expected.replace_range(at - 5..at + 5, "\"x\"");
+    assert_eq!(&original[at - 5..at + 5], removed);
+    assert!(saved == expected, "saved file differs from the edited buffer");
+    
+    // Lines under the threshold stay whole
+    let text = format!("{}\n", "1,".repeat(LONG_LINE_BYTES / 2 - 1));
+    assert_eq!(super::split_long_lines(text.as_bytes(), &[0, text.len()]), [0, text.len()]);
+}
+
+#[test]
+fn test_snapshot_while_editing() {
+    use crate::buffer::{Buffer, BufferSnapshot};
+    
+    /// Read `snapshot` over and over on another thread, checking it against
+    /// `text` each time, while `edit` runs on this one
+    fn read_while(snapshot: BufferSnapshot, text: String, edit: impl FnOnce()) {
+        let reader = std::thread::spawn(move || {
+            let lines: Vec<&str> = text.split_inclusive('\n').collect();
+            for round in 0..50 {
+                assert_eq!(snapshot.len_bytes(), text.len());
+                assert_eq!(snapshot.line_count(), lines.len());
+                let line = round * 7919 % lines.len();
+                assert_eq!(snapshot.get_line(line), lines[line]);
+                let start = round * 104_729 % text.len();
+                let end = (start + 5000).min(text.len());
+                assert_eq!(snapshot.slice(start..end), text[start..end]);
+            }
+            assert_eq!(snapshot.slice(0..text.len()), text);
+        });
+        edit();
+        reader.join().unwrap();
+    }
+    
+    let record = "{\"id\": 1, \"name\": \"item\"},\n";
+    let text = format!("[\n{}]", record.repeat(20_000));
+    let mut buffer = Buffer::from_text(&text);
+    let snapshot = buffer.snapshot().unwrap();
+    assert_eq!(snapshot.revision(), buffer.revision());
+    read_while(snapshot, text.clone(), || {
+        for i in 0..200 {
+            buffer.insert(i * 97, "\"x\", ").unwrap();
+            buffer.delete(i * 31, i * 31 + 3).unwrap();
+        }
+    });
+    assert_ne!(buffer.slice(0..buffer.len_bytes()), text);
+    
+    // Big enough to be read lazily, with an edit before the snapshot that
+    // moves every line after it
+    let test_path = std::env::temp_dir().join(format!("jim_test_snapshot_{}.json", std::process::id()));
+    let text = format!("[\n{}]", record.repeat(11 * 1024 * 1024 / record.len()));
+    fs::write(&test_path, &text).unwrap();
+    let mut buffer = Buffer::new();
+    buffer.load_file(test_path.to_str().unwrap()).unwrap();
+    fs::remove_file(&test_path).ok();
+    assert!(buffer.is_lazy());
+    let at = buffer.line_to_byte_offset(3) + 7;
+    buffer.replace(at, at + 1, "1000").unwrap();
+    let mut edited = text.clone();
+    edited.replace_range(at..at + 1, "1000");
+    let snapshot = buffer.snapshot().unwrap();
+    let revision = snapshot.revision();
+    assert_eq!(snapshot.slice(at - 3..at + 7), "\": 1000, \"");
+    read_while(snapshot, edited, || {
+        for line in (0..buffer.line_count()).step_by(1000) {
+            let at = buffer.line_to_byte_offset(line);
+            buffer.insert(at, " ").unwrap();
+        }
+    });
+    assert!(buffer.is_lazy());
+    assert!(buffer.revision() > revision);
+}
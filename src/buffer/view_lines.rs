@@ -0,0 +1,53 @@
+use super::Buffer;
+
+/// The lines a window shows, fetched through the buffer's line cache and
+/// kept from frame to frame until the view moves, the window is resized or
+/// the text changes
+#[derive(Debug, Default)]
+pub struct ViewLines {
+    /// (first line, line count, buffer revision) of what's held
+    key: Option<(usize, usize, u64)>,
+    start: usize,
+    lines: Vec<String>,
+}
+
+impl ViewLines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold lines `start..start + count` of `buffer` (fewer at the end of the
+    /// file), reading them again only if the range or the text changed.
+    /// When only the view moved, the lines it still shows are kept and just
+    /// those scrolled into it are read. Returns whether any were read.
+    pub fn fetch(&mut self, buffer: &mut Buffer, start: usize, count: usize) -> bool {
+        let key = (start, count, buffer.revision());
+        if self.key == Some(key) {
+            return false;
+        }
+        let end = (start + count).min(buffer.line_count());
+        let held = match self.key {
+            Some((_, _, revision)) if revision == key.2 => self.start..self.start + self.lines.len(),
+            _ => 0..0,
+        };
+        let kept = start.max(held.start)..end.min(held.end);
+        if kept.is_empty() {
+            self.lines.clear();
+            self.lines.extend((start..end).map(|line_idx| buffer.get_line_cached(line_idx)));
+        } else {
+            self.lines.truncate(kept.end - held.start);
+            self.lines.drain(..kept.start - held.start);
+            let above: Vec<String> = (start..kept.start).map(|line_idx| buffer.get_line_cached(line_idx)).collect();
+            self.lines.splice(0..0, above);
+            self.lines.extend((kept.end..end).map(|line_idx| buffer.get_line_cached(line_idx)));
+        }
+        self.start = start;
+        self.key = Some(key);
+        true
+    }
+
+    /// Line `line_idx`, newline included, if it's one of those held
+    pub fn get(&self, line_idx: usize) -> Option<&str> {
+        self.lines.get(line_idx.checked_sub(self.start)?).map(String::as_str)
+    }
+}
@@ -0,0 +1,284 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::mode::{Binding, Keymap, Mode};
+
+/// A value in a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Bool(bool),
+    Integer(i64),
+}
+
+/// One `key = value` line and the `[table]` it sits under ("" before any)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub line: usize,
+    pub table: String,
+    pub key: String,
+    pub value: Value,
+}
+
+/// Read the flat subset of TOML the config files use: `[table]` headers,
+/// `key = value` lines with bare or quoted keys, string, boolean and
+/// integer values, and `#` comments. Lines that don't parse are reported
+/// and skipped.
+pub fn parse_entries(text: &str) -> (Vec<Entry>, Vec<String>) {
+    let mut entries = Vec::new();
+    let mut warnings = Vec::new();
+    let mut table = String::new();
+    for (n, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            match strip_comment(header).trim().strip_suffix(']') {
+                Some(name) => table = name.trim().to_string(),
+                None => warnings.push(format!("line {}: unterminated table header", n + 1)),
+            }
+            continue;
+        }
+        match parse_entry(line) {
+            Ok((key, value)) => entries.push(Entry { line: n + 1, table: table.clone(), key, value }),
+            Err(e) => warnings.push(format!("line {}: {}", n + 1, e)),
+        }
+    }
+    (entries, warnings)
+}
+
+fn parse_entry(line: &str) -> Result<(String, Value), String> {
+    let (key, rest) = if line.starts_with('"') || line.starts_with('\'') {
+        let (key, rest) = parse_quoted(line)?;
+        let rest = rest.trim_start().strip_prefix('=').ok_or("expected '=' after key")?;
+        (key, rest)
+    } else {
+        let (key, rest) = line.split_once('=').ok_or("expected `key = value`")?;
+        (key.trim().to_string(), rest)
+    };
+    if key.is_empty() {
+        return Err("missing key".to_string());
+    }
+
+    let rest = rest.trim();
+    let (value, tail) = if rest.starts_with('"') || rest.starts_with('\'') {
+        let (text, tail) = parse_quoted(rest)?;
+        (Value::String(text), tail)
+    } else {
+        let word = strip_comment(rest).trim();
+        let value = match word {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => match word.replace('_', "").parse() {
+                Ok(n) => Value::Integer(n),
+                Err(_) => return Err(format!("invalid value '{}' (quote strings)", word)),
+            },
+        };
+        (value, "")
+    };
+    if !strip_comment(tail).trim().is_empty() {
+        return Err("unexpected text after value".to_string());
+    }
+    Ok((key, value))
+}
+
+/// Split a leading quoted string off `text`. Double quotes take backslash
+/// escapes, single quotes are literal.
+fn parse_quoted(text: &str) -> Result<(String, &str), String> {
+    let quote = text.chars().next().ok_or("expected a string")?;
+    let mut out = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            c if c == quote => return Ok((out, &text[i + 1..])),
+            '\\' if quote == '"' => match chars.next().map(|(_, c)| c) {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(c @ ('"' | '\\')) => out.push(c),
+                Some(c) => return Err(format!("unknown escape '\\{}'", c)),
+                None => break,
+            },
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Drop a trailing `# comment`, leaving `#` inside quotes alone
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, ch) in text.char_indices() {
+        match (ch, quote) {
+            ('"' | '\'', None) => quote = Some(ch),
+            (c, Some(q)) if c == q => quote = None,
+            ('#', None) => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+/// ~/.config/jim, honoring XDG_CONFIG_HOME
+pub fn config_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("jim"))
+}
+
+/// ~/.local/state/jim, honoring XDG_STATE_HOME
+pub fn state_dir() -> Option<PathBuf> {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+    Some(state.join("jim"))
+}
+
+/// Where what's kept about `file` goes under `state_dir()/kind`: named by
+/// a hash of its full path
+pub fn state_file(kind: &str, file: &Path) -> Option<PathBuf> {
+    let file = std::fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    // FNV-1a, stable across builds unlike DefaultHasher
+    let hash = file.as_os_str().as_encoded_bytes().iter().fold(0xcbf29ce484222325u64, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    state_dir().map(|dir| dir.join(kind).join(format!("{:016x}", hash)))
+}
+
+/// Settings loaded at startup or with :source
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// `[options]`, as :set arguments ("number", "nowrap", "theme=light")
+    pub options: Vec<String>,
+    /// `[keymap.normal]` and `[keymap.insert]`
+    pub keymap: Keymap,
+}
+
+impl Config {
+    /// ~/.config/jim/config.toml
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<(Self, Vec<String>)> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        Ok(Self::parse(&text))
+    }
+
+    /// Parse a config file. Anything invalid is skipped and reported, so a
+    /// typo never stops the rest of the file from applying.
+    ///
+    /// ```toml
+    /// [options]
+    /// relativenumber = true
+    /// theme = "light"
+    ///
+    /// [keymap.normal]
+    /// "gs" = "structural_next_sibling"
+    /// "<C-s>" = ":w"
+    ///
+    /// [keymap.insert]
+    /// "jk" = "normal_mode"
+    /// ```
+    pub fn parse(text: &str) -> (Self, Vec<String>) {
+        let (entries, mut warnings) = parse_entries(text);
+        let mut config = Self::default();
+        for entry in entries {
+            let mode = match entry.table.as_str() {
+                "options" => {
+                    config.options.push(match entry.value {
+                        Value::Bool(true) => entry.key,
+                        Value::Bool(false) => format!("no{}", entry.key),
+                        Value::String(value) => format!("{}={}", entry.key, value),
+                        Value::Integer(value) => format!("{}={}", entry.key, value),
+                    });
+                    continue;
+                }
+                "keymap.normal" => Mode::Normal,
+                "keymap.insert" => Mode::Insert,
+                table => {
+                    warnings.push(format!("line {}: unknown table [{}]", entry.line, table));
+                    continue;
+                }
+            };
+            let Value::String(value) = entry.value else {
+                warnings.push(format!("line {}: a mapping's action must be a string", entry.line));
+                continue;
+            };
+            let mapped = Binding::parse(&value).and_then(|binding| config.keymap.map(mode, &entry.key, binding));
+            if let Err(e) = mapped {
+                warnings.push(format!("line {}: {}", entry.line, e));
+            }
+        }
+        (config, warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mode::{Action, Lookup};
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    #[test]
+    fn test_parse_entries() {
+        let text = "top = 1\n[a.b]  # section\n\"=\" = 'x # y'\nk = \"q\\\"t\" # note\nbad\nn = yes\n";
+        let (entries, warnings) = parse_entries(text);
+        let got: Vec<_> = entries.iter().map(|e| (e.table.as_str(), e.key.as_str(), e.value.clone())).collect();
+        assert_eq!(got, vec![
+            ("", "top", Value::Integer(1)),
+            ("a.b", "=", Value::String("x # y".into())),
+            ("a.b", "k", Value::String("q\"t".into())),
+        ]);
+        assert_eq!(warnings, vec![
+            "line 5: expected `key = value`",
+            "line 6: invalid value 'yes' (quote strings)",
+        ]);
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let text = r#"
+[options]
+relativenumber = true
+wrap = false
+theme = "light"
+
+[keymap.normal]
+"gs" = "structural_next_sibling"
+"<C-s>" = ":w"
+"Q" = "no_such_action"
+
+[keymap.insert]
+"jk" = "normal_mode"
+"x" = ":w"
+
+[colors]
+key = "red"
+"#;
+        let (config, warnings) = Config::parse(text);
+        assert_eq!(config.options, vec!["relativenumber", "nowrap", "theme=light"]);
+
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        assert_eq!(
+            config.keymap.lookup(Mode::Normal, &[key('g'), key('s')]),
+            Lookup::Found(Binding::Action(Action::StructuralNextSibling))
+        );
+        let ctrl_s = KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL);
+        assert_eq!(config.keymap.lookup(Mode::Normal, &[ctrl_s]), Lookup::Found(Binding::Command("w".into())));
+        assert_eq!(
+            config.keymap.lookup(Mode::Insert, &[key('j'), key('k')]),
+            Lookup::Found(Binding::Action(Action::NormalMode))
+        );
+        assert_eq!(warnings, vec![
+            "line 10: unknown action 'no_such_action'",
+            "line 14: ':w' can't be mapped in insert mode",
+            "line 17: unknown table [colors]",
+        ]);
+    }
+}
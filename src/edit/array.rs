@@ -0,0 +1,219 @@
+//! Reordering the elements of an array. Elements move as they are written;
+//! only their order changes, and the separators stay where they were.
+
+use std::cmp::Ordering;
+
+use anyhow::{bail, Result};
+
+use super::structural::{check_complete, member_at, replace};
+use super::Edit;
+use crate::buffer::cursor::Cursor;
+use crate::buffer::Buffer;
+use crate::parser::decode::{self, DecodedNumber};
+use crate::parser::node::NodeId;
+use crate::parser::{NodeKind, StructuralIndex};
+
+/// What :array does to the array under the cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayOp {
+    Sort { descending: bool },
+    Unique,
+    Count,
+}
+
+impl ArrayOp {
+    /// Parse the argument to :array; `sort!` sorts descending
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sort" => Some(Self::Sort { descending: false }),
+            "sort!" => Some(Self::Sort { descending: true }),
+            "unique" | "uniq" => Some(Self::Unique),
+            "count" => Some(Self::Count),
+            _ => None,
+        }
+    }
+
+    /// The name `from_name` reads back
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sort { descending: false } => "sort",
+            Self::Sort { descending: true } => "sort!",
+            Self::Unique => "unique",
+            Self::Count => "count",
+        }
+    }
+}
+
+/// One element with what it's compared by
+struct Element {
+    kind: NodeKind,
+    text: String,
+    number: Option<DecodedNumber>,
+    string: Option<String>,
+}
+
+impl Element {
+    fn new(kind: NodeKind, text: String) -> Self {
+        let number = if kind == NodeKind::Number { decode::decode_number(&text) } else { None };
+        let string = if kind == NodeKind::String { decode::decode_string(&text).ok() } else { None };
+        Self { kind, text, number, string }
+    }
+
+    /// Where the element's type sorts in a mixed array
+    fn rank(&self) -> u8 {
+        match self.kind {
+            NodeKind::Null => 0,
+            NodeKind::Boolean => 1,
+            NodeKind::Number => 2,
+            NodeKind::String => 3,
+            NodeKind::Array => 4,
+            _ => 5,
+        }
+    }
+
+    /// Numbers by value, strings by code point, everything else by its text
+    /// (which puts false before true)
+    fn compare(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank()).then_with(|| match self.kind {
+            NodeKind::Number => match (&self.number, &other.number) {
+                (Some(a), Some(b)) => a.total_cmp(b),
+                (a, b) => a.is_none().cmp(&b.is_none()),
+            },
+            NodeKind::String if self.string.is_some() && other.string.is_some() => self.string.cmp(&other.string),
+            _ => self.text.cmp(&other.text),
+        })
+    }
+}
+
+/// The array under the cursor: the innermost one around it, or the value
+/// of the key it's on
+fn array_at(index: &StructuralIndex, offset: usize) -> Option<NodeId> {
+    let mut node = member_at(index, offset)?.value;
+    while index.kind(node)? != NodeKind::Array {
+        node = index.parent(node)?;
+    }
+    Some(node)
+}
+
+/// Apply `op` to the array under the cursor, leaving the cursor on its
+/// opening bracket. Returns the edits (none when nothing changed) and a
+/// summary for the status line.
+pub fn array(buffer: &mut Buffer, cursor: &mut Cursor, index: &StructuralIndex, op: ArrayOp) -> Result<(Vec<Edit>, String)> {
+    let Some(array) = array_at(index, cursor.byte_offset) else {
+        bail!("Not in an array");
+    };
+    check_complete(index, array)?;
+    if op == ArrayOp::Count {
+        let count = index.child_iter(array).count();
+        let plural = if count == 1 { "" } else { "s" };
+        return Ok((Vec::new(), format!("{} element{}", count, plural)));
+    }
+    let ids: Vec<NodeId> = index.child_iter(array).collect();
+    let mut spans = Vec::with_capacity(ids.len());
+    for &id in &ids {
+        let Some(node) = index.get(id) else {
+            bail!("Array isn't fully indexed yet");
+        };
+        if matches!(node.kind, NodeKind::Error | NodeKind::Unknown) || !index.is_closed(id) && node.is_container() {
+            bail!("Array has malformed elements");
+        }
+        spans.push(node.start..node.end);
+    }
+    let (Some(first), Some(last)) = (spans.first(), spans.last()) else {
+        return Ok((Vec::new(), "Empty array".to_string()));
+    };
+    let elements: Vec<Element> = ids.iter()
+        .zip(&spans)
+        .map(|(&id, span)| Element::new(index.kind(id).unwrap_or(NodeKind::Unknown), buffer.slice(span.clone())))
+        .collect();
+
+    let mut order: Vec<usize> = (0..elements.len()).collect();
+    let message = match op {
+        ArrayOp::Sort { descending } => {
+            order.sort_by(|&a, &b| {
+                let ordering = elements[a].compare(&elements[b]);
+                if descending { ordering.reverse() } else { ordering }
+            });
+            let moved = order.iter().enumerate().filter(|&(slot, &i)| slot != i).count();
+            let mixed = elements.iter().any(|e| e.rank() != elements[0].rank());
+            match (moved, mixed) {
+                (0, _) => "Already sorted".to_string(),
+                (_, false) => format!("Sorted {} elements ({} moved)", elements.len(), moved),
+                (_, true) => format!("Sorted {} elements by type, then value ({} moved; mixed types)", elements.len(), moved),
+            }
+        }
+        _ => {
+            let mut kept: Vec<usize> = Vec::new();
+            for i in order {
+                if !kept.iter().any(|&k| elements[k].compare(&elements[i]) == Ordering::Equal) {
+                    kept.push(i);
+                }
+            }
+            order = kept;
+            match elements.len() - order.len() {
+                0 => "No duplicates".to_string(),
+                removed => format!("Removed {} duplicates, {} left", removed, order.len()),
+            }
+        }
+    };
+    if order.iter().copied().eq(0..elements.len()) {
+        return Ok((Vec::new(), message));
+    }
+
+    // Element texts go into the slots in their new order, each followed by
+    // the separator that came after that slot
+    let mut text = String::new();
+    for (slot, &i) in order.iter().enumerate() {
+        if slot > 0 {
+            text.push_str(&buffer.slice(spans[slot - 1].end..spans[slot].start));
+        }
+        text.push_str(&elements[i].text);
+    }
+    let start = index.get(array).map_or(first.start, |node| node.start);
+    let edits = replace(buffer, cursor, first.start..last.end, &text, start)?;
+    Ok((edits, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    /// Apply `op` with the cursor on the first `at` in `text`
+    fn array_at_text(text: &str, at: &str, op: ArrayOp) -> (String, String) {
+        let mut buffer = Buffer::from_text(text);
+        let mut cursor = Cursor::new();
+        cursor.move_to_offset(&buffer, text.find(at).unwrap());
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let (_, message) = array(&mut buffer, &mut cursor, &index, op).unwrap();
+        (buffer.slice(0..buffer.len_bytes()), message)
+    }
+
+    #[test]
+    fn test_sort() {
+        let sort = ArrayOp::Sort { descending: false };
+        let (text, message) = array_at_text("[10, 9, 1e0, 2.5]", "9", sort);
+        assert_eq!((text.as_str(), message.as_str()), ("[1e0, 2.5, 9, 10]", "Sorted 4 elements (4 moved)"));
+        let (text, _) = array_at_text(r#"{"a": ["b", "\u00e9", "B", "a"]}"#, "\"a\"", ArrayOp::Sort { descending: true });
+        assert_eq!(text, r#"{"a": ["\u00e9", "b", "a", "B"]}"#);
+        assert_eq!(array_at_text("[1, 2]", "1", sort).1, "Already sorted");
+        // Long ids an f64 can't tell apart still sort exactly
+        let (text, _) = array_at_text("[9007199254740993, 9007199254740992, 100000000000000000001, 100000000000000000000]", "9", sort);
+        assert_eq!(text, "[9007199254740992, 9007199254740993, 100000000000000000000, 100000000000000000001]");
+
+        // Separators and indentation stay put; elements keep their layout
+        let text = "[\n  {\"b\": 1},\n  3,\n  \"x\",\n  null\n]";
+        let (sorted, message) = array_at_text(text, "3", sort);
+        assert_eq!(sorted, "[\n  null,\n  3,\n  \"x\",\n  {\"b\": 1}\n]");
+        assert!(message.contains("mixed types"), "{}", message);
+    }
+
+    #[test]
+    fn test_unique_and_count() {
+        let (text, message) = array_at_text("[1, \"a\", 1.0, \"\\u0061\", 2]", "2", ArrayOp::Unique);
+        assert_eq!((text.as_str(), message.as_str()), ("[1, \"a\", 2]", "Removed 2 duplicates, 3 left"));
+        assert_eq!(array_at_text("[1, 2]", "1", ArrayOp::Unique).1, "No duplicates");
+        assert_eq!(array_at_text("[[1], 2, 3]", "1", ArrayOp::Count).1, "1 element");
+        assert_eq!(array_at_text("[[1], 2, 3]", "2", ArrayOp::Count).1, "3 elements");
+    }
+}
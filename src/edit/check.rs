@@ -0,0 +1,79 @@
+//! Checking that a structural edit kept the document valid, for :set
+//! checkedits and strictedits. Only the innermost container around the
+//! edit is tokenized and indexed again, on its own, so a check costs about
+//! as much as that container is long, however big the file.
+
+use std::ops::Range;
+
+use crate::buffer::Buffer;
+use crate::parser::{OpenContainers, StructuralError, StructuralIndex, Tokenizer};
+
+/// What to check after edits that changed `changed`: the innermost closed
+/// container of `index`, already shifted past the edits, strictly around
+/// all of it. None when there's no such container, as for an edit to a
+/// top-level value or inside a container not indexed to its end.
+pub fn check_span(index: &StructuralIndex, changed: Range<usize>) -> Option<Range<usize>> {
+    let mut current = index.node_id_at(changed.start);
+    while let Some(id) = current {
+        let node = index.get(id)?;
+        if node.is_container() && index.is_closed(id) && node.start < changed.start && node.end > changed.end {
+            return Some(node.start..node.end);
+        }
+        current = index.parent(id);
+    }
+    None
+}
+
+/// The first structural error in `span` of `buffer`, tokenized and
+/// indexed as a document of its own
+pub fn first_problem(buffer: &Buffer, span: Range<usize>, relaxed: bool) -> Option<StructuralError> {
+    let text = buffer.slice(span.clone());
+    let tokens = Tokenizer::resume(text.as_bytes(), span.start, 0).with_comments(relaxed).tokenize_all();
+    let mut open = if relaxed { OpenContainers::relaxed() } else { OpenContainers::default() };
+    let mut index = StructuralIndex::new();
+    index.apply_chunk(StructuralIndex::build_chunk(&tokens, &mut open, 0));
+    index.finish(&open);
+    index.first_error().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::TextChange;
+    use crate::parser::StructuralErrorKind;
+
+    /// Index `text`, replace `range` of it with `with`, and check the edit
+    fn check(text: &str, range: Range<usize>, with: &str, relaxed: bool) -> Option<(Range<usize>, Option<StructuralError>)> {
+        let tokens = Tokenizer::new(text.to_string()).with_comments(relaxed).tokenize_all();
+        let mut index = if relaxed { StructuralIndex::from_tokens_relaxed(&tokens) } else { StructuralIndex::from_tokens(&tokens) };
+        let mut buffer = Buffer::from_text(text);
+        buffer.replace(range.start, range.end, with).unwrap();
+        index.apply_change(&TextChange { offset: range.start, removed: range.len(), inserted: with.len() });
+        let span = check_span(&index, range.start..range.start + with.len())?;
+        Some((span.clone(), first_problem(&buffer, span, relaxed)))
+    }
+
+    #[test]
+    fn test_check_edit() {
+        let text = r#"{"a": [1, 2, 3], "b": {"c": true}}"#;
+        // Deleting an element with its comma is fine; without, it isn't.
+        // Only the array around it is looked at.
+        assert_eq!(check(text, 7..10, "", false), Some((6..12, None)));
+        let (span, problem) = check(text, 7..8, "", false).unwrap();
+        assert_eq!(span, 6..14);
+        assert_eq!(problem.map(|e| (e.offset, e.kind)), Some((7, StructuralErrorKind::UnexpectedComma)));
+
+        // A value pasted in without a comma before it
+        let (span, problem) = check(text, 32..32, r#" "d": 1"#, false).unwrap();
+        assert_eq!(span, 22..40);
+        assert_eq!(problem.map(|e| e.kind), Some(StructuralErrorKind::MissingComma));
+
+        // A trailing comma passes in JSONC
+        let text = "{\"a\": [1, 2] // two\n}";
+        assert_eq!(check(text, 11..11, ",", true), Some((6..13, None)));
+        assert!(check(text, 11..11, ",", false).unwrap().1.is_some());
+
+        // Replacing the whole document leaves nothing around it to check
+        assert_eq!(check("[1]", 0..3, "[1", false), None);
+    }
+}
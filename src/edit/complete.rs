@@ -0,0 +1,163 @@
+//! Completing a key name in insert mode from the objects beside the one
+//! being edited, for adding a field to one record of an array of them.
+//! The neighbours nearest the object are read, so a huge array costs no
+//! more than a small one.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::buffer::Buffer;
+use crate::parser::node::NodeId;
+use crate::parser::{NodeKind, StructuralIndex};
+
+/// Objects read on each side of the one being edited
+pub const SAMPLE: usize = 500;
+
+/// Most candidates offered
+pub const MAX_CANDIDATES: usize = 100;
+
+/// How far back the `{` or `,` before a key's opening quote is looked for
+const LOOKBACK: usize = 4096;
+
+/// A key being typed and what it could become
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyCompletion {
+    /// Where the key's text starts, just after its opening quote
+    pub start: usize,
+    /// What's typed of it, up to the cursor
+    pub prefix: String,
+    /// Keys of the objects beside this one that start with the prefix and
+    /// this one doesn't have yet, the most common first
+    pub candidates: Vec<String>,
+}
+
+/// The key names the key typed up to `offset` could be completed to
+pub fn complete_key(index: &StructuralIndex, buffer: &Buffer, offset: usize) -> Result<KeyCompletion> {
+    let line_start = buffer.line_to_byte_offset(buffer.byte_offset_to_line(offset));
+    let before = buffer.slice(line_start..offset);
+    let Some(quote) = before.rfind('"') else {
+        bail!("Not in a key");
+    };
+    let prefix = &before[quote + 1..];
+    let start = line_start + quote + 1;
+    let back = buffer.slice((start - 1).saturating_sub(LOOKBACK)..start - 1);
+    if prefix.contains('\\') || !matches!(back.trim_end().chars().last(), Some('{' | ',')) {
+        bail!("Not in a key");
+    }
+    let object = std::iter::successors(index.node_id_at(offset), |&id| index.parent(id))
+        .find(|&id| matches!(index.kind(id), Some(NodeKind::Object | NodeKind::Array)))
+        .filter(|&id| index.kind(id) == Some(NodeKind::Object));
+    let Some(object) = object else {
+        bail!("Not in a key");
+    };
+
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    for sibling in neighbours(index, object) {
+        let Some(node) = index.get(sibling) else {
+            continue;
+        };
+        if node.kind != NodeKind::Object || index.touches_dirty(node.start..node.end) {
+            continue;
+        }
+        for key in keys(index, buffer, sibling) {
+            let seen = counts.len();
+            counts.entry(key).or_insert((0, seen)).0 += 1;
+        }
+    }
+    for key in keys(index, buffer, object) {
+        counts.remove(&key);
+    }
+    let mut candidates: Vec<(String, (usize, usize))> = counts.into_iter()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .collect();
+    candidates.sort_by_key(|&(_, (count, seen))| (std::cmp::Reverse(count), seen));
+    candidates.truncate(MAX_CANDIDATES);
+    if candidates.is_empty() {
+        bail!("No keys to complete");
+    }
+    Ok(KeyCompletion {
+        start,
+        prefix: prefix.to_string(),
+        candidates: candidates.into_iter().map(|(key, _)| key).collect(),
+    })
+}
+
+/// The elements of the array around `object`, or the records of a JSON
+/// Lines document around it, nearest first and up to `SAMPLE` on each side
+fn neighbours(index: &StructuralIndex, object: NodeId) -> Vec<NodeId> {
+    let (before, after): (Vec<NodeId>, Vec<NodeId>) = match index.parent(object) {
+        Some(parent) if index.kind(parent) == Some(NodeKind::Array) => (
+            std::iter::successors(index.prev_sibling(object), |&id| index.prev_sibling(id)).take(SAMPLE).collect(),
+            std::iter::successors(index.next_sibling(object), |&id| index.next_sibling(id)).take(SAMPLE).collect(),
+        ),
+        Some(_) => return Vec::new(),
+        None => match index.root_of(object) {
+            Some((n, _)) => (
+                (n.saturating_sub(SAMPLE)..n).rev().filter_map(|n| index.nth_root(n)).collect(),
+                (n + 1..n + 1 + SAMPLE).map_while(|n| index.nth_root(n)).collect(),
+            ),
+            None => return Vec::new(),
+        },
+    };
+    let mut nearest = Vec::with_capacity(before.len() + after.len());
+    for i in 0..before.len().max(after.len()) {
+        nearest.extend(before.get(i));
+        nearest.extend(after.get(i));
+    }
+    nearest
+}
+
+/// The keys of `object` as written, without their quotes, leaving out any
+/// that were edited since indexing
+fn keys<'a>(index: &'a StructuralIndex, buffer: &'a Buffer, object: NodeId) -> impl Iterator<Item = String> + 'a {
+    index.member_iter(object).map(|(key, _)| key).filter_map(|key| {
+        let key = index.get(key).filter(|key| key.len() >= 2 && !index.touches_dirty(key.start..key.end))?;
+        Some(buffer.slice(key.start + 1..key.end - 1))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::TextChange;
+    use crate::parser::Tokenizer;
+
+    /// Complete after typing `typed` where `after` ends in `text`, with
+    /// the index made before the typing, as it is in the editor
+    fn complete(text: &str, after: &str, typed: &str) -> Result<KeyCompletion> {
+        let mut index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let mut buffer = Buffer::from_text(text);
+        let offset = text.find(after).unwrap() + after.len();
+        buffer.insert(offset, typed).unwrap();
+        index.apply_change(&TextChange { offset, removed: 0, inserted: typed.len() });
+        complete_key(&index, &buffer, offset + typed.len())
+    }
+
+    #[test]
+    fn test_complete_key() {
+        let records = r#"[{"id": 1, "name": "a", "note": "x"}, {"id": 2, "name": "b"}, {"id": 3}]"#;
+        // The most common first; the object's own keys aren't offered
+        let completion = complete(records, r#"{"id": 3"#, r#", "n"#).unwrap();
+        assert_eq!(completion.candidates, ["name", "note"]);
+        assert_eq!(completion.prefix, "n");
+        assert_eq!(completion.start, records.find(r#"{"id": 3"#).unwrap() + r#"{"id": 3, ""#.len());
+        assert_eq!(complete(records, r#"{"id": 2, "name": "b""#, r#", ""#).unwrap().candidates, ["note"]);
+        assert_eq!(complete(records, r#"{"id": 3"#, ",\n  \"no").unwrap().candidates, ["note"]);
+        assert_eq!(complete(records, r#"{"id": 3"#, r#", "x"#).unwrap_err().to_string(), "No keys to complete");
+
+        // Only in key position, in an object among others
+        assert_eq!(complete(records, r#"{"id": 3"#, r#", "a": "n"#).unwrap_err().to_string(), "Not in a key");
+        assert!(complete(r#"[1, 2]"#, "1", r#", "n"#).is_err());
+        assert!(complete(r#"{"a": {"id": 1}, "b": {}}"#, r#""b": {"#, r#"""#).is_err());
+
+        // JSON Lines records complete from the records around them
+        let lines = "{\"id\": 1, \"level\": \"info\"}\n{\"id\": 2}\n";
+        let mut index = StructuralIndex::from_records(lines);
+        let mut buffer = Buffer::from_text(lines);
+        let offset = lines.rfind('}').unwrap();
+        buffer.insert(offset, ", \"l").unwrap();
+        index.apply_change(&TextChange { offset, removed: 0, inserted: 4 });
+        assert_eq!(complete_key(&index, &buffer, offset + 4).unwrap().candidates, ["level"]);
+    }
+}
@@ -0,0 +1,271 @@
+//! :diffsaved: what's changed since the last save, line by line, as a check
+//! before :w on a file that matters. A file held whole is compared with a
+//! fresh read of it from disk; a large one read lazily keeps the saved text
+//! in its mmap, so only the runs of lines it has edited are compared. Lines
+//! the two sides share at either end are set aside first, and the rest goes
+//! through Myers' algorithm, which gives up past `MAX_EDITS`.
+
+use std::ops::Range;
+
+use anyhow::{bail, Context, Result};
+
+use crate::buffer::Buffer;
+use crate::parser::diff::{Change, ChangeKind};
+
+/// Lines added and removed past which a diff gives up
+pub const MAX_EDITS: usize = 1000;
+
+/// Lines, both sides together, between the first change and the last past
+/// which a diff gives up: it takes time in proportion to them and the edits
+pub const MAX_LINES: usize = 200_000;
+
+/// Largest saved file read back to compare with
+pub const MAX_BYTES: u64 = 64 << 20;
+
+/// One run of changed lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// Lines of the text as it is now
+    pub ours: Range<usize>,
+    /// Lines of the saved text they replace
+    pub saved: Range<usize>,
+}
+
+/// The lines of `text`, each with its newline
+pub fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// The hunks that turn `saved` into `ours`, in order. None when there are
+/// more than `MAX_EDITS` lines to add and remove.
+pub fn diff_lines(ours: &[&str], saved: &[&str]) -> Option<Vec<Hunk>> {
+    let prefix = ours.iter().zip(saved).take_while(|(a, b)| a == b).count();
+    let suffix = ours[prefix..].iter().rev().zip(saved[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&saved[prefix..saved.len() - suffix], &ours[prefix..ours.len() - suffix]);
+    if a.len() + b.len() > MAX_LINES || a.len().abs_diff(b.len()) > MAX_EDITS {
+        return None;
+    }
+    let edits = myers(a, b)?;
+
+    // Runs of edits that follow on from each other make one hunk
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for (x, y, removed) in edits {
+        let (x, y) = (x + prefix, y + prefix);
+        match hunks.last_mut() {
+            Some(hunk) if hunk.saved.end == x && hunk.ours.end == y => {}
+            _ => hunks.push(Hunk { ours: y..y, saved: x..x }),
+        }
+        let Some(hunk) = hunks.last_mut() else { continue };
+        if removed {
+            hunk.saved.end += 1;
+        } else {
+            hunk.ours.end += 1;
+        }
+    }
+    Some(hunks)
+}
+
+/// The shortest edit script from `a` to `b`: where each line is removed
+/// from `a` or added from `b`, as (line of `a`, line of `b`, removed), in
+/// order
+fn myers(a: &[&str], b: &[&str]) -> Option<Vec<(usize, usize, bool)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let limit = (a.len() + b.len()).min(MAX_EDITS) as isize;
+    // Diagonal k is at v[k + offset]; each step's furthest reaches are
+    // kept, around the diagonals it could get to, to trace the way back
+    let offset = limit + 1;
+    let mut v = vec![0isize; 2 * limit as usize + 3];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    for d in 0..=limit {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize];
+            let mut x = if down { v[(offset + k + 1) as usize] } else { v[(offset + k - 1) as usize] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+    None
+}
+
+/// Follow the furthest reaches in `trace` back from the end
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<(usize, usize, bool)> {
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev().take_while(|&(d, _)| d > 0) {
+        let d = d as isize;
+        // v holds diagonals -d - 1 ..= d + 1
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let down = k == -d || k != d && at(k - 1) < at(k + 1);
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        // The lines in common after the edit needn't be walked back over
+        edits.push((prev_x as usize, prev_y as usize, !down));
+        (x, y) = (prev_x, prev_y);
+    }
+    edits.reverse();
+    edits
+}
+
+/// The changes made since the buffer's file was last saved, as :diff
+/// reports them, spanning the lines they're on now. Lines only removed are
+/// placed at the start of the line after them.
+pub fn against_saved(buffer: &Buffer) -> Result<Vec<Change>> {
+    let Some(path) = buffer.path() else {
+        bail!("No file name");
+    };
+    let mut changes = Vec::new();
+    if buffer.is_lazy() {
+        // The mmap still holds the file as saved; lines not edited since
+        // are the same on both sides
+        let edited = buffer.edited_lines();
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for line in edited {
+            match runs.last_mut() {
+                Some(run) if run.end == line => run.end += 1,
+                _ => runs.push(line..line + 1),
+            }
+        }
+        for run in runs {
+            let saved = buffer.slice(buffer.line_to_byte_offset(run.start)..buffer.line_to_byte_offset(run.end));
+            let now: Vec<String> = run.clone().map(|line| buffer.get_line(line)).collect();
+            let (mut ours, mut line_of) = (Vec::new(), Vec::new());
+            for (line, text) in run.clone().zip(&now) {
+                for piece in lines(text) {
+                    ours.push(piece);
+                    line_of.push(line);
+                }
+            }
+            let Some(hunks) = diff_lines(&ours, &lines(&saved)) else {
+                bail!("Diff too large around line {}", run.start + 1);
+            };
+            changes.extend(hunks.iter().map(|hunk| place(buffer, hunk, &line_of, run.end)));
+        }
+    } else {
+        let size = std::fs::metadata(path).with_context(|| format!("Can't read {}", path.display()))?.len();
+        if size > MAX_BYTES {
+            bail!("Diff too large: {} is over {} MB", path.display(), MAX_BYTES >> 20);
+        }
+        let saved = std::fs::read(path).with_context(|| format!("Can't read {}", path.display()))?;
+        let saved = String::from_utf8_lossy(&saved);
+        let now = buffer.slice(0..buffer.len_bytes());
+        let ours = lines(&now);
+        let line_of: Vec<usize> = (0..ours.len()).collect();
+        let Some(hunks) = diff_lines(&ours, &lines(&saved)) else {
+            bail!("Diff too large: over {} lines changed", MAX_EDITS);
+        };
+        changes.extend(hunks.iter().map(|hunk| place(buffer, hunk, &line_of, ours.len())));
+    }
+    Ok(changes)
+}
+
+/// `hunk` as a change in the buffer, its lines of ours being on the buffer
+/// lines in `line_of`, and `end` the buffer line after the last of them
+fn place(buffer: &Buffer, hunk: &Hunk, line_of: &[usize], end: usize) -> Change {
+    let first = line_of.get(hunk.ours.start).copied().unwrap_or(end);
+    let last = if hunk.ours.is_empty() { first } else { line_of.get(hunk.ours.end - 1).copied().unwrap_or(first) };
+    let kind = match (hunk.ours.is_empty(), hunk.saved.is_empty()) {
+        (false, true) => ChangeKind::Added,
+        (true, false) => ChangeKind::Removed,
+        _ => ChangeKind::Changed,
+    };
+    let start = buffer.line_to_byte_offset(first);
+    let span = match kind {
+        ChangeKind::Removed => start..start,
+        _ => start..buffer.line_to_byte_offset(last + 1).max(start),
+    };
+    let lines = if last > first { format!("lines {}-{}", first + 1, last + 1) } else { format!("line {}", first + 1) };
+    let path = format!("{} (+{} -{})", lines, hunk.ours.len(), hunk.saved.len());
+    Change { kind, span, path, other: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunks(ours: &str, saved: &str) -> Option<Vec<(Range<usize>, Range<usize>)>> {
+        let hunks = diff_lines(&lines(ours), &lines(saved))?;
+        Some(hunks.into_iter().map(|hunk| (hunk.ours, hunk.saved)).collect())
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(hunks("a\nb\nc\n", "a\nb\nc\n"), Some(vec![]));
+        assert_eq!(hunks("", ""), Some(vec![]));
+        // Added, removed, changed
+        assert_eq!(hunks("a\nx\nb\n", "a\nb\n"), Some(vec![(1..2, 1..1)]));
+        assert_eq!(hunks("a\nc\n", "a\nb\nc\n"), Some(vec![(1..1, 1..2)]));
+        assert_eq!(hunks("a\nB\nc\n", "a\nb\nc\n"), Some(vec![(1..2, 1..2)]));
+        assert_eq!(hunks("x\n", ""), Some(vec![(0..1, 0..0)]));
+        assert_eq!(hunks("", "x\ny\n"), Some(vec![(0..0, 0..2)]));
+        // Several hunks, with lines in common between them
+        assert_eq!(
+            hunks("1\n2\nthree\n4\n5\n6\n7\nnew\n8\n", "1\n2\n3\n4\n5\n6\n7\n8\n9\n"),
+            Some(vec![(2..3, 2..3), (7..8, 7..7), (9..9, 8..9)])
+        );
+        // A last line without its newline differs from one with it
+        assert_eq!(hunks("a\nb", "a\nb\n"), Some(vec![(1..2, 1..2)]));
+    }
+
+    #[test]
+    fn test_too_large() {
+        let ours: String = (0..MAX_EDITS).map(|i| format!("a{}\n", i)).collect();
+        let saved: String = (0..MAX_EDITS).map(|i| format!("b{}\n", i)).collect();
+        assert_eq!(hunks(&ours, &saved), None);
+        // The same size change in one place is cheap when the rest matches
+        let common = "x\n".repeat(100_000);
+        let ours = format!("{}{}{}", common, "new\n", common);
+        assert_eq!(hunks(&ours, &format!("{}{}", common, common)), Some(vec![(100_000..100_001, 100_000..100_000)]));
+    }
+
+    #[test]
+    fn test_against_saved() {
+        let path = std::env::temp_dir().join(format!("jim_test_diffsaved_{}.json", std::process::id()));
+        std::fs::write(&path, "{\n  \"a\": 1,\n  \"b\": 2,\n  \"c\": 3\n}\n").unwrap();
+        let mut buffer = Buffer::new();
+        buffer.load_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(against_saved(&buffer).unwrap(), vec![]);
+        let c = buffer.line_to_byte_offset(3);
+        buffer.delete(c, buffer.line_to_byte_offset(4)).unwrap();
+        buffer.insert(buffer.line_to_byte_offset(1) + 7, "0").unwrap();
+        let changes = against_saved(&buffer).unwrap();
+        let described: Vec<String> = changes.iter().map(Change::describe).collect();
+        assert_eq!(described, ["~ line 2 (+1 -1)", "- line 4 (+0 -1)"]);
+        // The removed line is marked where it was, on the closing brace,
+        // which the 0 put a byte further on
+        assert_eq!(changes[1].span, c + 1..c + 1);
+        buffer.insert(c + 1, "  \"d\": 4\n").unwrap();
+        let described: Vec<String> = against_saved(&buffer).unwrap().iter().map(Change::describe).collect();
+        assert_eq!(described, ["~ line 2 (+1 -1)", "~ line 4 (+1 -1)"]);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_lazy() {
+        let path = std::env::temp_dir().join(format!("jim_test_diffsaved_lazy_{}.json", std::process::id()));
+        let record = "{\"id\": 1, \"name\": \"item\"}\n";
+        std::fs::write(&path, record.repeat(11 * 1024 * 1024 / record.len())).unwrap();
+        let mut buffer = Buffer::new();
+        buffer.load_file(path.to_str().unwrap()).unwrap();
+        assert!(buffer.is_lazy());
+        // An edited line, and a line added inside another
+        buffer.insert(buffer.line_to_byte_offset(1000) + 1, "\"new\": 0, ").unwrap();
+        let at = buffer.line_to_byte_offset(5000);
+        buffer.insert(at, "{}\n").unwrap();
+        assert!(buffer.is_lazy());
+        let changes = against_saved(&buffer).unwrap();
+        let described: Vec<String> = changes.iter().map(Change::describe).collect();
+        assert_eq!(described, ["~ line 1001 (+1 -1)", "+ line 5001 (+1 -0)"]);
+        assert_eq!(changes[1].span.start, at);
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,181 @@
+//! Writing one node of the document to a file of its own for :wnode. The
+//! bytes stream from the buffer, so a node of several gigabytes is never
+//! held in memory whole.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::buffer::Buffer;
+
+const INDENT: &[u8] = b"  ";
+
+/// Write `span` of `buffer` to `path`, reformatted with two-space
+/// indentation when `pretty`. Refuses to replace an existing file unless
+/// `force`d. Returns the bytes written.
+pub fn export(buffer: &Buffer, span: Range<usize>, path: &Path, pretty: bool, force: bool) -> Result<usize> {
+    if !force && path.exists() {
+        bail!("{} exists (add ! to overwrite)", path.display());
+    }
+    let file = File::create(path).with_context(|| format!("Can't create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    let written = if pretty {
+        let mut pretty = PrettyWriter::new(&mut out);
+        buffer.write_range(span, &mut pretty)?;
+        pretty.finish()?
+    } else {
+        buffer.write_range(span, &mut out)?
+    };
+    out.flush()?;
+    Ok(written)
+}
+
+/// Reformats JSON as it's written through it, a byte at a time, so it
+/// needs no more than the nesting depth in memory. Whitespace outside
+/// strings is replaced; everything else passes through, so malformed input
+/// comes out reindented but otherwise as it was.
+pub struct PrettyWriter<W: Write> {
+    out: W,
+    /// Written once per level of nesting
+    indent: Vec<u8>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    /// An opening bracket was just written; it may be closed right away
+    open: Option<u8>,
+    written: usize,
+}
+
+impl<W: Write> PrettyWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self::with_indent(out, INDENT.len())
+    }
+
+    /// Indenting by `width` spaces a level, as :set shiftwidth asks
+    pub fn with_indent(out: W, width: usize) -> Self {
+        Self { out, indent: vec![b' '; width], depth: 0, in_string: false, escaped: false, open: None, written: 0 }
+    }
+
+    /// End the text with a newline; the total bytes written
+    pub fn finish(mut self) -> io::Result<usize> {
+        self.emit(b"\n")?;
+        Ok(self.written)
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.written += bytes.len();
+        self.out.write_all(bytes)
+    }
+
+    fn newline(&mut self) -> io::Result<()> {
+        self.emit(b"\n")?;
+        for _ in 0..self.depth {
+            self.written += self.indent.len();
+            self.out.write_all(&self.indent)?;
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, b: u8) -> io::Result<()> {
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if b == b'\\' {
+                self.escaped = true;
+            } else if b == b'"' {
+                self.in_string = false;
+            }
+            return self.emit(&[b]);
+        }
+        if b.is_ascii_whitespace() {
+            return Ok(());
+        }
+        if let Some(open) = self.open.take() {
+            if (open, b) == (b'{', b'}') || (open, b) == (b'[', b']') {
+                self.depth -= 1;
+                return self.emit(&[b]);
+            }
+            self.newline()?;
+        }
+        match b {
+            b'{' | b'[' => {
+                self.depth += 1;
+                self.open = Some(b);
+                self.emit(&[b])
+            }
+            b'}' | b']' => {
+                self.depth = self.depth.saturating_sub(1);
+                self.newline()?;
+                self.emit(&[b])
+            }
+            b',' => {
+                self.emit(b",")?;
+                self.newline()
+            }
+            b':' => self.emit(b": "),
+            b'"' => {
+                self.in_string = true;
+                self.emit(&[b])
+            }
+            _ => self.emit(&[b]),
+        }
+    }
+}
+
+impl<W: Write> Write for PrettyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            self.byte(b)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::preview::pretty_print;
+
+    fn pretty(text: &str) -> String {
+        let mut out = Vec::new();
+        let mut writer = PrettyWriter::new(&mut out);
+        // In pieces, as the buffer hands them over
+        for piece in text.as_bytes().chunks(3) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_pretty_writer() {
+        let json = r#"{"a": [1, 2], "b": {}, "c": {"d": null, "e": "x, {y}: \"é\""}, "f": [ ]}"#;
+        assert_eq!(pretty(json), pretty_print(json, 100).join("\n") + "\n");
+        assert_eq!(pretty("\"s\""), "\"s\"\n");
+    }
+
+    #[test]
+    fn test_export() {
+        let text = "[{\"id\": 1}, {\"id\": 2, \"tags\": [\"a\"]}]";
+        let buffer = Buffer::from_text(text);
+        let span = text.find("{\"id\": 2").unwrap()..text.len() - 1;
+        let path = std::env::temp_dir().join(format!("jim_test_export_{}.json", std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(export(&buffer, span.clone(), &path, false, false).unwrap(), span.len());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), &text[span.clone()]);
+        assert!(export(&buffer, span.clone(), &path, true, false).unwrap_err().to_string().contains("exists"));
+        let written = export(&buffer, span, &path, true, true).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(saved, "{\n  \"id\": 2,\n  \"tags\": [\n    \"a\"\n  ]\n}\n");
+        assert_eq!(written, saved.len());
+    }
+}
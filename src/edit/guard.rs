@@ -0,0 +1,165 @@
+//! Noticing edits that may have broken the document as a whole: its first
+//! or last token changed, as after a `9dd` that took the closing brace with
+//! it, or a large share of it deleted in one go. Only the ends of the text
+//! and the sizes of the edits are looked at, so keeping up costs next to
+//! nothing, and the edit itself is never held back.
+
+use crate::buffer::{Buffer, TextChange};
+
+/// The share of the document, in percent, one edit may delete before
+/// it's warned about
+pub const DEFAULT_DELETE_PERCENT: usize = 50;
+
+#[derive(Debug, Clone, Default)]
+pub struct Guard {
+    /// The document's opening and closing brackets when it was loaded or
+    /// last passed :validate; None when it wasn't one object or array
+    brackets: Option<(u8, u8)>,
+    /// Bytes in the buffer, kept up from the edits
+    len: usize,
+    /// The length before a deletion that took too much, and the percentage
+    /// it took, until undo brings the length back
+    deletion: Option<(usize, usize)>,
+    /// What's wrong now, for the status bar
+    problem: Option<String>,
+}
+
+impl Guard {
+    /// Watch the document `buffer` has just loaded
+    pub fn new(buffer: &Buffer) -> Self {
+        let mut guard = Self { len: buffer.len_bytes(), ..Self::default() };
+        guard.accept(buffer);
+        guard
+    }
+
+    /// Take the document as it is now to be whole, as when it passes
+    /// :validate
+    pub fn accept(&mut self, buffer: &Buffer) {
+        self.brackets = buffer.outer_bytes().filter(|ends| matches!(ends, (b'{', b'}') | (b'[', b']')));
+        self.deletion = None;
+        self.problem = None;
+    }
+
+    /// What's wrong with the document now, if anything
+    pub fn problem(&self) -> Option<&str> {
+        self.problem.as_deref()
+    }
+
+    /// Note `changes`, one edit's worth just made to `buffer`. Returns a
+    /// warning when they brought a problem that wasn't there before; one
+    /// that goes away, as when the edit is undone, clears quietly. What the
+    /// edit deleted is what the document lost over all of them, so a
+    /// replace, recorded as a deletion and then an insertion, counts only
+    /// what it didn't put back. `percent` 0 allows any deletion.
+    pub fn apply(&mut self, changes: &[TextChange], buffer: &Buffer, percent: usize) -> Option<String> {
+        let before = self.len;
+        self.len = changes.iter().fold(before, |len, change| (len + change.inserted).saturating_sub(change.removed));
+        let deleted = before.saturating_sub(self.len);
+        if percent > 0 && before > 0 && deleted * 100 > before * percent && self.deletion.is_none() {
+            self.deletion = Some((before, deleted * 100 / before));
+        }
+        if self.deletion.is_some_and(|(before, _)| self.len >= before) {
+            self.deletion = None;
+        }
+        let problem = self.check(buffer);
+        let warning = problem.clone().filter(|_| problem != self.problem);
+        self.problem = problem;
+        warning
+    }
+
+    fn check(&self, buffer: &Buffer) -> Option<String> {
+        if let Some((open, close)) = self.brackets {
+            match buffer.outer_bytes() {
+                None => return Some("Document is empty".to_string()),
+                Some((first, _)) if first != open => {
+                    return Some(format!("Document now starts with '{}', not '{}'", first as char, open as char));
+                }
+                Some((_, last)) if last != close => {
+                    return Some(format!("Document no longer ends with '{}'", close as char));
+                }
+                Some(_) => {}
+            }
+        }
+        self.deletion.map(|(_, percent)| format!("{}% of the document deleted in one edit", percent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Cursor;
+    use crate::edit::array::{self, ArrayOp};
+    use crate::parser::{StructuralIndex, Tokenizer};
+
+    /// Replace `range` of the buffer with `with`, as the guard sees it
+    fn edit(guard: &mut Guard, buffer: &mut Buffer, range: std::ops::Range<usize>, with: &str) -> Option<String> {
+        buffer.replace(range.start, range.end, with).unwrap();
+        let changes = [TextChange { offset: range.start, removed: range.len(), inserted: with.len() }];
+        guard.apply(&changes, buffer, DEFAULT_DELETE_PERCENT)
+    }
+
+    #[test]
+    fn test_ends() {
+        let text = "{\n  \"a\": [1, 2],\n  \"b\": true\n}\n";
+        let mut buffer = Buffer::from_text(text);
+        let mut guard = Guard::new(&buffer);
+        // Inside the document nothing is noticed
+        assert_eq!(edit(&mut guard, &mut buffer, 10..11, "3"), None);
+        assert_eq!(guard.problem(), None);
+
+        // The closing brace deleted, then put back
+        let end = text.len() - 2;
+        assert_eq!(edit(&mut guard, &mut buffer, end..end + 1, "").as_deref(), Some("Document no longer ends with '}'"));
+        assert_eq!(edit(&mut guard, &mut buffer, 0..0, " ").as_deref(), None);
+        assert_eq!(guard.problem(), Some("Document no longer ends with '}'"));
+        assert_eq!(edit(&mut guard, &mut buffer, end + 1..end + 1, "}"), None);
+        assert_eq!(guard.problem(), None);
+
+        // The top-level type changed
+        assert_eq!(edit(&mut guard, &mut buffer, 1..2, "[").as_deref(), Some("Document now starts with '[', not '{'"));
+        assert_eq!(edit(&mut guard, &mut buffer, end + 1..end + 2, "]"), None);
+        guard.accept(&buffer);
+        assert_eq!(guard.problem(), None);
+        assert_eq!(edit(&mut guard, &mut buffer, 1..2, "{").as_deref(), Some("Document now starts with '{', not '['"));
+
+        // A document that was never whole isn't watched for its ends
+        let mut buffer = Buffer::from_text("[1, 2");
+        let mut guard = Guard::new(&buffer);
+        assert_eq!(edit(&mut guard, &mut buffer, 4..5, "3]"), None);
+    }
+
+    #[test]
+    fn test_deletion() {
+        let text = format!("[{}0]\n", "1, ".repeat(100));
+        let mut buffer = Buffer::from_text(&text);
+        let mut guard = Guard::new(&buffer);
+        assert_eq!(edit(&mut guard, &mut buffer, 1..100, ""), None);
+        let warning = edit(&mut guard, &mut buffer, 1..150, "");
+        assert_eq!(warning.as_deref(), Some("72% of the document deleted in one edit"));
+        // Undone, it's forgotten
+        assert_eq!(edit(&mut guard, &mut buffer, 1..1, &"1, ".repeat(50)), None);
+        assert_eq!(guard.problem(), None);
+
+        // Off
+        let mut buffer = Buffer::from_text(&text);
+        let mut guard = Guard::new(&buffer);
+        buffer.delete(1, 300).unwrap();
+        assert_eq!(guard.apply(&[TextChange { offset: 1, removed: 299, inserted: 0 }], &buffer, 0), None);
+    }
+
+    #[test]
+    fn test_replace() {
+        // A command that rewrites the text in place, recorded as deletions
+        // and insertions, takes out only what it doesn't put back
+        let text = "[3, 1, 2, 1]";
+        let mut buffer = Buffer::from_text(text);
+        let mut guard = Guard::new(&buffer);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let mut cursor = Cursor::new();
+        array::array(&mut buffer, &mut cursor, &index, ArrayOp::Unique).unwrap();
+        assert_eq!(buffer.slice(0..buffer.len_bytes()), "[3, 1, 2]");
+        let changes = buffer.drain_deltas();
+        assert!(changes.len() > 1);
+        assert_eq!(guard.apply(&changes, &buffer, DEFAULT_DELETE_PERCENT), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! J and gJ: lines joined onto the cursor's. gJ takes out the line breaks
+//! and nothing else. J takes the indentation too and puts back a space, as
+//! vim does, except where the structural index covers the lines: there it
+//! knows the text is JSON and puts back exactly what goes between the two
+//! tokens, nothing after an opening bracket or before a comma or colon and
+//! a space after one, so joining never leaves `"value" ,` behind.
+
+use anyhow::{bail, Result};
+
+use super::whitespace::Scan;
+use crate::buffer::{Buffer, Edit};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinStyle {
+    /// gJ: just the line breaks
+    Raw,
+    /// J over text that isn't indexed yet: one space for the indentation
+    Spaced,
+    /// J over indexed text, with a space inside brackets before a closing
+    /// one when `brace_spacing` (:set bracespacing)
+    Json { brace_spacing: bool },
+}
+
+/// The replacements joining `count` lines from `line` makes (two when
+/// `count` is less), and where the cursor goes after: where the last two
+/// lines met. `start` is how the first line begins: inside a string when
+/// one runs into it from above.
+pub fn edits(buffer: &Buffer, line: usize, count: usize, style: JoinStyle, start: Scan) -> Result<(Vec<Edit>, usize)> {
+    let last = line.saturating_add(count.max(2) - 1).min(buffer.line_count().saturating_sub(1));
+    if last <= line {
+        bail!("No line below to join");
+    }
+    let mut edits: Vec<Edit> = Vec::new();
+    let mut cursor = 0;
+    let mut state = start;
+    // The last character kept before the break being joined, across lines
+    // left blank
+    let mut prev: Option<char> = None;
+    for line_idx in line..last {
+        let text = buffer.get_line(line_idx);
+        let offset = buffer.line_to_byte_offset(line_idx);
+        let content = text.strip_suffix('\n').unwrap_or(&text);
+        let content = if content.len() < text.len() { content.strip_suffix('\r').unwrap_or(content) } else { content };
+        // Each line after the first was taken up to its first character by
+        // the edit before
+        let from = if line_idx == line { 0 } else { leading_space(content) };
+        let body = &content[from..];
+        let at_break = state.over(content.as_bytes());
+        state = state.over(text.as_bytes());
+        if content.len() == text.len() {
+            // A row of a long line split where the file has no break
+            prev = body.trim_end().chars().last().or(prev);
+            continue;
+        }
+        if style != JoinStyle::Raw && at_break == Scan::LineComment {
+            bail!("Can't join after a // comment on line {}", line_idx + 1);
+        }
+
+        let next_text = buffer.get_line(line_idx + 1);
+        let next = next_text.strip_suffix('\n').unwrap_or(&next_text);
+        let next = next.strip_suffix('\r').unwrap_or(next);
+        let indent = leading_space(next);
+        let first = next[indent..].chars().next();
+        let kept = match style {
+            // A string running over the break keeps all its whitespace
+            JoinStyle::Raw => content.len(),
+            _ if at_break.in_string() => content.len(),
+            JoinStyle::Spaced => content.len(),
+            JoinStyle::Json { .. } => from + body.trim_end_matches([' ', '\t']).len(),
+        };
+        prev = content[..kept].chars().last().filter(|_| kept > from || line_idx == line).or(prev);
+        let separator = match style {
+            JoinStyle::Raw => "",
+            _ if at_break.in_string() => "",
+            JoinStyle::Spaced => match (prev, first) {
+                (Some(c), Some(_)) if !c.is_whitespace() => " ",
+                _ => "",
+            },
+            JoinStyle::Json { brace_spacing } => json_separator(prev, first, brace_spacing),
+        };
+        let removed = match style {
+            JoinStyle::Raw => text.len() - content.len(),
+            _ if at_break.in_string() => text.len() - content.len(),
+            _ => text.len() - kept + indent,
+        };
+        let at = offset + kept;
+        let shift: isize = edits.iter().map(|edit| edit.new_text.len() as isize - edit.old_len as isize).sum();
+        cursor = (at as isize + shift) as usize;
+        edits.push(Edit { file_offset: at, old_len: removed, new_text: separator.to_string() });
+    }
+    Ok((edits, cursor))
+}
+
+/// Spaces and tabs at the start of `text`
+fn leading_space(text: &str) -> usize {
+    text.len() - text.trim_start_matches([' ', '\t']).len()
+}
+
+/// What JSON puts between a token ending in `prev` and one starting with
+/// `next` when they're on one line
+fn json_separator(prev: Option<char>, next: Option<char>, brace_spacing: bool) -> &'static str {
+    let padded = if brace_spacing { " " } else { "" };
+    match (prev, next) {
+        (None, _) | (_, None) => "",
+        (Some('{'), Some('}')) | (Some('['), Some(']')) => "",
+        (Some('{' | '['), _) | (_, Some('}' | ']')) => padded,
+        (_, Some(',' | ':')) => "",
+        _ => " ",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{StructuralIndex, Tokenizer};
+
+    fn join(text: &str, line: usize, count: usize, style: JoinStyle) -> Result<(String, usize)> {
+        let mut buffer = Buffer::from_text(text);
+        let (edits, cursor) = edits(&buffer, line, count, style, Scan::Code)?;
+        buffer.apply_edits(&edits).unwrap();
+        Ok((buffer.slice(0..buffer.len_bytes()), cursor))
+    }
+
+    fn json(text: &str) -> String {
+        let joined = join(text, 0, usize::MAX, JoinStyle::Json { brace_spacing: false }).unwrap().0;
+        // Still the same JSON
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(joined.clone()).with_comments(true).tokenize_all());
+        assert!(index.errors().is_empty(), "{} has errors", joined);
+        joined
+    }
+
+    #[test]
+    fn test_json_pairs() {
+        assert_eq!(json("{\n  \"a\": 1\n}"), "{\"a\": 1}");
+        assert_eq!(json("[\n]"), "[]");
+        assert_eq!(json("{\n\n}"), "{}");
+        assert_eq!(json("[1  \n  , 2\n  ,3]"), "[1, 2,3]");
+        assert_eq!(json("{\"a\"\n  :\n    1,\n  \"b\":\n  [\n    true,\n    null\n  ]\n}"), "{\"a\": 1, \"b\": [true, null]}");
+        assert_eq!(json("[\n  {\n    \"x\": \"y\"\n  },\n\n  {}\n]"), "[{\"x\": \"y\"}, {}]");
+        assert_eq!(json("[1,\n/* two */\n2]"), "[1, /* two */ 2]");
+    }
+
+    #[test]
+    fn test_brace_spacing() {
+        let style = JoinStyle::Json { brace_spacing: true };
+        assert_eq!(join("{\n  \"a\": [\n    1\n  ]\n}", 0, 5, style).unwrap().0, "{ \"a\": [ 1 ] }");
+        assert_eq!(join("[\n]", 0, 2, style).unwrap().0, "[]");
+    }
+
+    #[test]
+    fn test_join() {
+        let text = "{\n  \"a\": 1,\n  \"b\": 2\n}";
+        // Two lines by default, the cursor where they met
+        assert_eq!(join(text, 1, 0, JoinStyle::Json { brace_spacing: false }).unwrap(), ("{\n  \"a\": 1, \"b\": 2\n}".into(), 11));
+        assert_eq!(join(text, 1, 2, JoinStyle::Raw).unwrap().0, "{\n  \"a\": 1,  \"b\": 2\n}");
+        assert_eq!(join(text, 0, 3, JoinStyle::Spaced).unwrap().0, "{ \"a\": 1, \"b\": 2\n}");
+        assert!(join(text, 3, 2, JoinStyle::Raw).is_err());
+        // A string running over the break is left as it is, and a line
+        // comment would swallow what's joined to it
+        assert_eq!(join("[\"a \n  b\", 1]", 0, 2, JoinStyle::Json { brace_spacing: false }).unwrap().0, "[\"a   b\", 1]");
+        assert!(join("[1, // one\n2]", 0, 2, JoinStyle::Json { brace_spacing: false }).is_err());
+        assert_eq!(join("[1, // one\n2]", 0, 2, JoinStyle::Raw).unwrap().0, "[1, // one2]");
+    }
+}
@@ -1,6 +1,23 @@
 use anyhow::Result;
 
+pub mod array;
+pub mod check;
+pub mod complete;
+pub mod diff;
+pub mod export;
+pub mod guard;
+pub mod join;
+pub mod ndjson;
+pub mod paste;
+pub mod pipe;
+pub mod renameall;
+pub mod rewrap;
+pub mod script;
+pub mod smartcomma;
+pub mod structural;
 pub mod undo;
+pub mod undofile;
+pub mod whitespace;
 
 use crate::buffer::Buffer;
 use crate::buffer::cursor::Cursor;
@@ -18,6 +35,10 @@ pub struct Edit {
     pub cursor_before: CursorState,
     /// Cursor position after edit
     pub cursor_after: CursorState,
+    /// Line and byte column of `offset`, for edits made while the buffer
+    /// reads a large file lazily: there the byte offsets of lines after an
+    /// edited one go stale, so undo finds the edit by line instead
+    pub line_pos: Option<(usize, usize)>,
 }
 
 /// Snapshot of cursor state for undo/redo
@@ -53,6 +74,7 @@ impl Edit {
             new_text,
             cursor_before,
             cursor_after,
+            line_pos: None,
         }
     }
     
@@ -69,6 +91,7 @@ impl Edit {
             new_text: self.old_text.clone(),
             cursor_before: self.cursor_after,
             cursor_after: self.cursor_before,
+            line_pos: self.line_pos,
         }
     }
 }
@@ -85,27 +108,21 @@ impl EditOperations {
         text: &str,
     ) -> Result<Edit> {
         let cursor_before = CursorState::from(&*cursor);
+        let line_pos = buffer.line_position(offset);
         
         // Perform the insertion
         buffer.insert(offset, text)?;
         
         // Update cursor position - move cursor after inserted text
         cursor.byte_offset = offset + text.len();
-        cursor.line = buffer.byte_offset_to_line(cursor.byte_offset);
-        
-        // Calculate column position
-        let line_start = buffer.line_to_byte_offset(cursor.line);
-        cursor.col = cursor.byte_offset.saturating_sub(line_start);
+        (cursor.line, cursor.col) = buffer.offset_to_line_col(cursor.byte_offset);
         
         let cursor_after = CursorState::from(&*cursor);
         
-        Ok(Edit::new(
-            offset,
-            String::new(),
-            text.to_string(),
-            cursor_before,
-            cursor_after,
-        ))
+        Ok(Edit {
+            line_pos,
+            ..Edit::new(offset, String::new(), text.to_string(), cursor_before, cursor_after)
+        })
     }
     
     /// Delete text in the given range
@@ -119,27 +136,55 @@ impl EditOperations {
         
         // Get the text being deleted
         let deleted_text = buffer.slice(start..end);
+        let line_pos = buffer.line_position(start);
         
         // Perform the deletion
         buffer.delete(start, end)?;
         
         // Update cursor position - move to start of deleted region
         cursor.byte_offset = start;
-        cursor.line = buffer.byte_offset_to_line(cursor.byte_offset);
+        (cursor.line, cursor.col) = buffer.offset_to_line_col(cursor.byte_offset);
+        
+        let cursor_after = CursorState::from(&*cursor);
+        
+        Ok(Edit {
+            line_pos,
+            ..Edit::new(start, deleted_text, String::new(), cursor_before, cursor_after)
+        })
+    }
+    
+    /// Make `edits` as one (see Buffer::apply_edits), moving the cursor
+    /// along with the text around it. The undo edits come back to front,
+    /// the order the buffer made them in, for one group.
+    pub fn apply_edits(
+        buffer: &mut Buffer,
+        cursor: &mut Cursor,
+        edits: &[crate::buffer::Edit],
+    ) -> Result<Vec<Edit>> {
+        let cursor_before = CursorState::from(&*cursor);
+        let old: Vec<(String, Option<(usize, usize)>)> = edits
+            .iter()
+            .map(|edit| {
+                let start = edit.file_offset;
+                (buffer.slice(start..start + edit.old_len), buffer.line_position(start))
+            })
+            .collect();
         
-        // Calculate column position
-        let line_start = buffer.line_to_byte_offset(cursor.line);
-        cursor.col = cursor.byte_offset.saturating_sub(line_start);
+        let map = buffer.apply_edits(edits)?;
         
+        cursor.byte_offset = map.map(cursor.byte_offset).min(buffer.len_bytes());
+        (cursor.line, cursor.col) = buffer.offset_to_line_col(cursor.byte_offset);
         let cursor_after = CursorState::from(&*cursor);
         
-        Ok(Edit::new(
-            start,
-            deleted_text,
-            String::new(),
-            cursor_before,
-            cursor_after,
-        ))
+        Ok(edits
+            .iter()
+            .zip(old)
+            .rev()
+            .map(|(edit, (old_text, line_pos))| Edit {
+                line_pos,
+                ..Edit::new(edit.file_offset, old_text, edit.new_text.clone(), cursor_before, cursor_after)
+            })
+            .collect())
     }
     
     /// Replace text in the given range
@@ -154,34 +199,20 @@ impl EditOperations {
         
         // Get the text being replaced
         let old_text = buffer.slice(start..end);
+        let line_pos = buffer.line_position(start);
         
         // Perform the replacement
         buffer.replace(start, end, new_text)?;
         
         // Update cursor position
         cursor.byte_offset = start + new_text.len();
-        cursor.line = buffer.byte_offset_to_line(cursor.byte_offset);
-        let line_start = buffer.line_to_byte_offset(cursor.line);
-        let offset_in_line = cursor.byte_offset - line_start;
-        let line_text = buffer.get_line(cursor.line);
-        cursor.col = line_text.chars()
-            .take_while(|_| {
-                let bytes: usize = line_text.chars()
-                    .take(cursor.col)
-                    .map(|c| c.len_utf8())
-                    .sum();
-                bytes < offset_in_line
-            })
-            .count();
+        (cursor.line, cursor.col) = buffer.offset_to_line_col(cursor.byte_offset);
         
         let cursor_after = CursorState::from(&*cursor);
         
-        Ok(Edit::new(
-            start,
-            old_text,
-            new_text.to_string(),
-            cursor_before,
-            cursor_after,
-        ))
+        Ok(Edit {
+            line_pos,
+            ..Edit::new(start, old_text, new_text.to_string(), cursor_before, cursor_after)
+        })
     }
 }
@@ -0,0 +1,234 @@
+//! :to-ndjson and :from-ndjson: a top-level array rewritten as JSON Lines,
+//! one record to a line, and back. Records keep their bytes: one already
+//! on a single line is copied as it is, and one spread over several only
+//! loses the whitespace between its tokens. What changes is what lies
+//! between the records: the brackets, the commas and the line breaks. So
+//! JSON Lines made into an array and back comes out as it went in, ready
+//! for grep, awk and split in between.
+//!
+//! Either way the text is read once, in a thread of its own, and the
+//! array's members are found with the scanner rather than the tokenizer,
+//! so a large file converts in about the time it takes to copy.
+
+use std::sync::atomic::Ordering;
+
+use anyhow::{bail, Context, Result};
+
+use crate::parser::scan::{self, StructuralKind};
+use crate::ui::tasks::Progress;
+
+/// How often progress is reported and cancelling looked for
+const REPORT_EVERY: usize = 1 << 20;
+
+/// Which way to convert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToNdjson,
+    FromNdjson,
+}
+
+impl Direction {
+    /// The command, as :undolist lists it
+    pub fn label(self) -> &'static str {
+        match self {
+            Direction::ToNdjson => "to-ndjson",
+            Direction::FromNdjson => "from-ndjson",
+        }
+    }
+}
+
+/// Add `record`, which ends at byte `end`, to `out` on a line of its own,
+/// its whitespace taken out if it runs over several lines. A comment can't
+/// be kept on one line with the rest, so it's refused.
+fn push_record(out: &mut Vec<u8>, record: &[u8], end: usize) -> Result<()> {
+    let record = record.trim_ascii();
+    if record.is_empty() {
+        bail!("Missing a value before byte {}", end);
+    }
+    let multiline = record.contains(&b'\n');
+    let (mut in_string, mut escaped) = (false, false);
+    for &byte in record {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            b'/' if !in_string => bail!("Comments can't be kept in JSON Lines"),
+            byte if multiline && !in_string && byte.is_ascii_whitespace() => continue,
+            _ => {}
+        }
+        if multiline {
+            out.push(byte);
+        }
+    }
+    if !multiline {
+        out.extend_from_slice(record);
+    }
+    out.push(b'\n');
+    Ok(())
+}
+
+/// The elements of the top-level array `text`, one to a line, and how
+/// many there are
+pub fn to_ndjson(text: &[u8], progress: &Progress) -> Result<(String, usize)> {
+    progress.total.store(text.len(), Ordering::Relaxed);
+    let start = text.iter().position(|byte| !byte.is_ascii_whitespace());
+    let Some(start) = start.filter(|&start| text[start] == b'[') else {
+        bail!("Not an array at the top level");
+    };
+    let mut out = Vec::with_capacity(text.len());
+    let mut records = 0;
+    let mut depth = 0usize;
+    let mut from = start + 1;
+    let mut end = None;
+    let mut report = REPORT_EVERY;
+    for ch in scan::structure(&text[start..]) {
+        let at = start + ch.offset;
+        if at >= report {
+            progress.done.store(at, Ordering::Relaxed);
+            if progress.is_cancelled() {
+                bail!("Cancelled");
+            }
+            report = at + REPORT_EVERY;
+        }
+        match ch.kind {
+            StructuralKind::BraceOpen | StructuralKind::BracketOpen => depth += 1,
+            StructuralKind::BraceClose | StructuralKind::BracketClose => {
+                depth -= 1;
+                if depth == 0 {
+                    // Nothing before the bracket: the array is empty, or
+                    // ends on a trailing comma
+                    if !text[from..at].trim_ascii().is_empty() {
+                        push_record(&mut out, &text[from..at], at)?;
+                        records += 1;
+                    }
+                    end = Some(at);
+                    break;
+                }
+            }
+            StructuralKind::Comma if depth == 1 => {
+                push_record(&mut out, &text[from..at], at)?;
+                records += 1;
+                from = at + 1;
+            }
+            _ => {}
+        }
+    }
+    let end = end.context("The array isn't closed")?;
+    if !text[end + 1..].trim_ascii().is_empty() {
+        bail!("More follows the array");
+    }
+    progress.done.store(text.len(), Ordering::Relaxed);
+    Ok((String::from_utf8(out).context("Not UTF-8")?, records))
+}
+
+/// The records of the JSON Lines text `text` as an array, each on a line
+/// of its own indented `indent` spaces, and how many there are. Blank lines
+/// are dropped.
+pub fn from_ndjson(text: &[u8], indent: usize, progress: &Progress) -> Result<(String, usize)> {
+    progress.total.store(text.len(), Ordering::Relaxed);
+    let mut out = Vec::with_capacity(text.len() + text.len() / 8);
+    out.push(b'[');
+    let mut records = 0;
+    let mut at = 0;
+    let mut report = REPORT_EVERY;
+    for line in text.split(|&byte| byte == b'\n') {
+        at += line.len() + 1;
+        if at >= report {
+            progress.done.store(at, Ordering::Relaxed);
+            if progress.is_cancelled() {
+                bail!("Cancelled");
+            }
+            report = at + REPORT_EVERY;
+        }
+        let record = line.trim_ascii();
+        if record.is_empty() {
+            continue;
+        }
+        out.extend_from_slice(if records == 0 { b"\n" } else { b",\n" });
+        out.resize(out.len() + indent, b' ');
+        out.extend_from_slice(record);
+        records += 1;
+    }
+    out.extend_from_slice(if records == 0 { b"]\n" } else { b"\n]\n" });
+    progress.done.store(text.len(), Ordering::Relaxed);
+    Ok((String::from_utf8(out).context("Not UTF-8")?, records))
+}
+
+/// Convert `text` the way `direction` says
+pub fn convert(text: &[u8], direction: Direction, indent: usize, progress: &Progress) -> Result<(String, usize)> {
+    match direction {
+        Direction::ToNdjson => to_ndjson(text, progress),
+        Direction::FromNdjson => from_ndjson(text, indent, progress),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::fixture;
+
+    fn to(text: &str) -> Result<String> {
+        to_ndjson(text.as_bytes(), &Progress::default()).map(|(text, _)| text)
+    }
+
+    fn from(text: &str) -> String {
+        from_ndjson(text.as_bytes(), 2, &Progress::default()).unwrap().0
+    }
+
+    #[test]
+    fn test_round_trip_fixture() {
+        let pretty = fixture(1000);
+        let (ndjson, records) = to_ndjson(pretty.as_bytes(), &Progress::default()).unwrap();
+        assert_eq!((ndjson.lines().count(), records), (1000, 1000));
+        // The fixture's strings have no whitespace in them to keep
+        let compact: String = pretty.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+        assert_eq!(format!("[{}]", ndjson.trim_end().replace('\n', ",")), compact);
+
+        let array = from(&ndjson);
+        assert!(array.starts_with("[\n  {\"id\":0,\"name\":\"item_0\","), "{}", &array[..40]);
+        assert_eq!(to(&array).unwrap(), ndjson);
+        assert_eq!(from(&to(&array).unwrap()), array);
+    }
+
+    #[test]
+    fn test_records_kept_as_they_are() {
+        let ndjson = "{\"a\": \"x , y\", \"b\": [1, 2]}\n  {\"say\": \"\\\"hi\\\" \\\\\"}\n\n[3, {\"c\": null}]\n\"text\"\n";
+        let array = from(ndjson);
+        assert_eq!(
+            array,
+            "[\n  {\"a\": \"x , y\", \"b\": [1, 2]},\n  {\"say\": \"\\\"hi\\\" \\\\\"},\n  [3, {\"c\": null}],\n  \"text\"\n]\n"
+        );
+        assert_eq!(to(&array).unwrap(), ndjson.replace("\n  ", "\n").replace("\n\n", "\n"));
+
+        // Only a record over several lines loses whitespace, and never a
+        // string's
+        assert_eq!(to("[{\"a b\": 1},\n {\n  \"c d\": [1,\n 2]\n }\n]").unwrap(), "{\"a b\": 1}\n{\"c d\":[1,2]}\n");
+    }
+
+    #[test]
+    fn test_edges() {
+        assert_eq!(to("[]").unwrap(), "");
+        assert_eq!(from(""), "[]\n");
+        assert_eq!(from("\n \n"), "[]\n");
+        assert_eq!(to("[1, 2, ]").unwrap(), "1\n2\n");
+        assert_eq!(from("{}"), "[\n  {}\n]\n");
+        assert_eq!(from_ndjson(b"1\n2\n", 4, &Progress::default()).unwrap(), ("[\n    1,\n    2\n]\n".to_string(), 2));
+
+        let error = |text: &str| to(text).unwrap_err().to_string();
+        assert_eq!(error("{\"a\": [1]}"), "Not an array at the top level");
+        assert_eq!(error("  "), "Not an array at the top level");
+        assert_eq!(error("[1, [2, 3]"), "The array isn't closed");
+        assert_eq!(error("[1] [2]"), "More follows the array");
+        assert_eq!(error("[1, , 2]"), "Missing a value before byte 4");
+        assert_eq!(error("[{\n\"a\": 1 // one\n}]"), "Comments can't be kept in JSON Lines");
+    }
+
+    #[test]
+    fn test_cancel() {
+        let pretty = fixture(10_000);
+        let progress = Progress::default();
+        progress.cancel.store(true, Ordering::Relaxed);
+        assert!(to_ndjson(pretty.as_bytes(), &progress).is_err());
+        assert!(from_ndjson(pretty.as_bytes(), 2, &progress).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Pasting text as one edit, for `p` and bracketed paste. With :set
+//! formatpaste, text that is one whole JSON object or array is laid out
+//! again at the depth it lands at, rather than staying a long minified line
+//! in a pretty-printed document. What a bracketed paste in normal mode
+//! does is up to :set pastebehavior.
+
+use anyhow::Result;
+use std::io::Write;
+
+use super::export::PrettyWriter;
+use super::structural::line_indent;
+use super::{Edit, EditOperations};
+use crate::buffer::{Buffer, Cursor};
+use crate::parser::token::TokenKind;
+use crate::parser::{NodeKind, StructuralIndex, Tokenizer};
+use crate::ui::perf;
+
+/// What a bracketed paste does in normal mode (:set pastebehavior)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PasteBehavior {
+    /// Insert it at the cursor, as in insert mode
+    #[default]
+    Insert,
+    /// Leave the buffer alone and say how to paste
+    Ignore,
+}
+
+impl PasteBehavior {
+    /// The behavior :set pastebehavior names
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "insert" => Some(PasteBehavior::Insert),
+            "ignore" => Some(PasteBehavior::Ignore),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PasteBehavior::Insert => "insert",
+            PasteBehavior::Ignore => "ignore",
+        }
+    }
+}
+
+/// Whether `text`, give or take surrounding whitespace, is exactly one
+/// JSON object or array
+pub fn is_json_container(text: &str) -> bool {
+    let tokens = Tokenizer::new(text.to_string()).tokenize_all();
+    if tokens.iter().any(|token| matches!(token.kind, TokenKind::Invalid | TokenKind::Comment)) {
+        return false;
+    }
+    let index = StructuralIndex::from_tokens(&tokens);
+    let Some(root) = index.nth_root(0) else {
+        return false;
+    };
+    index.root_count() == 1
+        && index.errors().is_empty()
+        && index.is_closed(root)
+        && matches!(index.kind(root), Some(NodeKind::Object | NodeKind::Array))
+}
+
+/// `text` pretty-printed `shiftwidth` spaces a level, each line after the
+/// first starting with `indent`, if it's one JSON object or array
+pub fn format_json(text: &str, indent: &str, shiftwidth: usize) -> Option<String> {
+    if !is_json_container(text) {
+        return None;
+    }
+    let mut timing = perf::scoped("format");
+    timing.bytes(text.len());
+    let mut out = Vec::with_capacity(text.len() * 2);
+    let mut writer = PrettyWriter::with_indent(&mut out, shiftwidth);
+    writer.write_all(text.trim().as_bytes()).ok()?;
+    let pretty = String::from_utf8(out).ok()?;
+    Some(pretty.replace('\n', &format!("\n{}", indent)))
+}
+
+/// Insert `text` at `offset` as one edit, cursor after it. With
+/// `shiftwidth` (:set formatpaste on) JSON is formatted to fit the
+/// indentation of the line it goes into first.
+pub fn paste(buffer: &mut Buffer, cursor: &mut Cursor, offset: usize, text: &str, shiftwidth: Option<usize>) -> Result<Edit> {
+    let formatted = shiftwidth.and_then(|width| format_json(text, &line_indent(buffer, offset), width));
+    EditOperations::insert(buffer, cursor, offset, formatted.as_deref().unwrap_or(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_json_container() {
+        assert!(is_json_container(r#"{"a": [1, 2, {"b": null}]}"#));
+        assert!(is_json_container("\n  [1, 2]\n"));
+        assert!(!is_json_container("42"));
+        assert!(!is_json_container(r#""text""#));
+        assert!(!is_json_container(r#"{"a": 1"#));
+        assert!(!is_json_container("[1, 2] [3]"));
+        assert!(!is_json_container(r#"{"a": 1,}"#));
+        assert!(!is_json_container("[1] // note"));
+        assert!(!is_json_container("hello world"));
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut buffer = Buffer::from_text("{\n  \"a\": 1,\n  \"b\": \n}");
+        let mut cursor = Cursor::new();
+        let at = buffer.find("\"b\": ", 0).unwrap() + 5;
+        let edit = paste(&mut buffer, &mut cursor, at, r#"{"c":[1,2],"d":{}}"#, Some(2)).unwrap();
+        let expected = "{\n  \"a\": 1,\n  \"b\": {\n    \"c\": [\n      1,\n      2\n    ],\n    \"d\": {}\n  }\n}";
+        assert_eq!(buffer.slice(0..buffer.len_bytes()), expected);
+        assert_eq!(cursor.byte_offset, expected.len() - 2);
+        assert_eq!(edit.new_text.lines().count(), 7);
+
+        // Text that isn't a whole value, or with formatting off, goes in as it is
+        let mut buffer = Buffer::from_text("[]");
+        paste(&mut buffer, &mut cursor, 1, r#"1, {"a":2}"#, Some(4)).unwrap();
+        paste(&mut buffer, &mut cursor, 1, r#"{"b":3}, "#, None).unwrap();
+        assert_eq!(buffer.slice(0..buffer.len_bytes()), r#"[{"b":3}, 1, {"a":2}]"#);
+
+        // A wider shiftwidth
+        let mut buffer = Buffer::from_text("");
+        paste(&mut buffer, &mut cursor, 0, "[[1]]", Some(4)).unwrap();
+        assert_eq!(buffer.slice(0..buffer.len_bytes()), "[\n    [\n        1\n    ]\n]");
+    }
+}
@@ -0,0 +1,216 @@
+//! Filtering text through an external command for :pipe. The command runs
+//! in the background with its output captured, so the editor keeps drawing
+//! and can cancel it.
+
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+/// How long a command may run before it's killed
+pub const PIPE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Split a command line into words the way a shell would for simple cases:
+/// whitespace separates, single quotes keep everything, double quotes keep
+/// everything but backslash escapes, and a backslash outside quotes escapes
+/// the next character. No variables, globs or redirections.
+pub fn split_words(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("Unterminated ' in command"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => bail!("Unterminated \" in command"),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("Unterminated \" in command"),
+                    }
+                }
+            }
+            '\\' => {
+                let Some(c) = chars.next() else {
+                    bail!("Trailing \\ in command");
+                };
+                word.get_or_insert_with(String::new).push(c);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// What a command that exited successfully printed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipeOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// A command running with `input` on its stdin
+pub struct PipeJob {
+    child: Child,
+    stdout: Option<JoinHandle<Vec<u8>>>,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    started: Instant,
+    timeout: Duration,
+    /// The command as typed, for messages
+    pub command: String,
+}
+
+impl PipeJob {
+    /// Start `command`, split into words or, with `shell`, handed to `sh -c`
+    pub fn start(command: &str, input: String, shell: bool) -> Result<Self> {
+        let mut process = if shell {
+            let mut process = Command::new("sh");
+            process.arg("-c").arg(command);
+            process
+        } else {
+            let words = split_words(command)?;
+            let Some((program, args)) = words.split_first() else {
+                bail!("No command to pipe through");
+            };
+            let mut process = Command::new(program);
+            process.args(args);
+            process
+        };
+        let mut child = process
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Can't run {}", command))?;
+
+        // Feed and drain the pipes on their own threads so a command that
+        // writes before it has read everything can't deadlock
+        if let Some(mut stdin) = child.stdin.take() {
+            thread::spawn(move || {
+                let _ = stdin.write_all(input.as_bytes());
+            });
+        }
+        let stdout = child.stdout.take().map(drain);
+        let stderr = child.stderr.take().map(drain);
+        Ok(Self {
+            child,
+            stdout,
+            stderr,
+            started: Instant::now(),
+            timeout: PIPE_TIMEOUT,
+            command: command.to_string(),
+        })
+    }
+
+    /// Check on the command without waiting: None while it's running, then
+    /// its output, or an error if it failed, couldn't be read or ran past
+    /// the timeout (and was killed)
+    pub fn poll(&mut self) -> Option<Result<PipeOutput>> {
+        let status = match self.child.try_wait() {
+            Ok(Some(status)) => status,
+            Ok(None) if self.started.elapsed() < self.timeout => return None,
+            Ok(None) => {
+                self.kill();
+                return Some(Err(anyhow::anyhow!("{} timed out after {}s", self.command, self.timeout.as_secs())));
+            }
+            Err(e) => return Some(Err(e.into())),
+        };
+        let collect = |handle: Option<JoinHandle<Vec<u8>>>| {
+            handle.and_then(|h| h.join().ok()).map(|bytes| String::from_utf8_lossy(&bytes).into_owned()).unwrap_or_default()
+        };
+        let stdout = collect(self.stdout.take());
+        let stderr = collect(self.stderr.take());
+        if !status.success() {
+            let detail = stderr.lines().rfind(|line| !line.trim().is_empty()).unwrap_or("no output");
+            let code = status.code().map_or("killed".to_string(), |code| format!("exit {}", code));
+            return Some(Err(anyhow::anyhow!("{} failed ({}): {}", self.command, code, detail)));
+        }
+        Some(Ok(PipeOutput { stdout, stderr }))
+    }
+
+    /// Stop the command; its output is dropped
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Read everything from `pipe` on a thread of its own
+fn drain(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        bytes
+    })
+}
+
+impl Drop for PipeJob {
+    fn drop(&mut self) {
+        if matches!(self.child.try_wait(), Ok(None)) {
+            self.kill();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finish(mut job: PipeJob) -> Result<PipeOutput> {
+        loop {
+            if let Some(result) = job.poll() {
+                return result;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(split_words("sort  -r").unwrap(), vec!["sort", "-r"]);
+        assert_eq!(
+            split_words(r#"python -c 'print("a b")' "x \"y\" \n" a\ b ''"#).unwrap(),
+            vec!["python", "-c", r#"print("a b")"#, r#"x "y" \n"#, "a b", ""]
+        );
+        assert!(split_words("echo 'open").is_err());
+        assert!(split_words("echo \\").is_err());
+    }
+
+    #[test]
+    fn test_pipe_job() {
+        let output = finish(PipeJob::start("tr a-z A-Z", "[\"abc\"]".to_string(), false).unwrap()).unwrap();
+        assert_eq!(output.stdout, "[\"ABC\"]");
+        let output = finish(PipeJob::start("echo out; echo err >&2", String::new(), true).unwrap()).unwrap();
+        assert_eq!((output.stdout.as_str(), output.stderr.as_str()), ("out\n", "err\n"));
+
+        let error = finish(PipeJob::start("sh -c 'echo bad >&2; exit 3'", String::new(), false).unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "sh -c 'echo bad >&2; exit 3' failed (exit 3): bad");
+        assert!(PipeJob::start("no-such-command-for-jim", String::new(), false).is_err());
+
+        let mut job = PipeJob::start("sleep 5", String::new(), false).unwrap();
+        job.timeout = Duration::ZERO;
+        assert!(finish(job).unwrap_err().to_string().contains("timed out"));
+    }
+}
@@ -0,0 +1,252 @@
+//! :renameall: every object key with one name given another, across the
+//! document or under a path. Keys are found by the structural index and
+//! compared after decoding escapes, so a value that reads the same as the
+//! key is never touched, which a text substitution couldn't promise. With
+//! the `c` flag each key is shown and asked about in the status line first.
+
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::buffer::{Buffer, Edit};
+use crate::navigation::path::{self, Resolved, Segment};
+use crate::parser::decode;
+use crate::parser::StructuralIndex;
+
+/// :renameall[!] [path] old new [c], as typed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenameAll {
+    /// Only keys inside the value at this path, `.config.*` or `.config`
+    pub scope: Option<Vec<Segment>>,
+    pub old: String,
+    pub new: String,
+    /// Ask before renaming each key
+    pub confirm: bool,
+    /// Rename even where the object already has a key called `new`
+    pub force: bool,
+}
+
+impl RenameAll {
+    /// Parse what follows `:renameall`. A first word starting with `.`, `$`
+    /// or `[` is the path; names with spaces in them are written quoted.
+    pub fn parse(args: &str, force: bool) -> Result<Self> {
+        let usage = || anyhow!("Usage: :renameall [path] old new [c]");
+        let mut words = split_words(args)?;
+        let scope = match words.first() {
+            Some(first) if first.starts_with(['.', '$', '[']) => Some(parse_scope(&words.remove(0))?),
+            _ => None,
+        };
+        let confirm = match words.len() {
+            3 if words[2] == "c" => true,
+            2 => false,
+            _ => return Err(usage()),
+        };
+        let name = |word: &str| match word.starts_with('"') {
+            true => decode::decode_string(word).map_err(|e| anyhow!("Bad key {}: {}", word, e)),
+            false => Ok(word.to_string()),
+        };
+        let (old, new) = (name(&words[0])?, name(&words[1])?);
+        if old == new {
+            bail!("Old and new names are the same");
+        }
+        Ok(Self { scope, old, new, confirm, force })
+    }
+
+    /// What the undo list calls it, with the keys it renamed
+    pub fn label(&self, count: usize) -> String {
+        format!("rename {} keys \"{}\" to \"{}\"", count, self.old, self.new)
+    }
+}
+
+/// Words split at spaces, except inside double quotes
+fn split_words(text: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let (mut quoted, mut escaped) = (false, false);
+    for c in text.chars() {
+        if c.is_whitespace() && !quoted {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        word.push(c);
+        if c == '"' && !escaped {
+            quoted = !quoted;
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    if quoted {
+        bail!("Unterminated quote in {}", text.trim());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// A path with an optional `.*` or `[*]` on the end, which means the same
+fn parse_scope(text: &str) -> Result<Vec<Segment>> {
+    let prefix = text.strip_suffix(".*").or_else(|| text.strip_suffix("[*]")).unwrap_or(text);
+    path::parse_path(prefix)
+}
+
+/// Where `scope` is in the document: the span keys must be inside
+pub fn scope_span(index: &StructuralIndex, buffer: &Buffer, scope: &[Segment]) -> Result<Range<usize>> {
+    match path::resolve(index, buffer, scope) {
+        Resolved::Found(node) => index.get(node).map(|node| node.start..node.end).ok_or_else(|| anyhow!("Index not ready")),
+        Resolved::Pending => bail!("Index not ready"),
+        Resolved::Missing(why) => bail!(why),
+    }
+}
+
+/// The keys to rename, in document order, and how many were left alone
+/// because their object already has a key called `new`
+pub fn find(index: &StructuralIndex, buffer: &Buffer, rename: &RenameAll, within: Range<usize>) -> (Vec<Range<usize>>, usize) {
+    let named = |id, name: &str| {
+        index.get(id).is_some_and(|key| decode::decode_string(&buffer.slice(key.start..key.end)).ok().as_deref() == Some(name))
+    };
+    let mut keys = Vec::new();
+    let mut skipped = 0;
+    for id in index.nodes_starting_in(within) {
+        if !index.is_key_node(id) || !named(id, &rename.old) {
+            continue;
+        }
+        let Some(node) = index.get(id) else { continue };
+        let taken = index.parent(id).is_some_and(|object| {
+            index.member_iter(object).map(|(key, _)| key).any(|other| other != id && named(other, &rename.new))
+        });
+        if taken && !rename.force {
+            skipped += 1;
+        } else {
+            keys.push(node.start..node.end);
+        }
+    }
+    (keys, skipped)
+}
+
+/// The replacements renaming `keys` to `new` makes
+pub fn edits(keys: &[Range<usize>], new: &str) -> Vec<Edit> {
+    let quoted = decode::encode_string(new);
+    keys.iter()
+        .map(|key| Edit { file_offset: key.start, old_len: key.len(), new_text: quoted.clone() })
+        .collect()
+}
+
+/// An answer to "Rename this key?"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    Yes,
+    No,
+    /// This key and every one after it
+    All,
+    /// No more, but keep the ones said yes to
+    Quit,
+}
+
+/// A :renameall with the `c` flag, going through its keys one at a time
+#[derive(Debug, Clone)]
+pub struct Confirming {
+    pub rename: RenameAll,
+    keys: Vec<Range<usize>>,
+    next: usize,
+    accepted: Vec<Range<usize>>,
+    pub skipped: usize,
+}
+
+impl Confirming {
+    pub fn new(rename: RenameAll, keys: Vec<Range<usize>>, skipped: usize) -> Self {
+        Self { rename, keys, next: 0, accepted: Vec::new(), skipped }
+    }
+
+    /// The key being asked about, with its place among them all: 3 of 12
+    pub fn current(&self) -> Option<(Range<usize>, usize, usize)> {
+        self.keys.get(self.next).map(|key| (key.clone(), self.next + 1, self.keys.len()))
+    }
+
+    pub fn answer(&mut self, answer: Answer) {
+        match answer {
+            Answer::Yes => self.accepted.extend(self.keys.get(self.next).cloned()),
+            Answer::No => {}
+            Answer::All => self.accepted.extend(self.keys.drain(self.next..)),
+            Answer::Quit => self.keys.truncate(self.next),
+        }
+        self.next = (self.next + 1).min(self.keys.len());
+    }
+
+    /// The keys said yes to
+    pub fn accepted(&self) -> &[Range<usize>] {
+        &self.accepted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    const TEXT: &str = r#"{"id": 1, "config": {"id": "id", "list": [{"id": 2, "ident": 3}], "x": {"id": 4, "key": 5}}, "name": "id"}"#;
+
+    fn run(text: &str, args: &str, force: bool) -> (String, usize) {
+        let mut buffer = Buffer::from_text(text);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let rename = RenameAll::parse(args, force).unwrap();
+        let within = match &rename.scope {
+            Some(scope) => scope_span(&index, &buffer, scope).unwrap(),
+            None => 0..buffer.len_bytes(),
+        };
+        let (keys, skipped) = find(&index, &buffer, &rename, within);
+        buffer.apply_edits(&edits(&keys, &rename.new)).unwrap();
+        (buffer.slice(0..buffer.len_bytes()), skipped)
+    }
+
+    #[test]
+    fn test_parse() {
+        let rename = RenameAll::parse("old new", false).unwrap();
+        assert_eq!((rename.scope, rename.old.as_str(), rename.new.as_str(), rename.confirm), (None, "old", "new", false));
+        let rename = RenameAll::parse(r#" .config["a b"].* "first name" name  c"#, true).unwrap();
+        assert_eq!(rename.scope, Some(vec![Segment::Key("config".into()), Segment::Key("a b".into())]));
+        assert_eq!((rename.old.as_str(), rename.confirm, rename.force), ("first name", true, true));
+        assert!(RenameAll::parse("old", false).is_err());
+        assert!(RenameAll::parse("old new x", false).is_err());
+        assert!(RenameAll::parse("\"old new", false).is_err());
+        assert!(RenameAll::parse("same same", false).is_err());
+    }
+
+    #[test]
+    fn test_rename_all() {
+        // Keys only, at any depth; values and longer keys are left alone
+        assert_eq!(
+            run(TEXT, "id key", false),
+            (r#"{"key": 1, "config": {"key": "id", "list": [{"key": 2, "ident": 3}], "x": {"id": 4, "key": 5}}, "name": "id"}"#.to_string(), 1),
+        );
+        // ...and with ! even where the object has one already
+        assert!(run(TEXT, "id key", true).0.contains(r#""x": {"key": 4, "key": 5}"#));
+        // Under a path
+        assert_eq!(
+            run(TEXT, ".config.list.* id \"new \\\"id\\\"\"", false).0,
+            r#"{"id": 1, "config": {"id": "id", "list": [{"new \"id\"": 2, "ident": 3}], "x": {"id": 4, "key": 5}}, "name": "id"}"#,
+        );
+        // A key written with escapes is still the same key
+        assert_eq!(run(r#"{"\u0069d": 1}"#, "id key", false).0, r#"{"key": 1}"#);
+    }
+
+    #[test]
+    fn test_confirming() {
+        let rename = RenameAll::parse("a b c", false).unwrap();
+        let mut confirming = Confirming::new(rename, vec![0..1, 2..3, 4..5, 6..7], 0);
+        assert_eq!(confirming.current(), Some((0..1, 1, 4)));
+        confirming.answer(Answer::No);
+        confirming.answer(Answer::Yes);
+        assert_eq!(confirming.current(), Some((4..5, 3, 4)));
+        confirming.answer(Answer::All);
+        assert_eq!(confirming.current(), None);
+        assert_eq!(confirming.accepted(), [2..3, 4..5, 6..7]);
+
+        let mut confirming = Confirming::new(RenameAll::parse("a b c", false).unwrap(), vec![0..1, 2..3], 0);
+        confirming.answer(Answer::Yes);
+        confirming.answer(Answer::Quit);
+        assert_eq!(confirming.current(), None);
+        assert_eq!(confirming.accepted(), std::slice::from_ref(&(0..1)));
+    }
+}
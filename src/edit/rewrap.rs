@@ -0,0 +1,201 @@
+//! gq: a long string value laid out again at :set textwidth. The string is
+//! decoded first, so an escape counts as the character it stands for, and
+//! what goes back is still valid JSON: the same string with `\n` where its
+//! lines now break (:set wrapstyle=escape), or an array of its lines, each
+//! keeping the space it broke at, so that joined together they make the
+//! string as it was (wrapstyle=array).
+
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+
+use super::structural::{line_indent, replace};
+use super::Edit;
+use crate::buffer::cursor::Cursor;
+use crate::buffer::Buffer;
+use crate::parser::decode;
+use crate::parser::{NodeKind, StructuralIndex};
+
+/// Default for :set textwidth
+pub const DEFAULT_TEXTWIDTH: usize = 80;
+
+/// Narrowest an array's lines are made, however deep it's indented
+const MIN_WIDTH: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapStyle {
+    /// One string, its lines joined by `\n`
+    #[default]
+    Escape,
+    /// An array of strings, one a line
+    Array,
+}
+
+impl WrapStyle {
+    /// The style :set wrapstyle names
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "escape" => Some(WrapStyle::Escape),
+            "array" => Some(WrapStyle::Array),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WrapStyle::Escape => "escape",
+            WrapStyle::Array => "array",
+        }
+    }
+}
+
+/// `text` cut into lines of at most `width` characters where it can be,
+/// between words; a word longer than that gets a line to itself. Each line
+/// keeps the spaces it broke at, and a line break in the text ends a line
+/// (and stays at the end of it), so the lines joined are `text` again.
+pub fn break_lines(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        // A word, the spaces after it and a line break, if one follows
+        let word = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let spaces = rest[word..].find(|c: char| !c.is_whitespace() || c == '\n').map_or(rest.len(), |at| word + at);
+        let newline = rest[spaces..].starts_with('\n');
+        let end = spaces + usize::from(newline);
+
+        let word_width = rest[..word].chars().count();
+        if word_width > 0 && !line.is_empty() && line.chars().count() + word_width > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        line.push_str(&rest[..end]);
+        if newline {
+            lines.push(std::mem::take(&mut line));
+        }
+        rest = &rest[end..];
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// `text` reflowed as gq does it: the lines of a paragraph run together
+/// and are broken again at `width`, and paragraphs stay a blank line apart
+pub fn reflow(text: &str, width: usize) -> String {
+    let mut paragraphs = Vec::new();
+    let mut words: Vec<&str> = Vec::new();
+    for line in text.lines().chain([""]) {
+        if line.trim().is_empty() {
+            if !words.is_empty() {
+                let lines = break_lines(&words.join(" "), width);
+                paragraphs.push(lines.iter().map(|line| line.trim_end()).collect::<Vec<_>>().join("\n"));
+                words.clear();
+            }
+        } else {
+            words.extend(line.split_whitespace());
+        }
+    }
+    paragraphs.join("\n\n")
+}
+
+/// Wrap the string value under the cursor, or the one `selection` is
+/// inside, at `width` columns, leaving the cursor at the start of it.
+/// Laid out as an array, the lines go one to a line, `shiftwidth` in from
+/// the line the string was on, and are narrowed to fit in `width` there.
+pub fn rewrap(
+    buffer: &mut Buffer,
+    cursor: &mut Cursor,
+    index: &StructuralIndex,
+    selection: Option<Range<usize>>,
+    width: usize,
+    style: WrapStyle,
+    shiftwidth: usize,
+) -> Result<Vec<Edit>> {
+    let at = selection.as_ref().map_or(cursor.byte_offset, |selection| selection.start);
+    let value = index.node_id_at(at).filter(|&id| !index.is_key_node(id));
+    let Some(node) = value.and_then(|id| index.get(id)).filter(|node| node.kind == NodeKind::String) else {
+        bail!("gq wraps a string value; not on one");
+    };
+    if selection.is_some_and(|selection| selection.end > node.end) {
+        bail!("gq wraps a string value; the selection goes outside it");
+    }
+    let literal = buffer.slice(node.start..node.end);
+    let Ok(text) = decode::decode_string(&literal) else {
+        bail!("Malformed string: {}", literal);
+    };
+    let wrapped = match style {
+        WrapStyle::Escape => decode::encode_string(&reflow(&text, width)),
+        WrapStyle::Array => {
+            let indent = line_indent(buffer, node.start);
+            let inner = format!("{}{}", indent, " ".repeat(shiftwidth));
+            // Room for the quotes and the comma
+            let width = width.saturating_sub(inner.chars().count() + 3).max(MIN_WIDTH);
+            let lines: Vec<String> = break_lines(&text, width)
+                .iter()
+                .map(|line| format!("{}{}", inner, decode::encode_string(line)))
+                .collect();
+            format!("[\n{}\n{}]", lines.join(",\n"), indent)
+        }
+    };
+    if wrapped == literal {
+        bail!("Already wrapped");
+    }
+    replace(buffer, cursor, node.start..node.end, &wrapped, node.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    const TEXT: &str = "The quick brown fox jumps over the lazy dog, and then it naps.";
+
+    #[test]
+    fn test_break_lines() {
+        let lines = break_lines(TEXT, 20);
+        assert_eq!(lines, ["The quick brown fox ", "jumps over the lazy ", "dog, and then it ", "naps."]);
+        assert_eq!(lines.concat(), TEXT);
+        // Line breaks stay, and a word too long for a line has one to itself
+        let text = "short\nsupercalifragilistic word  ";
+        let lines = break_lines(text, 10);
+        assert_eq!(lines, ["short\n", "supercalifragilistic ", "word  "]);
+        assert_eq!(lines.concat(), text);
+        assert_eq!(break_lines("", 10), [""]);
+    }
+
+    #[test]
+    fn test_reflow() {
+        assert_eq!(reflow(TEXT, 30), "The quick brown fox jumps over\nthe lazy dog, and then it\nnaps.");
+        // Lines of a paragraph run together; blank lines stay
+        assert_eq!(reflow("one\ntwo three\n\n\nfour", 9), "one two\nthree\n\nfour");
+    }
+
+    fn run(text: &str, at: &str, style: WrapStyle) -> Result<String> {
+        let mut buffer = Buffer::from_text(text);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let mut cursor = Cursor::new();
+        cursor.move_to_offset(&buffer, text.find(at).unwrap());
+        rewrap(&mut buffer, &mut cursor, &index, None, 30, style, 2)?;
+        // The cursor is left at the start of what replaced the string
+        assert!(matches!(buffer.slice(cursor.byte_offset..cursor.byte_offset + 1).as_str(), "\"" | "["));
+        Ok(buffer.slice(0..buffer.len_bytes()))
+    }
+
+    #[test]
+    fn test_rewrap() {
+        let text = format!("{{\n  \"about\": \"{}\",\n  \"n\": 1\n}}", TEXT);
+        assert_eq!(
+            run(&text, "quick", WrapStyle::Escape).unwrap(),
+            "{\n  \"about\": \"The quick brown fox jumps over\\nthe lazy dog, and then it\\nnaps.\",\n  \"n\": 1\n}",
+        );
+        assert_eq!(
+            run(&text, "quick", WrapStyle::Array).unwrap(),
+            "{\n  \"about\": [\n    \"The quick brown fox \",\n    \"jumps over the lazy \",\n    \"dog, and then it naps.\"\n  ],\n  \"n\": 1\n}",
+        );
+        // Only string values, not keys
+        assert!(run(&text, "about", WrapStyle::Array).unwrap_err().to_string().contains("not on one"));
+        assert!(run(&text, "1", WrapStyle::Escape).is_err());
+        assert_eq!(run("[\"short\"]", "short", WrapStyle::Escape).unwrap_err().to_string(), "Already wrapped");
+    }
+}
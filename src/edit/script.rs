@@ -0,0 +1,369 @@
+//! Edit scripts: what :script start records and `jim --apply` replays.
+//! Steps are kept by what they meant, not the keys typed for them: a node
+//! edit names the node by its path, so the same script works on a file
+//! whose members sit in another order or on other lines. Only edits that
+//! mean the same thing in any file are recorded; undo isn't, and neither
+//! is anything taking text from outside the script (paste, :pipe).
+//!
+//! A script is JSON, one step to a line:
+//!
+//! ```text
+//! {"jimscript":1,"steps":[
+//!   {"op":"delete","path":"$.debug"},
+//!   {"op":"rename","path":"$.users[0].name","to":"full_name"}
+//! ]}
+//! ```
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{Map, Value};
+
+use crate::edit::array::ArrayOp;
+use crate::edit::ndjson::Direction;
+use crate::edit::renameall::RenameAll;
+use crate::edit::structural::Conversion;
+use crate::edit::whitespace::WhitespaceOp;
+use crate::mode::NodeOp;
+use crate::navigation::path::{format_path, parse_path, Segment};
+use crate::parser::decode::encode_string;
+
+/// The format version written in `"jimscript"`; older jim refuses newer
+pub const VERSION: u64 = 1;
+
+/// One recorded edit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// A node edit on the node at `path`
+    Node { path: Vec<Segment>, op: NodeOp },
+    /// :renameall without the `c` flag
+    RenameAll(RenameAll),
+    /// :retab or :trimws over the whole buffer
+    Whitespace(WhitespaceOp),
+    /// :to-ndjson or :from-ndjson
+    Ndjson(Direction),
+}
+
+impl Step {
+    /// `op` at `path` as a step, if it's one a script can replay
+    pub fn node(op: &NodeOp, path: Vec<Segment>) -> Option<Self> {
+        let replayable = match op {
+            NodeOp::Delete | NodeOp::MoveUp | NodeOp::MoveDown => true,
+            NodeOp::Rename { .. } | NodeOp::Wrap { .. } | NodeOp::Unwrap { .. } | NodeOp::Convert(_) => true,
+            NodeOp::Array(array) => *array != ArrayOp::Count,
+            _ => false,
+        };
+        replayable.then(|| Step::Node { path, op: op.clone() })
+    }
+
+    /// The step as --apply reports it
+    pub fn describe(&self) -> String {
+        match self {
+            Step::Node { path, op } => {
+                let path = format_path(path);
+                match op {
+                    NodeOp::Rename { name, .. } => format!("rename {} to {}", path, encode_string(name)),
+                    NodeOp::Wrap { key: Some(key) } => format!("wrap {} in {}", path, encode_string(key)),
+                    NodeOp::Convert(conversion) => format!("convert {} to {}", path, conversion.name()),
+                    NodeOp::Array(array) => format!("array {} {}", array.name(), path),
+                    _ => format!("{} {}", node_op_name(op), path),
+                }
+            }
+            Step::RenameAll(rename) => {
+                let under = rename.scope.as_ref().map(|scope| format!(" under {}", format_path(scope))).unwrap_or_default();
+                format!("rename keys {} to {}{}", encode_string(&rename.old), encode_string(&rename.new), under)
+            }
+            Step::Whitespace(op) => whitespace_name(*op).to_string(),
+            Step::Ndjson(direction) => direction.label().to_string(),
+        }
+    }
+
+    /// The step as one line of JSON
+    pub fn to_json(&self) -> String {
+        let mut fields = Map::new();
+        let mut set = |name: &str, value: Value| {
+            fields.insert(name.to_string(), value);
+        };
+        match self {
+            Step::Node { path, op } => {
+                set("op", node_op_name(op).into());
+                set("path", format_path(path).into());
+                match op {
+                    NodeOp::Rename { name, force } => {
+                        set("to", name.as_str().into());
+                        if *force {
+                            set("force", true.into());
+                        }
+                    }
+                    NodeOp::Wrap { key: Some(key) } => set("key", key.as_str().into()),
+                    NodeOp::Unwrap { splice: true } => set("splice", true.into()),
+                    NodeOp::Convert(conversion) => set("to", conversion.name().into()),
+                    NodeOp::Array(array) => set("do", array.name().into()),
+                    _ => {}
+                }
+            }
+            Step::RenameAll(rename) => {
+                set("op", "renameall".into());
+                if let Some(scope) = &rename.scope {
+                    set("under", format_path(scope).into());
+                }
+                set("from", rename.old.as_str().into());
+                set("to", rename.new.as_str().into());
+                if rename.force {
+                    set("force", true.into());
+                }
+            }
+            Step::Whitespace(op) => {
+                set("op", whitespace_name(*op).into());
+                if let WhitespaceOp::Retab(Some(width)) = op {
+                    set("width", (*width).into());
+                }
+            }
+            Step::Ndjson(direction) => set("op", direction.label().into()),
+        }
+        Value::Object(fields).to_string()
+    }
+
+    /// Read a step back from its JSON; `n` numbers it in errors
+    pub fn from_json(value: &Value, n: usize) -> Result<Self> {
+        let fields = value.as_object().ok_or_else(|| anyhow!("Step {}: not an object", n))?;
+        let op = fields.get("op").and_then(Value::as_str).ok_or_else(|| anyhow!("Step {}: no \"op\"", n))?;
+        let text = |name: &str| -> Result<String> {
+            fields
+                .get(name)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Step {} ({}): no \"{}\"", n, op, name))
+        };
+        let optional = |name: &str| -> Result<Option<String>> {
+            match fields.get(name) {
+                None => Ok(None),
+                Some(_) => text(name).map(Some),
+            }
+        };
+        let flag = |name: &str| flag(fields, name).map_err(|e| anyhow!("Step {} ({}): {}", n, op, e));
+        let path = |name: &str| -> Result<Vec<Segment>> {
+            parse_path(&text(name)?).map_err(|e| anyhow!("Step {} ({}): {}", n, op, e))
+        };
+        let node = |op: NodeOp| -> Result<Self> { Ok(Step::Node { path: path("path")?, op }) };
+        match op {
+            "delete" => node(NodeOp::Delete),
+            "move-up" => node(NodeOp::MoveUp),
+            "move-down" => node(NodeOp::MoveDown),
+            "rename" => node(NodeOp::Rename { name: text("to")?, force: flag("force")? }),
+            "wrap" => node(NodeOp::Wrap { key: optional("key")? }),
+            "unwrap" => node(NodeOp::Unwrap { splice: flag("splice")? }),
+            "convert" => {
+                let to = text("to")?;
+                let conversion = Conversion::from_name(&to).ok_or_else(|| anyhow!("Step {} (convert): no conversion to {}", n, to))?;
+                node(NodeOp::Convert(conversion))
+            }
+            "array" => {
+                let name = text("do")?;
+                match ArrayOp::from_name(&name) {
+                    Some(array) if array != ArrayOp::Count => node(NodeOp::Array(array)),
+                    _ => bail!("Step {} (array): can't replay {}", n, name),
+                }
+            }
+            "renameall" => {
+                let scope = match fields.get("under") {
+                    Some(_) => Some(path("under")?),
+                    None => None,
+                };
+                let (old, new) = (text("from")?, text("to")?);
+                if old == new {
+                    bail!("Step {} (renameall): old and new names are the same", n);
+                }
+                Ok(Step::RenameAll(RenameAll { scope, old, new, confirm: false, force: flag("force")? }))
+            }
+            "retab" => {
+                let width = match fields.get("width") {
+                    None => None,
+                    Some(width) => Some(
+                        width
+                            .as_u64()
+                            .filter(|&width| width > 0)
+                            .ok_or_else(|| anyhow!("Step {} (retab): width must be a positive number", n))? as usize,
+                    ),
+                };
+                Ok(Step::Whitespace(WhitespaceOp::Retab(width)))
+            }
+            "trimws" => Ok(Step::Whitespace(WhitespaceOp::Trim)),
+            "to-ndjson" => Ok(Step::Ndjson(Direction::ToNdjson)),
+            "from-ndjson" => Ok(Step::Ndjson(Direction::FromNdjson)),
+            _ => bail!("Step {}: unknown op \"{}\"", n, op),
+        }
+    }
+}
+
+/// The `"op"` of a node step
+fn node_op_name(op: &NodeOp) -> &'static str {
+    match op {
+        NodeOp::Delete => "delete",
+        NodeOp::MoveUp => "move-up",
+        NodeOp::MoveDown => "move-down",
+        NodeOp::Rename { .. } => "rename",
+        NodeOp::Wrap { .. } => "wrap",
+        NodeOp::Unwrap { .. } => "unwrap",
+        NodeOp::Convert(_) => "convert",
+        NodeOp::Array(_) => "array",
+        _ => "node",
+    }
+}
+
+fn whitespace_name(op: WhitespaceOp) -> &'static str {
+    match op {
+        WhitespaceOp::Retab(_) => "retab",
+        WhitespaceOp::Trim => "trimws",
+    }
+}
+
+/// An optional `true`/`false` field, false if missing
+fn flag(fields: &Map<String, Value>, name: &str) -> Result<bool> {
+    match fields.get(name) {
+        None => Ok(false),
+        Some(value) => value.as_bool().ok_or_else(|| anyhow!("\"{}\" must be true or false", name)),
+    }
+}
+
+/// Recorded steps, in the order they were made
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EditScript {
+    pub steps: Vec<Step>,
+}
+
+impl EditScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+
+    /// The script as it's saved: a header line, then one step to a line
+    pub fn to_json(&self) -> String {
+        let mut out = format!("{{\"jimscript\":{},\"steps\":[", VERSION);
+        for (i, step) in self.steps.iter().enumerate() {
+            out.push_str(if i == 0 { "\n  " } else { ",\n  " });
+            out.push_str(&step.to_json());
+        }
+        out.push_str(if self.steps.is_empty() { "]}\n" } else { "\n]}\n" });
+        out
+    }
+
+    /// Read a script, refusing one from a newer jim
+    pub fn parse(text: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(text).map_err(|e| anyhow!("Not a jim script: {}", e))?;
+        let version = value.get("jimscript").and_then(Value::as_u64);
+        if version != Some(VERSION) {
+            bail!("Not a version {} jim script", VERSION);
+        }
+        let steps = value.get("steps").and_then(Value::as_array).ok_or_else(|| anyhow!("Script has no \"steps\" list"))?;
+        let steps = steps.iter().enumerate().map(|(i, step)| Step::from_json(step, i + 1)).collect::<Result<_>>()?;
+        Ok(Self { steps })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_json()).with_context(|| format!("Can't write {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Can't read {}", path.display()))?;
+        Self::parse(&text).with_context(|| path.display().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(text: &str) -> Vec<Segment> {
+        parse_path(text).unwrap()
+    }
+
+    fn every_step() -> EditScript {
+        let node = |p: &str, op| Step::Node { path: path(p), op };
+        EditScript {
+            steps: vec![
+                node("$.debug", NodeOp::Delete),
+                node("$.users[0][\"first name\"]", NodeOp::Rename { name: "given \"name\"".to_string(), force: true }),
+                node("$.a", NodeOp::Rename { name: "b".to_string(), force: false }),
+                node("$.tags", NodeOp::Wrap { key: None }),
+                node("$.tags", NodeOp::Wrap { key: Some("list".to_string()) }),
+                node("$.one", NodeOp::Unwrap { splice: false }),
+                node("$.many", NodeOp::Unwrap { splice: true }),
+                node("$.n", NodeOp::Convert(Conversion::Stringify)),
+                node("$.flag", NodeOp::Convert(Conversion::ToggleBool)),
+                node("$.ids[0]", NodeOp::Array(ArrayOp::Sort { descending: true })),
+                node("$.ids[0]", NodeOp::Array(ArrayOp::Unique)),
+                node("$.ids[2]", NodeOp::MoveUp),
+                node("$", NodeOp::MoveDown),
+                Step::RenameAll(RenameAll { scope: None, old: "id".to_string(), new: "key".to_string(), confirm: false, force: false }),
+                Step::RenameAll(RenameAll {
+                    scope: Some(path("$.config")),
+                    old: "a b".to_string(),
+                    new: "c".to_string(),
+                    confirm: false,
+                    force: true,
+                }),
+                Step::Whitespace(WhitespaceOp::Retab(None)),
+                Step::Whitespace(WhitespaceOp::Retab(Some(4))),
+                Step::Whitespace(WhitespaceOp::Trim),
+                Step::Ndjson(Direction::ToNdjson),
+                Step::Ndjson(Direction::FromNdjson),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let script = every_step();
+        let text = script.to_json();
+        assert!(serde_json::from_str::<Value>(&text).is_ok(), "{}", text);
+        assert_eq!(EditScript::parse(&text).unwrap(), script);
+        assert_eq!(EditScript::parse(&EditScript::new().to_json()).unwrap(), EditScript::new());
+    }
+
+    #[test]
+    fn test_format() {
+        let mut script = EditScript::new();
+        script.push(Step::node(&NodeOp::Delete, path("$.debug")).unwrap());
+        script.push(Step::node(&NodeOp::Rename { name: "b".to_string(), force: false }, path("$.list[1].a")).unwrap());
+        script.push(Step::Whitespace(WhitespaceOp::Trim));
+        assert_eq!(
+            script.to_json(),
+            "{\"jimscript\":1,\"steps\":[\n  \
+             {\"op\":\"delete\",\"path\":\"$.debug\"},\n  \
+             {\"op\":\"rename\",\"path\":\"$.list[1].a\",\"to\":\"b\"},\n  \
+             {\"op\":\"trimws\"}\n]}\n"
+        );
+        assert_eq!(script.steps[1].describe(), "rename $.list[1].a to \"b\"");
+        assert_eq!(EditScript::new().to_json(), "{\"jimscript\":1,\"steps\":[]}\n");
+    }
+
+    #[test]
+    fn test_unrecordable() {
+        assert_eq!(Step::node(&NodeOp::Yank, path("$")), None);
+        assert_eq!(Step::node(&NodeOp::Paste, path("$")), None);
+        assert_eq!(Step::node(&NodeOp::Pipe("jq .".to_string()), path("$")), None);
+        assert_eq!(Step::node(&NodeOp::Array(ArrayOp::Count), path("$")), None);
+    }
+
+    #[test]
+    fn test_rejects() {
+        let err = |text: &str| EditScript::parse(text).unwrap_err().to_string();
+        assert_eq!(err("{\"jimscript\": 2, \"steps\": []}"), "Not a version 1 jim script");
+        assert_eq!(err("{\"steps\": []}"), "Not a version 1 jim script");
+        assert!(err("{\"jimscript\": 1").starts_with("Not a jim script"));
+        assert_eq!(err("{\"jimscript\": 1}"), "Script has no \"steps\" list");
+        let step = |step: &str| err(&format!("{{\"jimscript\": 1, \"steps\": [{{\"op\": \"trimws\"}}, {}]}}", step));
+        assert_eq!(step("{\"op\": \"explode\"}"), "Step 2: unknown op \"explode\"");
+        assert_eq!(step("{\"op\": \"rename\", \"path\": \"$.a\"}"), "Step 2 (rename): no \"to\"");
+        assert_eq!(step("{\"op\": \"delete\"}"), "Step 2 (delete): no \"path\"");
+        assert_eq!(step("{\"op\": \"array\", \"path\": \"$\", \"do\": \"count\"}"), "Step 2 (array): can't replay count");
+        assert_eq!(step("{\"op\": \"unwrap\", \"path\": \"$\", \"splice\": 1}"), "Step 2 (unwrap): \"splice\" must be true or false");
+        assert_eq!(step("{\"op\": \"retab\", \"width\": 0}"), "Step 2 (retab): width must be a positive number");
+        assert_eq!(step("[]"), "Step 2: not an object");
+    }
+}
@@ -0,0 +1,153 @@
+//! :set smartcomma - keeping the commas between an object's members right
+//! as lines are opened below them and deleted. Where the index is stale
+//! nothing is done, so a fixup never lands on text it hasn't read.
+
+use std::ops::Range;
+
+use super::structural::{check_complete, line_indent, member_at};
+use crate::buffer::Buffer;
+use crate::parser::{NodeKind, StructuralIndex};
+
+/// The skeleton `o` opens a line with after an object member, with the
+/// cursor going between the quotes
+pub const SKELETON: &str = "\"\": ";
+
+/// What `o` does on the last line of an object member
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenMember {
+    /// Where a comma goes after the member, when it has none
+    pub comma: Option<usize>,
+    /// End of the line, before its line break
+    pub line_end: usize,
+    /// The member's indentation, for the new line
+    pub indent: String,
+}
+
+/// The fixups for opening a line below `line` when it ends an object
+/// member, e.g. `  "id": 3` or the `}` of a nested object. None when it
+/// doesn't, or when anything there has been edited since indexing.
+pub fn open_member(index: &StructuralIndex, buffer: &Buffer, line: usize) -> Option<OpenMember> {
+    let line_start = buffer.line_to_byte_offset(line);
+    let text = buffer.get_line(line);
+    let line_end = line_start + text.trim_end_matches(['\n', '\r']).len();
+    let content = text.trim_end();
+    let (content, has_comma) = match content.strip_suffix(',') {
+        Some(content) => (content.trim_end(), true),
+        None => (content, false),
+    };
+    if content.trim_start().is_empty() {
+        return None;
+    }
+    let last = line_start + content.len();
+    let member = member_at(index, last - 1)?;
+    let object = index.parent(member.value)?;
+    check_complete(index, object).ok()?;
+    if member.key.is_none() || member.span.end != last || index.touches_dirty(member.span.start..line_end) {
+        return None;
+    }
+    // A comma that's on a later line, before the next member, would end up
+    // after the new one
+    let has_next = index.next_sibling(member.value).is_some();
+    if has_next && !has_comma {
+        return None;
+    }
+    Some(OpenMember {
+        comma: (!has_comma).then_some(last),
+        line_end,
+        indent: line_indent(buffer, member.span.start),
+    })
+}
+
+/// The comma to take out with the whole lines of `range`, when they hold
+/// the last members of an object: the one after what becomes its last
+/// member, which nothing follows any more
+pub fn dangling_comma(index: &StructuralIndex, buffer: &Buffer, range: Range<usize>) -> Option<usize> {
+    let close = next_non_space(buffer, range.end)?;
+    if buffer.slice(close..close + 1) != "}" {
+        return None;
+    }
+    let object = index.node_id_at(close)?;
+    let node = index.get(object)?;
+    if node.kind != NodeKind::Object || node.end != close + 1 || !index.is_closed(object) {
+        return None;
+    }
+    // Back from the last member to the first one that ends before the range
+    let mut value = index.last_child(object)?;
+    let mut first_deleted = None;
+    let kept = loop {
+        let key = index.prev_sibling(value)?;
+        if index.get(value)?.end <= range.start {
+            break value;
+        }
+        first_deleted = Some(key);
+        value = index.prev_sibling(key)?;
+    };
+    // Only when the members taken out go whole
+    if index.get(first_deleted?)?.start < range.start {
+        return None;
+    }
+    let kept_end = index.get(kept)?.end;
+    let comma = next_non_space(buffer, kept_end)?;
+    if comma >= range.start || buffer.slice(comma..comma + 1) != "," || index.touches_dirty(kept_end..close + 1) {
+        return None;
+    }
+    Some(comma)
+}
+
+/// The first byte from `offset` that isn't whitespace, looking no further
+/// than a line past the one it's on
+fn next_non_space(buffer: &Buffer, offset: usize) -> Option<usize> {
+    let line = buffer.byte_offset_to_line(offset);
+    let end = buffer.line_to_byte_offset(line + 2).min(buffer.len_bytes());
+    let text = buffer.slice(offset..end);
+    text.find(|c: char| !c.is_whitespace()).map(|i| offset + i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    fn index_of(text: &str) -> StructuralIndex {
+        StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all())
+    }
+
+    #[test]
+    fn test_open_member() {
+        let text = "{\n  \"a\": 1,\n  \"b\": {\n    \"c\": 2\n  }\n}\n";
+        let (index, buffer) = (index_of(text), Buffer::from_text(text));
+        let after = |s: &str| text.find(s).unwrap() + s.len();
+        assert_eq!(
+            open_member(&index, &buffer, 1),
+            Some(OpenMember { comma: None, line_end: after("1,"), indent: "  ".to_string() })
+        );
+        assert_eq!(
+            open_member(&index, &buffer, 3),
+            Some(OpenMember { comma: Some(after("2")), line_end: after("2"), indent: "    ".to_string() })
+        );
+        assert_eq!(open_member(&index, &buffer, 4).unwrap().comma, Some(after("  }")));
+        // Not the end of a member
+        assert_eq!(open_member(&index, &buffer, 0), None);
+        assert_eq!(open_member(&index, &buffer, 2), None);
+        assert_eq!(open_member(&index, &buffer, 5), None);
+
+        // An array element isn't a member, and a comma on the next line is left be
+        assert_eq!(open_member(&index_of("[\n  1\n]"), &Buffer::from_text("[\n  1\n]"), 1), None);
+        let text = "{\n  \"a\": 1\n  , \"b\": 2\n}";
+        assert_eq!(open_member(&index_of(text), &Buffer::from_text(text), 1), None);
+    }
+
+    #[test]
+    fn test_dangling_comma() {
+        let text = "{\n  \"a\": 1,\n  \"b\": 2,\n  \"c\": [\n    3\n  ]\n}\n";
+        let (index, buffer) = (index_of(text), Buffer::from_text(text));
+        let lines = |from: usize, to: usize| buffer.line_to_byte_offset(from)..buffer.line_to_byte_offset(to + 1);
+        let comma = |s: &str| text.find(s).unwrap() + s.len() - 1;
+        assert_eq!(dangling_comma(&index, &buffer, lines(3, 5)), Some(comma("2,")));
+        assert_eq!(dangling_comma(&index, &buffer, lines(2, 5)), Some(comma("1,")));
+        // Members still follow, part of one goes, or nothing's left
+        assert_eq!(dangling_comma(&index, &buffer, lines(2, 2)), None);
+        assert_eq!(dangling_comma(&index, &buffer, lines(4, 5)), None);
+        assert_eq!(dangling_comma(&index, &buffer, lines(1, 5)), None);
+    }
+}
@@ -0,0 +1,727 @@
+//! Whole-node editing. Deleting or pasting an element takes the comma and
+//! indentation fixups that keep the document valid along with it.
+
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+
+use super::{Edit, EditOperations};
+use crate::buffer::cursor::Cursor;
+use crate::buffer::Buffer;
+use crate::mode::StructuralNavAction;
+use crate::parser::node::NodeId;
+use crate::parser::decode;
+use crate::parser::token::TokenKind;
+use crate::parser::{NodeKind, StructuralIndex, Tokenizer};
+
+/// A node together with its key when it's an object member
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub key: Option<NodeId>,
+    pub value: NodeId,
+    /// From the start of the key (or value) to the end of the value
+    pub span: Range<usize>,
+}
+
+/// The member under the cursor: the innermost node there, with its key. On
+/// a key, the member is the key and its value.
+pub fn member_at(index: &StructuralIndex, offset: usize) -> Option<Member> {
+    member_of(index, index.node_id_at(offset)?)
+}
+
+fn member_of(index: &StructuralIndex, node: NodeId) -> Option<Member> {
+    let in_object = index.parent(node).and_then(|p| index.kind(p)) == Some(NodeKind::Object);
+    let (key, value) = if index.is_key_node(node) {
+        (Some(node), index.next_sibling(node)?)
+    } else if in_object {
+        (Some(index.prev_sibling(node)?), node)
+    } else {
+        (None, node)
+    };
+    let start = index.get(key.unwrap_or(value))?.start;
+    Some(Member { key, value, span: start..index.get(value)?.end })
+}
+
+/// The members before and after `member` in its container
+fn neighbours(index: &StructuralIndex, member: &Member) -> (Option<Member>, Option<Member>) {
+    let first = member.key.unwrap_or(member.value);
+    let prev = index.prev_sibling(first).and_then(|id| member_of(index, id));
+    let next = index.next_sibling(member.value).and_then(|id| member_of(index, id));
+    (prev, next)
+}
+
+/// Refuse to edit around containers whose end hasn't been indexed yet
+pub(crate) fn check_complete(index: &StructuralIndex, node: NodeId) -> Result<()> {
+    let container = matches!(index.kind(node), Some(NodeKind::Object | NodeKind::Array));
+    if container && !index.is_closed(node) {
+        bail!("Node isn't fully indexed yet");
+    }
+    Ok(())
+}
+
+/// The bytes to delete to take `member` out of its container: up to the
+/// next member when there is one, else back to the end of the previous one
+/// so its comma goes too, else everything between the brackets
+pub fn deletion_range(index: &StructuralIndex, member: &Member) -> Option<Range<usize>> {
+    run_deletion_range(index, member, member)
+}
+
+/// `deletion_range` for the members `first` through `last` of one container
+fn run_deletion_range(index: &StructuralIndex, first: &Member, last: &Member) -> Option<Range<usize>> {
+    let Some(parent) = index.parent(first.value) else {
+        return Some(first.span.start..last.span.end);
+    };
+    match (neighbours(index, first).0, neighbours(index, last).1) {
+        (_, Some(next)) => Some(first.span.start..next.span.start),
+        (Some(prev), None) => Some(prev.span.end..last.span.end),
+        (None, None) => {
+            let container = index.get(parent)?;
+            Some(container.start + 1..container.end - 1)
+        }
+    }
+}
+
+/// What a structural motion after an operator covers from the member at
+/// `offset`: it and the members `count` along for Ctrl-j and Ctrl-k, or the
+/// container (Ctrl-h) or first member (Ctrl-l) it moves to. Keys come with
+/// their values, so the span is always of whole members.
+pub fn motion_span(index: &StructuralIndex, offset: usize, action: StructuralNavAction, count: usize) -> Result<Range<usize>> {
+    let Some(current) = member_at(index, offset) else {
+        bail!("No node under cursor");
+    };
+    let mut target: Option<Member> = None;
+    for _ in 0..count.max(1) {
+        let from = target.as_ref().unwrap_or(&current);
+        let next = match action {
+            StructuralNavAction::NextSibling => neighbours(index, from).1,
+            StructuralNavAction::PrevSibling => neighbours(index, from).0,
+            StructuralNavAction::Parent => index.parent(from.value).and_then(|id| member_of(index, id)),
+            StructuralNavAction::FirstChild => index.first_child(from.value).and_then(|id| member_of(index, id)),
+            _ => bail!("Not a motion an operator takes"),
+        };
+        match next {
+            Some(next) => target = Some(next),
+            None => break,
+        }
+    }
+    let Some(target) = target else {
+        bail!("No node to move to");
+    };
+    let span = match action {
+        StructuralNavAction::NextSibling | StructuralNavAction::PrevSibling => {
+            current.span.start.min(target.span.start)..current.span.end.max(target.span.end)
+        }
+        _ => target.span.clone(),
+    };
+    check_complete(index, current.value)?;
+    check_complete(index, target.value)?;
+    if index.touches_dirty(span.clone()) {
+        bail!("Edited there since indexing; try again once it's reindexed");
+    }
+    Ok(span)
+}
+
+/// The bytes d takes for a `motion_span`: the members in it along with the
+/// separators `deletion_range` would take for one
+pub fn span_deletion_range(index: &StructuralIndex, span: Range<usize>) -> Result<Range<usize>> {
+    let members = member_at(index, span.start).zip(span.end.checked_sub(1).and_then(|last| member_at(index, last)));
+    let Some((first, last)) = members else {
+        bail!("No node under cursor");
+    };
+    if let Some(parent) = index.parent(first.value) {
+        check_complete(index, parent)?;
+    }
+    match run_deletion_range(index, &first, &last) {
+        Some(range) if index.touches_dirty(range.clone()) => bail!("Edited there since indexing; try again once it's reindexed"),
+        Some(range) => Ok(range),
+        None => bail!("No node under cursor"),
+    }
+}
+
+/// Leading whitespace of the line `offset` is on
+pub(crate) fn line_indent(buffer: &Buffer, offset: usize) -> String {
+    let line = buffer.get_line(buffer.byte_offset_to_line(offset));
+    line.chars().take_while(|c| *c == ' ' || *c == '\t').collect()
+}
+
+/// Take `indent` off the start of every line after the first, so the text
+/// can be indented afresh wherever it's pasted
+fn dedent(text: &str, indent: &str) -> String {
+    let mut lines = text.split('\n');
+    let mut out = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        out.push('\n');
+        out.push_str(line.strip_prefix(indent).unwrap_or_else(|| line.trim_start_matches([' ', '\t'])));
+    }
+    out
+}
+
+fn reindent(text: &str, indent: &str) -> String {
+    text.replace('\n', &format!("\n{}", indent))
+}
+
+/// The text of the member under the cursor, for a register
+pub fn yank(buffer: &Buffer, index: &StructuralIndex, offset: usize) -> Result<String> {
+    let Some(member) = member_at(index, offset) else {
+        bail!("No node under cursor");
+    };
+    check_complete(index, member.value)?;
+    let indent = line_indent(buffer, member.span.start);
+    Ok(dedent(&buffer.slice(member.span), &indent))
+}
+
+/// Delete the member under the cursor and its separator, returning the
+/// edit and the member's text
+pub fn delete(buffer: &mut Buffer, cursor: &mut Cursor, index: &StructuralIndex) -> Result<(Edit, String)> {
+    let text = yank(buffer, index, cursor.byte_offset)?;
+    let Some(member) = member_at(index, cursor.byte_offset) else {
+        bail!("No node under cursor");
+    };
+    if let Some(parent) = index.parent(member.value) {
+        check_complete(index, parent)?;
+    }
+    let Some(range) = deletion_range(index, &member) else {
+        bail!("No node under cursor");
+    };
+    let edit = EditOperations::delete(buffer, cursor, range.start, range.end)?;
+    Ok((edit, text))
+}
+
+/// A key and value (`"a": 1`), rather than a bare value. Returns where the
+/// value starts.
+fn value_start(text: &str) -> Option<usize> {
+    let mut tokens = Tokenizer::new(text.to_string())
+        .tokenize_all()
+        .into_iter()
+        .filter(|t| t.kind != TokenKind::Whitespace);
+    let (key, colon, value) = (tokens.next()?, tokens.next()?, tokens.next()?);
+    (key.kind == TokenKind::String && colon.kind == TokenKind::Colon).then_some(value.start)
+}
+
+/// Paste `text` as a new member after the one under the cursor, with a
+/// comma and the same indentation; on an empty container, as its only
+/// member. A key is dropped when pasting into an array, and a bare value
+/// can't go into an object. The cursor ends on the pasted member.
+pub fn paste(buffer: &mut Buffer, cursor: &mut Cursor, index: &StructuralIndex, text: &str) -> Result<Edit> {
+    let text = text.trim();
+    let Some(node) = index.node_id_at(cursor.byte_offset) else {
+        bail!("No node under cursor");
+    };
+    let empty = index.child_count(node).is_some_and(|count| count.count == 0);
+    let (container, insert_at, separator, indent) = if empty {
+        check_complete(index, node)?;
+        (node, index.get(node).map_or(0, |n| n.start + 1), String::new(), String::new())
+    } else {
+        let Some(member) = member_of(index, node) else {
+            bail!("No node under cursor");
+        };
+        let Some(container) = index.parent(member.value) else {
+            bail!("Can't paste beside a top-level value");
+        };
+        // Lay the new member out like this one: on its own line, or not
+        let before = match neighbours(index, &member) {
+            (Some(prev), _) => prev.span.end,
+            (None, _) => index.get(container).map_or(0, |n| n.start),
+        };
+        let indent = line_indent(buffer, member.span.start);
+        let separator = if buffer.slice(before..member.span.start).contains('\n') {
+            format!(",\n{}", indent)
+        } else {
+            ", ".to_string()
+        };
+        (container, member.span.end, separator, indent)
+    };
+
+    let text = match (index.kind(container), value_start(text)) {
+        (Some(NodeKind::Object), None) => bail!("Only a key and value can be pasted into an object"),
+        (Some(NodeKind::Array), Some(start)) => &text[start..],
+        _ => text,
+    };
+    let inserted = format!("{}{}", separator, reindent(text, &indent));
+    let edit = EditOperations::insert(buffer, cursor, insert_at, &inserted)?;
+    cursor.move_to_offset(buffer, insert_at + separator.len());
+    Ok(Edit { cursor_after: (&*cursor).into(), ..edit })
+}
+
+/// Swap the member under the cursor with the next one, or the previous,
+/// keeping the separator between them where it was. The cursor moves with
+/// the member. Returns the deletion and insertion making up the swap.
+pub fn swap(buffer: &mut Buffer, cursor: &mut Cursor, index: &StructuralIndex, down: bool) -> Result<Vec<Edit>> {
+    let Some(member) = member_at(index, cursor.byte_offset) else {
+        bail!("No node under cursor");
+    };
+    let Some(parent) = index.parent(member.value) else {
+        bail!("A top-level value has no siblings");
+    };
+    check_complete(index, parent)?;
+    let (first, second) = match (neighbours(index, &member), down) {
+        ((_, Some(next)), true) => (member.clone(), next),
+        ((Some(prev), _), false) => (prev, member.clone()),
+        (_, true) => bail!("Already the last member"),
+        (_, false) => bail!("Already the first member"),
+    };
+    let within = cursor.byte_offset - member.span.start;
+    let (first_text, between, second_text) = (
+        buffer.slice(first.span.clone()),
+        buffer.slice(first.span.end..second.span.start),
+        buffer.slice(second.span.clone()),
+    );
+    let moved_to = if down { first.span.start + second_text.len() + between.len() } else { first.span.start };
+
+    let swapped = format!("{}{}{}", second_text, between, first_text);
+    replace(buffer, cursor, first.span.start..second.span.end, &swapped, moved_to + within)
+}
+
+/// Replace `range` with `text` as a deletion then an insertion, leaving the
+/// cursor at `cursor_to`
+pub(crate) fn replace(buffer: &mut Buffer, cursor: &mut Cursor, range: Range<usize>, text: &str, cursor_to: usize) -> Result<Vec<Edit>> {
+    let delete = EditOperations::delete(buffer, cursor, range.start, range.end)?;
+    let insert = EditOperations::insert(buffer, cursor, range.start, text)?;
+    cursor.move_to_offset(buffer, cursor_to);
+    Ok(vec![delete, Edit { cursor_after: (&*cursor).into(), ..insert }])
+}
+
+/// Give the member under the cursor (on its key or its value) the key
+/// `name`, quoted and escaped. Unless `force`d, refuses a name another
+/// member of the object already has. The cursor stays on the character it
+/// was on, or on the key's opening quote if it was on the key.
+pub fn rename(buffer: &mut Buffer, cursor: &mut Cursor, index: &StructuralIndex, name: &str, force: bool) -> Result<Vec<Edit>> {
+    let Some(key) = member_at(index, cursor.byte_offset).and_then(|member| member.key) else {
+        bail!("Not on an object member");
+    };
+    let Some(span) = index.get(key).map(|node| node.start..node.end) else {
+        bail!("Not on an object member");
+    };
+    if let Some(object) = index.parent(key).filter(|_| !force) {
+        check_complete(index, object)?;
+        let taken = index.member_iter(object)
+            .map(|(key, _)| key)
+            .filter(|&other| other != key)
+            .filter_map(|other| index.get(other))
+            .any(|other| decode::decode_string(&buffer.slice(other.start..other.end)).ok().as_deref() == Some(name));
+        if taken {
+            bail!("Key \"{}\" already exists (add ! to override)", name);
+        }
+    }
+
+    let quoted = decode::encode_string(name);
+    let cursor_to = match cursor.byte_offset {
+        offset if offset >= span.end => offset - span.len() + quoted.len(),
+        offset if offset >= span.start => span.start,
+        offset => offset,
+    };
+    replace(buffer, cursor, span, &quoted, cursor_to)
+}
+
+/// Surround the value under the cursor (not its key) with a new array, or
+/// with an object under `key`. A value spanning lines goes on a line of its
+/// own, indented `shiftwidth` further.
+pub fn wrap(
+    buffer: &mut Buffer,
+    cursor: &mut Cursor,
+    index: &StructuralIndex,
+    key: Option<&str>,
+    shiftwidth: usize,
+) -> Result<Vec<Edit>> {
+    let Some(member) = member_at(index, cursor.byte_offset) else {
+        bail!("No node under cursor");
+    };
+    check_complete(index, member.value)?;
+    let Some(value) = index.get(member.value) else {
+        bail!("No node under cursor");
+    };
+    let text = buffer.slice(value.start..value.end);
+    let (open, key, close) = match key {
+        Some(key) => ('{', format!("{}: ", decode::encode_string(key)), '}'),
+        None => ('[', String::new(), ']'),
+    };
+    let wrapped = if text.contains('\n') {
+        let indent = line_indent(buffer, member.span.start);
+        let shift = " ".repeat(shiftwidth);
+        format!("{}\n{}{}{}{}\n{}{}", open, indent, shift, key, reindent(&text, &shift), indent, close)
+    } else {
+        format!("{}{}{}{}", open, key, text, close)
+    };
+    replace(buffer, cursor, value.start..value.end, &wrapped, value.start)
+}
+
+/// Replace the container under the cursor (or around the scalar under it)
+/// with its one member's value. With more members, `splice` moves them all
+/// into the parent instead: an array's elements into the parent array, an
+/// object's members into the parent object.
+pub fn unwrap(buffer: &mut Buffer, cursor: &mut Cursor, index: &StructuralIndex, splice: bool) -> Result<Vec<Edit>> {
+    let Some(node) = index.node_id_at(cursor.byte_offset) else {
+        bail!("No node under cursor");
+    };
+    let container = match index.kind(node) {
+        Some(NodeKind::Object | NodeKind::Array) if !index.is_key_node(node) => Some(node),
+        _ => index.parent(node),
+    };
+    let Some((container, kind)) = container.and_then(|id| Some((id, index.kind(id)?))) else {
+        bail!("Not in a container");
+    };
+    check_complete(index, container)?;
+    let Some(outer) = member_of(index, container) else {
+        bail!("Not in a container");
+    };
+    let members: Vec<Member> = index.child_iter(container)
+        .filter(|&child| kind == NodeKind::Array || index.is_key_node(child))
+        .filter_map(|child| member_of(index, child))
+        .collect();
+    let (Some(first), Some(last)) = (members.first(), members.last()) else {
+        bail!("Nothing to unwrap");
+    };
+
+    let (range, inner) = if members.len() == 1 && !splice {
+        // The container (not its key) becomes its member's value
+        let value = index.get(first.value).map_or(first.span.clone(), |n| n.start..n.end);
+        let range = index.get(container).map_or(outer.span.clone(), |n| n.start..n.end);
+        (range, value)
+    } else {
+        let parent_kind = index.parent(container).and_then(|p| index.kind(p));
+        if !splice {
+            bail!("Container has {} members (use :unwrap! to splice them into the parent)", members.len());
+        }
+        if parent_kind != Some(kind) {
+            bail!("Can only splice into a container of the same kind");
+        }
+        // The container and its key become its members
+        (outer.span.clone(), first.span.start..last.span.end)
+    };
+    let text = dedent(&buffer.slice(inner.clone()), &line_indent(buffer, inner.start));
+    let text = reindent(&text, &line_indent(buffer, range.start));
+    replace(buffer, cursor, range.clone(), &text, range.start)
+}
+
+/// Ways to rewrite a value as another type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conversion {
+    /// gts: `42` → `"42"`
+    Stringify,
+    /// gtu: `"42"` → `42`, when the string holds a JSON scalar
+    Unstringify,
+    /// gtb: `true` ↔ `false`
+    ToggleBool,
+    /// gtn: anything → `null`
+    Null,
+}
+
+impl Conversion {
+    /// The :convert argument naming it
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "string" => Some(Conversion::Stringify),
+            "unstring" => Some(Conversion::Unstringify),
+            "bool" => Some(Conversion::ToggleBool),
+            "null" => Some(Conversion::Null),
+            _ => None,
+        }
+    }
+
+    /// The name `from_name` reads back
+    pub fn name(self) -> &'static str {
+        match self {
+            Conversion::Stringify => "string",
+            Conversion::Unstringify => "unstring",
+            Conversion::ToggleBool => "bool",
+            Conversion::Null => "null",
+        }
+    }
+}
+
+/// Rewrite the value under the cursor (or after the key under it),
+/// returning the edit and the text replaced. Only `Null` applies to
+/// containers. The cursor ends on the new value.
+pub fn convert(buffer: &mut Buffer, cursor: &mut Cursor, index: &StructuralIndex, conversion: Conversion) -> Result<(Edit, String)> {
+    let Some(member) = member_at(index, cursor.byte_offset) else {
+        bail!("No value under cursor");
+    };
+    let Some(value) = index.get(member.value) else {
+        bail!("No value under cursor");
+    };
+    check_complete(index, member.value)?;
+    let text = buffer.slice(value.start..value.end);
+    let converted = match (conversion, value.kind) {
+        (Conversion::Null, _) => "null".to_string(),
+        (_, NodeKind::Object | NodeKind::Array) => bail!("Can't convert a container"),
+        (Conversion::Stringify, NodeKind::String) => bail!("Already a string"),
+        (Conversion::Stringify, _) => decode::encode_string(&text),
+        (Conversion::Unstringify, NodeKind::String) => {
+            let content = decode::decode_string(&text).unwrap_or_default();
+            if !is_scalar(&content) {
+                bail!("Not a JSON value: {}", text);
+            }
+            content
+        }
+        (Conversion::Unstringify, _) => bail!("Not a string"),
+        (Conversion::ToggleBool, NodeKind::Boolean) => if text == "true" { "false" } else { "true" }.to_string(),
+        (Conversion::ToggleBool, _) => bail!("Not true or false"),
+    };
+    let edit = EditOperations::replace(buffer, cursor, value.start, value.end, &converted)?;
+    cursor.move_to_offset(buffer, value.start);
+    Ok((Edit { cursor_after: (&*cursor).into(), ..edit }, text))
+}
+
+/// The string under the cursor, key or value: its span and the text it
+/// stands for
+pub fn string_at(buffer: &Buffer, index: &StructuralIndex, offset: usize) -> Result<(Range<usize>, String)> {
+    let node = index.node_id_at(offset).and_then(|id| index.get(id));
+    let Some(node) = node.filter(|node| matches!(node.kind, NodeKind::String | NodeKind::Key)) else {
+        bail!("Not on a string");
+    };
+    let literal = buffer.slice(node.start..node.end);
+    let Some(text) = decode::decode_string(&literal).ok() else {
+        bail!("Malformed string: {}", literal);
+    };
+    Ok((node.start..node.end, text))
+}
+
+/// Put `value`, escaped, in place of the string literal at `span`, leaving
+/// the cursor on its opening quote
+pub fn replace_string(buffer: &mut Buffer, cursor: &mut Cursor, span: Range<usize>, value: &str) -> Result<Vec<Edit>> {
+    let start = span.start;
+    replace(buffer, cursor, span, &decode::encode_string(value), start)
+}
+
+/// Whether `text` is exactly one well-formed JSON scalar
+fn is_scalar(text: &str) -> bool {
+    match Tokenizer::new(text.to_string()).tokenize_all().as_slice() {
+        [token] => {
+            let scalar = matches!(
+                token.kind,
+                TokenKind::String | TokenKind::Number | TokenKind::True | TokenKind::False | TokenKind::Null
+            );
+            scalar && token.issue == crate::parser::TokenIssue::None && token.start == 0 && token.end == text.len()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_of(buffer: &Buffer) -> StructuralIndex {
+        let text = buffer.slice(0..buffer.len_bytes());
+        StructuralIndex::from_tokens(&Tokenizer::new(text).tokenize_all())
+    }
+
+    /// Delete the member at the first `at` in `text`: the document left and
+    /// the text taken
+    fn delete_at(text: &str, at: &str) -> (String, String) {
+        let mut buffer = Buffer::from_text(text);
+        let mut cursor = Cursor::new();
+        cursor.move_to_offset(&buffer, text.find(at).unwrap());
+        let index = index_of(&buffer);
+        let (_, taken) = delete(&mut buffer, &mut cursor, &index).unwrap();
+        (buffer.slice(0..buffer.len_bytes()), taken)
+    }
+
+    fn paste_at(text: &str, at: &str, pasted: &str) -> Result<String> {
+        let mut buffer = Buffer::from_text(text);
+        let mut cursor = Cursor::new();
+        cursor.move_to_offset(&buffer, text.find(at).unwrap());
+        let index = index_of(&buffer);
+        paste(&mut buffer, &mut cursor, &index, pasted)?;
+        Ok(buffer.slice(0..buffer.len_bytes()))
+    }
+
+    #[test]
+    fn test_delete_positions() {
+        let array = "[\n  1,\n  [2, 3],\n  4\n]";
+        assert_eq!(delete_at(array, "1"), ("[\n  [2, 3],\n  4\n]".to_string(), "1".to_string()));
+        assert_eq!(delete_at(array, "[2").0, "[\n  1,\n  4\n]");
+        assert_eq!(delete_at(array, "4").0, "[\n  1,\n  [2, 3]\n]");
+        assert_eq!(delete_at("[ 7 ]", "7").0, "[]");
+
+        let object = "{\n  \"a\": 1,\n  \"b\": {\n    \"c\": true\n  },\n  \"d\": null\n}";
+        let (left, taken) = delete_at(object, "\"b\"");
+        assert_eq!(left, "{\n  \"a\": 1,\n  \"d\": null\n}");
+        assert_eq!(taken, "\"b\": {\n  \"c\": true\n}");
+        // On the value deletes the key too
+        assert_eq!(delete_at(object, "1").0, "{\n  \"b\": {\n    \"c\": true\n  },\n  \"d\": null\n}");
+        assert_eq!(delete_at(object, "null").0, "{\n  \"a\": 1,\n  \"b\": {\n    \"c\": true\n  }\n}");
+        assert_eq!(delete_at(object, "true").0, "{\n  \"a\": 1,\n  \"b\": {},\n  \"d\": null\n}");
+        assert_eq!(delete_at(r#"{"only": 1}"#, "only").0, "{}");
+    }
+
+    #[test]
+    fn test_paste_positions() {
+        let array = "[\n  1,\n  2\n]";
+        assert_eq!(paste_at(array, "1", "9").unwrap(), "[\n  1,\n  9,\n  2\n]");
+        assert_eq!(paste_at(array, "2", "9").unwrap(), "[\n  1,\n  2,\n  9\n]");
+        assert_eq!(paste_at("[1, 2]", "2", "[3,\n4]").unwrap(), "[1, 2, [3,\n4]]");
+        assert_eq!(paste_at("[]", "[", "9").unwrap(), "[9]");
+        // A member's key is dropped in an array
+        assert_eq!(paste_at("[1]", "1", r#""k": 9"#).unwrap(), "[1, 9]");
+
+        let object = "{\n  \"a\": 1\n}";
+        assert_eq!(paste_at(object, "a", "\"k\": {\n  \"x\": 2\n}").unwrap(), "{\n  \"a\": 1,\n  \"k\": {\n    \"x\": 2\n  }\n}");
+        assert_eq!(paste_at(r#"{"a": {}}"#, "{}", r#""k": 9"#).unwrap(), r#"{"a": {"k": 9}}"#);
+        assert!(paste_at(object, "a", "9").is_err());
+        assert!(paste_at("[1]", "[", "9").is_err());
+
+        // The cursor ends on the pasted member
+        let mut buffer = Buffer::from_text(array);
+        let mut cursor = Cursor::new();
+        cursor.move_to_offset(&buffer, 4);
+        let index = index_of(&buffer);
+        paste(&mut buffer, &mut cursor, &index, "9").unwrap();
+        assert_eq!((cursor.byte_offset, cursor.line, cursor.col), (9, 2, 2));
+    }
+
+    #[test]
+    fn test_swap_siblings() {
+        // Moves `at` by the given steps, reindexing between them like the
+        // editor does; the document after, and the text under the cursor
+        fn moves(text: &str, at: &str, steps: &[bool]) -> Result<(String, String)> {
+            let mut buffer = Buffer::from_text(text);
+            let mut cursor = Cursor::new();
+            cursor.move_to_offset(&buffer, text.find(at).unwrap());
+            for &down in steps {
+                let index = index_of(&buffer);
+                swap(&mut buffer, &mut cursor, &index, down)?;
+            }
+            let rest = buffer.slice(cursor.byte_offset..buffer.len_bytes());
+            Ok((buffer.slice(0..buffer.len_bytes()), rest.chars().take(at.len()).collect()))
+        }
+        let doc = |result: Result<(String, String)>| result.unwrap().0;
+
+        let array = "[\n  1,\n  [2, 3],\n  4\n]";
+        assert_eq!(moves(array, "1", &[true]).unwrap(), ("[\n  [2, 3],\n  1,\n  4\n]".to_string(), "1".to_string()));
+        assert_eq!(moves(array, "1", &[true, true]).unwrap(), ("[\n  [2, 3],\n  4,\n  1\n]".to_string(), "1".to_string()));
+        assert_eq!(doc(moves(array, "4", &[false])), "[\n  1,\n  4,\n  [2, 3]\n]");
+        // The cursor stays on the same character of the moved node
+        assert_eq!(moves(array, ", 3]", &[false]).unwrap(), ("[\n  [2, 3],\n  1,\n  4\n]".to_string(), ", 3]".to_string()));
+        assert_eq!(doc(moves(array, "3]", &[false])), "[\n  1,\n  [3, 2],\n  4\n]");
+        assert_eq!(moves(array, "4", &[true]).unwrap_err().to_string(), "Already the last member");
+        assert_eq!(moves(array, "1", &[false]).unwrap_err().to_string(), "Already the first member");
+        assert!(moves("[1]", "1", &[true]).is_err());
+
+        let object = r#"{"a": 1, "b": {"c": [true]}, "d": null}"#;
+        assert!(moves(object, "true", &[false]).is_err());
+        assert_eq!(doc(moves(object, "{\"c", &[true])), r#"{"a": 1, "d": null, "b": {"c": [true]}}"#);
+        assert_eq!(doc(moves(object, "\"a\"", &[true, true, false])), r#"{"b": {"c": [true]}, "a": 1, "d": null}"#);
+    }
+
+    #[test]
+    fn test_rename() {
+        fn rename_at(text: &str, at: &str, name: &str, force: bool) -> Result<(String, String)> {
+            let mut buffer = Buffer::from_text(text);
+            let mut cursor = Cursor::new();
+            cursor.move_to_offset(&buffer, text.find(at).unwrap());
+            let index = index_of(&buffer);
+            rename(&mut buffer, &mut cursor, &index, name, force)?;
+            let rest = buffer.slice(cursor.byte_offset..buffer.len_bytes());
+            Ok((buffer.slice(0..buffer.len_bytes()), rest.chars().take(4).collect()))
+        }
+
+        let text = "{\"a\":  1, \"say \\\"hi\\\"\": [2] }";
+        // On the key, and on the value; the rest of the line is untouched
+        assert_eq!(rename_at(text, "\"a\"", "id", false).unwrap(), ("{\"id\":  1, \"say \\\"hi\\\"\": [2] }".to_string(), "\"id\"".to_string()));
+        assert_eq!(rename_at(text, "[2]", "list", false).unwrap(), ("{\"a\":  1, \"list\": [2] }".to_string(), "[2] ".to_string()));
+        // Quotes, backslashes and control characters are escaped
+        assert_eq!(rename_at(text, "1", "x\"\\\ty", false).unwrap().0, "{\"x\\\"\\\\\\ty\":  1, \"say \\\"hi\\\"\": [2] }");
+        // Names are compared unescaped
+        let err = rename_at(text, "1", "say \"hi\"", false).unwrap_err();
+        assert_eq!(err.to_string(), "Key \"say \"hi\"\" already exists (add ! to override)");
+        assert!(rename_at(text, "1", "say \"hi\"", true).is_ok());
+        // Renaming a key to itself is fine
+        assert!(rename_at(text, "1", "a", false).is_ok());
+        assert!(rename_at("[1, 2]", "2", "b", false).is_err());
+    }
+
+    fn edit_at(text: &str, at: &str, edit: impl Fn(&mut Buffer, &mut Cursor, &StructuralIndex) -> Result<Vec<Edit>>) -> Result<String> {
+        let mut buffer = Buffer::from_text(text);
+        let mut cursor = Cursor::new();
+        cursor.move_to_offset(&buffer, text.find(at).unwrap());
+        let index = index_of(&buffer);
+        edit(&mut buffer, &mut cursor, &index)?;
+        Ok(buffer.slice(0..buffer.len_bytes()))
+    }
+
+    #[test]
+    fn test_wrap() {
+        let wrap_at = |text, at, key| edit_at(text, at, |b, c, i| wrap(b, c, i, key, 2)).unwrap();
+        assert_eq!(wrap_at("[1, 2]", "2", None), "[1, [2]]");
+        assert_eq!(wrap_at(r#"{"a": 1}"#, "a", Some("n\"b")), r#"{"a": {"n\"b": 1}}"#);
+        let text = "{\n  \"a\": {\n    \"x\": 1\n  }\n}";
+        assert_eq!(wrap_at(text, "{\n    ", None), "{\n  \"a\": [\n    {\n      \"x\": 1\n    }\n  ]\n}");
+        assert_eq!(wrap_at(text, "\"a", Some("k")), "{\n  \"a\": {\n    \"k\": {\n      \"x\": 1\n    }\n  }\n}");
+    }
+
+    #[test]
+    fn test_unwrap() {
+        let unwrap_at = |text, at, splice| edit_at(text, at, |b, c, i| unwrap(b, c, i, splice));
+        // On the container or on its member
+        assert_eq!(unwrap_at("[1, [2], 3]", "[2", false).unwrap(), "[1, 2, 3]");
+        assert_eq!(unwrap_at("[1, [2], 3]", "2", false).unwrap(), "[1, 2, 3]");
+        assert_eq!(unwrap_at(r#"{"a": {"b": true}}"#, "true", false).unwrap(), r#"{"a": true}"#);
+        let text = "{\n  \"a\": [\n    {\n      \"x\": 1\n    }\n  ]\n}";
+        assert_eq!(unwrap_at(text, "[", false).unwrap(), "{\n  \"a\": {\n    \"x\": 1\n  }\n}");
+
+        let err = unwrap_at("[0, [1, 2]]", "1", false).unwrap_err();
+        assert_eq!(err.to_string(), "Container has 2 members (use :unwrap! to splice them into the parent)");
+        assert_eq!(unwrap_at("[0, [1, 2], 3]", "1", true).unwrap(), "[0, 1, 2, 3]");
+        let text = "[\n  0,\n  [\n    1,\n    2\n  ]\n]";
+        assert_eq!(unwrap_at(text, "1", true).unwrap(), "[\n  0,\n  1,\n  2\n]");
+        assert_eq!(unwrap_at(r#"{"a": 1, "b": {"c": 2, "d": 3}}"#, "2", true).unwrap(), r#"{"a": 1, "c": 2, "d": 3}"#);
+        assert!(unwrap_at(r#"[{"c": 2, "d": 3}]"#, "2", true).is_err());
+        assert_eq!(unwrap_at("[[]]", "[]", false).unwrap_err().to_string(), "Nothing to unwrap");
+    }
+
+    #[test]
+    fn test_convert() {
+        let convert_at = |text, at, conversion| {
+            edit_at(text, at, |b, c, i| Ok(vec![convert(b, c, i, conversion)?.0]))
+        };
+        let text = r#"{"n": 42, "s": "4.5e3", "b": true, "q": "say \"hi\"", "o": {}}"#;
+        assert_eq!(convert_at(text, "42", Conversion::Stringify).unwrap(), text.replace("42", r#""42""#));
+        assert_eq!(convert_at(text, "true", Conversion::Stringify).unwrap(), text.replace("true", r#""true""#));
+        assert_eq!(convert_at(text, "\"s\"", Conversion::Unstringify).unwrap(), text.replace(r#""4.5e3""#, "4.5e3"));
+        assert_eq!(convert_at(text, "true", Conversion::ToggleBool).unwrap(), text.replace("true", "false"));
+        assert_eq!(convert_at(text, "{}", Conversion::Null).unwrap(), text.replace("{}", "null"));
+        assert_eq!(convert_at(text, "42", Conversion::Null).unwrap(), text.replace("42", "null"));
+
+        for (at, conversion, message) in [
+            ("{}", Conversion::Stringify, "Can't convert a container"),
+            ("\"s\"", Conversion::Stringify, "Already a string"),
+            ("42", Conversion::Unstringify, "Not a string"),
+            ("say", Conversion::Unstringify, r#"Not a JSON value: "say \"hi\"""#),
+            ("42", Conversion::ToggleBool, "Not true or false"),
+        ] {
+            assert_eq!(convert_at(text, at, conversion).unwrap_err().to_string(), message);
+        }
+        // A string holding a quoted string unwraps one level
+        assert_eq!(convert_at(r#"["\"x\""]"#, "x", Conversion::Unstringify).unwrap(), r#"["x"]"#);
+        assert!(convert_at(r#"["12 34"]"#, "12", Conversion::Unstringify).is_err());
+    }
+
+    #[test]
+    fn test_edit_string() {
+        let text = r#"{"k\u00e9y": "a\nb \ud83c\udf89", "n": 1}"#;
+        let buffer = Buffer::from_text(text);
+        let index = index_of(&buffer);
+        let (span, value) = string_at(&buffer, &index, text.find("a\\n").unwrap()).unwrap();
+        assert_eq!((&text[span], value.as_str()), (r#""a\nb \ud83c\udf89""#, "a\nb \u{1f389}"));
+        assert_eq!(string_at(&buffer, &index, 3).unwrap().1, "k\u{e9}y");
+        assert!(string_at(&buffer, &index, text.find('1').unwrap()).is_err());
+
+        let edited = edit_at(text, "a\\n", |b, c, i| {
+            let (span, _) = string_at(b, i, c.byte_offset)?;
+            replace_string(b, c, span, "say \"hi\"\n\tbye 🎉")
+        });
+        assert_eq!(edited.unwrap(), r#"{"k\u00e9y": "say \"hi\"\n\tbye 🎉", "n": 1}"#);
+    }
+
+    #[test]
+    fn test_yank_then_paste_round_trip() {
+        let text = "{\n  \"list\": [\n    {\"id\": 1},\n    {\"id\": 2}\n  ]\n}";
+        let buffer = Buffer::from_text(text);
+        let index = index_of(&buffer);
+        let taken = yank(&buffer, &index, text.find("\"list\"").unwrap()).unwrap();
+        assert_eq!(taken, "\"list\": [\n  {\"id\": 1},\n  {\"id\": 2}\n]");
+        let pasted = paste_at(text, "\"list\"", &taken).unwrap();
+        assert_eq!(pasted, text.replace("\n}", &format!(",\n  {}\n}}", reindent(&taken, "  "))));
+    }
+}
@@ -1,24 +1,64 @@
-use anyhow::Result;
-use std::time::{SystemTime, Instant};
+use anyhow::{bail, Result};
+use std::ops::Range;
+use std::time::{Duration, Instant, SystemTime};
 
 use super::Edit;
 use crate::buffer::Buffer;
 use crate::buffer::cursor::Cursor;
 
-/// Undo/redo stack with transaction grouping
+/// Undo history with transaction grouping, kept as a tree: an edit made
+/// after an undo starts a new branch rather than dropping the changes that
+/// were undone, and every state stays reachable with g- and g+
 pub struct UndoStack {
-    /// Stack of undo operations
-    stack: Vec<EditGroup>,
-    /// Stack of redo operations
-    redo_stack: Vec<EditGroup>,
+    /// States of the buffer; the first is the one before any edit
+    nodes: Vec<UndoNode>,
+    /// The state the buffer is in
+    current: usize,
+    /// Sequence number for the next state
+    next_seq: usize,
     /// Current edit group (for grouping multiple edits)
     current_group: Vec<Edit>,
+    /// Label for the current group, from begin_labeled_group
+    current_label: Option<String>,
     /// Maximum number of undo levels
     max_size: usize,
     /// When the current group was started
     group_start_time: Option<Instant>,
     /// Timeout for auto-grouping edits (milliseconds)
     group_timeout_ms: u64,
+    /// Whether the timeout is off until the group is committed, from
+    /// hold_group
+    held: bool,
+    /// Where the last change was made, undone or redone, for the `. mark:
+    /// its offset, and its line and column for a lazily read buffer
+    last_change: Option<(usize, Option<(usize, usize)>)>,
+}
+
+/// One state in the tree, reached from its parent by its group
+struct UndoNode {
+    /// Edits leading here from the parent; empty for the root
+    group: EditGroup,
+    parent: usize,
+    children: Vec<usize>,
+    /// Order of creation, which g- and g+ follow
+    seq: usize,
+    /// The child redo goes to: the last one made or undone from
+    redo_child: Option<usize>,
+}
+
+impl UndoNode {
+    fn new(group: EditGroup, parent: usize, seq: usize) -> Self {
+        Self { group, parent, children: Vec::new(), seq, redo_child: None }
+    }
+}
+
+/// A branch tip, for :undolist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UndoBranch {
+    pub seq: usize,
+    /// Groups between it and the oldest state
+    pub changes: usize,
+    pub timestamp: SystemTime,
 }
 
 /// A group of edits that are undone/redone together
@@ -26,26 +66,64 @@ pub struct UndoStack {
 pub struct EditGroup {
     edits: Vec<Edit>,
     timestamp: SystemTime,
+    /// What the group did, for bulk operations: "sort 38 elements"
+    label: Option<String>,
 }
 
 impl EditGroup {
     fn new(edits: Vec<Edit>) -> Self {
-        Self {
-            edits,
-            timestamp: SystemTime::now(),
+        Self::with_timestamp(edits, SystemTime::now())
+    }
+
+    /// A group made at `timestamp`, as read back from an undo file
+    pub fn with_timestamp(edits: Vec<Edit>, timestamp: SystemTime) -> Self {
+        Self { edits, timestamp, label: None }
+    }
+
+    /// The group with `label` saying what it did
+    pub fn labeled(self, label: Option<String>) -> Self {
+        Self { label, ..self }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// The label, or what kind of edits a group without one holds
+    pub fn describe(&self) -> String {
+        if let Some(label) = &self.label {
+            return label.clone();
         }
+        if self.edits.iter().all(|edit| edit.old_text.is_empty()) {
+            "insert".to_string()
+        } else if self.edits.iter().all(|edit| edit.new_text.is_empty()) {
+            "delete".to_string()
+        } else {
+            "change".to_string()
+        }
+    }
+
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
     }
     
-    /// Apply all edits in the group
+    /// Where the group was made, as the `. mark keeps it: its last edit is
+    /// where it finished, its first where undoing it (`undone`) leaves off
+    fn place(&self, undone: bool) -> Option<(usize, Option<(usize, usize)>)> {
+        let edit = if undone { self.edits.first() } else { self.edits.last() }?;
+        Some((edit.offset, edit.line_pos))
+    }
+
+    /// Apply all edits in the group. Each edit's offset is where it was
+    /// made, after the edits before it in the group, so replaying them in
+    /// order puts each one back where it was even when they overlap.
     fn apply(&self, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<()> {
         for edit in &self.edits {
-            // Apply the edit
-            if !edit.new_text.is_empty() {
-                buffer.insert(edit.offset, &edit.new_text)?;
-            }
-            if !edit.old_text.is_empty() {
-                buffer.delete(edit.offset, edit.offset + edit.old_text.len())?;
-            }
+            replay(edit, buffer)?;
         }
         
         // Restore cursor to final position
@@ -58,18 +136,11 @@ impl EditGroup {
         Ok(())
     }
     
-    /// Apply all edits in reverse (for undo)
+    /// Apply all edits in reverse (for undo): the last edit is undone
+    /// first, so each one meets the text it left behind
     fn apply_reverse(&self, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<()> {
         for edit in self.edits.iter().rev() {
-            let reversed = edit.reverse();
-            
-            // Apply the reversed edit
-            if !reversed.new_text.is_empty() {
-                buffer.insert(reversed.offset, &reversed.new_text)?;
-            }
-            if !reversed.old_text.is_empty() {
-                buffer.delete(reversed.offset, reversed.offset + reversed.old_text.len())?;
-            }
+            replay(&edit.reverse(), buffer)?;
         }
         
         // Restore cursor to initial position
@@ -83,22 +154,119 @@ impl EditGroup {
     }
 }
 
+/// "12s ago", "5m ago", "3h ago" or "2d ago"
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// Make `edit` again the way it was first made: remove the old text, then
+/// insert the new text in its place
+fn replay(edit: &Edit, buffer: &mut Buffer) -> Result<()> {
+    if let Some((line, col)) = edit.line_pos.filter(|_| buffer.is_lazy()) {
+        return buffer.replace_in_line(line, col, edit.old_text.len(), &edit.new_text);
+    }
+    if !edit.old_text.is_empty() {
+        buffer.delete(edit.offset, edit.offset + edit.old_text.len())?;
+    }
+    if !edit.new_text.is_empty() {
+        buffer.insert(edit.offset, &edit.new_text)?;
+    }
+    Ok(())
+}
+
+/// Where `pos`, in the text after `edit`, was before it. A place inside
+/// what the edit put in goes to the start of what it took out, or with
+/// `end` to the end of it.
+fn map_back(edit: &Edit, pos: usize, end: bool) -> usize {
+    let inserted_end = edit.offset + edit.new_text.len();
+    if pos <= edit.offset {
+        pos
+    } else if pos >= inserted_end {
+        pos - edit.new_text.len() + edit.old_text.len()
+    } else if end {
+        edit.offset + edit.old_text.len()
+    } else {
+        edit.offset
+    }
+}
+
+/// Edits `a` then `b` made the other way round: `b` moved to where it
+/// falls before `a`, then `a` moved to where it falls after `b`. None when
+/// `b` touches what `a` put in, with not even a byte between them, so
+/// which came first matters.
+fn commute(a: &Edit, b: &Edit) -> Option<(Edit, Edit)> {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    if b.offset > a.offset + a.new_text.len() {
+        b.offset = b.offset - a.new_text.len() + a.old_text.len();
+    } else if b.offset + b.old_text.len() < a.offset {
+        a.offset = a.offset - b.old_text.len() + b.new_text.len();
+    } else {
+        return None;
+    }
+    Some((b, a))
+}
+
+/// Carry `moved`, a group's edits, past the edits of a `later` group: the
+/// later group as made without `moved` before it, and `moved` as made after
+/// it. None if they overlap.
+fn carry(moved: &[Edit], later: &[Edit]) -> Option<(Vec<Edit>, Vec<Edit>)> {
+    let mut moved = moved.to_vec();
+    let mut rebased = Vec::with_capacity(later.len());
+    for edit in later {
+        let mut edit = edit.clone();
+        for earlier in moved.iter_mut().rev() {
+            let (before, after) = commute(earlier, &edit)?;
+            (edit, *earlier) = (before, after);
+        }
+        rebased.push(edit);
+    }
+    Some((rebased, moved))
+}
+
 impl UndoStack {
     pub fn new() -> Self {
         Self {
-            stack: Vec::new(),
-            redo_stack: Vec::new(),
+            nodes: vec![UndoNode::new(EditGroup::new(Vec::new()), 0, 0)],
+            current: 0,
+            next_seq: 1,
             current_group: Vec::new(),
+            current_label: None,
             max_size: 1000,
             group_start_time: None,
             group_timeout_ms: 1000, // 1 second
+            held: false,
+            last_change: None,
+        }
+    }
+
+    /// A history of `groups` made one after another, oldest first, with
+    /// the buffer after the last
+    pub fn from_groups(groups: Vec<EditGroup>) -> Self {
+        let mut stack = Self::new();
+        for group in groups {
+            stack.add_node(group);
         }
+        stack
+    }
+
+    /// The committed groups leading to the current state, oldest first;
+    /// other branches and an open group aren't included
+    pub fn groups(&self) -> Vec<&EditGroup> {
+        let mut groups: Vec<&EditGroup> = self.ancestors(self.current).map(|id| &self.nodes[id].group).collect();
+        groups.reverse();
+        groups
     }
     
     /// Add an edit to the current group
     pub fn push(&mut self, edit: Edit) {
         // Check if we should start a new group
-        if let Some(start_time) = self.group_start_time {
+        if let Some(start_time) = self.group_start_time.filter(|_| !self.held) {
             if start_time.elapsed().as_millis() > self.group_timeout_ms as u128 {
                 self.commit_group();
             }
@@ -108,10 +276,8 @@ impl UndoStack {
             self.group_start_time = Some(Instant::now());
         }
         
+        self.last_change = Some((edit.offset, edit.line_pos));
         self.current_group.push(edit);
-        
-        // Clear redo stack when new edit is made
-        self.redo_stack.clear();
     }
     
     /// Start a new edit group (for explicit transaction boundaries)
@@ -122,65 +288,402 @@ impl UndoStack {
         self.group_start_time = Some(Instant::now());
     }
     
+    /// Start a group for one bulk operation, listed by :undolist as `label`
+    pub fn begin_labeled_group(&mut self, label: &str) {
+        self.begin_group();
+        self.current_label = Some(label.to_string());
+    }
+    
+    /// Record edits made together, as from EditOperations::apply_edits, as
+    /// one group listed as `label`
+    pub fn push_batch(&mut self, edits: Vec<Edit>, label: &str) {
+        self.begin_labeled_group(label);
+        self.current_group.extend(edits);
+        self.end_group();
+    }
+    
+    /// Keep edits in the open group however long they take until it's
+    /// ended, so a completion is undone with the text it completed
+    pub fn hold_group(&mut self) {
+        self.held = true;
+    }
+    
     /// Commit the current edit group to the undo stack
     pub fn end_group(&mut self) {
         self.commit_group();
     }
     
-    /// Commit current group to undo stack
+    /// Commit current group as a new state after the current one
     fn commit_group(&mut self) {
+        self.held = false;
         if self.current_group.is_empty() {
             return;
         }
         
-        let group = EditGroup::new(std::mem::take(&mut self.current_group));
-        self.stack.push(group);
+        let group = EditGroup::new(std::mem::take(&mut self.current_group)).labeled(self.current_label.take());
+        self.add_node(group);
         self.group_start_time = None;
         
         // Enforce max size
-        if self.stack.len() > self.max_size {
-            self.stack.remove(0);
+        if self.nodes.len() > self.max_size + 1 {
+            self.drop_oldest();
         }
     }
+
+    fn add_node(&mut self, group: EditGroup) {
+        self.last_change = group.place(false).or(self.last_change);
+        let id = self.nodes.len();
+        self.nodes.push(UndoNode::new(group, self.current, self.next_seq));
+        self.next_seq += 1;
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(id);
+        parent.redo_child = Some(id);
+        self.current = id;
+    }
+
+    /// Make the first state on the way to the current one the oldest,
+    /// dropping the branches that leave from before it
+    fn drop_oldest(&mut self) {
+        let Some(new_root) = self.ancestors(self.current).last() else {
+            return;
+        };
+        self.keep_subtree(new_root);
+        // Its edits are part of where the history now starts
+        self.nodes[0].group = EditGroup::new(Vec::new());
+    }
+
+    /// Keep only `root` and the states below it, `root` now the oldest.
+    /// The current state must be among them.
+    fn keep_subtree(&mut self, root: usize) {
+        // Renumber the subtree breadth first, so parents come before children
+        let mut order = vec![root];
+        let mut i = 0;
+        while i < order.len() {
+            order.extend(self.nodes[order[i]].children.iter().copied());
+            i += 1;
+        }
+        let mut renumbered = vec![usize::MAX; self.nodes.len()];
+        for (new, &old) in order.iter().enumerate() {
+            renumbered[old] = new;
+        }
+        let mut old_nodes: Vec<Option<UndoNode>> = std::mem::take(&mut self.nodes).into_iter().map(Some).collect();
+        self.nodes = order
+            .iter()
+            .map(|&old| {
+                let mut node = old_nodes[old].take().expect("each state is visited once");
+                node.parent = if old == root { 0 } else { renumbered[node.parent] };
+                node.children.iter_mut().for_each(|child| *child = renumbered[*child]);
+                node.redo_child = node.redo_child.map(|child| renumbered[child]);
+                node
+            })
+            .collect();
+        self.current = renumbered[self.current];
+    }
+
+    /// Take state `id` out of the tree, the states after it now following
+    /// from its parent, and drop the branches starting at `dropped`
+    fn splice_out(&mut self, id: usize, dropped: &[usize]) {
+        let parent = self.nodes[id].parent;
+        let children = std::mem::take(&mut self.nodes[id].children);
+        for &child in &children {
+            self.nodes[child].parent = parent;
+        }
+        let redo_child = self.nodes[id].redo_child;
+        let parent_node = &mut self.nodes[parent];
+        let at = parent_node.children.iter().position(|&child| child == id).expect("a state is among its parent's children");
+        parent_node.children.splice(at..at + 1, children);
+        if parent_node.redo_child == Some(id) {
+            parent_node.redo_child = redo_child;
+        }
+        if self.current == id {
+            self.current = parent;
+        }
+        for &branch in dropped {
+            let parent = self.nodes[branch].parent;
+            let parent_node = &mut self.nodes[parent];
+            parent_node.children.retain(|&child| child != branch);
+            if parent_node.redo_child == Some(branch) {
+                parent_node.redo_child = parent_node.children.last().copied();
+            }
+        }
+        // What's no longer reachable from the oldest state goes
+        self.keep_subtree(0);
+    }
+
+    /// `id` and the states before it, back to (not including) the oldest
+    fn ancestors(&self, id: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(Some(id), |&id| Some(self.nodes[id].parent)).take_while(|&id| id != 0)
+    }
     
     /// Undo the last edit group
     pub fn undo(&mut self, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<bool> {
         // Commit any pending edits first
         self.commit_group();
         
-        if let Some(group) = self.stack.pop() {
-            group.apply_reverse(buffer, cursor)?;
-            self.redo_stack.push(group);
-            Ok(true)
-        } else {
-            Ok(false)
+        if self.current == 0 {
+            return Ok(false);
         }
+        let node = &self.nodes[self.current];
+        node.group.apply_reverse(buffer, cursor)?;
+        self.last_change = node.group.place(true).or(self.last_change);
+        let parent = node.parent;
+        self.nodes[parent].redo_child = Some(self.current);
+        self.current = parent;
+        Ok(true)
     }
     
-    /// Redo the last undone edit group
+    /// Take back the last edit group and forget it, as if it had never
+    /// been made: for an edit refused once it's been tried. With the group
+    /// no longer the newest state it's undone as usual.
+    pub fn drop_last(&mut self, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<bool> {
+        self.commit_group();
+        let id = self.current;
+        if id == 0 || id + 1 != self.nodes.len() || !self.nodes[id].children.is_empty() {
+            return self.undo(buffer, cursor);
+        }
+        self.nodes[id].group.apply_reverse(buffer, cursor)?;
+        self.last_change = self.nodes[id].group.place(true).or(self.last_change);
+        let parent = self.nodes[id].parent;
+        self.nodes.pop();
+        let parent_node = &mut self.nodes[parent];
+        parent_node.children.retain(|&child| child != id);
+        parent_node.redo_child = parent_node.children.last().copied();
+        self.current = parent;
+        self.next_seq -= 1;
+        Ok(true)
+    }
+    
+    /// :undo-here - take back only the newest group whose edits all fall
+    /// within `region` of the text as it is now, keeping the groups made
+    /// since. Those are moved to where they'd have been made without it, so
+    /// every state stays one undo from the next; a branch off to the side
+    /// that can't be is dropped. Refused when a later group on the way to
+    /// the current state edits text it touched, since then which came
+    /// first matters. Says what was taken back.
+    pub fn undo_in(&mut self, region: Range<usize>, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<String> {
+        self.commit_group();
+        if buffer.is_lazy() {
+            bail!("Not in a file read lazily, where edits are found by line");
+        }
+        // Follow the region back through each group in turn, newest first,
+        // to where it was when the group was made
+        let path: Vec<usize> = self.ancestors(self.current).collect();
+        let (mut start, mut end) = (region.start, region.end);
+        let mut target = None;
+        for &id in &path {
+            let edits = &self.nodes[id].group.edits;
+            let mut inside = !edits.is_empty();
+            for edit in edits.iter().rev() {
+                inside &= start <= edit.offset && edit.offset + edit.new_text.len() <= end;
+                (start, end) = (map_back(edit, start, false), map_back(edit, end, true));
+            }
+            if inside {
+                target = Some(id);
+                break;
+            }
+        }
+        let Some(target) = target else {
+            bail!("No change to undo here");
+        };
+
+        // Carry its edits past every state after it, to see where they are
+        // now and what each later group becomes without them
+        let mut rebased = Vec::new();
+        let mut dropped = Vec::new();
+        let mut undone = None;
+        let mut pending = vec![(target, self.nodes[target].group.edits.clone())];
+        while let Some((id, moved)) = pending.pop() {
+            for &child in &self.nodes[id].children {
+                match carry(&moved, &self.nodes[child].group.edits) {
+                    Some((edits, moved)) => {
+                        rebased.push((child, edits));
+                        pending.push((child, moved));
+                    }
+                    None if path.contains(&child) => {
+                        let (group, blocking) = (&self.nodes[target], &self.nodes[child]);
+                        bail!(
+                            "Change {} ({}) can't be undone alone: change {} ({}, {}) touched the same text since",
+                            group.seq,
+                            group.group.describe(),
+                            blocking.seq,
+                            blocking.group.describe(),
+                            format_age(blocking.group.timestamp.elapsed().unwrap_or_default())
+                        );
+                    }
+                    None => dropped.push(child),
+                }
+            }
+            if id == self.current {
+                undone = Some(moved);
+            }
+        }
+        let undone = undone.expect("the current state follows the group");
+
+        for edit in undone.iter().rev() {
+            replay(&edit.reverse(), buffer)?;
+        }
+        if let Some(first) = undone.first() {
+            cursor.move_to_offset(buffer, first.offset);
+            self.last_change = Some((first.offset, None));
+        }
+        for (id, edits) in rebased {
+            self.nodes[id].group.edits = edits;
+        }
+        let group = &self.nodes[target];
+        let mut message = format!(
+            "Undid change {} ({}, {})",
+            group.seq,
+            group.group.describe(),
+            format_age(group.group.timestamp.elapsed().unwrap_or_default())
+        );
+        let kept = path.iter().position(|&id| id == target).unwrap_or(0);
+        if kept > 0 {
+            message.push_str(&format!(", keeping {} newer", kept));
+        }
+        if !dropped.is_empty() {
+            let branches = if dropped.len() == 1 { "branch" } else { "branches" };
+            message.push_str(&format!("; dropped {} undo {} it ran into", dropped.len(), branches));
+        }
+        self.splice_out(target, &dropped);
+        Ok(message)
+    }
+
+    /// Redo the last undone edit group, along the branch last taken
     pub fn redo(&mut self, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<bool> {
-        if let Some(group) = self.redo_stack.pop() {
-            group.apply(buffer, cursor)?;
-            self.stack.push(group);
-            Ok(true)
-        } else {
-            Ok(false)
+        self.commit_group();
+
+        let Some(child) = self.nodes[self.current].redo_child else {
+            return Ok(false);
+        };
+        self.nodes[child].group.apply(buffer, cursor)?;
+        self.last_change = self.nodes[child].group.place(false).or(self.last_change);
+        self.current = child;
+        Ok(true)
+    }
+
+    /// The `. mark: where the last change was made, undone or redone, as a
+    /// byte offset in `buffer`
+    pub fn last_change(&self, buffer: &Buffer) -> Option<usize> {
+        let (offset, line_pos) = self.last_change?;
+        let offset = match line_pos.filter(|_| buffer.is_lazy()) {
+            Some((line, col)) => buffer.line_to_byte_offset(line) + col,
+            None => offset,
+        };
+        Some(offset.min(buffer.len_bytes()))
+    }
+
+    /// Undo up to `count` groups, stopping at the oldest state; how many
+    /// were undone
+    pub fn undo_many(&mut self, count: usize, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<usize> {
+        let mut done = 0;
+        while done < count && self.undo(buffer, cursor)? {
+            done += 1;
         }
+        Ok(done)
+    }
+
+    /// Redo up to `count` groups along the branch last taken; how many
+    /// were redone
+    pub fn redo_many(&mut self, count: usize, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<usize> {
+        let mut done = 0;
+        while done < count && self.redo(buffer, cursor)? {
+            done += 1;
+        }
+        Ok(done)
+    }
+
+    /// What moving `steps` groups back (`older`) or forward did, with how
+    /// old the state now is: "3 changes older; 5m ago"
+    pub fn describe(&self, steps: usize, older: bool) -> String {
+        match (steps, older) {
+            (0, true) => return "Already at oldest change".to_string(),
+            (0, false) => return "Already at newest change".to_string(),
+            _ => {}
+        }
+        let changes = if steps == 1 { "change" } else { "changes" };
+        let direction = if older { "older" } else { "newer" };
+        let age = match self.current {
+            0 => "original text".to_string(),
+            id => format_age(self.nodes[id].group.timestamp.elapsed().unwrap_or_default()),
+        };
+        format!("{} {} {}; {}", steps, changes, direction, age)
+    }
+
+    /// Go to the state made just before the current one, whichever branch
+    /// it's on (g-)
+    pub fn earlier(&mut self, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<bool> {
+        self.commit_group();
+        let seq = self.nodes[self.current].seq;
+        let target = (0..self.nodes.len()).filter(|&id| self.nodes[id].seq < seq).max_by_key(|&id| self.nodes[id].seq);
+        self.go_to(target, buffer, cursor)
+    }
+
+    /// Go to the state made just after the current one (g+)
+    pub fn later(&mut self, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<bool> {
+        self.commit_group();
+        let seq = self.nodes[self.current].seq;
+        let target = (0..self.nodes.len()).filter(|&id| self.nodes[id].seq > seq).min_by_key(|&id| self.nodes[id].seq);
+        self.go_to(target, buffer, cursor)
+    }
+
+    /// Move the buffer to state `target`: undo up to where its branch
+    /// leaves the current one, then redo down to it
+    fn go_to(&mut self, target: Option<usize>, buffer: &mut Buffer, cursor: &mut Cursor) -> Result<bool> {
+        let Some(target) = target else {
+            return Ok(false);
+        };
+        let current_path: Vec<usize> = self.ancestors(self.current).collect();
+        let mut down: Vec<usize> = self.ancestors(target).take_while(|id| !current_path.contains(id)).collect();
+        let common = down.last().map_or(target, |&id| self.nodes[id].parent);
+        while self.current != common {
+            self.undo(buffer, cursor)?;
+        }
+        while let Some(id) = down.pop() {
+            let parent = self.nodes[id].parent;
+            self.nodes[parent].redo_child = Some(id);
+            self.redo(buffer, cursor)?;
+        }
+        Ok(true)
+    }
+
+    /// Up to `limit` groups leading to the current state, newest (the one
+    /// u reverts) first, with their sequence numbers
+    pub fn recent(&self, limit: usize) -> Vec<(usize, &EditGroup)> {
+        self.ancestors(self.current).take(limit).map(|id| (self.nodes[id].seq, &self.nodes[id].group)).collect()
+    }
+
+    /// The tips of all branches, oldest first
+    pub fn branches(&self) -> Vec<UndoBranch> {
+        let mut branches: Vec<UndoBranch> = (1..self.nodes.len())
+            .filter(|&id| self.nodes[id].children.is_empty())
+            .map(|id| UndoBranch {
+                seq: self.nodes[id].seq,
+                changes: self.ancestors(id).count(),
+                timestamp: self.nodes[id].group.timestamp,
+            })
+            .collect();
+        branches.sort_by_key(|branch| branch.seq);
+        branches
+    }
+
+    /// Sequence number of the current state; 0 before any edit
+    pub fn seq(&self) -> usize {
+        self.nodes[self.current].seq
     }
     
     /// Check if undo is available
     pub fn can_undo(&self) -> bool {
-        !self.stack.is_empty() || !self.current_group.is_empty()
+        self.current != 0 || !self.current_group.is_empty()
     }
     
     /// Check if redo is available
     pub fn can_redo(&self) -> bool {
-        !self.redo_stack.is_empty()
+        self.nodes[self.current].redo_child.is_some()
     }
     
     /// Get the number of undo levels available
     pub fn undo_count(&self) -> usize {
-        self.stack.len() + if self.current_group.is_empty() { 0 } else { 1 }
+        self.ancestors(self.current).count() + if self.current_group.is_empty() { 0 } else { 1 }
     }
 }
 
@@ -189,3 +692,538 @@ impl Default for UndoStack {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edit::CursorState;
+
+    fn insert(stack: &mut UndoStack, buffer: &mut Buffer, offset: usize, text: &str) {
+        let at = CursorState { line: 0, col: offset, byte_offset: offset };
+        buffer.insert(offset, text).unwrap();
+        stack.begin_group();
+        stack.push(Edit::new(offset, String::new(), text.to_string(), at, at));
+        stack.end_group();
+    }
+
+    fn text(buffer: &Buffer) -> String {
+        buffer.slice(0..buffer.len_bytes())
+    }
+
+    #[test]
+    fn test_branches() {
+        let mut stack = UndoStack::new();
+        let mut buffer = Buffer::from_text("ab");
+        let mut cursor = Cursor::new();
+        insert(&mut stack, &mut buffer, 0, "X");
+        assert!(stack.undo(&mut buffer, &mut cursor).unwrap());
+        // A different edit starts a second branch
+        insert(&mut stack, &mut buffer, 2, "Y");
+        assert_eq!(text(&buffer), "abY");
+        assert!(!stack.can_redo());
+        assert_eq!(stack.branches().iter().map(|b| (b.seq, b.changes)).collect::<Vec<_>>(), [(1, 1), (2, 1)]);
+
+        // g- and g+ go through the states in the order they were made
+        let mut seen = Vec::new();
+        while stack.earlier(&mut buffer, &mut cursor).unwrap() {
+            seen.push(text(&buffer));
+        }
+        assert_eq!(seen, ["Xab", "ab"]);
+        while stack.later(&mut buffer, &mut cursor).unwrap() {
+            seen.push(text(&buffer));
+        }
+        assert_eq!(seen, ["Xab", "ab", "Xab", "abY"]);
+        assert_eq!(stack.seq(), 2);
+
+        // u and Ctrl-r follow the branch last taken
+        stack.earlier(&mut buffer, &mut cursor).unwrap();
+        stack.undo(&mut buffer, &mut cursor).unwrap();
+        assert!(stack.redo(&mut buffer, &mut cursor).unwrap());
+        assert_eq!(text(&buffer), "Xab");
+        insert(&mut stack, &mut buffer, 3, "Z");
+        assert_eq!(stack.undo_count(), 2);
+        assert_eq!(stack.groups().len(), 2);
+        stack.later(&mut buffer, &mut cursor).unwrap();
+        assert_eq!(text(&buffer), "XabZ");
+        assert!(!stack.later(&mut buffer, &mut cursor).unwrap());
+    }
+
+    #[test]
+    fn test_drop_last() {
+        let mut stack = UndoStack::new();
+        let mut buffer = Buffer::from_text("ab");
+        let mut cursor = Cursor::new();
+        insert(&mut stack, &mut buffer, 0, "X");
+        assert!(stack.undo(&mut buffer, &mut cursor).unwrap());
+        insert(&mut stack, &mut buffer, 2, "Y");
+        assert!(stack.drop_last(&mut buffer, &mut cursor).unwrap());
+        // Gone without a trace: redo goes back to the first branch
+        assert_eq!(text(&buffer), "ab");
+        assert_eq!(stack.branches().len(), 1);
+        assert!(stack.redo(&mut buffer, &mut cursor).unwrap());
+        assert_eq!(text(&buffer), "Xab");
+        assert_eq!(stack.seq(), 1);
+        assert!(stack.drop_last(&mut buffer, &mut cursor).unwrap());
+        assert!(!stack.can_redo());
+        assert!(!stack.drop_last(&mut buffer, &mut cursor).unwrap());
+    }
+
+    /// Replace `range` of `reference` and `buffer` alike, recording the edit
+    fn replace(buffer: &mut Buffer, reference: &mut String, range: std::ops::Range<usize>, text: &str) -> Edit {
+        let at = CursorState { line: 0, col: range.start, byte_offset: range.start };
+        let old = reference[range.clone()].to_string();
+        reference.replace_range(range.clone(), text);
+        buffer.replace(range.start, range.end, text).unwrap();
+        Edit::new(range.start, old, text.to_string(), at, at)
+    }
+
+    #[test]
+    fn test_replay() {
+        // (start, end, text) replacements made one after another in a group
+        let cases: &[&[(usize, usize, &str)]] = &[
+            &[(1, 2, "xyz")],
+            &[(0, 3, "")],
+            &[(2, 2, "abc"), (3, 5, "Q")],
+            &[(1, 4, "12"), (0, 3, "-"), (1, 1, "++")],
+            &[(5, 6, "long replacement"), (4, 20, "s")],
+        ];
+        for edits in cases {
+            let original = "[1, 2, 3]";
+            let mut reference = original.to_string();
+            let mut buffer = Buffer::from_text(original);
+            let mut stack = UndoStack::new();
+            stack.begin_group();
+            for &(start, end, text) in edits.iter() {
+                stack.push(replace(&mut buffer, &mut reference, start..end, text));
+            }
+            stack.end_group();
+            let mut cursor = Cursor::new();
+            for _ in 0..2 {
+                stack.undo(&mut buffer, &mut cursor).unwrap();
+                assert_eq!(text(&buffer), original, "undo {:?}", edits);
+                stack.redo(&mut buffer, &mut cursor).unwrap();
+                assert_eq!(text(&buffer), reference, "redo {:?}", edits);
+            }
+        }
+    }
+
+    #[test]
+    fn test_replay_random() {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = |limit: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % (limit as u64 + 1)) as usize
+        };
+        let mut reference = "{\"a\": [1, 2, 3], \"b\": null}".to_string();
+        let mut buffer = Buffer::from_text(&reference);
+        let mut stack = UndoStack::new();
+        let mut states = vec![reference.clone()];
+        for _ in 0..50 {
+            stack.begin_group();
+            for _ in 0..1 + next(3) {
+                let start = next(reference.len());
+                let end = start + next(reference.len() - start).min(6);
+                let text = &"0123456789\"{}[],: "[next(8)..][..next(6)];
+                stack.push(replace(&mut buffer, &mut reference, start..end, text));
+            }
+            stack.end_group();
+            states.push(reference.clone());
+        }
+        let mut cursor = Cursor::new();
+        for state in states.iter().rev().skip(1) {
+            assert!(stack.undo(&mut buffer, &mut cursor).unwrap());
+            assert_eq!(&text(&buffer), state);
+        }
+        for state in states.iter().skip(1) {
+            assert!(stack.redo(&mut buffer, &mut cursor).unwrap());
+            assert_eq!(&text(&buffer), state);
+        }
+    }
+
+    #[test]
+    fn test_undo_lazy() {
+        use crate::edit::EditOperations;
+
+        // Big enough to be read lazily
+        let path = std::env::temp_dir().join(format!("jim_test_undo_lazy_{}.json", std::process::id()));
+        let record = "{\"id\": 1, \"name\": \"item\"},\n";
+        let original = format!("[\n{}{{\"id\": 2}}]", record.repeat(11 * 1024 * 1024 / record.len()));
+        std::fs::write(&path, &original).unwrap();
+        let mut buffer = Buffer::new();
+        buffer.load_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(buffer.is_lazy());
+
+        let mut stack = UndoStack::new();
+        let mut cursor = Cursor::new();
+        let edit = |stack: &mut UndoStack, edit: Edit| {
+            stack.begin_group();
+            stack.push(edit);
+            stack.end_group();
+        };
+        // Grow line 1 at its end, so the offsets past it no longer match
+        let end = buffer.line_to_byte_offset(2) - 3;
+        let grown = EditOperations::insert(&mut buffer, &mut cursor, end, ", \"tags\": [\"a\", \"b\"]").unwrap();
+        edit(&mut stack, grown);
+        let start = buffer.line_to_byte_offset(3) + 1;
+        let deleted = EditOperations::delete(&mut buffer, &mut cursor, start, start + 9).unwrap();
+        edit(&mut stack, deleted);
+        let start = buffer.line_to_byte_offset(5) + "{\"id\": ".len();
+        let replaced = EditOperations::replace(&mut buffer, &mut cursor, start, start + 1, "12345").unwrap();
+        edit(&mut stack, replaced);
+        assert_eq!(buffer.get_line(1), "{\"id\": 1, \"name\": \"item\", \"tags\": [\"a\", \"b\"]},\n");
+        assert_eq!(buffer.get_line(3), "{\"name\": \"item\"},\n");
+        assert_eq!(buffer.get_line(5), "{\"id\": 12345, \"name\": \"item\"},\n");
+
+        while stack.undo(&mut buffer, &mut cursor).unwrap() {}
+        // The last line has no newline, so isn't in line_count
+        let text: String = (0..=buffer.line_count()).map(|line| buffer.get_line(line)).collect();
+        assert!(text == original, "undone buffer differs from the file");
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut stack = UndoStack::new();
+        let mut buffer = Buffer::from_text("[3, 1, 2]");
+        insert(&mut stack, &mut buffer, 1, "0, ");
+        let at = CursorState { line: 0, col: 0, byte_offset: 0 };
+        stack.begin_labeled_group("sort 4 elements");
+        stack.push(Edit::new(1, "0, 3, 1, 2".to_string(), String::new(), at, at));
+        stack.push(Edit::new(1, String::new(), "0, 1, 2, 3".to_string(), at, at));
+        stack.end_group();
+        // A later group isn't labeled unless asked
+        insert(&mut stack, &mut buffer, 0, " ");
+        let recent: Vec<_> = stack.recent(10).iter().map(|(seq, group)| (*seq, group.describe(), group.edits().len())).collect();
+        assert_eq!(recent, [(3, "insert".to_string(), 1), (2, "sort 4 elements".to_string(), 2), (1, "insert".to_string(), 1)]);
+        assert_eq!(stack.recent(1).len(), 1);
+    }
+
+    #[test]
+    fn test_batch() {
+        use crate::buffer::Edit as Replacement;
+        use crate::edit::EditOperations;
+
+        let original = "[false, 1, false, 2, false]";
+        let mut buffer = Buffer::from_text(original);
+        let mut cursor = Cursor::new();
+        cursor.byte_offset = 9;
+        let replacements: Vec<Replacement> = original
+            .match_indices("false")
+            .map(|(offset, _)| Replacement { file_offset: offset, old_len: 5, new_text: "true".to_string() })
+            .collect();
+        let mut stack = UndoStack::new();
+        let edits = EditOperations::apply_edits(&mut buffer, &mut cursor, &replacements).unwrap();
+        stack.push_batch(edits, "replace 3 values");
+        assert_eq!(text(&buffer), "[true, 1, true, 2, true]");
+        // The cursor stays on the 1
+        assert_eq!(cursor.byte_offset, 8);
+
+        assert_eq!(stack.recent(10)[0].1.describe(), "replace 3 values");
+        assert!(stack.undo(&mut buffer, &mut cursor).unwrap());
+        assert_eq!(text(&buffer), original);
+        assert_eq!(cursor.byte_offset, 9);
+        assert!(!stack.can_undo());
+        stack.redo(&mut buffer, &mut cursor).unwrap();
+        assert_eq!(text(&buffer), "[true, 1, true, 2, true]");
+    }
+
+    #[test]
+    fn test_counts() {
+        let mut stack = UndoStack::new();
+        let mut buffer = Buffer::from_text("");
+        let mut cursor = Cursor::new();
+        for (i, c) in ["a", "b", "c"].iter().enumerate() {
+            insert(&mut stack, &mut buffer, i, c);
+        }
+        assert_eq!(stack.undo_many(2, &mut buffer, &mut cursor).unwrap(), 2);
+        assert_eq!(text(&buffer), "a");
+        assert_eq!(stack.describe(2, true), "2 changes older; 0s ago");
+        // Asking for more than there is stops at the ends
+        assert_eq!(stack.undo_many(5, &mut buffer, &mut cursor).unwrap(), 1);
+        assert_eq!(stack.describe(1, true), "1 change older; original text");
+        assert_eq!(stack.undo_many(1, &mut buffer, &mut cursor).unwrap(), 0);
+        assert_eq!(stack.describe(0, true), "Already at oldest change");
+        assert_eq!(stack.redo_many(10, &mut buffer, &mut cursor).unwrap(), 3);
+        assert_eq!(text(&buffer), "abc");
+        assert_eq!(stack.redo_many(1, &mut buffer, &mut cursor).unwrap(), 0);
+        assert_eq!(stack.describe(0, false), "Already at newest change");
+    }
+
+    #[test]
+    fn test_last_change() {
+        let mut stack = UndoStack::new();
+        let mut buffer = Buffer::from_text("abcdef");
+        let mut cursor = Cursor::new();
+        assert_eq!(stack.last_change(&buffer), None);
+        insert(&mut stack, &mut buffer, 4, "X");
+        insert(&mut stack, &mut buffer, 1, "Y");
+        assert_eq!(text(&buffer), "aYbcdXef");
+        assert_eq!(stack.last_change(&buffer), Some(1));
+
+        // Undo and redo move it to the change they took back or made again
+        stack.undo(&mut buffer, &mut cursor).unwrap();
+        assert_eq!(stack.last_change(&buffer), Some(1));
+        stack.undo(&mut buffer, &mut cursor).unwrap();
+        assert_eq!(stack.last_change(&buffer), Some(4));
+        stack.redo(&mut buffer, &mut cursor).unwrap();
+        assert_eq!(stack.last_change(&buffer), Some(4));
+
+        // A batch leaves it at its last edit; an edit still being grouped
+        // counts straight away
+        let at = CursorState { line: 0, col: 0, byte_offset: 0 };
+        buffer.insert(0, "Z").unwrap();
+        stack.push(Edit::new(0, String::new(), "Z".to_string(), at, at));
+        assert_eq!(stack.last_change(&buffer), Some(0));
+        stack.end_group();
+        let batch = vec![
+            Edit::new(2, String::new(), "1".to_string(), at, at),
+            Edit::new(6, String::new(), "2".to_string(), at, at),
+        ];
+        stack.push_batch(batch, "two");
+        assert_eq!(stack.last_change(&buffer), Some(6));
+    }
+
+    #[test]
+    fn test_max_size() {
+        let mut stack = UndoStack::new();
+        stack.max_size = 2;
+        let mut buffer = Buffer::from_text("");
+        let mut cursor = Cursor::new();
+        insert(&mut stack, &mut buffer, 0, "a");
+        stack.undo(&mut buffer, &mut cursor).unwrap();
+        insert(&mut stack, &mut buffer, 0, "b");
+        insert(&mut stack, &mut buffer, 1, "c");
+        insert(&mut stack, &mut buffer, 2, "d");
+        assert_eq!(stack.undo_count(), 2);
+        while stack.undo(&mut buffer, &mut cursor).unwrap() {}
+        assert_eq!(text(&buffer), "b");
+        // The "a" branch left from before the oldest state kept
+        assert!(!stack.earlier(&mut buffer, &mut cursor).unwrap());
+        assert_eq!(stack.branches().len(), 1);
+    }
+
+    #[test]
+    fn test_hold_group() {
+        let mut stack = UndoStack::new();
+        stack.group_timeout_ms = 0;
+        let mut buffer = Buffer::from_text("");
+        let mut cursor = Cursor::new();
+        let at = CursorState { line: 0, col: 0, byte_offset: 0 };
+        let mut push = |stack: &mut UndoStack, offset: usize, text: &str| {
+            buffer.insert(offset, text).unwrap();
+            stack.push(Edit::new(offset, String::new(), text.to_string(), at, at));
+            std::thread::sleep(Duration::from_millis(2));
+        };
+        push(&mut stack, 0, "a");
+        push(&mut stack, 1, "b");
+        stack.hold_group();
+        push(&mut stack, 2, "c");
+        push(&mut stack, 3, "d");
+        stack.end_group();
+        assert_eq!(stack.undo_count(), 2);
+        // Held until the group ended
+        push(&mut stack, 4, "e");
+        push(&mut stack, 5, "f");
+        assert_eq!(stack.undo_count(), 4);
+        while stack.undo(&mut buffer, &mut cursor).unwrap() {}
+        assert_eq!(text(&buffer), "");
+    }
+
+    /// A group of `(start, end, text)` replacements made on `buffer` and
+    /// `reference` alike
+    fn change(stack: &mut UndoStack, buffer: &mut Buffer, reference: &mut String, edits: &[(usize, usize, &str)]) {
+        stack.begin_group();
+        for &(start, end, text) in edits {
+            stack.push(replace(buffer, reference, start..end, text));
+        }
+        stack.end_group();
+    }
+
+    #[test]
+    fn test_undo_in() {
+        let original = "[{\"a\": 1}, {\"b\": 2}, {\"c\": 3}]";
+        let mut reference = original.to_string();
+        let mut buffer = Buffer::from_text(original);
+        let mut stack = UndoStack::new();
+        let mut cursor = Cursor::new();
+        change(&mut stack, &mut buffer, &mut reference, &[(7, 8, "10")]);
+        change(&mut stack, &mut buffer, &mut reference, &[(18, 19, "20"), (13, 13, "\"x\": 0, ")]);
+        change(&mut stack, &mut buffer, &mut reference, &[(37, 38, "30")]);
+        assert_eq!(reference, "[{\"a\": 10}, {\"x\": 0, \"b\": 20}, {\"c\": 30}]");
+
+        // The first record's change goes; the later ones stay where they are
+        let message = stack.undo_in(1..11, &mut buffer, &mut cursor).unwrap();
+        assert!(message.starts_with("Undid change 1 (change, ") && message.ends_with(", keeping 2 newer"), "{}", message);
+        assert_eq!(text(&buffer), "[{\"a\": 1}, {\"x\": 0, \"b\": 20}, {\"c\": 30}]");
+        assert_eq!((cursor.byte_offset, stack.last_change(&buffer)), (7, Some(7)));
+        assert_eq!(stack.undo_count(), 2);
+        // And the history still leads back to the original and forward again
+        let states = ["[{\"a\": 1}, {\"x\": 0, \"b\": 20}, {\"c\": 3}]", original];
+        for state in states {
+            assert!(stack.undo(&mut buffer, &mut cursor).unwrap());
+            assert_eq!(text(&buffer), state);
+        }
+        stack.redo_many(2, &mut buffer, &mut cursor).unwrap();
+        assert_eq!(text(&buffer), "[{\"a\": 1}, {\"x\": 0, \"b\": 20}, {\"c\": 30}]");
+
+        assert_eq!(stack.undo_in(1..9, &mut buffer, &mut cursor).unwrap_err().to_string(), "No change to undo here");
+        // A region holding the newest change is the same as u, with nothing
+        // left to redo
+        stack.undo_in(0..buffer.len_bytes(), &mut buffer, &mut cursor).unwrap();
+        assert_eq!(text(&buffer), "[{\"a\": 1}, {\"x\": 0, \"b\": 20}, {\"c\": 3}]");
+        assert!(!stack.can_redo());
+    }
+
+    #[test]
+    fn test_undo_in_blocked() {
+        let original = "[{\"a\": 1}, {\"b\": 2}]";
+        let mut reference = original.to_string();
+        let mut buffer = Buffer::from_text(original);
+        let mut stack = UndoStack::new();
+        let mut cursor = Cursor::new();
+        change(&mut stack, &mut buffer, &mut reference, &[(7, 8, "10")]);
+        // Over the first record's change and into the second
+        stack.begin_labeled_group("sort 2 elements");
+        stack.push(replace(&mut buffer, &mut reference, 18..19, "5"));
+        stack.push(replace(&mut buffer, &mut reference, 7..9, "11"));
+        stack.end_group();
+        let before = text(&buffer);
+        let error = stack.undo_in(1..11, &mut buffer, &mut cursor).unwrap_err().to_string();
+        assert!(error.starts_with("Change 1 (change) can't be undone alone: change 2 (sort 2 elements, "), "{}", error);
+        assert_eq!((text(&buffer), stack.undo_count()), (before, 2));
+
+        // Right next to it counts too: which came first would decide where
+        // the text goes
+        let mut reference = "ab".to_string();
+        let mut buffer = Buffer::from_text(&reference);
+        let mut stack = UndoStack::new();
+        change(&mut stack, &mut buffer, &mut reference, &[(1, 1, "X")]);
+        change(&mut stack, &mut buffer, &mut reference, &[(2, 2, "Y")]);
+        change(&mut stack, &mut buffer, &mut reference, &[(4, 4, "Z")]);
+        assert!(stack.undo_in(1..2, &mut buffer, &mut cursor).is_err());
+        stack.undo_in(4..5, &mut buffer, &mut cursor).unwrap();
+        assert_eq!(text(&buffer), "aXYb");
+    }
+
+    #[test]
+    fn test_undo_in_branches() {
+        let original = "[{\"a\": 1}, {\"b\": 2}]";
+        let mut reference = original.to_string();
+        let mut buffer = Buffer::from_text(original);
+        let mut stack = UndoStack::new();
+        let mut cursor = Cursor::new();
+        change(&mut stack, &mut buffer, &mut reference, &[(7, 8, "10")]);
+        change(&mut stack, &mut buffer, &mut reference, &[(18, 19, "20")]);
+        stack.undo(&mut buffer, &mut cursor).unwrap();
+        // A branch that touches the change taken back can't stay
+        reference = text(&buffer);
+        change(&mut stack, &mut buffer, &mut reference, &[(8, 8, "0")]);
+        stack.undo(&mut buffer, &mut cursor).unwrap();
+        reference = text(&buffer);
+        change(&mut stack, &mut buffer, &mut reference, &[(18, 19, "30")]);
+        assert_eq!(text(&buffer), "[{\"a\": 10}, {\"b\": 30}]");
+
+        let message = stack.undo_in(1..11, &mut buffer, &mut cursor).unwrap();
+        assert!(message.ends_with(", keeping 1 newer; dropped 1 undo branch it ran into"), "{}", message);
+        assert_eq!(text(&buffer), "[{\"a\": 1}, {\"b\": 30}]");
+        // The other branch is there without the change too
+        assert_eq!(stack.branches().iter().map(|branch| (branch.seq, branch.changes)).collect::<Vec<_>>(), [(2, 1), (4, 1)]);
+        assert!(stack.earlier(&mut buffer, &mut cursor).unwrap());
+        assert_eq!(text(&buffer), "[{\"a\": 1}, {\"b\": 20}]");
+        assert!(stack.earlier(&mut buffer, &mut cursor).unwrap());
+        assert_eq!(text(&buffer), original);
+    }
+
+    /// A byte of the text as the history made it: which group put it there
+    /// (0 for the original) and which took it out again
+    #[derive(Clone, Copy)]
+    struct Traced {
+        byte: u8,
+        by: usize,
+        gone: Option<usize>,
+    }
+
+    /// Replace `range` of what `traced` shows, for group `by`, leaving what
+    /// it takes out in place but gone
+    fn trace(traced: &mut Vec<Traced>, range: std::ops::Range<usize>, text: &str, by: usize) {
+        let shown: Vec<usize> = (0..traced.len()).filter(|&i| traced[i].gone.is_none()).collect();
+        let at = shown.get(range.start).copied().unwrap_or(traced.len());
+        for &i in &shown[range.clone()] {
+            traced[i].gone = Some(by);
+        }
+        let bytes = text.bytes().map(|byte| Traced { byte, by, gone: None });
+        traced.splice(at..at, bytes);
+    }
+
+    #[test]
+    fn test_undo_in_random() {
+        let mut state = 0x9e3779b97f4a7c15u64;
+        let mut next = |limit: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % (limit as u64 + 1)) as usize
+        };
+        let original: String = (0..6).map(|i| format!("{{\"id\": {}, \"v\": [1, 2]}}\n", i)).collect();
+        let (mut undone, mut refused, mut nothing) = (0, 0, 0);
+        for _ in 0..300 {
+            let mut reference = original.clone();
+            let mut buffer = Buffer::from_text(&original);
+            let mut traced: Vec<Traced> = original.bytes().map(|byte| Traced { byte, by: 0, gone: None }).collect();
+            let mut stack = UndoStack::new();
+            let mut cursor = Cursor::new();
+            let groups = 2 + next(8);
+            for group in 1..=groups {
+                // Mostly near each other, as a group's edits usually are,
+                // else anywhere, as a bulk change's are
+                let near = next(reference.len());
+                let spread = if next(2) == 0 { reference.len() } else { 12 };
+                stack.begin_group();
+                for _ in 0..1 + next(2) {
+                    let start = (near + next(spread)).min(reference.len());
+                    let end = start + next(reference.len() - start).min(4);
+                    let text = &"0123456789abc"[next(8)..][..next(4)];
+                    trace(&mut traced, start..end, text, group);
+                    stack.push(replace(&mut buffer, &mut reference, start..end, text));
+                }
+                stack.end_group();
+            }
+            let start = next(reference.len());
+            let region = start..start + next(reference.len() - start);
+            match stack.undo_in(region.clone(), &mut buffer, &mut cursor) {
+                Ok(message) => {
+                    undone += 1;
+                    let group: usize = message.split(' ').nth(2).unwrap().parse().unwrap();
+                    // What the group put in that's still there lies in the region
+                    let shown: Vec<&Traced> = traced.iter().filter(|traced| traced.gone.is_none()).collect();
+                    let placed = (0..shown.len()).filter(|&i| shown[i].by == group);
+                    assert!(placed.clone().all(|i| region.contains(&i)), "{:?} {}", region, message);
+                    // The text is what the other groups alone would make
+                    let expected: Vec<u8> = traced
+                        .iter()
+                        .filter(|traced| traced.by != group && traced.gone.is_none_or(|by| by == group))
+                        .map(|traced| traced.byte)
+                        .collect();
+                    assert_eq!(text(&buffer), String::from_utf8(expected).unwrap(), "{}", message);
+                    // and the rest of the history still undoes and redoes
+                    let now = text(&buffer);
+                    assert_eq!(stack.undo_many(groups, &mut buffer, &mut cursor).unwrap(), groups - 1);
+                    assert_eq!(text(&buffer), original);
+                    stack.redo_many(groups, &mut buffer, &mut cursor).unwrap();
+                    assert_eq!(text(&buffer), now);
+                }
+                Err(error) => {
+                    match error.to_string().as_str() {
+                        "No change to undo here" => nothing += 1,
+                        _ => refused += 1,
+                    }
+                    assert_eq!(text(&buffer), reference, "{}", error);
+                    assert_eq!(stack.undo_count(), groups);
+                }
+            }
+        }
+        assert!(undone > 50 && refused > 20 && nothing > 10, "{} undone, {} refused, {} with nothing", undone, refused, nothing);
+    }
+}
@@ -0,0 +1,275 @@
+//! Keeping undo history between sessions for :set undofile. The history of
+//! each file goes to a file of its own under ~/.local/state/jim/undo, tagged
+//! with the size and modification time the file had when it was saved, and
+//! is read back only when the file still matches. Anything that doesn't
+//! parse is ignored rather than half loaded.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+
+use super::undo::{EditGroup, UndoStack};
+use super::{CursorState, Edit};
+use crate::config;
+
+const MAGIC: &str = "jim-undo";
+const VERSION: u32 = 2;
+
+/// Default bound on an undo file, in megabytes
+pub const DEFAULT_MAX_MB: usize = 10;
+
+/// What identifies a version of a file on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    /// Modification time as seconds and nanoseconds since the epoch
+    pub modified: (u64, u32),
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(Self { size: metadata.len(), modified: (modified.as_secs(), modified.subsec_nanos()) })
+    }
+}
+
+/// Where the history of `file` is kept
+pub fn undo_path(file: &Path) -> Option<PathBuf> {
+    config::state_file("undo", file)
+}
+
+/// Write the history of `file` as it is now on disk
+pub fn save(stack: &UndoStack, file: &Path, max_bytes: usize) -> Result<()> {
+    let path = undo_path(file).context("No home directory for the undo file")?;
+    let stamp = FileStamp::of(file)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Can't create {}", dir.display()))?;
+    }
+    // Written aside and renamed, so a crash never leaves half a file
+    let temp = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&temp).with_context(|| format!("Can't create {}", temp.display()))?);
+    write(stack, stamp, max_bytes, &mut out)?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&temp, &path)?;
+    Ok(())
+}
+
+/// The history saved for `file`, if there is one and it belongs to the
+/// file as it is on disk now
+pub fn load(file: &Path) -> Option<UndoStack> {
+    let input = File::open(undo_path(file)?).ok()?;
+    read(&mut BufReader::new(input), FileStamp::of(file).ok()?).ok()
+}
+
+/// Serialize the committed groups of `stack`, newest kept first when they
+/// don't all fit in `max_bytes`
+pub fn write(stack: &UndoStack, stamp: FileStamp, max_bytes: usize, out: &mut impl Write) -> Result<()> {
+    let mut kept = Vec::new();
+    let mut size = 0;
+    for group in stack.groups().iter().rev() {
+        let mut bytes = Vec::new();
+        write_group(group, &mut bytes)?;
+        size += bytes.len();
+        if size > max_bytes {
+            break;
+        }
+        kept.push(bytes);
+    }
+
+    writeln!(out, "{} {}", MAGIC, VERSION)?;
+    writeln!(out, "stamp {} {} {}", stamp.size, stamp.modified.0, stamp.modified.1)?;
+    for bytes in kept.iter().rev() {
+        out.write_all(bytes)?;
+    }
+    writeln!(out, "end {}", kept.len())?;
+    Ok(())
+}
+
+fn write_group(group: &EditGroup, out: &mut impl Write) -> Result<()> {
+    let time = group.timestamp().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let label = group.label().unwrap_or_default();
+    writeln!(out, "group {} {} {}", time, group.edits().len(), label.len())?;
+    writeln!(out, "{}", label)?;
+    for edit in group.edits() {
+        let (b, a) = (edit.cursor_before, edit.cursor_after);
+        writeln!(
+            out,
+            "edit {} {} {} {} {} {} {} {} {}",
+            edit.offset,
+            edit.old_text.len(),
+            edit.new_text.len(),
+            b.line,
+            b.col,
+            b.byte_offset,
+            a.line,
+            a.col,
+            a.byte_offset
+        )?;
+        out.write_all(edit.old_text.as_bytes())?;
+        out.write_all(edit.new_text.as_bytes())?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Parse what `write` wrote, refusing anything from another version, for
+/// another `stamp`, or cut short
+pub fn read(input: &mut impl BufRead, stamp: FileStamp) -> Result<UndoStack> {
+    let header = line(input)?;
+    if header != format!("{} {}", MAGIC, VERSION) {
+        bail!("Not a version {} undo file", VERSION);
+    }
+    let saved = numbers(&line(input)?, "stamp", 3)?;
+    if saved != [stamp.size, stamp.modified.0, stamp.modified.1 as u64] {
+        bail!("Undo file is for another version of the file");
+    }
+
+    let mut groups = Vec::new();
+    loop {
+        let text = line(input)?;
+        if let Ok(end) = numbers(&text, "end", 1) {
+            if end[0] as usize != groups.len() {
+                bail!("Undo file is incomplete");
+            }
+            return Ok(UndoStack::from_groups(groups));
+        }
+        let [time, count, label_len] = numbers(&text, "group", 3)?[..] else { unreachable!() };
+        let label = text_of(input, label_len)?;
+        if !line(input)?.is_empty() {
+            bail!("Malformed group in undo file");
+        }
+        let mut edits = Vec::new();
+        for _ in 0..count {
+            let fields = numbers(&line(input)?, "edit", 9)?;
+            let [offset, old_len, new_len, ..] = fields[..] else { unreachable!() };
+            let cursor = |at: usize| CursorState {
+                line: fields[at] as usize,
+                col: fields[at + 1] as usize,
+                byte_offset: fields[at + 2] as usize,
+            };
+            let old_text = text_of(input, old_len)?;
+            let new_text = text_of(input, new_len)?;
+            if !line(input)?.is_empty() {
+                bail!("Malformed edit in undo file");
+            }
+            edits.push(Edit::new(offset as usize, old_text, new_text, cursor(3), cursor(6)));
+        }
+        let group = EditGroup::with_timestamp(edits, UNIX_EPOCH + Duration::from_secs(time));
+        groups.push(group.labeled(Some(label).filter(|label| !label.is_empty())));
+    }
+}
+
+/// The next line, without its newline; running out is an error
+fn line(input: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        bail!("Undo file ends early");
+    }
+    if line.pop() != Some('\n') {
+        bail!("Undo file ends early");
+    }
+    Ok(line)
+}
+
+/// The `count` numbers after `tag` on `line`
+fn numbers(line: &str, tag: &str, count: usize) -> Result<Vec<u64>> {
+    let mut words = line.split(' ');
+    if words.next() != Some(tag) {
+        bail!("Expected {} in undo file", tag);
+    }
+    let numbers = words.map(|word| word.parse::<u64>()).collect::<Result<Vec<_>, _>>()?;
+    if numbers.len() != count {
+        bail!("Malformed {} in undo file", tag);
+    }
+    Ok(numbers)
+}
+
+fn text_of(input: &mut impl BufRead, len: u64) -> Result<String> {
+    let mut bytes = Vec::new();
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        bail!("Undo file ends early");
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::cursor::Cursor;
+    use crate::buffer::Buffer;
+
+    const STAMP: FileStamp = FileStamp { size: 14, modified: (1_700_000_000, 5) };
+
+    fn edit(offset: usize, old: &str, new: &str) -> Edit {
+        let at = CursorState { line: 0, col: offset, byte_offset: offset };
+        Edit::new(offset, old.to_string(), new.to_string(), at, at)
+    }
+
+    fn history() -> UndoStack {
+        let mut stack = UndoStack::new();
+        stack.begin_labeled_group("add b");
+        stack.push(edit(1, "", "\"b\": 2, "));
+        stack.end_group();
+        stack.begin_group();
+        stack.push(edit(6, "2", ""));
+        stack.push(edit(6, "", "3"));
+        stack.push(edit(14, "1", ""));
+        stack.push(edit(14, "", "\"e\nx\""));
+        stack.end_group();
+        stack
+    }
+
+    fn serialized(stack: &UndoStack, max_bytes: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        write(stack, STAMP, max_bytes, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = serialized(&history(), usize::MAX);
+        let mut stack = read(&mut &bytes[..], STAMP).unwrap();
+        assert_eq!(stack.undo_count(), 2);
+        assert_eq!(serialized(&stack, usize::MAX), bytes);
+        assert_eq!(stack.groups()[0].label(), Some("add b"));
+
+        // The reopened file is where the session left it, and u walks back
+        let mut buffer = Buffer::from_text("{\"b\": 3, \"a\": \"e\nx\"}");
+        let mut cursor = Cursor::new();
+        assert!(stack.undo(&mut buffer, &mut cursor).unwrap());
+        assert_eq!(buffer.slice(0..buffer.len_bytes()), "{\"b\": 2, \"a\": 1}");
+        assert_eq!(cursor.byte_offset, 6);
+        assert!(stack.undo(&mut buffer, &mut cursor).unwrap());
+        assert_eq!(buffer.slice(0..buffer.len_bytes()), "{\"a\": 1}");
+        assert!(!stack.undo(&mut buffer, &mut cursor).unwrap());
+    }
+
+    #[test]
+    fn test_rejects_mismatch() {
+        let bytes = serialized(&history(), usize::MAX);
+        let other = FileStamp { size: 15, ..STAMP };
+        assert!(read(&mut &bytes[..], other).is_err());
+        // Cut anywhere, or from another version
+        for len in [0, 10, bytes.len() / 2, bytes.len() - 1] {
+            assert!(read(&mut &bytes[..len], STAMP).is_err());
+        }
+        let newer = String::from_utf8(bytes).unwrap().replacen("jim-undo 2", "jim-undo 3", 1);
+        assert!(read(&mut newer.as_bytes(), STAMP).is_err());
+    }
+
+    #[test]
+    fn test_max_size() {
+        let stack = history();
+        let full = serialized(&stack, usize::MAX);
+        // Room for only the newest group
+        let bytes = serialized(&stack, full.len() - 60);
+        let stack = read(&mut &bytes[..], STAMP).unwrap();
+        assert_eq!(stack.undo_count(), 1);
+        assert_eq!(read(&mut &serialized(&history(), 0)[..], STAMP).unwrap().undo_count(), 0);
+    }
+}
@@ -0,0 +1,174 @@
+//! :retab and :trimws: indentation made all spaces (or tabs, with :set
+//! noexpandtab), and trailing whitespace taken off, a line at a time. Lines
+//! are read one by one, so a large file is never copied out whole, and
+//! quotes are followed from line to line so whitespace inside a string that
+//! runs over a line break is left as it is.
+
+use std::ops::Range;
+
+use crate::buffer::{Buffer, Edit};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhitespaceOp {
+    /// :retab [n]: tabs `n` columns wide (shiftwidth if not given)
+    Retab(Option<usize>),
+    /// :trimws
+    Trim,
+}
+
+impl WhitespaceOp {
+    /// What the undo list calls it, with the lines it changed
+    pub fn label(self, lines: usize) -> String {
+        match self {
+            WhitespaceOp::Retab(_) => format!("retab {} lines", lines),
+            WhitespaceOp::Trim => format!("trimws {} lines", lines),
+        }
+    }
+}
+
+/// Where the text is between two bytes: in a string, or a comment, or not
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scan {
+    #[default]
+    Code,
+    String,
+    /// Just after a backslash in a string
+    Escape,
+    LineComment,
+    BlockComment,
+}
+
+impl Scan {
+    /// The state after `bytes`
+    pub fn over(mut self, bytes: &[u8]) -> Self {
+        let mut prev = 0;
+        for &byte in bytes {
+            self = match (self, byte) {
+                (Scan::Code, b'"') => Scan::String,
+                (Scan::Code, b'/') if prev == b'/' => Scan::LineComment,
+                (Scan::Code, b'*') if prev == b'/' => Scan::BlockComment,
+                (Scan::String, b'\\') => Scan::Escape,
+                (Scan::String, b'"') => Scan::Code,
+                (Scan::Escape, _) => Scan::String,
+                (Scan::LineComment, b'\n') => Scan::Code,
+                (Scan::BlockComment, b'/') if prev == b'*' => Scan::Code,
+                (state, _) => state,
+            };
+            // A slash that opened or closed a comment doesn't start another
+            prev = if prev == b'/' && byte == b'*' || prev == b'*' && byte == b'/' { 0 } else { byte };
+        }
+        self
+    }
+
+    pub fn in_string(self) -> bool {
+        matches!(self, Scan::String | Scan::Escape)
+    }
+}
+
+/// The replacements `op` makes to `lines` of `buffer`, one at most to a
+/// line, for tabs `width` columns wide, indenting with tabs unless
+/// `expand`. `start` is where the first line begins: inside a string when
+/// one runs into it from above. Only whitespace before a line's first
+/// token or after its last is changed, never that of a string; rows of a
+/// split long line are only lines where the file has a line break.
+pub fn edits(buffer: &Buffer, lines: Range<usize>, op: WhitespaceOp, width: usize, expand: bool, start: Scan) -> Vec<Edit> {
+    let width = match op {
+        WhitespaceOp::Retab(Some(n)) => n,
+        _ => width,
+    }
+    .max(1);
+    let mut edits = Vec::new();
+    let mut state = start;
+    let mut at_line_start = true;
+    let last = buffer.line_count();
+    for line_idx in lines.start..lines.end.min(last) {
+        let text = buffer.get_line(line_idx);
+        let content = text.strip_suffix('\n').unwrap_or(&text);
+        let has_break = content.len() < text.len() || line_idx + 1 == last;
+        let content = if has_break { content.strip_suffix('\r').unwrap_or(content) } else { content };
+        let offset = buffer.line_to_byte_offset(line_idx);
+
+        match op {
+            WhitespaceOp::Retab(_) if at_line_start && state == Scan::Code => {
+                let indent = content.len() - content.trim_start_matches([' ', '\t']).len();
+                let lead = &content[..indent];
+                let wanted = reindent(lead, width, expand);
+                if wanted != lead {
+                    edits.push(Edit { file_offset: offset, old_len: lead.len(), new_text: wanted });
+                }
+            }
+            WhitespaceOp::Trim if has_break => {
+                let kept = content.trim_end_matches([' ', '\t']).len();
+                let before = state.over(&content.as_bytes()[..kept]);
+                if kept < content.len() && !before.in_string() {
+                    edits.push(Edit { file_offset: offset + kept, old_len: content.len() - kept, new_text: String::new() });
+                }
+            }
+            _ => {}
+        }
+        state = state.over(text.as_bytes());
+        at_line_start = text.ends_with('\n');
+    }
+    edits
+}
+
+/// Indentation `lead` laid out again: as spaces, or as tabs and then
+/// fewer than `width` spaces
+fn reindent(lead: &str, width: usize, expand: bool) -> String {
+    let columns = lead.chars().fold(0, |col, c| if c == '\t' { (col / width + 1) * width } else { col + 1 });
+    if expand {
+        " ".repeat(columns)
+    } else {
+        format!("{}{}", "\t".repeat(columns / width), " ".repeat(columns % width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(text: &str, op: WhitespaceOp, expand: bool) -> (String, usize) {
+        let mut buffer = Buffer::from_text(text);
+        let edits = edits(&buffer, 0..buffer.line_count(), op, 2, expand, Scan::Code);
+        buffer.apply_edits(&edits).unwrap();
+        (buffer.slice(0..buffer.len_bytes()), edits.len())
+    }
+
+    #[test]
+    fn test_retab() {
+        let text = "{\n\t\"a\": [\n\t\t1,\n\t  2\n\t],\n  \"b\": \"x\\ty\"\n}";
+        let (spaces, changed) = run(text, WhitespaceOp::Retab(None), true);
+        assert_eq!(spaces, "{\n  \"a\": [\n    1,\n    2\n  ],\n  \"b\": \"x\\ty\"\n}");
+        assert_eq!(changed, 4);
+        let (tabs, changed) = run(&spaces, WhitespaceOp::Retab(None), false);
+        assert_eq!(tabs, "{\n\t\"a\": [\n\t\t1,\n\t\t2\n\t],\n\t\"b\": \"x\\ty\"\n}");
+        assert_eq!(changed, 5);
+        // A width given to :retab, and a tab after spaces going to the next stop
+        assert_eq!(run("[\n\t1,\n \t2\n]", WhitespaceOp::Retab(Some(4)), true).0, "[\n    1,\n    2\n]");
+        assert_eq!(run("[\n   1\n]", WhitespaceOp::Retab(None), false).0, "[\n\t 1\n]");
+    }
+
+    #[test]
+    fn test_trim() {
+        let text = "{  \r\n  \"a\": 1, \t\n\n   \n  \"b\": [] // note  \n}  ";
+        let (trimmed, changed) = run(text, WhitespaceOp::Trim, true);
+        assert_eq!(trimmed, "{\r\n  \"a\": 1,\n\n\n  \"b\": [] // note\n}");
+        assert_eq!(changed, 5);
+        assert_eq!(run(&trimmed, WhitespaceOp::Trim, true).1, 0);
+    }
+
+    #[test]
+    fn test_strings_across_lines() {
+        // A string running over line breaks keeps its spaces and tabs, and
+        // a quote inside a comment or escaped doesn't start or end one
+        let text = "[\"two  \n\t lines\",  \n\t\"q\\\"  \n\t\" /* \" */  \n\t1 ]";
+        assert_eq!(run(text, WhitespaceOp::Trim, true).0, "[\"two  \n\t lines\",\n\t\"q\\\"  \n\t\" /* \" */\n\t1 ]");
+        assert_eq!(run(text, WhitespaceOp::Retab(None), true).0, "[\"two  \n\t lines\",  \n  \"q\\\"  \n\t\" /* \" */  \n  1 ]");
+
+        // A range starting inside a string
+        let buffer = Buffer::from_text("\t inside\"\n\tout");
+        let edits = edits(&buffer, 0..2, WhitespaceOp::Retab(None), 2, true, Scan::String);
+        assert_eq!(edits.len(), 1);
+        assert_eq!((edits[0].file_offset, edits[0].old_len), (10, 1));
+    }
+}
@@ -1,8 +1,11 @@
 pub mod buffer;
+pub mod config;
 pub mod edit;
 pub mod mode;
 pub mod navigation;
 pub mod parser;
+pub mod session;
+pub mod signals;
 pub mod ui;
 
 pub use buffer::Buffer;
@@ -1,37 +1,198 @@
 use anyhow::Result;
 use crossterm::{
-    cursor::SetCursorStyle,
-    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    cursor::{SetCursorStyle, Show},
+    event::{self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
+    layout::Rect,
+    style::{Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Paragraph},
+    widgets::{Block, Borders, Clear, Paragraph},
     Terminal,
 };
-use std::io::{stdout, Stdout};
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{stdout, BufWriter, Stdout, Write};
+use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
-mod buffer;
-mod edit;
-mod mode;
-mod navigation;
-mod parser;
-mod ui;
-
-use buffer::{Buffer, Cursor};
-use ui::viewport::Viewport;
-use parser::{Tokenizer, StructuralIndex};
-use mode::{Mode, ModeHandler, EditorContext, InputResult, normal::NormalMode, insert::InsertMode, RegisterMap, PendingOperator, StructuralNavAction};
-use edit::undo::UndoStack;
+use json_tool::buffer::{checksum::{self, Checksum}, counts, Buffer, Cursor, Prefetcher, SaveOutcome, SavePlan, ViewLines};
+use json_tool::ui::capabilities::{self, Capabilities};
+use json_tool::ui::elide::{self, Piece};
+use json_tool::ui::fold::{FoldSet, FoldSpan, FoldedLines};
+use json_tool::ui::format::NumberFormat;
+use json_tool::ui::frame_stats::FrameTimes;
+use json_tool::ui::perf::{self, PerfMetrics, PerfSample};
+use json_tool::ui::redraw::Redraw;
+use json_tool::ui::gutter::Gutter;
+use json_tool::ui::signs::{SignColumn, SignKind, Signs};
+use json_tool::ui::help::{HelpLine, HelpView};
+use json_tool::ui::stats;
+use json_tool::ui::inspect::{self, Inspect};
+use json_tool::ui::string_editor::{EditorOutcome, StringEditor};
+use json_tool::ui::highlight::{colorize_from_index, colorize_json_line};
+use json_tool::ui::matchparen;
+use json_tool::ui::line_cache::{LineCache, Overlay};
+use json_tool::ui::list;
+use json_tool::ui::layout::{render_too_small, text_pane, ScreenLayout};
+use json_tool::ui::mouse::{ScreenMap, ScreenRow};
+use json_tool::ui::picker::{self, Picker, PickerOutcome};
+use json_tool::ui::prompt::{Choice, Prompt, PromptOutcome};
+use json_tool::ui::preview::{self, ValuePreview};
+use json_tool::ui::scrollbar;
+use json_tool::ui::statusline::{format_status, StatusInfo, DEFAULT_STATUSLINE, SPLIT_LINES_STATUSLINE};
+use json_tool::ui::terminal::TerminalGuard;
+use json_tool::ui::theme::Theme;
+use json_tool::ui::tree::{Focus, TreeRowKind, TreeView};
+use json_tool::ui::loclist::{self, Location, LocationList, Severity};
+use json_tool::ui::viewport::Viewport;
+use json_tool::ui::window::{self, View, Windows};
+use json_tool::ui::tasks::{TaskKind, TaskManager};
+use json_tool::parser::diff::{self, ChangeKind, Diff, Progress, Side};
+use json_tool::parser::decode;
+use json_tool::parser::dump;
+use json_tool::parser::dupkeys::{DupKeys, DuplicateKey, DuplicateScan};
+use json_tool::parser::frontier::{INDEX_CHUNK_LINES, INDEX_MARGIN};
+use json_tool::parser::node::ChildCount;
+use json_tool::parser::schema::{self, Diagnostic, Schema};
+use json_tool::parser::shape::{self, Shape};
+use json_tool::parser::structural_index;
+use json_tool::parser::window::{IndexWindow, WINDOW_BYTES};
+use json_tool::parser::yaml::{self, YamlError};
+use json_tool::parser::{DocumentFormat, NodeKind, Staleness, StructuralError, StructuralErrorKind, StructuralIndex, IndexLimits, OpenContainers, IndexRequest, ParserResponse, ParserThread, IndexFrontier, ChunkText};
+use json_tool::config::Config;
+use json_tool::navigation::{self, accel::{self, Acceleration}, path::{self, json_path, Resolved, Segment}, ItemPositions};
+use json_tool::navigation::filter::{self, Filtered, Predicate};
+use json_tool::navigation::bookmarks::{Bookmarks, Place};
+use json_tool::navigation::extract::{Extraction, Pattern};
+use json_tool::navigation::jumps::JumpList;
+use json_tool::navigation::outline;
+use json_tool::navigation::search::{Match, NodeSearch, Scope, Search};
+use json_tool::mode::{self, command::PaletteCommand, Action, Binding, Mode, ModeHandler, EditorContext, InputResult, AppCommand, BookmarkOp, FoldAction, HorizontalScroll, NodeOp, PipeRange, ScriptOp, SearchScope, StructuralNavAction, clipboard, normal::NormalMode, insert::InsertMode, RegisterMap, PendingOperator, Continuation, Keymap, KeyQueue, Lookup, format_keys, EscapeSequences, DEFAULT_TIMEOUTLEN, DEFAULT_TTIMEOUTLEN};
+use json_tool::edit::{array, check, complete, diff::against_saved, export, guard::{self, Guard}, join::{self, JoinStyle}, paste::{self, PasteBehavior}, renameall::{self, Confirming, RenameAll}, rewrap::{self, WrapStyle}, undofile::{self, FileStamp}, whitespace::{self, WhitespaceOp}};
+use json_tool::session::{self, Session};
+use json_tool::edit::ndjson::{self, Direction};
+use json_tool::edit::pipe::PipeJob;
+use json_tool::edit::script::{EditScript, Step};
+use json_tool::edit::structural::{self, Conversion};
+use json_tool::edit::undo::{format_age, UndoStack};
+use json_tool::edit::{Edit, EditOperations};
 use std::time::Instant as StdInstant;
 
+/// How long typing must pause before the schema is checked again
+const SCHEMA_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How long typing must pause before an edited document is reindexed
+const REINDEX_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// How long a message stays in the status bar
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Largest buffer reindexed on its own after edits; bigger ones wait for
+/// a command that needs the index
+const REINDEX_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Groups :undolist shows on the way back from the current state
+const UNDO_LIST_LENGTH: usize = 50;
+
+/// Most events handled before drawing again, so a held key or fast input
+/// can't queue up a frame's wait behind each event
+const EVENTS_PER_FRAME: usize = 256;
+
+/// Where the cursor starts, from the command line
+enum StartAt {
+    /// +N, 1-based; a bare + is the last line
+    Line(Option<usize>),
+    /// +/text: the first occurrence
+    Search(String),
+    /// --path: resolved as the index reaches it
+    Path(String),
+}
+
+/// What a picker entry leads to
+enum Pick {
+    /// :keys - the key starting at this offset
+    Key(usize),
+    /// :outline - a section's key or first element
+    Node(usize),
+    /// The command palette - an action, with the count typed before it
+    Action(Action, Option<usize>),
+    Command(PaletteCommand),
+}
+
+/// Key completion in insert mode: the candidates, with the one in the
+/// buffer selected, and the text they replace
+struct Completion {
+    picker: Picker<()>,
+    /// Where the key's text starts
+    start: usize,
+    /// What was typed before completing, put back by Esc
+    prefix: String,
+}
+
+/// What an answer to a prompt in the status line does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromptAction {
+    /// :q with unsaved changes, or a :wq whose save failed: save them
+    /// (again), or not
+    Quit { save: bool },
+    /// :bd with unsaved changes: save them, then close, or just close
+    CloseBuffer { save: bool },
+    /// :w of a large file: go ahead with the write
+    Write,
+    /// :renameall c, about the key under the cursor
+    Rename(renameall::Answer),
+    Cancel,
+}
+
+/// A :diff running in the background, with its progress, the file and the
+/// buffer revision it reads
+type DiffJob = (JoinHandle<Result<Diff>>, Arc<Progress>, String, u64);
+
+/// A :checksum running in the background, with its progress, whether to
+/// copy the hash when it's done and the buffer revision it reads
+type ChecksumJob = (JoinHandle<Result<Checksum>>, Arc<checksum::Progress>, bool, u64);
+
+/// The top-level shape being found in the background, the buffer revision
+/// it reads and the load message to add it to
+type ShapeJob = (JoinHandle<Option<Shape>>, u64, Option<String>);
+
+/// A :to-ndjson or :from-ndjson running in the background, with the buffer
+/// revision it reads
+type ConvertJob = (JoinHandle<Result<(String, usize)>>, Direction, u64);
+
+/// An :extract following the index as it's built
+struct ExtractJob {
+    extraction: Extraction,
+    /// The path as typed
+    path: String,
+    /// The file being written to, or else the values for the location list
+    out: Option<(String, BufWriter<File>)>,
+    locations: Vec<Location>,
+    /// Buffer revision and index generation it started on
+    revision: u64,
+    generation: u64,
+}
+
+/// `jim --apply`: the script's steps being run on one file
+struct Replay {
+    /// The file, as reports name it
+    file: String,
+    steps: VecDeque<Step>,
+    total: usize,
+    /// The step run last, numbered from 1, until it's known to have worked
+    running: Option<(usize, Step)>,
+}
+
 struct App {
     should_quit: bool,
+    quit_after_save: bool, // :wq or ZZ waiting for its save to be written
+    close_after_save: bool, // :bd waiting for its save to be written
+    tasks: TaskManager, // Work running in the background, for the status bar's spinner, :tasks and Ctrl-C
     buffer: Buffer,
     viewport: Viewport,
     cursor: Cursor,
@@ -41,10 +202,124 @@ struct App {
     structural_index: Option<StructuralIndex>,
     index_build_time: f64,
     current_node_id: Option<usize>, // Current node we're on
-    indexed_up_to_line: usize, // Last line that's been indexed
+    index_frontier: IndexFrontier, // How far the index reaches and is to reach
+    index_open_containers: OpenContainers, // Containers still open at the frontier
+    index_window: Option<IndexWindow>, // Indexed around the cursor, far past the index
+    index_depth: u32, // Nesting indexed before the rest is skipped
+    split_docs: bool, // Read the file as concatenated documents (:split-docs)
+    parser: ParserThread, // Builds index chunks off the UI thread
+    index_in_flight: Option<(usize, StdInstant)>, // End line and start time of the chunk being indexed
+    index_rebuilding: bool, // The index was thrown away after edits and isn't back yet
+    pending_nav: Option<(StructuralNavAction, usize)>, // Navigation (and its count) waiting for the index to catch up
+    pending_search: Option<(bool, usize)>, // n / N of a :key or :value search waiting for more of the index
+    pending_node: Option<(NodeOp, Option<char>, bool)>, // Node edit waiting for the index to be rebuilt, and whether it's forced
+    refused_edit: Option<(NodeOp, Option<char>)>, // Node edit :set strictedits last refused, for :force
+    check_edits: bool, // Warn when a node edit leaves its container invalid
+    strict_edits: bool, // Refuse such edits instead
+    pending_bookmark: Option<String>, // :bookmark add note waiting for the index to be rebuilt
+    pending_rename: Option<RenameAll>, // :renameall waiting for the whole file to be indexed
+    renaming: Option<Confirming>, // :renameall c going through its keys, each asked about in the status line
+    format: DocumentFormat, // Plain JSON, JSON Lines or JSONC
+    index_generation: u64, // Bumped when the index is discarded, to drop stale chunks
+    index_revision: u64, // Buffer revision the index was built from
+    node_summary: Option<((usize, usize, u64), String)>, // Status text for (node, index len, generation)
+    cursor_path: Option<((usize, usize, u64), String)>, // JSON path of the cursor's node, cached the same way
+    item_positions: ItemPositions, // Member positions for the status bar's %i and after motions, counted from the last one
+    jumps: JumpList, // Where long jumps left from, for Ctrl-o / Tab
+    outline: bool, // Whether the picker is :outline's, refreshed as the index grows
+    statusline: String, // Status bar template (:set statusline=)
+    numbers: NumberFormat, // How counts and sizes are shown (:set numberformat=)
+    folds: FoldSet, // Closed folds, by container byte range
+    fold_open: bool, // Open folds hiding the cursor after a jump (:set foldopen)
+    max_render_depth: Option<u32>, // Containers nested this deep are folded to a summary (:set maxrenderdepth=N)
+    depth_expand: bool, // Moving onto such a summary opens it rather than stepping over it (:set depthexpand)
+    deep_scan: (usize, Vec<usize>), // Nodes looked at for maxrenderdepth, and containers among them not closed yet
+    tree: TreeView, // Outline sidebar state
+    show_tree: bool, // Whether the outline sidebar is open (F2 / :tree)
+    loclist: Option<LocationList>, // Places found by :validate, the schema check or :grep, for ]q / [q
+    bookmarks: Bookmarks, // Notes on nodes (:bookmark), kept in the session by path
+    show_loclist: bool, // Whether the location list pane is open (:copen)
+    windows: Windows, // Text windows (:split / :vsplit); the focused one's cursor and viewport live in App
+    focus: Focus, // Pane receiving keys
+    show_numbers: bool, // Line number gutter (:set number)
+    relative_numbers: bool, // Hybrid relative numbering (:set relativenumber)
+    sign_column: SignColumn, // When the sign column is drawn (:set signcolumn)
+    signs: Signs, // Structural errors and warnings marked in the sign column
+    error_signs: (u64, usize, usize), // Index generation and error and warning counts the signs were placed for
+    wrap: bool, // Soft-wrap long lines instead of scrolling sideways (:set wrap)
+    list: bool, // Show tabs, trailing spaces and invisible characters (:set list)
+    elide_strings: Option<usize>, // Characters kept of longer strings, the rest drawn as a marker (:set elide-strings=N)
+    shiftwidth: usize, // Spaces per indent level for node edits (:set shiftwidth=N)
+    expand_tab: bool, // :retab indents with spaces rather than tabs (:set expandtab)
+    text_width: usize, // Columns gq wraps strings at (:set textwidth=N)
+    wrap_style: WrapStyle, // Whether gq breaks a string with \n or into an array (:set wrapstyle)
+    format_paste: bool, // Lay out pasted JSON objects and arrays at the depth they land (:set formatpaste)
+    smart_comma: bool, // o opens an object member and dd takes its dangling comma (:set smartcomma)
+    expanded_strings: HashSet<usize>, // Start offsets of long strings shown in full (ze)
+    line_cache: LineCache<ColorStamp>, // Colorized lines from the last frame
+    view_lines: ViewLines, // Text of the lines the focused window shows
+    prefetcher: Prefetcher, // Reads the screens around the view of a large file ahead of drawing them
+    preview: Option<ValuePreview>, // K popup, closed by the next key
+    string_editor: Option<StringEditor>, // :edit-string overlay, which takes every key while open
+    completion: Option<Completion>, // Ctrl-n / Ctrl-p popup in insert mode
+    last_selection: Option<std::ops::Range<usize>>, // for :'<,'>
+    search: Option<Search>, // The last search, for n and N
+    pipe: Option<(PipeJob, std::ops::Range<usize>, u64)>, // running :pipe, the span it replaces and the revision it read
+    shellpipe: bool, // :pipe through sh -c rather than splitting words
+    undofile: bool, // Keep undo history across sessions
+    session: bool, // Keep and restore where each file was left (:set session)
+    undofile_size_mb: usize, // Bound on each undo file
+    undofile_revision: Option<u64>, // Buffer revision whose history is on disk
+    schema: Option<Arc<Schema>>, // :schema load
+    diagnostics: Vec<Diagnostic>, // Schema violations, in document order
+    dupkeys: DupKeys, // How duplicate object keys are reported (:set dupkeys)
+    duplicate_keys: DuplicateScan, // Duplicate keys found so far, for ]u / [u and :validate
+    duplicate_keys_generation: u64, // Index generation the duplicates were found in
+    schema_job: Option<(JoinHandle<Vec<Diagnostic>>, u64)>, // Validation running in the background and the revision it checks
+    filter_job: Option<(JoinHandle<Filtered>, u64, usize)>, // :filter running in the background, the revision and where the array starts
+    schema_due: Option<Instant>, // When to validate again after an edit
+    reindex_due: Option<Instant>, // When to rebuild the index after an edit
+    diff: Option<(String, Diff)>, // :diff file and its changes, kept on their text across edits
+    diff_job: Option<DiffJob>,
+    checksum_job: Option<ChecksumJob>,
+    shape_job: Option<ShapeJob>,
+    convert_job: Option<ConvertJob>,
+    extract: Option<ExtractJob>,
+    start_path: Option<(String, Vec<Segment>)>, // --path still waiting for the index to reach it
+    help: Option<HelpView>, // :help pane, shown over the text windows
+    inspect: Option<Inspect>, // Debug view the help pane shows (:tokens / :nodes), refreshed by r
+    picker: Option<Picker<Pick>>, // :keys and command palette fuzzy finder, which takes every key while open
+    prompt: Option<Prompt<PromptAction>>, // A question in the status line, which takes every key until answered
+    keys: KeyQueue, // Keys to handle before reading the terminal again
+    keymap: Keymap, // User key mappings from the config file
+    pending_keys: Vec<KeyEvent>, // Start of a key sequence still being typed
+    pending_since: Option<Instant>, // When a sequence that could be a mapping last grew, for the timeout
+    timeoutlen: u64, // Milliseconds a sequence that could be a mapping waits for its next key (:set timeoutlen=N)
+    ttimeoutlen: u64, // Milliseconds an Esc waits for the rest of a split escape sequence (:set ttimeoutlen=N)
+    escapes: EscapeSequences, // An Esc held back in case more of an escape sequence follows
+    script: Option<VecDeque<KeyEvent>>, // Keys left of --keys; jim quits once they've run
+    script_errors: Vec<String>, // Errors shown while --keys runs, for the exit status
+    edit_script: EditScript, // Edits :script start recorded, by path, for :script save
+    recording_script: bool, // Between :script start and :script stop
+    replay: Option<Replay>, // What --apply has still to run
+    theme: Theme, // Styles for everything drawn (:set theme=, --theme)
+    caps: Capabilities, // What the terminal can show: colors, non-ASCII characters, cursor shapes
+    show_scrollbar: bool, // Scrollbar over the text pane's right border (:set scrollbar)
+    brace_spacing: bool, // J leaves a space inside brackets it joins onto: { "a": 1 } (:set bracespacing)
+    nav_accel: Acceleration, // How long a structural motion has been held down
+    nav_accel_max: usize, // The step held-down motions speed up to (:set nav-accel=N, 1 for none)
+    guard: Guard, // Watches for edits that break the document's ends or delete most of it
+    deletewarn: usize, // Percent of the document one edit may delete unwarned (:set deletewarn=N, 0 for any)
+    nav_step: usize, // The step the last structural motion moved by, shown while above 1
+    node_preview: bool, // Show the first members of an object structural navigation lands on (:set nodepreview)
+    mouse: bool, // Capture the mouse (:set mouse=off leaves it to the terminal)
+    screen: ScreenMap, // Layout of the last frame, for mapping mouse clicks
+    drag_anchor: Option<usize>, // Byte offset a left-button drag started from
     max_index_size_mb: usize, // Max memory for index (default 500MB)
     show_performance: bool, // Toggle performance overlay with F12
-    frame_times: Vec<Duration>, // Track last 60 frame times
+    frame_times: FrameTimes, // Track last 60 frame times
+    perf: PerfMetrics, // The overlay's other figures, sampled a few times a second
+    redraw: Redraw, // Whether the screen needs drawing again
     // Phase 1 additions
     mode: Mode,
     normal_mode_handler: NormalMode,
@@ -57,12 +332,16 @@ struct App {
     // Message display
     message: Option<String>,
     message_time: Option<Instant>,
+    message_is_error: bool,
 }
 
 impl App {
     fn new() -> Self {
         Self {
             should_quit: false,
+            quit_after_save: false,
+            close_after_save: false,
+            tasks: TaskManager::new(),
             buffer: Buffer::new(),
             viewport: Viewport::new(0, 40), // Start at line 0, 40 lines visible
             cursor: Cursor::new(),
@@ -72,10 +351,129 @@ impl App {
             structural_index: None,
             index_build_time: 0.0,
             current_node_id: None,
-            indexed_up_to_line: 0,
-            max_index_size_mb: 500,
+            index_frontier: IndexFrontier::new(),
+            index_open_containers: OpenContainers::default(),
+            index_window: None,
+            index_depth: structural_index::DEFAULT_MAX_DEPTH,
+            split_docs: false,
+            parser: ParserThread::new(),
+            index_in_flight: None,
+            index_rebuilding: false,
+            pending_nav: None,
+            pending_search: None,
+            pending_node: None,
+            refused_edit: None,
+            check_edits: false,
+            strict_edits: false,
+            pending_bookmark: None,
+            pending_rename: None,
+            renaming: None,
+            format: DocumentFormat::Json,
+            index_generation: 0,
+            index_revision: 0,
+            node_summary: None,
+            cursor_path: None,
+            item_positions: ItemPositions::new(),
+            jumps: JumpList::new(),
+            outline: false,
+            statusline: DEFAULT_STATUSLINE.to_string(),
+            numbers: NumberFormat::default(),
+            folds: FoldSet::new(),
+            fold_open: true,
+            max_render_depth: None,
+            depth_expand: false,
+            deep_scan: (0, Vec::new()),
+            tree: TreeView::new(),
+            show_tree: false,
+            loclist: None,
+            bookmarks: Bookmarks::new(),
+            show_loclist: false,
+            windows: Windows::new(View {
+                cursor: Cursor::new(),
+                viewport: Viewport::new(0, 40),
+                current_node_id: None,
+                top: 0,
+            }),
+            focus: Focus::Text,
+            show_numbers: true,
+            relative_numbers: false,
+            sign_column: SignColumn::default(),
+            signs: Signs::new(),
+            error_signs: (0, 0, 0),
+            wrap: false,
+            list: false,
+            elide_strings: None,
+            shiftwidth: 2,
+            expand_tab: true,
+            text_width: rewrap::DEFAULT_TEXTWIDTH,
+            wrap_style: WrapStyle::default(),
+            format_paste: false,
+            smart_comma: false,
+            expanded_strings: HashSet::new(),
+            line_cache: LineCache::new(),
+            view_lines: ViewLines::new(),
+            prefetcher: Prefetcher::new(),
+            preview: None,
+            string_editor: None,
+            completion: None,
+            picker: None,
+            prompt: None,
+            last_selection: None,
+            search: None,
+            pipe: None,
+            shellpipe: false,
+            undofile: false,
+            session: true,
+            undofile_size_mb: undofile::DEFAULT_MAX_MB,
+            undofile_revision: None,
+            schema: None,
+            diagnostics: Vec::new(),
+            dupkeys: DupKeys::default(),
+            duplicate_keys: DuplicateScan::default(),
+            duplicate_keys_generation: 0,
+            schema_job: None,
+            filter_job: None,
+            schema_due: None,
+            reindex_due: None,
+            diff: None,
+            diff_job: None,
+            checksum_job: None,
+            shape_job: None,
+            convert_job: None,
+            extract: None,
+            start_path: None,
+            help: None,
+            inspect: None,
+            keys: KeyQueue::new(),
+            keymap: Keymap::new(),
+            pending_keys: Vec::new(),
+            pending_since: None,
+            timeoutlen: DEFAULT_TIMEOUTLEN,
+            ttimeoutlen: DEFAULT_TTIMEOUTLEN,
+            escapes: EscapeSequences::new(),
+            script: None,
+            script_errors: Vec::new(),
+            edit_script: EditScript::new(),
+            recording_script: false,
+            replay: None,
+            theme: Theme::dark(),
+            caps: Capabilities::default(),
+            show_scrollbar: true,
+            brace_spacing: false,
+            nav_accel: Acceleration::default(),
+            nav_accel_max: accel::DEFAULT_MAX_STEP,
+            guard: Guard::default(),
+            deletewarn: guard::DEFAULT_DELETE_PERCENT,
+            nav_step: 1,
+            node_preview: false,
+            mouse: true,
+            screen: ScreenMap::default(),
+            drag_anchor: None,
+            max_index_size_mb: structural_index::DEFAULT_MAX_SIZE_MB,
             show_performance: false,
-            frame_times: Vec::with_capacity(60),
+            frame_times: FrameTimes::new(),
+            perf: PerfMetrics::new(),
+            redraw: Redraw::new(),
             // Phase 1 initialization
             mode: Mode::Normal,
             normal_mode_handler: NormalMode::new(),
@@ -87,686 +485,5854 @@ impl App {
             pending_operator: None,
             message: None,
             message_time: None,
+            message_is_error: false,
         }
     }
 
+    /// An App run without a terminal, as by --bench-open and --apply:
+    /// sessions and undo files are left alone so runs start the same way,
+    /// and no cursor shape is sent
+    fn headless() -> Self {
+        let mut app = Self::new();
+        app.session = false;
+        app.undofile = false;
+        app.caps.cursor_shape = false;
+        app
+    }
+
     fn load_file(&mut self, path: &str) -> Result<()> {
         let start = StdInstant::now();
-        self.buffer.load_file(path)?;
+        let yaml_error = self.open_buffer(path)?;
         let load_time = start.elapsed();
+        self.guard = Guard::new(&self.buffer);
         
-        eprintln!("File loaded in {:.2}s (indexed {} lines)", 
-                  load_time.as_secs_f64(), 
-                  self.buffer.line_count());
-        
-        // Build structural index incrementally (start with first 10000 lines)
-        self.expand_structural_index(10000)?;
-        
-        Ok(())
-    }
-    
-    fn expand_structural_index(&mut self, target_line: usize) -> Result<()> {
-        let total_lines = self.buffer.line_count();
-        
-        // Already indexed enough
-        if self.indexed_up_to_line >= target_line.min(total_lines) {
-            return Ok(());
-        }
-        
-        let index_start = StdInstant::now();
-        
-        // Index in chunks to avoid loading entire file at once
-        let chunk_size = 5000;
-        let start_line = self.indexed_up_to_line;
-        let end_line = (target_line + chunk_size).min(total_lines);
-        
-        // Get the text for this chunk
-        let chunk_text = self.buffer.get_visible_lines(start_line, end_line - start_line);
-        
-        // Tokenize the chunk
-        let mut tokenizer = Tokenizer::new(chunk_text);
-        let tokens = tokenizer.tokenize_all();
-        
-        // If this is the first chunk, create new index
-        if self.structural_index.is_none() {
-            self.structural_index = Some(StructuralIndex::from_tokens(&tokens));
+        let lines = if self.buffer.has_split_lines() { "rows (long lines split)" } else { "lines" };
+        let loaded = format!(
+            "Loaded {} {} in {:.2}s",
+            self.numbers.count(self.buffer.line_count()),
+            lines,
+            load_time.as_secs_f64()
+        );
+        if self.buffer.is_new_file() {
+            self.show_message(format!("\"{}\" [New File]", path));
         } else {
-            // Rebuild from scratch with extended range
-            // This is acceptable for now as incremental parsing is a Phase 3+ feature
-            let extended_text = self.buffer.get_visible_lines(0, end_line);
-            let mut tokenizer = Tokenizer::new(extended_text);
-            let tokens = tokenizer.tokenize_all();
-            self.structural_index = Some(StructuralIndex::from_tokens(&tokens));
+            self.show_message(loaded.clone());
+        }
+        // Pick up where the last session left off
+        self.undofile_revision = Some(self.buffer.revision());
+        if self.undofile && self.buffer.converted_from().is_none() {
+            if let Some(stack) = undofile::load(std::path::Path::new(path)) {
+                self.undo_stack = stack;
+            }
+        }
+        if self.session {
+            if let Some(session) = session::load(std::path::Path::new(path)) {
+                self.restore_session(session, true);
+            }
+            // Bookmarks are kept by path, so they're looked for even in a
+            // file that has changed
+            self.bookmarks = Bookmarks::from_saved(session::bookmarks(std::path::Path::new(path)));
         }
         
-        self.indexed_up_to_line = end_line;
-        self.index_build_time = index_start.elapsed().as_secs_f64();
+        // JSON Lines files are indexed record by record
+        let sample = self.buffer.get_visible_lines(0, 16);
+        self.format = DocumentFormat::detect(self.buffer.path().map(|p| p.as_path()), &sample);
+        self.split_docs = false;
+        self.index_open_containers = self.initial_open_containers();
+        self.start_shape(Some(loaded));
         
-        eprintln!("Indexed up to line {} ({} nodes, {:.3}s)",
-                  end_line,
-                  self.structural_index.as_ref().map(|i| i.len()).unwrap_or(0),
-                  self.index_build_time);
+        // Build structural index incrementally (start with first 10000 lines)
+        self.index_revision = self.buffer.revision();
+        self.request_structural_index(10000)?;
         
-        Ok(())
-    }
-
-    fn handle_event(&mut self, event: Event) -> Result<()> {
-        if let Event::Key(key) = event {
-            self.handle_key(key)?;
+        if let Some(e) = yaml_error {
+            self.move_cursor_to_offset(self.buffer.line_to_byte_offset(e.line - 1));
+            self.show_error(format!("Not converted: {}", e));
         }
         Ok(())
     }
-
-    fn handle_key(&mut self, key: KeyEvent) -> Result<()> {
-        // Global shortcuts (work in all modes)
-        match key.code {
-            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.should_quit = true;
-                return Ok(());
-            }
-            KeyCode::F(12) => {
-                self.show_performance = !self.show_performance;
-                return Ok(());
-            }
-            _ => {}
+    
+    /// Open `path` in the buffer. A YAML file is shown as JSON, read-only;
+    /// one that can't be converted opens as it is, and why is returned.
+    fn open_buffer(&mut self, path: &str) -> Result<Option<YamlError>> {
+        let file = std::path::Path::new(path);
+        if !yaml::is_yaml_path(file) || !file.is_file() {
+            self.buffer.open(path)?;
+            return Ok(None);
         }
-        
-        // Read current mode before borrowing
-        let current_mode = self.mode;
-        
-        // Capture cursor offset before borrowing (needed for visual mode initialization)
-        let cursor_offset = self.cursor.byte_offset;
-        
-        // Create editor context for mode handlers
-        let ctx = EditorContext {
-            buffer: &mut self.buffer,
-            cursor: &mut self.cursor,
-            mode: &mut self.mode,
-            undo_stack: &mut self.undo_stack,
-            register_map: &mut self.register_map,
-            pending_operator: &mut self.pending_operator,
-            structural_index: self.structural_index.as_ref(),
-        };
-        
-        // Route to appropriate mode handler based on saved mode value
-        let result = match current_mode {
-            Mode::Normal => self.normal_mode_handler.handle_key(key, ctx)?,
-            Mode::Insert => self.insert_mode_handler.handle_key(key, ctx)?,
-            Mode::Visual { line_wise } => {
-                // Initialize visual mode if not already active
-                if self.visual_mode_handler.is_none() {
-                    self.visual_mode_handler = Some(mode::visual::VisualMode::new(
-                        cursor_offset,
-                        line_wise,
-                    ));
-                }
-                
-                if let Some(ref mut handler) = self.visual_mode_handler {
-                    handler.handle_key(key, ctx)?
-                } else {
-                    InputResult::NotHandled
-                }
-            }
-            Mode::Command => {
-                self.command_mode_handler.handle_key(key, ctx)?
-            }
-        };
-        
-        // Handle mode handler results
-        match result {
-            InputResult::Handled => {
-                // Update viewport to follow cursor
-                self.update_viewport_for_cursor();
-            }
-            InputResult::ModeSwitch(new_mode) => {
-                // Clear visual mode handler when leaving visual mode
-                if !matches!(new_mode, Mode::Visual { .. }) {
-                    self.visual_mode_handler = None;
-                }
-                
-                // Change cursor style based on mode
-                let mut out = stdout();
-                let _ = match new_mode {
-                    Mode::Normal => out.execute(SetCursorStyle::SteadyBlock),
-                    Mode::Insert => out.execute(SetCursorStyle::SteadyBar),
-                    Mode::Visual { .. } => out.execute(SetCursorStyle::SteadyBlock),
-                    Mode::Command => out.execute(SetCursorStyle::SteadyUnderScore),
-                };
-                
-                self.mode = new_mode;
-                // Update viewport to follow cursor
-                self.update_viewport_for_cursor();
-            }
-            InputResult::Quit => {
-                self.should_quit = true;
-            }
-            InputResult::StructuralNav(action) => {
-                match action {
-                    mode::StructuralNavAction::NextSibling => self.navigate_next_sibling(),
-                    mode::StructuralNavAction::PrevSibling => self.navigate_prev_sibling(),
-                    mode::StructuralNavAction::Parent => self.navigate_parent(),
-                    mode::StructuralNavAction::FirstChild => self.navigate_first_child(),
-                    mode::StructuralNavAction::NextKey => self.navigate_next_key(),
-                    mode::StructuralNavAction::PrevKey => self.navigate_prev_key(),
-                    mode::StructuralNavAction::NextValue => self.navigate_next_value(),
-                    mode::StructuralNavAction::PrevValue => self.navigate_prev_value(),
-                }
-                self.update_viewport_for_cursor();
-            }
-            InputResult::ClearNodeTracking => {
-                // Cursor moved manually, invalidate cached node position
-                self.current_node_id = None;
-                self.update_viewport_for_cursor();
-            }
-            InputResult::NotHandled => {
-                // Fallback to structural navigation (kept from Phase 0)
-                match key.code {
-                    KeyCode::Char(']') if self.mode == Mode::Normal => {
-                        // Next sibling navigation - wait for 'j'
-                        if let Ok(true) = event::poll(Duration::from_millis(100)) {
-                            if let Ok(Event::Key(next_key)) = event::read() {
-                                if next_key.code == KeyCode::Char('j') {
-                                    self.navigate_next_sibling();
-                                    self.update_viewport_for_cursor();
-                                }
-                            }
-                        }
-                    }
-                    KeyCode::Char('[') if self.mode == Mode::Normal => {
-                        // Previous sibling navigation - wait for 'j'
-                        if let Ok(true) = event::poll(Duration::from_millis(100)) {
-                            if let Ok(Event::Key(next_key)) = event::read() {
-                                if next_key.code == KeyCode::Char('j') {
-                                    self.navigate_prev_sibling();
-                                    self.update_viewport_for_cursor();
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            InputResult::Message(msg) => {
-                // Display message for 3 seconds
-                self.message = Some(msg);
-                self.message_time = Some(Instant::now());
-                
-                // Return to normal mode after showing message
-                if matches!(self.mode, Mode::Command) {
-                    self.command_mode_handler.command_line.clear();
-                    self.mode = Mode::Normal;
-                    let mut out = stdout();
-                    let _ = out.execute(SetCursorStyle::SteadyBlock);
-                }
+        let text = std::fs::read_to_string(file).map_err(|e| anyhow::anyhow!("Can't open {}: {}", path, e))?;
+        match yaml::to_json(&text) {
+            Ok(json) => {
+                self.buffer.open_converted(Some(path), &json, "YAML");
+                Ok(None)
             }
-        }
-        
-        // Clear message after 3 seconds
-        if let Some(message_time) = self.message_time {
-            if message_time.elapsed().as_secs() >= 3 {
-                self.message = None;
-                self.message_time = None;
+            Err(e) => {
+                self.buffer.open(path)?;
+                Ok(Some(e))
             }
         }
-        
-        Ok(())
     }
     
-    fn update_viewport_for_cursor(&mut self) {
-        let viewport_height = self.viewport.height;
-        let cursor_line = self.cursor.line;
-        let start_line = self.viewport.start_line;
-        
-        // Keep cursor in view with some padding
-        if cursor_line < start_line {
-            // Cursor above viewport - scroll up
-            self.viewport.start_line = cursor_line;
-        } else if cursor_line >= start_line + viewport_height {
-            // Cursor below viewport - scroll down
-            self.viewport.start_line = cursor_line.saturating_sub(viewport_height - 1);
+    /// :yamlview - read the buffer as YAML and show its JSON form instead,
+    /// read-only
+    fn yaml_view(&mut self) -> Result<()> {
+        if self.buffer.converted_from().is_some() {
+            anyhow::bail!("Already shown as JSON");
         }
+        if self.buffer.is_modified() {
+            anyhow::bail!("No write since last change");
+        }
+        let json = yaml::to_json(&self.buffer.slice(0..self.buffer.len_bytes()))?;
+        let path = self.buffer.path().map(|path| path.to_string_lossy().into_owned());
+        self.buffer.open_converted(path.as_deref(), &json, "YAML");
+        self.format = DocumentFormat::Json;
+        self.split_docs = false;
+        self.text_replaced(0)?;
+        self.show_message(format!("Converted from YAML: {} lines of JSON, read-only", self.numbers.count(self.buffer.line_count())));
+        Ok(())
     }
     
-    fn navigate_next_sibling(&mut self) {
-        // Ensure we've indexed enough of the file
-        let target_line = self.cursor.line + 1000; // Look ahead
-        let _ = self.expand_structural_index(target_line);
-        
-        if let Some(ref index) = self.structural_index {
-            // Find current node or node at current byte offset
-            let current_node = if let Some(node_id) = self.current_node_id {
-                node_id
-            } else {
-                // Find node at current byte offset
-                if let Some(node) = index.node_at(self.cursor.byte_offset) {
-                    // node_at returns &NodeInfo, we need to find its index
-                    // Use the node's byte offset to find its ID in the index
-                    let node_id = index.nodes().iter().position(|n| n.start == node.start).unwrap_or(0);
-                    self.current_node_id = Some(node_id);
-                    node_id
-                } else {
-                    return;
-                }
-            };
-            
-            // Get next sibling - returns NodeId (usize)
-            if let Some(next_sibling_id) = index.next_sibling(current_node) {
-                self.current_node_id = Some(next_sibling_id);
-                
-                // Get the actual node info
-                if let Some(next_node) = index.nodes().get(next_sibling_id) {
-                    // Update cursor byte offset
-                    self.cursor.byte_offset = next_node.start;
-                    
-                    // Convert byte offset to line number
-                    let target_line = self.buffer.byte_offset_to_line(next_node.start);
-                    self.cursor.line = target_line;
-                    
-                    // Calculate column within the line
-                    let line_start_offset = self.buffer.line_to_byte_offset(target_line);
-                    let offset_in_line = next_node.start - line_start_offset;
-                    let line_text = self.buffer.get_line(target_line);
-                    let col = line_text.chars().take_while(|c| {
-                        let len = c.len_utf8();
-                        let current_bytes: usize = line_text.chars()
-                            .take_while(|ch| ch != c)
-                            .map(|ch| ch.len_utf8())
-                            .sum();
-                        current_bytes < offset_in_line
-                    }).count();
-                    self.cursor.col = col;
-                }
-            }
+    /// :e / :e! - load the file again from disk, keeping the cursor on the
+    /// same line where there still is one. Undo history, folds and the
+    /// other byte positions kept on the old text go with it.
+    fn reload(&mut self, force: bool) -> Result<()> {
+        let Some(path) = self.buffer.path().cloned() else {
+            anyhow::bail!("No file name");
+        };
+        if !force && self.buffer.is_modified() {
+            anyhow::bail!("No write since last change (add ! to override)");
+        }
+        let line = self.cursor.line;
+        self.refresh_bookmark_paths();
+        let yaml_error = self.open_buffer(&path.to_string_lossy())?;
+        self.text_replaced(line)?;
+        match yaml_error {
+            Some(e) => self.show_error(format!("Not converted: {}", e)),
+            None => self.show_message(format!("\"{}\" {} lines", path.display(), self.numbers.count(self.buffer.line_count()))),
         }
+        Ok(())
     }
     
-    fn navigate_prev_sibling(&mut self) {
-        // Ensure we've indexed enough of the file
-        let target_line = self.cursor.line + 1000; // Current region
-        let _ = self.expand_structural_index(target_line);
-        
-        if let Some(ref index) = self.structural_index {
-            // Find current node or node at current byte offset
-            let current_node = if let Some(node_id) = self.current_node_id {
-                node_id
-            } else {
-                // Find node at current byte offset
-                if let Some(node) = index.node_at(self.cursor.byte_offset) {
-                    // node_at returns &NodeInfo, we need to find its index
-                    let node_id = index.nodes().iter().position(|n| n.start == node.start).unwrap_or(0);
-                    self.current_node_id = Some(node_id);
-                    node_id
-                } else {
-                    return;
-                }
-            };
-            
-            // Get previous sibling - returns NodeId (usize)
-            if let Some(prev_sibling_id) = index.prev_sibling(current_node) {
-                self.current_node_id = Some(prev_sibling_id);
-                
-                // Get the actual node info
-                if let Some(prev_node) = index.nodes().get(prev_sibling_id) {
-                    // Update cursor byte offset
-                    self.cursor.byte_offset = prev_node.start;
-                    
-                    // Convert byte offset to line number
-                    let target_line = self.buffer.byte_offset_to_line(prev_node.start);
-                    self.cursor.line = target_line;
-                    
-                    // Calculate column within the line
-                    let line_start_offset = self.buffer.line_to_byte_offset(target_line);
-                    let offset_in_line = prev_node.start - line_start_offset;
-                    let line_text = self.buffer.get_line(target_line);
-                    let col = line_text.chars().take_while(|c| {
-                        let len = c.len_utf8();
-                        let current_bytes: usize = line_text.chars()
-                            .take_while(|ch| ch != c)
-                            .map(|ch| ch.len_utf8())
-                            .sum();
-                        current_bytes < offset_in_line
-                    }).count();
-                    self.cursor.col = col;
-                }
-            }
+    /// :bd - close the file, keeping its undo history and session as
+    /// quitting would, and carry on with an empty buffer
+    fn close_buffer(&mut self) {
+        let Some(path) = self.buffer.path().cloned() else {
+            self.show_error("No file to close".to_string());
+            return;
+        };
+        self.quit_undofile();
+        self.quit_session();
+        if let Err(e) = self.buffer.close() {
+            self.show_error(e.to_string());
+            return;
         }
+        self.format = DocumentFormat::Json;
+        self.split_docs = false;
+        self.index_open_containers = self.initial_open_containers();
+        self.start_path = None;
+        self.bookmarks = Bookmarks::default();
+        if let Err(e) = self.text_replaced(0) {
+            self.show_error(e.to_string());
+            return;
+        }
+        self.show_message(format!("\"{}\" closed", path.display()));
     }
-    
-    fn navigate_parent(&mut self) {
-        // Ensure we've indexed enough of the file
-        let target_line = self.cursor.line + 1000;
-        let _ = self.expand_structural_index(target_line);
-        
-        if let Some(ref index) = self.structural_index {
-            // Find current node
-            let current_node = if let Some(node_id) = self.current_node_id {
-                node_id
-            } else {
-                if let Some(node) = index.node_at(self.cursor.byte_offset) {
-                    let node_id = index.nodes().iter().position(|n| n.start == node.start).unwrap_or(0);
-                    self.current_node_id = Some(node_id);
-                    node_id
-                } else {
-                    return;
-                }
-            };
-            
-            // Get parent node
-            if let Some(parent_id) = index.parent(current_node) {
-                self.current_node_id = Some(parent_id);
-                
-                if let Some(parent_node) = index.nodes().get(parent_id) {
-                    self.cursor.byte_offset = parent_node.start;
-                    let target_line = self.buffer.byte_offset_to_line(parent_node.start);
-                    self.cursor.line = target_line;
-                    
-                    let line_start_offset = self.buffer.line_to_byte_offset(target_line);
-                    let offset_in_line = parent_node.start - line_start_offset;
-                    let line_text = self.buffer.get_line(target_line);
-                    let col = line_text.chars().take_while(|c| {
-                        let current_bytes: usize = line_text.chars()
-                            .take_while(|ch| ch != c)
-                            .map(|ch| ch.len_utf8())
-                            .sum();
-                        current_bytes < offset_in_line
-                    }).count();
-                    self.cursor.col = col;
-                }
-            }
+
+    /// After the buffer's text is replaced as a whole: drop what was kept
+    /// by byte offset in the old text, and put the cursor on `line` if it's
+    /// still there
+    fn text_replaced(&mut self, line: usize) -> Result<()> {
+        self.guard = Guard::new(&self.buffer);
+        self.bookmarks.unresolve();
+        self.undo_stack = UndoStack::new();
+        self.undofile_revision = Some(self.buffer.revision());
+        self.folds.clear();
+        self.expanded_strings.clear();
+        self.line_cache.clear();
+        self.diff = None;
+        self.diagnostics.clear();
+        self.signs = Signs::new();
+        self.start_shape(None);
+        if self.schema.is_some() {
+            self.schema_due = Some(Instant::now());
+        }
+        if let Some(list) = self.loclist.as_mut() {
+            list.clamp(self.buffer.len_bytes());
         }
+        let last = self.buffer.line_count().saturating_sub(1);
+        self.move_cursor_to_offset(self.buffer.line_to_byte_offset(line.min(last)));
+        for view in self.windows.inactive_mut() {
+            view.resync(&self.buffer);
+        }
+        self.reset_structural_index()?;
+        self.update_viewport_for_cursor();
+        Ok(())
     }
     
-    fn navigate_first_child(&mut self) {
-        // Ensure we've indexed enough of the file
-        let target_line = self.cursor.line + 1000;
-        let _ = self.expand_structural_index(target_line);
-        
-        if let Some(ref index) = self.structural_index {
-            // Find current node
-            let current_node = if let Some(node_id) = self.current_node_id {
-                node_id
-            } else {
-                if let Some(node) = index.node_at(self.cursor.byte_offset) {
-                    let node_id = index.nodes().iter().position(|n| n.start == node.start).unwrap_or(0);
-                    self.current_node_id = Some(node_id);
-                    node_id
-                } else {
-                    return;
+    /// Put the cursor where the command line asked. A target that isn't
+    /// there is reported and the cursor stays at the top.
+    fn start_at(&mut self, start: StartAt) {
+        match start {
+            StartAt::Line(line) => {
+                let total = self.buffer.line_count();
+                match line.unwrap_or(total) {
+                    line if (1..=total).contains(&line) => self.move_cursor_to_offset(self.buffer.line_to_byte_offset(line - 1)),
+                    line => self.show_error(format!("Line {} is past the end ({} lines)", line, total)),
                 }
-            };
-            
-            // Get first child
-            if let Some(child_id) = index.first_child(current_node) {
-                self.current_node_id = Some(child_id);
-                
-                if let Some(child_node) = index.nodes().get(child_id) {
-                    self.cursor.byte_offset = child_node.start;
-                    let target_line = self.buffer.byte_offset_to_line(child_node.start);
-                    self.cursor.line = target_line;
-                    
-                    let line_start_offset = self.buffer.line_to_byte_offset(target_line);
-                    let offset_in_line = child_node.start - line_start_offset;
-                    let line_text = self.buffer.get_line(target_line);
-                    let col = line_text.chars().take_while(|c| {
-                        let current_bytes: usize = line_text.chars()
-                            .take_while(|ch| ch != c)
-                            .map(|ch| ch.len_utf8())
-                            .sum();
-                        current_bytes < offset_in_line
-                    }).count();
-                    self.cursor.col = col;
+            }
+            StartAt::Search(text) => {
+                match self.buffer.find(&text, 0) {
+                    Some(offset) => self.move_cursor_to_offset(offset),
+                    None => self.show_error(format!("Pattern not found: {}", text)),
                 }
+                self.search = Some(Search::new(text, None));
             }
+            StartAt::Path(text) => match path::parse_path(&text) {
+                Ok(segments) => {
+                    self.start_path = Some((text, segments));
+                    self.goto_start_path();
+                }
+                Err(e) => self.show_error(e.to_string()),
+            },
         }
     }
-    
-    fn navigate_next_key(&mut self) {
-        // Ensure we've indexed enough of the file
-        let target_line = self.cursor.line + 1000;
-        let _ = self.expand_structural_index(target_line);
-        
-        if let Some(ref index) = self.structural_index {
-            if let Some(next_key_id) = index.next_key(self.cursor.byte_offset) {
-                self.current_node_id = Some(next_key_id);
-                
-                if let Some(key_node) = index.nodes().get(next_key_id) {
-                    self.cursor.byte_offset = key_node.start;
-                    let target_line = self.buffer.byte_offset_to_line(key_node.start);
-                    self.cursor.line = target_line;
-                    
-                    let line_start_offset = self.buffer.line_to_byte_offset(target_line);
-                    let offset_in_line = key_node.start - line_start_offset;
-                    let line_text = self.buffer.get_line(target_line);
-                    let col = line_text.chars().take_while(|c| {
-                        let current_bytes: usize = line_text.chars()
-                            .take_while(|ch| ch != c)
-                            .map(|ch| ch.len_utf8())
-                            .sum();
-                        current_bytes < offset_in_line
-                    }).count();
-                    self.cursor.col = col;
+
+    /// Move to the --path target once the index reaches it, indexing on
+    /// through the file until then
+    fn goto_start_path(&mut self) {
+        let (Some((text, segments)), Some(index)) = (&self.start_path, &self.structural_index) else {
+            return;
+        };
+        match path::resolve(index, &self.buffer, segments) {
+            Resolved::Found(id) => {
+                self.start_path = None;
+                // Stop after the chunk on its way
+                self.index_frontier.stop();
+                self.move_to_node(Some(id));
+            }
+            Resolved::Missing(why) => {
+                self.start_path = None;
+                self.show_error(why);
+            }
+            Resolved::Pending if self.fully_indexed() => {
+                let message = format!("{} not found", text);
+                self.start_path = None;
+                self.show_error(message);
+            }
+            Resolved::Pending => {
+                let message = format!("Finding {}… (Esc cancels)", text);
+                self.show_message(message);
+                if let Err(e) = self.request_structural_index(self.buffer.line_count()) {
+                    self.start_path = None;
+                    self.show_error(format!("Indexing failed: {}", e));
                 }
             }
         }
     }
-    
-    fn navigate_prev_key(&mut self) {
-        // Ensure we've indexed enough of the file
-        let target_line = self.cursor.line + 1000;
-        let _ = self.expand_structural_index(target_line);
-        
-        if let Some(ref index) = self.structural_index {
-            if let Some(prev_key_id) = index.prev_key(self.cursor.byte_offset) {
-                self.current_node_id = Some(prev_key_id);
-                
-                if let Some(key_node) = index.nodes().get(prev_key_id) {
-                    self.cursor.byte_offset = key_node.start;
-                    let target_line = self.buffer.byte_offset_to_line(key_node.start);
-                    self.cursor.line = target_line;
-                    
-                    let line_start_offset = self.buffer.line_to_byte_offset(target_line);
-                    let offset_in_line = key_node.start - line_start_offset;
-                    let line_text = self.buffer.get_line(target_line);
-                    let col = line_text.chars().take_while(|c| {
-                        let current_bytes: usize = line_text.chars()
-                            .take_while(|ch| ch != c)
-                            .map(|ch| ch.len_utf8())
-                            .sum();
-                        current_bytes < offset_in_line
-                    }).count();
-                    self.cursor.col = col;
-                }
-            }
+
+    /// Ask the parser thread to index up to `target_line`, and a little
+    /// past it. Chunks go one at a time, since each resumes from the
+    /// containers the previous one left open; `poll_parser` merges them and
+    /// sends the next.
+    fn request_structural_index(&mut self, target_line: usize) -> Result<()> {
+        if !self.index_frontier.extend(target_line, self.buffer.line_count()) {
+            return Ok(());
         }
+        self.index_next_chunk()
     }
-    
-    fn navigate_next_value(&mut self) {
-        // Ensure we've indexed enough of the file
-        let target_line = self.cursor.line + 1000;
-        let _ = self.expand_structural_index(target_line);
+
+    /// Send the next chunk toward the frontier's target, unless one is on
+    /// its way. Requests in between only move the target.
+    fn index_next_chunk(&mut self) -> Result<()> {
+        if self.index_in_flight.is_some() {
+            return Ok(());
+        }
+        let Some(chunk) = self.index_frontier.next_chunk() else {
+            return Ok(());
+        };
+        // Lines after an edit no longer line up with the nodes before it
+        if self.structural_index.as_ref().is_some_and(|index| index.is_dirty()) {
+            return self.reset_structural_index();
+        }
+        let (start_line, end_line) = (chunk.start, chunk.end);
+        // Unedited lines of a large file go to the parser as they are in
+        // the memory map; anything else is copied out
+        let chunk_text = match self.buffer.mapped_lines(start_line..end_line) {
+            Some((map, range)) => ChunkText::Mapped(map, range),
+            None => ChunkText::Owned(self.buffer.get_visible_lines(start_line, end_line - start_line).into_bytes()),
+        };
         
-        if let Some(ref index) = self.structural_index {
-            if let Some(next_value_id) = index.next_value(self.cursor.byte_offset) {
-                self.current_node_id = Some(next_value_id);
-                
-                if let Some(value_node) = index.nodes().get(next_value_id) {
-                    self.cursor.byte_offset = value_node.start;
-                    let target_line = self.buffer.byte_offset_to_line(value_node.start);
-                    self.cursor.line = target_line;
-                    
-                    let line_start_offset = self.buffer.line_to_byte_offset(target_line);
-                    let offset_in_line = value_node.start - line_start_offset;
-                    let line_text = self.buffer.get_line(target_line);
-                    let col = line_text.chars().take_while(|c| {
-                        let current_bytes: usize = line_text.chars()
-                            .take_while(|ch| ch != c)
-                            .map(|ch| ch.len_utf8())
-                            .sum();
-                        current_bytes < offset_in_line
-                    }).count();
-                    self.cursor.col = col;
-                }
-            }
+        self.parser
+            .index(IndexRequest {
+                text: chunk_text,
+                base_offset: self.buffer.line_to_byte_offset(start_line),
+                open: self.index_open_containers.clone(),
+                first_id: self.structural_index.as_ref().map_or(0, |i| i.len()),
+                records: self.format == DocumentFormat::JsonLines,
+                generation: self.index_generation,
+            })
+            .map_err(anyhow::Error::msg)?;
+        
+        self.index_in_flight = Some((end_line, StdInstant::now()));
+        Ok(())
+    }
+
+    /// How far the current run of chunks has got, as a percentage
+    fn index_percent(&self) -> usize {
+        let from = self.buffer.line_to_byte_offset(self.index_frontier.from());
+        let target = self.buffer.line_to_byte_offset(self.index_frontier.target().min(self.buffer.line_count()));
+        let done = self.buffer.line_to_byte_offset(self.index_frontier.indexed()) + self.parser.index_bytes_done();
+        (done.saturating_sub(from) * 100 / target.saturating_sub(from).max(1)).min(100)
+    }
+
+    /// Esc while indexing: stop where the index has got to. Navigation
+    /// waiting on it is dropped, and only reaches the indexed part of the
+    /// file until something asks for more.
+    fn cancel_indexing(&mut self) {
+        self.parser.cancel_index();
+        self.index_frontier.stop();
+        self.pending_nav = None;
+        self.pending_node = None;
+        self.pending_bookmark = None;
+        self.pending_rename = None;
+        self.pending_search = None;
+        self.start_path = None;
+        self.show_message(format!("Indexing cancelled at line {}", self.numbers.count(self.index_frontier.indexed() + 1)));
+    }
+
+    /// Say where indexing stopped short of a pathological part of the
+    /// document, once per build of the index
+    fn warn_index_limit(&mut self) {
+        let Some(hit) = self.index_open_containers.limit_hit() else {
+            return;
+        };
+        let what = match hit.kind {
+            StructuralErrorKind::TooDeep(_) => "deep",
+            _ => "complex",
+        };
+        let line = self.buffer.byte_offset_to_line(hit.offset) + 1;
+        self.show_error(format!("Structure too {} beyond line {}; not indexed past there", what, line));
+    }
+
+    /// Index state for the start of the document in the current format
+    fn initial_open_containers(&self) -> OpenContainers {
+        let open = if self.format.is_relaxed() {
+            OpenContainers::relaxed()
+        } else {
+            OpenContainers::default()
+        };
+        let open = if self.split_docs { open.documents() } else { open };
+        open.with_limits(IndexLimits::new(self.index_depth, self.max_index_size_mb))
+    }
+
+    /// Throw away the structural index (e.g. after changing format) and
+    /// start rebuilding it around the cursor
+    fn reset_structural_index(&mut self) -> Result<()> {
+        self.index_rebuilding |= self.structural_index.as_ref().is_some_and(StructuralIndex::is_dirty);
+        self.structural_index = None;
+        self.index_frontier.reset();
+        self.index_open_containers = self.initial_open_containers();
+        self.index_generation += 1;
+        self.index_revision = self.buffer.revision();
+        self.index_in_flight = None;
+        self.index_window = None;
+        self.current_node_id = None;
+        self.pending_nav = None;
+        self.deep_scan = (0, Vec::new());
+        self.request_structural_index((self.cursor.line + INDEX_MARGIN).max(10000))
+    }
+
+    /// Show a message in the error style
+    fn show_error(&mut self, msg: String) {
+        if self.script.is_some() {
+            self.script_errors.push(msg.clone());
         }
+        self.show_message(msg);
+        self.message_is_error = true;
     }
-    
-    fn navigate_prev_value(&mut self) {
-        // Ensure we've indexed enough of the file
-        let target_line = self.cursor.line + 1000;
-        let _ = self.expand_structural_index(target_line);
-        
-        if let Some(ref index) = self.structural_index {
-            if let Some(prev_value_id) = index.prev_value(self.cursor.byte_offset) {
-                self.current_node_id = Some(prev_value_id);
-                
-                if let Some(value_node) = index.nodes().get(prev_value_id) {
-                    self.cursor.byte_offset = value_node.start;
-                    let target_line = self.buffer.byte_offset_to_line(value_node.start);
-                    self.cursor.line = target_line;
-                    
-                    let line_start_offset = self.buffer.line_to_byte_offset(target_line);
-                    let offset_in_line = value_node.start - line_start_offset;
-                    let line_text = self.buffer.get_line(target_line);
-                    let col = line_text.chars().take_while(|c| {
-                        let current_bytes: usize = line_text.chars()
-                            .take_while(|ch| ch != c)
-                            .map(|ch| ch.len_utf8())
-                            .sum();
-                        current_bytes < offset_in_line
-                    }).count();
-                    self.cursor.col = col;
-                }
+
+    /// Load options and key mappings from a config file, replacing the
+    /// current mappings. Problems are collected into one message rather
+    /// than stopping the rest of the file from applying.
+    fn source_config(&mut self, path: &std::path::Path) -> Result<String, String> {
+        let (config, mut warnings) = Config::load(path).map_err(|e| format!("{:#}", e))?;
+        self.keymap = config.keymap;
+        for option in &config.options {
+            if let Err(e) = self.set_option(option) {
+                warnings.push(e);
             }
         }
+        match warnings.as_slice() {
+            [] => Ok(format!("Sourced {}", path.display())),
+            [warning] => Err(format!("{}: {}", path.display(), warning)),
+            [first, rest @ ..] => Err(format!("{}: {} (and {} more)", path.display(), first, rest.len())),
+        }
     }
 
-    fn update_fps(&mut self) {
-        self.frame_count += 1;
-        let elapsed = self.last_fps_update.elapsed();
-        if elapsed >= Duration::from_secs(1) {
-            self.fps = self.frame_count as f64 / elapsed.as_secs_f64();
-            self.frame_count = 0;
-            self.last_fps_update = Instant::now();
+    /// Apply a :set option, returning the confirmation or the error to show
+    fn set_option(&mut self, option: &str) -> Result<String, String> {
+        if let Some(template) = option.strip_prefix("statusline=") {
+            self.statusline = if template.is_empty() { DEFAULT_STATUSLINE } else { template }.to_string();
+            return Ok(format!("statusline={}", self.statusline));
+        }
+        if let Some(width) = option.strip_prefix("shiftwidth=").or_else(|| option.strip_prefix("sw=")) {
+            self.shiftwidth = width.parse().map_err(|_| format!("Invalid shiftwidth: {}", width))?;
+            return Ok(format!("shiftwidth={}", self.shiftwidth));
+        }
+        if let Some(width) = option.strip_prefix("textwidth=").or_else(|| option.strip_prefix("tw=")) {
+            match width.parse() {
+                Ok(width) if width > 0 => self.text_width = width,
+                _ => return Err(format!("Invalid textwidth: {}", width)),
+            }
+            return Ok(format!("textwidth={}", self.text_width));
+        }
+        if let Some(name) = option.strip_prefix("signcolumn=") {
+            self.sign_column = SignColumn::parse(name).ok_or_else(|| format!("Invalid signcolumn: {} (auto, yes or no)", name))?;
+            return Ok(format!("signcolumn={}", self.sign_column.name()));
+        }
+        if let Some(name) = option.strip_prefix("dupkeys=") {
+            self.dupkeys = DupKeys::parse(name).ok_or_else(|| format!("Invalid dupkeys: {} (error, warn or ignore)", name))?;
+            // Looked for again from the start, or no longer at all
+            self.duplicate_keys = DuplicateScan::default();
+            self.scan_duplicate_keys();
+            return Ok(format!("dupkeys={}", self.dupkeys.name()));
+        }
+        if let Some(name) = option.strip_prefix("pastebehavior=") {
+            let behavior = PasteBehavior::parse(name).ok_or_else(|| format!("Invalid pastebehavior: {} (insert or ignore)", name))?;
+            self.normal_mode_handler.paste_behavior = behavior;
+            return Ok(format!("pastebehavior={}", behavior.name()));
+        }
+        if let Some(name) = option.strip_prefix("wrapstyle=") {
+            self.wrap_style = WrapStyle::parse(name).ok_or_else(|| format!("Invalid wrapstyle: {} (escape or array)", name))?;
+            return Ok(format!("wrapstyle={}", self.wrap_style.name()));
+        }
+        if let Some(depth) = option.strip_prefix("maxrenderdepth=") {
+            let depth: u32 = depth.parse().map_err(|_| format!("Invalid maxrenderdepth: {}", depth))?;
+            self.max_render_depth = (depth > 0).then_some(depth);
+            self.folds.clear_deep();
+            self.deep_scan = (0, Vec::new());
+            self.fold_deep();
+            self.update_viewport_for_cursor();
+            return Ok(option.to_string());
+        }
+        if let Some(count) = option.strip_prefix("elide-strings=") {
+            let count: usize = count.parse().map_err(|_| format!("Invalid elide-strings: {}", count))?;
+            self.elide_strings = (count > 0).then_some(count);
+            return Ok(option.to_string());
+        }
+        if let Some(ms) = option.strip_prefix("timeoutlen=").or_else(|| option.strip_prefix("tm=")) {
+            self.timeoutlen = ms.parse().map_err(|_| format!("Invalid timeoutlen: {}", ms))?;
+            return Ok(format!("timeoutlen={}", self.timeoutlen));
+        }
+        if let Some(ms) = option.strip_prefix("ttimeoutlen=").or_else(|| option.strip_prefix("ttm=")) {
+            self.ttimeoutlen = ms.parse().map_err(|_| format!("Invalid ttimeoutlen: {}", ms))?;
+            return Ok(format!("ttimeoutlen={}", self.ttimeoutlen));
+        }
+        if let Some(step) = option.strip_prefix("nav-accel=") {
+            self.nav_accel_max = step.parse().ok().filter(|&step| step > 0).ok_or_else(|| format!("Invalid nav-accel: {}", step))?;
+            return Ok(format!("nav-accel={}", self.nav_accel_max));
+        }
+        if let Some(percent) = option.strip_prefix("deletewarn=") {
+            self.deletewarn = percent.parse().ok().filter(|&percent| percent <= 100).ok_or_else(|| format!("Invalid deletewarn: {}", percent))?;
+            return Ok(format!("deletewarn={}", self.deletewarn));
+        }
+        if let Some(size) = option.strip_prefix("undofile-size=") {
+            self.undofile_size_mb = size.parse().map_err(|_| format!("Invalid undofile-size: {}", size))?;
+            return Ok(format!("undofile-size={}", self.undofile_size_mb));
+        }
+        if let Some(depth) = option.strip_prefix("index-depth=") {
+            self.index_depth = depth.parse().map_err(|_| format!("Invalid index-depth: {}", depth))?;
+            self.reset_structural_index().map_err(|e| format!("Indexing failed: {}", e))?;
+            return Ok(format!("index-depth={}", self.index_depth));
+        }
+        if let Some(size) = option.strip_prefix("index-size=") {
+            self.max_index_size_mb = size.parse().map_err(|_| format!("Invalid index-size: {}", size))?;
+            self.reset_structural_index().map_err(|e| format!("Indexing failed: {}", e))?;
+            return Ok(format!("index-size={}", self.max_index_size_mb));
+        }
+        if let Some(name) = option.strip_prefix("numberformat=") {
+            self.numbers = NumberFormat::parse(name).ok_or_else(|| format!("Invalid numberformat: {} (plain, comma or underscore)", name))?;
+            self.node_summary = None;
+            return Ok(format!("numberformat={}", self.numbers.name()));
+        }
+        if let Some(name) = option.strip_prefix("theme=") {
+            let (theme, warnings) = Theme::named(name).map_err(|e| e.to_string())?;
+            self.theme = theme;
+            self.line_cache.clear();
+            return match warnings.into_iter().next() {
+                Some(warning) => Err(warning),
+                None => Ok(format!("Theme: {}", self.theme.name)),
+            };
+        }
+        let format = match option {
+            "jsonc" => DocumentFormat::Jsonc,
+            "nojsonc" => DocumentFormat::Json,
+            "expandtab" | "et" | "noexpandtab" | "noet" => {
+                self.expand_tab = !option.starts_with("no");
+                return Ok(option.to_string());
+            }
+            "foldopen" | "nofoldopen" => {
+                self.fold_open = option == "foldopen";
+                return Ok(option.to_string());
+            }
+            "nomaxrenderdepth" => {
+                self.max_render_depth = None;
+                self.folds.clear_deep();
+                return Ok(option.to_string());
+            }
+            "depthexpand" | "nodepthexpand" => {
+                self.depth_expand = option == "depthexpand";
+                return Ok(option.to_string());
+            }
+            "number" | "nu" | "nonumber" | "nonu" => {
+                self.show_numbers = !option.starts_with("no");
+                return Ok(option.to_string());
+            }
+            "relativenumber" | "rnu" | "norelativenumber" | "nornu" => {
+                self.relative_numbers = !option.starts_with("no");
+                return Ok(option.to_string());
+            }
+            "mouse" | "mouse=a" | "mouse=on" | "nomouse" | "mouse=" | "mouse=off" => {
+                self.mouse = matches!(option, "mouse" | "mouse=a" | "mouse=on");
+                return Ok(if self.mouse { "mouse" } else { "nomouse" }.to_string());
+            }
+            "list" | "nolist" => {
+                self.list = option == "list";
+                return Ok(option.to_string());
+            }
+            "noelide-strings" => {
+                self.elide_strings = None;
+                return Ok(option.to_string());
+            }
+            "scrollbar" | "noscrollbar" => {
+                self.show_scrollbar = option == "scrollbar";
+                return Ok(option.to_string());
+            }
+            "bracespacing" | "nobracespacing" => {
+                self.brace_spacing = option == "bracespacing";
+                return Ok(option.to_string());
+            }
+            "nodepreview" | "nonodepreview" => {
+                self.node_preview = option == "nodepreview";
+                return Ok(option.to_string());
+            }
+            "checkedits" | "nocheckedits" => {
+                self.check_edits = option == "checkedits";
+                return Ok(option.to_string());
+            }
+            "strictedits" | "nostrictedits" => {
+                self.strict_edits = option == "strictedits";
+                return Ok(option.to_string());
+            }
+            "formatpaste" | "noformatpaste" => {
+                self.format_paste = option == "formatpaste";
+                return Ok(option.to_string());
+            }
+            "smartcomma" | "nosmartcomma" => {
+                self.smart_comma = option == "smartcomma";
+                return Ok(option.to_string());
+            }
+            "shellpipe" | "noshellpipe" => {
+                self.shellpipe = option == "shellpipe";
+                return Ok(option.to_string());
+            }
+            "session" | "nosession" => {
+                self.session = option == "session";
+                return Ok(option.to_string());
+            }
+            "undofile" | "noundofile" => {
+                self.undofile = option == "undofile";
+                return Ok(option.to_string());
+            }
+            "wrap" | "nowrap" => {
+                self.wrap = option == "wrap";
+                self.update_viewport_for_cursor();
+                return Ok(option.to_string());
+            }
+            _ => return Err(format!("Unknown option: {}", option)),
+        };
+        if format != self.format {
+            self.format = format;
+            self.reset_structural_index().map_err(|e| format!("Indexing failed: {}", e))?;
+        }
+        Ok(format!("Format: {}", self.format.name()))
+    }
+
+    /// Write the undo history once the buffer matches the file on disk
+    /// again, after a save finishes. Edits that were never saved are left
+    /// out, since the history couldn't be matched to the file next time.
+    fn save_undofile(&mut self) {
+        if !self.undofile || self.buffer.is_modified() || self.buffer.is_saving() {
+            return;
+        }
+        let revision = self.buffer.revision();
+        if self.undofile_revision == Some(revision) {
+            return;
+        }
+        self.undofile_revision = Some(revision);
+        let Some(path) = self.buffer.path().cloned() else {
+            return;
+        };
+        self.undo_stack.end_group();
+        if let Err(e) = undofile::save(&self.undo_stack, &path, self.undofile_size_mb * 1024 * 1024) {
+            self.show_error(format!("Can't write undo file: {}", e));
+        }
+    }
+
+    /// :w - save, unless the file is there unchanged: an untouched file of
+    /// gigabytes isn't written again out of habit. A write of
+    /// CONFIRM_SAVE_BYTES or more says how much and how, and waits for a
+    /// yes. :w! saves anyway, without asking.
+    fn write(&mut self, force: bool) {
+        let plan = self.buffer.save_plan(force);
+        let size = self.numbers.size(plan.bytes());
+        let question = match plan {
+            SavePlan::Unchanged => {
+                self.show_message("No changes to write (:w! writes anyway)".to_string());
+                return;
+            }
+            _ if force || !plan.is_large() => {
+                self.start_write();
+                return;
+            }
+            SavePlan::Overlay { edited, .. } => format!(
+                "Writing {}, streamed from the file with {} edited {} laid over it. Go ahead?",
+                size,
+                self.numbers.count(edited),
+                if edited == 1 { "line" } else { "lines" }
+            ),
+            SavePlan::Rope { .. } => format!("Writing {} from memory. Go ahead?", size),
+        };
+        self.prompt = Some(Prompt::new(question, vec![
+            Choice { key: 'y', label: "yes", answer: PromptAction::Write },
+            Choice { key: 'n', label: "no", answer: PromptAction::Cancel },
+        ]));
+    }
+
+    fn start_write(&mut self) {
+        if let Err(e) = self.buffer.save() {
+            self.show_error(e.to_string());
+        }
+    }
+
+    /// :wq, :x and ZZ: save, then close the window, or quit once the save
+    /// is written. poll_save waits for it, with the progress in the status
+    /// line.
+    fn write_quit(&mut self, always: bool) {
+        if always || self.buffer.is_modified() {
+            if let Err(e) = self.buffer.save() {
+                self.show_error(format!("Not saved: {}", e));
+                return;
+            }
+        }
+        if self.close_window() {
+            return;
+        }
+        self.quit_after_save = true;
+        self.poll_save();
+    }
+
+    /// Finish a background save that's done, or say why it failed. A :wq
+    /// waiting on it quits once it's written, and otherwise asks whether
+    /// to try again, stay, or quit anyway.
+    fn poll_save(&mut self) {
+        if !self.quit_after_save && !self.close_after_save {
+            if let Err(e) = self.buffer.finalize_save() {
+                self.show_error(e.to_string());
+            }
+            return;
+        }
+        let why = match self.buffer.save_outcome() {
+            SaveOutcome::Saving => return,
+            SaveOutcome::Saved if self.close_after_save => {
+                self.close_after_save = false;
+                self.close_buffer();
+                return;
+            }
+            SaveOutcome::Saved => {
+                self.quit_after_save = false;
+                self.should_quit = true;
+                return;
+            }
+            SaveOutcome::Unsaved(Some(error)) => error,
+            SaveOutcome::Unsaved(None) => "Changed while saving".to_string(),
+        };
+        if self.close_after_save {
+            self.close_after_save = false;
+            self.show_error(format!("Not closed: {}", why));
+            return;
+        }
+        self.quit_after_save = false;
+        self.prompt = Some(Prompt::new(format!("{}. Quit anyway?", why), vec![
+            Choice { key: 'r', label: "retry", answer: PromptAction::Quit { save: true } },
+            Choice { key: 's', label: "stay", answer: PromptAction::Cancel },
+            Choice { key: 'q', label: "quit without saving", answer: PromptAction::Quit { save: false } },
+        ]));
+    }
+
+    /// On the way out, let a :wq save finish so its history is kept
+    fn quit_undofile(&mut self) {
+        if !self.undofile {
+            return;
+        }
+        while self.buffer.is_saving() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if self.buffer.finalize_save().is_ok() {
+            self.save_undofile();
+        }
+    }
+
+    /// Where the file was left, for a session. Only options that belong
+    /// with the file are kept; the document format is detected again.
+    fn session_state(&self) -> Session {
+        let mut options = vec![
+            if self.wrap { "wrap" } else { "nowrap" }.to_string(),
+            if self.list { "list" } else { "nolist" }.to_string(),
+            if self.show_numbers { "number" } else { "nonumber" }.to_string(),
+            if self.relative_numbers { "relativenumber" } else { "norelativenumber" }.to_string(),
+            if self.fold_open { "foldopen" } else { "nofoldopen" }.to_string(),
+            format!("shiftwidth={}", self.shiftwidth),
+        ];
+        options.push(match self.elide_strings {
+            Some(count) => format!("elide-strings={}", count),
+            None => "noelide-strings".to_string(),
+        });
+        options.push(match self.max_render_depth {
+            Some(depth) => format!("maxrenderdepth={}", depth),
+            None => "nomaxrenderdepth".to_string(),
+        });
+        Session {
+            stamp: None,
+            line: self.cursor.line,
+            col: self.cursor.col,
+            top: self.viewport.start_line,
+            left: self.viewport.start_col,
+            folds: self.folds.ranges().to_vec(),
+            options,
+            bookmarks: self.bookmarks.saved(),
+        }
+    }
+
+    /// Go back to `session`, its folds only when they still fit the text
+    fn restore_session(&mut self, session: Session, folds: bool) {
+        for option in &session.options {
+            // Options this version doesn't know are skipped
+            let _ = self.set_option(option);
+        }
+        let last_line = self.buffer.line_count().saturating_sub(1);
+        self.cursor.set_position(session.line.min(last_line), session.col);
+        self.cursor.sync_byte_offset(&self.buffer);
+        self.viewport.start_line = session.top.min(self.cursor.line);
+        self.viewport.start_col = session.left;
+        if folds {
+            let len = self.buffer.len_bytes();
+            for fold in session.folds.into_iter().filter(|fold| fold.end <= len) {
+                self.folds.close(fold);
+            }
+        }
+    }
+
+    /// :mksession - write the session to `path`, stamped with the file
+    /// on disk if there is one
+    fn make_session(&mut self, path: &str, force: bool) -> Result<()> {
+        let path = std::path::Path::new(path);
+        if !force && path.exists() {
+            anyhow::bail!("{} exists (add ! to overwrite)", path.display());
+        }
+        self.refresh_bookmark_paths();
+        let mut state = self.session_state();
+        state.stamp = self.buffer.path().and_then(|file| FileStamp::of(file).ok()).filter(|_| !self.buffer.is_modified());
+        session::write(&state, path)?;
+        self.show_message(format!("Session written to {}", path.display()));
+        Ok(())
+    }
+
+    /// :source-session - go back to a session written by :mksession. Its
+    /// folds are byte ranges, so they're left out unless it was written
+    /// for the file as it is now.
+    fn source_session(&mut self, path: &str) -> Result<()> {
+        let state = session::read(std::path::Path::new(path))?;
+        let current = self.buffer.path().and_then(|file| FileStamp::of(file).ok()).filter(|_| !self.buffer.is_modified());
+        let folds = state.stamp.is_some() && state.stamp == current;
+        let skipped = !folds && !state.folds.is_empty();
+        self.current_node_id = None;
+        if !state.bookmarks.is_empty() {
+            self.bookmarks = Bookmarks::from_saved(state.bookmarks.clone());
+            self.resolve_bookmarks();
+        }
+        self.restore_session(state, folds);
+        self.reveal_cursor();
+        self.show_message(if skipped {
+            "Session restored, but not its folds: the file has changed".to_string()
+        } else {
+            "Session restored".to_string()
+        });
+        Ok(())
+    }
+
+    /// On the way out, keep where the file was left. Folds of unsaved
+    /// edits would land in the wrong places next time, so they're dropped.
+    fn quit_session(&mut self) {
+        let Some(path) = self.buffer.path().cloned().filter(|_| self.session) else {
+            return;
+        };
+        while self.buffer.is_saving() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.buffer.finalize_save();
+        self.refresh_bookmark_paths();
+        let mut state = self.session_state();
+        if self.buffer.is_modified() {
+            state.folds.clear();
+        }
+        // Nowhere to report a failure any more
+        let _ = session::save(state, &path);
+    }
+
+    /// Merge index chunks finished by the parser thread and run any
+    /// structural navigation that was waiting for them
+    fn poll_parser(&mut self) {
+        while let Some(response) = self.parser.try_recv_response() {
+            match response {
+                ParserResponse::IndexCancelled { generation } if generation == self.index_generation => {
+                    self.index_in_flight = None;
+                }
+                ParserResponse::Indexed { chunk, open, generation } => {
+                    if generation != self.index_generation {
+                        continue;
+                    }
+                    let Some((end_line, started)) = self.index_in_flight.take() else {
+                        continue;
+                    };
+                    let index = self.structural_index.get_or_insert_with(StructuralIndex::new);
+                    if index.apply_chunk(*chunk) {
+                        // Containers still open at the end of the file are errors
+                        if end_line >= self.buffer.line_count() {
+                            index.finish(&open);
+                        }
+                        let newly_limited = self.index_open_containers.limit_hit().is_none();
+                        self.index_open_containers = open;
+                        if newly_limited {
+                            self.warn_index_limit();
+                        }
+                        self.index_frontier.advance(end_line);
+                        self.index_build_time = started.elapsed().as_secs_f64();
+                    }
+                    self.goto_start_path();
+                    self.resolve_bookmarks();
+                    self.refresh_outline();
+                    self.scan_duplicate_keys();
+                    self.place_error_signs();
+                    self.fold_deep();
+                    if let Err(e) = self.index_next_chunk() {
+                        self.show_error(format!("Indexing failed: {}", e));
+                    }
+                }
+                _ => {}
+            }
+        }
+        
+        if self.index_in_flight.is_none() {
+            self.index_rebuilding = false;
+            if let Some((action, count)) = self.pending_nav.take() {
+                self.structural_nav(action, count);
+            }
+            if let Some((forward, count)) = self.pending_search.take() {
+                self.search_next(forward, count);
+            }
+            if let Some((op, register, force)) = self.pending_node.take() {
+                if let Err(e) = self.node_command(op, register, force) {
+                    self.show_error(e.to_string());
+                }
+            }
+            if let Some(note) = self.pending_bookmark.take() {
+                self.add_bookmark(note);
+            }
+            if let Some(rename) = self.pending_rename.take() {
+                if let Err(e) = self.rename_all(rename, true) {
+                    self.show_error(e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Run a structural navigation command `count` times, or queue it until
+    /// the index covers enough of the file past the cursor
+    fn structural_nav(&mut self, action: StructuralNavAction, count: usize) {
+        if self.past_index() && navigation::is_local(action) {
+            self.window_nav(action, count);
+            self.update_viewport_for_cursor();
+            return;
+        }
+        self.index_window = None;
+        let target_line = self.cursor.line + INDEX_MARGIN;
+        if !self.index_frontier.covers(target_line, self.buffer.line_count()) {
+            match self.request_structural_index(target_line) {
+                Ok(()) => self.pending_nav = Some((action, count)),
+                Err(e) => self.show_error(format!("Indexing failed: {}", e)),
+            }
+            return;
+        }
+        
+        match action {
+            StructuralNavAction::NextError => self.navigate_error(true, count),
+            StructuralNavAction::PrevError => self.navigate_error(false, count),
+            StructuralNavAction::NextDiagnostic => self.navigate_diagnostic(true, count),
+            StructuralNavAction::PrevDiagnostic => self.navigate_diagnostic(false, count),
+            StructuralNavAction::NextChange => self.navigate_change(true, count),
+            StructuralNavAction::PrevChange => self.navigate_change(false, count),
+            StructuralNavAction::NextDuplicate => self.navigate_duplicate(true, count),
+            StructuralNavAction::PrevDuplicate => self.navigate_duplicate(false, count),
+            StructuralNavAction::ArrayIndex(n) => self.goto_array_index(n),
+            _ => {
+                let Some(ref index) = self.structural_index else {
+                    return;
+                };
+                let offset = self.cursor.byte_offset;
+                if navigation::is_relative(action) {
+                    // Relative moves start from the last node landed on,
+                    // or else the one under the cursor
+                    self.current_node_id = self.current_node_id.or_else(|| index.node_id_at(offset));
+                }
+                let target = navigation::target(index, action, self.current_node_id, offset, count);
+                // Rather than land where a node used to be before an edit
+                // there, have the index rebuilt
+                if target.is_some_and(|id| index.lands_in_dirty(id)) {
+                    if let Err(e) = self.reset_structural_index() {
+                        self.show_error(format!("Indexing failed: {}", e));
+                    } else {
+                        self.show_error("Edited there since indexing; reindexing".to_string());
+                    }
+                    return;
+                }
+                let position = target.filter(|_| navigation::is_relative(action) && action != StructuralNavAction::Parent)
+                    .and_then(|id| self.item_positions.member(index, id, self.index_generation));
+                self.move_to_node(target);
+                let mut message = position
+                    .map(|(position, total)| format!("item {}/{}", self.numbers.count(position), self.numbers.child_count(total)));
+                if self.nav_step > 1 {
+                    let step = format!("step {}", self.nav_step);
+                    message = Some(message.map_or(step.clone(), |message| format!("{}  {}", message, step)));
+                }
+                if let Some(preview) = self.node_preview(target, message.as_ref().map_or(0, |m| m.chars().count() + 2)) {
+                    message = Some(message.map_or(preview.clone(), |message| format!("{}  {}", message, preview)));
+                }
+                if let Some(message) = message {
+                    self.show_message(message);
+                }
+            }
+        }
+        self.update_viewport_for_cursor();
+    }
+
+    /// Whether the cursor is so far past what's indexed that waiting for
+    /// the index would take a while: more than a run of chunks beyond the
+    /// frontier, or past where the index stopped at its size limit
+    fn past_index(&self) -> bool {
+        let beyond_limit = self.index_open_containers.limit_hit().is_some_and(|hit| {
+            matches!(hit.kind, StructuralErrorKind::TooManyNodes(_)) && hit.offset <= self.cursor.byte_offset
+        });
+        beyond_limit || self.cursor.line > self.index_frontier.indexed() + INDEX_CHUNK_LINES
+    }
+
+    /// A structural move past the index, in a window indexed around the
+    /// cursor there and then. Nothing outside the window is known, so a
+    /// move that would leave it (up to the container the window starts
+    /// in) says so rather than guess.
+    fn window_nav(&mut self, action: StructuralNavAction, count: usize) {
+        let offset = self.cursor.byte_offset;
+        if !self.index_window.as_ref().is_some_and(|window| window.reaches(offset, self.buffer.len_bytes())) {
+            let start_line = self.buffer.byte_offset_to_line(offset.saturating_sub(WINDOW_BYTES));
+            let end_line = (self.buffer.byte_offset_to_line(offset + WINDOW_BYTES) + 1).min(self.buffer.line_count());
+            let text = self.buffer.get_visible_lines(start_line, end_line.saturating_sub(start_line));
+            let base = self.buffer.line_to_byte_offset(start_line);
+            self.index_window = IndexWindow::build(text.as_bytes(), base, offset, self.format);
+        }
+        let Some(window) = &self.index_window else {
+            self.show_error("Not indexed this far, and no structure found around the cursor".to_string());
+            return;
+        };
+        let current = window.index.node_id_at(offset);
+        // Members of the container the window starts in are counted from
+        // the window's start, not the container's
+        let counted = matches!(action, StructuralNavAction::FirstChild | StructuralNavAction::NthChild(_));
+        let target = match current {
+            Some(id) if counted && window.is_outside(id) => Some(id),
+            _ => navigation::target(&window.index, action, current, offset, count),
+        };
+        match target.filter(|&id| !window.is_outside(id)).and_then(|id| window.index.get(id)) {
+            Some(node) => {
+                self.current_node_id = None;
+                self.move_cursor_to_offset(node.start);
+                self.show_message("≈ Not indexed this far: structure found around the cursor only".to_string());
+            }
+            None if target.is_some() => self.show_error("≈ Not indexed this far: outside what's indexed around the cursor".to_string()),
+            None => {}
+        }
+    }
+
+    /// :index n: the nth element of the array around the cursor. An
+    /// element past what's indexed waits for the index to grow, a run of
+    /// chunks at a time, until it or the array's end turns up.
+    fn goto_array_index(&mut self, n: usize) {
+        let Some(ref index) = self.structural_index else {
+            return;
+        };
+        let offset = self.cursor.byte_offset;
+        self.current_node_id = self.current_node_id.or_else(|| index.node_id_at(offset));
+        let Some((array, _)) = self.current_node_id.and_then(|id| navigation::array_element(index, id)) else {
+            self.show_error("Not in an array".to_string());
+            return;
+        };
+        let action = StructuralNavAction::ArrayIndex(n);
+        if let Some(target) = navigation::target(index, action, self.current_node_id, offset, 1) {
+            let count = index.child_count(array);
+            self.jumps.push(self.cursor.byte_offset);
+            self.move_to_node(Some(target));
+            if let Some(count) = count {
+                self.show_message(format!("item {}/{}", self.numbers.count(n), self.numbers.child_count(count)));
+            }
+            return;
+        }
+        if !index.is_closed(array) && !self.fully_indexed() {
+            let next = self.index_frontier.indexed() + 1;
+            match self.request_structural_index(next) {
+                Ok(()) => self.pending_nav = Some((action, 1)),
+                Err(e) => self.show_error(format!("Indexing failed: {}", e)),
+            }
+            return;
+        }
+        let count = index.child_count(array).map_or(0, |count| count.count);
+        self.show_error(format!("No element {}: the array has {}", n, ChildCount { count, complete: true }));
+    }
+
+    fn handle_event(&mut self, event: Event) -> Result<()> {
+        if let Event::Resize(width, height) = event {
+            self.resize(width, height);
+        } else if let Event::Mouse(mouse) = event {
+            self.handle_mouse(mouse);
+        } else if let Event::Key(key) = event {
+            for key in self.escapes.key(key, Instant::now(), Duration::from_millis(self.ttimeoutlen)) {
+                self.handle_key(key)?;
+            }
+        } else if let Event::Paste(text) = event {
+            self.handle_paste(&text)?;
+        }
+        Ok(())
+    }
+
+    /// Handle the events queued since the last frame. A run of the same
+    /// j or k is one counted motion, when nothing is pending that the
+    /// count would change and neither key is mapped.
+    fn handle_events(&mut self, events: Vec<Event>) -> Result<()> {
+        let mut events = events.into_iter().peekable();
+        while let Some(event) = events.next() {
+            if self.should_quit {
+                break;
+            }
+            let Event::Key(key) = event else {
+                self.handle_event(event)?;
+                continue;
+            };
+            let mut count = 1;
+            if self.coalesces(key) {
+                while events.next_if(|next| matches!(next, Event::Key(next) if next.code == key.code && next.modifiers == key.modifiers)).is_some() {
+                    count += 1;
+                }
+            }
+            if count == 1 {
+                self.handle_event(event)?;
+                continue;
+            }
+            let mut keys: Vec<KeyEvent> = count.to_string()
+                .chars()
+                .map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+                .collect();
+            keys.push(key);
+            self.replay(keys)?;
+        }
+        Ok(())
+    }
+
+    /// Whether repeats of `key` can be folded into one counted motion
+    fn coalesces(&self, key: KeyEvent) -> bool {
+        matches!(key.code, KeyCode::Char('j' | 'k'))
+            && key.modifiers.is_empty()
+            && self.mode == Mode::Normal
+            && self.focus == Focus::Text
+            && self.pending_keys.is_empty()
+            && self.pending_operator.is_none()
+            && self.normal_mode_handler.is_idle()
+            && !self.escapes.is_holding()
+            && self.prompt.is_none()
+            && self.help.is_none()
+            && self.picker.is_none()
+            && self.string_editor.is_none()
+            && self.preview.is_none()
+            && matches!(self.keymap.lookup(Mode::Normal, &[key]), Lookup::None)
+    }
+
+    /// Text from a bracketed paste: one edit, undone in one step, rather
+    /// than a key per character. The command line and the picker take it
+    /// as typed, a line at a time; in normal mode :set pastebehavior says
+    /// whether it's inserted.
+    fn handle_paste(&mut self, text: &str) -> Result<()> {
+        if let Some(picker) = self.picker.as_mut() {
+            let query = format!("{}{}", picker.query, text.replace(['\r', '\n'], " "));
+            picker.set_query(query);
+            return Ok(());
+        }
+        let editing = matches!(self.mode, Mode::Normal | Mode::Insert) && self.focus == Focus::Text;
+        if self.mode != Mode::Command && (!editing || self.help.is_some() || self.string_editor.is_some() || text.is_empty()) {
+            return Ok(());
+        }
+        let result = match self.mode {
+            Mode::Command => self.command_mode_handler.handle_paste(text),
+            Mode::Normal => self.normal_mode_handler.handle_paste(text),
+            _ => self.insert_mode_handler.handle_paste(text),
+        };
+        match result {
+            InputResult::NotHandled => {}
+            InputResult::Message(message) => {
+                self.show_message(message);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let shiftwidth = self.format_paste.then_some(self.shiftwidth);
+        let at = self.cursor.byte_offset;
+        let edit = paste::paste(&mut self.buffer, &mut self.cursor, at, &text, shiftwidth)?;
+        self.undo_stack.begin_group();
+        self.undo_stack.push(edit);
+        self.undo_stack.end_group();
+        self.apply_buffer_changes();
+        self.update_viewport_for_cursor();
+        Ok(())
+    }
+
+    /// Keys to look continuations up from: the pending sequence, after the
+    /// operator it follows when the operator was typed earlier
+    fn pending_sequence(&self) -> Vec<KeyEvent> {
+        self.pending_operator_key()
+            .into_iter()
+            .chain(self.pending_keys.iter().copied())
+            .collect()
+    }
+
+    fn pending_operator_key(&self) -> Option<KeyEvent> {
+        let operator = self.pending_operator.as_ref().map(|pending| pending.operator.key());
+        operator.map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+    }
+
+    /// Ways to finish the pending sequence. The outline and the location
+    /// list only take Ctrl-w sequences; visual and command mode have none.
+    fn pending_continuations(&self) -> Vec<Continuation> {
+        let ctrl_w = KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL);
+        let tree_keys = self.focus != Focus::Text && self.mode == Mode::Normal;
+        if self.pending_keys.is_empty() || (tree_keys && self.pending_keys[0] != ctrl_w) {
+            return Vec::new();
+        }
+        self.keymap.continuations(self.mode, &self.pending_sequence())
+    }
+
+    /// Handle a key press. Keys that start a longer sequence are held until
+    /// it's complete (or, for mappings, until it times out) so the hint can
+    /// show what may follow and handlers find the whole sequence queued.
+    fn handle_key(&mut self, key: KeyEvent) -> Result<()> {
+        if let Some(prompt) = &self.prompt {
+            match prompt.handle_key(key) {
+                PromptOutcome::Waiting => {}
+                PromptOutcome::Answered(action) => {
+                    self.prompt = None;
+                    self.answer_prompt(action);
+                }
+                PromptOutcome::Cancel => {
+                    self.prompt = None;
+                    if self.renaming.take().is_some() {
+                        self.show_message("Rename cancelled; no keys renamed".to_string());
+                    }
+                }
+            }
+            return Ok(());
+        }
+        let idle = self.mode == Mode::Normal && self.pending_keys.is_empty() && self.pending_operator.is_none();
+        let quit = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        // Esc in a pane closes the pane
+        let esc = key.code == KeyCode::Esc && idle && self.help.is_none();
+        if quit || esc {
+            self.sync_tasks();
+            if let Some(kind) = self.tasks.foremost_cancellable(esc) {
+                self.cancel_task(kind);
+                return Ok(());
+            }
+        }
+        if let Some(help) = self.help.as_mut().filter(|_| !quit) {
+            let refresh = help.query.is_none() && key.code == KeyCode::Char('r');
+            match self.inspect.filter(|_| refresh) {
+                Some(view) => {
+                    let lines = self.inspect_lines(view);
+                    if let Some(help) = &mut self.help {
+                        help.replace_lines(lines, self.viewport.height);
+                    }
+                }
+                None if !help.handle_key(key, self.viewport.height) => {
+                    self.help = None;
+                    self.inspect = None;
+                }
+                None => {}
+            }
+            return Ok(());
+        }
+        if let Some(picker) = self.picker.as_mut().filter(|_| !quit) {
+            match picker.handle_key(key, self.viewport.height) {
+                PickerOutcome::Picking => {}
+                PickerOutcome::Cancel => self.picker = None,
+                PickerOutcome::Chosen => {
+                    let chosen = self.picker.take().and_then(|mut picker| picker.take_chosen());
+                    match chosen {
+                        Some(Pick::Key(offset)) => self.jump_to_key(offset),
+                        Some(Pick::Node(id)) => self.jump_to_node(id),
+                        Some(Pick::Action(action, count)) => {
+                            let mut keys: Vec<KeyEvent> = count.map_or(String::new(), |count| count.to_string())
+                                .chars()
+                                .map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+                                .collect();
+                            keys.extend(action.keys(Mode::Normal).unwrap_or_default());
+                            self.replay(keys)?;
+                        }
+                        Some(Pick::Command(command)) if command.complete => {
+                            self.replay(Binding::Command(command.line).keys(Mode::Normal).unwrap_or_default())?;
+                        }
+                        Some(Pick::Command(command)) => {
+                            self.command_mode_handler.prompt = ':';
+                            self.command_mode_handler.command_line = command.line;
+                            self.switch_mode(Mode::Command);
+                        }
+                        None => {}
+                    }
+                }
+            }
+            return Ok(());
+        }
+        if let Some(editor) = self.string_editor.as_mut().filter(|_| !quit) {
+            match editor.handle_key(key) {
+                EditorOutcome::Editing => {}
+                EditorOutcome::Cancel => self.string_editor = None,
+                EditorOutcome::Save => {
+                    if let Some(editor) = self.string_editor.take() {
+                        self.save_string(editor)?;
+                    }
+                }
+            }
+            return Ok(());
+        }
+        // Esc drops a half-typed sequence, and the operator and count
+        // before it
+        if key.code == KeyCode::Esc && self.mode == Mode::Normal && !self.pending_keys.is_empty() {
+            self.pending_keys.clear();
+            self.pending_since = None;
+            return self.replay(vec![key]);
+        }
+        self.pending_keys.push(key);
+        if self.pending_continuations().is_empty() {
+            return self.dispatch_pending();
+        }
+        // Vim only times out sequences that could be user mappings
+        let mapping = matches!(self.keymap.lookup(self.mode, &self.pending_keys), Lookup::Prefix(_));
+        self.pending_since = mapping.then(Instant::now);
+        Ok(())
+    }
+
+    /// Called every frame, so timeouts fire without another key: an Esc
+    /// held for ttimeoutlen with nothing after it goes through as Esc, and
+    /// a sequence that could become a mapping runs as typed once
+    /// timeoutlen passes without its next key
+    fn check_pending_timeout(&mut self) -> Result<()> {
+        for key in self.escapes.expire(Instant::now(), Duration::from_millis(self.ttimeoutlen)) {
+            self.handle_key(key)?;
+        }
+        let timeout = Duration::from_millis(self.timeoutlen);
+        let Some(since) = self.pending_since.filter(|since| since.elapsed() >= timeout) else {
+            return Ok(());
+        };
+        self.pending_since = None;
+        let operator = self.pending_operator_key();
+        if self.keymap.times_out(self.mode, operator, &self.pending_keys, since.elapsed(), timeout) {
+            self.dispatch_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Run the pending sequence: the longest mapping it starts with is
+    /// replaced by the mapped keys, and everything is handled in order
+    fn dispatch_pending(&mut self) -> Result<()> {
+        self.pending_since = None;
+        let sequence = std::mem::take(&mut self.pending_keys);
+        let mappable = matches!(self.mode, Mode::Normal | Mode::Insert)
+            && self.focus == Focus::Text
+            && self.pending_operator.is_none();
+        let matched = (1..=sequence.len()).rev()
+            .filter(|_| mappable)
+            .find_map(|len| match self.keymap.lookup(self.mode, &sequence[..len]) {
+                Lookup::Found(binding) | Lookup::Prefix(Some(binding)) => {
+                    Some((len, self.keymap.expand(self.mode, &sequence[..len], &binding)?))
+                }
+                _ => None,
+            });
+        let (used, mut replay) = matched.unwrap_or_default();
+        replay.extend_from_slice(&sequence[used..]);
+        self.replay(replay)
+    }
+
+    /// Run `keys` through the built-in handlers, past any mappings
+    fn replay(&mut self, keys: Vec<KeyEvent>) -> Result<()> {
+        let Some((&first, rest)) = keys.split_first() else {
+            return Ok(());
+        };
+        self.keys.unread(rest);
+        self.handle_key_unmapped(first)?;
+        // Whatever the handlers didn't take as part of their own sequence
+        while let Some(key) = self.keys.next_key() {
+            if self.should_quit {
+                break;
+            }
+            self.handle_key_unmapped(key)?;
+        }
+        Ok(())
+    }
+
+    fn handle_key_unmapped(&mut self, key: KeyEvent) -> Result<()> {
+        // Global shortcuts (work in all modes)
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.should_quit = true;
+                return Ok(());
+            }
+            KeyCode::F(12) => {
+                self.show_performance = !self.show_performance;
+                return Ok(());
+            }
+            KeyCode::F(2) => {
+                self.toggle_tree();
+                return Ok(());
+            }
+            KeyCode::F(1) => {
+                self.help = Some(HelpView::new(&self.keymap));
+                self.inspect = None;
+                return Ok(());
+            }
+            KeyCode::Char('w') if key.modifiers.contains(KeyModifiers::CONTROL) && self.mode == Mode::Normal => {
+                // Ctrl-w - windows and focus between the outline and the text
+                if let Some(next_key) = self.keys.next_key() {
+                    self.window_command(next_key.code);
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        
+        if self.mode == Mode::Insert && self.completion_key(key)? {
+            return Ok(());
+        }
+        
+        // Any key closes the value preview; Esc does nothing else
+        if self.preview.take().is_some() && key.code == KeyCode::Esc {
+            return Ok(());
+        }
+        
+        if self.focus == Focus::Tree && self.mode == Mode::Normal {
+            self.handle_tree_key(key);
+            return Ok(());
+        }
+        if self.focus == Focus::List && self.mode == Mode::Normal {
+            self.handle_loclist_key(key);
+            return Ok(());
+        }
+        
+        // Read current mode before borrowing
+        let current_mode = self.mode;
+        
+        // Capture cursor offset before borrowing (needed for visual mode initialization)
+        let cursor_offset = self.cursor.byte_offset;
+        
+        let folded = self.folds.lines(&self.buffer);
+        
+        // Create editor context for mode handlers
+        let ctx = EditorContext {
+            buffer: &mut self.buffer,
+            cursor: &mut self.cursor,
+            mode: &mut self.mode,
+            undo_stack: &mut self.undo_stack,
+            register_map: &mut self.register_map,
+            pending_operator: &mut self.pending_operator,
+            structural_index: self.structural_index.as_ref(),
+            folds: &folded,
+            keys: &mut self.keys,
+            format_paste: self.format_paste.then_some(self.shiftwidth),
+            smart_comma: self.smart_comma,
+        };
+        
+        // Route to appropriate mode handler based on saved mode value
+        let result = match current_mode {
+            Mode::Normal => self.normal_mode_handler.handle_key(key, ctx),
+            Mode::Insert => self.insert_mode_handler.handle_key(key, ctx),
+            Mode::Visual { line_wise } => {
+                // Initialize visual mode if not already active
+                if self.visual_mode_handler.is_none() {
+                    self.visual_mode_handler = Some(mode::visual::VisualMode::new(
+                        cursor_offset,
+                        line_wise,
+                    ));
+                }
+                
+                if let Some(ref mut handler) = self.visual_mode_handler {
+                    handler.handle_key(key, ctx)
+                } else {
+                    Ok(InputResult::NotHandled)
+                }
+            }
+            Mode::Command => {
+                self.command_mode_handler.handle_key(key, ctx)
+            }
+        };
+        // A refused edit or a failed command is reported, not fatal
+        let result = result.unwrap_or_else(|e| {
+            if current_mode == Mode::Command {
+                self.switch_mode(Mode::Normal);
+            }
+            self.show_error(e.to_string());
+            InputResult::Handled
+        });
+        
+        self.apply_buffer_changes();
+        // Only a structural motion pressed again keeps speeding up
+        if !matches!(result, InputResult::StructuralNav(..)) {
+            self.nav_accel.reset();
+        }
+        
+        // Handle mode handler results
+        match result {
+            InputResult::Handled => {
+                // Update viewport to follow cursor
+                self.update_viewport_for_cursor();
+            }
+            InputResult::ModeSwitch(new_mode) => {
+                self.switch_mode(new_mode);
+                // Update viewport to follow cursor
+                self.update_viewport_for_cursor();
+            }
+            InputResult::Quit => {
+                // With the view split, :q and friends close a window
+                if !self.close_window() {
+                    self.should_quit = true;
+                }
+            }
+            InputResult::StructuralNav(action, count) => {
+                // From :child
+                if self.mode == Mode::Command {
+                    self.switch_mode(Mode::Normal);
+                }
+                // Held down, a motion along siblings goes further each time
+                self.nav_step = match count == 1 && accel::accelerates(action) {
+                    true => self.nav_accel.step(action, Instant::now(), self.nav_accel_max),
+                    false => 1,
+                };
+                self.structural_nav(action, count * self.nav_step);
+            }
+            InputResult::ClearNodeTracking => {
+                // Cursor moved manually, invalidate cached node position
+                self.current_node_id = None;
+                self.update_viewport_for_cursor();
+            }
+            InputResult::NotHandled => {}
+            InputResult::Message(msg) => {
+                self.show_message(msg);
+                // u and :undo report how far they went
+                self.update_viewport_for_cursor();
+            }
+            InputResult::Command(AppCommand::Fold(action)) => {
+                self.fold(action);
+            }
+            InputResult::Command(AppCommand::ToggleElision) => {
+                self.toggle_elision();
+            }
+            InputResult::Command(AppCommand::ScrollHorizontal(scroll)) => {
+                self.scroll_horizontal(scroll);
+            }
+            InputResult::Command(AppCommand::DisplayLine { down }) => {
+                self.display_line(down);
+            }
+            InputResult::Command(AppCommand::PreviewValue) => {
+                self.preview_value();
+            }
+            InputResult::Command(AppCommand::Checksum { copy }) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.start_checksum(copy) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::FileInfo { detailed }) => {
+                let info = if detailed { self.position_counts() } else { self.file_info() };
+                self.show_message(info);
+            }
+            InputResult::Command(AppCommand::MakeSession { path, force }) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.make_session(&path, force) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::SourceSession(path)) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.source_session(&path) {
+                    self.show_error(format!("{:#}", e));
+                }
+            }
+            InputResult::Command(AppCommand::Source(path)) => {
+                match self.source_config(std::path::Path::new(&path)) {
+                    Ok(msg) => self.show_message(msg),
+                    Err(msg) => self.show_error(msg),
+                }
+            }
+            InputResult::Command(AppCommand::ToggleTree) => {
+                self.mode = Mode::Normal;
+                self.command_mode_handler.command_line.clear();
+                self.toggle_tree();
+            }
+            InputResult::Command(AppCommand::Quit { force }) => {
+                if self.close_window() {
+                    // Back to normal mode already
+                } else if !force && self.buffer.is_modified() {
+                    self.switch_mode(Mode::Normal);
+                    self.prompt = Some(Prompt::new("Save changes?", vec![
+                        Choice { key: 'y', label: "yes", answer: PromptAction::Quit { save: true } },
+                        Choice { key: 'n', label: "no", answer: PromptAction::Quit { save: false } },
+                        Choice { key: 'c', label: "cancel", answer: PromptAction::Cancel },
+                    ]));
+                } else {
+                    self.should_quit = true;
+                }
+            }
+            InputResult::Command(AppCommand::Write { force }) => {
+                self.switch_mode(Mode::Normal);
+                self.write(force);
+            }
+            InputResult::Command(AppCommand::WriteQuit { always }) => {
+                self.switch_mode(Mode::Normal);
+                self.write_quit(always);
+            }
+            InputResult::Command(AppCommand::Reload { force }) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.reload(force) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::CloseBuffer { force }) => {
+                self.switch_mode(Mode::Normal);
+                if !force && self.buffer.is_modified() {
+                    self.prompt = Some(Prompt::new("Save changes?", vec![
+                        Choice { key: 'y', label: "yes", answer: PromptAction::CloseBuffer { save: true } },
+                        Choice { key: 'n', label: "no", answer: PromptAction::CloseBuffer { save: false } },
+                        Choice { key: 'c', label: "cancel", answer: PromptAction::Cancel },
+                    ]));
+                } else {
+                    self.close_buffer();
+                }
+            }
+            InputResult::Command(AppCommand::Split { vertical }) => {
+                self.switch_mode(Mode::Normal);
+                self.split_window(vertical);
+            }
+            InputResult::Command(AppCommand::CloseWindow) => self.close_window_command(),
+            InputResult::Command(AppCommand::Filter(predicate)) => {
+                self.switch_mode(Mode::Normal);
+                match predicate {
+                    Some(predicate) => {
+                        if let Err(e) = self.start_filter(&predicate) {
+                            self.show_error(e.to_string());
+                        }
+                    }
+                    None => {
+                        self.filter_job = None;
+                        let message = if self.folds.unhide() { "Filter off" } else { "No filter" };
+                        self.show_message(message.to_string());
+                    }
+                }
+            }
+            InputResult::Command(AppCommand::SearchPrompt) => {
+                self.command_mode_handler.prompt = '/';
+                self.switch_mode(Mode::Command);
+            }
+            InputResult::Command(AppCommand::Search { pattern, scope }) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.search(pattern, scope) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::SearchKey(name)) => {
+                self.switch_mode(Mode::Normal);
+                self.search_nodes(Search::key(name));
+            }
+            InputResult::Command(AppCommand::SearchValue(literal)) => {
+                self.switch_mode(Mode::Normal);
+                self.search_nodes(Search::value(literal));
+            }
+            InputResult::Command(AppCommand::Palette { count }) => {
+                self.switch_mode(Mode::Normal);
+                self.open_palette(count);
+            }
+            InputResult::Command(AppCommand::Outline) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.open_outline() {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::Jump { back, count }) => self.jump(back, count),
+            InputResult::Command(AppCommand::Mark { name, linewise }) => self.goto_mark(name, linewise),
+            InputResult::Command(AppCommand::Map { mapping, remap }) => {
+                self.switch_mode(Mode::Normal);
+                match mapping {
+                    None => self.preview = Some(self.mappings_list()),
+                    Some((lhs, rhs)) => {
+                        let mapped = mode::parse_keys(&rhs)
+                            .and_then(|keys| self.keymap.map(Mode::Normal, &lhs, Binding::Keys { keys, remap }));
+                        if let Err(e) = mapped {
+                            self.show_error(e.to_string());
+                        }
+                    }
+                }
+            }
+            InputResult::Command(AppCommand::Unmap(lhs)) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.keymap.unmap(Mode::Normal, &lhs) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::Marks) => {
+                self.switch_mode(Mode::Normal);
+                self.preview = Some(self.marks_list());
+            }
+            InputResult::Command(AppCommand::Keys { all }) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.open_keys(all) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::SearchNext { forward, count }) => self.search_next(forward, count),
+            InputResult::Command(AppCommand::Help) => {
+                self.mode = Mode::Normal;
+                self.command_mode_handler.command_line.clear();
+                self.help = Some(HelpView::new(&self.keymap));
+                self.inspect = None;
+            }
+            InputResult::Command(AppCommand::Inspect { tokens }) => {
+                self.switch_mode(Mode::Normal);
+                let view = if tokens { Inspect::Tokens } else { Inspect::Nodes };
+                if view == Inspect::Nodes && self.structural_index.is_none() {
+                    self.show_message("Index not ready".to_string());
+                } else {
+                    self.help = Some(HelpView::scratch(view.title(), self.inspect_lines(view)));
+                    self.inspect = Some(view);
+                }
+            }
+            InputResult::Command(AppCommand::Profile) => {
+                self.switch_mode(Mode::Normal);
+                self.help = Some(HelpView::scratch(Inspect::Profile.title(), self.inspect_lines(Inspect::Profile)));
+                self.inspect = Some(Inspect::Profile);
+            }
+            InputResult::Command(AppCommand::Tasks) => {
+                self.switch_mode(Mode::Normal);
+                self.sync_tasks();
+                self.help = Some(HelpView::scratch(Inspect::Tasks.title(), self.inspect_lines(Inspect::Tasks)));
+                self.inspect = Some(Inspect::Tasks);
+            }
+            InputResult::Command(AppCommand::CopyToClipboard { path }) => {
+                self.switch_mode(Mode::Normal);
+                match self.clipboard_text(path) {
+                    Some(text) => self.register_map.set_clipboard(text),
+                    None => self.show_message("No value under cursor".to_string()),
+                }
+            }
+            InputResult::Command(AppCommand::Pipe { range, command }) => {
+                self.switch_mode(Mode::Normal);
+                let result = match range {
+                    PipeRange::Node => self.node_command(NodeOp::Pipe(command), None, false),
+                    PipeRange::Selection => match self.last_selection.clone() {
+                        Some(span) => self.start_pipe(span, &command),
+                        None => Err(anyhow::anyhow!("No selection")),
+                    },
+                    PipeRange::Buffer => self.start_pipe(0..self.buffer.len_bytes(), &command),
+                    PipeRange::Lines(_) => self.range_span(range).and_then(|span| self.start_pipe(span, &command)),
+                };
+                if let Err(e) = result {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::Stats) => {
+                self.switch_mode(Mode::Normal);
+                match self.structural_index.as_ref() {
+                    Some(index) => match stats::stats(index, &self.buffer, self.cursor.byte_offset, self.numbers) {
+                        Some(preview) => self.preview = Some(preview),
+                        None => self.show_message("No value under cursor".to_string()),
+                    },
+                    None => self.show_message("Index not ready".to_string()),
+                }
+            }
+            InputResult::Command(AppCommand::Diff(path)) => {
+                self.switch_mode(Mode::Normal);
+                match path {
+                    Some(path) => self.start_diff(path),
+                    None => match &self.diff {
+                        Some((path, diff)) => self.preview = Some(diff_summary(path, diff)),
+                        None => self.show_message("No diff (:diff {file})".to_string()),
+                    },
+                }
+            }
+            InputResult::Command(AppCommand::UndoList) => {
+                self.switch_mode(Mode::Normal);
+                self.preview = Some(undo_list(&self.undo_stack));
+            }
+            InputResult::Command(AppCommand::UndoHere(range)) => {
+                self.switch_mode(Mode::Normal);
+                match self.undo_here(range) {
+                    Ok(message) => self.show_message(message),
+                    Err(e) => self.show_error(e.to_string()),
+                }
+            }
+            InputResult::Command(AppCommand::DiffOff) => {
+                self.switch_mode(Mode::Normal);
+                if let Some((_, progress, _, _)) = self.diff_job.take() {
+                    progress.cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+                }
+                self.diff = None;
+                self.show_message("Diff off".to_string());
+            }
+            InputResult::Command(AppCommand::DiffSaved) => {
+                self.switch_mode(Mode::Normal);
+                self.diff_saved();
+            }
+            InputResult::Command(AppCommand::Schema(path)) => {
+                self.switch_mode(Mode::Normal);
+                match path {
+                    Some(path) => match std::fs::read_to_string(&path) {
+                        Ok(text) => match Schema::parse(&text) {
+                            Ok(loaded) => {
+                                self.schema = Some(Arc::new(loaded));
+                                // Filled in when the check is done
+                                self.fill_loclist(loclist::Source::Schema, Vec::new());
+                                self.start_schema_check();
+                                self.show_message(format!("Checking against {}…", path));
+                            }
+                            Err(e) => self.show_error(format!("{}: {}", path, e)),
+                        },
+                        Err(e) => self.show_error(format!("Can't read {}: {}", path, e)),
+                    },
+                    None => {
+                        self.schema = None;
+                        self.schema_job = None;
+                        self.schema_due = None;
+                        self.diagnostics.clear();
+                        self.show_message("Schema off".to_string());
+                    }
+                }
+            }
+            InputResult::Command(AppCommand::YamlView) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.yaml_view() {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::Ndjson(direction)) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.start_convert(direction) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::SplitDocs) => {
+                self.switch_mode(Mode::Normal);
+                if self.format == DocumentFormat::JsonLines {
+                    self.show_message("JSON Lines records are separate documents already".to_string());
+                } else if !self.split_docs {
+                    self.split_docs = true;
+                    match self.reset_structural_index() {
+                        Ok(()) => self.show_message("Reading concatenated documents; ]] and [[ move between them".to_string()),
+                        Err(e) => self.show_error(format!("Indexing failed: {}", e)),
+                    }
+                }
+            }
+            InputResult::Command(AppCommand::Validate) => {
+                // Passing, the document's ends as they are now are its own
+                let passes = self.structural_index.as_ref().is_some_and(|index| !index.is_dirty() && index.first_error().is_none());
+                if passes && !self.index_rebuilding && self.fully_indexed() {
+                    self.guard.accept(&self.buffer);
+                }
+                let msg = self.validate_report();
+                let locations = self.validation_locations();
+                if !locations.is_empty() {
+                    self.fill_loclist(loclist::Source::Validate, locations);
+                }
+                self.show_message(msg);
+            }
+            InputResult::Command(AppCommand::Bookmark(op)) => {
+                self.switch_mode(Mode::Normal);
+                match op {
+                    BookmarkOp::Add(note) => self.add_bookmark(note),
+                    BookmarkOp::Delete => self.delete_bookmark(),
+                    BookmarkOp::List => self.list_bookmarks(),
+                }
+            }
+            InputResult::Command(AppCommand::Script(op)) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.script_command(op) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::Grep { pattern, range }) => {
+                self.switch_mode(Mode::Normal);
+                match self.range_span(range) {
+                    Ok(span) => self.grep(pattern, span),
+                    Err(e) => self.show_error(e.to_string()),
+                }
+            }
+            InputResult::Command(AppCommand::WriteRange { range, path, force }) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.write_range(range, &path, force) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::Extract { pattern, path, output, force }) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.start_extract(pattern, path, output, force) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::LocationList { open }) => {
+                self.switch_mode(Mode::Normal);
+                self.open_loclist(open);
+            }
+            InputResult::Command(AppCommand::Location { forward, count }) => self.step_location(forward, count),
+            InputResult::Command(AppCommand::Node { op, register }) => {
+                if matches!(self.mode, Mode::Command | Mode::Visual { .. }) {
+                    self.switch_mode(Mode::Normal);
+                }
+                if let Err(e) = self.node_command(op, register, false) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::ForceEdit) => {
+                self.switch_mode(Mode::Normal);
+                let result = match self.refused_edit.take() {
+                    Some((op, register)) => self.node_command(op, register, true),
+                    None => Err(anyhow::anyhow!("No refused edit to force")),
+                };
+                if let Err(e) = result {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::Whitespace { op, range }) => {
+                self.switch_mode(Mode::Normal);
+                let _timing = perf::scoped("whitespace");
+                if let Err(e) = self.whitespace_command(op, range) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::CompleteKey { forward }) => {
+                if let Err(e) = self.complete_key(forward) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::Join { count, raw, selection }) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.join_lines(count, raw, selection) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::RenameAll(rename)) => {
+                self.switch_mode(Mode::Normal);
+                if let Err(e) = self.rename_all(rename, false) {
+                    self.show_error(e.to_string());
+                }
+            }
+            InputResult::Command(AppCommand::Set(option)) => {
+                match self.set_option(&option) {
+                    Ok(msg) => self.show_message(msg),
+                    Err(msg) => self.show_error(msg),
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Clear the message after 3 seconds
+    fn expire_message(&mut self) {
+        if self.message_time.is_some_and(|time| time.elapsed() >= MESSAGE_TIMEOUT) {
+            self.message = None;
+            self.message_time = None;
+        }
+    }
+    
+    /// Bring the task list up to date with the work running: saves and
+    /// indexing are tracked from their state here, and the other tasks,
+    /// registered as they start, are dropped once their jobs are gone
+    fn sync_tasks(&mut self) {
+        let save = self.buffer.save_progress();
+        let tracked = self.tasks.get(TaskKind::Save).is_some_and(|task| Arc::ptr_eq(&task.progress, &save));
+        if !self.buffer.is_saving() {
+            self.tasks.finish(TaskKind::Save);
+        } else if !tracked && !save.is_cancelled() {
+            let name = self.buffer.path().map(|path| path.display().to_string()).unwrap_or_default();
+            self.tasks.track(TaskKind::Save, name, save);
+        }
+        if let Some(task) = self.tasks.get_mut(TaskKind::Save) {
+            task.then = match (self.quit_after_save, self.close_after_save) {
+                (true, _) => Some("then quitting"),
+                (_, true) => Some("then closing"),
+                _ => None,
+            };
+        }
+
+        if self.index_in_flight.is_some() {
+            if self.tasks.get(TaskKind::Index).is_none() {
+                let lines = format!("to line {}", self.numbers.count(self.index_frontier.target().min(self.buffer.line_count())));
+                self.tasks.start(TaskKind::Index, lines);
+            }
+            let percent = self.index_percent();
+            if let Some(task) = self.tasks.get(TaskKind::Index) {
+                task.progress.total.store(100, std::sync::atomic::Ordering::Relaxed);
+                task.progress.done.store(percent, std::sync::atomic::Ordering::Relaxed);
+            }
+        } else {
+            self.tasks.finish(TaskKind::Index);
+        }
+
+        let running = [
+            (TaskKind::Schema, self.schema_job.is_some()),
+            (TaskKind::Filter, self.filter_job.is_some()),
+            (TaskKind::Diff, self.diff_job.is_some()),
+            (TaskKind::Checksum, self.checksum_job.is_some()),
+            (TaskKind::Shape, self.shape_job.is_some()),
+            (TaskKind::Convert, self.convert_job.is_some()),
+            (TaskKind::Pipe, self.pipe.is_some()),
+        ];
+        for (kind, running) in running {
+            if !running {
+                self.tasks.finish(kind);
+            }
+        }
+    }
+
+    /// Ctrl-C, or Esc with nothing else to do: stop a background task.
+    /// Its thread sees the cancel flag and gives up; what it would have
+    /// done is dropped.
+    fn cancel_task(&mut self, kind: TaskKind) {
+        self.tasks.cancel(kind);
+        match kind {
+            TaskKind::Index => self.cancel_indexing(),
+            TaskKind::Save => {
+                self.quit_after_save = false;
+                self.close_after_save = false;
+                self.show_message("Cancelling save…".to_string());
+            }
+            TaskKind::Schema => {}
+            TaskKind::Filter => {
+                self.filter_job = None;
+                self.show_message("Filter cancelled".to_string());
+            }
+            TaskKind::Diff => {
+                self.diff_job = None;
+                self.show_message("Diff cancelled".to_string());
+            }
+            TaskKind::Checksum => {
+                self.checksum_job = None;
+                self.show_message("Checksum cancelled".to_string());
+            }
+            TaskKind::Shape => self.shape_job = None,
+            TaskKind::Convert => {
+                self.convert_job = None;
+                self.show_message("Conversion cancelled".to_string());
+            }
+            TaskKind::Pipe => {
+                self.pipe = None;
+                self.show_message("Pipe cancelled".to_string());
+            }
+        }
+    }
+
+    /// Whether a background job is running, whose results the event loop
+    /// has to keep polling for
+    fn is_busy(&self) -> bool {
+        self.index_in_flight.is_some()
+            || self.buffer.is_saving()
+            || self.pipe.is_some()
+            || self.schema_job.is_some()
+            || self.filter_job.is_some()
+            || self.diff_job.is_some()
+            || self.checksum_job.is_some()
+            || self.shape_job.is_some()
+            || self.convert_job.is_some()
+            || self.extract.is_some()
+    }
+    
+    /// When the timers that can change the screen go off
+    fn deadlines(&self) -> Vec<Instant> {
+        [
+            self.message_time.map(|time| time + MESSAGE_TIMEOUT),
+            self.pending_since.map(|since| since + Duration::from_millis(self.timeoutlen)),
+            self.escapes.deadline(Duration::from_millis(self.ttimeoutlen)),
+            self.schema_due,
+            self.reindex_due,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+    
+    /// Keep folds on the text they were closed over, and other windows on
+    /// the lines they were showing
+    fn apply_buffer_changes(&mut self) {
+        let changes = self.buffer.drain_deltas();
+        // A lone edit redraws only the lines it left; the text below just
+        // moved. Several in a row, and everything from the first goes.
+        if let [change] = changes.as_slice() {
+            let first = self.buffer.byte_offset_to_line(change.offset);
+            let last = self.buffer.byte_offset_to_line(change.offset + change.inserted);
+            self.line_cache.apply_edit(first..=last, change.inserted as isize - change.removed as isize);
+        }
+        for change in changes.iter().filter(|_| changes.len() > 1) {
+            self.line_cache.invalidate_from(self.buffer.byte_offset_to_line(change.offset));
+        }
+        for change in &changes {
+            if let Some(index) = self.structural_index.as_mut() {
+                index.apply_change(change);
+            }
+            self.folds.apply_change(change);
+            self.signs.apply_change(change);
+            for view in self.windows.inactive_mut() {
+                view.apply_change(change);
+            }
+            schema::apply_change(&mut self.diagnostics, change);
+            self.duplicate_keys.apply_change(change);
+            if let Some(list) = self.loclist.as_mut() {
+                list.apply_change(change);
+            }
+            self.bookmarks.apply_change(change);
+            if let Some(search) = self.search.as_mut() {
+                search.apply_change(change);
+            }
+            if let Some((_, diff)) = self.diff.as_mut() {
+                diff::apply_change(&mut diff.changes, change);
+            }
+        }
+        // The whole text replaced at once, as by :to-ndjson or undoing it,
+        // is a new document: its format is looked at again, and it's what
+        // the guard watches from now on
+        let replaced = replaced_whole(&changes, self.buffer.len_bytes());
+        if replaced && !self.format.is_relaxed() {
+            let format = DocumentFormat::sniff(&self.buffer.get_visible_lines(0, 16));
+            if let Err(e) = self.set_format(format) {
+                self.show_error(format!("Indexing failed: {}", e));
+            }
+        }
+        if replaced {
+            self.guard = Guard::new(&self.buffer);
+        } else if let Some(warning) = self.guard.apply(&changes, &self.buffer, self.deletewarn) {
+            self.show_error(format!("Warning: {}", warning));
+        }
+        if !changes.is_empty() {
+            self.index_window = None;
+            if let Some(index) = self.structural_index.as_mut() {
+                index.recheck_strings(|span| self.buffer.slice(span));
+            }
+            if self.schema.is_some() {
+                self.schema_due = Some(Instant::now() + SCHEMA_DEBOUNCE);
+            }
+            self.expanded_strings.clear();
+            for view in self.windows.inactive_mut() {
+                view.resync(&self.buffer);
+            }
+            self.index_frontier.truncate(self.buffer.line_count());
+            if self.index_in_flight.is_some() {
+                // The chunk on its way was cut from the text before the edit
+                if let Err(e) = self.reset_structural_index() {
+                    self.show_error(format!("Indexing failed: {}", e));
+                }
+            } else if self.structural_index.is_some() && self.buffer.len_bytes() <= REINDEX_MAX_BYTES {
+                self.reindex_due = Some(Instant::now() + REINDEX_DEBOUNCE);
+            }
+        }
+    }
+
+    /// Rebuild an index left dirty by edits once typing has paused
+    fn poll_reindex(&mut self) {
+        if self.reindex_due.is_some_and(|due| Instant::now() >= due) {
+            self.reindex_due = None;
+            if self.structural_index.as_ref().is_some_and(|index| index.is_dirty()) {
+                if let Err(e) = self.reset_structural_index() {
+                    self.show_error(format!("Indexing failed: {}", e));
+                }
+            }
+        }
+    }
+
+    /// dn / yn / ]p / ]m / [m. These need node spans that match the text,
+    /// so after an edit (including their own) they wait for the index to be
+    /// rebuilt; that way ]m can be pressed again straight away.
+    fn node_command(&mut self, op: NodeOp, register: Option<char>, force: bool) -> Result<()> {
+        if self.index_revision != self.buffer.revision() {
+            self.reset_structural_index()?;
+        }
+        if self.index_in_flight.is_some() {
+            self.pending_node = Some((op, register, force));
+            return Ok(());
+        }
+        self.refused_edit = None;
+        let refusable = op.clone();
+        let Some(index) = self.structural_index.as_ref() else {
+            anyhow::bail!("Index not ready");
+        };
+        let step = match self.recording_script {
+            true => path::parse_path(&json_path(index, &self.buffer, self.cursor.byte_offset)).ok().and_then(|path| Step::node(&op, path)),
+            false => None,
+        };
+        match op {
+            NodeOp::Yank => {
+                let text = structural::yank(&self.buffer, index, self.cursor.byte_offset)?;
+                self.register_map.set(register, text, true);
+                return Ok(());
+            }
+            NodeOp::Delete => {
+                let (edit, text) = structural::delete(&mut self.buffer, &mut self.cursor, index)?;
+                self.register_map.set(register, text, false);
+                self.undo_stack.begin_labeled_group(&op.label());
+                self.undo_stack.push(edit);
+                self.undo_stack.end_group();
+            }
+            NodeOp::Paste => {
+                let Some(text) = self.register_map.get(register) else {
+                    return Ok(());
+                };
+                let edit = structural::paste(&mut self.buffer, &mut self.cursor, index, &text)?;
+                self.undo_stack.begin_labeled_group(&op.label());
+                self.undo_stack.push(edit);
+                self.undo_stack.end_group();
+            }
+            NodeOp::EditString => {
+                let (span, text) = structural::string_at(&self.buffer, index, self.cursor.byte_offset)?;
+                self.string_editor = Some(StringEditor::new(span, &text));
+                return Ok(());
+            }
+            NodeOp::Pipe(_) | NodeOp::Export { .. } => {
+                let Some(member) = structural::member_at(index, self.cursor.byte_offset) else {
+                    anyhow::bail!("No value under cursor");
+                };
+                let Some(value) = index.get(member.value) else {
+                    anyhow::bail!("No value under cursor");
+                };
+                if value.is_container() && !index.is_closed(member.value) {
+                    anyhow::bail!("Node isn't fully indexed yet");
+                }
+                let span = value.start..value.end;
+                return match op {
+                    NodeOp::Export { ref path, pretty, force } => {
+                        let written = export::export(&self.buffer, span, std::path::Path::new(path), pretty, force)?;
+                        self.show_message(format!("\"{}\" {} bytes written", path, self.numbers.count(written)));
+                        Ok(())
+                    }
+                    NodeOp::Pipe(ref command) => self.start_pipe(span, command),
+                    _ => Ok(()),
+                };
+            }
+            NodeOp::Array(op) => {
+                let (edits, message) = array::array(&mut self.buffer, &mut self.cursor, index, op)?;
+                if edits.is_empty() {
+                    self.show_message(message);
+                    return Ok(());
+                }
+                self.undo_stack.begin_labeled_group(&message);
+                for edit in edits {
+                    self.undo_stack.push(edit);
+                }
+                self.undo_stack.end_group();
+                self.show_message(message);
+            }
+            NodeOp::Rewrap { ref selection } => {
+                let (buffer, cursor) = (&mut self.buffer, &mut self.cursor);
+                let edits = rewrap::rewrap(buffer, cursor, index, selection.clone(), self.text_width, self.wrap_style, self.shiftwidth)?;
+                self.undo_stack.begin_labeled_group(&op.label());
+                for edit in edits {
+                    self.undo_stack.push(edit);
+                }
+                self.undo_stack.end_group();
+            }
+            NodeOp::Convert(conversion) => {
+                let (edit, old) = structural::convert(&mut self.buffer, &mut self.cursor, index, conversion)?;
+                if conversion == Conversion::Null {
+                    self.register_map.set(register, old, false);
+                }
+                self.undo_stack.begin_labeled_group(&op.label());
+                self.undo_stack.push(edit);
+                self.undo_stack.end_group();
+            }
+            NodeOp::MoveUp | NodeOp::MoveDown | NodeOp::Rename { .. } | NodeOp::Wrap { .. } | NodeOp::Unwrap { .. } => {
+                let (buffer, cursor) = (&mut self.buffer, &mut self.cursor);
+                let edits = match op {
+                    NodeOp::Rename { ref name, force } => structural::rename(buffer, cursor, index, name, force)?,
+                    NodeOp::Wrap { ref key } => structural::wrap(buffer, cursor, index, key.as_deref(), self.shiftwidth)?,
+                    NodeOp::Unwrap { splice } => structural::unwrap(buffer, cursor, index, splice)?,
+                    _ => structural::swap(buffer, cursor, index, op == NodeOp::MoveDown)?,
+                };
+                self.undo_stack.begin_labeled_group(&op.label());
+                for edit in edits {
+                    self.undo_stack.push(edit);
+                }
+                self.undo_stack.end_group();
+            }
+        }
+        if let Some(refusal) = self.after_node_edit(force)? {
+            self.refused_edit = Some((refusable, register));
+            anyhow::bail!(refusal);
+        }
+        if let Some(step) = step {
+            self.edit_script.push(step);
+        }
+        Ok(())
+    }
+
+    /// Write the :edit-string overlay's text back over the literal it was
+    /// opened on, escaped, as one undo step
+    fn save_string(&mut self, editor: StringEditor) -> Result<()> {
+        let edits = structural::replace_string(&mut self.buffer, &mut self.cursor, editor.span.clone(), &editor.text())?;
+        self.undo_stack.begin_labeled_group(&NodeOp::EditString.label());
+        for edit in edits {
+            self.undo_stack.push(edit);
+        }
+        self.undo_stack.end_group();
+        self.after_node_edit(true).map(drop)
+    }
+
+    /// Catch the view and index up with a node edit. With :set checkedits,
+    /// an edit that leaves the container around it invalid is warned
+    /// about; with strictedits it's taken back unless `force`, and the
+    /// reason returned.
+    fn after_node_edit(&mut self, force: bool) -> Result<Option<String>> {
+        self.apply_buffer_changes();
+        let problem = self.edit_problem().map(|problem| {
+            let line = self.buffer.byte_offset_to_line(problem.offset) + 1;
+            format!("invalid JSON at byte {} on line {}: {}", problem.offset, line, problem.message())
+        });
+        let refused = self.strict_edits && !force && problem.is_some();
+        if refused {
+            self.undo_stack.drop_last(&mut self.buffer, &mut self.cursor)?;
+            self.apply_buffer_changes();
+        }
+        self.current_node_id = None;
+        self.update_viewport_for_cursor();
+        self.reset_structural_index()?;
+        Ok(match problem {
+            Some(problem) if refused => Some(format!("Refused: the edit would leave {} (:force makes it)", problem)),
+            Some(problem) => {
+                self.show_error(format!("Warning: the edit left {}", problem));
+                None
+            }
+            None => None,
+        })
+    }
+
+    /// The first problem in the container around the node edit just made,
+    /// with :set checkedits or strictedits. The index has been shifted past
+    /// the edit, but not yet rebuilt, so its dirty spans are the edit.
+    fn edit_problem(&self) -> Option<StructuralError> {
+        if !self.check_edits && !self.strict_edits {
+            return None;
+        }
+        let index = self.structural_index.as_ref()?;
+        let changed = index.dirty_spans().first()?.start..index.dirty_spans().last()?.end;
+        let span = check::check_span(index, changed)?;
+        check::first_problem(&self.buffer, span, self.format.is_relaxed())
+    }
+
+    /// Start piping `span` through `command`; the output replaces it when
+    /// the command succeeds
+    fn start_pipe(&mut self, span: std::ops::Range<usize>, command: &str) -> Result<()> {
+        if self.pipe.is_some() {
+            anyhow::bail!("A pipe is already running");
+        }
+        let job = PipeJob::start(command, self.buffer.slice(span.clone()), self.shellpipe)?;
+        self.tasks.start(TaskKind::Pipe, command);
+        self.pipe = Some((job, span, self.buffer.revision()));
+        self.show_message(format!("Piping through {}… (Ctrl-C cancels)", command));
+        Ok(())
+    }
+
+    /// Put a finished pipe's output in place of what it was given, as one
+    /// undo step. A failed command, or a buffer edited since, leaves the
+    /// text alone.
+    fn poll_pipe(&mut self) -> Result<()> {
+        let Some(result) = self.pipe.as_mut().and_then(|(job, _, _)| job.poll()) else {
+            return Ok(());
+        };
+        let Some((job, span, revision)) = self.pipe.take() else {
+            return Ok(());
+        };
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                self.show_error(e.to_string());
+                return Ok(());
+            }
+        };
+        if revision != self.buffer.revision() {
+            self.show_error("Buffer changed while the pipe ran; output dropped".to_string());
+            return Ok(());
+        }
+        // Keep a command's final newline only where the input had one
+        let input_newline = self.buffer.slice(span.clone()).ends_with('\n');
+        let text = match output.stdout.strip_suffix('\n') {
+            Some(text) if !input_newline => text,
+            _ => output.stdout.as_str(),
+        };
+        let delete = EditOperations::delete(&mut self.buffer, &mut self.cursor, span.start, span.end)?;
+        let insert = EditOperations::insert(&mut self.buffer, &mut self.cursor, span.start, text)?;
+        self.cursor.move_to_offset(&self.buffer, span.start);
+        self.undo_stack.begin_labeled_group(&NodeOp::Pipe(job.command.clone()).label());
+        self.undo_stack.push(delete);
+        self.undo_stack.push(Edit { cursor_after: (&self.cursor).into(), ..insert });
+        self.undo_stack.end_group();
+        match output.stderr.lines().rfind(|line| !line.trim().is_empty()) {
+            Some(line) => self.show_message(format!("Piped {} bytes; {}", text.len(), line)),
+            None => self.show_message(format!("Piped {} bytes", text.len())),
+        }
+        // The command has run, so its output is only ever warned about
+        self.after_node_edit(true).map(drop)
+    }
+
+    /// Check the whole buffer against the loaded schema on a thread of its
+    /// own. A check already running is left to finish and dropped.
+    fn start_schema_check(&mut self) {
+        let Some(schema) = self.schema.clone() else {
+            return;
+        };
+        let text = self.buffer.slice(0..self.buffer.len_bytes());
+        self.tasks.start(TaskKind::Schema, "");
+        let job = std::thread::spawn(move || {
+            let mut timing = perf::scoped("schema check");
+            timing.bytes(text.len());
+            schema::validate_text(&schema, &text)
+        });
+        self.schema_job = Some((job, self.buffer.revision()));
+        self.schema_due = None;
+    }
+
+    /// Pick up a finished schema check, and start the next one once edits
+    /// have paused
+    fn poll_schema(&mut self) {
+        if self.schema_job.as_ref().is_some_and(|(job, _)| job.is_finished()) {
+            if let Some((job, revision)) = self.schema_job.take() {
+                match job.join() {
+                    // Results for text edited since are stale; a newer check is due
+                    Ok(diagnostics) if revision == self.buffer.revision() => {
+                        self.diagnostics = diagnostics;
+                        if self.loclist.as_ref().is_some_and(|list| *list.source() == loclist::Source::Schema) {
+                            self.fill_loclist(loclist::Source::Schema, self.schema_locations());
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(_) => self.show_error("Schema check failed".to_string()),
+                }
+            }
+        }
+        if self.schema_job.is_none() && self.schema_due.is_some_and(|due| Instant::now() >= due) {
+            self.start_schema_check();
+        }
+    }
+
+    /// :filter: test the elements of the array around the cursor on a
+    /// thread of their own, then fold away those that fail
+    fn start_filter(&mut self, typed: &str) -> Result<()> {
+        let predicate = Predicate::parse(typed)?;
+        let Some(index) = &self.structural_index else {
+            anyhow::bail!("Index not ready");
+        };
+        let array = std::iter::successors(index.node_id_at(self.cursor.byte_offset), |&id| index.parent(id))
+            .find(|&id| index.kind(id) == Some(NodeKind::Array));
+        let Some((id, array)) = array.and_then(|id| Some((id, index.get(id)?))) else {
+            anyhow::bail!("Not in an array");
+        };
+        if !index.is_closed(id) {
+            anyhow::bail!("Array isn't fully indexed yet");
+        }
+        let text = self.buffer.slice(array.start..array.end);
+        let progress = self.tasks.start(TaskKind::Filter, typed);
+        let job = std::thread::spawn(move || {
+            let mut timing = perf::scoped("filter");
+            timing.bytes(text.len());
+            filter::filter_array(&text, &predicate, &progress)
+        });
+        self.filter_job = Some((job, self.buffer.revision(), array.start));
+        self.show_message("Filtering…".to_string());
+        Ok(())
+    }
+
+    /// Fold away what a finished :filter found, unless the text has
+    /// changed under it
+    fn poll_filter(&mut self) {
+        if !self.filter_job.as_ref().is_some_and(|(job, _, _)| job.is_finished()) {
+            return;
+        }
+        let Some((job, revision, base)) = self.filter_job.take() else {
+            return;
+        };
+        match job.join() {
+            Ok(_) if revision != self.buffer.revision() => {
+                self.show_error("The buffer changed while filtering; run :filter again".to_string());
+            }
+            Ok(filtered) => {
+                let hidden = filtered.hidden();
+                let runs = filtered.runs.into_iter().map(|(run, count)| (run.start + base..run.end + base, count)).collect();
+                self.folds.hide(runs);
+                let grouped = |count| ChildCount { count, complete: true };
+                let mut message =
+                    format!("Filter: {} of {} shown", grouped(filtered.total - hidden), grouped(filtered.total));
+                if filtered.kept > 0 {
+                    message.push_str(&format!(" ({} failing share lines with others)", filtered.kept));
+                }
+                self.show_message(message);
+                self.reveal_cursor();
+                self.update_viewport_for_cursor();
+            }
+            Err(_) => self.show_error("Filter failed".to_string()),
+        }
+    }
+
+    /// Compare the buffer with `path` on a thread of its own, both indexed
+    /// from scratch there
+    fn start_diff(&mut self, path: String) {
+        if let Some((_, progress, _, _)) = self.diff_job.take() {
+            progress.cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        let text = self.buffer.slice(0..self.buffer.len_bytes());
+        let progress = self.tasks.start(TaskKind::Diff, path.clone());
+        let job = {
+            let (path, progress) = (path.clone(), progress.clone());
+            std::thread::spawn(move || {
+                let other_text = std::fs::read_to_string(&path).map_err(|e| anyhow::anyhow!("Can't read {}: {}", path, e))?;
+                let (ours, other) = (diff::index_text(&text), diff::index_text(&other_text));
+                Ok(diff::diff(&Side { index: &ours, text: &text }, &Side { index: &other, text: &other_text }, &progress))
+            })
+        };
+        self.diff_job = Some((job, progress, path, self.buffer.revision()));
+    }
+
+    /// :diffsaved - the lines changed since the last save, in the location
+    /// list and, as :diff's are, in the gutter for ]c / [c
+    fn diff_saved(&mut self) {
+        let changes = match against_saved(&self.buffer) {
+            Ok(changes) => changes,
+            Err(e) => {
+                self.show_error(e.to_string());
+                return;
+            }
+        };
+        if changes.is_empty() {
+            self.show_message("No changes since the last save".to_string());
+            return;
+        }
+        if let Some((_, progress, _, _)) = self.diff_job.take() {
+            progress.cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        let locations = changes.iter().map(|c| self.location(c.span.start, c.describe(), Severity::Info)).collect();
+        self.fill_loclist(loclist::Source::DiffSaved, locations);
+        self.open_loclist(true);
+        let diff = Diff { changes, truncated: false };
+        let path = "the last save".to_string();
+        self.show_message(diff_count(&path, &diff));
+        self.diff = Some((path, diff));
+    }
+
+    /// Pick up a finished diff and show its summary
+    fn poll_diff(&mut self) {
+        if !self.diff_job.as_ref().is_some_and(|(job, _, _, _)| job.is_finished()) {
+            return;
+        }
+        let Some((job, _, path, revision)) = self.diff_job.take() else {
+            return;
+        };
+        match job.join() {
+            Ok(Ok(_)) if revision != self.buffer.revision() => {
+                self.show_error("Buffer changed during the diff; run :diff again".to_string());
+            }
+            Ok(Ok(diff)) => {
+                self.show_message(diff_count(&path, &diff));
+                self.preview = Some(diff_summary(&path, &diff));
+                self.diff = Some((path, diff));
+            }
+            Ok(Err(e)) => self.show_error(e.to_string()),
+            Err(_) => self.show_error("Diff failed".to_string()),
+        }
+    }
+
+    /// :checksum - hash the text as it is now on a thread of its own,
+    /// editing going on meanwhile
+    fn start_checksum(&mut self, copy: bool) -> Result<()> {
+        if let Some((_, progress, _, _)) = self.checksum_job.take() {
+            progress.cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        let snapshot = self.buffer.snapshot()?;
+        let revision = snapshot.revision();
+        let progress = self.tasks.start(TaskKind::Checksum, "");
+        let job = {
+            let progress = progress.clone();
+            std::thread::spawn(move || checksum::of_snapshot(&snapshot, &progress))
+        };
+        self.checksum_job = Some((job, progress, copy, revision));
+        Ok(())
+    }
+
+    /// Pick up a finished checksum: `SHA-256 <hex>  12,345 bytes`
+    fn poll_checksum(&mut self) {
+        if !self.checksum_job.as_ref().is_some_and(|(job, _, _, _)| job.is_finished()) {
+            return;
+        }
+        let Some((job, _, copy, revision)) = self.checksum_job.take() else {
+            return;
+        };
+        match job.join() {
+            Ok(Ok(checksum)) => {
+                let copied = if copy { " (copied)" } else { "" };
+                let stale = if revision != self.buffer.revision() { ", before the latest edits" } else { "" };
+                if copy {
+                    self.register_map.set_clipboard(checksum.hex());
+                }
+                self.show_message(format!("{}  {} bytes{}{}", checksum, self.numbers.count(checksum.bytes), stale, copied));
+            }
+            Ok(Err(e)) => self.show_error(e.to_string()),
+            Err(_) => self.show_error("Checksum failed".to_string()),
+        }
+    }
+
+    /// Find the document's top-level shape on another thread, reading the
+    /// file's mmap rather than copying it when it's that big. Once found it's
+    /// added to `loaded`, if that's still the message showing.
+    fn start_shape(&mut self, loaded: Option<String>) {
+        if self.shape_job.take().is_some() {
+            self.tasks.cancel(TaskKind::Shape);
+        }
+        if self.buffer.len_bytes() == 0 {
+            return;
+        }
+        let text = self.buffer.shared_text();
+        let records = self.format == DocumentFormat::JsonLines;
+        let name = self.buffer.path().map(|path| path.display().to_string()).unwrap_or_default();
+        let progress = self.tasks.start(TaskKind::Shape, name);
+        let job = std::thread::spawn(move || match records {
+            true => shape::count_records(text.as_bytes(), &progress),
+            false => shape::detect(text.as_bytes(), &progress),
+        });
+        self.shape_job = Some((job, self.buffer.revision(), loaded));
+    }
+
+    /// Pick up the top-level shape, kept on the buffer for Ctrl-g
+    fn poll_shape(&mut self) {
+        if !self.shape_job.as_ref().is_some_and(|(job, _, _)| job.is_finished()) {
+            return;
+        }
+        let Some((job, revision, loaded)) = self.shape_job.take() else {
+            return;
+        };
+        let Ok(Some(shape)) = job.join() else {
+            return;
+        };
+        self.buffer.set_shape(revision, shape);
+        let Some(loaded) = loaded else {
+            return;
+        };
+        let top_level = format!("Top-level: {}", shape.describe(self.numbers));
+        match &self.message {
+            Some(message) if *message == loaded => self.show_message(format!("{}. {}", loaded, top_level)),
+            None => self.show_message(top_level),
+            Some(_) => {}
+        }
+    }
+
+    /// :script start/stop/save - record node edits, renames and reformats
+    /// by path, for `jim --apply` to make on other files
+    fn script_command(&mut self, op: ScriptOp) -> Result<()> {
+        let count = self.edit_script.steps.len();
+        let steps = if count == 1 { "1 step".to_string() } else { format!("{} steps", self.numbers.count(count)) };
+        match op {
+            ScriptOp::Start => {
+                self.edit_script = EditScript::new();
+                self.recording_script = true;
+                self.show_message("Recording edits (:script stop ends it)".to_string());
+            }
+            ScriptOp::Stop => {
+                if !self.recording_script {
+                    anyhow::bail!("Not recording (:script start begins)");
+                }
+                self.recording_script = false;
+                self.show_message(format!("Recorded {}", steps));
+            }
+            ScriptOp::Save(file) => {
+                if count == 0 {
+                    anyhow::bail!("Nothing recorded (:script start begins)");
+                }
+                self.edit_script.save(std::path::Path::new(&file))?;
+                self.show_message(format!("\"{}\" {} written", file, steps));
+            }
+        }
+        Ok(())
+    }
+
+    /// --apply: say how the step run last went, then run the next once the
+    /// whole file is indexed, so its path can be found. An error shown
+    /// since stops the script with the file unwritten; once every step has
+    /// worked the file is written.
+    fn replay_step(&mut self) -> Result<()> {
+        let Some(replay) = self.replay.as_mut() else {
+            return Ok(());
+        };
+        let report = replay.running.take().map(|(n, step)| format!("{}: step {}/{} {}", replay.file, n, replay.total, step.describe()));
+        if !self.script_errors.is_empty() {
+            let at = report.unwrap_or_else(|| replay.file.clone());
+            let errors = std::mem::take(&mut self.script_errors).join("; ");
+            self.script_errors.push(format!("{}: {}", at, errors));
+            self.replay = None;
+            self.should_quit = true;
+            return Ok(());
+        }
+        if let Some(report) = report {
+            println!("{}: ok", report);
+        }
+        if self.index_revision != self.buffer.revision() {
+            self.reset_structural_index()?;
+        }
+        if !self.fully_indexed() {
+            self.request_structural_index(self.buffer.line_count())?;
+        }
+        if self.index_in_flight.is_some() {
+            return Ok(());
+        }
+        let Some(replay) = self.replay.as_mut() else {
+            return Ok(());
+        };
+        let Some(step) = replay.steps.pop_front() else {
+            self.replay = None;
+            if self.buffer.is_modified() {
+                self.start_write();
+            }
+            return Ok(());
+        };
+        replay.running = Some((replay.total - replay.steps.len(), step.clone()));
+        if let Err(e) = self.run_step(step) {
+            self.show_error(e.to_string());
+        }
+        Ok(())
+    }
+
+    /// Make one step of a script, a node edit on the node its path names
+    fn run_step(&mut self, step: Step) -> Result<()> {
+        match step {
+            Step::Node { path, op } => {
+                let Some(index) = self.structural_index.as_ref() else {
+                    anyhow::bail!("Index not ready");
+                };
+                let node = match path::resolve(index, &self.buffer, &path) {
+                    Resolved::Found(node) => node,
+                    Resolved::Pending => anyhow::bail!("{} isn't indexed", path::format_path(&path)),
+                    Resolved::Missing(why) => anyhow::bail!(why),
+                };
+                let Some(start) = index.get(node).map(|node| node.start) else {
+                    anyhow::bail!("Index not ready");
+                };
+                self.current_node_id = None;
+                self.move_cursor_to_offset(start);
+                self.node_command(op, None, false)
+            }
+            Step::RenameAll(rename) => self.rename_all(rename, true),
+            Step::Whitespace(op) => self.whitespace_command(op, PipeRange::Buffer),
+            Step::Ndjson(direction) => self.start_convert(direction),
+        }
+    }
+
+    /// :to-ndjson / :from-ndjson - convert the whole text on another thread
+    fn start_convert(&mut self, direction: Direction) -> Result<()> {
+        let ndjson = self.format == DocumentFormat::JsonLines;
+        match direction {
+            Direction::ToNdjson if ndjson => anyhow::bail!("Already JSON Lines"),
+            Direction::FromNdjson if !ndjson => anyhow::bail!("Not JSON Lines"),
+            _ => {}
+        }
+        if self.convert_job.is_some() {
+            anyhow::bail!("Already converting");
+        }
+        let text = self.buffer.shared_text();
+        let indent = self.shiftwidth;
+        let progress = self.tasks.start(TaskKind::Convert, direction.label());
+        let job = std::thread::spawn(move || {
+            let mut timing = perf::scoped(direction.label());
+            timing.bytes(text.as_bytes().len());
+            ndjson::convert(text.as_bytes(), direction, indent, &progress)
+        });
+        self.convert_job = Some((job, direction, self.buffer.revision()));
+        Ok(())
+    }
+
+    /// Put a finished conversion in place of the text, as one change to
+    /// undo, and index it again in the format it's now in
+    fn poll_convert(&mut self) -> Result<()> {
+        if !self.convert_job.as_ref().is_some_and(|(job, _, _)| job.is_finished()) {
+            return Ok(());
+        }
+        let Some((job, direction, revision)) = self.convert_job.take() else {
+            return Ok(());
+        };
+        let (text, records) = job.join().map_err(|_| anyhow::anyhow!("Conversion failed"))??;
+        if revision != self.buffer.revision() {
+            anyhow::bail!("The buffer changed while converting; run :{} again", direction.label());
+        }
+        let old_text = self.buffer.slice(0..self.buffer.len_bytes());
+        let cursor_before = (&self.cursor).into();
+        self.buffer.replace_all(&text)?;
+        self.cursor.move_to_offset(&self.buffer, 0);
+        self.undo_stack.begin_labeled_group(direction.label());
+        self.undo_stack.push(Edit::new(0, old_text, text, cursor_before, (&self.cursor).into()));
+        self.undo_stack.end_group();
+        self.apply_buffer_changes();
+        let format = match direction {
+            Direction::ToNdjson => DocumentFormat::JsonLines,
+            Direction::FromNdjson => DocumentFormat::Json,
+        };
+        self.set_format(format)?;
+        self.update_viewport_for_cursor();
+        if self.recording_script {
+            self.edit_script.push(Step::Ndjson(direction));
+        }
+        let records = ChildCount { count: records, complete: true };
+        match direction {
+            Direction::ToNdjson => self.show_message(format!("{} records as JSON Lines", records)),
+            Direction::FromNdjson => self.show_message(format!("{} records as an array", records)),
+        }
+        Ok(())
+    }
+
+    /// Read the text as `format` from now on, indexing it again if that's a
+    /// change
+    fn set_format(&mut self, format: DocumentFormat) -> Result<()> {
+        if format == self.format {
+            return Ok(());
+        }
+        self.format = format;
+        self.split_docs = false;
+        self.reset_structural_index()
+    }
+
+    /// What :yank-path and :yank-value copy: the path to the cursor, or the
+    /// text of the value under it (after the key, on a key)
+    fn clipboard_text(&mut self, path: bool) -> Option<String> {
+        if path {
+            return self.structural_index.is_some().then(|| self.cursor_path());
+        }
+        let index = self.structural_index.as_ref()?;
+        let mut id = index.node_id_at(self.cursor.byte_offset)?;
+        if index.is_key_node(id) {
+            id = index.next_sibling(id)?;
+        }
+        let node = index.get(id)?;
+        Some(self.buffer.slice(node.start..node.end))
+    }
+
+    /// Send text put in the + or * register to the system clipboard: as an
+    /// OSC 52 sequence through the terminal's own handle, between frames so
+    /// it can't split a draw, and to a local clipboard tool if there is one
+    fn send_clipboard(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) {
+        let Some(text) = self.register_map.take_outgoing() else {
+            return;
+        };
+        let backend = terminal.backend_mut();
+        let sent = backend.write_all(clipboard::osc52(&text).as_bytes()).and_then(|_| backend.flush());
+        match (sent, clipboard::copy_with_tool(&text)) {
+            (Err(e), Err(_)) => self.show_error(format!("Clipboard: {}", e)),
+            _ => self.show_message(format!("Copied {} bytes to the clipboard", self.numbers.count(text.len()))),
+        }
+    }
+
+    /// Ctrl-g: `"data.json" [Modified] line 12 of 340 --3%-- offset 1,234`
+    fn file_info(&self) -> String {
+        let name = self.buffer.path().map_or("[No Name]".to_string(), |path| path.display().to_string());
+        let modified = match (self.buffer.is_modified(), self.buffer.is_new_file()) {
+            (true, true) => " [Modified] [New File]",
+            (true, false) => " [Modified]",
+            (false, true) => " [New File]",
+            (false, false) => "",
+        };
+        let lines = self.buffer.line_count().max(1);
+        let line = self.buffer.byte_offset_to_line(self.cursor.byte_offset) + 1;
+        let mut info = format!(
+            "\"{}\"{} line {} of {} --{}%-- offset {}",
+            name,
+            modified,
+            self.numbers.count(line),
+            self.numbers.count(lines),
+            line * 100 / lines,
+            self.numbers.count(self.cursor.byte_offset),
+        );
+        if let Some(shape) = self.buffer.shape() {
+            info.push_str(&format!(" | Top-level: {}", shape.describe(self.numbers)));
+        }
+        info
+    }
+
+    /// g Ctrl-g: where the cursor is counted in columns, lines, words,
+    /// characters and bytes, then the node under it. Past
+    /// `counts::SCAN_LIMIT` bytes words and characters are estimates,
+    /// marked ≈.
+    fn position_counts(&self) -> String {
+        let offset = self.cursor.byte_offset;
+        let (line, col) = self.buffer.offset_to_line_col(offset);
+        let text = self.buffer.get_line(line);
+        let width = text.trim_end_matches(['\n', '\r']).chars().count();
+        let report = counts::count(&self.buffer, offset, counts::SCAN_LIMIT);
+        let approx = if report.estimated { "≈" } else { "" };
+        let mut info = format!(
+            "Col {} of {}; Line {} of {}; Word {}{} of {}{}; Char {}{} of {}{}; Byte {} of {}",
+            self.numbers.count((col + 1).min(width)),
+            self.numbers.count(width),
+            self.numbers.count(line + 1),
+            self.numbers.count(self.buffer.line_count()),
+            approx,
+            self.numbers.count(report.cursor.words),
+            approx,
+            self.numbers.count(report.total.words),
+            approx,
+            self.numbers.count(report.cursor.chars),
+            approx,
+            self.numbers.count(report.total.chars),
+            self.numbers.count(report.cursor.bytes),
+            self.numbers.count(report.total.bytes),
+        );
+        let Some(index) = &self.structural_index else {
+            return info;
+        };
+        let node = self.current_node_id.or_else(|| index.node_id_at(offset));
+        if let Some((id, node)) = node.and_then(|id| Some((id, index.get(id)?))) {
+            info.push_str(&format!("; {:?} {}-{}", node.kind, self.numbers.count(node.start), self.numbers.count(node.end)));
+            if let Some(count) = index.child_count(id) {
+                let noun = if node.kind == NodeKind::Object { "members" } else { "items" };
+                info.push_str(&format!(", {} {}", self.numbers.child_count(count), noun));
+            }
+        }
+        info
+    }
+
+    /// K: pop up the value under the cursor (or after the key under it)
+    fn preview_value(&mut self) {
+        let Some(index) = self.structural_index.as_ref() else {
+            self.show_message("Index not ready".to_string());
+            return;
+        };
+        // On text that isn't JSON, what's wrong with it
+        let error = index.node_id_at(self.cursor.byte_offset)
+            .and_then(|id| index.get(id))
+            .filter(|node| node.kind == NodeKind::Error)
+            .and_then(|node| {
+                let errors = index.errors();
+                errors[errors.partition_point(|e| e.offset < node.start)..]
+                    .iter()
+                    .take_while(|error| error.offset == node.start)
+                    .find(|error| error.kind.is_syntax())
+            });
+        if let Some(error) = error {
+            let message = error.message();
+            self.show_error(message);
+            return;
+        }
+        self.preview = ValuePreview::at(index, &self.buffer, self.cursor.byte_offset);
+        if self.preview.is_none() {
+            self.show_message("No value under cursor".to_string());
+        }
+    }
+
+    /// What a :tokens or :nodes pane shows now: the tokens of the lines in
+    /// view, or the nodes around the cursor, from the index as it stands
+    fn inspect_lines(&self, view: Inspect) -> Vec<HelpLine> {
+        let index = self.structural_index.as_ref();
+        match view {
+            Inspect::Tokens => {
+                let top = self.viewport.start_line;
+                let lines = top..(top + self.viewport.height).min(self.buffer.line_count());
+                inspect::tokens(index, &self.buffer, lines, self.format.is_relaxed())
+            }
+            Inspect::Nodes => match index {
+                Some(index) => inspect::nodes(index, self.cursor.byte_offset),
+                None => vec![HelpLine::Heading("Index not ready".to_string())],
+            },
+            Inspect::Profile => {
+                let ops = perf::recent();
+                let heading = match ops.len() {
+                    0 => "Nothing timed yet".to_string(),
+                    n => format!("The last {} timed operations, in the order they started", n),
+                };
+                let lines = perf::report(&ops, Instant::now(), |bytes| self.numbers.size(bytes));
+                std::iter::once(HelpLine::Heading(heading)).chain(lines.into_iter().map(HelpLine::Text)).collect()
+            }
+            Inspect::Tasks => {
+                let heading = match self.tasks.len() {
+                    0 => "Nothing running in the background".to_string(),
+                    n => format!("{} running, most recent first; Ctrl-C cancels the first it can", n),
+                };
+                let lines = self.tasks.list(Instant::now());
+                std::iter::once(HelpLine::Heading(heading)).chain(lines.into_iter().map(HelpLine::Text)).collect()
+            }
+        }
+    }
+
+    fn show_message(&mut self, msg: String) {
+        // Display message for 3 seconds
+        self.redraw.mark();
+        self.message = Some(msg);
+        self.message_time = Some(Instant::now());
+        self.message_is_error = false;
+        
+        // Return to normal mode after showing message
+        if matches!(self.mode, Mode::Command) {
+            self.command_mode_handler.command_line.clear();
+            self.mode = Mode::Normal;
+            self.set_cursor_shape(SetCursorStyle::SteadyBlock);
+        }
+    }
+
+    /// Change the cursor shape, on terminals that have them
+    fn set_cursor_shape(&self, shape: SetCursorStyle) {
+        if self.caps.cursor_shape {
+            let _ = stdout().execute(shape);
+        }
+    }
+
+    /// Whether the structural index covers the whole file
+    fn fully_indexed(&self) -> bool {
+        self.index_frontier.indexed() >= self.buffer.line_count()
+    }
+
+    /// How far the structural index is behind the text, if there is one
+    fn index_staleness(&self) -> Option<Staleness> {
+        if self.index_rebuilding {
+            return Some(Staleness::Rebuilding);
+        }
+        self.structural_index.as_ref().map(StructuralIndex::staleness)
+    }
+
+    /// Describe the first structural error, for :validate
+    fn validate_report(&self) -> String {
+        let Some(ref index) = self.structural_index else {
+            return "Nothing indexed yet".to_string();
+        };
+        let scope = if self.fully_indexed() {
+            String::new()
+        } else {
+            format!(" in the first {} lines (not fully indexed)", self.numbers.count(self.index_frontier.indexed()))
+        };
+        
+        let warnings = match index.warnings().len() {
+            0 => String::new(),
+            n => format!(", {} warnings", self.numbers.count(n)),
+        };
+        let duplicates = match (self.dupkeys, self.duplicate_keys.found.len()) {
+            (DupKeys::Ignore, _) | (_, 0) => String::new(),
+            (_, n) => format!(", {} duplicate keys", self.numbers.count(n)),
+        };
+        // As errors, a duplicate key is reported when nothing worse is
+        let first_duplicate = self.duplicate_keys.found.first().filter(|_| self.dupkeys == DupKeys::Error);
+        
+        match index.first_error() {
+            None => match first_duplicate {
+                None => format!("No structural errors{}{}{}", warnings, duplicates, scope),
+                Some(duplicate) => {
+                    let line = self.numbers.count(self.buffer.byte_offset_to_line(duplicate.span.start) + 1);
+                    format!("Line {}: {} ({}{})", line, self.duplicate_message(duplicate), &duplicates[2..], scope)
+                }
+            },
+            Some(error) => {
+                let line = self.numbers.count(self.buffer.byte_offset_to_line(error.offset) + 1);
+                let what = match self.format {
+                    DocumentFormat::JsonLines => format!("Record on line {} is malformed", line),
+                    DocumentFormat::Json | DocumentFormat::Jsonc => format!("Line {}", line),
+                };
+                let message = match error.kind {
+                    StructuralErrorKind::TrailingContent => format!("{} (:split-docs reads it as more documents)", error.message()),
+                    _ => error.message(),
+                };
+                format!("{}: {} ({} errors{}{})", what, message, self.numbers.count(index.errors().len()), duplicates, scope)
+            }
+        }
+    }
+
+    fn update_viewport_for_cursor(&mut self) {
+        let folded = self.reveal_cursor();
+        if self.wrap {
+            self.viewport.start_col = 0;
+            self.scroll_wrapped(&folded);
+            return;
+        }
+        self.viewport.start_row = 0;
+        self.viewport.scroll_to_col(self.cursor.col);
+        
+        let viewport_height = self.viewport.height.max(1);
+        let cursor_line = self.cursor.line;
+        let start_line = folded.visible_line(self.viewport.start_line);
+        self.viewport.start_line = start_line;
+        
+        // Keep cursor in view with some padding
+        if cursor_line < start_line {
+            // Cursor above viewport - scroll up
+            self.viewport.start_line = cursor_line;
+        } else if !line_within_rows(&folded, start_line, cursor_line, viewport_height) {
+            // Cursor below viewport - scroll down, counting folds as one row
+            let mut line = cursor_line;
+            for _ in 1..viewport_height {
+                match folded.prev_visible(line) {
+                    Some(prev) => line = prev,
+                    None => break,
+                }
+            }
+            self.viewport.start_line = line;
+        }
+    }
+    
+    /// Screen rows taken by a line when wrapping
+    fn wrapped_rows(&self, folded: &FoldedLines, line: usize) -> usize {
+        if folded.span_at(line).is_some() {
+            return 1;
+        }
+        let text = self.buffer.get_line(line);
+        let chars = text.trim_end_matches(['\n', '\r']).chars().count();
+        chars.div_ceil(self.viewport.width.max(1)).max(1)
+    }
+    
+    /// Vertical scrolling when long lines wrap. Positions are (line, wrapped
+    /// row) pairs so a single huge line can scroll a row at a time.
+    fn scroll_wrapped(&mut self, folded: &FoldedLines) {
+        let height = self.viewport.height.max(1);
+        let width = self.viewport.width.max(1);
+        let cursor = (
+            self.cursor.line,
+            (self.cursor.col / width).min(self.wrapped_rows(folded, self.cursor.line) - 1),
+        );
+        let start_line = folded.visible_line(self.viewport.start_line);
+        let start_row = if start_line == self.viewport.start_line { self.viewport.start_row } else { 0 };
+        let mut start = (start_line, start_row);
+        
+        if cursor < start {
+            start = cursor;
+        } else {
+            // Walk down from the top row looking for the cursor
+            let mut pos = start;
+            let mut rows_here = self.wrapped_rows(folded, pos.0);
+            let mut found = false;
+            for _ in 0..height {
+                if pos == cursor {
+                    found = true;
+                    break;
+                }
+                if pos.1 + 1 < rows_here {
+                    pos.1 += 1;
+                } else {
+                    pos = (folded.next_visible(pos.0), 0);
+                    rows_here = self.wrapped_rows(folded, pos.0);
+                }
+                if pos.0 > cursor.0 {
+                    break;
+                }
+            }
+            if !found {
+                // Put the cursor on the bottom row
+                let mut pos = cursor;
+                for _ in 1..height {
+                    if pos.1 > 0 {
+                        pos.1 -= 1;
+                    } else if let Some(prev) = folded.prev_visible(pos.0) {
+                        pos = (prev, self.wrapped_rows(folded, prev) - 1);
+                    } else {
+                        break;
+                    }
+                }
+                start = pos;
+            }
+        }
+        self.viewport.start_line = start.0;
+        self.viewport.start_row = start.1;
+    }
+    
+    /// zh/zl/zH/zL: scroll sideways, dragging the cursor along only if it
+    /// would leave the screen
+    fn scroll_horizontal(&mut self, scroll: HorizontalScroll) {
+        if self.wrap {
+            return;
+        }
+        let half = (self.viewport.width / 2).max(1);
+        match scroll {
+            HorizontalScroll::Left => self.viewport.scroll_left(1),
+            HorizontalScroll::Right => self.viewport.scroll_right(1),
+            HorizontalScroll::HalfLeft => self.viewport.scroll_left(half),
+            HorizontalScroll::HalfRight => self.viewport.scroll_right(half),
+        }
+        self.cursor.col = self.viewport.clamp_col(self.cursor.col);
+        self.cursor.sync_byte_offset(&self.buffer);
+        self.current_node_id = None;
+    }
+    
+    /// gj/gk: move by screen row within a wrapped line, like j/k otherwise
+    fn display_line(&mut self, down: bool) {
+        let folded = self.folds.lines(&self.buffer);
+        let width = self.viewport.width.max(1);
+        let col = self.cursor.col;
+        if self.wrap && down && col + width < self.wrapped_rows(&folded, self.cursor.line) * width
+            && col + width < self.buffer.get_line(self.cursor.line).trim_end_matches(['\n', '\r']).chars().count()
+        {
+            self.cursor.col += width;
+        } else if self.wrap && !down && col >= width {
+            self.cursor.col -= width;
+        } else if down {
+            self.cursor.move_down(&self.buffer);
+            folded.skip_down(&mut self.cursor, &self.buffer);
+            if self.wrap {
+                self.cursor.col = col % width;
+            }
+        } else {
+            self.cursor.move_up(&self.buffer);
+            folded.skip_up(&mut self.cursor, &self.buffer);
+            if self.wrap {
+                // Land on the last row of the line above
+                let rows = self.wrapped_rows(&folded, self.cursor.line);
+                self.cursor.col = (rows - 1) * width + col % width;
+            }
+        }
+        self.cursor.sync_byte_offset(&self.buffer);
+        self.current_node_id = None;
+        self.update_viewport_for_cursor();
+    }
+    
+    /// Make sure the cursor isn't on a line hidden by a fold, either by
+    /// opening the fold (:set foldopen) or by moving to its summary row
+    fn reveal_cursor(&mut self) -> FoldedLines {
+        if self.depth_expand {
+            self.folds.open_deep_on_line(&self.buffer, self.cursor.line);
+        }
+        let folded = self.folds.lines(&self.buffer);
+        if !folded.is_hidden(self.cursor.line) {
+            return folded;
+        }
+        if self.fold_open {
+            self.folds.open_containing(self.cursor.byte_offset);
+            return self.folds.lines(&self.buffer);
+        }
+        folded.skip_up(&mut self.cursor, &self.buffer);
+        folded
+    }
+    
+    /// Byte range of the innermost closed, multi-line container around the
+    /// cursor, skipping ones that are already folded when `skip_folded`
+    fn fold_target(&self, skip_folded: bool) -> Option<std::ops::Range<usize>> {
+        let index = self.structural_index.as_ref()?;
+        let mut node_id = index.node_id_at(self.cursor.byte_offset);
+        while let Some(id) = node_id {
+            let node = index.get(id)?;
+            let range = node.start..node.end;
+            if node.is_container()
+                && index.is_closed(id)
+                && self.buffer.byte_offset_to_line(node.start) < self.buffer.byte_offset_to_line(node.end - 1)
+                && !(skip_folded && self.folds.is_closed(&range))
+            {
+                return Some(range);
+            }
+            node_id = node.parent;
+        }
+        None
+    }
+    
+    /// Line numbers when they're on, then the sign column: with
+    /// :set signcolumn=auto, while there's an error, a diff, a duplicate
+    /// key or a bookmark to mark
+    fn gutter(&self) -> Option<Gutter> {
+        let signs = match self.sign_column {
+            SignColumn::Yes => true,
+            SignColumn::No => false,
+            SignColumn::Auto => {
+                let duplicates = self.dupkeys != DupKeys::Ignore && !self.duplicate_keys.found.is_empty();
+                !self.signs.is_empty() || self.diff.is_some() || duplicates || self.bookmarks.offsets().next().is_some()
+            }
+        };
+        match (self.show_numbers, signs) {
+            (true, true) => Some(Gutter::new(self.buffer.line_count(), self.relative_numbers).with_signs()),
+            (true, false) => Some(Gutter::new(self.buffer.line_count(), self.relative_numbers)),
+            (false, true) => Some(Gutter::signs()),
+            (false, false) => None,
+        }
+    }
+
+    /// Fit the viewport to a resized terminal straight away, so the cursor
+    /// stays in view on the next frame
+    fn resize(&mut self, width: u16, height: u16) {
+        let gutter = self.gutter();
+        let area = Rect::new(0, 0, width, height);
+        if let Some(layout) = ScreenLayout::new(area, self.show_tree, self.list_height(), gutter.map_or(0, |g| g.width)) {
+            self.viewport.height = layout.content.height as usize;
+            self.viewport.width = layout.content.width as usize;
+            self.update_viewport_for_cursor();
+        }
+    }
+    
+    /// The focused window's state, as stored for an unfocused one
+    fn current_view(&self) -> View {
+        View {
+            cursor: self.cursor,
+            viewport: self.viewport.clone(),
+            current_node_id: self.current_node_id,
+            top: self.buffer.line_to_byte_offset(self.viewport.start_line),
+        }
+    }
+    
+    fn load_view(&mut self, view: View) {
+        self.cursor = view.cursor;
+        self.viewport = view.viewport;
+        self.current_node_id = view.current_node_id;
+    }
+    
+    /// Run `f` as if window `index` had focus, keeping whatever it changes
+    /// about that window (scrolling to follow its cursor while drawing)
+    fn with_window<T>(&mut self, index: usize, f: impl FnOnce(&mut Self) -> T) -> T {
+        let live = self.current_view();
+        self.load_view(self.windows.view(index).clone());
+        let result = f(self);
+        self.windows.set_view(index, self.current_view());
+        self.load_view(live);
+        result
+    }
+    
+    /// :split / :vsplit - open a second view of the buffer at the same place
+    fn split_window(&mut self, vertical: bool) {
+        let view = self.current_view();
+        self.windows.split(vertical, view);
+        self.focus = Focus::Text;
+    }
+    
+    /// Close the focused window, unless it's the last one
+    fn close_window(&mut self) -> bool {
+        let Some(view) = self.windows.close() else { return false };
+        if self.mode != Mode::Normal {
+            self.switch_mode(Mode::Normal);
+        }
+        self.load_view(view);
+        self.update_viewport_for_cursor();
+        true
+    }
+    
+    /// :close / Ctrl-w c
+    fn close_window_command(&mut self) {
+        if !self.close_window() {
+            self.show_error("Cannot close last window".to_string());
+        }
+    }
+    
+    /// Give window `index` the keyboard
+    fn focus_window(&mut self, index: usize) {
+        self.focus = Focus::Text;
+        let current = self.current_view();
+        if let Some(view) = self.windows.focus(index, current) {
+            if matches!(self.mode, Mode::Visual { .. }) {
+                self.switch_mode(Mode::Normal);
+            }
+            self.load_view(view);
+            self.update_viewport_for_cursor();
+        }
+    }
+    
+    /// The key after Ctrl-w: h/j/k/l move between windows and the outline,
+    /// w cycles through them, s/v split and c closes
+    fn window_command(&mut self, code: KeyCode) {
+        let direction = match code {
+            KeyCode::Char('h') | KeyCode::Left => Some(window::Direction::Left),
+            KeyCode::Char('j') | KeyCode::Down => Some(window::Direction::Down),
+            KeyCode::Char('k') | KeyCode::Up => Some(window::Direction::Up),
+            KeyCode::Char('l') | KeyCode::Right => Some(window::Direction::Right),
+            _ => None,
+        };
+        if let Some(direction) = direction {
+            match self.focus {
+                Focus::Tree if direction == window::Direction::Right => self.focus = Focus::Text,
+                Focus::List if direction == window::Direction::Up => self.focus = Focus::Text,
+                Focus::Tree | Focus::List => {}
+                Focus::Text => {
+                    if let Some(index) = self.windows.neighbor(direction) {
+                        self.focus_window(index);
+                    } else if direction == window::Direction::Left && self.show_tree {
+                        self.focus = Focus::Tree;
+                    } else if direction == window::Direction::Down && self.show_loclist {
+                        self.focus = Focus::List;
+                    }
+                }
+            }
+            return;
+        }
+        match code {
+            KeyCode::Char('w') => {
+                let next = self.windows.active() + 1;
+                if self.focus == Focus::Tree {
+                    self.focus_window(0);
+                } else if self.focus == Focus::List {
+                    if self.show_tree {
+                        self.focus = Focus::Tree;
+                    } else {
+                        self.focus_window(0);
+                    }
+                } else if next < self.windows.len() {
+                    self.focus_window(next);
+                } else if self.show_loclist {
+                    self.focus = Focus::List;
+                } else if self.show_tree {
+                    self.focus = Focus::Tree;
+                } else {
+                    self.focus_window(0);
+                }
+            }
+            KeyCode::Char('s' | 'S') => self.split_window(false),
+            KeyCode::Char('v') => self.split_window(true),
+            KeyCode::Char('c') => self.close_window_command(),
+            _ => {}
+        }
+    }
+    
+    /// Enter `new_mode`, updating the cursor shape to match
+    fn switch_mode(&mut self, new_mode: Mode) {
+        if new_mode != Mode::Insert {
+            self.completion = None;
+        }
+        // Clear visual mode handler when leaving visual mode, keeping the
+        // selection for '< and '>; : starts a :'<,'> command with it
+        if !matches!(new_mode, Mode::Visual { .. }) {
+            if let Some(visual) = self.visual_mode_handler.take() {
+                let (start, end) = visual.get_selection_range(self.cursor.byte_offset);
+                self.last_selection = Some(start..end);
+                if new_mode == Mode::Command {
+                    self.command_mode_handler.command_line = "'<,'>".to_string();
+                }
+            }
+        }
+        
+        // Change cursor style based on mode
+        self.set_cursor_shape(match new_mode {
+            Mode::Normal => SetCursorStyle::SteadyBlock,
+            Mode::Insert => SetCursorStyle::SteadyBar,
+            Mode::Visual { .. } => SetCursorStyle::SteadyBlock,
+            Mode::Command => SetCursorStyle::SteadyUnderScore,
+        });
+        
+        self.mode = new_mode;
+    }
+    
+    /// Clicks move the cursor (or select in the outline), a left-button drag
+    /// makes a character-wise visual selection and the wheel scrolls
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        let (x, y) = (mouse.column, mouse.row);
+        match mouse.kind {
+            MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+                let down = mouse.kind == MouseEventKind::ScrollDown;
+                if let Some(help) = &mut self.help {
+                    let key = KeyEvent::new(if down { KeyCode::Down } else { KeyCode::Up }, KeyModifiers::NONE);
+                    for _ in 0..3 {
+                        help.handle_key(key, self.viewport.height);
+                    }
+                } else if self.screen.over_tree(x) {
+                    self.tree.move_selection(if down { 3 } else { -3 });
+                } else if let Some(index) = self.screen.window_at(x, y).filter(|&i| i != self.windows.active()) {
+                    self.with_window(index, |app| app.scroll_view(3, down));
+                } else {
+                    self.scroll_view(3, down);
+                }
+            }
+            _ if self.mode == Mode::Command || self.help.is_some() => {}
+            MouseEventKind::Down(MouseButton::Left) => {
+                self.preview = None;
+                if let Some(row) = self.screen.tree_row(x, y) {
+                    self.click_tree(row);
+                    return;
+                }
+                if let Some(index) = self.screen.window_at(x, y).filter(|&i| i != self.windows.active()) {
+                    self.focus_window(index);
+                    return;
+                }
+                let Some((line, col)) = self.screen.text_position(x, y) else { return };
+                self.focus = Focus::Text;
+                if matches!(self.mode, Mode::Visual { .. }) {
+                    self.switch_mode(Mode::Normal);
+                }
+                self.click_text(line, col);
+                self.drag_anchor = Some(self.cursor.byte_offset);
+            }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                let Some(anchor) = self.drag_anchor else { return };
+                let Some((line, col)) = self.screen.text_position_clamped(x, y) else { return };
+                self.click_text(line, col);
+                if self.mode == Mode::Normal && self.cursor.byte_offset != anchor {
+                    self.visual_mode_handler = Some(mode::visual::VisualMode::new(anchor, false));
+                    self.switch_mode(Mode::Visual { line_wise: false });
+                }
+            }
+            MouseEventKind::Up(_) => self.drag_anchor = None,
+            _ => {}
+        }
+    }
+    
+    /// Put the cursor on a clicked cell, clamping columns past the end of
+    /// the line
+    fn click_text(&mut self, line: usize, col: usize) {
+        let text = self.buffer.get_line(line);
+        let len = text.trim_end_matches(['\n', '\r']).chars().count();
+        let last = if self.mode == Mode::Insert { len } else { len.saturating_sub(1) };
+        self.cursor.line = line;
+        self.cursor.col = col.min(last);
+        self.cursor.sync_byte_offset(&self.buffer);
+        self.current_node_id = None;
+        self.update_viewport_for_cursor();
+    }
+    
+    /// Select a clicked outline row and show its node in the text; a click
+    /// on a "more" row lists more children
+    fn click_tree(&mut self, row: usize) {
+        let Some(index) = &self.structural_index else { return };
+        if row >= self.tree.rows().len() {
+            return;
+        }
+        self.tree.selected = row;
+        self.focus = Focus::Tree;
+        match self.tree.rows()[row].kind {
+            TreeRowKind::More { parent, .. } => self.tree.show_more(index, parent),
+            TreeRowKind::Node { id, .. } => {
+                if let Some(start) = index.get(id).map(|node| node.start) {
+                    self.move_cursor_to_offset(start);
+                    self.current_node_id = Some(id);
+                    self.update_viewport_for_cursor();
+                }
+            }
+        }
+    }
+    
+    /// Scroll the view by `lines` lines (folds count as one), dragging the
+    /// cursor along when it would go off screen
+    fn scroll_view(&mut self, lines: usize, down: bool) {
+        let folded = self.folds.lines(&self.buffer);
+        let line_count = self.buffer.line_count();
+        let height = self.viewport.height.max(1);
+        let mut start = folded.visible_line(self.viewport.start_line);
+        for _ in 0..lines {
+            start = if down {
+                match folded.next_visible(start) {
+                    next if next < line_count => next,
+                    _ => break,
+                }
+            } else {
+                match folded.prev_visible(start) {
+                    Some(prev) => prev,
+                    None => break,
+                }
+            };
+        }
+        self.viewport.start_line = start;
+        self.viewport.start_row = 0;
+        
+        if self.cursor.line < start {
+            self.cursor.line = start;
+        } else if !line_within_rows(&folded, start, self.cursor.line, height) {
+            let mut last = start;
+            for _ in 1..height {
+                match folded.next_visible(last) {
+                    next if next < line_count => last = next,
+                    _ => break,
+                }
+            }
+            self.cursor.line = last;
+        } else {
+            return;
+        }
+        self.cursor.sync_byte_offset(&self.buffer);
+        self.current_node_id = None;
+    }
+    
+    /// Rows the location list pane wants, 0 while it's closed
+    fn list_height(&self) -> u16 {
+        if self.show_loclist && self.loclist.is_some() { loclist::PANE_HEIGHT } else { 0 }
+    }
+
+    /// Open or close the outline sidebar, focusing it when it opens
+    fn toggle_tree(&mut self) {
+        self.show_tree = !self.show_tree;
+        self.focus = if self.show_tree { Focus::Tree } else { Focus::Text };
+    }
+    
+    /// Keys while the outline has focus: j/k move, l/h expand and collapse,
+    /// Enter jumps the text cursor to the entry (or lists more children)
+    fn handle_tree_key(&mut self, key: KeyEvent) {
+        let Some(index) = &self.structural_index else {
+            if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+                self.focus = Focus::Text;
+            }
+            return;
+        };
+        let Some(row) = self.tree.selected_row().cloned() else {
+            self.focus = Focus::Text;
+            return;
+        };
+        
+        let mut jump_to = None;
+        match (key.code, row.kind) {
+            (KeyCode::Char('j') | KeyCode::Down, _) => self.tree.move_selection(1),
+            (KeyCode::Char('k') | KeyCode::Up, _) => self.tree.move_selection(-1),
+            (KeyCode::PageDown, _) => self.tree.move_selection(self.viewport.height as isize),
+            (KeyCode::PageUp, _) => self.tree.move_selection(-(self.viewport.height as isize)),
+            (KeyCode::Char('g'), _) => self.tree.selected = 0,
+            (KeyCode::Char('G'), _) => self.tree.move_selection(isize::MAX),
+            (KeyCode::Esc | KeyCode::Char('q'), _) => self.focus = Focus::Text,
+            (KeyCode::Enter | KeyCode::Char('l' | ' ') | KeyCode::Right, TreeRowKind::More { parent, .. }) => {
+                self.tree.show_more(index, parent);
+            }
+            (KeyCode::Enter, TreeRowKind::Node { id, .. }) => jump_to = Some(id),
+            (KeyCode::Char('l') | KeyCode::Right, TreeRowKind::Node { id, expandable: true, .. }) => {
+                self.tree.expand(index, id);
+            }
+            (KeyCode::Char(' '), TreeRowKind::Node { id, expandable: true, expanded }) => {
+                if expanded {
+                    self.tree.collapse(index, id);
+                } else {
+                    self.tree.expand(index, id);
+                }
+            }
+            (KeyCode::Char('h') | KeyCode::Left, TreeRowKind::Node { id, expanded: true, .. }) => {
+                self.tree.collapse(index, id);
+            }
+            (KeyCode::Char('h') | KeyCode::Left, _) => {
+                // Move to the parent entry
+                let rows = self.tree.rows();
+                if let Some(parent) = rows[..self.tree.selected].iter().rposition(|r| r.depth < row.depth) {
+                    self.tree.selected = parent;
+                }
+            }
+            _ => {}
+        }
+        
+        if let Some(id) = jump_to {
+            if let Some(node) = index.get(id) {
+                self.move_cursor_to_offset(node.start);
+                self.current_node_id = Some(id);
+                self.update_viewport_for_cursor();
+            }
+        }
+    }
+    
+    /// Run a z-prefixed folding command
+    fn fold(&mut self, action: FoldAction) {
+        let folded = self.folds.lines(&self.buffer);
+        // The closed fold drawn on the cursor line, if any
+        let shown = folded.span_at(self.cursor.line).map(|span| span.start..span.end);
+        
+        let changed = match action {
+            FoldAction::Toggle | FoldAction::Open if shown.is_some() => {
+                self.folds.open_on_line(&self.buffer, self.cursor.line)
+            }
+            FoldAction::Open => false,
+            FoldAction::Toggle | FoldAction::Close => match self.fold_target(true) {
+                Some(range) => self.folds.close(range),
+                None => false,
+            },
+            FoldAction::OpenAll => match shown.or_else(|| self.fold_target(false)) {
+                Some(range) => self.folds.open_within(&range),
+                None => self.folds.open_all(),
+            },
+            FoldAction::CloseAll => self.close_subtree(),
+        };
+        
+        if !changed {
+            self.show_message("No fold found".to_string());
+        }
+        // Closing may have hidden the cursor line; stay on the summary row
+        let folded = self.folds.lines(&self.buffer);
+        folded.skip_up(&mut self.cursor, &self.buffer);
+        self.update_viewport_for_cursor();
+    }
+    
+    /// Fold the containers nested past :set maxrenderdepth among the nodes
+    /// indexed since last time, and those seen then that have closed since
+    fn fold_deep(&mut self) {
+        let (Some(depth), Some(index)) = (self.max_render_depth, &self.structural_index) else {
+            return;
+        };
+        let (seen, open) = std::mem::take(&mut self.deep_scan);
+        let mut still_open = Vec::new();
+        for id in open.into_iter().chain(seen..index.len()) {
+            let Some(node) = index.get(id) else { continue };
+            if !node.is_container() || node.depth != depth {
+                continue;
+            }
+            if index.is_closed(id) {
+                self.folds.close_deep(node.start..node.end);
+            } else {
+                still_open.push(id);
+            }
+        }
+        self.deep_scan = (index.len(), still_open);
+    }
+
+    /// zM: fold every multi-line container in the cursor's subtree (or the
+    /// whole document when the cursor isn't inside one)
+    fn close_subtree(&mut self) -> bool {
+        let Some(index) = &self.structural_index else {
+            return false;
+        };
+        let range = self.fold_target(false).unwrap_or(0..usize::MAX);
+        let first = index.node_id_at(range.start).unwrap_or(0);
+        let mut changed = false;
+        for id in first..index.len() {
+            let Some(node) = index.get(id) else { break };
+            if node.start >= range.end {
+                break;
+            }
+            if node.is_container()
+                && node.start >= range.start
+                && index.is_closed(id)
+                && self.buffer.byte_offset_to_line(node.start) < self.buffer.byte_offset_to_line(node.end - 1)
+            {
+                changed |= self.folds.close(node.start..node.end);
+            }
+        }
+        changed
+    }
+    
+    /// Do what the answer to the prompt in the status line asked for
+    fn answer_prompt(&mut self, action: PromptAction) {
+        match action {
+            PromptAction::Quit { save: true } => self.write_quit(true),
+            PromptAction::Quit { save: false } => self.should_quit = true,
+            PromptAction::CloseBuffer { save: true } => match self.buffer.save() {
+                Ok(()) => {
+                    self.close_after_save = true;
+                    self.poll_save();
+                }
+                Err(e) => self.show_error(format!("Not saved: {}", e)),
+            },
+            PromptAction::CloseBuffer { save: false } => self.close_buffer(),
+            PromptAction::Write => self.start_write(),
+            PromptAction::Rename(answer) => {
+                if let Some(renaming) = self.renaming.as_mut() {
+                    renaming.answer(answer);
+                }
+                if let Err(e) = self.ask_rename() {
+                    self.show_error(e.to_string());
+                }
+            }
+            PromptAction::Cancel => {}
+        }
+    }
+
+    /// :renameall, once the whole file is indexed. `queued` is set when it
+    /// was waiting for that, so it isn't asked for again.
+    fn rename_all(&mut self, rename: RenameAll, queued: bool) -> Result<()> {
+        if self.index_revision != self.buffer.revision() {
+            self.reset_structural_index()?;
+        }
+        if self.index_in_flight.is_some() || !self.fully_indexed() {
+            if queued && self.index_in_flight.is_none() {
+                anyhow::bail!("The index doesn't cover the whole file; nothing renamed");
+            }
+            self.pending_rename = Some(rename);
+            return self.request_structural_index(self.buffer.line_count());
+        }
+        let Some(index) = self.structural_index.as_ref() else {
+            anyhow::bail!("Index not ready");
+        };
+        let within = match &rename.scope {
+            Some(scope) => renameall::scope_span(index, &self.buffer, scope)?,
+            None => 0..self.buffer.len_bytes(),
+        };
+        let (keys, skipped) = renameall::find(index, &self.buffer, &rename, within);
+        if keys.is_empty() {
+            anyhow::bail!("{}", self.renamed_message(&rename, 0, skipped));
+        }
+        if rename.confirm {
+            self.jumps.push(self.cursor.byte_offset);
+            self.renaming = Some(Confirming::new(rename, keys, skipped));
+            return self.ask_rename();
+        }
+        self.rename_keys(&rename, &keys, skipped)
+    }
+
+    /// Put the next key :renameall c is going through under the cursor and
+    /// ask about it, or rename the ones said yes to when there are no more
+    fn ask_rename(&mut self) -> Result<()> {
+        let Some(renaming) = &self.renaming else {
+            return Ok(());
+        };
+        let Some((key, n, total)) = renaming.current() else {
+            let renaming = self.renaming.take().expect("checked above");
+            return self.rename_keys(&renaming.rename, renaming.accepted(), renaming.skipped);
+        };
+        let question = format!("Rename to \"{}\"? ({} of {})", renaming.rename.new, self.numbers.count(n), self.numbers.count(total));
+        self.current_node_id = None;
+        self.folds.open_containing(key.start);
+        self.move_cursor_to_offset(key.start);
+        self.update_viewport_for_cursor();
+        self.prompt = Some(Prompt::new(question, vec![
+            Choice { key: 'y', label: "yes", answer: PromptAction::Rename(renameall::Answer::Yes) },
+            Choice { key: 'n', label: "no", answer: PromptAction::Rename(renameall::Answer::No) },
+            Choice { key: 'a', label: "all", answer: PromptAction::Rename(renameall::Answer::All) },
+            Choice { key: 'q', label: "quit", answer: PromptAction::Rename(renameall::Answer::Quit) },
+        ]));
+        Ok(())
+    }
+
+    /// Rename `keys` as one undo group
+    fn rename_keys(&mut self, rename: &RenameAll, keys: &[std::ops::Range<usize>], skipped: usize) -> Result<()> {
+        if !keys.is_empty() {
+            let replacements = renameall::edits(keys, &rename.new);
+            let edits = EditOperations::apply_edits(&mut self.buffer, &mut self.cursor, &replacements)?;
+            self.undo_stack.push_batch(edits, &rename.label(keys.len()));
+            self.apply_buffer_changes();
+            self.update_viewport_for_cursor();
+            if self.recording_script && !rename.confirm {
+                self.edit_script.push(Step::RenameAll(rename.clone()));
+            }
+        }
+        self.show_message(self.renamed_message(rename, keys.len(), skipped));
+        Ok(())
+    }
+
+    /// "Renamed 12 keys", and how many were left alone and why
+    fn renamed_message(&self, rename: &RenameAll, count: usize, skipped: usize) -> String {
+        let mut message = match count {
+            0 => format!("No keys \"{}\" renamed", rename.old),
+            1 => "Renamed 1 key".to_string(),
+            count => format!("Renamed {} keys", self.numbers.count(count)),
+        };
+        if skipped > 0 {
+            message.push_str(&format!(
+                "; {} left alone, their object has \"{}\" already (add ! to rename anyway)",
+                self.numbers.count(skipped),
+                rename.new,
+            ));
+        }
+        message
+    }
+
+    /// How `line` begins: inside a string, when one in the index runs
+    /// into it from above
+    fn scan_at_line(&self, line: usize) -> whitespace::Scan {
+        let line_start = self.buffer.line_to_byte_offset(line);
+        let in_string = self.structural_index.as_ref().is_some_and(|index| {
+            index.node_id_at(line_start)
+                .and_then(|id| index.get(id))
+                .is_some_and(|node| node.kind == NodeKind::String && node.start < line_start)
+        });
+        if in_string { whitespace::Scan::String } else { whitespace::Scan::Code }
+    }
+
+    /// J / gJ: join `count` lines from the cursor's, or the lines of the
+    /// visual selection, as one undo step. J keeps the text valid JSON
+    /// where the index has got as far as the last of them.
+    fn join_lines(&mut self, count: usize, raw: bool, selection: Option<std::ops::Range<usize>>) -> Result<()> {
+        let (line, count) = match selection {
+            Some(span) => {
+                let first = self.buffer.byte_offset_to_line(span.start);
+                (first, self.buffer.byte_offset_to_line(span.end.saturating_sub(1).max(span.start)) - first + 1)
+            }
+            None => (self.cursor.line, count),
+        };
+        let last = line.saturating_add(count.max(2) - 1);
+        let indexed = self.structural_index.is_some() && self.index_frontier.covers(last + 1, self.buffer.line_count());
+        let style = match (raw, indexed) {
+            (true, _) => JoinStyle::Raw,
+            (false, true) => JoinStyle::Json { brace_spacing: self.brace_spacing },
+            (false, false) => JoinStyle::Spaced,
+        };
+        let (replacements, cursor) = join::edits(&self.buffer, line, count, style, self.scan_at_line(line))?;
+        let edits = EditOperations::apply_edits(&mut self.buffer, &mut self.cursor, &replacements)?;
+        self.undo_stack.push_batch(edits, &format!("join {} lines", replacements.len() + 1));
+        self.cursor.move_to_offset(&self.buffer, cursor);
+        self.apply_buffer_changes();
+        self.update_viewport_for_cursor();
+        Ok(())
+    }
+
+    /// :retab and :trimws, as one undo group over the lines of `range`
+    fn whitespace_command(&mut self, op: WhitespaceOp, range: PipeRange) -> Result<()> {
+        let lines = self.range_lines(range)?;
+        let start = self.scan_at_line(lines.start);
+        let replacements = whitespace::edits(&self.buffer, lines, op, self.shiftwidth, self.expand_tab, start);
+        if replacements.is_empty() {
+            self.show_message("No lines changed".to_string());
+            return Ok(());
+        }
+        let changed = replacements.len();
+        let edits = EditOperations::apply_edits(&mut self.buffer, &mut self.cursor, &replacements)?;
+        self.undo_stack.push_batch(edits, &op.label(changed));
+        self.apply_buffer_changes();
+        self.update_viewport_for_cursor();
+        if self.recording_script && matches!(range, PipeRange::Node | PipeRange::Buffer) {
+            self.edit_script.push(Step::Whitespace(op));
+        }
+        self.show_message(format!("{} lines changed", self.numbers.count(changed)));
+        Ok(())
+    }
+
+    /// The lines an ex command's range covers, from 0 and end-exclusive:
+    /// every line a selection touches, or all of them for :% or no range
+    fn range_lines(&self, range: PipeRange) -> Result<std::ops::Range<usize>> {
+        match range {
+            PipeRange::Selection => {
+                let span = self.last_selection.clone().ok_or_else(|| anyhow::anyhow!("No selection"))?;
+                let last = self.mark_line('>').unwrap_or(0);
+                Ok(self.buffer.byte_offset_to_line(span.start)..last + 1)
+            }
+            PipeRange::Node | PipeRange::Buffer => Ok(0..self.buffer.line_count()),
+            PipeRange::Lines(lines) => {
+                let cursor_line = self.buffer.byte_offset_to_line(self.cursor.byte_offset);
+                lines.resolve(cursor_line, self.buffer.line_count(), |name| self.mark_line(name))
+            }
+        }
+    }
+
+    /// :undo-here - undo the latest change made inside the object or array
+    /// under the cursor, or inside a range, keeping the ones made since
+    /// elsewhere
+    fn undo_here(&mut self, range: PipeRange) -> Result<String> {
+        let region = match range {
+            PipeRange::Node => {
+                let Some(index) = self.structural_index.as_ref() else {
+                    anyhow::bail!("Index not ready");
+                };
+                let mut node = index.node_id_at(self.cursor.byte_offset);
+                while let Some(id) = node.filter(|&id| !matches!(index.kind(id), Some(NodeKind::Object | NodeKind::Array))) {
+                    node = index.parent(id);
+                }
+                let node = node.or_else(|| index.node_id_at(self.cursor.byte_offset)).and_then(|id| index.get(id));
+                let Some(node) = node else {
+                    anyhow::bail!("No value under cursor");
+                };
+                node.start..node.end
+            }
+            PipeRange::Selection => self.last_selection.clone().ok_or_else(|| anyhow::anyhow!("No selection"))?,
+            PipeRange::Buffer => 0..self.buffer.len_bytes(),
+            PipeRange::Lines(_) => self.range_span(range)?,
+        };
+        let message = self.undo_stack.undo_in(region, &mut self.buffer, &mut self.cursor)?;
+        self.apply_buffer_changes();
+        self.update_viewport_for_cursor();
+        Ok(message)
+    }
+
+    /// The bytes of the lines `range` covers, line breaks and all
+    fn range_span(&self, range: PipeRange) -> Result<std::ops::Range<usize>> {
+        let lines = self.range_lines(range)?;
+        let end = match lines.end < self.buffer.line_count() {
+            true => self.buffer.line_to_byte_offset(lines.end),
+            false => self.buffer.len_bytes(),
+        };
+        Ok(self.buffer.line_to_byte_offset(lines.start)..end)
+    }
+
+    /// :{range}w[!] file - the lines of `range` streamed to a file of their
+    /// own, which is only replaced with !
+    fn write_range(&mut self, range: PipeRange, path: &str, force: bool) -> Result<()> {
+        if self.buffer.overwrites_converted(std::path::Path::new(path)) {
+            anyhow::bail!("That's the file the JSON was converted from");
+        }
+        let lines = self.range_lines(range)?.len();
+        let span = self.range_span(range)?;
+        let written = export::export(&self.buffer, span, std::path::Path::new(path), false, force)?;
+        self.show_message(format!("\"{}\" {} lines, {} bytes written", path, self.numbers.count(lines), self.numbers.count(written)));
+        Ok(())
+    }
+
+    /// Status bar description of the current node, e.g. "Array[38,112 items]".
+    /// Counting members walks every child, so the text is cached until the
+    /// node or the index changes.
+    fn node_summary(&mut self) -> String {
+        let (Some(node_id), Some(index)) = (self.current_node_id, &self.structural_index) else {
+            return String::new();
+        };
+        let key = (node_id, index.len(), self.index_generation);
+        if let Some((cached_key, text)) = &self.node_summary {
+            if *cached_key == key {
+                return text.clone();
+            }
+        }
+        
+        let text = match (index.get(node_id), index.child_count(node_id)) {
+            (Some(node), Some(count)) => {
+                let size = self.numbers.size(index.subtree_size_bytes(node_id).unwrap_or(0));
+                let count = self.numbers.child_count(count);
+                match node.kind {
+                    NodeKind::Object => format!(" | Object{{{} keys}} {}", count, size),
+                    _ => format!(" | Array[{} items] {}", count, size),
+                }
+            }
+            (Some(node), None) => format!(" | {:?}", node.kind),
+            (None, _) => String::new(),
+        };
+        self.node_summary = Some((key, text.clone()));
+        text
+    }
+
+    /// What the colors of every line depend on besides its own text: the
+    /// index generation and how much of the file it covers, and the format
+    fn color_stamp(&self) -> ColorStamp {
+        let nodes = self.structural_index.as_ref().map_or(0, |index| index.len());
+        (self.index_generation, nodes, self.index_frontier.indexed(), self.format.is_relaxed())
+    }
+
+    /// Where the cursor is in the array around it, for the status bar's `%i`
+    fn item_position(&mut self) -> String {
+        let Some(index) = &self.structural_index else {
+            return String::new();
+        };
+        let node = self.current_node_id.or_else(|| index.node_id_at(self.cursor.byte_offset));
+        let Some((array, element)) = node.and_then(|id| navigation::array_element(index, id)) else {
+            return String::new();
+        };
+        let (position, count) = self.item_positions.position(index, array, element, self.index_generation);
+        format!(" | [item {} / {}]", self.numbers.count(position), self.numbers.child_count(count))
+    }
+
+    /// JSON path of the value under the cursor, for the status bar's `%p`
+    fn cursor_path(&mut self) -> String {
+        let Some(index) = &self.structural_index else {
+            return String::new();
+        };
+        let Some(node_id) = index.node_id_at(self.cursor.byte_offset) else {
+            return "$".to_string();
+        };
+        let key = (node_id, index.len(), self.index_generation);
+        if let Some((cached_key, path)) = &self.cursor_path {
+            if *cached_key == key {
+                return path.clone();
+            }
+        }
+        let path = json_path(index, &self.buffer, self.cursor.byte_offset);
+        self.cursor_path = Some((key, path.clone()));
+        path
+    }
+
+    /// The first string overlapping `range` that's long enough to elide and
+    /// not expanded with ze, quotes included
+    fn elided_string(&self, range: std::ops::Range<usize>) -> Option<std::ops::Range<usize>> {
+        let threshold = self.elide_strings?;
+        let index = self.structural_index.as_ref()?;
+        let long = |id| index.get(id)
+            .filter(|node| node.kind == NodeKind::String && node.end - node.start > threshold + 2)
+            .filter(|node| !self.expanded_strings.contains(&node.start))
+            .map(|node| node.start..node.end);
+        index.node_id_at(range.start).and_then(long)
+            .or_else(|| index.nodes_starting_in(range).find_map(long))
+    }
+
+    /// Show an elided string in full once the cursor moves into the part
+    /// left out, so edits always land on text that's on screen
+    fn expand_string_at_cursor(&mut self) {
+        let offset = self.cursor.byte_offset;
+        let Some(string) = self.elided_string(offset..offset + 1) else {
+            return;
+        };
+        let threshold = self.elide_strings.unwrap_or(0);
+        if offset > string.start + threshold && offset + 1 < string.end
+            && self.buffer.slice(string.start + 1..offset).chars().count() >= threshold
+        {
+            self.expanded_strings.insert(string.start);
+        }
+    }
+
+    /// ze: show the long string under the cursor in full, or elide it again
+    fn toggle_elision(&mut self) {
+        let Some(threshold) = self.elide_strings else {
+            self.show_message("Long strings aren't elided (:set elide-strings=N)".to_string());
+            return;
+        };
+        let string = self.structural_index.as_ref()
+            .and_then(|index| index.get(index.node_id_at(self.cursor.byte_offset)?))
+            .filter(|node| node.kind == NodeKind::String);
+        let Some(string) = string else {
+            self.show_message("No string under the cursor".to_string());
+            return;
+        };
+        if self.expanded_strings.remove(&string.start) {
+            // Off the hidden part, or it would expand again straight away
+            self.move_cursor_to_offset(string.start);
+            self.update_viewport_for_cursor();
+        } else if self.elided_string(string.start..string.start + 1).is_some() {
+            self.expanded_strings.insert(string.start);
+        } else {
+            self.show_message(format!("String is no longer than {} characters", threshold));
+        }
+    }
+
+    /// :keys - pick from the keys of the object around the cursor, or with
+    /// :keys! from every key path in the document up to `KEY_PATH_DEPTH`
+    fn open_keys(&mut self, all: bool) -> Result<()> {
+        let Some(index) = &self.structural_index else {
+            anyhow::bail!("Index not ready");
+        };
+        let picker = if all {
+            let paths = path::key_paths(index, &self.buffer, path::KEY_PATH_DEPTH);
+            Picker::new("Key paths", paths.map(|(path, offset)| (path, Pick::Key(offset))))
+        } else {
+            let object = std::iter::successors(index.node_id_at(self.cursor.byte_offset), |&id| index.parent(id))
+                .find(|&id| index.kind(id) == Some(NodeKind::Object))
+                .ok_or_else(|| anyhow::anyhow!("Not in an object"))?;
+            let start = index.get(object).map_or(0, |node| node.start);
+            let title = format!("Keys of {}", path::json_path(index, &self.buffer, start));
+            let keys = path::object_keys(index, &self.buffer, object);
+            Picker::new(title, keys.map(|(key, offset)| (key, Pick::Key(offset))))
+        };
+        if picker.is_empty() {
+            anyhow::bail!("No keys");
+        }
+        self.picker = Some(picker);
+        Ok(())
+    }
+
+    /// Ctrl-p, :palette - every action with the keys it has now, then every
+    /// command. Commands that take an argument go on to the command line.
+    fn open_palette(&mut self, count: Option<usize>) {
+        let actions = self.keymap.actions(Mode::Normal)
+            .into_iter()
+            .filter(|&(action, _)| action != Action::Palette)
+            .map(|(action, c)| (format!("{:<28} {}", c.description, c.keys), Pick::Action(action, count)));
+        let commands = mode::command::palette_commands()
+            .into_iter()
+            .filter(|command| command.form != "palette")
+            .map(|command| (format!("{:<28} {}", format!(":{}", command.form), command.description), Pick::Command(command)));
+        let title = match count {
+            Some(count) => format!("Run (count {})", count),
+            None => "Run".to_string(),
+        };
+        self.picker = Some(Picker::new(title, actions.chain(commands)));
+    }
+
+    /// :outline - pick a section of the document. The whole file is indexed
+    /// behind it, and sections are added as their part of it is.
+    fn open_outline(&mut self) -> Result<()> {
+        let entries = self.outline_entries().ok_or_else(|| anyhow::anyhow!("Index not ready"))?;
+        if entries.is_empty() && self.fully_indexed() {
+            anyhow::bail!("No keys or elements at the top level");
+        }
+        self.picker = Some(Picker::new("Outline", entries));
+        self.outline = true;
+        self.request_structural_index(self.buffer.line_count())
+    }
+
+    /// The outline as picker candidates
+    fn outline_entries(&self) -> Option<Vec<(String, Pick)>> {
+        let index = self.structural_index.as_ref()?;
+        let entries = outline::outline(index, &self.buffer, picker::MAX_CANDIDATES + 1);
+        Some(entries.iter().map(|entry| (entry.display(), Pick::Node(entry.node))).collect())
+    }
+
+    /// Bring an open :outline up to date with the index
+    fn refresh_outline(&mut self) {
+        self.outline &= self.picker.is_some();
+        if !self.outline {
+            return;
+        }
+        if let (Some(entries), Some(picker)) = (self.outline_entries(), self.picker.as_mut()) {
+            picker.set_items(entries);
+        }
+    }
+
+    /// Ctrl-o / Tab - go back or forward `count` places in the jump list
+    fn jump(&mut self, back: bool, count: usize) {
+        let offset = if back {
+            self.jumps.back(self.cursor.byte_offset, count)
+        } else {
+            self.jumps.forward(count)
+        };
+        let Some(offset) = offset else {
+            self.show_message(format!("At the {} of the jump list", if back { "start" } else { "end" }));
+            return;
+        };
+        self.current_node_id = None;
+        self.folds.open_containing(offset);
+        self.move_cursor_to_offset(offset.min(self.buffer.len_bytes()));
+        self.update_viewport_for_cursor();
+    }
+
+    /// Where mark `name` is; only `.`, the last change, so far
+    fn mark(&self, name: char) -> Option<usize> {
+        match name {
+            '.' => self.undo_stack.last_change(&self.buffer),
+            _ => None,
+        }
+    }
+
+    /// The line mark `name` is on, for a range: `'<` and `'>` are the
+    /// first and last lines of the last selection, the last being the one
+    /// the cursor ended on
+    fn mark_line(&self, name: char) -> Option<usize> {
+        let offset = match name {
+            '<' => self.last_selection.as_ref()?.start,
+            '>' => self.last_selection.as_ref()?.end.min(self.buffer.len_bytes().saturating_sub(1)),
+            _ => self.mark(name)?,
+        };
+        Some(self.buffer.byte_offset_to_line(offset))
+    }
+
+    /// `x / 'x - go to mark x, or to the first non-blank of its line
+    fn goto_mark(&mut self, name: char, linewise: bool) {
+        let Some(mut offset) = self.mark(name) else {
+            self.show_error(format!("Mark not set: {}", name));
+            return;
+        };
+        if linewise {
+            let line = self.buffer.byte_offset_to_line(offset);
+            let text = self.buffer.get_line(line);
+            offset = self.buffer.line_to_byte_offset(line) + (text.len() - text.trim_start_matches([' ', '\t']).len());
+        }
+        self.jump_to_key(offset.min(self.buffer.len_bytes()));
+    }
+
+    /// :marks, as vim lists them: each mark's line, column and text
+    fn marks_list(&self) -> ValuePreview {
+        let mut lines = vec!["mark   line   col  text".to_string()];
+        for name in ['.'] {
+            let Some(offset) = self.mark(name) else {
+                continue;
+            };
+            let line = self.buffer.byte_offset_to_line(offset);
+            let col = offset - self.buffer.line_to_byte_offset(line);
+            let text = self.buffer.get_line(line);
+            lines.push(format!(" {} {:>8} {:>5}  {}", name, self.numbers.count(line + 1), col, text.trim_end()));
+        }
+        if lines.len() == 1 {
+            lines = vec!["No marks set".to_string()];
+        }
+        ValuePreview { title: "Marks".to_string(), total_lines: lines.len(), lines }
+    }
+
+    /// :map, as vim lists them: the normal mode mappings, from the config
+    /// file and made since, `*` marking those that don't remap
+    fn mappings_list(&self) -> ValuePreview {
+        let mappings = self.keymap.mappings(Mode::Normal);
+        let width = mappings.iter().map(|mapping| mapping.keys.len()).max().unwrap_or(0);
+        let mut lines: Vec<String> = mappings.iter()
+            .map(|mapping| format!("{:width$}  {}", mapping.keys, mapping.description))
+            .collect();
+        if lines.is_empty() {
+            lines.push("No mappings".to_string());
+        }
+        ValuePreview { title: "Mappings".to_string(), total_lines: lines.len(), lines }
+    }
+
+    /// Ctrl-n / Ctrl-p in insert mode: put the first (last) key name the
+    /// one being typed could be in its place, and list the others
+    fn complete_key(&mut self, forward: bool) -> Result<()> {
+        let Some(index) = self.structural_index.as_ref() else {
+            anyhow::bail!("Not in a key");
+        };
+        let completion = complete::complete_key(index, &self.buffer, self.cursor.byte_offset)?;
+        let mut picker = Picker::new("Keys", completion.candidates.into_iter().map(|key| (key, ())));
+        picker.selected = if forward { 0 } else { picker.len() - 1 };
+        // Undone with the text typed before it
+        self.undo_stack.hold_group();
+        self.completion = Some(Completion { picker, start: completion.start, prefix: completion.prefix });
+        self.put_completion()
+    }
+
+    /// A key while completing: Ctrl-n / Ctrl-p (Down / Up) go round the
+    /// candidates, Esc puts back what was typed and Enter keeps the one
+    /// chosen. Anything else keeps it too, then goes on as typed, so
+    /// whether the key was taken is returned.
+    fn completion_key(&mut self, key: KeyEvent) -> Result<bool> {
+        let Some(completion) = self.completion.as_mut() else {
+            return Ok(false);
+        };
+        let len = completion.picker.len();
+        match (key.code, key.modifiers) {
+            (KeyCode::Down, _) | (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
+                completion.picker.selected = (completion.picker.selected + 1) % len;
+            }
+            (KeyCode::Up, _) | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                completion.picker.selected = (completion.picker.selected + len - 1) % len;
+            }
+            (KeyCode::Esc, _) => {
+                if let Some(completion) = self.completion.take() {
+                    self.replace_key_text(completion.start, &completion.prefix)?;
+                }
+                return Ok(true);
+            }
+            (KeyCode::Enter, _) => {
+                self.completion = None;
+                return Ok(true);
+            }
+            _ => {
+                self.completion = None;
+                return Ok(false);
+            }
+        }
+        self.put_completion()?;
+        Ok(true)
+    }
+
+    /// Put the selected candidate in place of the key's text
+    fn put_completion(&mut self) -> Result<()> {
+        let Some(completion) = self.completion.as_ref() else {
+            return Ok(());
+        };
+        let Some((_, key)) = completion.picker.rows(completion.picker.selected).next() else {
+            return Ok(());
+        };
+        let (start, key) = (completion.start, key.to_string());
+        self.replace_key_text(start, &key)
+    }
+
+    /// Replace the text from `start` up to the cursor with `text`
+    fn replace_key_text(&mut self, start: usize, text: &str) -> Result<()> {
+        let end = self.cursor.byte_offset;
+        let edit = EditOperations::replace(&mut self.buffer, &mut self.cursor, start, end, text)?;
+        self.undo_stack.push(edit);
+        self.apply_buffer_changes();
+        self.update_viewport_for_cursor();
+        Ok(())
+    }
+
+    /// Go to the node a picker chose, remembering where from
+    fn jump_to_node(&mut self, id: usize) {
+        let Some(start) = self.structural_index.as_ref().and_then(|index| index.get(id)).map(|node| node.start) else {
+            return;
+        };
+        self.jumps.push(self.cursor.byte_offset);
+        self.folds.open_containing(start);
+        self.move_to_node(Some(id));
+        self.update_viewport_for_cursor();
+    }
+
+    /// Go to the key a picker chose, opening any fold it's in
+    fn jump_to_key(&mut self, offset: usize) {
+        self.jumps.push(self.cursor.byte_offset);
+        self.current_node_id = None;
+        self.folds.open_containing(offset);
+        self.move_cursor_to_offset(offset);
+        self.update_viewport_for_cursor();
+    }
+
+    /// Replace the location list, keeping the pane open if it was
+    fn fill_loclist(&mut self, source: loclist::Source, locations: Vec<Location>) {
+        self.loclist = Some(LocationList::new(source, locations));
+    }
+
+    /// A location at `offset`
+    fn location(&self, offset: usize, message: String, severity: Severity) -> Location {
+        let (line, col) = self.buffer.offset_to_line_col(offset);
+        Location { byte_offset: offset, line, col, message, severity }
+    }
+
+    /// The structural errors and warnings indexed so far
+    fn validation_locations(&self) -> Vec<Location> {
+        let Some(index) = &self.structural_index else {
+            return Vec::new();
+        };
+        let errors = index.errors().iter().map(|error| (error, Severity::Error));
+        let warnings = index.warnings().iter().map(|warning| (warning, Severity::Warning));
+        let mut locations: Vec<Location> = errors
+            .chain(warnings)
+            .map(|(error, severity)| self.location(error.offset, error.message(), severity))
+            .collect();
+        if let Some(severity) = self.duplicate_severity() {
+            locations.extend(self.duplicate_keys.found.iter().map(|d| self.location(d.span.start, self.duplicate_message(d), severity)));
+        }
+        locations
+    }
+
+    /// How duplicate keys are shown, unless they aren't
+    fn duplicate_severity(&self) -> Option<Severity> {
+        match self.dupkeys {
+            DupKeys::Error => Some(Severity::Error),
+            DupKeys::Warn => Some(Severity::Warning),
+            DupKeys::Ignore => None,
+        }
+    }
+
+    /// `duplicate key "id" (first at line 120)`
+    fn duplicate_message(&self, duplicate: &DuplicateKey) -> String {
+        let first = self.buffer.byte_offset_to_line(duplicate.first) + 1;
+        format!("duplicate key {} (first at line {})", decode::encode_string(&duplicate.name), self.numbers.count(first))
+    }
+
+    /// Check the keys indexed since last time for duplicates, starting
+    /// again from the top when the index has been rebuilt
+    fn scan_duplicate_keys(&mut self) {
+        if self.duplicate_keys_generation != self.index_generation {
+            self.duplicate_keys = DuplicateScan::default();
+            self.duplicate_keys_generation = self.index_generation;
+        }
+        if let Some(index) = self.structural_index.as_ref().filter(|_| self.dupkeys != DupKeys::Ignore) {
+            self.duplicate_keys.advance(index, |span| self.buffer.slice(span));
+        }
+    }
+
+    /// Give the structural errors and warnings signs again whenever the
+    /// lists of them have changed. Errors aren't only added at the end:
+    /// those of containers left open go in by offset once indexing ends.
+    fn place_error_signs(&mut self) {
+        let Some(index) = self.structural_index.as_ref() else {
+            return;
+        };
+        let counted = (self.index_generation, index.errors().len(), index.warnings().len());
+        if counted == self.error_signs {
+            return;
+        }
+        self.signs.clear(&[SignKind::Error, SignKind::Warning]);
+        for (found, kind) in [(index.errors(), SignKind::Error), (index.warnings(), SignKind::Warning)] {
+            self.signs.place_all(found.iter().map(|error| error.offset), kind, kind.priority());
+        }
+        self.error_signs = counted;
+    }
+
+    fn schema_locations(&self) -> Vec<Location> {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| self.location(diagnostic.span.start, diagnostic.message.clone(), Severity::Error))
+            .collect()
+    }
+
+    /// :grep - list every match of `pattern` inside `within` and go to the
+    /// first after the cursor, or the first
+    fn grep(&mut self, pattern: String, within: std::ops::Range<usize>) {
+        let mut locations = Vec::new();
+        let mut from = within.start;
+        while let Some(offset) = self.buffer.find(&pattern, from).filter(|&offset| offset + pattern.len() <= within.end) {
+            if locations.len() == loclist::MAX_LOCATIONS {
+                break;
+            }
+            locations.push(self.location(offset, String::new(), Severity::Info));
+            from = offset + pattern.len().max(1);
+        }
+        if locations.is_empty() {
+            self.show_error(format!("Pattern not found: {}", pattern));
+            return;
+        }
+        let total = locations.len();
+        let first = locations.partition_point(|location| location.byte_offset <= self.cursor.byte_offset) % total;
+        self.fill_loclist(loclist::Source::Grep(pattern), locations);
+        self.go_to_location(first);
+        let more = if total == loclist::MAX_LOCATIONS { "+" } else { "" };
+        self.show_message(format!("({} of {}{}) :copen lists them", self.numbers.count(first + 1), self.numbers.count(total), more));
+    }
+
+    /// :extract - go through the index as it's built for the values at
+    /// `pattern`, writing them to `output` as they're found or collecting
+    /// them for the location list
+    fn start_extract(&mut self, pattern: Pattern, path: String, output: Option<String>, force: bool) -> Result<()> {
+        let out = match output {
+            Some(file) => {
+                if !force && std::path::Path::new(&file).exists() {
+                    anyhow::bail!("{} exists (add ! to overwrite)", file);
+                }
+                let created = File::create(&file).map_err(|e| anyhow::anyhow!("Can't create {}: {}", file, e))?;
+                Some((file, BufWriter::new(created)))
+            }
+            None => None,
+        };
+        if self.index_revision != self.buffer.revision() {
+            self.reset_structural_index()?;
+        }
+        self.extract = Some(ExtractJob {
+            extraction: Extraction::new(pattern),
+            path,
+            out,
+            locations: Vec::new(),
+            revision: self.buffer.revision(),
+            generation: self.index_generation,
+        });
+        self.request_structural_index(self.buffer.line_count())?;
+        self.poll_extract();
+        Ok(())
+    }
+
+    /// Take an :extract through what's been indexed since it last looked,
+    /// and finish it once the index is complete or stops short
+    fn poll_extract(&mut self) {
+        let Some(job) = self.extract.as_mut() else {
+            return;
+        };
+        if job.revision != self.buffer.revision() || job.generation != self.index_generation {
+            self.extract = None;
+            self.show_error("The buffer changed while extracting; run :extract again".to_string());
+            return;
+        }
+        let Some(index) = &self.structural_index else {
+            return;
+        };
+        let buffer = &self.buffer;
+        let mut failed = None;
+        let caught_up = job.extraction.advance(index, buffer, |node| match &mut job.out {
+            Some((_, out)) => {
+                let written = buffer.write_range(node.start..node.end, out).and_then(|_| Ok(out.write_all(b"\n")?));
+                failed = written.err();
+                failed.is_none()
+            }
+            None => {
+                let (line, col) = buffer.offset_to_line_col(node.start);
+                job.locations.push(Location { byte_offset: node.start, line, col, message: String::new(), severity: Severity::Info });
+                job.locations.len() < loclist::MAX_LOCATIONS
+            }
+        });
+        let full = job.locations.len() >= loclist::MAX_LOCATIONS;
+        if failed.is_none() && !full && !(caught_up && self.index_in_flight.is_none()) {
+            let message = format!("Extracting {}: {} values", job.path, self.numbers.count(job.extraction.values));
+            self.show_message(format!("{} ({}%)", message, self.index_percent()));
+            return;
+        }
+
+        let Some(job) = self.extract.take() else {
+            return;
+        };
+        let (values, skipped) = (job.extraction.values, job.extraction.skipped());
+        let mut note = String::new();
+        if skipped > 0 {
+            note.push_str(&format!("; {} records without it skipped", self.numbers.count(skipped)));
+        }
+        if !full && !self.fully_indexed() {
+            note.push_str("; indexing stopped short of the end of the file");
+        }
+        match job.out {
+            Some((file, mut out)) => match failed.map_or_else(|| out.flush().map_err(anyhow::Error::from), Err) {
+                Ok(()) => self.show_message(format!("\"{}\" {} values written{}", file, self.numbers.count(values), note)),
+                Err(e) => self.show_error(format!("Can't write {}: {}", file, e)),
+            },
+            None if job.locations.is_empty() => self.show_error(format!("No values at {}{}", job.path, note)),
+            None => {
+                let more = if full { "+" } else { "" };
+                self.fill_loclist(loclist::Source::Extract(job.path.clone()), job.locations);
+                self.open_loclist(true);
+                self.show_message(format!("{}{} values at {}{}", self.numbers.count(values), more, job.path, note));
+            }
+        }
+    }
+
+    /// :bookmark add - note the node under the cursor, by its path. After
+    /// an edit this waits for the index to be rebuilt, so the path is right.
+    fn add_bookmark(&mut self, note: String) {
+        if self.index_revision != self.buffer.revision() {
+            if let Err(e) = self.reset_structural_index() {
+                self.show_error(format!("Indexing failed: {}", e));
+                return;
+            }
+        }
+        if self.index_in_flight.is_some() {
+            self.pending_bookmark = Some(note);
+            return;
+        }
+        let Some(index) = &self.structural_index else {
+            self.show_error("Index not ready".to_string());
+            return;
+        };
+        let bookmark_path = json_path(index, &self.buffer, self.cursor.byte_offset);
+        let found = path::parse_path(&bookmark_path).ok().map(|segments| path::resolve(index, &self.buffer, &segments));
+        let Some(node) = found.and_then(|found| match found {
+            Resolved::Found(node) => index.get(node),
+            _ => None,
+        }) else {
+            self.show_error("No node under cursor".to_string());
+            return;
+        };
+        let replaced = self.bookmarks.add(bookmark_path.clone(), note, node.start);
+        self.refresh_bookmark_list();
+        let verb = if replaced { "updated" } else { "added" };
+        self.show_message(format!("Bookmark {}: {}", verb, bookmark_path));
+    }
+
+    /// :bookmark delete - take off the bookmarks on the cursor's line
+    fn delete_bookmark(&mut self) {
+        let line = self.cursor.line;
+        let on_line: Vec<usize> = self.bookmarks.offsets().filter(|&offset| self.buffer.byte_offset_to_line(offset) == line).collect();
+        if on_line.is_empty() {
+            self.show_error("No bookmark on this line".to_string());
+            return;
+        }
+        let removed: Vec<String> = on_line.into_iter().filter_map(|offset| self.bookmarks.remove(offset)).map(|bookmark| bookmark.path).collect();
+        self.refresh_bookmark_list();
+        self.show_message(format!("Bookmark deleted: {}", removed.join(", ")));
+    }
+
+    /// :bookmark list - the bookmarks in the location list, with those whose
+    /// path isn't in the file any more at the top
+    fn list_bookmarks(&mut self) {
+        if self.bookmarks.is_empty() {
+            self.show_error("No bookmarks (:bookmark add sets one)".to_string());
+            return;
+        }
+        self.fill_loclist(loclist::Source::Bookmarks, self.bookmark_locations());
+        self.open_loclist(true);
+    }
+
+    fn bookmark_locations(&self) -> Vec<Location> {
+        self.bookmarks.entries()
+            .iter()
+            .map(|bookmark| {
+                let note = if bookmark.note.is_empty() { String::new() } else { format!(": {}", bookmark.note) };
+                match bookmark.place {
+                    Place::Found(offset) => self.location(offset, format!("{}{}", bookmark.path, note), Severity::Info),
+                    Place::Pending => self.location(0, format!("{} (not yet indexed){}", bookmark.path, note), Severity::Info),
+                    Place::Unresolved => self.location(0, format!("{} (unresolved){}", bookmark.path, note), Severity::Warning),
+                }
+            })
+            .collect()
+    }
+
+    /// Keep the location list up to date while it's showing bookmarks
+    fn refresh_bookmark_list(&mut self) {
+        if self.loclist.as_ref().is_some_and(|list| *list.source() == loclist::Source::Bookmarks) {
+            self.fill_loclist(loclist::Source::Bookmarks, self.bookmark_locations());
+        }
+    }
+
+    /// Find bookmarks read from the session as the index reaches them
+    fn resolve_bookmarks(&mut self) {
+        if !self.bookmarks.has_pending() || self.index_revision != self.buffer.revision() {
+            return;
+        }
+        let complete = self.fully_indexed();
+        let Some(index) = &self.structural_index else {
+            return;
+        };
+        if self.bookmarks.resolve(index, &self.buffer, complete) {
+            self.refresh_bookmark_list();
+        }
+    }
+
+    /// Bring the bookmarks' paths up to date with edits, when the index is
+    fn refresh_bookmark_paths(&mut self) {
+        if let Some(index) = self.structural_index.as_ref().filter(|_| self.index_revision == self.buffer.revision()) {
+            self.bookmarks.refresh_paths(index, &self.buffer);
+        }
+    }
+
+    /// :copen / :cclose
+    fn open_loclist(&mut self, open: bool) {
+        if !open {
+            self.show_loclist = false;
+            if self.focus == Focus::List {
+                self.focus = Focus::Text;
+            }
+            return;
+        }
+        match &self.loclist {
+            Some(_) => {
+                self.show_loclist = true;
+                self.focus = Focus::List;
+            }
+            None => self.show_error("No location list (:grep, :validate or :schema load fill it)".to_string()),
+        }
+    }
+
+    /// ]q / [q
+    fn step_location(&mut self, forward: bool, count: usize) {
+        let Some(list) = self.loclist.as_mut() else {
+            self.show_error("No location list".to_string());
+            return;
+        };
+        match list.step(forward, count) {
+            Some(i) => self.go_to_location(i),
+            None if list.is_empty() => self.show_message("Location list is empty".to_string()),
+            None => self.show_error("No more items".to_string()),
+        }
+    }
+
+    /// Go to entry `i` of the location list and say which it is
+    fn go_to_location(&mut self, i: usize) {
+        let Some(list) = self.loclist.as_mut() else {
+            return;
+        };
+        list.select(i);
+        let len = self.buffer.len_bytes();
+        list.clamp(len);
+        let location = list.locations()[i].clone();
+        let total = list.len();
+        self.jump_to_key(location.byte_offset);
+        self.show_message(format!("({} of {}) {}", self.numbers.count(i + 1), self.numbers.count(total), location.message).trim_end().to_string());
+    }
+
+    /// Keys while the location list has focus: j/k move, Enter goes to the
+    /// entry, q closes the pane and Esc goes back to the text
+    fn handle_loclist_key(&mut self, key: KeyEvent) {
+        let Some(list) = self.loclist.as_mut() else {
+            self.focus = Focus::Text;
+            return;
+        };
+        let page = loclist::PANE_HEIGHT as isize - 2;
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => list.move_selection(1),
+            KeyCode::Char('k') | KeyCode::Up => list.move_selection(-1),
+            KeyCode::PageDown => list.move_selection(page),
+            KeyCode::PageUp => list.move_selection(-page),
+            KeyCode::Char('g') => list.selected = 0,
+            KeyCode::Char('G') => list.move_selection(isize::MAX),
+            KeyCode::Enter if !list.is_empty() => {
+                let selected = list.selected;
+                self.go_to_location(selected);
+            }
+            KeyCode::Char('q') => self.open_loclist(false),
+            KeyCode::Esc => self.focus = Focus::Text,
+            _ => {}
+        }
+    }
+
+    /// Place the cursor on a byte offset, updating line and column
+    fn move_cursor_to_offset(&mut self, offset: usize) {
+        self.cursor.move_to_offset(&self.buffer, offset);
+    }
+
+    /// Search from the cursor. :/ and :'<,'>/ fix a span that n and N
+    /// then stay in, until a plain / searches the whole buffer again.
+    fn search(&mut self, pattern: String, scope: SearchScope) -> Result<()> {
+        let pattern = if !pattern.is_empty() {
+            pattern
+        } else if let Some(search) = &self.search {
+            search.pattern.clone()
+        } else {
+            anyhow::bail!("No previous search pattern");
+        };
+        let scope = match scope {
+            SearchScope::Buffer => None,
+            SearchScope::Node => Some(Scope { span: self.search_node_span()?, name: "node" }),
+            SearchScope::Selection => match self.last_selection.clone() {
+                Some(span) => Some(Scope { span, name: "selection" }),
+                None => anyhow::bail!("No selection"),
+            },
+        };
+        self.search = Some(Search::new(pattern, scope));
+        self.search_next(true, 1);
+        Ok(())
+    }
+
+    /// The span :/ searches: the innermost object or array around the
+    /// cursor, or the value under it at the top level
+    fn search_node_span(&self) -> Result<std::ops::Range<usize>> {
+        let Some(index) = &self.structural_index else {
+            anyhow::bail!("Index not ready");
+        };
+        let Some(under) = index.node_id_at(self.cursor.byte_offset) else {
+            anyhow::bail!("No node under cursor");
+        };
+        let container = std::iter::successors(Some(under), |&id| index.parent(id))
+            .find(|&id| matches!(index.kind(id), Some(NodeKind::Object | NodeKind::Array)))
+            .unwrap_or(under);
+        match index.get(container) {
+            Some(node) => Ok(node.start..node.end),
+            None => anyhow::bail!("No node under cursor"),
+        }
+    }
+
+    /// Start a :key or :value search from the cursor
+    fn search_nodes(&mut self, search: Search) {
+        if search.pattern.is_empty() {
+            self.show_error(format!("Usage: {}{{...}}", search.title()));
+            return;
+        }
+        self.search = Some(search);
+        self.search_next(true, 1);
+    }
+
+    /// n / N for :key and :value: matches are looked for in the index, and
+    /// when it runs out the search waits while more of the file is indexed
+    fn search_next_node(&mut self, forward: bool, count: usize) {
+        let total_lines = self.buffer.line_count();
+        let complete = self.index_frontier.indexed() >= total_lines;
+        let stamp = (self.index_generation, self.buffer.revision());
+        let (Some(search), Some(index)) = (self.search.as_mut(), self.structural_index.as_ref()) else {
+            // Nothing indexed yet
+            if let Err(e) = self.request_structural_index(self.cursor.line) {
+                self.show_error(format!("Indexing failed: {}", e));
+            } else {
+                self.pending_search = Some((forward, count));
+            }
+            return;
+        };
+        search.scan(index, &self.buffer, stamp);
+
+        let mut found: Option<Match> = None;
+        for _ in 0..count.max(1) {
+            let from = found.map_or(self.cursor.byte_offset, |m| m.offset);
+            match search.find_node(index, from, forward, complete) {
+                NodeSearch::Found(next) => found = Some(Match { wrapped: next.wrapped || found.is_some_and(|m| m.wrapped), ..next }),
+                NodeSearch::NotFound => break,
+                NodeSearch::NeedsIndex => {
+                    // Forward, the next chunks may hold a match; going back
+                    // round needs the whole file
+                    let target = if forward { self.index_frontier.indexed() + INDEX_CHUNK_LINES } else { total_lines };
+                    let message = format!("{}: searching…", search.title());
+                    match self.request_structural_index(target) {
+                        Ok(()) => {
+                            self.pending_search = Some((forward, count));
+                            self.show_message(message);
+                        }
+                        Err(e) => self.show_error(format!("Indexing failed: {}", e)),
+                    }
+                    return;
+                }
+            }
+        }
+        let Some(search) = &self.search else { return };
+        match found {
+            Some(found) => {
+                let message = search.describe(&found);
+                self.current_node_id = None;
+                self.move_cursor_to_offset(found.offset);
+                self.update_viewport_for_cursor();
+                self.show_message(message);
+            }
+            None => self.show_error(search.not_found()),
+        }
+    }
+
+    /// n / N: go `count` matches on from the cursor with the last search
+    fn search_next(&mut self, forward: bool, count: usize) {
+        let Some(search) = &self.search else {
+            self.show_error("No previous search pattern".to_string());
+            return;
+        };
+        if search.is_structural() {
+            return self.search_next_node(forward, count);
+        }
+        let timing = perf::scoped("search scan");
+        let mut found: Option<Match> = None;
+        for _ in 0..count.max(1) {
+            let from = found.map_or(self.cursor.byte_offset, |m| m.offset);
+            match search.find(&self.buffer, from, forward) {
+                Some(next) => found = Some(Match { wrapped: next.wrapped || found.is_some_and(|m| m.wrapped), ..next }),
+                None => break,
+            }
+        }
+        drop(timing);
+        match found {
+            Some(found) => {
+                let message = search.describe(&found);
+                self.current_node_id = None;
+                self.move_cursor_to_offset(found.offset);
+                self.update_viewport_for_cursor();
+                self.show_message(message);
+            }
+            None => self.show_error(search.not_found()),
+        }
+    }
+
+    /// Jump `count` structural errors forward or back and describe the one
+    /// landed on, or as far as there are errors
+    fn navigate_error(&mut self, forward: bool, count: usize) {
+        let Some(ref index) = self.structural_index else {
+            return;
+        };
+        let mut error = None;
+        for _ in 0..count.max(1) {
+            let offset = error.map_or(self.cursor.byte_offset, |e: StructuralError| e.offset);
+            let next = if forward { index.next_error(offset) } else { index.prev_error(offset) };
+            match next {
+                Some(next) => error = Some(*next),
+                None => break,
+            }
+        }
+        
+        match error {
+            Some(error) => {
+                let position = index.errors().partition_point(|e| e.offset < error.offset) + 1;
+                let total = index.errors().len();
+                self.current_node_id = None;
+                self.move_cursor_to_offset(error.offset);
+                self.show_error(format!("Error {}/{}: {}", position, total, error.message()));
+            }
+            None => self.show_message("No more structural errors".to_string()),
+        }
+    }
+
+    /// Jump `count` schema diagnostics forward or back and show the message
+    /// of the one landed on
+    fn navigate_diagnostic(&mut self, forward: bool, count: usize) {
+        if self.schema.is_none() {
+            self.show_message("No schema loaded (:schema load {file})".to_string());
+            return;
+        }
+        let offset = self.cursor.byte_offset;
+        let position = if forward {
+            let after = self.diagnostics.partition_point(|d| d.span.start <= offset);
+            (after + count.max(1) - 1).min(self.diagnostics.len().saturating_sub(1))
+        } else {
+            let before = self.diagnostics.partition_point(|d| d.span.start < offset);
+            before.saturating_sub(count.max(1))
+        };
+        let found = self.diagnostics.get(position).filter(|d| if forward { d.span.start > offset } else { d.span.start < offset });
+        match found {
+            Some(diagnostic) => {
+                let (start, message) = (diagnostic.span.start, diagnostic.message.clone());
+                let total = self.diagnostics.len();
+                self.current_node_id = None;
+                self.move_cursor_to_offset(start);
+                self.show_error(format!("Schema error {}/{}: {}", position + 1, total, message));
+            }
+            None if self.diagnostics.is_empty() => self.show_message("No schema errors".to_string()),
+            None => self.show_message("No more schema errors".to_string()),
+        }
+    }
+
+    /// Jump `count` duplicate keys forward or back and say which key the
+    /// one landed on repeats
+    fn navigate_duplicate(&mut self, forward: bool, count: usize) {
+        let Some(severity) = self.duplicate_severity() else {
+            self.show_message("Duplicate keys are ignored (:set dupkeys=warn)".to_string());
+            return;
+        };
+        let found = self.duplicate_keys.step(self.cursor.byte_offset, forward, count);
+        match found {
+            Some((position, duplicate)) => {
+                let (start, message) = (duplicate.span.start, self.duplicate_message(duplicate));
+                let total = self.duplicate_keys.found.len();
+                self.current_node_id = None;
+                self.move_cursor_to_offset(start);
+                let message = format!("Duplicate {}/{}: {}", self.numbers.count(position + 1), self.numbers.count(total), message);
+                match severity {
+                    Severity::Error => self.show_error(message),
+                    _ => self.show_message(message),
+                }
+            }
+            None if self.duplicate_keys.found.is_empty() => self.show_message("No duplicate keys".to_string()),
+            None => self.show_message("No more duplicate keys".to_string()),
+        }
+    }
+
+    /// Jump `count` diff changes forward or back and describe the one
+    /// landed on
+    fn navigate_change(&mut self, forward: bool, count: usize) {
+        let Some((_, diff)) = self.diff.as_ref() else {
+            self.show_message("No diff (:diff {file})".to_string());
+            return;
+        };
+        let changes = &diff.changes;
+        let offset = self.cursor.byte_offset;
+        let position = if forward {
+            let after = changes.partition_point(|c| c.span.start <= offset);
+            (after + count.max(1) - 1).min(changes.len().saturating_sub(1))
+        } else {
+            changes.partition_point(|c| c.span.start < offset).saturating_sub(count.max(1))
+        };
+        let found = changes.get(position).filter(|c| if forward { c.span.start > offset } else { c.span.start < offset });
+        match found {
+            Some(change) => {
+                let (start, description) = (change.span.start, change.describe());
+                let total = changes.len();
+                self.current_node_id = None;
+                self.move_cursor_to_offset(start);
+                self.show_message(format!("Change {}/{}: {}", self.numbers.count(position + 1), self.numbers.count(total), description));
+            }
+            None if changes.is_empty() => self.show_message("No changes".to_string()),
+            None => self.show_message("No more changes".to_string()),
+        }
+    }
+
+    /// Make `target` the current node and put the cursor on its start.
+    /// Lands nowhere when there's no target, e.g. past the last sibling.
+    /// With :set nodepreview, the object `target` on one line, in what's
+    /// left of the text pane's width after `used` columns
+    fn node_preview(&self, target: Option<usize>, used: usize) -> Option<String> {
+        let (index, id) = (self.structural_index.as_ref()?, target.filter(|_| self.node_preview)?);
+        preview::one_line(index, &self.buffer, id, self.viewport.width.saturating_sub(used))
+    }
+
+    fn move_to_node(&mut self, target: Option<usize>) {
+        let Some(node) = target.and_then(|id| self.structural_index.as_ref()?.get(id)) else {
+            return;
+        };
+        let start = node.start;
+        self.current_node_id = target;
+        self.move_cursor_to_offset(start);
+    }
+
+    fn update_fps(&mut self) {
+        self.frame_count += 1;
+        let elapsed = self.last_fps_update.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frame_count as f64 / elapsed.as_secs_f64();
+            self.frame_count = 0;
+            self.last_fps_update = Instant::now();
         }
     }
 }
 
-fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
+/// Raw mode and the alternate screen, with the guard that undoes them.
+/// The guard comes first, so a failure part way is undone too.
+fn setup_terminal(cursor_shape: bool) -> Result<(Terminal<CrosstermBackend<Stdout>>, TerminalGuard)> {
     enable_raw_mode()?;
+    let guard = TerminalGuard::new(move || restore_terminal(cursor_shape));
     stdout().execute(EnterAlternateScreen)?;
+    stdout().execute(EnableBracketedPaste)?;
     let backend = CrosstermBackend::new(stdout());
     let terminal = Terminal::new(backend)?;
-    Ok(terminal)
+    Ok((terminal, guard))
+}
+
+/// Undo setup_terminal and whatever the editor turned on since. Every
+/// step is tried, since there's nowhere to report a failure to.
+fn restore_terminal(cursor_shape: bool) {
+    let _ = stdout().execute(DisableMouseCapture);
+    let _ = stdout().execute(DisableBracketedPaste);
+    let _ = disable_raw_mode();
+    if cursor_shape {
+        let _ = stdout().execute(SetCursorStyle::DefaultUserShape);
+    }
+    let _ = stdout().execute(Show);
+    let _ = stdout().execute(LeaveAlternateScreen);
+}
+
+/// Byte offset of character column `col` in `text`, or its length
+fn char_to_byte(text: &str, col: usize) -> usize {
+    text.char_indices().nth(col).map_or(text.len(), |(i, _)| i)
+}
+
+/// Index generation, nodes indexed, lines indexed and whether the format
+/// allows comments: a change to any recolors every line
+type ColorStamp = (u64, usize, usize, bool);
+
+/// Colorize the bytes `range` of a line. Indexed lines are styled from the
+/// structural index; elsewhere the line lexer runs over the slice alone, so
+/// a multi-megabyte minified line costs no more than a short one, seeded
+/// with an opening quote when the slice starts inside a string.
+fn colorize_slice(app: &App, line_idx: usize, line_start: usize, text: &str, range: std::ops::Range<usize>) -> Line<'static> {
+    let slice = &text[range.clone()];
+    let Some(index) = &app.structural_index else {
+        return Line::styled(slice.to_string(), app.theme.text);
+    };
+    let relaxed = app.format.is_relaxed();
+    // Nodes around an edit keep their old extent until the reindex
+    let edited = index.touches_dirty(line_start + range.start..line_start + range.end);
+    if line_idx < app.index_frontier.indexed() && !edited {
+        return colorize_from_index(index, slice, line_start + range.start, relaxed, &app.theme);
+    }
+    if range.start == 0 || !inside_string(app, line_idx, line_start, text, range.start) {
+        return colorize_json_line(slice, relaxed, &app.theme);
+    }
+    let mut line = colorize_json_line(&format!("\"{}", slice), relaxed, &app.theme);
+    if let Some(first) = line.spans.first_mut() {
+        first.content = first.content[1..].to_string().into();
+    }
+    line
+}
+
+/// Whether byte `start` of a line falls inside a string literal
+fn inside_string(app: &App, line_idx: usize, line_start: usize, text: &str, start: usize) -> bool {
+    let offset = line_start + start;
+    let indexed = |index: &&StructuralIndex| line_idx < app.index_frontier.indexed() && !index.touches_dirty(offset..offset);
+    if let Some(index) = app.structural_index.as_ref().filter(indexed) {
+        return index.node_id_at(offset)
+            .and_then(|id| index.get(id))
+            .is_some_and(|node| node.kind == NodeKind::String && node.start < offset && offset < node.end);
+    }
+    // Not indexed yet: count unescaped quotes before the slice
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in &text.as_bytes()[..start] {
+        match byte {
+            b'\\' if in_string => escaped = !escaped,
+            b'"' if !escaped => in_string = !in_string,
+            _ => escaped = false,
+        }
+    }
+    in_string
+}
+
+/// Break a rendered line into rows of `width` characters for soft wrap
+fn split_line(line: Line<'static>, width: usize) -> Vec<Line<'static>> {
+    let mut rows = vec![Line::default()];
+    let mut used = 0;
+    for span in line.spans {
+        let mut rest: &str = &span.content;
+        while !rest.is_empty() {
+            if used == width {
+                rows.push(Line::default());
+                used = 0;
+            }
+            let take = char_to_byte(rest, width - used);
+            let piece = &rest[..take];
+            used += piece.chars().count();
+            rows.last_mut().unwrap().spans.push(Span::styled(piece.to_string(), span.style));
+            rest = &rest[take..];
+        }
+    }
+    rows
+}
+
+/// Whether `line` is drawn within `rows` rows of `start_line`
+fn line_within_rows(folded: &FoldedLines, start_line: usize, line: usize, rows: usize) -> bool {
+    let mut current = start_line;
+    for _ in 0..rows {
+        if folded.visible_line(line) == current {
+            return true;
+        }
+        if current > line {
+            return false;
+        }
+        current = folded.next_visible(current);
+    }
+    false
+}
+
+/// One row standing in for a closed fold: the text before the container,
+/// its member count, and whatever follows the closer on its last line,
+/// e.g. `  "items": [ … 10,000 items ],`
+fn fold_summary_line(app: &App, first_line: &str, span: &FoldSpan) -> Line<'static> {
+    let line_start = app.buffer.line_to_byte_offset(span.start_line);
+    let prefix_len = (span.start - line_start).min(first_line.len());
+    let prefix = first_line.get(..prefix_len).unwrap_or(first_line);
+    let opener = app.buffer.char_at(span.start).unwrap_or('[');
+    let closer = if opener == '{' { '}' } else { ']' };
+    
+    let count = app.structural_index.as_ref()
+        .and_then(|index| index.node_id_at(span.start).and_then(|id| index.child_count(id)));
+    let inner = match count {
+        Some(count) if opener == '{' => format!(" … {} keys ", app.numbers.child_count(count)),
+        Some(count) => format!(" … {} items ", app.numbers.child_count(count)),
+        None => format!(" … {} lines ", app.numbers.count(span.end_line - span.start_line + 1)),
+    };
+    
+    let last_line = app.buffer.get_line(span.end_line);
+    let last_line = last_line.lines().next().unwrap_or("");
+    let tail_start = span.end - app.buffer.line_to_byte_offset(span.end_line);
+    let tail = last_line.get(tail_start..).unwrap_or("");
+    
+    let fold_style = app.theme.fold;
+    if let Some(hidden) = span.hidden {
+        let indent = first_line.len() - first_line.trim_start().len();
+        let marker = format!("⋯ {} hidden", ChildCount { count: hidden, complete: true });
+        return Line::from(vec![Span::raw(first_line[..indent].to_string()), Span::styled(marker, fold_style)]);
+    }
+    let mut spans = colorize_slice(app, span.start_line, line_start, first_line, 0..prefix.len()).spans;
+    spans.push(Span::styled(format!("{}{}{}", opener, inner, closer), fold_style));
+    spans.push(Span::raw(tail.to_string()));
+    Line::from(spans)
+}
+
+/// The :diff changes touching buffer `lines`, as the lines each covers
+fn diff_signs(app: &App, lines: std::ops::Range<usize>) -> Vec<(std::ops::RangeInclusive<usize>, ChangeKind)> {
+    let Some((_, diff)) = &app.diff else {
+        return Vec::new();
+    };
+    let from = app.buffer.line_to_byte_offset(lines.start);
+    let to = if lines.end < app.buffer.line_count() { app.buffer.line_to_byte_offset(lines.end) } else { usize::MAX };
+    diff.changes.iter()
+        .filter(|c| c.span.start < to && (c.span.end > from || c.span.start >= from))
+        .map(|c| {
+            let first = app.buffer.byte_offset_to_line(c.span.start);
+            let last = app.buffer.byte_offset_to_line(c.span.end.saturating_sub(1).max(c.span.start));
+            (first..=last, c.kind)
+        })
+        .collect()
+}
+
+/// The buffer `lines` with a bookmark on them
+fn bookmark_lines(app: &App, lines: std::ops::Range<usize>) -> Vec<usize> {
+    let from = app.buffer.line_to_byte_offset(lines.start);
+    let to = if lines.end < app.buffer.line_count() { app.buffer.line_to_byte_offset(lines.end) } else { usize::MAX };
+    app.bookmarks.offsets()
+        .filter(|offset| (from..to).contains(offset))
+        .map(|offset| app.buffer.byte_offset_to_line(offset))
+        .collect()
+}
+
+/// The buffer `lines` with a duplicate key on them, unless :set
+/// dupkeys=ignore
+fn duplicate_lines(app: &App, lines: std::ops::Range<usize>) -> Vec<usize> {
+    if app.dupkeys == DupKeys::Ignore {
+        return Vec::new();
+    }
+    let from = app.buffer.line_to_byte_offset(lines.start);
+    let to = if lines.end < app.buffer.line_count() { app.buffer.line_to_byte_offset(lines.end) } else { usize::MAX };
+    let found = &app.duplicate_keys.found;
+    let first = found.partition_point(|d| d.span.start < from);
+    found[first..].iter()
+        .take_while(|d| d.span.start < to)
+        .map(|d| app.buffer.byte_offset_to_line(d.span.start))
+        .collect()
+}
+
+/// "3 changes from other.json (+1 -1 ~1)"
+fn diff_count(path: &str, diff: &Diff) -> String {
+    let count = |kind| diff.changes.iter().filter(|c| c.kind == kind).count();
+    let total = diff.changes.len();
+    format!(
+        "{} change{} from {} (+{} -{} ~{}){}",
+        total,
+        if total == 1 { "" } else { "s" },
+        path,
+        count(ChangeKind::Added),
+        count(ChangeKind::Removed),
+        count(ChangeKind::Changed),
+        if diff.truncated { ", stopped early" } else { "" },
+    )
+}
+
+/// :diff's summary pane: every change, one per line
+fn diff_summary(path: &str, diff: &Diff) -> ValuePreview {
+    let mut lines: Vec<String> = diff.changes.iter().map(|c| c.describe()).collect();
+    if lines.is_empty() {
+        lines.push("No differences".to_string());
+    }
+    if diff.truncated {
+        lines.push("…".to_string());
+    }
+    ValuePreview {
+        title: diff_count(path, diff),
+        total_lines: lines.len(),
+        lines,
+    }
+}
+
+/// Whether `changes`, which left the text `len` bytes long, took all of it
+/// out at some point and put something else in its place
+fn replaced_whole(changes: &[json_tool::buffer::TextChange], mut len: usize) -> bool {
+    len > 0
+        && changes.iter().rev().any(|change| {
+            len = len + change.removed - change.inserted;
+            change.offset == 0 && change.removed > 0 && change.removed == len
+        })
+}
+
+/// :undolist: the groups u would revert, newest first, then each branch
+/// of the undo tree by its last change, the one the buffer is on marked
+fn undo_list(stack: &UndoStack) -> ValuePreview {
+    let recent = stack.recent(UNDO_LIST_LENGTH);
+    if recent.is_empty() && stack.branches().is_empty() {
+        let lines = vec!["Nothing to undo".to_string()];
+        return ValuePreview { title: "Undo history".to_string(), total_lines: 1, lines };
+    }
+    // The way back from here, the next group u reverts marked
+    let mut lines = vec!["   seq  edits  when     what".to_string()];
+    for (i, (seq, group)) in recent.iter().enumerate() {
+        let age = format_age(group.timestamp().elapsed().unwrap_or_default());
+        let mark = if i == 0 { "u" } else { " " };
+        lines.push(format!("{} {:>4} {:>6}  {:<8} {}", mark, seq, group.edits().len(), age, group.describe()));
+    }
+    if recent.len() == UNDO_LIST_LENGTH && stack.undo_count() > UNDO_LIST_LENGTH {
+        lines.push(format!("  … {} older", stack.undo_count() - UNDO_LIST_LENGTH));
+    }
+    let branches = stack.branches();
+    if branches.len() > 1 {
+        lines.push(String::new());
+        lines.push("Branches (g- / g+):".to_string());
+        lines.push("number changes  when".to_string());
+        for branch in &branches {
+            let age = branch.timestamp.elapsed().unwrap_or_default();
+            let mark = if branch.seq == stack.seq() { ">" } else { " " };
+            lines.push(format!("{}{:>5} {:>7}  {}", mark, branch.seq, branch.changes, format_age(age)));
+        }
+    }
+    ValuePreview {
+        title: format!("Undo history ({} changes, {} branches)", stack.undo_count(), branches.len()),
+        total_lines: lines.len(),
+        lines,
+    }
+}
+
+/// Finish drawing the bytes `range` of a line, colorized as `line`: the
+/// delimiters matched at the cursor and the visual selection highlighted,
+/// and :set list substitutions
+/// What `draw_slice` would put over the document bytes `slice`, for
+/// telling whether the row it was drawn on last frame can be kept
+fn slice_overlay(app: &App, slice: std::ops::Range<usize>, highlights: &[usize], selection: Option<&std::ops::Range<usize>>) -> Overlay {
+    let first = app.diagnostics.partition_point(|d| d.span.end <= slice.start);
+    Overlay {
+        marks: highlights.iter().filter(|offset| slice.contains(offset)).map(|offset| offset - slice.start).collect(),
+        diagnostics: app.diagnostics[first..]
+            .iter()
+            .take_while(|d| d.span.start < slice.end)
+            .map(|d| d.span.start.max(slice.start) - slice.start..d.span.end.min(slice.end) - slice.start)
+            .collect(),
+        selection: selection
+            .map(|selected| selected.start.max(slice.start)..selected.end.min(slice.end))
+            .filter(|clipped| clipped.start < clipped.end)
+            .map(|clipped| clipped.start - slice.start..clipped.end - slice.start),
+        list: app.list,
+    }
+}
+
+fn draw_slice(
+    app: &App,
+    mut line: Line<'static>,
+    line_start: usize,
+    text: &str,
+    range: std::ops::Range<usize>,
+    highlights: &[usize],
+    selection: Option<&std::ops::Range<usize>>,
+) -> Line<'static> {
+    let (start, end) = (range.start, range.end);
+    // Highlight the delimiter under the cursor and its partner
+    for &offset in highlights {
+        if (line_start + start..line_start + end).contains(&offset) {
+            highlight_byte(&mut line, offset - line_start - start, app.theme.current_node);
+        }
+    }
+    
+    // Values that break the schema
+    let slice = line_start + start..line_start + end;
+    let first = app.diagnostics.partition_point(|d| d.span.end <= slice.start);
+    for diagnostic in app.diagnostics[first..].iter().take_while(|d| d.span.start < slice.end) {
+        let from = diagnostic.span.start.max(slice.start);
+        let to = diagnostic.span.end.min(slice.end);
+        if from < to {
+            highlight_range(&mut line, from - slice.start..to - slice.start, app.theme.invalid);
+        }
+    }
+
+    // Visual selection
+    if let Some(selected) = selection {
+        let from = selected.start.max(line_start + start);
+        let to = selected.end.min(line_start + end);
+        if from < to {
+            highlight_range(&mut line, from - line_start - start..to - line_start - start, app.theme.selection);
+        }
+    }
+    
+    if app.list {
+        let trailing_from = list::trailing_start(text).saturating_sub(start);
+        line = list::apply(line, trailing_from, app.theme.whitespace, app.theme.special);
+    }
+    line
+}
+
+/// Give the single-byte span at `col` of a rendered line a highlight
+/// background, splitting the span if it covers more than that byte
+fn highlight_byte(line: &mut Line<'_>, col: usize, style: Style) {
+    highlight_range(line, col..col + 1, style);
+}
+
+/// Patch `style` onto a byte range of a rendered line, splitting the spans
+/// at its ends
+fn highlight_range(line: &mut Line<'_>, range: std::ops::Range<usize>, style: Style) {
+    let mut pos = 0;
+    let mut i = 0;
+    while i < line.spans.len() && pos < range.end {
+        let len = line.spans[i].content.len();
+        let (from, to) = (range.start.max(pos), range.end.min(pos + len));
+        if from >= to {
+            pos += len;
+            i += 1;
+            continue;
+        }
+        let span = line.spans.remove(i);
+        let text = span.content.into_owned();
+        let (at, until) = (from - pos, to - pos);
+        let mut parts = Vec::new();
+        if at > 0 {
+            parts.push(Span::styled(text[..at].to_string(), span.style));
+        }
+        parts.push(Span::styled(text[at..until].to_string(), span.style.patch(style)));
+        if until < text.len() {
+            parts.push(Span::styled(text[until..].to_string(), span.style));
+        }
+        let added = parts.len();
+        line.spans.splice(i..i, parts);
+        pos += len;
+        i += added;
+    }
+}
+
+/// Draw the K popup below the cursor row (or above it when there's more room
+/// there), sized to its content up to half of `area`
+fn render_preview(
+    frame: &mut ratatui::Frame,
+    preview: &ValuePreview,
+    theme: &Theme,
+    area: Rect,
+    cursor_y: Option<u16>,
+) {
+    let max_rows = (area.height / 2).saturating_sub(2).max(1) as usize;
+    let lines = preview.visible_lines(max_rows);
+    let content_width = lines
+        .iter()
+        .chain(std::iter::once(&preview.title))
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let width = (content_width as u16 + 2).min((area.width / 2).max(10)).min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+
+    let cursor_y = cursor_y.unwrap_or(area.y);
+    let below = (area.y + area.height).saturating_sub(cursor_y + 1);
+    let above = cursor_y.saturating_sub(area.y);
+    let y = if below >= height || below >= above {
+        (cursor_y + 1).min((area.y + area.height).saturating_sub(height))
+    } else {
+        cursor_y.saturating_sub(height)
+    };
+    let popup = Rect { x: area.x, y, width, height };
+
+    let text: Vec<Line> = lines
+        .iter()
+        .map(|line| {
+            if line.starts_with('…') {
+                Line::from(Span::styled(line.clone(), theme.hint))
+            } else {
+                colorize_json_line(line, false, theme)
+            }
+        })
+        .collect();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(preview.title.clone())
+        .style(theme.popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(text).block(block), popup);
+}
+
+/// Draw the keys that can follow a pending sequence in the bottom right
+/// corner of `area`, in columns when there are too many for one
+fn render_key_hint(frame: &mut ratatui::Frame, sequence: &[KeyEvent], next: &[Continuation], theme: &Theme, area: Rect) {
+    let key_width = next.iter().map(|c| c.keys.chars().count()).max().unwrap_or(0);
+    let entry_width = next.iter()
+        .map(|c| key_width + 2 + c.description.chars().count())
+        .max()
+        .unwrap_or(0) as u16;
+    let max_rows = area.height.saturating_sub(2).max(1);
+    let columns = (next.len() as u16).div_ceil(max_rows).max(1);
+    let rows = (next.len() as u16).div_ceil(columns);
+    let width = (columns * (entry_width + 2) + 1).min(area.width);
+    let height = (rows + 2).min(area.height);
+    let popup = Rect {
+        x: area.x + area.width - width,
+        y: area.y + area.height - height,
+        width,
+        height,
+    };
+
+    let entry = |c: &Continuation| vec![
+        Span::styled(format!(" {:<width$}", c.keys, width = key_width), theme.key),
+        Span::styled(format!("  {:<width$} ", c.description, width = entry_width as usize - key_width - 2), theme.hint),
+    ];
+    let lines: Vec<Line> = (0..rows as usize)
+        .map(|row| {
+            let spans = (0..columns as usize)
+                .filter_map(|column| next.get(column * rows as usize + row))
+                .flat_map(entry)
+                .collect::<Vec<_>>();
+            Line::from(spans)
+        })
+        .collect();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" {} ", format_keys(sequence)))
+        .style(theme.popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines).block(block), popup);
+}
+
+/// Draw the :edit-string overlay in the middle of `area`, scrolled to keep
+/// its cursor in view. Control characters show as `·`.
+fn render_string_editor(frame: &mut ratatui::Frame, editor: &StringEditor, theme: &Theme, area: Rect) {
+    let lines = editor.lines();
+    let content_width = lines.iter().map(|line| line.chars().count() + 1).max().unwrap_or(0).max(30);
+    let width = (content_width as u16 + 2).min(area.width.saturating_sub(4)).max(3).min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height.saturating_sub(2)).max(3).min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let (rows, columns) = (height as usize - 2, width as usize - 2);
+    let top = (editor.row + 1).saturating_sub(rows.max(1));
+    let left = (editor.col + 1).saturating_sub(columns.max(1));
+
+    let text: Vec<Line> = lines.iter()
+        .skip(top)
+        .take(rows)
+        .map(|line| {
+            let shown: String = line.chars()
+                .skip(left)
+                .map(|c| if c.is_control() { '·' } else { c })
+                .collect();
+            Line::from(shown)
+        })
+        .collect();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(" Edit string ")
+        .style(theme.popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(text).block(block), popup);
+    if rows > 0 && columns > 0 {
+        frame.set_cursor_position((popup.x + 1 + (editor.col - left) as u16, popup.y + 1 + (editor.row - top) as u16));
+    }
+}
+
+/// Draw a picker in the middle of `area`: the query with the counts on
+/// its first row, then the matches with their matched characters marked
+fn render_picker<T>(frame: &mut ratatui::Frame, picker: &mut Picker<T>, theme: &Theme, area: Rect) {
+    let width = (area.width * 3 / 4).max(40).min(area.width);
+    let height = (picker.len() as u16 + 3).min(area.height * 3 / 4).max(4).min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let rows = (height as usize).saturating_sub(3);
+    picker.scroll_to_selected(rows.max(1));
+
+    let prompt = format!("> {}", picker.query);
+    let counts = picker.counts();
+    let gap = (width as usize - 2).saturating_sub(prompt.chars().count() + counts.chars().count()).max(1);
+    let mut lines = vec![Line::from(vec![
+        Span::styled(prompt.clone(), theme.text),
+        Span::raw(" ".repeat(gap)),
+        Span::styled(counts, theme.hint),
+    ])];
+    for (row, text) in picker.rows(picker.scroll).take(rows) {
+        let marked = picker::positions(&picker.query, text).unwrap_or_default();
+        let base = if row == picker.selected { theme.selection } else { theme.popup };
+        let spans: Vec<Span> = text.chars()
+            .enumerate()
+            .map(|(i, c)| {
+                let style = if marked.contains(&i) { base.patch(theme.key).add_modifier(Modifier::BOLD) } else { base };
+                Span::styled(c.to_string(), style)
+            })
+            .collect();
+        lines.push(Line::from(spans));
+    }
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" {} ", picker.title))
+        .style(theme.popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines).block(block), popup);
+    frame.set_cursor_position((popup.x + 1 + prompt.chars().count().min(width as usize - 2) as u16, popup.y + 1));
+}
+
+/// Most rows of key completion candidates shown at once
+const COMPLETION_ROWS: usize = 10;
+
+/// Draw the candidates of a key completion in a small popup under `at`,
+/// the start of the key's text, or over it when there's more room above
+fn render_completion(frame: &mut ratatui::Frame, picker: &mut Picker<()>, theme: &Theme, area: Rect, at: (u16, u16)) {
+    let (x, cursor_y) = at;
+    let rows = picker.len().min(COMPLETION_ROWS);
+    let width = picker.rows(0).map(|(_, key)| key.chars().count()).max().unwrap_or(0) as u16 + 2;
+    let width = width.min(area.width);
+    let height = (rows as u16 + 2).min(area.height);
+    let below = (area.y + area.height).saturating_sub(cursor_y + 1);
+    let above = cursor_y.saturating_sub(area.y);
+    let y = if below >= height || below >= above {
+        (cursor_y + 1).min((area.y + area.height).saturating_sub(height))
+    } else {
+        cursor_y.saturating_sub(height)
+    };
+    let x = x.min((area.x + area.width).saturating_sub(width));
+    let popup = Rect { x, y, width, height };
+    picker.scroll_to_selected((height as usize).saturating_sub(2).max(1));
+
+    let lines: Vec<Line> = picker.rows(picker.scroll)
+        .take(rows)
+        .map(|(row, key)| {
+            let style = if row == picker.selected { theme.selection } else { theme.popup };
+            Line::from(Span::styled(key.to_string(), style))
+        })
+        .collect();
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines).block(Block::default().borders(Borders::ALL).style(theme.popup)), popup);
+}
+
+/// Draw the help pane over `area`, highlighting lines that match the search
+fn render_help(frame: &mut ratatui::Frame, help: &HelpView, theme: &Theme, area: Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" {} ", help.title))
+        .border_style(theme.focus_border);
+    let inner = block.inner(area);
+    let key_width = help.key_width();
+    let lines: Vec<Line> = help.lines()
+        .iter()
+        .skip(help.scroll)
+        .take(inner.height as usize)
+        .map(|line| {
+            let text = match line {
+                HelpLine::Heading(title) => Line::from(Span::styled(title.clone(), theme.current_node)),
+                HelpLine::Entry { keys, description } => Line::from(vec![
+                    Span::styled(format!("  {:<width$}", keys, width = key_width), theme.key),
+                    Span::styled(format!("  {}", description), theme.text),
+                ]),
+                HelpLine::Text(text) => Line::from(Span::styled(text.clone(), theme.text)),
+                HelpLine::Blank => Line::from(""),
+            };
+            if help.is_match(line) { text.style(theme.selection) } else { text }
+        })
+        .collect();
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(lines).block(block), area);
 }
 
-fn format_size(bytes: usize) -> String {
-    const KB: usize = 1024;
-    const MB: usize = KB * 1024;
-    const GB: usize = MB * 1024;
+/// Draw the outline pane, keeping its selection on the cursor's node while
+/// the text has focus
+fn render_tree(frame: &mut ratatui::Frame, app: &mut App, area: Rect) {
+    let focused = app.focus == Focus::Tree;
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(" Outline ")
+        .border_style(if focused { app.theme.focus_border } else { Style::default() });
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    app.screen.tree = Some(inner);
     
-    if bytes >= GB {
-        format!("{:.2} GB", bytes as f64 / GB as f64)
-    } else if bytes >= MB {
-        format!("{:.2} MB", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.2} KB", bytes as f64 / KB as f64)
-    } else {
-        format!("{} B", bytes)
+    let Some(index) = &app.structural_index else {
+        frame.render_widget(Paragraph::new("Indexing…"), inner);
+        return;
+    };
+    app.tree.refresh(index, &app.buffer, (index.len(), app.index_generation));
+    if !focused {
+        app.tree.select_offset(index, app.cursor.byte_offset);
     }
+    let height = inner.height as usize;
+    app.tree.scroll_to_selected(height);
+    app.screen.tree_scroll = app.tree.scroll;
+    
+    let lines: Vec<Line> = app.tree.rows().iter()
+        .enumerate()
+        .skip(app.tree.scroll)
+        .take(height)
+        .map(|(i, row)| {
+            let marker = match row.kind {
+                TreeRowKind::Node { expandable: true, expanded: true, .. } => "▾ ",
+                TreeRowKind::Node { expandable: true, expanded: false, .. } => "▸ ",
+                _ => "  ",
+            };
+            let text = format!("{}{}{}", "  ".repeat(row.depth), marker, row.label);
+            let style = match (i == app.tree.selected, focused, row.kind) {
+                (true, true, _) => app.theme.selection.add_modifier(Modifier::BOLD),
+                (true, false, _) => app.theme.selection,
+                (false, _, TreeRowKind::More { .. }) => app.theme.hint,
+                _ => Style::default(),
+            };
+            Line::from(Span::styled(text, style))
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), inner);
 }
 
-fn restore_terminal(mut terminal: Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
-    disable_raw_mode()?;
-    terminal.show_cursor()?;
-    stdout().execute(LeaveAlternateScreen)?;
-    Ok(())
+/// Draw the location list pane: each entry's position, message and a
+/// snippet of its line, the current one marked
+fn render_loclist(frame: &mut ratatui::Frame, app: &mut App, area: Rect) {
+    let focused = app.focus == Focus::List;
+    let Some(list) = app.loclist.as_mut() else {
+        return;
+    };
+    let position = match list.current() {
+        Some(current) => format!("{}/{}", current + 1, list.len()),
+        None => list.len().to_string(),
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" Locations: {} ({}) ", list.source().title(), position))
+        .border_style(if focused { app.theme.focus_border } else { Style::default() });
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    if list.is_empty() {
+        let text = match list.source() {
+            loclist::Source::Schema if app.schema_job.is_some() => "Checking…",
+            _ => "No entries",
+        };
+        frame.render_widget(Paragraph::new(Span::styled(text, app.theme.hint)), inner);
+        return;
+    }
+
+    let height = inner.height as usize;
+    list.scroll_to_selected(height);
+    let rows = list.scroll..list.scroll + height;
+    let buffer = &app.buffer;
+    list.relocate(rows.clone(), |offset| buffer.offset_to_line_col(offset));
+    let width = inner.width as usize;
+    let lines: Vec<Line> = list.locations()[rows.start..rows.end.min(list.len())]
+        .iter()
+        .zip(rows)
+        .map(|(location, i)| {
+            let marker = if list.current() == Some(i) { "> " } else { "  " };
+            let at = format!("{}{}:{} ", marker, location.line + 1, location.col + 1);
+            let severity = match location.severity {
+                Severity::Error => app.theme.invalid,
+                Severity::Warning | Severity::Info => app.theme.hint,
+            };
+            let mut spans = vec![Span::raw(at.clone())];
+            let mut used = at.chars().count();
+            if !location.message.is_empty() {
+                let message = format!("{}: {}  ", location.severity.name(), location.message);
+                used += message.chars().count();
+                spans.push(Span::styled(message, severity));
+            }
+            let snippet = loclist::snippet(&buffer.get_line(location.line), width.saturating_sub(used));
+            spans.push(Span::styled(snippet, app.theme.hint));
+            let mut line = Line::from(spans);
+            if i == list.selected {
+                let style = if focused { app.theme.selection.add_modifier(Modifier::BOLD) } else { app.theme.selection };
+                line = line.patch_style(style);
+            }
+            line
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), inner);
 }
 
-fn colorize_json_line(line: &str) -> Line {
-    let mut spans = Vec::new();
-    let chars: Vec<char> = line.chars().collect();
-    let mut pos = 0;
+/// Draw one text window in `area` (border included): the lines from its
+/// viewport, line numbers and scrollbar, and for the focused window the
+/// cursor and value popup. Returns how many buffer lines it spans.
+fn render_window(frame: &mut ratatui::Frame, app: &mut App, area: Rect, gutter: Option<Gutter>, active: bool) -> usize {
+    let focused = active && app.focus == Focus::Text && app.windows.len() > 1;
+    let main_block = Block::default()
+        .borders(Borders::ALL)
+        .title("Jim - JSON Interactive Manager v0.1.0")
+        .border_style(if focused { app.theme.focus_border } else { Style::default() });
+    frame.render_widget(main_block, area);
+    let line_count = app.buffer.line_count();
+    let Some((gutter_area, content_area)) = text_pane(area, gutter.map_or(0, |g| g.width)) else {
+        return 1;
+    };
+    let gutter = gutter.filter(|_| gutter_area.width > 0);
+    let inner_area = Rect { width: gutter_area.width + content_area.width, ..gutter_area };
     
-    while pos < chars.len() {
-        let ch = chars[pos];
-        match ch {
-            '{' | '}' | '[' | ']' => {
-                spans.push(Span::styled(ch.to_string(), Style::default().fg(Color::Blue)));
-                pos += 1;
-            }
-            '"' => {
-                // Find matching quote
-                let start = pos;
-                pos += 1;
-                while pos < chars.len() && chars[pos] != '"' {
-                    if chars[pos] == '\\' {
-                        pos += 1; // Skip escaped char
+    // Update viewport size to match actual terminal size and keep the
+    // cursor in view
+    app.viewport.height = inner_area.height as usize;
+    app.viewport.width = content_area.width as usize;
+    if active {
+        app.expand_string_at_cursor();
+    }
+    app.update_viewport_for_cursor();
+
+    // Render buffer content with syntax highlighting, one row per line
+    // (several when wrapping) except for closed folds, which take a
+    // single summary row
+    let folded = app.folds.lines(&app.buffer);
+    let height = inner_area.height as usize;
+    let width = app.viewport.width.max(1);
+    let highlights = matchparen::highlights(
+        app.structural_index.as_ref(),
+        &app.buffer,
+        app.cursor.byte_offset,
+        0..usize::MAX,
+    );
+    let selection = app.visual_mode_handler.as_ref()
+        .filter(|_| active)
+        .map(|visual| visual.get_selection_range(app.cursor.byte_offset))
+        .map(|(start, end)| start..end);
+    let mut lines: Vec<Line> = Vec::with_capacity(height);
+    let mut row_lines: Vec<(usize, bool, usize)> = Vec::with_capacity(height); // (buffer line, is fold, wrapped row)
+    let mut cursor_pieces = None; // How the cursor line was laid out, when strings are elided
+    let mut cache = std::mem::take(&mut app.line_cache);
+    // The focused window's lines are read once and kept while nothing
+    // changes; other windows, and lines past closed folds, read as they go
+    let mut view_lines = if active { std::mem::take(&mut app.view_lines) } else { ViewLines::new() };
+    if active {
+        view_lines.fetch(&mut app.buffer, app.viewport.start_line, height);
+    }
+    let mut line_idx = app.viewport.start_line;
+    while lines.len() < height && line_idx < line_count {
+        let read;
+        let text = match view_lines.get(line_idx) {
+            Some(text) => text,
+            None => {
+                read = app.buffer.get_line(line_idx);
+                &read
+            }
+        };
+        let text = text.lines().next().unwrap_or("");
+        if let Some(span) = folded.span_at(line_idx).copied() {
+            lines.push(fold_summary_line(app, text, &span));
+            row_lines.push((line_idx, true, 0));
+            line_idx = folded.next_visible(line_idx);
+            continue;
+        }
+        
+        // Only the part of the line that lands on screen is colorized
+        let first_row = if app.wrap && line_idx == app.viewport.start_line { app.viewport.start_row } else { 0 };
+        let (first_col, cols) = if app.wrap {
+            (first_row * width, (height - lines.len()) * width)
+        } else {
+            (app.viewport.start_col, width)
+        };
+        let start = char_to_byte(text, first_col);
+        let end = start + char_to_byte(&text[start..], cols);
+        let line_start = app.buffer.line_to_byte_offset(line_idx);
+        let mut draw = |range: std::ops::Range<usize>| {
+            let overlay = slice_overlay(app, line_start + range.start..line_start + range.end, &highlights, selection.as_ref());
+            cache.drawn_or_insert_with(
+                line_idx,
+                line_start,
+                range.clone(),
+                overlay,
+                || colorize_slice(app, line_idx, line_start, text, range.clone()),
+                |line| draw_slice(app, line, line_start, text, range.clone(), &highlights, selection.as_ref()),
+            )
+        };
+        
+        // Long strings keep their first characters and a marker stands in
+        // for the rest; wrapped lines are always drawn in full
+        let line = match app.elide_strings.filter(|_| !app.wrap) {
+            Some(threshold) => {
+                let pieces = elide::plan(text, start, width, threshold, |range| {
+                    let string = app.elided_string(line_start + range.start..line_start + range.end)?;
+                    Some(string.start.max(line_start) - line_start..(string.end - line_start).min(text.len() + 1))
+                });
+                let mut spans = Vec::new();
+                for piece in &pieces {
+                    match piece {
+                        Piece::Text(range) => spans.extend(draw(range.clone()).spans),
+                        Piece::Marker { label, .. } => spans.push(Span::styled(label.clone(), app.theme.hint)),
                     }
-                    pos += 1;
-                }
-                if pos < chars.len() {
-                    pos += 1; // Include closing quote
-                }
-                let string: String = chars[start..pos].iter().collect();
-                spans.push(Span::styled(string, Style::default().fg(Color::Green)));
-            }
-            '0'..='9' | '-' => {
-                // Number
-                let start = pos;
-                while pos < chars.len() && 
-                      (chars[pos].is_ascii_digit() || 
-                       chars[pos] == '.' || 
-                       chars[pos] == '-' ||
-                       chars[pos] == 'e' ||
-                       chars[pos] == 'E' ||
-                       chars[pos] == '+') {
-                    pos += 1;
-                }
-                let number: String = chars[start..pos].iter().collect();
-                spans.push(Span::styled(number, Style::default().fg(Color::Yellow)));
-            }
-            't' if pos + 4 <= chars.len() => {
-                let word: String = chars[pos..pos+4].iter().collect();
-                if word == "true" {
-                    spans.push(Span::styled(word, Style::default().fg(Color::Cyan)));
-                    pos += 4;
-                } else {
-                    spans.push(Span::styled(ch.to_string(), Style::default().fg(Color::White)));
-                    pos += 1;
                 }
+                if line_idx == app.cursor.line {
+                    cursor_pieces = Some(pieces);
+                }
+                Line::from(spans)
             }
-            'f' if pos + 5 <= chars.len() => {
-                let word: String = chars[pos..pos+5].iter().collect();
-                if word == "false" {
-                    spans.push(Span::styled(word, Style::default().fg(Color::Cyan)));
-                    pos += 5;
-                } else {
-                    spans.push(Span::styled(ch.to_string(), Style::default().fg(Color::White)));
-                    pos += 1;
+            None => draw(start..end),
+        };
+        
+        if app.wrap {
+            for (row, segment) in split_line(line, width).into_iter().enumerate() {
+                if lines.len() == height {
+                    break;
                 }
+                lines.push(segment);
+                row_lines.push((line_idx, false, first_row + row));
             }
-            'n' if pos + 4 <= chars.len() => {
-                let word: String = chars[pos..pos+4].iter().collect();
-                if word == "null" {
-                    spans.push(Span::styled(word, Style::default().fg(Color::Gray)));
-                    pos += 4;
-                } else {
-                    spans.push(Span::styled(ch.to_string(), Style::default().fg(Color::White)));
-                    pos += 1;
+        } else {
+            lines.push(line);
+            row_lines.push((line_idx, false, 0));
+        }
+        line_idx += 1;
+    }
+    app.line_cache = cache;
+    if active {
+        app.view_lines = view_lines;
+    }
+    
+    if active {
+        app.screen.text = content_area;
+        app.screen.rows = row_lines.iter().map(|&(line, _, wrapped_row)| ScreenRow { line, wrapped_row }).collect();
+        app.screen.start_col = app.viewport.start_col;
+        app.screen.wrap = app.wrap;
+    }
+    
+    let cursor_segment = if app.wrap { app.cursor.col / width } else { 0 };
+    let cursor_row = row_lines.iter()
+        .position(|&(l, _, row)| l == app.cursor.line && row == cursor_segment)
+        .or_else(|| row_lines.iter().position(|&(l, _, _)| l == app.cursor.line));
+    
+    // Line numbers: wrapped continuation rows are left blank, and
+    // relative numbers count buffer lines rather than rows. The sign column
+    // after them shows the placed signs, with :diff changes, duplicate keys
+    // and bookmarks offered against them.
+    if let Some(gutter) = gutter {
+        let line_rank = |row: usize| row_lines[..row].iter().filter(|&&(_, _, r)| r == 0).count();
+        let cursor_rank = cursor_row.map(line_rank);
+        let shown_lines = app.viewport.start_line..line_idx;
+        let mut signs = app.signs.lines(&app.buffer, shown_lines.clone());
+        if gutter.signs {
+            for (lines, kind) in diff_signs(app, shown_lines.clone()) {
+                let kind = SignKind::Diff(kind);
+                for line in (*lines.start()).max(shown_lines.start)..=(*lines.end()).min(line_idx) {
+                    signs.offer(line, kind, kind.priority());
                 }
             }
-            ':' => {
-                spans.push(Span::styled(ch.to_string(), Style::default().fg(Color::Magenta)));
-                pos += 1;
+            let duplicate = SignKind::DuplicateKey { error: app.dupkeys == DupKeys::Error };
+            for line in duplicate_lines(app, shown_lines.clone()) {
+                signs.offer(line, duplicate, duplicate.priority());
             }
-            _ => {
-                spans.push(Span::styled(ch.to_string(), Style::default().fg(Color::White)));
-                pos += 1;
+            for line in bookmark_lines(app, shown_lines.clone()) {
+                signs.offer(line, SignKind::Bookmark, SignKind::Bookmark.priority());
             }
         }
+        let gutter_lines: Vec<Line> = row_lines.iter().enumerate().map(|(row, &(line, _, wrapped))| {
+            if wrapped > 0 {
+                return Line::from(" ".repeat(gutter.width as usize));
+            }
+            let style = if Some(row) == cursor_row {
+                app.theme.current_line_number
+            } else {
+                app.theme.line_number
+            };
+            let label = Span::styled(gutter.label(line, line_rank(row), cursor_rank), style);
+            match signs.get(line).filter(|_| gutter.signs) {
+                Some(kind) => Line::from(vec![label, Span::styled(format!("{} ", kind.glyph()), kind.style(&app.theme))]),
+                None if gutter.signs => Line::from(vec![label, Span::raw("  ")]),
+                None => Line::from(label),
+            }
+        }).collect();
+        frame.render_widget(Paragraph::new(gutter_lines), gutter_area);
     }
     
-    Line::from(spans)
+    // Lines from the top of the view to the last one on screen
+    let view_lines = line_idx.saturating_sub(app.viewport.start_line).max(1);
+    
+    let paragraph = Paragraph::new(lines);
+    frame.render_widget(paragraph, content_area);
+    
+    // Scrollbar over the right border, so it never costs a text column,
+    // with ticks at structural errors found so far
+    if app.show_scrollbar && line_count > 0 {
+        let track = Rect { x: area.x + area.width - 1, ..inner_area };
+        let error_lines: Vec<usize> = app.structural_index.iter()
+            .flat_map(|index| index.errors().iter().take(1000))
+            .map(|error| app.buffer.byte_offset_to_line(error.offset))
+            .collect();
+        scrollbar::render(
+            frame.buffer_mut(),
+            track,
+            (app.viewport.start_line, view_lines, line_count),
+            &error_lines,
+            app.theme.line_number,
+            app.theme.current_line_number,
+            app.theme.error,
+        );
+    }
+    
+    // Set cursor position for visibility
+    // Calculate cursor position relative to viewport
+    if let Some(cursor_screen_line) = cursor_row.filter(|_| active) {
+        // Cursor is visible in viewport
+        let cursor_col = if app.wrap {
+            app.cursor.col % width
+        } else {
+            app.cursor.col.saturating_sub(app.viewport.start_col)
+        };
+        // Substitutions wider than the character they stand for
+        let cursor_col = if let Some(pieces) = &cursor_pieces {
+            let text = app.buffer.get_line(app.cursor.line);
+            let offset = char_to_byte(&text, app.cursor.col);
+            let measure = |run: &str| run.chars().count() + if app.list { list::extra_width(run) } else { 0 };
+            elide::column_of(pieces, &text, offset, measure).unwrap_or(cursor_col)
+        } else if app.list && !app.wrap {
+            let text = app.buffer.get_line(app.cursor.line);
+            let from = char_to_byte(&text, app.viewport.start_col);
+            let to = from + char_to_byte(&text[from..], cursor_col);
+            cursor_col + list::extra_width(&text[from..to])
+        } else {
+            cursor_col
+        };
+        let cursor_x = content_area.x + cursor_col as u16;
+        let cursor_y = inner_area.y + cursor_screen_line as u16;
+        frame.set_cursor_position((cursor_x, cursor_y));
+        if let Some(completion) = app.completion.as_mut() {
+            // Lined up under the key's text
+            let typed = app.buffer.slice(completion.start..app.cursor.byte_offset).chars().count() as u16;
+            let x = cursor_x.saturating_sub(typed).max(content_area.x);
+            render_completion(frame, &mut completion.picker, &app.theme, content_area, (x, cursor_y));
+        }
+    }
+    
+    if let Some(preview) = app.preview.as_ref().filter(|_| active) {
+        let cursor_y = cursor_row.map(|row| inner_area.y + row as u16);
+        render_preview(frame, preview, &app.theme, content_area, cursor_y);
+    }
+    view_lines
 }
 
 fn render_ui(
@@ -776,149 +6342,211 @@ fn render_ui(
     terminal.draw(|frame| {
         let size = frame.area();
         
-        // Split into main area and status bar
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Min(1),
-                Constraint::Length(1),
-            ])
-            .split(size);
-
-        // Main content area with border
-        let main_block = Block::default()
-            .borders(Borders::ALL)
-            .title("Jim - JSON Interactive Manager v0.1.0");
-        
-        let inner_area = main_block.inner(chunks[0]);
-        frame.render_widget(main_block, chunks[0]);
-        
-        // Update viewport height to match actual terminal size
-        let old_height = app.viewport.height;
-        app.viewport.height = inner_area.height as usize;
+        // Line numbers take the left edge of the text pane
+        let line_count = app.buffer.line_count();
+        let gutter = app.gutter();
+        let Some(layout) = ScreenLayout::new(size, app.show_tree, app.list_height(), gutter.map_or(0, |g| g.width)) else {
+            app.screen = ScreenMap::default();
+            render_too_small(frame, size);
+            return;
+        };
         
-        // If height changed or cursor out of view, update viewport
-        if old_height != app.viewport.height || 
-           app.cursor.line < app.viewport.start_line ||
-           app.cursor.line >= app.viewport.start_line + app.viewport.height {
-            let cursor_line = app.cursor.line;
-            let viewport_height = app.viewport.height;
-            if cursor_line < app.viewport.start_line {
-                app.viewport.start_line = cursor_line;
-            } else if cursor_line >= app.viewport.start_line + viewport_height {
-                app.viewport.start_line = cursor_line.saturating_sub(viewport_height - 1);
-            }
-        }
-
-        // Render buffer content with syntax highlighting
-        let content = app.buffer.get_visible_lines(
-            app.viewport.start_line,
-            inner_area.height as usize,
-        );
+        // Outline sidebar to the left of the text
+        app.screen.tree = None;
+        match layout.tree {
+            Some(tree_area) => render_tree(frame, app, tree_area),
+            // Squeezed out by a narrow terminal
+            None if app.focus == Focus::Tree => app.focus = Focus::Text,
+            None => {}
+        }
+        // Location list across the bottom, or likewise squeezed out
+        match layout.list {
+            Some(list_area) => render_loclist(frame, app, list_area),
+            None if app.focus == Focus::List => app.focus = Focus::Text,
+            None => {}
+        }
         
-        // Apply syntax highlighting if we have content
-        let lines: Vec<Line> = if app.structural_index.is_some() {
-            content.lines().map(|line| {
-                colorize_json_line(line)
-            }).collect()
+        // Text windows, the focused one last so it sets the cursor; the
+        // help pane takes their place while it's open
+        let view_lines = if let Some(help) = &app.help {
+            app.screen.windows = Vec::new();
+            app.screen.text = Rect::default();
+            render_help(frame, help, &app.theme, layout.text_block);
+            app.viewport.height
         } else {
-            content.lines().map(|line| Line::from(line.to_string())).collect()
+            let window_areas = app.windows.areas(layout.text_block);
+            app.screen.windows = if window_areas.len() > 1 { window_areas.clone() } else { Vec::new() };
+            let active = app.windows.active();
+            let stamp = app.color_stamp();
+            app.line_cache.begin_frame(stamp);
+            for (i, &area) in window_areas.iter().enumerate() {
+                if i != active {
+                    app.with_window(i, |app| render_window(frame, app, area, gutter, false));
+                }
+            }
+            let view_lines = render_window(frame, app, window_areas[active], gutter, true);
+            app.line_cache.end_frame();
+            view_lines
         };
-        
-        let paragraph = Paragraph::new(lines);
-        frame.render_widget(paragraph, inner_area);
-        
-        // Set cursor position for visibility
-        // Calculate cursor position relative to viewport
-        let cursor_screen_line = app.cursor.line.saturating_sub(app.viewport.start_line);
-        if cursor_screen_line < inner_area.height as usize {
-            // Cursor is visible in viewport
-            let cursor_x = inner_area.x + app.cursor.col as u16;
-            let cursor_y = inner_area.y + cursor_screen_line as u16;
-            frame.set_cursor_position((cursor_x, cursor_y));
+        if let Some(editor) = &app.string_editor {
+            render_string_editor(frame, editor, &app.theme, layout.text_block);
         }
-
+        if let Some(picker) = app.picker.as_mut() {
+            render_picker(frame, picker, &app.theme, layout.text_block);
+        }
+        
         // Status bar
-        let status_text = if app.buffer.is_empty() {
+        let status_text = if app.buffer.is_empty() && app.buffer.path().is_none() {
             format!(
-                " No file loaded | Press 'q' to quit | F12: perf | FPS: {:.1}",
+                " No file loaded | F1: help | :q to quit | F12: perf | FPS: {:.1}",
                 app.fps
             )
         } else {
+            // Get node type and size if available
+            let node_info = app.node_summary();
+            let path = if app.statusline.contains("%p") { app.cursor_path() } else { String::new() };
+            let item = if app.statusline.contains("%i") { app.item_position() } else { String::new() };
+            
             // Get file info
             let file_name = app.buffer.path()
                 .and_then(|p| p.file_name())
                 .and_then(|n| n.to_str())
                 .unwrap_or("<unknown>");
-            let file_size = format_size(app.buffer.get_file_size());
-            
-            // Get node type if available
-            let node_info = if let (Some(node_id), Some(ref index)) = (app.current_node_id, &app.structural_index) {
-                if let Some(node) = index.nodes().get(node_id) {
-                    format!(" | {:?}", node.kind)
-                } else {
-                    String::new()
-                }
-            } else {
-                String::new()
-            };
-            
-            // Cursor position
-            let cursor_pos = format!("{}:{}", app.cursor.line + 1, app.cursor.col + 1);
+            let file_size = app.numbers.size(app.buffer.get_file_size());
             
             // Mode indicator (Phase 1)
             let mode_indicator = app.mode.display();
-            let mode_str = if !mode_indicator.is_empty() {
+            let mut mode_str = if !mode_indicator.is_empty() {
                 format!(" {} |", mode_indicator)
             } else {
                 String::new()
             };
+            // An operator waiting for its motion, e.g. "2d3"
+            if let Some(pending) = app.pending_operator.as_ref().filter(|_| app.mode == Mode::Normal) {
+                mode_str.push_str(&format!(" {} |", app.normal_mode_handler.pending_display(pending)));
+            }
             
             // Modified indicator
-            let modified = if app.buffer.is_modified() { " [+]" } else { "" };
+            let modified = if app.buffer.is_truncated() {
+                " [truncated]"
+            } else if app.buffer.converted_from() == Some("YAML") {
+                " [converted from YAML]"
+            } else {
+                match (app.buffer.is_modified(), app.buffer.is_new_file()) {
+                    (true, true) => " [+] [New File]",
+                    (true, false) => " [+]",
+                    (false, true) => " [New File]",
+                    (false, false) => "",
+                }
+            };
             
-            // If loading a large file, show loading progress
-            let mut progress_suffix = String::new();
-            if app.buffer.load_in_progress.load(std::sync::atomic::Ordering::Relaxed) {
-                let pct = app.buffer.load_progress.load(std::sync::atomic::Ordering::Relaxed);
-                let bar_len = 10usize;
-                let filled = ((pct as usize * bar_len) / 100).min(bar_len);
-                let mut bar = String::new();
-                for i in 0..bar_len {
-                    if i < filled { bar.push('#'); } else { bar.push('-'); }
-                }
-                progress_suffix = format!(" | Loading: [{}] {}%", bar, pct);
-            }
-            // If a background save is in progress, show a small progress bar
-            else if app.buffer.is_saving() {
-                let pct = app.buffer.save_progress_percent();
-                let bar_len = 10usize;
-                let filled = ((pct as usize * bar_len) / 100).min(bar_len);
-                let mut bar = String::new();
-                for i in 0..bar_len {
-                    if i < filled { bar.push('#'); } else { bar.push('-'); }
-                }
-                progress_suffix = format!(" | Saving: [{}] {}%", bar, pct);
-            }
+            // Background work: a spinner and how far the latest has got
+            let progress_suffix = app.tasks.status(Instant::now()).map(|status| format!(" | {}", status)).unwrap_or_default();
 
-            format!(
-                " {}{} ({}) | {}:{} | {}{} |{} FPS: {:.1}{} | F12: perf",
-                file_name,
-                modified,
+            // Record position in JSON Lines files
+            let record = match (app.format, &app.structural_index) {
+                (DocumentFormat::JsonLines, Some(index)) => {
+                    let total = if app.fully_indexed() {
+                        app.numbers.count(index.root_count())
+                    } else {
+                        format!("~{}", app.numbers.count(app.buffer.line_count()))
+                    };
+                    index.node_id_at(app.cursor.byte_offset)
+                        .and_then(|id| index.root_of(id))
+                        .map(|(n, _)| format!(" | record {} of {}", app.numbers.count(n + 1), total))
+                        .unwrap_or_default()
+                }
+                (_, Some(index)) if app.split_docs => {
+                    let total = app.numbers.count(index.root_count());
+                    let total = if app.fully_indexed() { total } else { format!("{}+", total) };
+                    index.node_id_at(app.cursor.byte_offset)
+                        .and_then(|id| index.root_of(id))
+                        .map(|(n, _)| format!(" | document {} of {}", app.numbers.count(n + 1), total))
+                        .unwrap_or_default()
+                }
+                _ => String::new(),
+            };
+
+            // Structural error count, after a mark when the index is behind the text
+            let errors = match app.index_staleness().and_then(Staleness::badge) {
+                Some(badge) => format!(" | {}", badge),
+                None => String::new(),
+            };
+            // Text that isn't JSON at all counted apart from grammar errors
+            let (syntax, grammar) = app.structural_index.as_ref()
+                .map_or((0, 0), |i| (i.syntax_error_count(), i.errors().len() - i.syntax_error_count()));
+            let errors = match syntax {
+                0 => errors,
+                1 => errors + " | 1 syntax error",
+                n => format!("{} | {} syntax errors", errors, app.numbers.count(n)),
+            };
+            let errors = match grammar {
+                0 => errors,
+                1 => errors + " | 1 error",
+                n => format!("{} | {} errors", errors, app.numbers.count(n)),
+            };
+            let errors = match app.diagnostics.len() {
+                _ if app.schema.is_none() => errors,
+                0 if app.schema_job.is_some() => errors + " | checking schema…",
+                0 => errors + " | ✓ schema",
+                1 => errors + " | ✗ 1 schema error",
+                n => format!("{} | ✗ {} schema errors", errors, app.numbers.count(n)),
+            };
+            let errors = match &app.index_window {
+                Some(window) if window.span.contains(&app.cursor.byte_offset) => errors + " | ≈ indexed nearby",
+                _ => errors,
+            };
+            let errors = match app.guard.problem() {
+                Some(problem) => format!("{} | ⚠ {}", errors, problem),
+                None => errors,
+            };
+            let errors = match &app.diff {
+                Some((_, diff)) if diff.changes.len() == 1 => errors + " | 1 change",
+                Some((_, diff)) => format!("{} | {} changes", errors, app.numbers.count(diff.changes.len())),
+                None => errors,
+            };
+
+            let info = StatusInfo {
+                file_name: file_name.to_string(),
+                modified: modified.to_string(),
                 file_size,
-                app.viewport.start_line + 1,
-                app.buffer.line_count(),
-                cursor_pos,
-                node_info,
-                mode_str,
-                app.fps,
-                progress_suffix
-            )
+                path,
+                line: app.cursor.line + 1,
+                col: app.cursor.col + 1,
+                offset: app.cursor.byte_offset,
+                top_line: app.viewport.start_line + 1,
+                line_count,
+                view_position: scrollbar::position_label(app.viewport.start_line, view_lines, line_count),
+                percent: (app.cursor.line + 1) * 100 / line_count.max(1),
+                node: node_info,
+                item,
+                record,
+                errors,
+                mode: mode_str,
+                fps: app.fps,
+                progress: progress_suffix,
+                numbers: app.numbers,
+            };
+            let template = match app.statusline.as_str() {
+                DEFAULT_STATUSLINE if app.buffer.has_split_lines() => SPLIT_LINES_STATUSLINE,
+                template => template,
+            };
+            format_status(template, &info, layout.status.width as usize)
         };
         
         // Override status with command line or message if present
-        let (final_status_text, cursor_in_status) = if matches!(app.mode, Mode::Command) {
-            let cmd_text = format!(":{}", app.command_mode_handler.command_line);
+        let (final_status_text, cursor_in_status) = if app.string_editor.is_some() {
+            (" Edit string | C-s save | Esc cancel".to_string(), None)
+        } else if app.picker.is_some() {
+            (" Pick | type to filter | ↑/↓ or C-n/C-p select | Enter go | Esc cancel".to_string(), None)
+        } else if let Some(help) = &app.help {
+            match &help.query {
+                Some(query) => (format!("/{}", query), Some(query.len() + 1)),
+                None if app.inspect.is_some() => (format!(" {} | j/k scroll | / search | r refresh | q close", help.title), None),
+                None => (" Help | j/k scroll | / search | n/N next match | q close".to_string(), None),
+            }
+        } else if matches!(app.mode, Mode::Command) {
+            let cmd_text = format!("{}{}", app.command_mode_handler.prompt, app.command_mode_handler.command_line);
             let cursor_pos = cmd_text.len();
             (cmd_text, Some(cursor_pos))
         } else if let Some(ref msg) = app.message {
@@ -927,124 +6555,695 @@ fn render_ui(
             (status_text, None)
         };
         
-        let status = Paragraph::new(final_status_text)
-            .style(Style::default().bg(Color::DarkGray).fg(Color::White));
-        frame.render_widget(status, chunks[1]);
+        let status_style = if app.message_is_error && app.message.is_some() && !matches!(app.mode, Mode::Command) {
+            app.theme.message_error
+        } else {
+            app.theme.status_bar
+        };
+        let status = match &app.prompt {
+            Some(prompt) => {
+                let key_style = app.theme.status_bar.patch(app.theme.key).add_modifier(Modifier::BOLD);
+                let spans: Vec<Span> = prompt.pieces()
+                    .into_iter()
+                    .map(|(text, key)| if key { Span::styled(text, key_style) } else { Span::raw(text) })
+                    .collect();
+                Paragraph::new(Line::from(spans)).style(app.theme.status_bar)
+            }
+            None => Paragraph::new(final_status_text).style(status_style),
+        };
+        frame.render_widget(status, layout.status);
         
         // Set cursor in status bar if in command mode
         if let Some(cursor_pos) = cursor_in_status {
-            let cursor_x = chunks[1].x + cursor_pos as u16;
-            let cursor_y = chunks[1].y;
+            let cursor_x = layout.status.x + (cursor_pos as u16).min(layout.status.width - 1);
+            let cursor_y = layout.status.y;
             frame.set_cursor_position((cursor_x, cursor_y));
         }
         
+        // What can follow the keys typed so far, until the sequence is done
+        let next = app.pending_continuations();
+        if !next.is_empty() {
+            let above_status = Rect { height: layout.status.y - size.y, ..size };
+            render_key_hint(frame, &app.pending_sequence(), &next, &app.theme, above_status);
+        }
+        
         // Performance overlay (toggle with F12)
         if app.show_performance {
-            let perf_area = ratatui::layout::Rect {
-                x: size.width.saturating_sub(35),
-                y: 2,
-                width: 33,
-                height: 8,
-            };
-            
-            let avg_frame_time = if !app.frame_times.is_empty() {
-                let sum: Duration = app.frame_times.iter().sum();
-                sum.as_micros() as f64 / app.frame_times.len() as f64 / 1000.0
-            } else {
-                0.0
-            };
-            
-            let p99_frame_time = if !app.frame_times.is_empty() {
-                let mut sorted = app.frame_times.clone();
-                sorted.sort();
-                let idx = (sorted.len() as f64 * 0.99) as usize;
-                sorted.get(idx).unwrap_or(&Duration::ZERO).as_micros() as f64 / 1000.0
-            } else {
-                0.0
-            };
+            let stats = app.frame_times.frame_stats();
+            app.perf.refresh(Instant::now(), || PerfSample {
+                resident: perf::resident_memory(),
+                cache_hits: app.line_cache.hits(),
+                cache_misses: app.line_cache.misses(),
+                cache_lines: app.line_cache.len(),
+                index_bytes: app.structural_index.as_ref().map_or(0, |index| index.memory_usage()),
+                index_runs: app.index_frontier.runs(),
+                index_rebuilds: app.index_frontier.rebuilds(),
+                pending_edits: app.buffer.pending_edits(),
+                undo_depth: app.undo_stack.undo_count(),
+                last_save: app.buffer.last_save_duration(),
+            });
+            let sample = app.perf.sample();
             
-            let perf_text = vec![
-                Line::from(vec![Span::styled(" Performance ", Style::default().fg(Color::Yellow))]),
+            let mut perf_text = vec![
+                Line::from(vec![Span::styled(" Performance ", app.theme.current_line_number)]),
                 Line::from(""),
                 Line::from(format!(" FPS: {:.1}", app.fps)),
-                Line::from(format!(" Frame: {:.2}ms avg", avg_frame_time)),
-                Line::from(format!(" Frame: {:.2}ms p99", p99_frame_time)),
-                Line::from(format!(" Nodes: {}", app.structural_index.as_ref().map(|i| i.len()).unwrap_or(0))),
-                Line::from(format!(" Index: {:.3}s", app.index_build_time)),
+                Line::from(format!(" Frame: {:.2}ms avg", stats.avg_ms)),
+                Line::from(format!(" Frame: {:.2}ms p99", stats.p99_ms)),
+                Line::from(format!(" Nodes: {}", app.numbers.count(app.structural_index.as_ref().map(|i| i.len()).unwrap_or(0)))),
+                Line::from(format!(" Index: {:.3}s, {}", app.index_build_time, app.numbers.size(sample.index_bytes))),
+                Line::from(format!(" Index runs: {}, rebuilds: {}", sample.index_runs, sample.index_rebuilds)),
+                Line::from(match sample.resident {
+                    Some(resident) if resident.peak => format!(" Memory: {} peak", app.numbers.size(resident.bytes)),
+                    Some(resident) => format!(" Memory: {}", app.numbers.size(resident.bytes)),
+                    None => " Memory: n/a".to_string(),
+                }),
+                Line::from(match app.perf.hit_rate() {
+                    Some(rate) => format!(" Cache: {:.1}% hit, {} lines", rate * 100.0, app.numbers.count(sample.cache_lines)),
+                    None => format!(" Cache: {} lines", app.numbers.count(sample.cache_lines)),
+                }),
+                Line::from(format!(" Pending edits: {}", app.numbers.count(sample.pending_edits))),
+                Line::from(format!(" Undo depth: {}", app.numbers.count(sample.undo_depth))),
             ];
+            if let Some(time) = sample.last_save {
+                perf_text.push(Line::from(format!(" Last save: {:.2}s", time.as_secs_f64())));
+            }
+            // What a background save holds in memory besides the buffer
+            if let Some(bytes) = app.buffer.save_footprint() {
+                perf_text.push(Line::from(format!(" Save: {} held", app.numbers.size(bytes))));
+            }
+            
+            let perf_area = ratatui::layout::Rect {
+                x: size.width.saturating_sub(35),
+                y: 2,
+                width: 33,
+                height: perf_text.len() as u16 + 2,
+            }.intersection(size);
             
             let perf_block = Block::default()
                 .borders(Borders::ALL)
-                .style(Style::default().bg(Color::Black).fg(Color::White));
+                .style(app.theme.popup);
             let perf_paragraph = Paragraph::new(perf_text).block(perf_block);
             frame.render_widget(perf_paragraph, perf_area);
         }
+        
+        if !app.caps.unicode {
+            capabilities::asciify(frame.buffer_mut());
+        }
     })?;
     
     Ok(())
 }
 
-fn run(mut app: App, mut terminal: Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+/// Draw only when something may have changed the screen, and otherwise
+/// sleep until input or the next timer. With --keys the script is typed
+/// instead of reading the terminal, and without a terminal (--headless)
+/// nothing is drawn.
+fn run(mut app: App, mut terminal: Option<Terminal<CrosstermBackend<Stdout>>>) -> Result<Vec<String>> {
+    let mut mouse_captured = false;
     loop {
         let frame_start = Instant::now();
         
-        // If a background save just finished, finalize (reload mmap)
-        if let Err(e) = app.buffer.finalize_save() {
-            // show message (non-fatal)
-            eprintln!("Failed to finalize save: {:?}", e);
+        // Follow :set mouse, giving clicks back to the terminal when off
+        if terminal.is_some() && app.mouse != mouse_captured {
+            if app.mouse {
+                stdout().execute(EnableMouseCapture)?;
+            } else {
+                stdout().execute(DisableMouseCapture)?;
+            }
+            mouse_captured = app.mouse;
         }
-
-        app.update_fps();
-        render_ui(&mut terminal, &mut app)?;
         
-        let frame_time = frame_start.elapsed();
-        app.frame_times.push(frame_time);
-        if app.frame_times.len() > 60 {
-            app.frame_times.remove(0);
+        // If a background save just finished, finalize (reload mmap), or
+        // say why it failed; a :wq waiting on it quits now
+        app.poll_save();
+        // A :diffsaved listing is stale once the file is written again
+        if !app.buffer.is_modified() && app.diff.as_ref().is_some_and(|(path, _)| path == "the last save") {
+            app.diff = None;
+        }
+        app.save_undofile();
+        if app.buffer.check_truncated() {
+            app.show_error("File was truncated on disk; editing is off (:e! reloads it)".to_string());
+        }
+
+        // Merge structural index chunks built in the background
+        app.poll_parser();
+        app.poll_reindex();
+        app.check_pending_timeout()?;
+        if let Some(terminal) = terminal.as_mut() {
+            app.send_clipboard(terminal);
+        }
+        if let Err(e) = app.poll_pipe() {
+            app.show_error(e.to_string());
+        }
+        app.poll_schema();
+        app.poll_filter();
+        app.poll_extract();
+        app.poll_diff();
+        app.poll_checksum();
+        app.poll_shape();
+        if let Err(e) = app.poll_convert() {
+            app.show_error(e.to_string());
+        }
+        app.sync_tasks();
+        app.expire_message();
+
+        let busy = app.is_busy();
+        app.redraw.set_busy(busy);
+        if let Some(terminal) = terminal.as_mut().filter(|_| app.redraw.is_due(frame_start)) {
+            app.prefetcher.around(&app.buffer, app.viewport.start_line, app.viewport.height);
+            app.update_fps();
+            render_ui(terminal, &mut app)?;
+            app.redraw.drew(frame_start);
+            
+            let frame_time = frame_start.elapsed();
+            app.frame_times.push(frame_time);
         }
 
         if app.should_quit {
+            // A save still being written, as after :wq, finishes first; if
+            // it fails the edits would be lost, so jim stays open
+            while app.buffer.is_saving() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            if let Err(e) = app.buffer.finalize_save() {
+                app.should_quit = false;
+                app.show_error(e.to_string());
+                app.redraw.mark();
+                continue;
+            }
+            app.quit_undofile();
+            app.quit_session();
             break;
         }
 
-        // Poll for events with timeout to maintain ~60fps
-        if event::poll(Duration::from_millis(16))? {
-            let event = event::read()?;
-            app.handle_event(event)?;
+        // --apply's steps go one at a time, each after the background
+        // work the one before started
+        if app.replay.is_some() {
+            if busy {
+                std::thread::sleep(Duration::from_millis(1));
+            } else {
+                app.replay_step()?;
+                app.redraw.mark();
+            }
+            continue;
+        }
+
+        // A script's next key waits for background work it may depend
+        // on, as typing would; once they've all run, jim quits
+        if let Some(script) = app.script.as_mut() {
+            if busy {
+                std::thread::sleep(Duration::from_millis(1));
+            } else if let Some(key) = script.pop_front() {
+                app.handle_key(key)?;
+                app.redraw.mark();
+            } else if app.pending_since.is_some() {
+                // A sequence that may be a mapping runs once it times out
+                std::thread::sleep(Duration::from_millis(1));
+            } else {
+                app.should_quit = true;
+            }
+            continue;
+        }
+
+        // Wait for input, a frame's time while busy, or until a timer
+        let deadlines = app.deadlines();
+        if event::poll(app.redraw.timeout(Instant::now(), deadlines.iter().copied()))? {
+            // Take in everything already queued before drawing again
+            let events = queued_events(event::read()?, || {
+                Ok(if event::poll(Duration::ZERO)? { Some(event::read()?) } else { None })
+            })?;
+            app.handle_events(events)?;
+            app.redraw.mark();
+        } else {
+            app.redraw.wake(Instant::now(), deadlines);
+        }
+    }
+
+    Ok(app.script_errors)
+}
+
+/// `first` and the events `next` has ready after it, up to a frame's worth
+fn queued_events(first: Event, mut next: impl FnMut() -> Result<Option<Event>>) -> Result<Vec<Event>> {
+    let mut events = vec![first];
+    while events.len() < EVENTS_PER_FRAME {
+        match next()? {
+            Some(event) => events.push(event),
+            None => break,
+        }
+    }
+    Ok(events)
+}
+
+/// --dump-index / --outline: write the report for `file` to stdout
+/// without starting the editor, with progress on stderr for big files
+fn print_report(mode: &str, file: &std::path::Path, max_depth: u32) -> Result<()> {
+    use std::io::IsTerminal;
+    let (input, size) = dump::open(file)?;
+    let show_progress = size >= 10 * 1024 * 1024 && std::io::stderr().is_terminal();
+    let mut last = None;
+    let progress = |read: usize| {
+        let percent = (read as u64 * 100 / size.max(1)) as usize;
+        if show_progress && last != Some(percent) {
+            last = Some(percent);
+            eprint!("\rReading {}… {}%", file.display(), percent);
+        }
+    };
+    let mut out = std::io::BufWriter::new(stdout().lock());
+    let result = if mode == "--outline" {
+        dump::outline(input, &mut out, max_depth, progress)
+    } else {
+        dump::dump_index(input, &mut out, progress).map(|_| ())
+    };
+    if show_progress {
+        eprintln!();
+    }
+    // A closed pipe (`| head`) just ends the output
+    match result.and_then(|()| Ok(out.flush()?)) {
+        Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) => Ok(()),
+        result => result,
+    }
+}
+
+/// --checksum: print `file`'s SHA-256 as sha256sum does, with progress on
+/// stderr for big files
+fn print_checksum(file: &std::path::Path) -> Result<()> {
+    use std::io::IsTerminal;
+    let progress = Arc::new(checksum::Progress::default());
+    let job = {
+        let progress = progress.clone();
+        let file = file.to_path_buf();
+        std::thread::spawn(move || checksum::of_file(&file, &progress))
+    };
+    let show_progress = std::io::stderr().is_terminal();
+    let mut shown = false;
+    while !job.is_finished() {
+        std::thread::sleep(Duration::from_millis(100));
+        if show_progress && progress.total.load(std::sync::atomic::Ordering::Relaxed) >= 10 * 1024 * 1024 {
+            eprint!("\rHashing {}… {}%", file.display(), progress.percent());
+            shown = true;
         }
     }
+    if shown {
+        eprintln!();
+    }
+    let checksum = job.join().map_err(|_| anyhow::anyhow!("Checksum failed"))??;
+    println!("{}  {}", checksum.hex(), file.display());
+    Ok(())
+}
+
+/// --bench-open: load `file`, index all of it and time `ops` scrolls and
+/// structural moves through the editor as it runs, without a terminal,
+/// then print the timings as one JSON object. Sessions and undo files are
+/// left alone so runs start the same way.
+fn bench_open(file: &str, ops: usize) -> Result<()> {
+    let mut app = App::headless();
+
+    let start = Instant::now();
+    app.load_file(file)?;
+    let load_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let start = Instant::now();
+    let lines = app.buffer.line_count();
+    app.request_structural_index(lines)?;
+    while app.index_in_flight.is_some() || !app.index_frontier.covers(lines, lines) {
+        app.poll_parser();
+        std::thread::sleep(Duration::from_micros(100));
+    }
+    let index_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let nodes = app.structural_index.as_ref().map_or(0, |index| index.len());
+
+    // Screens spread through the file, in an order that jumps about
+    let ops = ops.max(1);
+    let height = 50;
+    let start = Instant::now();
+    for i in 0..ops {
+        let line = i.wrapping_mul(7919) % lines.max(1);
+        std::hint::black_box(app.buffer.get_visible_lines(line, height));
+    }
+    let visible_lines_us = start.elapsed().as_secs_f64() * 1e6 / ops as f64;
+
+    // From places spread through the file, a sibling and a parent move
+    let len = app.buffer.len_bytes().max(1);
+    let start = Instant::now();
+    for i in 0..ops {
+        app.move_cursor_to_offset(i.wrapping_mul(104_729) % len);
+        app.current_node_id = None;
+        app.structural_nav(StructuralNavAction::NextSibling, 1);
+        app.structural_nav(StructuralNavAction::Parent, 1);
+    }
+    let navigation_us = start.elapsed().as_secs_f64() * 1e6 / (ops * 2) as f64;
 
+    println!(
+        "{{\"bytes\": {}, \"lines\": {}, \"load_ms\": {:.3}, \"index_ms\": {:.3}, \"nodes\": {}, \"ops\": {}, \"visible_lines_us\": {:.3}, \"navigation_us\": {:.3}}}",
+        app.buffer.len_bytes(),
+        lines,
+        load_ms,
+        index_ms,
+        nodes,
+        ops,
+        visible_lines_us,
+        navigation_us
+    );
     Ok(())
 }
 
 fn main() -> Result<()> {
-    // Set up panic hook to restore terminal
-    let default_panic = std::panic::take_hook();
-    std::panic::set_hook(Box::new(move |info| {
-        let _ = disable_raw_mode();
-        let _ = stdout().execute(SetCursorStyle::DefaultUserShape);
-        let _ = stdout().execute(LeaveAlternateScreen);
-        default_panic(info);
-    }));
-
+    // jim [--theme NAME] [--config PATH] [--no-color] [+N | +/TEXT | --path PATH] [FILE]
+    // jim --dump-index FILE | --outline [--max-depth N] FILE | --checksum FILE
+    // jim --keys KEYS [--headless] [FILE]
+    // jim --apply SCRIPT FILE...
+    let mut theme_name = None;
+    let mut no_color = false;
+    let mut config_path = None;
+    let mut start = None;
+    let mut report = None;
+    let mut max_depth = u32::MAX;
+    let mut bench = None;
+    let mut bench_ops = 1000;
+    let mut arg_error = None;
+    let mut script = None;
+    let mut headless = false;
+    let mut apply = None;
+    let mut files = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(target) = arg.strip_prefix('+') {
+            start = match target.strip_prefix('/') {
+                Some(text) => Some(StartAt::Search(text.to_string())),
+                None if target.is_empty() => Some(StartAt::Line(None)),
+                None => match target.parse() {
+                    Ok(line) => Some(StartAt::Line(Some(line))),
+                    Err(_) => {
+                        arg_error = Some(format!("Bad line number: +{}", target));
+                        None
+                    }
+                },
+            };
+        } else if arg == "--path" {
+            start = args.next().map(StartAt::Path);
+        } else if let Some(path) = arg.strip_prefix("--path=") {
+            start = Some(StartAt::Path(path.to_string()));
+        } else if arg == "--dump-index" || arg == "--outline" || arg == "--checksum" {
+            report = Some(arg);
+        } else if arg == "--max-depth" || arg.starts_with("--max-depth=") {
+            let value = match arg.strip_prefix("--max-depth=") {
+                Some(value) => Some(value.to_string()),
+                None => args.next(),
+            };
+            match value.as_deref().map(str::parse) {
+                Some(Ok(depth)) => max_depth = depth,
+                _ => anyhow::bail!("--max-depth needs a number"),
+            }
+        } else if arg == "--bench-open" {
+            // Not in the usage above: for measuring, not for use
+            bench = Some(args.next().ok_or_else(|| anyhow::anyhow!("--bench-open needs a file"))?);
+        } else if arg == "--bench-ops" || arg.starts_with("--bench-ops=") {
+            let value = match arg.strip_prefix("--bench-ops=") {
+                Some(value) => Some(value.to_string()),
+                None => args.next(),
+            };
+            match value.as_deref().map(str::parse) {
+                Some(Ok(ops)) => bench_ops = ops,
+                _ => anyhow::bail!("--bench-ops needs a number"),
+            }
+        } else if arg == "--keys" || arg.starts_with("--keys=") {
+            let keys = match arg.strip_prefix("--keys=") {
+                Some(keys) => Some(keys.to_string()),
+                None => args.next(),
+            };
+            let keys = keys.ok_or_else(|| anyhow::anyhow!("--keys needs keys to type"))?;
+            script = Some(mode::parse_keys(&keys).map_err(|e| anyhow::anyhow!("--keys: {}", e))?);
+        } else if arg == "--apply" || arg.starts_with("--apply=") {
+            let path = match arg.strip_prefix("--apply=") {
+                Some(path) => Some(path.to_string()),
+                None => args.next(),
+            };
+            apply = Some(path.ok_or_else(|| anyhow::anyhow!("--apply needs a script"))?);
+        } else if arg == "--headless" {
+            headless = true;
+        } else if arg == "--theme" {
+            theme_name = args.next();
+        } else if let Some(name) = arg.strip_prefix("--theme=") {
+            theme_name = Some(name.to_string());
+        } else if arg == "--no-color" {
+            no_color = true;
+        } else if arg == "--config" {
+            config_path = args.next().map(std::path::PathBuf::from);
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config_path = Some(path.into());
+        } else {
+            files.push(arg);
+        }
+    }
+    if let Some(script) = apply {
+        return apply_script(std::path::Path::new(&script), &files, config_path);
+    }
+    let file = files.pop();
+    if let Some(file) = bench {
+        return bench_open(&file, bench_ops);
+    }
+    if let Some(mode) = report {
+        let Some(file) = file else {
+            anyhow::bail!("{} needs a file", mode);
+        };
+        if mode == "--checksum" {
+            return print_checksum(std::path::Path::new(&file));
+        }
+        return print_report(&mode, std::path::Path::new(&file), max_depth);
+    }
+    
+    // --no-color stands in for the plainest terminal, for trying it out
+    let caps = if no_color { Capabilities::plain() } else { Capabilities::detect() };
+    if !caps.color {
+        crossterm::style::force_color_output(false);
+    }
+    
+    if headless && script.is_none() {
+        anyhow::bail!("--headless needs --keys");
+    }
+    
     let mut app = App::new();
+    app.caps = caps;
+    app.script = script.map(VecDeque::from);
+    if let Some(error) = arg_error {
+        app.show_error(error);
+    }
+    let (theme, warnings) = Theme::startup(None, caps.color);
+    app.theme = theme;
+    if let Some(warning) = warnings.into_iter().next() {
+        app.show_error(format!("Theme: {}", warning));
+    }
+    
+    // Settings from the config file; an explicitly given file must exist
+    let config_path = match config_path {
+        Some(path) => Some(path),
+        None => Config::default_path().filter(|path| path.exists()),
+    };
+    if let Some(path) = config_path {
+        if let Err(e) = app.source_config(&path) {
+            app.show_error(e);
+        }
+    }
+    // The command line wins over the config file
+    if let Some(name) = theme_name {
+        if let Err(e) = app.set_option(&format!("theme={}", name)) {
+            app.show_error(e);
+        }
+    }
+    if no_color {
+        app.theme = Theme::monochrome();
+    }
     
     // Load file if provided as argument
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 1 {
-        app.load_file(&args[1])?;
+    if let Some(file) = file {
+        app.load_file(&file)?;
+        if let Some(start) = start {
+            app.start_at(start);
+        }
+    }
+    
+    if headless {
+        // Nothing may reach the terminal, not even the cursor's shape
+        app.caps.cursor_shape = false;
+        return finish_script(run(app, None));
     }
     
     // Set initial cursor style (Normal mode = block)
-    stdout().execute(SetCursorStyle::SteadyBlock)?;
+    app.set_cursor_shape(SetCursorStyle::SteadyBlock);
 
-    let terminal = setup_terminal()?;
-    let result = run(app, terminal);
+    json_tool::signals::install_sigbus_handler(caps.cursor_shape);
+    let (terminal, guard) = setup_terminal(caps.cursor_shape)?;
+    
+    // A panic puts the terminal back before its message is printed
+    let default_panic = std::panic::take_hook();
+    let restore = guard.restorer();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        default_panic(info);
+    }));
     
-    // Restore terminal and cursor
-    let terminal = setup_terminal()?;
-    stdout().execute(SetCursorStyle::DefaultUserShape)?;
-    restore_terminal(terminal)?;
+    let result = run(app, Some(terminal));
+    guard.restore();
+    finish_script(result)
+}
+
+/// --apply: run a script :script save wrote on each file in turn, headless,
+/// saying how each step went. The first step to fail stops everything,
+/// leaving its file unwritten.
+fn apply_script(script: &std::path::Path, files: &[String], config_path: Option<std::path::PathBuf>) -> Result<()> {
+    let edits = EditScript::load(script)?;
+    if files.is_empty() {
+        anyhow::bail!("--apply needs a file to edit");
+    }
+    let config_path = config_path.or_else(|| Config::default_path().filter(|path| path.exists()));
+    for file in files {
+        let errors = apply_to_file(&edits, file, config_path.as_deref())?;
+        if !errors.is_empty() {
+            return finish_script(Ok(errors));
+        }
+    }
+    Ok(())
+}
+
+/// Run `edits` on `file` and write it, returning the error that stopped
+/// them, if one did
+fn apply_to_file(edits: &EditScript, file: &str, config_path: Option<&std::path::Path>) -> Result<Vec<String>> {
+    let mut app = App::headless();
+    // Errors are collected as for --keys, for the exit status
+    app.script = Some(VecDeque::new());
+    if let Some(path) = config_path {
+        app.source_config(path).map_err(|e| anyhow::anyhow!(e))?;
+    }
+    app.load_file(file)?;
+    app.replay = Some(Replay {
+        file: file.to_string(),
+        steps: edits.steps.iter().cloned().collect(),
+        total: edits.steps.len(),
+        running: None,
+    });
+    run(app, None)
+}
+
+/// After run: the errors a --keys script met, on stderr with a failing
+/// exit status
+fn finish_script(result: Result<Vec<String>>) -> Result<()> {
+    let errors = result?;
+    for error in &errors {
+        eprintln!("jim: {}", error);
+    }
+    if !errors.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// A headless App editing `text`
+    fn app_with_text(text: &str) -> App {
+        let mut app = App::headless();
+        app.buffer = Buffer::from_text(text);
+        app
+    }
+
+    #[test]
+    fn test_queued_keys_are_handled_per_frame() {
+        let mut app = app_with_text(&"[1,\n".repeat(1500));
+        let j = Event::Key(KeyEvent::new(KeyCode::Char('j'), KeyModifiers::NONE));
+        let mut queue: VecDeque<Event> = std::iter::repeat_n(j, 1000).collect();
+        let mut frames = 0;
+        while let Some(first) = queue.pop_front() {
+            let events = queued_events(first, || Ok(queue.pop_front())).unwrap();
+            app.handle_events(events).unwrap();
+            frames += 1;
+        }
+        assert!(frames <= 1000 / EVENTS_PER_FRAME + 1);
+        assert_eq!(app.cursor.line, 1000);
+    }
+
+    #[test]
+    fn test_edit_on_truncated_file_is_refused() {
+        let path = std::env::temp_dir().join(format!("jim_test_truncated_app_{}.json", std::process::id()));
+        let record = "{\"id\": 1, \"name\": \"item\"},\n";
+        std::fs::write(&path, record.repeat(11 * 1024 * 1024 / record.len())).unwrap();
+        let mut app = App::headless();
+        app.load_file(path.to_str().unwrap()).unwrap();
+        // The indexer reads the mapped file too; it mustn't be part way
+        // through it when the file is cut short
+        let settle = |app: &mut App| {
+            while app.index_in_flight.is_some() {
+                app.poll_parser();
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        };
+        settle(&mut app);
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(4096).unwrap();
+        assert!(app.buffer.check_truncated());
+
+        // x and typing are refused with a message; jim keeps running
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        app.handle_key(key('x')).unwrap();
+        assert!(app.message_is_error);
+        app.handle_key(key('i')).unwrap();
+        app.handle_key(key('y')).unwrap();
+        assert!(!app.should_quit);
+        assert_eq!(app.buffer.get_line(0), record);
+        settle(&mut app);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_script_runs_and_reports_errors() {
+        let script = |keys: &str| {
+            let mut app = app_with_text("[\n  1,\n  2\n]\n");
+            app.script = Some(mode::parse_keys(keys).unwrap().into());
+            run(app, None).unwrap()
+        };
+        assert!(script("jdd").is_empty());
+        assert_eq!(script(":set nosuchoption<CR>jdd").len(), 1);
+    }
+
+    #[test]
+    fn test_recorded_script_applies_to_another_file() {
+        let dir = std::env::temp_dir();
+        let script_path = dir.join(format!("jim_test_script_{}.jimscript", std::process::id()));
+        let recorded = dir.join(format!("jim_test_script_recorded_{}.json", std::process::id()));
+        let other = dir.join(format!("jim_test_script_{}.json", std::process::id()));
+        let other_name = other.display().to_string();
+
+        let mut app = App::headless();
+        std::fs::write(&recorded, "{\n  \"debug\": true,\n  \"name\": \"a\",\n  \"tags\": [3, 1, 2],\n  \"n\": 5\n}\n").unwrap();
+        app.load_file(&recorded.display().to_string()).unwrap();
+        let keys = format!(
+            ":script start<CR>jwdn:rename title<CR>jw:array sort<CR>Gkkwgts:script stop<CR>:script save {}<CR>",
+            script_path.display()
+        );
+        app.script = Some(mode::parse_keys(&keys).unwrap().into());
+        assert_eq!(run(app, None).unwrap(), Vec::<String>::new());
+        let edits = EditScript::load(&script_path).unwrap();
+        let ops: Vec<String> = edits.steps.iter().map(Step::describe).collect();
+        assert_eq!(ops, ["delete $.debug", "rename $.name to \"title\"", "array sort $.tags", "convert $.n to string"]);
 
-    result
+        // The same members in another order, and a nested "name" left alone
+        std::fs::write(&other, "{\n  \"n\": 7,\n  \"tags\": [9, 8],\n  \"extra\": {\"name\": \"x\"},\n  \"name\": \"b\",\n  \"debug\": false\n}\n").unwrap();
+        assert!(apply_to_file(&edits, &other_name, None).unwrap().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(&other).unwrap(),
+            "{\n  \"n\": \"7\",\n  \"tags\": [8, 9],\n  \"extra\": {\"name\": \"x\"},\n  \"title\": \"b\"\n}\n"
+        );
+
+        // A path that isn't there stops the script at that step, unwritten
+        std::fs::write(&other, "{\"debug\": 1, \"tags\": [2, 1]}\n").unwrap();
+        let errors = apply_to_file(&edits, &other_name, None).unwrap();
+        assert_eq!(errors, [format!("{}: step 2/4 rename $.name to \"title\": No key \"name\" in $", other_name)]);
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "{\"debug\": 1, \"tags\": [2, 1]}\n");
+
+        let _ = std::fs::remove_file(&script_path);
+        let _ = std::fs::remove_file(&recorded);
+        let _ = std::fs::remove_file(&other);
+    }
 }
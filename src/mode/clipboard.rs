@@ -0,0 +1,103 @@
+//! The system clipboard, behind the `+` and `*` registers. Copies go out as
+//! an OSC 52 escape sequence, which terminals honour even over SSH, and
+//! also to a local clipboard tool when one is installed. Pasting needs the
+//! tool.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+
+/// Tools tried in order for copying, then pasting
+const COPY_TOOLS: [(&str, &[&str]); 4] = [
+    ("pbcopy", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+];
+const PASTE_TOOLS: [(&str, &[&str]); 4] = [
+    ("pbpaste", &[]),
+    ("wl-paste", &["--no-newline"]),
+    ("xclip", &["-selection", "clipboard", "-o"]),
+    ("xsel", &["--clipboard", "--output"]),
+];
+
+/// Whether `name` is a register that means the system clipboard
+pub fn is_clipboard(name: char) -> bool {
+    name == '+' || name == '*'
+}
+
+/// The OSC 52 sequence that asks the terminal to put `text` on the
+/// clipboard
+pub fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+/// Hand `text` to the first clipboard tool that runs. Errors when none is
+/// installed or it fails.
+pub fn copy_with_tool(text: &str) -> Result<()> {
+    for (program, args) in COPY_TOOLS {
+        let Ok(mut child) = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes()).with_context(|| format!("{} failed", program))?;
+        }
+        if !child.wait()?.success() {
+            bail!("{} failed", program);
+        }
+        return Ok(());
+    }
+    bail!("No clipboard tool found")
+}
+
+/// The clipboard's text from the first paste tool that runs
+pub fn paste_with_tool() -> Result<String> {
+    for (program, args) in PASTE_TOOLS {
+        let Ok(output) = Command::new(program).args(args).stderr(Stdio::null()).output() else {
+            continue;
+        };
+        if !output.status.success() {
+            bail!("{} failed", program);
+        }
+        return String::from_utf8(output.stdout).context("Clipboard isn't UTF-8 text");
+    }
+    bail!("No clipboard tool found to paste with")
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("é{}".as_bytes()), "w6l7fQ==");
+        assert_eq!(osc52(".a[1]"), "\x1b]52;c;LmFbMV0=\x07");
+    }
+}
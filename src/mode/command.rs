@@ -1,18 +1,264 @@
-use anyhow::Result;
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use super::{EditorContext, InputResult, ModeHandler, Mode};
+use super::{AppCommand, BookmarkOp, EditorContext, InputResult, ModeHandler, Mode, NodeOp, PipeRange, ScriptOp, SearchScope, StructuralNavAction};
+use crate::edit::array::ArrayOp;
+use crate::edit::ndjson::Direction;
+use crate::edit::renameall::RenameAll;
+use crate::edit::structural::Conversion;
+use crate::edit::whitespace::WhitespaceOp;
+use crate::navigation::extract::Pattern;
+use crate::parser::decode;
+
+/// Ex commands for the help screen, with their short forms. Keep in step
+/// with `execute_command`.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("w, write [file]", "save (if changed), or save as"),
+    ("w!", "save even unchanged, making the directory of a new file if it isn't there"),
+    ("{range}w[!] {file}", "write just those lines, e.g. :'<,'>w part.json or :.,+100w (! overwrites)"),
+    ("wq, x", "save and quit, once the save is written (x only saves changes)"),
+    ("q, quit", "close the window, or quit"),
+    ("q!", "quit without saving"),
+    ("e, edit[!]", "load the file again from disk (! throws away changes)"),
+    ("bd, bdelete[!]", "close the file, leaving an empty buffer (! throws away changes)"),
+    ("sp, split", "split the window"),
+    ("vs, vsplit", "split the window side by side"),
+    ("clo, close", "close the window"),
+    ("set {option}", "change an option, e.g. wrap, number, theme=light"),
+    ("so, source {file}", "load options and key mappings"),
+    ("map {lhs} {rhs}", "make keys type others in normal mode, e.g. :map gs <C-j> (:map alone lists mappings)"),
+    ("noremap {lhs} {rhs}", "the same, without mappings applying to rhs"),
+    ("unmap {lhs}", "take a mapping away"),
+    ("mksession[!] {file}", "write the cursor, view, folds and options to a file"),
+    ("source-session {file}", "go back to a session written by mksession"),
+    ("/{pattern}", "search the node under the cursor only (n/N stay in it; / leaves)"),
+    ("'<,'>/{pattern}", "search the last visual selection only"),
+    ("key {name}", "go to the next key with that name (name* for keys starting with it)"),
+    ("value {literal}", "go to the next value equal to it, e.g. 42, true or \"text\""),
+    ("palette", "pick any action or command by name (also C-p)"),
+    ("keys[!]", "pick a key of this object to go to (! any key path in the document)"),
+    ("outline", "pick a top-level key, or a group of elements, to go to"),
+    ("child {n}", "go to the nth member of the current container"),
+    ("index {n}", "go to the nth element of the array around the cursor"),
+    ("move-up, move-down", "swap the node with its previous or next sibling"),
+    ("rename[!] {name}", "change the key under the cursor"),
+    ("renameall[!] [path] {old} {new} [c]", "rename every key called old, or those under path (c asks each)"),
+    ("wrap [, wrap {key}", "put the value in an array, or an object under key"),
+    ("unwrap[!]", "replace a container with its member (! splices several)"),
+    ("convert {type}", "make the value a string, unstring, bool (toggle) or null"),
+    ("array {op}", "sort, sort! (descending), unique or count the array"),
+    ("edit-string", "edit the string under the cursor unescaped (C-s saves)"),
+    ("force", "make the node edit strictedits refused for leaving invalid JSON"),
+    ("undo-here", "undo just the latest change inside the object or array under the cursor (or a range), keeping later ones"),
+    ("validate", "report the first structural error, and list them all"),
+    ("split-docs", "read concatenated documents as records (]] / [[ move between them)"),
+    ("yamlview", "show the buffer, read as YAML, as JSON (read-only; :w file.json writes it)"),
+    ("to-ndjson, from-ndjson", "rewrite the top-level array as JSON Lines, a record to a line, or back"),
+    ("grep {pattern}, g/{pattern}/", "list every match in the location list (with a range, only in those lines)"),
+    ("bookmark add [note], bookmark delete", "note the node under the cursor, kept in the session"),
+    ("bookmark list", "list the bookmarks in the location list"),
+    ("script start, script stop", "record node edits, renames and reformats by path"),
+    ("script save {file}", "write the recorded edits for jim --apply"),
+    ("marks", "list the marks: `. is where the last change was made"),
+    ("copen, cclose", "show or hide the location list (]q / [q go through it)"),
+    ("diff {file}, diff off", "compare with a file by structure (]c / [c jump to changes)"),
+    ("diffsaved", "list the lines changed since the last save"),
+    ("filter {pred}, filter off", "hide elements of the array failing e.g. .status == \"failed\""),
+    ("schema load {file}, schema off", "check against a JSON Schema (]d / [d jump to errors)"),
+    ("yank-path", "copy the path to the cursor to the system clipboard"),
+    ("yank-value", "copy the value under the cursor to the system clipboard"),
+    ("wnode[!] [++pretty] {file}", "write the value to a file (! overwrites)"),
+    ("extract[!] {path} [> file]", "list every value at a path like .items[].id, or write them to a file"),
+    ("[%]pipe {cmd}", "filter the value (selection, % buffer) through a command"),
+    ("retab [n]", "indent with spaces, or tabs with noexpandtab (a range like '<,'> for just those lines)"),
+    ("trimws", "take trailing whitespace off every line (a range for just those lines)"),
+    ("stats", "node counts, depth, size, numbers and keys of the value"),
+    ("tokens", "list the tokens of the lines in view (r refreshes)"),
+    ("nodes", "list the indexed nodes around the cursor (r refreshes)"),
+    ("profile", "list how long recent operations took (r refreshes)"),
+    ("tasks", "list the work running in the background (r refreshes, Ctrl-C cancels)"),
+    ("checksum[!]", "SHA-256 and size of the buffer as it is (! copies the hash)"),
+    ("tree", "show or hide the outline"),
+    ("h, help", "this help"),
+];
+
+/// A `COMMANDS` form as the command palette offers it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteCommand {
+    /// As listed, placeholders and all, e.g. `child {n}`
+    pub form: &'static str,
+    /// The command line to start from, e.g. `child `
+    pub line: String,
+    /// Whether `line` runs as it is, rather than wanting more typed
+    pub complete: bool,
+    pub description: &'static str,
+}
+
+/// Every form in `COMMANDS` for the command palette, leaving out short
+/// forms like `sp` that a longer one beside them starts with. Marks like
+/// `[!]` are dropped; anything else in brackets or braces is for typing.
+pub fn palette_commands() -> Vec<PaletteCommand> {
+    let mut commands = Vec::new();
+    for &(forms, description) in COMMANDS {
+        let forms: Vec<&str> = forms.split(", ").collect();
+        for &form in &forms {
+            if forms.iter().any(|&other| other != form && other.starts_with(form)) {
+                continue;
+            }
+            let line = form.trim_start_matches("[%]").trim_start_matches("{range}").replace("[!]", "");
+            let end = line.find(['{', '[']).unwrap_or(line.len());
+            commands.push(PaletteCommand {
+                form,
+                complete: end == line.len(),
+                line: line[..end].to_string(),
+                description,
+            });
+        }
+    }
+    commands
+}
+
+/// One end of a line range: a line and lines counted on from it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineAddress {
+    pub base: Address,
+    pub offset: isize,
+}
+
+/// The line an address starts from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address {
+    /// `.`, or nothing before a `+` or `-`
+    Current,
+    /// `$`
+    Last,
+    /// A line number, from 1
+    Line(usize),
+    /// `'x`: the line mark x is on; `'<` and `'>` are the last selection's
+    Mark(char),
+}
+
+/// The lines an ex command is given, as typed: `.,+100`, `'<,'>`, `5;+2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub from: LineAddress,
+    pub to: LineAddress,
+    /// Written with `;`: `to` counts from `from` rather than the cursor
+    pub relative: bool,
+}
+
+impl LineRange {
+    /// `'<,'>`
+    pub const SELECTION: Self = Self {
+        from: LineAddress { base: Address::Mark('<'), offset: 0 },
+        to: LineAddress { base: Address::Mark('>'), offset: 0 },
+        relative: false,
+    };
+
+    /// The range at the start of `cmd` and what follows it, or None if it
+    /// doesn't start with one. `%` is left to the caller.
+    pub fn parse(cmd: &str) -> Result<Option<(Self, &str)>> {
+        let Some((from, rest)) = parse_address(cmd)? else {
+            return Ok(None);
+        };
+        let (relative, rest) = match rest.chars().next() {
+            Some(',') => (false, &rest[1..]),
+            Some(';') => (true, &rest[1..]),
+            _ => return Ok(Some((Self { from, to: from, relative: false }, rest))),
+        };
+        match parse_address(rest)? {
+            Some((to, rest)) => Ok(Some((Self { from, to, relative }, rest))),
+            None => bail!("Missing address after {}", &cmd[..cmd.len() - rest.len()]),
+        }
+    }
+
+    /// The lines meant, from 0 and end-exclusive, with the cursor on
+    /// `cursor_line` and `mark` giving the line a mark is on
+    pub fn resolve(&self, cursor_line: usize, line_count: usize, mark: impl Fn(char) -> Option<usize>) -> Result<Range<usize>> {
+        let line = |address: LineAddress, current: usize| -> Result<usize> {
+            let base = match address.base {
+                Address::Current => current,
+                Address::Last => line_count,
+                Address::Line(line) => line,
+                Address::Mark(name) => mark(name).ok_or_else(|| anyhow!("Mark not set: {}", name))? + 1,
+            };
+            match base.checked_add_signed(address.offset) {
+                Some(line) if (1..=line_count).contains(&line) => Ok(line),
+                _ => bail!("Invalid range"),
+            }
+        };
+        let from = line(self.from, cursor_line + 1)?;
+        let to = line(self.to, if self.relative { from } else { cursor_line + 1 })?;
+        if to < from {
+            bail!("Backwards range");
+        }
+        Ok(from - 1..to)
+    }
+}
+
+/// One address at the start of `text`: a line, a mark or `.`/`$`, then any
+/// `+n` and `-n` (a bare `+` or `-` is one line)
+fn parse_address(text: &str) -> Result<Option<(LineAddress, &str)>> {
+    let digits = |text: &str| text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (base, mut rest) = match text.chars().next() {
+        Some('.') => (Some(Address::Current), &text[1..]),
+        Some('$') => (Some(Address::Last), &text[1..]),
+        Some('\'') => match text[1..].chars().next() {
+            Some(name) if !name.is_whitespace() => (Some(Address::Mark(name)), &text[1 + name.len_utf8()..]),
+            _ => bail!("Missing mark name after '"),
+        },
+        Some(c) if c.is_ascii_digit() => {
+            let len = digits(text);
+            let line = text[..len].parse().map_err(|_| anyhow!("Invalid line number: {}", &text[..len]))?;
+            (Some(Address::Line(line)), &text[len..])
+        }
+        _ => (None, text),
+    };
+    let mut offset: isize = 0;
+    let mut any_offset = false;
+    while let Some(sign) = rest.chars().next().filter(|&c| c == '+' || c == '-') {
+        let len = digits(&rest[1..]);
+        let count: isize = match len {
+            0 => 1,
+            _ => rest[1..1 + len].parse().map_err(|_| anyhow!("Invalid offset: {}", &rest[..1 + len]))?,
+        };
+        offset = if sign == '+' { offset.saturating_add(count) } else { offset.saturating_sub(count) };
+        any_offset = true;
+        rest = &rest[1 + len..];
+    }
+    Ok(match (base, any_offset) {
+        (None, false) => None,
+        (base, _) => Some((LineAddress { base: base.unwrap_or(Address::Current), offset }, rest)),
+    })
+}
+
+/// `w file`, `w! file`, `write file` or `write! file` after a range: the
+/// file and whether to overwrite one that's there
+fn parse_write(rest: &str) -> Option<(bool, &str)> {
+    let rest = rest.strip_prefix("write").or_else(|| rest.strip_prefix('w'))?;
+    let (force, rest) = rest.strip_prefix('!').map_or((false, rest), |rest| (true, rest));
+    match rest {
+        "" => Some((force, "")),
+        _ if rest.starts_with(' ') => Some((force, rest.trim())),
+        _ => None,
+    }
+}
 
 /// Command mode handler - ex-style commands
 pub struct CommandMode {
     /// Current command being typed
     pub command_line: String,
+    /// ':' for ex commands, '/' for a search pattern
+    pub prompt: char,
 }
 
 impl CommandMode {
     pub fn new() -> Self {
         Self {
             command_line: String::new(),
+            prompt: ':',
         }
     }
     
@@ -21,39 +267,327 @@ impl CommandMode {
         let cmd = cmd.trim();
         
         // Check for :w <filename> pattern
-        if cmd.starts_with("w ") || cmd.starts_with("write ") {
-            let filename = if cmd.starts_with("w ") {
-                &cmd[2..].trim()
-            } else {
-                &cmd[6..].trim()
-            };
+        if let Some(filename) = cmd.strip_prefix("w ").or_else(|| cmd.strip_prefix("write ")) {
+            let filename = filename.trim();
             
             ctx.buffer.save_as(filename)?;
             return Ok(InputResult::ModeSwitch(Mode::Normal));
         }
         
-        match cmd {
-            "q" | "quit" => {
-                // Check if buffer is modified
-                if ctx.buffer.is_modified() {
-                    return Ok(InputResult::Message("No write since last change (use :q! to override)".to_string()));
-                }
-                Ok(InputResult::Quit)
+        if let Some(path) = cmd.strip_prefix("source-session ") {
+            return Ok(InputResult::Command(AppCommand::SourceSession(path.trim().to_string())));
+        }
+        
+        if let Some(rest) = cmd.strip_prefix("mksession") {
+            let (force, path) = rest.strip_prefix('!').map_or((false, rest), |rest| (true, rest));
+            if !path.starts_with(' ') || path.trim().is_empty() {
+                return Ok(InputResult::Message("Usage: mksession[!] {file}".to_string()));
             }
-            "w" | "write" => {
-                // Save file
-                ctx.buffer.save()?;
-                Ok(InputResult::ModeSwitch(Mode::Normal))
+            return Ok(InputResult::Command(AppCommand::MakeSession { path: path.trim().to_string(), force }));
+        }
+        
+        if let Some(path) = cmd.strip_prefix("source ").or_else(|| cmd.strip_prefix("so ")) {
+            return Ok(InputResult::Command(AppCommand::Source(path.trim().to_string())));
+        }
+        
+        // :map gs <C-j>; only one level of :map applies to the keys on the
+        // right, and :noremap's not even that
+        if let Some((remap, args)) = cmd.strip_prefix("map").map(|args| (true, args))
+            .or_else(|| cmd.strip_prefix("noremap").or_else(|| cmd.strip_prefix("no")).map(|args| (false, args)))
+            .filter(|(_, args)| args.is_empty() || args.starts_with(' '))
+        {
+            let mapping = match args.trim().split_once(' ') {
+                _ if args.trim().is_empty() => None,
+                Some((lhs, rhs)) if !rhs.trim().is_empty() => Some((lhs.to_string(), rhs.trim().to_string())),
+                _ => return Ok(InputResult::Message(format!("Usage: {} {{lhs}} {{rhs}}", if remap { "map" } else { "noremap" }))),
+            };
+            return Ok(InputResult::Command(AppCommand::Map { mapping, remap }));
+        }
+        
+        if let Some(lhs) = cmd.strip_prefix("unmap ").or_else(|| cmd.strip_prefix("unm ")) {
+            return Ok(InputResult::Command(AppCommand::Unmap(lhs.trim().to_string())));
+        }
+        
+        if let Some(option) = cmd.strip_prefix("set ") {
+            return Ok(InputResult::Command(AppCommand::Set(option.trim().to_string())));
+        }
+        
+        if let Some(name) = cmd.strip_prefix("key ") {
+            return Ok(InputResult::Command(AppCommand::SearchKey(name.trim().to_string())));
+        }
+        
+        if let Some(literal) = cmd.strip_prefix("value ") {
+            return Ok(InputResult::Command(AppCommand::SearchValue(literal.trim().to_string())));
+        }
+        
+        if let Some(n) = cmd.strip_prefix("child ") {
+            return Ok(match n.trim().parse() {
+                Ok(n) if n > 0 => InputResult::StructuralNav(StructuralNavAction::NthChild(n), 1),
+                _ => InputResult::Message(format!("Invalid child number: {}", n.trim())),
+            });
+        }
+        
+        if let Some(n) = cmd.strip_prefix("index ") {
+            return Ok(match n.trim().parse() {
+                Ok(n) if n > 0 => InputResult::StructuralNav(StructuralNavAction::ArrayIndex(n), 1),
+                _ => InputResult::Message(format!("Invalid element number: {}", n.trim())),
+            });
+        }
+        
+        if let Some((bang, args)) = cmd.strip_prefix("renameall").and_then(|rest| {
+            let (bang, rest) = rest.strip_prefix('!').map_or((false, rest), |rest| (true, rest));
+            Some((bang, rest.strip_prefix(' ')?))
+        }) {
+            return Ok(match RenameAll::parse(args, bang) {
+                Ok(rename) => InputResult::Command(AppCommand::RenameAll(rename)),
+                Err(e) => InputResult::Message(e.to_string()),
+            });
+        }
+        
+        if let Some((bang, name)) = cmd.strip_prefix("rename").and_then(|rest| {
+            let (bang, rest) = rest.strip_prefix('!').map_or((false, rest), |rest| (true, rest));
+            Some((bang, rest.strip_prefix(' ')?))
+        }) {
+            let op = NodeOp::Rename { name: name.to_string(), force: bang };
+            return Ok(InputResult::Command(AppCommand::Node { op, register: None }));
+        }
+        
+        if let Some(arg) = cmd.strip_prefix("wrap ") {
+            let key = match arg.trim() {
+                "[" => None,
+                arg => match arg.strip_prefix('{').and_then(|key| key.strip_suffix('}')) {
+                    Some(key) => Some(key.to_string()),
+                    None => return Ok(InputResult::Message("Usage: :wrap [ or :wrap {key}".to_string())),
+                },
+            };
+            return Ok(InputResult::Command(AppCommand::Node { op: NodeOp::Wrap { key }, register: None }));
+        }
+        
+        if let Some(name) = cmd.strip_prefix("convert ") {
+            return Ok(match Conversion::from_name(name.trim()) {
+                Some(conversion) => InputResult::Command(AppCommand::Node { op: NodeOp::Convert(conversion), register: None }),
+                None => InputResult::Message(format!("Unknown type: {} (string, unstring, bool or null)", name.trim())),
+            });
+        }
+        
+        // '<,'> alone keeps the selection as it was made, down to the
+        // character; any other range is whole lines
+        let (range, rest) = if let Some(rest) = cmd.strip_prefix('%') {
+            (PipeRange::Buffer, rest)
+        } else {
+            match LineRange::parse(cmd) {
+                Ok(Some((lines, rest))) if lines == LineRange::SELECTION => (PipeRange::Selection, rest),
+                Ok(Some((lines, rest))) => (PipeRange::Lines(lines), rest),
+                Ok(None) => (PipeRange::Node, cmd),
+                Err(e) => return Ok(InputResult::Message(e.to_string())),
+            }
+        };
+        // :/pattern searches only the node under the cursor, :'<,'>/ only
+        // the selection
+        if let Some(pattern) = rest.strip_prefix('/') {
+            let scope = match range {
+                PipeRange::Node => SearchScope::Node,
+                PipeRange::Selection => SearchScope::Selection,
+                PipeRange::Buffer => SearchScope::Buffer,
+                PipeRange::Lines(_) => return Ok(InputResult::Message(format!("No range allowed: {}", cmd))),
+            };
+            return Ok(InputResult::Command(AppCommand::Search { pattern: pattern.to_string(), scope }));
+        }
+        if let Some(command) = rest.strip_prefix("pipe ").or_else(|| rest.strip_prefix("pi ")) {
+            let command = command.trim().to_string();
+            return Ok(InputResult::Command(AppCommand::Pipe { range, command }));
+        }
+        // Without a range these go over the whole buffer
+        let (name, width) = rest.split_once(' ').unwrap_or((rest, ""));
+        let op = match (name, width.trim()) {
+            ("retab" | "ret", "") => Some(WhitespaceOp::Retab(None)),
+            ("retab" | "ret", width) => match width.parse() {
+                Ok(width) if width > 0 => Some(WhitespaceOp::Retab(Some(width))),
+                _ => return Ok(InputResult::Message(format!("Invalid width: {}", width))),
+            },
+            ("trimws", "") => Some(WhitespaceOp::Trim),
+            _ => None,
+        };
+        if let Some(op) = op {
+            let range = if range == PipeRange::Node { PipeRange::Buffer } else { range };
+            return Ok(InputResult::Command(AppCommand::Whitespace { op, range }));
+        }
+        // :g/pattern/ lists the matches; there's no command to run on them
+        if let Some(pattern) = rest.strip_prefix("grep ").or_else(|| rest.strip_prefix("g/")) {
+            let pattern = if rest.starts_with("g/") { pattern.strip_suffix('/').unwrap_or(pattern) } else { pattern.trim() };
+            if pattern.is_empty() || rest.starts_with("g/") && pattern.contains('/') {
+                return Ok(InputResult::Message("Usage: grep {pattern} or g/{pattern}/".to_string()));
+            }
+            let range = if range == PipeRange::Node { PipeRange::Buffer } else { range };
+            return Ok(InputResult::Command(AppCommand::Grep { pattern: pattern.to_string(), range }));
+        }
+        if rest == "undo-here" {
+            return Ok(InputResult::Command(AppCommand::UndoHere(range)));
+        }
+        // :w with a range writes just those lines; without one it saves
+        if let Some((force, path)) = parse_write(rest).filter(|_| range != PipeRange::Node) {
+            if path.is_empty() {
+                return Ok(InputResult::Message("Usage: :{range}w[!] {file}".to_string()));
+            }
+            return Ok(InputResult::Command(AppCommand::WriteRange { range, path: path.to_string(), force }));
+        }
+        if range != PipeRange::Node {
+            return Ok(InputResult::Message(format!("No range allowed: {}", cmd)));
+        }
+        
+        if let Some((force, args)) = cmd.strip_prefix("wnode").and_then(|rest| {
+            let (force, rest) = rest.strip_prefix('!').map_or((false, rest), |rest| (true, rest));
+            Some((force, rest.strip_prefix(' ')?.trim()))
+        }) {
+            let (pretty, path) = match args.strip_prefix("++pretty") {
+                Some(path) => (true, path.trim()),
+                None => (false, args),
+            };
+            if path.is_empty() {
+                return Ok(InputResult::Message("Usage: wnode[!] [++pretty] {file}".to_string()));
             }
-            "wq" | "x" => {
-                // Save and quit
-                ctx.buffer.save()?;
-                Ok(InputResult::Quit)
+            let op = NodeOp::Export { path: path.to_string(), pretty, force };
+            return Ok(InputResult::Command(AppCommand::Node { op, register: None }));
+        }
+        
+        if let Some((force, args)) = cmd.strip_prefix("extract").and_then(|rest| {
+            let (force, rest) = rest.strip_prefix('!').map_or((false, rest), |rest| (true, rest));
+            Some((force, rest.strip_prefix(' ')?.trim()))
+        }) {
+            let (path, output) = match args.rsplit_once('>') {
+                Some((path, file)) if !file.trim().is_empty() => (path.trim(), Some(file.trim().to_string())),
+                Some(_) => return Ok(InputResult::Message("Usage: extract[!] {path} [> file]".to_string())),
+                None => (args, None),
+            };
+            return Ok(match Pattern::parse(path) {
+                Ok(pattern) => InputResult::Command(AppCommand::Extract { pattern, path: path.to_string(), output, force }),
+                Err(e) => InputResult::Message(e.to_string()),
+            });
+        }
+        
+        if let Some(path) = cmd.strip_prefix("diff ").map(str::trim).filter(|&path| path != "off") {
+            return Ok(InputResult::Command(AppCommand::Diff(Some(path.to_string()))));
+        }
+        
+        if let Some(predicate) = cmd.strip_prefix("filter ").map(str::trim).filter(|&predicate| predicate != "off") {
+            return Ok(InputResult::Command(AppCommand::Filter(Some(predicate.to_string()))));
+        }
+        
+        if let Some(args) = cmd.strip_prefix("bookmark").filter(|args| args.is_empty() || args.starts_with(' ')) {
+            let (op, note) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+            let note = note.trim();
+            return Ok(match op {
+                "add" => InputResult::Command(AppCommand::Bookmark(BookmarkOp::Add(decode::decode_string(note).ok().unwrap_or_else(|| note.to_string())))),
+                "delete" | "del" if note.is_empty() => InputResult::Command(AppCommand::Bookmark(BookmarkOp::Delete)),
+                "list" | "" if note.is_empty() => InputResult::Command(AppCommand::Bookmark(BookmarkOp::List)),
+                _ => InputResult::Message("Usage: bookmark add [note], bookmark delete or bookmark list".to_string()),
+            });
+        }
+        
+        if let Some(args) = cmd.strip_prefix("script").filter(|args| args.is_empty() || args.starts_with(' ')) {
+            let (op, file) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+            let file = file.trim();
+            return Ok(match op {
+                "start" if file.is_empty() => InputResult::Command(AppCommand::Script(ScriptOp::Start)),
+                "stop" if file.is_empty() => InputResult::Command(AppCommand::Script(ScriptOp::Stop)),
+                "save" if !file.is_empty() => InputResult::Command(AppCommand::Script(ScriptOp::Save(file.to_string()))),
+                _ => InputResult::Message("Usage: script start, script stop or script save {file}".to_string()),
+            });
+        }
+        
+        if let Some(path) = cmd.strip_prefix("schema load ") {
+            return Ok(InputResult::Command(AppCommand::Schema(Some(path.trim().to_string()))));
+        }
+        
+        // :undo N / :redo N step through that many changes
+        let (name, count) = cmd.split_once(' ').unwrap_or((cmd, ""));
+        if matches!(name, "u" | "undo" | "red" | "redo") {
+            let count = match count.trim() {
+                "" => 1,
+                count => match count.parse() {
+                    Ok(count) => count,
+                    Err(_) => return Ok(InputResult::Message(format!("Invalid count: {}", count))),
+                },
+            };
+            let older = matches!(name, "u" | "undo");
+            let steps = if older {
+                ctx.undo_stack.undo_many(count, ctx.buffer, ctx.cursor)?
+            } else {
+                ctx.undo_stack.redo_many(count, ctx.buffer, ctx.cursor)?
+            };
+            return Ok(InputResult::Message(ctx.undo_stack.describe(steps, older)));
+        }
+        
+        if let Some(name) = cmd.strip_prefix("array ") {
+            return Ok(match ArrayOp::from_name(name.trim()) {
+                Some(op) => InputResult::Command(AppCommand::Node { op: NodeOp::Array(op), register: None }),
+                None => InputResult::Message(format!("Unknown array command: {} (sort, sort!, unique or count)", name.trim())),
+            });
+        }
+        
+        match cmd {
+            // Closes a window when the view is split, so the modified
+            // check is left to the app
+            "q" | "quit" => Ok(InputResult::Command(AppCommand::Quit { force: false })),
+            "w" | "write" | "w!" | "write!" => {
+                // A new file's directory is made only when asked, with !
+                if let Some(dir) = ctx.buffer.missing_directory() {
+                    if !cmd.ends_with('!') {
+                        return Ok(InputResult::Message(format!("No such directory: {} (:w! creates it)", dir.display())));
+                    }
+                    if let Err(e) = std::fs::create_dir_all(&dir) {
+                        return Ok(InputResult::Message(format!("Can't create {}: {}", dir.display(), e)));
+                    }
+                }
+                Ok(InputResult::Command(AppCommand::Write { force: cmd.ends_with('!') }))
             }
-            "q!" => {
-                // Force quit without saving
-                Ok(InputResult::Quit)
+            "wq" => Ok(InputResult::Command(AppCommand::WriteQuit { always: true })),
+            "x" => Ok(InputResult::Command(AppCommand::WriteQuit { always: false })),
+            "q!" => Ok(InputResult::Command(AppCommand::Quit { force: true })),
+            "e" | "edit" => Ok(InputResult::Command(AppCommand::Reload { force: false })),
+            "e!" | "edit!" => Ok(InputResult::Command(AppCommand::Reload { force: true })),
+            "bd" | "bdelete" => Ok(InputResult::Command(AppCommand::CloseBuffer { force: false })),
+            "bd!" | "bdelete!" => Ok(InputResult::Command(AppCommand::CloseBuffer { force: true })),
+            "sp" | "split" => Ok(InputResult::Command(AppCommand::Split { vertical: false })),
+            "vs" | "vsp" | "vsplit" => Ok(InputResult::Command(AppCommand::Split { vertical: true })),
+            "clo" | "close" => Ok(InputResult::Command(AppCommand::CloseWindow)),
+            "move-up" => Ok(InputResult::Command(AppCommand::Node { op: NodeOp::MoveUp, register: None })),
+            "move-down" => Ok(InputResult::Command(AppCommand::Node { op: NodeOp::MoveDown, register: None })),
+            "unwrap" | "unwrap!" => {
+                let op = NodeOp::Unwrap { splice: cmd == "unwrap!" };
+                Ok(InputResult::Command(AppCommand::Node { op, register: None }))
             }
+            "edit-string" => Ok(InputResult::Command(AppCommand::Node { op: NodeOp::EditString, register: None })),
+            "validate" => Ok(InputResult::Command(AppCommand::Validate)),
+            "split-docs" => Ok(InputResult::Command(AppCommand::SplitDocs)),
+            "yamlview" => Ok(InputResult::Command(AppCommand::YamlView)),
+            "to-ndjson" => Ok(InputResult::Command(AppCommand::Ndjson(Direction::ToNdjson))),
+            "from-ndjson" => Ok(InputResult::Command(AppCommand::Ndjson(Direction::FromNdjson))),
+            "force" => Ok(InputResult::Command(AppCommand::ForceEdit)),
+            "copen" | "cope" => Ok(InputResult::Command(AppCommand::LocationList { open: true })),
+            "cclose" | "ccl" => Ok(InputResult::Command(AppCommand::LocationList { open: false })),
+            "stats" => Ok(InputResult::Command(AppCommand::Stats)),
+            "tokens" => Ok(InputResult::Command(AppCommand::Inspect { tokens: true })),
+            "nodes" => Ok(InputResult::Command(AppCommand::Inspect { tokens: false })),
+            "profile" => Ok(InputResult::Command(AppCommand::Profile)),
+            "tasks" => Ok(InputResult::Command(AppCommand::Tasks)),
+            "checksum" | "checksum!" => Ok(InputResult::Command(AppCommand::Checksum { copy: cmd == "checksum!" })),
+            "schema off" => Ok(InputResult::Command(AppCommand::Schema(None))),
+            "diff" => Ok(InputResult::Command(AppCommand::Diff(None))),
+            "diff off" => Ok(InputResult::Command(AppCommand::DiffOff)),
+            "diffsaved" => Ok(InputResult::Command(AppCommand::DiffSaved)),
+            "palette" => Ok(InputResult::Command(AppCommand::Palette { count: None })),
+            "outline" => Ok(InputResult::Command(AppCommand::Outline)),
+            "keys" => Ok(InputResult::Command(AppCommand::Keys { all: false })),
+            "keys!" => Ok(InputResult::Command(AppCommand::Keys { all: true })),
+            "filter off" => Ok(InputResult::Command(AppCommand::Filter(None))),
+            "filter" => Ok(InputResult::Message("Usage: :filter .path == value, or :filter off".to_string())),
+            "undol" | "undolist" => Ok(InputResult::Command(AppCommand::UndoList)),
+            "marks" => Ok(InputResult::Command(AppCommand::Marks)),
+            "yank-path" => Ok(InputResult::Command(AppCommand::CopyToClipboard { path: true })),
+            "yank-value" => Ok(InputResult::Command(AppCommand::CopyToClipboard { path: false })),
+            "tree" => Ok(InputResult::Command(AppCommand::ToggleTree)),
+            "h" | "help" => Ok(InputResult::Command(AppCommand::Help)),
             "" => {
                 // Empty command, just return to normal
                 Ok(InputResult::ModeSwitch(Mode::Normal))
@@ -66,19 +600,28 @@ impl CommandMode {
     }
 }
 
+impl Default for CommandMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ModeHandler for CommandMode {
     fn handle_key(&mut self, key: KeyEvent, mut ctx: EditorContext) -> Result<InputResult> {
         match (key.code, key.modifiers) {
             // Escape - cancel command mode
             (KeyCode::Esc, _) => {
                 self.command_line.clear();
+                self.prompt = ':';
                 Ok(InputResult::ModeSwitch(Mode::Normal))
             }
             
             // Enter - execute command
             (KeyCode::Enter, _) => {
-                let cmd = self.command_line.clone();
-                self.command_line.clear();
+                let cmd = std::mem::take(&mut self.command_line);
+                if std::mem::replace(&mut self.prompt, ':') == '/' {
+                    return Ok(InputResult::Command(AppCommand::Search { pattern: cmd, scope: SearchScope::Buffer }));
+                }
                 self.execute_command(&cmd, &mut ctx)
             }
             
@@ -86,6 +629,7 @@ impl ModeHandler for CommandMode {
             (KeyCode::Backspace, _) => {
                 self.command_line.pop();
                 if self.command_line.is_empty() {
+                    self.prompt = ':';
                     // If command line becomes empty, return to normal mode
                     Ok(InputResult::ModeSwitch(Mode::Normal))
                 } else {
@@ -102,4 +646,100 @@ impl ModeHandler for CommandMode {
             _ => Ok(InputResult::NotHandled),
         }
     }
+
+    /// Pasted text goes on the command line as typed. Its line breaks
+    /// (but a last one, dropped) become spaces rather than Enter, so nothing runs until Enter is
+    /// pressed.
+    fn handle_paste(&mut self, text: &str) -> InputResult {
+        let text = text.trim_end_matches(['\r', '\n']);
+        let lines = text.lines().count();
+        self.command_line.push_str(&text.replace("\r\n", " ").replace(['\r', '\n'], " "));
+        if lines > 1 {
+            InputResult::Message(format!("Pasted {} lines onto the command line as one; Enter runs it", lines))
+        } else {
+            InputResult::Handled
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_commands() {
+        let commands = palette_commands();
+        let line = |form: &str| commands.iter().find(|c| c.form == form).map(|c| (c.line.as_str(), c.complete));
+        assert_eq!(line("write [file]"), Some(("write ", false)));
+        assert_eq!(line("w"), None);
+        assert_eq!(line("x"), Some(("x", true)));
+        assert_eq!(line("child {n}"), Some(("child ", false)));
+        assert_eq!(line("keys[!]"), Some(("keys", true)));
+        assert_eq!(line("[%]pipe {cmd}"), Some(("pipe ", false)));
+        assert_eq!(line("diff off"), Some(("diff off", true)));
+        assert_eq!(line("/{pattern}"), Some(("/", false)));
+        assert_eq!(line("{range}w[!] {file}"), Some(("w ", false)));
+    }
+
+    fn range(cmd: &str) -> (LineRange, &str) {
+        LineRange::parse(cmd).unwrap().unwrap()
+    }
+
+    fn address(base: Address, offset: isize) -> LineAddress {
+        LineAddress { base, offset }
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(range(".,+100w x"), (LineRange { from: address(Address::Current, 0), to: address(Address::Current, 100), relative: false }, "w x"));
+        assert_eq!(range("'<,'>w"), (LineRange::SELECTION, "w"));
+        assert_ne!(range("'<,'>+1w").0, LineRange::SELECTION);
+        assert_eq!(range("12;+-3pipe"), (LineRange { from: address(Address::Line(12), 0), to: address(Address::Current, -2), relative: true }, "pipe"));
+        assert_eq!(range("$-2,$").0.from, address(Address::Last, -2));
+        // One address is a range of one line
+        assert_eq!(range("'a+1trimws"), (LineRange { from: address(Address::Mark('a'), 1), to: address(Address::Mark('a'), 1), relative: false }, "trimws"));
+        assert!(LineRange::parse("wnode x").unwrap().is_none());
+        assert!(LineRange::parse("1,w").is_err());
+        assert!(LineRange::parse("'").is_err());
+        assert!(LineRange::parse("99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn test_resolve_range() {
+        let marks = |name| match name {
+            '<' => Some(3),
+            '>' => Some(5),
+            _ => None,
+        };
+        let lines = |cmd: &str| range(cmd).0.resolve(9, 20, marks);
+        assert_eq!(lines(".,+10").unwrap(), 9..20);
+        assert_eq!(lines("'<,'>").unwrap(), 3..6);
+        assert_eq!(lines("5").unwrap(), 4..5);
+        assert_eq!(lines("1,$").unwrap(), 0..20);
+        assert_eq!(lines("3;+2").unwrap(), 2..5);
+        assert_eq!(lines("-,+").unwrap(), 8..11);
+        assert!(lines(".,+11").is_err());
+        assert!(lines("0").is_err());
+        assert!(lines("5,3").is_err());
+        assert!(lines("'x").is_err());
+    }
+
+    #[test]
+    fn test_parse_write() {
+        assert_eq!(parse_write("w part.json"), Some((false, "part.json")));
+        assert_eq!(parse_write("write! part.json"), Some((true, "part.json")));
+        assert_eq!(parse_write("wnode x"), None);
+    }
+
+    #[test]
+    fn test_paste_never_runs() {
+        let mut command = CommandMode::new();
+        assert!(matches!(command.handle_paste("q\n"), InputResult::Handled));
+        assert_eq!(command.command_line, "q");
+
+        let mut command = CommandMode::new();
+        command.command_line.push_str("w half");
+        assert!(matches!(command.handle_paste("\r\nname\n:q!\n"), InputResult::Message(_)));
+        assert_eq!(command.command_line, "w half name :q!");
+    }
 }
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use super::{EditorContext, InputResult, ModeHandler, Mode};
+use super::{AppCommand, EditorContext, InputResult, ModeHandler, Mode};
 use crate::edit::EditOperations;
 
 /// Insert mode handler - text editing
@@ -13,6 +13,12 @@ impl InsertMode {
     }
 }
 
+impl Default for InsertMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ModeHandler for InsertMode {
     fn handle_key(&mut self, key: KeyEvent, ctx: EditorContext) -> Result<InputResult> {
         match (key.code, key.modifiers) {
@@ -119,6 +125,11 @@ impl ModeHandler for InsertMode {
                 Ok(InputResult::Handled)
             }
             
+            // Key name completion
+            (KeyCode::Char(c @ ('n' | 'p')), KeyModifiers::CONTROL) => {
+                Ok(InputResult::Command(AppCommand::CompleteKey { forward: c == 'n' }))
+            }
+            
             // Arrow keys (allow navigation in insert mode)
             (KeyCode::Left, _) => {
                 ctx.cursor.move_left(ctx.buffer);
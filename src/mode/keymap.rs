@@ -0,0 +1,1170 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::Mode;
+
+/// Keys waiting to be handled before the terminal is read again: the rest
+/// of a pending sequence, or the keys a mapped action replays. Sequences
+/// are collected before they're dispatched, so handlers that take a second
+/// key find it here rather than waiting on the terminal.
+#[derive(Debug, Default)]
+pub struct KeyQueue {
+    keys: VecDeque<KeyEvent>,
+}
+
+impl KeyQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next queued key; None once the sequence being handled is used up
+    pub fn next_key(&mut self) -> Option<KeyEvent> {
+        self.keys.pop_front()
+    }
+
+    /// Queue `keys` ahead of anything already queued
+    pub fn unread(&mut self, keys: &[KeyEvent]) {
+        for &key in keys.iter().rev() {
+            self.keys.push_front(key);
+        }
+    }
+}
+
+/// Default for :set timeoutlen: how long a sequence that could still
+/// become a mapping waits for its next key, in milliseconds
+pub const DEFAULT_TIMEOUTLEN: u64 = 1000;
+
+/// Default for :set ttimeoutlen: how long an Esc waits for the rest of an
+/// escape sequence, in milliseconds
+pub const DEFAULT_TTIMEOUTLEN: u64 = 50;
+
+/// The ends of escape sequences, after `Esc [` or `Esc O`, for keys whose
+/// sequence arrived split
+const SEQUENCE_KEYS: &[(char, KeyCode)] = &[
+    ('A', KeyCode::Up),
+    ('B', KeyCode::Down),
+    ('C', KeyCode::Right),
+    ('D', KeyCode::Left),
+    ('H', KeyCode::Home),
+    ('F', KeyCode::End),
+];
+
+/// Puts back together escape sequences that reach the terminal reader in
+/// pieces, as they do over a slow connection: the terminal library only
+/// recognizes a sequence that arrives whole, and hands over a split one as
+/// Esc followed by ordinary keys. An Esc is held for up to ttimeoutlen;
+/// the key after it inside that time makes Alt with it, or with `[` or
+/// `O` begins an arrow, Home or End key.
+#[derive(Debug, Default)]
+pub struct EscapeSequences {
+    /// The start of a sequence, and when its last key came
+    held: Vec<KeyEvent>,
+    since: Option<Instant>,
+}
+
+impl EscapeSequences {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The keys to handle now that `key` has come at `now`
+    pub fn key(&mut self, key: KeyEvent, now: Instant, timeout: Duration) -> Vec<KeyEvent> {
+        let mut keys = self.expire(now, timeout);
+        let plain = match key.code {
+            KeyCode::Char(c) if (key.modifiers - KeyModifiers::SHIFT).is_empty() => Some(c),
+            _ => None,
+        };
+        match (self.held.as_slice(), plain) {
+            ([_], Some('[' | 'O')) => {
+                self.held.push(key);
+                self.since = Some(now);
+                return keys;
+            }
+            ([_], Some(_)) => {
+                self.held.clear();
+                keys.push(KeyEvent::new(key.code, key.modifiers | KeyModifiers::ALT));
+                return keys;
+            }
+            ([_, _], Some(c)) => {
+                if let Some(&(_, code)) = SEQUENCE_KEYS.iter().find(|&&(end, _)| end == c) {
+                    self.held.clear();
+                    keys.push(KeyEvent::new(code, KeyModifiers::NONE));
+                    return keys;
+                }
+            }
+            _ => {}
+        }
+        keys.append(&mut self.held);
+        if key.code == KeyCode::Esc && key.modifiers.is_empty() && !timeout.is_zero() {
+            self.held.push(key);
+            self.since = Some(now);
+        } else {
+            keys.push(key);
+        }
+        keys
+    }
+
+    /// What's held, once `timeout` has passed by `now` with nothing more
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<KeyEvent> {
+        match self.since {
+            Some(since) if now.saturating_duration_since(since) >= timeout => std::mem::take(&mut self.held),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the start of a sequence is held back
+    pub fn is_holding(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// When what's held will expire, if anything is
+    pub fn deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.since.filter(|_| !self.held.is_empty()).map(|since| since + timeout)
+    }
+}
+
+/// Built-in editor actions that keys can be mapped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    MoveLeft,
+    MoveDown,
+    MoveUp,
+    MoveRight,
+    HalfPageDown,
+    HalfPageUp,
+    GotoTop,
+    GotoBottom,
+    LineStart,
+    LineEnd,
+    FirstNonBlank,
+    WordForward,
+    WordBack,
+    WordEnd,
+    DisplayLineDown,
+    DisplayLineUp,
+    StructuralNextSibling,
+    StructuralPrevSibling,
+    StructuralParent,
+    StructuralFirstChild,
+    StructuralLastChild,
+    StructuralNthChild,
+    NextKey,
+    PrevKey,
+    NextValue,
+    PrevValue,
+    NextError,
+    PrevError,
+    NextDiagnostic,
+    PrevDiagnostic,
+    NextDuplicate,
+    PrevDuplicate,
+    NextChange,
+    PrevChange,
+    NextLocation,
+    PrevLocation,
+    NextRecord,
+    PrevRecord,
+    MatchPair,
+    PreviewValue,
+    CharInfo,
+    FileInfo,
+    FileInfoDetailed,
+    FoldToggle,
+    FoldOpen,
+    FoldClose,
+    FoldOpenAll,
+    FoldCloseAll,
+    ToggleElision,
+    ScrollLeft,
+    ScrollRight,
+    ScrollHalfLeft,
+    ScrollHalfRight,
+    CommandMode,
+    Search,
+    SearchNext,
+    SearchPrev,
+    Visual,
+    VisualLine,
+    Insert,
+    Append,
+    AppendLineEnd,
+    InsertLineStart,
+    OpenBelow,
+    OpenAbove,
+    Undo,
+    Redo,
+    UndoEarlier,
+    UndoLater,
+    Paste,
+    PasteBefore,
+    DeleteChar,
+    DeleteCharBefore,
+    DeleteLine,
+    ChangeLine,
+    YankLine,
+    DeleteNode,
+    YankNode,
+    PasteNode,
+    MoveNodeUp,
+    MoveNodeDown,
+    Stringify,
+    Unstringify,
+    ToggleBool,
+    NullOut,
+    WrapString,
+    JoinLines,
+    JoinLinesRaw,
+    ToggleTree,
+    Help,
+    Palette,
+    JumpBack,
+    JumpForward,
+    LastChange,
+    LastChangeLine,
+    WindowLeft,
+    WindowDown,
+    WindowUp,
+    WindowRight,
+    WindowNext,
+    Split,
+    VerticalSplit,
+    CloseWindow,
+    Quit,
+    SaveAndQuit,
+    QuitWithoutSaving,
+    NormalMode,
+    Newline,
+}
+
+/// Each action's name in config files and the keys that perform it in
+/// normal and insert mode (None where it means nothing)
+const ACTIONS: &[(Action, &str, Option<&str>, Option<&str>)] = &[
+    (Action::MoveLeft, "move_left", Some("h"), Some("<Left>")),
+    (Action::MoveDown, "move_down", Some("j"), Some("<Down>")),
+    (Action::MoveUp, "move_up", Some("k"), Some("<Up>")),
+    (Action::MoveRight, "move_right", Some("l"), Some("<Right>")),
+    (Action::HalfPageDown, "half_page_down", Some("<C-d>"), None),
+    (Action::HalfPageUp, "half_page_up", Some("<C-u>"), None),
+    (Action::GotoTop, "goto_top", Some("gg"), None),
+    (Action::GotoBottom, "goto_bottom", Some("G"), None),
+    (Action::LineStart, "line_start", Some("0"), None),
+    (Action::LineEnd, "line_end", Some("$"), None),
+    (Action::FirstNonBlank, "first_non_blank", Some("^"), None),
+    (Action::WordForward, "word_forward", Some("w"), None),
+    (Action::WordBack, "word_back", Some("b"), None),
+    (Action::WordEnd, "word_end", Some("e"), None),
+    (Action::DisplayLineDown, "display_line_down", Some("gj"), None),
+    (Action::DisplayLineUp, "display_line_up", Some("gk"), None),
+    (Action::StructuralNextSibling, "structural_next_sibling", Some("<C-j>"), None),
+    (Action::StructuralPrevSibling, "structural_prev_sibling", Some("<C-k>"), None),
+    (Action::StructuralParent, "structural_parent", Some("<C-h>"), None),
+    (Action::StructuralFirstChild, "structural_first_child", Some("<C-l>"), None),
+    (Action::StructuralLastChild, "structural_last_child", Some("]$"), None),
+    (Action::StructuralNthChild, "structural_nth_child", Some("]g"), None),
+    (Action::NextKey, "next_key", Some("]l"), None),
+    (Action::PrevKey, "prev_key", Some("[l"), None),
+    (Action::NextValue, "next_value", Some("]v"), None),
+    (Action::PrevValue, "prev_value", Some("[v"), None),
+    (Action::NextError, "next_error", Some("]e"), None),
+    (Action::PrevError, "prev_error", Some("[e"), None),
+    (Action::NextDiagnostic, "next_diagnostic", Some("]d"), None),
+    (Action::PrevDiagnostic, "prev_diagnostic", Some("[d"), None),
+    (Action::NextDuplicate, "next_duplicate_key", Some("]u"), None),
+    (Action::PrevDuplicate, "prev_duplicate_key", Some("[u"), None),
+    (Action::NextChange, "next_change", Some("]c"), None),
+    (Action::PrevChange, "prev_change", Some("[c"), None),
+    (Action::NextLocation, "next_location", Some("]q"), None),
+    (Action::PrevLocation, "prev_location", Some("[q"), None),
+    (Action::NextRecord, "next_record", Some("]]"), None),
+    (Action::PrevRecord, "prev_record", Some("[["), None),
+    (Action::MatchPair, "match_pair", Some("%"), None),
+    (Action::CharInfo, "char_info", Some("ga"), None),
+    (Action::FileInfo, "file_info", Some("<C-g>"), None),
+    (Action::FileInfoDetailed, "file_info_detailed", Some("g<C-g>"), None),
+    (Action::PreviewValue, "preview_value", Some("K"), None),
+    (Action::FoldToggle, "fold_toggle", Some("za"), None),
+    (Action::FoldOpen, "fold_open", Some("zo"), None),
+    (Action::FoldClose, "fold_close", Some("zc"), None),
+    (Action::FoldOpenAll, "fold_open_all", Some("zR"), None),
+    (Action::FoldCloseAll, "fold_close_all", Some("zM"), None),
+    (Action::ToggleElision, "toggle_elision", Some("ze"), None),
+    (Action::ScrollLeft, "scroll_left", Some("zh"), None),
+    (Action::ScrollRight, "scroll_right", Some("zl"), None),
+    (Action::ScrollHalfLeft, "scroll_half_left", Some("zH"), None),
+    (Action::ScrollHalfRight, "scroll_half_right", Some("zL"), None),
+    (Action::CommandMode, "command_mode", Some(":"), None),
+    (Action::Search, "search", Some("/"), None),
+    (Action::SearchNext, "search_next", Some("n"), None),
+    (Action::SearchPrev, "search_prev", Some("N"), None),
+    (Action::Visual, "visual", Some("v"), None),
+    (Action::VisualLine, "visual_line", Some("V"), None),
+    (Action::Insert, "insert", Some("i"), None),
+    (Action::Append, "append", Some("a"), None),
+    (Action::AppendLineEnd, "append_line_end", Some("A"), None),
+    (Action::InsertLineStart, "insert_line_start", Some("I"), None),
+    (Action::OpenBelow, "open_below", Some("o"), None),
+    (Action::OpenAbove, "open_above", Some("O"), None),
+    (Action::Undo, "undo", Some("u"), None),
+    (Action::Redo, "redo", Some("<C-r>"), None),
+    (Action::UndoEarlier, "undo_earlier", Some("g-"), None),
+    (Action::UndoLater, "undo_later", Some("g+"), None),
+    (Action::Paste, "paste", Some("p"), None),
+    (Action::PasteBefore, "paste_before", Some("P"), None),
+    (Action::DeleteChar, "delete_char", Some("x"), Some("<Del>")),
+    (Action::DeleteCharBefore, "delete_char_before", Some("X"), Some("<BS>")),
+    (Action::DeleteLine, "delete_line", Some("dd"), None),
+    (Action::ChangeLine, "change_line", Some("cc"), None),
+    (Action::YankLine, "yank_line", Some("yy"), None),
+    (Action::DeleteNode, "delete_node", Some("dn"), None),
+    (Action::YankNode, "yank_node", Some("yn"), None),
+    (Action::PasteNode, "paste_node", Some("]p"), None),
+    (Action::MoveNodeUp, "move_node_up", Some("[m"), None),
+    (Action::MoveNodeDown, "move_node_down", Some("]m"), None),
+    (Action::Stringify, "stringify", Some("gts"), None),
+    (Action::Unstringify, "unstringify", Some("gtu"), None),
+    (Action::ToggleBool, "toggle_bool", Some("gtb"), None),
+    (Action::NullOut, "null_out", Some("gtn"), None),
+    (Action::WrapString, "wrap_string", Some("gq"), None),
+    (Action::JoinLines, "join_lines", Some("J"), None),
+    (Action::JoinLinesRaw, "join_lines_raw", Some("gJ"), None),
+    (Action::ToggleTree, "toggle_tree", Some("<F2>"), Some("<F2>")),
+    (Action::Help, "help", Some("<F1>"), Some("<F1>")),
+    (Action::Palette, "palette", Some("<C-p>"), None),
+    (Action::JumpBack, "jump_back", Some("<C-o>"), None),
+    (Action::JumpForward, "jump_forward", Some("<Tab>"), None),
+    (Action::LastChange, "last_change", Some("`."), None),
+    (Action::LastChangeLine, "last_change_line", Some("'."), None),
+    (Action::WindowLeft, "window_left", Some("<C-w>h"), None),
+    (Action::WindowDown, "window_down", Some("<C-w>j"), None),
+    (Action::WindowUp, "window_up", Some("<C-w>k"), None),
+    (Action::WindowRight, "window_right", Some("<C-w>l"), None),
+    (Action::WindowNext, "window_next", Some("<C-w>w"), None),
+    (Action::Split, "split", Some("<C-w>s"), None),
+    (Action::VerticalSplit, "vertical_split", Some("<C-w>v"), None),
+    (Action::CloseWindow, "close_window", Some("<C-w>c"), None),
+    (Action::Quit, "quit", None, None),
+    (Action::SaveAndQuit, "save_and_quit", Some("ZZ"), None),
+    (Action::QuitWithoutSaving, "quit_without_saving", Some("ZQ"), None),
+    (Action::NormalMode, "normal_mode", Some("<Esc>"), Some("<Esc>")),
+    (Action::Newline, "newline", None, Some("<CR>")),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS.iter().find(|(_, n, _, _)| *n == name).map(|&(action, _, _, _)| action)
+    }
+
+    pub fn name(self) -> &'static str {
+        ACTIONS.iter().find(|(a, _, _, _)| *a == self).map_or("", |&(_, name, _, _)| name)
+    }
+
+    /// The built-in keys that perform this action in `mode`
+    pub fn keys(self, mode: Mode) -> Option<Vec<KeyEvent>> {
+        // q no longer quits, being too easy to hit, but it can be mapped
+        // back; quit runs :q, which asks about unsaved changes
+        if self == Action::Quit && mode == Mode::Normal {
+            return Binding::Command("q".to_string()).keys(mode);
+        }
+        let &(_, _, normal, insert) = ACTIONS.iter().find(|(a, _, _, _)| *a == self)?;
+        let keys = match mode {
+            Mode::Normal => normal,
+            Mode::Insert => insert,
+            _ => None,
+        }?;
+        parse_keys(keys).ok()
+    }
+}
+
+/// What a mapped key sequence does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    Action(Action),
+    /// An ex command line, e.g. ":w" (normal mode only)
+    Command(String),
+    /// Keys typed in place of the mapped ones, from :map or :noremap.
+    /// Mappings among :map's keys are applied once more, but no further.
+    Keys { keys: Vec<KeyEvent>, remap: bool },
+}
+
+impl Binding {
+    /// Parse a config value: an action name or ":command"
+    pub fn parse(value: &str) -> Result<Self> {
+        if let Some(command) = value.strip_prefix(':') {
+            return Ok(Binding::Command(command.to_string()));
+        }
+        match Action::from_name(value) {
+            Some(action) => Ok(Binding::Action(action)),
+            None => bail!("unknown action '{}'", value),
+        }
+    }
+
+    /// The keys to replay in `mode` to carry this binding out
+    pub fn keys(&self, mode: Mode) -> Option<Vec<KeyEvent>> {
+        match self {
+            Binding::Action(action) => action.keys(mode),
+            Binding::Command(command) if mode == Mode::Normal => {
+                let mut keys = vec![key(KeyCode::Char(':'))];
+                keys.extend(command.chars().map(|c| key(KeyCode::Char(c))));
+                keys.push(key(KeyCode::Enter));
+                Some(keys)
+            }
+            Binding::Command(_) => None,
+            Binding::Keys { keys, .. } => Some(keys.clone()),
+        }
+    }
+}
+
+/// A way to go on from a pending key sequence, for the hint popup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Continuation {
+    /// The keys still to type, in key notation
+    pub keys: String,
+    pub description: String,
+}
+
+impl Continuation {
+    fn new(keys: impl Into<String>, description: impl Into<String>) -> Self {
+        Self { keys: keys.into(), description: description.into() }
+    }
+}
+
+/// Motions an operator takes, besides repeating itself for the whole line
+const OPERATOR_MOTIONS: &[Action] = &[
+    Action::WordForward,
+    Action::WordBack,
+    Action::WordEnd,
+    Action::StructuralNextSibling,
+    Action::StructuralPrevSibling,
+    Action::StructuralParent,
+    Action::StructuralFirstChild,
+];
+
+/// Built-in sequences that aren't an action's default keys
+const OTHER_SEQUENCES: &[(&str, &str)] = &[("]j", "next sibling"), ("[j", "previous sibling")];
+
+/// Text objects after `i` or `a`
+const TEXT_OBJECTS: &[(char, &str)] = &[
+    ('w', "word"),
+    ('"', "string"),
+    ('{', "braces"),
+    ('[', "brackets"),
+    ('<', "angle brackets"),
+    ('b', "nearest {}, [] or ()"),
+];
+
+/// How built-in normal mode sequences can continue from `sequence`: the
+/// action table's multi-key entries, plus operators, text objects and
+/// registers, whose keys combine too freely for the table
+pub fn builtin_continuations(sequence: &[KeyEvent]) -> Vec<Continuation> {
+    let chars: Vec<Option<char>> = sequence.iter()
+        .map(|key| match key.code {
+            KeyCode::Char(c) if (key.modifiers - KeyModifiers::SHIFT).is_empty() => Some(c),
+            _ => None,
+        })
+        .collect();
+    let operator = |c| match c {
+        'd' => Some("delete"),
+        'c' => Some("change"),
+        'y' => Some("yank"),
+        _ => None,
+    };
+    match chars.as_slice() {
+        [Some('"')] => vec![
+            Continuation::new("a-z", "named register"),
+            Continuation::new("A-Z", "append to named register"),
+            Continuation::new("0", "last yank"),
+            Continuation::new("-", "small delete"),
+            Continuation::new("+", "system clipboard"),
+            Continuation::new("\"", "unnamed register"),
+        ],
+        &[Some(op)] if operator(op).is_some() => {
+            let verb = operator(op).unwrap_or_default();
+            let mut next = vec![Continuation::new(op.to_string(), format!("{} line", verb))];
+            for action in OPERATOR_MOTIONS {
+                let keys = ACTIONS.iter().find(|(a, _, _, _)| a == action).and_then(|entry| entry.2);
+                next.push(Continuation::new(keys.unwrap_or_default(), describe(action.name())));
+            }
+            if op != 'c' {
+                next.push(Continuation::new("n", format!("{} node", verb)));
+            }
+            next.push(Continuation::new("i", format!("{} inside an object", verb)));
+            next.push(Continuation::new("a", format!("{} around an object", verb)));
+            next
+        }
+        [Some(']'), Some('g')] => vec![Continuation::new("1-9", "that child")],
+        &[Some(op), Some(which @ ('i' | 'a'))] if operator(op).is_some() => object_continuations(which),
+        _ => {
+            let sequence: Vec<KeyEvent> = sequence.iter().copied().map(normalize).collect();
+            let sequences = ACTIONS.iter()
+                .filter_map(|&(_, name, keys, _)| Some((keys?, describe(name))))
+                .chain(OTHER_SEQUENCES.iter().map(|&(keys, description)| (keys, description.to_string())));
+            sequences
+                .filter_map(|(keys, description)| {
+                    let keys: Vec<KeyEvent> = parse_keys(keys).ok()?.into_iter().map(normalize).collect();
+                    let rest = keys.strip_prefix(sequence.as_slice()).filter(|rest| !rest.is_empty())?;
+                    Some(Continuation::new(format_keys(rest), description))
+                })
+                .collect()
+        }
+    }
+}
+
+/// How built-in visual mode sequences go on: a register for the operator
+/// after it, or gq
+fn visual_continuations(sequence: &[KeyEvent]) -> Vec<Continuation> {
+    match sequence {
+        [key] if key.code == KeyCode::Char('"') => builtin_continuations(sequence),
+        [key] if key.code == KeyCode::Char('g') => vec![Continuation::new("q", describe(Action::WrapString.name()))],
+        [key] => match key.code {
+            KeyCode::Char(which @ ('i' | 'a')) => object_continuations(which),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// The text objects that can follow `which`, i or a
+fn object_continuations(which: char) -> Vec<Continuation> {
+    let extent = if which == 'i' { "inner" } else { "a" };
+    TEXT_OBJECTS.iter()
+        .map(|&(key, name)| Continuation::new(key.to_string(), format!("{} {}", extent, name)))
+        .collect()
+}
+
+/// An action name as a hint: "structural_next_sibling" → "structural next sibling"
+fn describe(name: &str) -> String {
+    name.replace('_', " ")
+}
+
+/// Result of looking up a key sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// The sequence is mapped and no longer mapping starts with it
+    Found(Binding),
+    /// Longer mappings start with the sequence; it may be mapped itself
+    Prefix(Option<Binding>),
+    /// Nothing is mapped to the sequence or starts with it
+    None,
+}
+
+/// User key mappings, consulted before the built-in keys
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    normal: Vec<(Vec<KeyEvent>, Binding)>,
+    insert: Vec<(Vec<KeyEvent>, Binding)>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.normal.is_empty() && self.insert.is_empty()
+    }
+
+    /// Map `keys` (in key notation, e.g. "gs" or "<C-s>") to `binding` in
+    /// `mode`, replacing any earlier mapping of the same keys
+    pub fn map(&mut self, mode: Mode, keys: &str, binding: Binding) -> Result<()> {
+        let keys = parse_keys(keys)?;
+        if keys.is_empty() {
+            bail!("empty key sequence");
+        }
+        if binding.keys(mode).is_none() {
+            bail!("'{}' can't be mapped in {} mode", binding_name(&binding), mode_name(mode));
+        }
+        let maps = match mode {
+            Mode::Normal => &mut self.normal,
+            Mode::Insert => &mut self.insert,
+            _ => bail!("only normal and insert mode keys can be mapped"),
+        };
+        let keys: Vec<KeyEvent> = keys.into_iter().map(normalize).collect();
+        maps.retain(|(k, _)| *k != keys);
+        maps.push((keys, binding));
+        Ok(())
+    }
+
+    /// Take the mapping of `keys` (in key notation) out of `mode`
+    pub fn unmap(&mut self, mode: Mode, keys: &str) -> Result<()> {
+        let keys: Vec<KeyEvent> = parse_keys(keys)?.into_iter().map(normalize).collect();
+        let maps = match mode {
+            Mode::Normal => &mut self.normal,
+            Mode::Insert => &mut self.insert,
+            _ => bail!("only normal and insert mode keys can be mapped"),
+        };
+        let before = maps.len();
+        maps.retain(|(k, _)| *k != keys);
+        if maps.len() == before {
+            bail!("No such mapping: {}", format_keys(&keys));
+        }
+        Ok(())
+    }
+
+    /// The keys to run for `binding`, which `lhs` is mapped to. A :map
+    /// right-hand side has the mappings in it replaced, once; as in vim,
+    /// `lhs` itself at its start is left as typed, so `:map j jzz` works.
+    pub fn expand(&self, mode: Mode, lhs: &[KeyEvent], binding: &Binding) -> Option<Vec<KeyEvent>> {
+        let keys = binding.keys(mode)?;
+        if !matches!(binding, Binding::Keys { remap: true, .. }) {
+            return Some(keys);
+        }
+        let lhs: Vec<KeyEvent> = lhs.iter().copied().map(normalize).collect();
+        let normalized: Vec<KeyEvent> = keys.iter().copied().map(normalize).collect();
+        let mut expanded = Vec::new();
+        let mut at = 0;
+        if normalized.starts_with(&lhs) {
+            expanded.extend_from_slice(&keys[..lhs.len()]);
+            at = lhs.len();
+        }
+        while at < keys.len() {
+            let longest = self.maps(mode).iter()
+                .filter(|(k, _)| normalized[at..].starts_with(k))
+                .max_by_key(|(k, _)| k.len())
+                .and_then(|(k, b)| Some((k.len(), b.keys(mode)?)));
+            match longest {
+                Some((len, replacement)) => {
+                    expanded.extend(replacement);
+                    at += len;
+                }
+                None => {
+                    expanded.push(keys[at]);
+                    at += 1;
+                }
+            }
+        }
+        Some(expanded)
+    }
+
+    /// Every mapping in `mode` with what it does, in the order they were
+    /// made, for :map. Keys from :noremap are marked with a `*`.
+    pub fn mappings(&self, mode: Mode) -> Vec<Continuation> {
+        self.maps(mode).iter()
+            .map(|(keys, binding)| {
+                let description = match binding {
+                    Binding::Keys { remap: false, .. } => format!("* {}", binding_name(binding)),
+                    _ => binding_name(binding),
+                };
+                Continuation::new(format_keys(keys), description)
+            })
+            .collect()
+    }
+
+    /// Ways to finish `sequence` in `mode`, counting both mappings and the
+    /// built-in keys; empty when no longer sequence starts with it
+    pub fn continuations(&self, mode: Mode, sequence: &[KeyEvent]) -> Vec<Continuation> {
+        let maps = match mode {
+            Mode::Normal => &self.normal,
+            Mode::Insert => &self.insert,
+            Mode::Visual { .. } => return visual_continuations(sequence),
+            _ => return Vec::new(),
+        };
+        let normalized: Vec<KeyEvent> = sequence.iter().copied().map(normalize).collect();
+        let mut next: Vec<Continuation> = maps.iter()
+            .filter_map(|(keys, binding)| {
+                let rest = keys.strip_prefix(normalized.as_slice()).filter(|rest| !rest.is_empty())?;
+                Some(Continuation::new(format_keys(rest), binding_name(binding)))
+            })
+            .collect();
+        if mode == Mode::Normal {
+            next.extend(builtin_continuations(sequence));
+        }
+        next
+    }
+
+    /// Whether the pending `keys` should stop waiting and run as typed,
+    /// `elapsed` after the last of them. Only a sequence that could still
+    /// become a mapping times out; one that is also the start of a
+    /// built-in sequence keeps waiting unless it's a mapping itself.
+    /// `operator` is the operator typed before them, if one is pending.
+    pub fn times_out(
+        &self,
+        mode: Mode,
+        operator: Option<KeyEvent>,
+        keys: &[KeyEvent],
+        elapsed: Duration,
+        timeout: Duration,
+    ) -> bool {
+        let Lookup::Prefix(exact) = self.lookup(mode, keys) else {
+            return false;
+        };
+        if elapsed < timeout {
+            return false;
+        }
+        let sequence: Vec<KeyEvent> = operator.into_iter().chain(keys.iter().copied()).collect();
+        exact.is_some() || mode != Mode::Normal || builtin_continuations(&sequence).is_empty()
+    }
+
+    pub fn lookup(&self, mode: Mode, sequence: &[KeyEvent]) -> Lookup {
+        let maps = match mode {
+            Mode::Normal => &self.normal,
+            Mode::Insert => &self.insert,
+            _ => return Lookup::None,
+        };
+        let sequence: Vec<KeyEvent> = sequence.iter().copied().map(normalize).collect();
+        let exact = maps.iter().find(|(k, _)| *k == sequence).map(|(_, b)| b.clone());
+        let longer = maps.iter().any(|(k, _)| k.len() > sequence.len() && k.starts_with(&sequence));
+        match (exact, longer) {
+            (exact, true) => Lookup::Prefix(exact),
+            (Some(binding), false) => Lookup::Found(binding),
+            (None, false) => Lookup::None,
+        }
+    }
+}
+
+impl Keymap {
+    fn maps(&self, mode: Mode) -> &[(Vec<KeyEvent>, Binding)] {
+        match mode {
+            Mode::Normal => &self.normal,
+            Mode::Insert => &self.insert,
+            _ => &[],
+        }
+    }
+
+    /// Whether `keys` are mapped in `mode`
+    fn is_mapped(&self, mode: Mode, keys: &[KeyEvent]) -> bool {
+        let keys: Vec<KeyEvent> = keys.iter().copied().map(normalize).collect();
+        self.maps(mode).iter().any(|(k, _)| *k == keys)
+    }
+
+    /// Every action that means something in `mode`, with the keys that
+    /// perform it now (empty when a mapping took its only key over), for
+    /// the help screen and the command palette
+    pub fn actions(&self, mode: Mode) -> Vec<(Action, Continuation)> {
+        ACTIONS.iter()
+            .filter(|&&(action, _, _, _)| action.keys(mode).is_some())
+            .map(|&(action, name, _, _)| {
+                let mut keys: Vec<String> = self.maps(mode).iter()
+                    .filter(|(_, binding)| *binding == Binding::Action(action))
+                    .map(|(keys, _)| format_keys(keys))
+                    .collect();
+                if let Some(default) = action.keys(mode).filter(|default| !self.is_mapped(mode, default)) {
+                    keys.push(format_keys(&default));
+                }
+                (action, Continuation::new(keys.join(" "), describe(name)))
+            })
+            .collect()
+    }
+
+    /// Every key in `mode` with what it does, mappings included, for the
+    /// help screen. Built-in keys that a mapping took over are left out.
+    pub fn bindings(&self, mode: Mode) -> Vec<Continuation> {
+        let maps = self.maps(mode);
+        let mapped = |keys: &[KeyEvent]| self.is_mapped(mode, keys);
+        let mut bindings: Vec<Continuation> = self.actions(mode)
+            .into_iter()
+            .map(|(_, continuation)| continuation)
+            .filter(|continuation| !continuation.keys.is_empty())
+            .collect();
+        if mode == Mode::Normal {
+            for &(keys, description) in OTHER_SEQUENCES {
+                if parse_keys(keys).is_ok_and(|keys| !mapped(&keys)) {
+                    bindings.push(Continuation::new(keys, description));
+                }
+            }
+        }
+        for (keys, binding) in maps {
+            if !matches!(binding, Binding::Action(_)) {
+                bindings.push(Continuation::new(format_keys(keys), binding_name(binding)));
+            }
+        }
+        bindings
+    }
+}
+
+/// Operators, their motions and text objects, and registers, for the help
+/// screen
+pub fn operator_help() -> Vec<Continuation> {
+    let mut help = Vec::new();
+    for (op, verb) in [('d', "delete"), ('c', "change"), ('y', "yank")] {
+        help.push(Continuation::new(format!("{}{}", op, op), format!("{} line", verb)));
+        help.push(Continuation::new(format!("{}{{motion}}", op), format!("{} over a motion", verb)));
+        help.push(Continuation::new(format!("{}i{{object}}", op), format!("{} inside an object", verb)));
+        help.push(Continuation::new(format!("{}a{{object}}", op), format!("{} around an object", verb)));
+    }
+    let motions: Vec<&str> = OPERATOR_MOTIONS.iter()
+        .filter_map(|action| ACTIONS.iter().find(|(a, _, _, _)| a == action)?.2)
+        .collect();
+    help.push(Continuation::new("{motion}", motions.join(" ")));
+    let objects: Vec<String> = TEXT_OBJECTS.iter().map(|(key, name)| format!("{} {}", key, name)).collect();
+    help.push(Continuation::new("{object}", objects.join(", ")));
+    let quote = key(KeyCode::Char('"'));
+    for register in builtin_continuations(&[quote]) {
+        help.push(Continuation::new(format!("\"{}", register.keys), register.description));
+    }
+    help
+}
+
+fn binding_name(binding: &Binding) -> String {
+    match binding {
+        Binding::Action(action) => action.name().to_string(),
+        Binding::Command(command) => format!(":{}", command),
+        Binding::Keys { keys, .. } => format_keys(keys),
+    }
+}
+
+fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Normal => "normal",
+        Mode::Insert => "insert",
+        Mode::Visual { .. } => "visual",
+        Mode::Command => "command",
+    }
+}
+
+fn key(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::NONE)
+}
+
+/// Compare keys by code and modifiers only. Shift is implied by the
+/// character for letters and symbols, and terminals disagree on sending it.
+fn normalize(key: KeyEvent) -> KeyEvent {
+    let modifiers = match key.code {
+        KeyCode::Char(_) => key.modifiers - KeyModifiers::SHIFT,
+        _ => key.modifiers,
+    };
+    KeyEvent::new(key.code, modifiers)
+}
+
+/// Write keys in the notation `parse_keys` reads
+pub fn format_keys(keys: &[KeyEvent]) -> String {
+    keys.iter().map(|&key| format_key(key)).collect()
+}
+
+fn format_key(key: KeyEvent) -> String {
+    let name = match key.code {
+        KeyCode::Char('<') => "lt".to_string(),
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Esc => "Esc".to_string(),
+        KeyCode::Enter => "CR".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::BackTab => "S-Tab".to_string(),
+        KeyCode::Backspace => "BS".to_string(),
+        KeyCode::Delete => "Del".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        other => format!("{:?}", other),
+    };
+    let mut prefix = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        prefix.push_str("C-");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        prefix.push_str("A-");
+    }
+    let plain_char = matches!(key.code, KeyCode::Char(c) if c != '<' && c != ' ');
+    if prefix.is_empty() && (plain_char || name.len() == 1) {
+        name
+    } else {
+        format!("<{}{}>", prefix, name)
+    }
+}
+
+/// Parse vim-style key notation: plain characters plus `<C-x>`, `<A-x>`,
+/// `<S-Tab>`, `<Esc>`, `<CR>`, `<Tab>`, `<BS>`, `<Del>`, `<Space>`,
+/// `<Up>`/`<Down>`/`<Left>`/`<Right>`, `<Home>`, `<End>`, `<PageUp>`,
+/// `<PageDown>`, `<F1>`…`<F12>` and `<lt>` for a literal `<`.
+pub fn parse_keys(notation: &str) -> Result<Vec<KeyEvent>> {
+    let mut keys = Vec::new();
+    let mut rest = notation;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '<' {
+            if let Some(end) = rest.find('>').filter(|&end| end > 1) {
+                keys.push(parse_special(&rest[1..end])?);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        // Terminals report capital letters with Shift held
+        let modifiers = if ch.is_ascii_uppercase() { KeyModifiers::SHIFT } else { KeyModifiers::NONE };
+        keys.push(KeyEvent::new(KeyCode::Char(ch), modifiers));
+        rest = &rest[ch.len_utf8()..];
+    }
+    Ok(keys)
+}
+
+/// The inside of a `<…>` key
+fn parse_special(name: &str) -> Result<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut base = name;
+    while let Some((prefix, tail)) = base.split_once('-').filter(|(_, tail)| !tail.is_empty()) {
+        modifiers |= match prefix.to_ascii_uppercase().as_str() {
+            "C" => KeyModifiers::CONTROL,
+            "A" | "M" => KeyModifiers::ALT,
+            "S" => KeyModifiers::SHIFT,
+            _ => bail!("unknown modifier in <{}>", name),
+        };
+        base = tail;
+    }
+    let code = match base.to_ascii_lowercase().as_str() {
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "tab" if modifiers.contains(KeyModifiers::SHIFT) => {
+            modifiers -= KeyModifiers::SHIFT;
+            KeyCode::BackTab
+        }
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        lower => match lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n @ 1..=12) => KeyCode::F(n),
+            _ => {
+                let mut chars = base.chars();
+                match (chars.next(), chars.next()) {
+                    // <C-j> and friends; a control letter is sent lowercase
+                    (Some(c), None) if !modifiers.is_empty() => {
+                        KeyCode::Char(if modifiers.contains(KeyModifiers::CONTROL) { c.to_ascii_lowercase() } else { c })
+                    }
+                    _ => bail!("unknown key <{}>", name),
+                }
+            }
+        },
+    };
+    Ok(KeyEvent::new(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(parse_keys("gs").unwrap(), vec![key(KeyCode::Char('g')), key(KeyCode::Char('s'))]);
+        assert_eq!(parse_keys("<C-j>").unwrap(), vec![ctrl('j')]);
+        assert_eq!(parse_keys("<Esc><lt>x").unwrap(), vec![
+            key(KeyCode::Esc),
+            key(KeyCode::Char('<')),
+            key(KeyCode::Char('x')),
+        ]);
+        assert_eq!(parse_keys("<F5>").unwrap(), vec![key(KeyCode::F(5))]);
+        assert_eq!(parse_keys("<S-Tab>").unwrap(), vec![key(KeyCode::BackTab)]);
+        assert_eq!(parse_keys("<").unwrap(), vec![key(KeyCode::Char('<'))]);
+        assert!(parse_keys("<Bogus>").is_err());
+    }
+
+    #[test]
+    fn test_lookup_sequences() {
+        let mut keymap = Keymap::new();
+        keymap.map(Mode::Normal, "gs", Binding::parse("structural_next_sibling").unwrap()).unwrap();
+        keymap.map(Mode::Normal, "g", Binding::parse(":tree").unwrap()).unwrap();
+        keymap.map(Mode::Insert, "jk", Binding::parse("normal_mode").unwrap()).unwrap();
+
+        let g = key(KeyCode::Char('g'));
+        let s = key(KeyCode::Char('s'));
+        assert_eq!(keymap.lookup(Mode::Normal, &[g]), Lookup::Prefix(Some(Binding::Command("tree".into()))));
+        assert_eq!(keymap.lookup(Mode::Normal, &[g, s]), Lookup::Found(Binding::Action(Action::StructuralNextSibling)));
+        assert_eq!(keymap.lookup(Mode::Normal, &[s]), Lookup::None);
+        // Shifted characters match whether or not the terminal reports Shift
+        keymap.map(Mode::Normal, "S", Binding::Action(Action::Quit)).unwrap();
+        let shifted = KeyEvent::new(KeyCode::Char('S'), KeyModifiers::SHIFT);
+        assert_eq!(keymap.lookup(Mode::Normal, &[shifted]), Lookup::Found(Binding::Action(Action::Quit)));
+
+        assert!(matches!(keymap.lookup(Mode::Insert, &[key(KeyCode::Char('j'))]), Lookup::Prefix(None)));
+        assert!(keymap.map(Mode::Insert, "<C-q>", Binding::Action(Action::Quit)).is_err());
+    }
+
+    #[test]
+    fn test_map_keys() {
+        let keys = |notation: &str| parse_keys(notation).unwrap();
+        let expand = |keymap: &Keymap, lhs: &str| {
+            let Lookup::Found(binding) = keymap.lookup(Mode::Normal, &keys(lhs)) else {
+                panic!("{} isn't mapped", lhs);
+            };
+            format_keys(&keymap.expand(Mode::Normal, &keys(lhs), &binding).unwrap())
+        };
+        let mut keymap = Keymap::new();
+        keymap.map(Mode::Normal, "gs", Binding::Keys { keys: keys("<C-j>"), remap: true }).unwrap();
+        keymap.map(Mode::Normal, ",s", Binding::Keys { keys: keys("gsgs"), remap: true }).unwrap();
+        keymap.map(Mode::Normal, ",n", Binding::Keys { keys: keys("gs"), remap: false }).unwrap();
+        keymap.map(Mode::Normal, ",w", Binding::Keys { keys: keys("gs,s"), remap: true }).unwrap();
+        keymap.map(Mode::Normal, "j", Binding::Keys { keys: keys("jzz"), remap: true }).unwrap();
+        keymap.map(Mode::Normal, "zz", Binding::parse(":tree").unwrap()).unwrap();
+        assert_eq!(expand(&keymap, "gs"), "<C-j>");
+        assert_eq!(expand(&keymap, ",s"), "<C-j><C-j>");
+        assert_eq!(expand(&keymap, ",n"), "gs");
+        // One level only: the gs that ,s brings into ,w isn't mapped again
+        assert_eq!(expand(&keymap, ",w"), "<C-j>gsgs");
+        // The mapped keys at the start of their own right-hand side aren't
+        // mapped again
+        assert_eq!(expand(&keymap, "j"), "j:tree<CR>");
+
+        let listed: Vec<String> = keymap.mappings(Mode::Normal).into_iter()
+            .map(|c| format!("{} {}", c.keys, c.description))
+            .collect();
+        assert_eq!(listed[..3], ["gs <C-j>", ",s gsgs", ",n * gs"]);
+        assert_eq!(listed[5], "zz :tree");
+
+        keymap.unmap(Mode::Normal, "gs").unwrap();
+        assert_eq!(keymap.lookup(Mode::Normal, &keys("gs")), Lookup::None);
+        assert!(keymap.unmap(Mode::Normal, "gs").is_err());
+        assert_eq!(expand(&keymap, ",s"), "gsgs");
+    }
+
+    #[test]
+    fn test_action_keys() {
+        assert_eq!(Action::from_name("next_key"), Some(Action::NextKey));
+        assert_eq!(Action::NextKey.name(), "next_key");
+        assert_eq!(Action::StructuralNextSibling.keys(Mode::Normal), Some(vec![ctrl('j')]));
+        assert_eq!(Action::MoveLeft.keys(Mode::Insert), Some(vec![key(KeyCode::Left)]));
+        // q doesn't quit any more; quit runs :q
+        assert_eq!(Action::Quit.keys(Mode::Normal), Binding::Command("q".into()).keys(Mode::Normal));
+        assert_eq!(Action::QuitWithoutSaving.keys(Mode::Normal), Some(vec![key(KeyCode::Char('Z')), key(KeyCode::Char('Q'))]));
+        assert_eq!(
+            Binding::Command("w".into()).keys(Mode::Normal),
+            Some(vec![key(KeyCode::Char(':')), key(KeyCode::Char('w')), key(KeyCode::Enter)])
+        );
+    }
+
+    #[test]
+    fn test_actions() {
+        let keys_of = |keymap: &Keymap, action| {
+            keymap.actions(Mode::Normal).into_iter().find(|&(a, _)| a == action).map(|(_, c)| c.keys)
+        };
+        let mut keymap = Keymap::new();
+        assert_eq!(keys_of(&keymap, Action::NextValue).as_deref(), Some("]v"));
+        assert_eq!(keys_of(&keymap, Action::Newline), None);
+        // A mapping shows beside the default; taking the default's keys hides it
+        keymap.map(Mode::Normal, "gv", Binding::Action(Action::NextValue)).unwrap();
+        assert_eq!(keys_of(&keymap, Action::NextValue).as_deref(), Some("gv ]v"));
+        keymap.map(Mode::Normal, "]v", Binding::parse(":tree").unwrap()).unwrap();
+        keymap.map(Mode::Normal, "[v", Binding::Action(Action::Quit)).unwrap();
+        assert_eq!(keys_of(&keymap, Action::NextValue).as_deref(), Some("gv"));
+        assert_eq!(keys_of(&keymap, Action::PrevValue).as_deref(), Some(""));
+        assert_eq!(keymap.actions(Mode::Normal)[0].1.description, "move left");
+    }
+
+    #[test]
+    fn test_continuations() {
+        let keys = |notation: &str| parse_keys(notation).unwrap();
+        let listed = |next: Vec<Continuation>| -> Vec<String> {
+            next.into_iter().map(|c| format!("{}: {}", c.keys, c.description)).collect()
+        };
+        let keymap = Keymap::new();
+        let after_bracket = listed(keymap.continuations(Mode::Normal, &keys("]")));
+        for hint in ["l: next key", "v: next value", "e: next error", "]: next record", "j: next sibling", "$: structural last child"] {
+            assert!(after_bracket.contains(&hint.to_string()), "{:?}", after_bracket);
+        }
+        assert!(listed(keymap.continuations(Mode::Normal, &keys("<C-w>"))).contains(&"v: vertical split".to_string()));
+        assert!(listed(keymap.continuations(Mode::Normal, &keys("\""))).contains(&"0: last yank".to_string()));
+
+        let after_d = listed(keymap.continuations(Mode::Normal, &keys("d")));
+        assert_eq!(after_d[0], "d: delete line");
+        assert!(after_d.contains(&"w: word forward".to_string()));
+        assert!(after_d.contains(&"n: delete node".to_string()));
+        assert!(listed(keymap.continuations(Mode::Normal, &keys("ci"))).contains(&"\": inner string".to_string()));
+        assert_eq!(listed(keymap.continuations(Mode::Normal, &keys("]g"))), vec!["1-9: that child"]);
+
+        // Complete sequences and plain keys have nothing to wait for
+        assert!(keymap.continuations(Mode::Normal, &keys("]l")).is_empty());
+        assert!(keymap.continuations(Mode::Normal, &keys("dd")).is_empty());
+        assert!(keymap.continuations(Mode::Normal, &keys("x")).is_empty());
+        assert!(keymap.continuations(Mode::Insert, &keys("j")).is_empty());
+
+        // Mappings show up alongside the built-in keys
+        let mut keymap = Keymap::new();
+        keymap.map(Mode::Normal, "gs", Binding::parse("structural_next_sibling").unwrap()).unwrap();
+        keymap.map(Mode::Insert, "jk", Binding::parse("normal_mode").unwrap()).unwrap();
+        let after_g = listed(keymap.continuations(Mode::Normal, &keys("g")));
+        assert!(after_g.contains(&"s: structural_next_sibling".to_string()));
+        assert!(after_g.contains(&"g: goto top".to_string()));
+        assert_eq!(listed(keymap.continuations(Mode::Insert, &keys("j"))), vec!["k: normal_mode"]);
+    }
+
+    #[test]
+    fn test_times_out() {
+        let keys = |notation: &str| parse_keys(notation).unwrap();
+        let timeout = Duration::from_millis(DEFAULT_TIMEOUTLEN);
+        let (soon, late) = (Duration::from_millis(300), Duration::from_millis(1200));
+        let mut keymap = Keymap::new();
+        keymap.map(Mode::Normal, "gs", Binding::parse("structural_next_sibling").unwrap()).unwrap();
+        keymap.map(Mode::Normal, ",", Binding::parse(":tree").unwrap()).unwrap();
+        keymap.map(Mode::Normal, ",t", Binding::parse(":w").unwrap()).unwrap();
+        keymap.map(Mode::Insert, "jk", Binding::parse("normal_mode").unwrap()).unwrap();
+
+        // A second key within the timeout still makes the mapping
+        assert!(!keymap.times_out(Mode::Normal, None, &keys(","), soon, timeout));
+        assert!(!keymap.times_out(Mode::Insert, None, &keys("j"), soon, timeout));
+        // Past it, the prefix runs as what it is by itself
+        assert!(keymap.times_out(Mode::Normal, None, &keys(","), late, timeout));
+        assert!(keymap.times_out(Mode::Insert, None, &keys("j"), late, timeout));
+        // g also starts built-in sequences, which wait for as long as it takes
+        assert!(!keymap.times_out(Mode::Normal, None, &keys("g"), late, timeout));
+        // Nothing to wait for when no mapping starts with the keys
+        assert!(!keymap.times_out(Mode::Normal, None, &keys("x"), late, timeout));
+        assert!(keymap.times_out(Mode::Normal, None, &keys(","), timeout, timeout));
+        assert!(!keymap.times_out(Mode::Normal, None, &keys(","), late, Duration::from_millis(2000)));
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let timeout = Duration::from_millis(DEFAULT_TTIMEOUTLEN);
+        let esc = key(KeyCode::Esc);
+        let mut escapes = EscapeSequences::new();
+
+        // An Esc waits; with nothing after it, it goes through by itself
+        assert_eq!(escapes.key(esc, at(0), timeout), []);
+        assert_eq!(escapes.expire(at(20), timeout), []);
+        assert_eq!(escapes.expire(at(60), timeout), [esc]);
+        assert_eq!(escapes.expire(at(200), timeout), []);
+
+        // An arrow key arriving in pieces, each within the timeout
+        assert_eq!(escapes.key(esc, at(100), timeout), []);
+        assert_eq!(escapes.key(key(KeyCode::Char('[')), at(130), timeout), []);
+        assert_eq!(escapes.key(key(KeyCode::Char('A')), at(170), timeout), [key(KeyCode::Up)]);
+
+        // Esc and a key close behind it are Alt with that key
+        assert_eq!(escapes.key(esc, at(300), timeout), []);
+        let alt_x = KeyEvent::new(KeyCode::Char('x'), KeyModifiers::ALT);
+        assert_eq!(escapes.key(key(KeyCode::Char('x')), at(310), timeout), [alt_x]);
+
+        // The same keys past the timeout are Esc, then the key
+        assert_eq!(escapes.key(esc, at(400), timeout), []);
+        assert_eq!(escapes.key(key(KeyCode::Char('x')), at(500), timeout), [esc, key(KeyCode::Char('x'))]);
+        assert_eq!(escapes.key(esc, at(600), timeout), []);
+        assert_eq!(escapes.key(key(KeyCode::Char('[')), at(610), timeout), []);
+        assert_eq!(escapes.key(key(KeyCode::Char('A')), at(700), timeout), [
+            esc,
+            key(KeyCode::Char('[')),
+            key(KeyCode::Char('A')),
+        ]);
+
+        // Esc Esc is two of them, and ttimeoutlen=0 holds nothing back
+        assert_eq!(escapes.key(esc, at(800), timeout), []);
+        assert_eq!(escapes.key(esc, at(810), timeout), [esc]);
+        assert_eq!(escapes.key(key(KeyCode::Enter), at(820), timeout), [esc, key(KeyCode::Enter)]);
+        assert_eq!(escapes.key(esc, at(900), Duration::ZERO), [esc]);
+    }
+
+    #[test]
+    fn test_format_keys() {
+        for notation in ["gs", "<C-w>v", "<Esc><lt>x", "<F5>", "<S-Tab>", "<Space>"] {
+            assert_eq!(format_keys(&parse_keys(notation).unwrap()), notation);
+        }
+    }
+}
@@ -7,13 +7,17 @@ pub mod visual;
 pub mod command;
 pub mod operator;
 pub mod register;
+pub mod clipboard;
+pub mod keymap;
 
 use crate::buffer::Buffer;
 use crate::buffer::cursor::Cursor;
 use crate::edit::undo::UndoStack;
+use crate::edit::whitespace::WhitespaceOp;
 
 pub use operator::{Operator, Motion, PendingOperator, OperatorResult};
 pub use register::RegisterMap;
+pub use keymap::{builtin_continuations, format_keys, operator_help, parse_keys, Action, Binding, Continuation, EscapeSequences, Keymap, KeyQueue, Lookup, DEFAULT_TIMEOUTLEN, DEFAULT_TTIMEOUTLEN};
 
 /// Editor mode states
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -46,6 +50,13 @@ pub struct EditorContext<'a> {
     pub register_map: &'a mut RegisterMap,
     pub pending_operator: &'a mut Option<PendingOperator>,
     pub structural_index: Option<&'a crate::parser::StructuralIndex>,
+    pub folds: &'a crate::ui::fold::FoldedLines,
+    pub keys: &'a mut KeyQueue,
+    /// Shiftwidth to lay out pasted JSON with, while :set formatpaste is on
+    pub format_paste: Option<usize>,
+    /// :set smartcomma - `o` after an object member opens a key to fill
+    /// in, and dd on an object's last members takes the comma before them
+    pub smart_comma: bool,
 }
 
 /// Result of handling an input event
@@ -59,28 +70,351 @@ pub enum InputResult {
     Quit,
     /// Input not handled, pass to next handler
     NotHandled,
-    /// Request structural navigation
-    StructuralNav(StructuralNavAction),
+    /// Request structural navigation, repeated by a count
+    StructuralNav(StructuralNavAction, usize),
     /// Request to clear node tracking (cursor moved manually)
     ClearNodeTracking,
     /// Display a message to the user
     Message(String),
+    /// A command that needs editor state beyond the EditorContext
+    Command(AppCommand),
+}
+
+/// Commands carried out by the application rather than a mode handler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppCommand {
+    /// Report the first structural error
+    Validate,
+    /// :split-docs - read the file as documents one after another, rather
+    /// than one with trailing content
+    SplitDocs,
+    /// :yamlview - show the buffer, read as YAML, as JSON (read-only)
+    YamlView,
+    /// :to-ndjson / :from-ndjson - rewrite the top-level array as JSON
+    /// Lines, a record to a line, or JSON Lines as an array
+    Ndjson(crate::edit::ndjson::Direction),
+    /// :yank-path / :yank-value: copy the cursor's path, or the raw text
+    /// of the value under it, to the system clipboard
+    CopyToClipboard { path: bool },
+    /// :pipe cmd: filter the node under the cursor (the selection with
+    /// :'<,'>pipe, the buffer with :%pipe) through a command
+    Pipe { range: PipeRange, command: String },
+    /// :stats: summarize the value under the cursor in a popup
+    Stats,
+    /// :tokens / :nodes - the tokens in view, or the indexed nodes around
+    /// the cursor, in a read-only pane
+    Inspect { tokens: bool },
+    /// :profile - how long recent operations took, in a read-only pane
+    Profile,
+    /// :tasks - the work running in the background, in a read-only pane
+    Tasks,
+    /// :checksum[!] - the SHA-256 and size of the buffer; ! copies the hash
+    Checksum { copy: bool },
+    /// :schema load {file} validates against a JSON Schema, :schema off
+    /// (None) stops
+    Schema(Option<String>),
+    /// :diff {file} compares the buffer with a file; plain :diff shows the
+    /// last comparison again
+    Diff(Option<String>),
+    /// :diff off
+    DiffOff,
+    /// :diffsaved compares the buffer with its file as last saved
+    DiffSaved,
+    /// :filter {predicate} hides the elements of the array under the cursor
+    /// that fail it; :filter off (None) shows them again
+    Filter(Option<String>),
+    /// :undolist: list the branches of the undo tree
+    UndoList,
+    /// :undo-here - take back the latest change inside the container under
+    /// the cursor, or inside a range, keeping the ones made since
+    UndoHere(PipeRange),
+    /// / - type a search pattern
+    SearchPrompt,
+    /// /pattern, :/pattern, :'<,'>/pattern; an empty pattern repeats the
+    /// last one
+    Search { pattern: String, scope: SearchScope },
+    /// :key name - the next object key with that name (name* for a prefix)
+    SearchKey(String),
+    /// :value literal - the next scalar equal to the literal
+    SearchValue(String),
+    /// :keys picks a key of the object around the cursor; :keys! (all)
+    /// any key path in the document
+    Keys { all: bool },
+    /// :mksession[!] file - write where the file was left to `file`; !
+    /// overwrites one that exists
+    MakeSession { path: String, force: bool },
+    /// :source-session file - go back to a session :mksession wrote
+    SourceSession(String),
+    /// Ctrl-p, :palette - pick any action or command to run, the count
+    /// typed before going to the action
+    Palette { count: Option<usize> },
+    /// :outline - pick a top-level section of the document to go to
+    Outline,
+    /// Ctrl-o / Tab - back or forward through the jump list
+    Jump { back: bool, count: usize },
+    /// `x goes to mark x, 'x to the first non-blank of its line
+    Mark { name: char, linewise: bool },
+    /// :marks - list the marks that are set
+    Marks,
+    /// :grep pattern, :g/pattern/ - list every match in the location list,
+    /// or every one in the lines of `range`
+    Grep { pattern: String, range: PipeRange },
+    /// :extract[!] path [> file] - every value at a path with wildcards, in
+    /// the location list or written to a file (! overwrites it)
+    Extract { pattern: crate::navigation::extract::Pattern, path: String, output: Option<String>, force: bool },
+    /// :bookmark add/delete/list
+    Bookmark(BookmarkOp),
+    /// :script start/stop/save
+    Script(ScriptOp),
+    /// :copen / :cclose - show or hide the location list pane
+    LocationList { open: bool },
+    /// ]q / [q - the next or previous entry of the location list
+    Location { forward: bool, count: usize },
+    /// n / N - the next match of the last search, forward or back
+    SearchNext { forward: bool, count: usize },
+    /// :set <option>
+    Set(String),
+    /// z-prefixed folding commands
+    Fold(FoldAction),
+    /// :tree - show or hide the outline sidebar
+    ToggleTree,
+    /// ze - show a long string in full, or elided again
+    ToggleElision,
+    /// zh/zl/zH/zL - scroll the view sideways without moving the cursor
+    ScrollHorizontal(HorizontalScroll),
+    /// gj/gk - move by screen row when lines wrap
+    DisplayLine { down: bool },
+    /// K - pop up the value under the cursor, pretty-printed
+    PreviewValue,
+    /// Ctrl-g - the file, its state and where the cursor is; g Ctrl-g adds
+    /// word and character counts and the node under the cursor
+    FileInfo { detailed: bool },
+    /// :source <file> - load settings and key mappings
+    Source(String),
+    /// :q / :q! - close the window, or quit from the last one
+    Quit { force: bool },
+    /// :w / :w! - save, unless nothing has changed; ! saves anyway,
+    /// without asking about a large write
+    Write { force: bool },
+    /// :wq / :x / ZZ - save (:x and ZZ only if there are changes), then
+    /// close the window, or quit once the save is on disk
+    WriteQuit { always: bool },
+    /// :map / :noremap {lhs} {rhs} - map keys in normal mode for this
+    /// session; with no keys, list the mappings
+    Map { mapping: Option<(String, String)>, remap: bool },
+    /// :unmap {lhs} - take a mapping away
+    Unmap(String),
+    /// :e / :e! - load the file again from disk; ! throws away changes
+    Reload { force: bool },
+    /// :bd / :bd! - close the file, leaving an empty buffer; ! throws away
+    /// changes
+    CloseBuffer { force: bool },
+    /// :split / :vsplit - view the buffer in a second window
+    Split { vertical: bool },
+    /// :close - close the focused window
+    CloseWindow,
+    /// :help - list the keys and commands
+    Help,
+    /// dn / yn / ]p / ]m / [m and the node-editing ex commands, with a register
+    Node { op: NodeOp, register: Option<char> },
+    /// :force - make the node edit :set strictedits last refused
+    ForceEdit,
+    /// :retab / :trimws over the buffer, or the lines of the selection
+    Whitespace { op: WhitespaceOp, range: PipeRange },
+    /// :[range]w[!] file - write just the lines of `range` to a file; !
+    /// overwrites one that exists
+    WriteRange { range: PipeRange, path: String, force: bool },
+    /// :renameall[!] - give every key with one name another
+    RenameAll(crate::edit::renameall::RenameAll),
+    /// J / gJ - join `count` lines, or those of the visual `selection`;
+    /// `raw` takes out only the line breaks
+    Join { count: usize, raw: bool, selection: Option<std::ops::Range<usize>> },
+    /// Ctrl-n / Ctrl-p in insert mode - complete the key being typed from
+    /// the objects beside its own, starting from the first or last match
+    CompleteKey { forward: bool },
+}
+
+/// Whole-node edits, which keep commas and indentation right
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeOp {
+    /// dn: delete the node under the cursor with its key and comma
+    Delete,
+    /// yn: yank the node under the cursor with its key
+    Yank,
+    /// ]p: paste a node as the next sibling
+    Paste,
+    /// [m / :move-up: swap the node with its previous sibling
+    MoveUp,
+    /// ]m / :move-down: swap the node with its next sibling
+    MoveDown,
+    /// :rename[!] name: give the member under the cursor a new key; ! even
+    /// if the object already has one by that name
+    Rename { name: String, force: bool },
+    /// :wrap [ / :wrap {key}: surround the value with an array, or an
+    /// object under `key`
+    Wrap { key: Option<String> },
+    /// :unwrap[!]: replace a container with its only member; ! splices
+    /// several into the parent
+    Unwrap { splice: bool },
+    /// gt{s,u,b,n} / :convert: rewrite the value as another type
+    Convert(crate::edit::structural::Conversion),
+    /// :edit-string: edit the decoded text of the string under the cursor
+    /// in an overlay
+    EditString,
+    /// :array sort[!]|unique|count: reorder or count the enclosing array
+    Array(crate::edit::array::ArrayOp),
+    /// gq: wrap the string value under the cursor, or the one the visual
+    /// `selection` is inside, at :set textwidth
+    Rewrap { selection: Option<std::ops::Range<usize>> },
+    /// :pipe cmd: replace the value under the cursor with what `cmd`
+    /// prints when given it
+    Pipe(String),
+    /// :wnode[!] [++pretty] path: write the value under the cursor to a
+    /// file; ! overwrites one that exists
+    Export { path: String, pretty: bool, force: bool },
+}
+
+impl NodeOp {
+    /// What the edit did, as :undolist lists it
+    pub fn label(&self) -> String {
+        use crate::edit::structural::Conversion;
+        match self {
+            NodeOp::Delete => "delete node".to_string(),
+            NodeOp::Paste => "paste node".to_string(),
+            NodeOp::MoveUp => "move node up".to_string(),
+            NodeOp::MoveDown => "move node down".to_string(),
+            NodeOp::Rename { name, .. } => format!("rename key to \"{}\"", name),
+            NodeOp::Wrap { key: None } => "wrap in array".to_string(),
+            NodeOp::Wrap { key: Some(key) } => format!("wrap under \"{}\"", key),
+            NodeOp::Unwrap { splice: false } => "unwrap".to_string(),
+            NodeOp::Unwrap { splice: true } => "splice into parent".to_string(),
+            NodeOp::Convert(Conversion::Stringify) => "convert to string".to_string(),
+            NodeOp::Convert(Conversion::Unstringify) => "convert from string".to_string(),
+            NodeOp::Convert(Conversion::ToggleBool) => "toggle boolean".to_string(),
+            NodeOp::Convert(Conversion::Null) => "set to null".to_string(),
+            NodeOp::EditString => "edit string".to_string(),
+            NodeOp::Array(_) => "array".to_string(),
+            NodeOp::Rewrap { .. } => "wrap string".to_string(),
+            NodeOp::Pipe(command) => format!("pipe through {}", command),
+            NodeOp::Yank | NodeOp::Export { .. } => String::new(),
+        }
+    }
+}
+
+/// The text :pipe sends to its command, or the range another ex command
+/// was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeRange {
+    /// The value under the cursor
+    Node,
+    /// The last visual selection, :'<,'>
+    Selection,
+    /// Everything, :%
+    Buffer,
+    /// Whole lines, :.,+100 or :'a,$
+    Lines(command::LineRange),
+}
+
+/// Where a search looks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchScope {
+    /// The whole buffer, /
+    Buffer,
+    /// The node under the cursor, :/
+    Node,
+    /// The last visual selection, :'<,'>/
+    Selection,
+}
+
+/// Horizontal scroll amounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HorizontalScroll {
+    /// zh: one column left
+    Left,
+    /// zl: one column right
+    Right,
+    /// zH: half a screen left
+    HalfLeft,
+    /// zL: half a screen right
+    HalfRight,
+}
+
+/// :bookmark subcommands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookmarkOp {
+    /// :bookmark add [note]: bookmark the node under the cursor
+    Add(String),
+    /// :bookmark delete: take the bookmark off the node under the cursor
+    Delete,
+    /// :bookmark list: all bookmarks in the location list
+    List,
+}
+
+/// :script subcommands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptOp {
+    /// :script start: record the edits that follow, by path
+    Start,
+    /// :script stop: stop recording, keeping what was recorded
+    Stop,
+    /// :script save file: write the recorded script for `jim --apply`
+    Save(String),
+}
+
+/// Folding commands, acting on the container under the cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldAction {
+    /// za
+    Toggle,
+    /// zo
+    Open,
+    /// zc
+    Close,
+    /// zR: open every fold in the cursor's subtree
+    OpenAll,
+    /// zM: close every container in the cursor's subtree
+    CloseAll,
 }
 
 /// Structural navigation actions
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StructuralNavAction {
     NextSibling,
     PrevSibling,
     Parent,
     FirstChild,
+    /// ]$: the last member of the current container
+    LastChild,
+    /// ]g{n} / :child n: the nth (1-based) member of the current container
+    NthChild(usize),
+    /// :index n: the nth (1-based) element of the array around the current node
+    ArrayIndex(usize),
     NextKey,
     PrevKey,
     NextValue,
     PrevValue,
+    NextError,
+    PrevError,
+    /// ]d / [d: schema diagnostics
+    NextDiagnostic,
+    PrevDiagnostic,
+    /// ]u / [u: duplicate object keys
+    NextDuplicate,
+    PrevDuplicate,
+    /// ]c / [c: :diff changes
+    NextChange,
+    PrevChange,
+    NextRecord,
+    PrevRecord,
 }
 
 /// Trait for mode-specific input handlers
 pub trait ModeHandler {
     fn handle_key(&mut self, key: KeyEvent, ctx: EditorContext) -> Result<InputResult>;
+
+    /// Text from a bracketed paste, which never runs as keys. NotHandled
+    /// leaves it to the application to insert at the cursor.
+    fn handle_paste(&mut self, _text: &str) -> InputResult {
+        InputResult::NotHandled
+    }
 }
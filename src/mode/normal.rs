@@ -1,34 +1,74 @@
+
 use anyhow::Result;
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, Event};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use super::{EditorContext, InputResult, ModeHandler, Mode, StructuralNavAction};
+use super::{AppCommand, EditorContext, FoldAction, HorizontalScroll, InputResult, ModeHandler, Mode, NodeOp, StructuralNavAction};
 use super::operator::{Operator, Motion, Direction, WordMotion, PendingOperator};
+use super::register::is_register;
+use crate::edit::{paste::{self, PasteBehavior}, smartcomma};
+use crate::edit::structural::Conversion;
 use crate::edit::EditOperations;
 
 /// Normal mode handler - navigation and commands
 pub struct NormalMode {
     /// Register to use for next operation (if specified with ")
     selected_register: Option<char>,
+    /// Count typed before a command, e.g. the 3 of 3]j
+    count: Option<usize>,
+    /// What a bracketed paste does (:set pastebehavior)
+    pub paste_behavior: PasteBehavior,
 }
 
 impl NormalMode {
     pub fn new() -> Self {
         Self {
             selected_register: None,
+            count: None,
+            paste_behavior: PasteBehavior::default(),
         }
     }
     
+    /// Whether no count or register is waiting for the next key
+    pub fn is_idle(&self) -> bool {
+        self.count.is_none() && self.selected_register.is_none()
+    }
+    
+    /// What's been typed of a pending operator, e.g. `"a2d3`, for the
+    /// status bar while it waits for a motion
+    pub fn pending_display(&self, pending: &PendingOperator) -> String {
+        let register = self.selected_register.map(|name| format!("\"{}", name)).unwrap_or_default();
+        let before = Some(pending.count).filter(|&count| count > 1).map(|count| count.to_string()).unwrap_or_default();
+        let after = self.count.map(|count| count.to_string()).unwrap_or_default();
+        format!("{}{}{}{}", register, before, pending.operator.key(), after)
+    }
+    
     /// Execute an operator with a motion
     fn execute_operator(&mut self, op: Operator, motion: Motion, ctx: &mut EditorContext) -> Result<()> {
-        let range = motion.calculate_range(ctx.buffer, ctx.cursor.byte_offset)?;
+        let mut range = motion.calculate_range_in(ctx.buffer, ctx.structural_index, ctx.cursor.byte_offset)?;
+        // A structural d takes the commas between nodes along, as dn does
+        if let (Motion::Structural(..), Operator::Delete, Some(index)) = (&motion, op, ctx.structural_index) {
+            range = crate::edit::structural::span_deletion_range(index, range)?;
+        }
         let text = ctx.buffer.slice(range.clone());
         
         match op {
             Operator::Delete => {
+                let comma = match (&motion, ctx.structural_index) {
+                    (Motion::Line(..), Some(index)) if ctx.smart_comma => {
+                        smartcomma::dangling_comma(index, ctx.buffer, range.clone())
+                    }
+                    _ => None,
+                };
                 // Delete and store in register
                 let edit = EditOperations::delete(ctx.buffer, ctx.cursor, range.start, range.end)?;
                 ctx.register_map.set(self.selected_register, text, false);
                 ctx.undo_stack.push(edit);
+                if let Some(comma) = comma {
+                    let edit = EditOperations::delete(ctx.buffer, ctx.cursor, comma, comma + 1)?;
+                    ctx.undo_stack.push(edit);
+                    ctx.cursor.byte_offset = range.start - 1;
+                    (ctx.cursor.line, ctx.cursor.col) = ctx.buffer.offset_to_line_col(ctx.cursor.byte_offset);
+                }
             }
             Operator::Change => {
                 // Delete and enter insert mode
@@ -48,12 +88,48 @@ impl NormalMode {
     }
 }
 
+impl Default for NormalMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ModeHandler for NormalMode {
     fn handle_key(&mut self, key: KeyEvent, mut ctx: EditorContext) -> Result<InputResult> {
+        // Digits make up a count, except a leading 0, which is a motion
+        if let (KeyCode::Char(c @ '0'..='9'), KeyModifiers::NONE) = (key.code, key.modifiers) {
+            if c != '0' || self.count.is_some() {
+                let digit = c as usize - '0' as usize;
+                self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                return Ok(InputResult::NotHandled);
+            }
+        }
+        // Whatever key follows uses the count up
+        let count = self.count.take();
+        // A pending operator waits only for what can finish it; anything
+        // else, Esc included, cancels it and is otherwise ignored
+        if let Some(pending) = *ctx.pending_operator {
+            if !finishes_operator(pending.operator, key) {
+                *ctx.pending_operator = None;
+                self.selected_register = None;
+                return Ok(InputResult::Handled);
+            }
+        }
+        let nav = |action| Ok(InputResult::StructuralNav(action, count.unwrap_or(1)));
         match (key.code, key.modifiers) {
-            // Quit commands
+            // q used to quit, so say what does now; :map q :q<CR> brings
+            // it back
             (KeyCode::Char('q'), KeyModifiers::NONE) => {
-                return Ok(InputResult::Quit);
+                Ok(InputResult::Message("Type :q to quit, ZZ to save and quit, or ZQ to quit without saving".to_string()))
+            }
+            // ZZ saves (if there's anything to save) and quits, ZQ quits
+            // without saving, as :x and :q! do
+            (KeyCode::Char('Z'), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                match ctx.keys.next_key().map(|key| key.code) {
+                    Some(KeyCode::Char('Z')) => Ok(InputResult::Command(AppCommand::WriteQuit { always: false })),
+                    Some(KeyCode::Char('Q')) => Ok(InputResult::Command(AppCommand::Quit { force: true })),
+                    _ => Ok(InputResult::NotHandled),
+                }
             }
             
             // Navigation (handled by existing cursor logic, but we acknowledge it here)
@@ -62,11 +138,17 @@ impl ModeHandler for NormalMode {
                 Ok(InputResult::ClearNodeTracking)
             }
             (KeyCode::Char('j'), KeyModifiers::NONE) => {
-                ctx.cursor.move_down(ctx.buffer);
+                for _ in 0..count.unwrap_or(1) {
+                    ctx.cursor.move_down(ctx.buffer);
+                    ctx.folds.skip_down(ctx.cursor, ctx.buffer);
+                }
                 Ok(InputResult::ClearNodeTracking)
             }
             (KeyCode::Char('k'), KeyModifiers::NONE) => {
-                ctx.cursor.move_up(ctx.buffer);
+                for _ in 0..count.unwrap_or(1) {
+                    ctx.cursor.move_up(ctx.buffer);
+                    ctx.folds.skip_up(ctx.cursor, ctx.buffer);
+                }
                 Ok(InputResult::ClearNodeTracking)
             }
             (KeyCode::Char('l'), KeyModifiers::NONE) => {
@@ -79,6 +161,7 @@ impl ModeHandler for NormalMode {
                 // Move down half a page (approximately 20 lines)
                 for _ in 0..20 {
                     ctx.cursor.move_down(ctx.buffer);
+                    ctx.folds.skip_down(ctx.cursor, ctx.buffer);
                 }
                 Ok(InputResult::ClearNodeTracking)
             }
@@ -86,6 +169,7 @@ impl ModeHandler for NormalMode {
                 // Move up half a page (approximately 20 lines)
                 for _ in 0..20 {
                     ctx.cursor.move_up(ctx.buffer);
+                    ctx.folds.skip_up(ctx.cursor, ctx.buffer);
                 }
                 Ok(InputResult::ClearNodeTracking)
             }
@@ -94,16 +178,17 @@ impl ModeHandler for NormalMode {
             (KeyCode::Char(':'), _) => {
                 Ok(InputResult::ModeSwitch(Mode::Command))
             }
+            (KeyCode::Char('/'), _) => Ok(InputResult::Command(AppCommand::SearchPrompt)),
             
             // Text object handlers (must be before 'i' and 'a' insert mode handlers)
             (KeyCode::Char('i'), KeyModifiers::NONE) if ctx.pending_operator.is_some() => {
                 // Inner text object - wait for next key
-                self.handle_text_object(true, &mut ctx)?;
+                self.handle_text_object(true, count, &mut ctx)?;
                 Ok(InputResult::Handled)
             }
             (KeyCode::Char('a'), KeyModifiers::NONE) if ctx.pending_operator.is_some() => {
                 // Around text object - wait for next key
-                self.handle_text_object(false, &mut ctx)?;
+                self.handle_text_object(false, count, &mut ctx)?;
                 Ok(InputResult::Handled)
             }
             
@@ -126,6 +211,25 @@ impl ModeHandler for NormalMode {
                 Ok(InputResult::ModeSwitch(Mode::Insert))
             }
             (KeyCode::Char('o'), KeyModifiers::NONE) => {
+                // After an object member with :set smartcomma, the new line
+                // starts the next member, and this one gets its comma
+                let member = ctx.structural_index
+                    .filter(|_| ctx.smart_comma)
+                    .and_then(|index| smartcomma::open_member(index, ctx.buffer, ctx.cursor.line));
+                if let Some(member) = member {
+                    if let Some(comma) = member.comma {
+                        let edit = EditOperations::insert(ctx.buffer, ctx.cursor, comma, ",")?;
+                        ctx.undo_stack.push(edit);
+                    }
+                    let line_end = member.line_end + usize::from(member.comma.is_some());
+                    let line = format!("\n{}{}", member.indent, smartcomma::SKELETON);
+                    let edit = EditOperations::insert(ctx.buffer, ctx.cursor, line_end, &line)?;
+                    ctx.undo_stack.push(edit);
+                    // Between the quotes of the key
+                    ctx.cursor.byte_offset -= smartcomma::SKELETON.len() - 1;
+                    (ctx.cursor.line, ctx.cursor.col) = ctx.buffer.offset_to_line_col(ctx.cursor.byte_offset);
+                    return Ok(InputResult::ModeSwitch(Mode::Insert));
+                }
                 // Open line below - move to end of line, insert newline, enter insert mode
                 ctx.cursor.move_end_of_line(ctx.buffer);
                 let edit = EditOperations::insert(
@@ -182,65 +286,236 @@ impl ModeHandler for NormalMode {
             // Structural navigation
             (KeyCode::Char('j'), KeyModifiers::CONTROL) => {
                 // Ctrl+j - next sibling
-                Ok(InputResult::StructuralNav(StructuralNavAction::NextSibling))
+                self.structural_motion(StructuralNavAction::NextSibling, count, &mut ctx)
             }
             (KeyCode::Char('k'), KeyModifiers::CONTROL) => {
                 // Ctrl+k - prev sibling  
-                Ok(InputResult::StructuralNav(StructuralNavAction::PrevSibling))
+                self.structural_motion(StructuralNavAction::PrevSibling, count, &mut ctx)
             }
             (KeyCode::Char('h'), KeyModifiers::CONTROL) => {
                 // Ctrl+h - parent (move out)
-                Ok(InputResult::StructuralNav(StructuralNavAction::Parent))
+                self.structural_motion(StructuralNavAction::Parent, count, &mut ctx)
             }
             (KeyCode::Char('l'), KeyModifiers::CONTROL) => {
                 // Ctrl+l - first child (move in)
-                Ok(InputResult::StructuralNav(StructuralNavAction::FirstChild))
+                self.structural_motion(StructuralNavAction::FirstChild, count, &mut ctx)
+            }
+            (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                // Ctrl+p - command palette; the count goes to what's picked,
+                // as does a register still selected
+                Ok(InputResult::Command(AppCommand::Palette { count }))
+            }
+            (KeyCode::Char('g'), KeyModifiers::CONTROL) => {
+                // Ctrl+g - where the cursor is in the file
+                Ok(InputResult::Command(AppCommand::FileInfo { detailed: false }))
+            }
+            (KeyCode::Char('o'), KeyModifiers::CONTROL) => {
+                // Ctrl+o - back through the jump list
+                Ok(InputResult::Command(AppCommand::Jump { back: true, count: count.unwrap_or(1) }))
+            }
+            // `. and '. - to the last change, or its line
+            (KeyCode::Char(c @ ('`' | '\'')), KeyModifiers::NONE) => match ctx.keys.next_key().map(|key| key.code) {
+                Some(KeyCode::Char(name)) => Ok(InputResult::Command(AppCommand::Mark { name, linewise: c == '\'' })),
+                _ => Ok(InputResult::NotHandled),
+            },
+            (KeyCode::Tab, KeyModifiers::NONE) => {
+                // Tab (Ctrl+i) - forward again
+                Ok(InputResult::Command(AppCommand::Jump { back: false, count: count.unwrap_or(1) }))
             }
             
             // Key/Value navigation with two-key sequences
             (KeyCode::Char(']'), KeyModifiers::NONE) => {
-                // Wait for second key: 'l' for next key, 'v' for next value
-                use crossterm::event;
-                use std::time::Duration;
-                
-                if let Ok(true) = event::poll(Duration::from_millis(500)) {
-                    if let Ok(Event::Key(next_key)) = event::read() {
-                        match next_key.code {
-                            KeyCode::Char('l') => {
-                                return Ok(InputResult::StructuralNav(StructuralNavAction::NextKey));
-                            }
-                            KeyCode::Char('v') => {
-                                return Ok(InputResult::StructuralNav(StructuralNavAction::NextValue));
-                            }
-                            _ => {}
+                // Second key: 'j' for next sibling, 'l' for next key, 'v' for next value, 'e' for next error, 'd' for next schema error, 'u' for next duplicate key, 'c' for next diff change, 'q' for the next location, ']' for next record,
+                // '$' for the last child, 'g' and a digit for that child (the count without one),
+                // 'p' to paste a node after this one, 'm' to move this one down
+                if let Some(next_key) = ctx.keys.next_key() {
+                    match next_key.code {
+                        KeyCode::Char('$') => {
+                            return nav(StructuralNavAction::LastChild);
+                        }
+                        KeyCode::Char('p') => {
+                            let register = self.selected_register.take();
+                            return Ok(InputResult::Command(AppCommand::Node { op: NodeOp::Paste, register }));
+                        }
+                        KeyCode::Char('m') => {
+                            return Ok(InputResult::Command(AppCommand::Node { op: NodeOp::MoveDown, register: None }));
+                        }
+                        KeyCode::Char('g') => {
+                            let n = match ctx.keys.next_key().map(|key| key.code) {
+                                Some(KeyCode::Char(c @ '1'..='9')) => c as usize - '0' as usize,
+                                None => count.unwrap_or(1),
+                                Some(_) => return Ok(InputResult::NotHandled),
+                            };
+                            return Ok(InputResult::StructuralNav(StructuralNavAction::NthChild(n), 1));
+                        }
+                        KeyCode::Char('j') => {
+                            return nav(StructuralNavAction::NextSibling);
+                        }
+                        KeyCode::Char('l') => {
+                            return nav(StructuralNavAction::NextKey);
+                        }
+                        KeyCode::Char('v') => {
+                            return nav(StructuralNavAction::NextValue);
+                        }
+                        KeyCode::Char('e') => {
+                            return nav(StructuralNavAction::NextError);
+                        }
+                        KeyCode::Char('d') => {
+                            return nav(StructuralNavAction::NextDiagnostic);
                         }
+                        KeyCode::Char('u') => {
+                            return nav(StructuralNavAction::NextDuplicate);
+                        }
+                        KeyCode::Char('c') => {
+                            return nav(StructuralNavAction::NextChange);
+                        }
+                        KeyCode::Char('q') => {
+                            let count = count.unwrap_or(1);
+                            return Ok(InputResult::Command(AppCommand::Location { forward: true, count }));
+                        }
+                        KeyCode::Char(']') => {
+                            return nav(StructuralNavAction::NextRecord);
+                        }
+                        _ => {}
                     }
                 }
                 Ok(InputResult::NotHandled)
             }
             (KeyCode::Char('['), KeyModifiers::NONE) => {
-                // Wait for second key: 'l' for prev key, 'v' for prev value
-                use crossterm::event;
-                use std::time::Duration;
-                
-                if let Ok(true) = event::poll(Duration::from_millis(500)) {
-                    if let Ok(Event::Key(next_key)) = event::read() {
-                        match next_key.code {
-                            KeyCode::Char('l') => {
-                                return Ok(InputResult::StructuralNav(StructuralNavAction::PrevKey));
-                            }
-                            KeyCode::Char('v') => {
-                                return Ok(InputResult::StructuralNav(StructuralNavAction::PrevValue));
-                            }
-                            _ => {}
+                // Second key: 'j' for prev sibling, 'l' for prev key, 'v' for prev value, 'e' for prev error, 'd' for prev schema error, 'u' for prev duplicate key, 'c' for prev diff change, 'q' for the previous location, '[' for prev record,
+                // 'm' to move this node up
+                if let Some(next_key) = ctx.keys.next_key() {
+                    match next_key.code {
+                        KeyCode::Char('m') => {
+                            return Ok(InputResult::Command(AppCommand::Node { op: NodeOp::MoveUp, register: None }));
+                        }
+                        KeyCode::Char('j') => {
+                            return nav(StructuralNavAction::PrevSibling);
+                        }
+                        KeyCode::Char('l') => {
+                            return nav(StructuralNavAction::PrevKey);
+                        }
+                        KeyCode::Char('v') => {
+                            return nav(StructuralNavAction::PrevValue);
+                        }
+                        KeyCode::Char('e') => {
+                            return nav(StructuralNavAction::PrevError);
+                        }
+                        KeyCode::Char('d') => {
+                            return nav(StructuralNavAction::PrevDiagnostic);
+                        }
+                        KeyCode::Char('u') => {
+                            return nav(StructuralNavAction::PrevDuplicate);
                         }
+                        KeyCode::Char('c') => {
+                            return nav(StructuralNavAction::PrevChange);
+                        }
+                        KeyCode::Char('q') => {
+                            let count = count.unwrap_or(1);
+                            return Ok(InputResult::Command(AppCommand::Location { forward: false, count }));
+                        }
+                        KeyCode::Char('[') => {
+                            return nav(StructuralNavAction::PrevRecord);
+                        }
+                        _ => {}
                     }
                 }
                 Ok(InputResult::NotHandled)
             }
+            (KeyCode::Char('z'), KeyModifiers::NONE) => {
+                // Folding: za toggle, zo open, zc close, zR open all, zM close all
+                // Scrolling: zh/zl one column, zH/zL half a screen
+                // ze: expand or elide the long string under the cursor
+                if let Some(next_key) = ctx.keys.next_key() {
+                    if next_key.code == KeyCode::Char('e') {
+                        return Ok(InputResult::Command(AppCommand::ToggleElision));
+                    }
+                    let action = match next_key.code {
+                        KeyCode::Char('a') => Some(FoldAction::Toggle),
+                        KeyCode::Char('o') => Some(FoldAction::Open),
+                        KeyCode::Char('c') => Some(FoldAction::Close),
+                        KeyCode::Char('R') => Some(FoldAction::OpenAll),
+                        KeyCode::Char('M') => Some(FoldAction::CloseAll),
+                        _ => None,
+                    };
+                    if let Some(action) = action {
+                        return Ok(InputResult::Command(AppCommand::Fold(action)));
+                    }
+                    let scroll = match next_key.code {
+                        KeyCode::Char('h') => Some(HorizontalScroll::Left),
+                        KeyCode::Char('l') => Some(HorizontalScroll::Right),
+                        KeyCode::Char('H') => Some(HorizontalScroll::HalfLeft),
+                        KeyCode::Char('L') => Some(HorizontalScroll::HalfRight),
+                        _ => None,
+                    };
+                    if let Some(scroll) = scroll {
+                        return Ok(InputResult::Command(AppCommand::ScrollHorizontal(scroll)));
+                    }
+                }
+                Ok(InputResult::NotHandled)
+            }
+            (KeyCode::Char('%'), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                // Jump to the delimiter matching the one under the cursor
+                let offset = ctx.cursor.byte_offset;
+                match crate::ui::matchparen::find_match(ctx.structural_index, ctx.buffer, offset) {
+                    Some(partner) => {
+                        ctx.cursor.move_to_offset(ctx.buffer, partner);
+                        Ok(InputResult::ClearNodeTracking)
+                    }
+                    None => Ok(InputResult::Handled),
+                }
+            }
+            (KeyCode::Char('K'), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                Ok(InputResult::Command(AppCommand::PreviewValue))
+            }
+            (KeyCode::Char('J'), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                Ok(InputResult::Command(AppCommand::Join { count: count.unwrap_or(2), raw: false, selection: None }))
+            }
             
             // Page navigation
             (KeyCode::Char('g'), KeyModifiers::NONE) => {
+                // gj/gk move by screen row, ga describes the character under
+                // the cursor, gt and s/u/b/n converts the value under it, gJ
+                // joins lines without touching the spaces, g Ctrl-g counts
+                // words up to the cursor, g-/g+ step through the undo states
+                // in time; anything else is gg
+                if let Some(next_key) = ctx.keys.next_key() {
+                    match next_key.code {
+                        KeyCode::Char('t') => {
+                            let conversion = match ctx.keys.next_key().map(|key| key.code) {
+                                Some(KeyCode::Char('s')) => Conversion::Stringify,
+                                Some(KeyCode::Char('u')) => Conversion::Unstringify,
+                                Some(KeyCode::Char('b')) => Conversion::ToggleBool,
+                                Some(KeyCode::Char('n')) => Conversion::Null,
+                                _ => return Ok(InputResult::NotHandled),
+                            };
+                            let register = self.selected_register.take();
+                            return Ok(InputResult::Command(AppCommand::Node { op: NodeOp::Convert(conversion), register }));
+                        }
+                        KeyCode::Char('q') => {
+                            return Ok(InputResult::Command(AppCommand::Node { op: NodeOp::Rewrap { selection: None }, register: None }));
+                        }
+                        KeyCode::Char('J') => {
+                            return Ok(InputResult::Command(AppCommand::Join { count: count.unwrap_or(2), raw: true, selection: None }));
+                        }
+                        KeyCode::Char('j') => return Ok(InputResult::Command(AppCommand::DisplayLine { down: true })),
+                        KeyCode::Char('k') => return Ok(InputResult::Command(AppCommand::DisplayLine { down: false })),
+                        KeyCode::Char('a') => return Ok(char_info(ctx.buffer, ctx.cursor.byte_offset)),
+                        KeyCode::Char('g') if next_key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            return Ok(InputResult::Command(AppCommand::FileInfo { detailed: true }));
+                        }
+                        KeyCode::Char('-') => {
+                            ctx.undo_stack.earlier(ctx.buffer, ctx.cursor)?;
+                            return Ok(InputResult::Handled);
+                        }
+                        KeyCode::Char('+') => {
+                            ctx.undo_stack.later(ctx.buffer, ctx.cursor)?;
+                            return Ok(InputResult::Handled);
+                        }
+                        _ => {}
+                    }
+                }
+                
                 // Go to first line (gg command)
                 ctx.cursor.line = 0;
                 ctx.cursor.col = 0;
@@ -258,39 +533,33 @@ impl ModeHandler for NormalMode {
             
             // Undo/Redo
             (KeyCode::Char('u'), KeyModifiers::NONE) => {
-                match ctx.undo_stack.undo(ctx.buffer, ctx.cursor) {
-                    Ok(true) => Ok(InputResult::Handled),
-                    Ok(false) => Ok(InputResult::Handled),
-                    Err(e) => Err(e),
-                }
+                let steps = ctx.undo_stack.undo_many(count.unwrap_or(1), ctx.buffer, ctx.cursor)?;
+                Ok(InputResult::Message(ctx.undo_stack.describe(steps, true)))
             }
             (KeyCode::Char('r'), KeyModifiers::CONTROL) => {
-                match ctx.undo_stack.redo(ctx.buffer, ctx.cursor) {
-                    Ok(true) => Ok(InputResult::Handled),
-                    Ok(false) => Ok(InputResult::Handled),
-                    Err(e) => Err(e),
-                }
+                let steps = ctx.undo_stack.redo_many(count.unwrap_or(1), ctx.buffer, ctx.cursor)?;
+                Ok(InputResult::Message(ctx.undo_stack.describe(steps, false)))
             }
             
             // Operators - set pending operator
             (KeyCode::Char('d'), KeyModifiers::NONE) if ctx.pending_operator.is_none() => {
                 *ctx.pending_operator = Some(PendingOperator {
                     operator: Operator::Delete,
-                    count: 1,
+                    count: count.unwrap_or(1),
                 });
                 Ok(InputResult::Handled)
             }
             (KeyCode::Char('c'), KeyModifiers::NONE) if ctx.pending_operator.is_none() => {
                 *ctx.pending_operator = Some(PendingOperator {
                     operator: Operator::Change,
-                    count: 1,
+                    count: count.unwrap_or(1),
                 });
                 Ok(InputResult::Handled)
             }
             (KeyCode::Char('y'), KeyModifiers::NONE) if ctx.pending_operator.is_none() => {
                 *ctx.pending_operator = Some(PendingOperator {
                     operator: Operator::Yank,
-                    count: 1,
+                    count: count.unwrap_or(1),
                 });
                 Ok(InputResult::Handled)
             }
@@ -298,11 +567,12 @@ impl ModeHandler for NormalMode {
             // Paste
             (KeyCode::Char('p'), KeyModifiers::NONE) => {
                 if let Some(text) = ctx.register_map.get(self.selected_register) {
-                    let edit = EditOperations::insert(
+                    let edit = paste::paste(
                         ctx.buffer,
                         ctx.cursor,
                         ctx.cursor.byte_offset,
                         &text,
+                        ctx.format_paste,
                     )?;
                     ctx.undo_stack.push(edit);
                 }
@@ -317,11 +587,12 @@ impl ModeHandler for NormalMode {
                     } else {
                         0
                     };
-                    let edit = EditOperations::insert(
+                    let edit = paste::paste(
                         ctx.buffer,
                         ctx.cursor,
                         paste_pos,
                         &text,
+                        ctx.format_paste,
                     )?;
                     ctx.undo_stack.push(edit);
                 }
@@ -332,7 +603,7 @@ impl ModeHandler for NormalMode {
             // Word motions - if pending operator, apply it
             (KeyCode::Char('w'), KeyModifiers::NONE) => {
                 if let Some(pending) = ctx.pending_operator.take() {
-                    let motion = Motion::Word(WordMotion::Start, pending.count);
+                    let motion = Motion::Word(WordMotion::Start, pending.times(count));
                     self.execute_operator(pending.operator, motion, &mut ctx)?;
                     Ok(InputResult::Handled)
                 } else {
@@ -347,7 +618,7 @@ impl ModeHandler for NormalMode {
             }
             (KeyCode::Char('b'), KeyModifiers::NONE) => {
                 if let Some(pending) = ctx.pending_operator.take() {
-                    let motion = Motion::Word(WordMotion::BackStart, pending.count);
+                    let motion = Motion::Word(WordMotion::BackStart, pending.times(count));
                     self.execute_operator(pending.operator, motion, &mut ctx)?;
                     Ok(InputResult::Handled)
                 } else {
@@ -361,7 +632,7 @@ impl ModeHandler for NormalMode {
             }
             (KeyCode::Char('e'), KeyModifiers::NONE) => {
                 if let Some(pending) = ctx.pending_operator.take() {
-                    let motion = Motion::Word(WordMotion::End, pending.count);
+                    let motion = Motion::Word(WordMotion::End, pending.times(count));
                     self.execute_operator(pending.operator, motion, &mut ctx)?;
                     Ok(InputResult::Handled)
                 } else {
@@ -374,27 +645,33 @@ impl ModeHandler for NormalMode {
                 }
             }
             
-            // Line operations
-            (KeyCode::Char('d'), KeyModifiers::NONE) if ctx.pending_operator.is_some() => {
-                // dd - delete line
-                *ctx.pending_operator = None;
-                let motion = Motion::Line(Direction::Forward, 1);
-                self.execute_operator(Operator::Delete, motion, &mut ctx)?;
+            // Line operations - dd, cc, yy on the cursor line and the
+            // lines after it that the counts add up to
+            (KeyCode::Char('d' | 'c' | 'y'), KeyModifiers::NONE) => {
+                if let Some(pending) = ctx.pending_operator.take() {
+                    let motion = Motion::Line(Direction::Forward, pending.times(count) - 1);
+                    self.execute_operator(pending.operator, motion, &mut ctx)?;
+                }
                 Ok(InputResult::Handled)
             }
-            (KeyCode::Char('c'), KeyModifiers::NONE) if ctx.pending_operator.is_some() => {
-                // cc - change line
-                *ctx.pending_operator = None;
-                let motion = Motion::Line(Direction::Forward, 1);
-                self.execute_operator(Operator::Change, motion, &mut ctx)?;
-                Ok(InputResult::Handled)
+            
+            // dn / yn - the whole node under the cursor
+            (KeyCode::Char('n'), KeyModifiers::NONE) if ctx.pending_operator.is_some() => {
+                let op = match ctx.pending_operator.take().map(|pending| pending.operator) {
+                    Some(Operator::Delete) => NodeOp::Delete,
+                    Some(Operator::Yank) => NodeOp::Yank,
+                    _ => return Ok(InputResult::NotHandled),
+                };
+                let register = self.selected_register.take();
+                Ok(InputResult::Command(AppCommand::Node { op, register }))
             }
-            (KeyCode::Char('y'), KeyModifiers::NONE) if ctx.pending_operator.is_some() => {
-                // yy - yank line
-                *ctx.pending_operator = None;
-                let motion = Motion::Line(Direction::Forward, 1);
-                self.execute_operator(Operator::Yank, motion, &mut ctx)?;
-                Ok(InputResult::Handled)
+            
+            // n / N - repeat the last search
+            (KeyCode::Char('n'), KeyModifiers::NONE) => {
+                Ok(InputResult::Command(AppCommand::SearchNext { forward: true, count: count.unwrap_or(1) }))
+            }
+            (KeyCode::Char('N'), _) => {
+                Ok(InputResult::Command(AppCommand::SearchNext { forward: false, count: count.unwrap_or(1) }))
             }
             
             // x - delete character (like dl)
@@ -412,44 +689,383 @@ impl ModeHandler for NormalMode {
             }
             
             // Register selection with "
-            (KeyCode::Char('"'), KeyModifiers::NONE) => {
-                // Next character will be the register name
-                // For now, just clear pending operator
+            (KeyCode::Char('"'), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                // Next character is the register name, used by the next
+                // yank, delete, change or put
                 *ctx.pending_operator = None;
+                if let Some(KeyEvent { code: KeyCode::Char(name), .. }) = ctx.keys.next_key() {
+                    match name {
+                        '"' => self.selected_register = None,
+                        name if is_register(name) => self.selected_register = Some(name),
+                        _ => {}
+                    }
+                }
                 Ok(InputResult::Handled)
             }
             
             _ => Ok(InputResult::NotHandled),
         }
     }
+
+    /// A paste in normal mode is text, never commands: inserted at the
+    /// cursor, or with :set pastebehavior=ignore left out with a hint
+    fn handle_paste(&mut self, _text: &str) -> InputResult {
+        self.count = None;
+        match self.paste_behavior {
+            PasteBehavior::Insert => InputResult::NotHandled,
+            PasteBehavior::Ignore => InputResult::Message("Paste ignored: use p to paste from a register, or i to insert".to_string()),
+        }
+    }
+}
+
+/// Whether `key` finishes, or goes on with, a pending `operator`: a word
+/// or structural motion, a text object, the operator again for whole
+/// lines, or n for the node under the cursor
+fn finishes_operator(operator: Operator, key: KeyEvent) -> bool {
+    if key.modifiers == KeyModifiers::CONTROL {
+        return matches!(key.code, KeyCode::Char('j' | 'k' | 'h' | 'l'));
+    }
+    if key.modifiers != KeyModifiers::NONE {
+        return false;
+    }
+    match key.code {
+        KeyCode::Char('w' | 'b' | 'e' | 'i' | 'a') => true,
+        KeyCode::Char('d') => operator == Operator::Delete,
+        KeyCode::Char('c') => operator == Operator::Change,
+        KeyCode::Char('y') => operator == Operator::Yank,
+        KeyCode::Char('n') => operator != Operator::Change,
+        _ => false,
+    }
+}
+
+/// `ga`: the character under the cursor, its code point, UTF-8 bytes and
+/// byte offset
+fn char_info(buffer: &crate::buffer::Buffer, offset: usize) -> InputResult {
+    let Some(c) = buffer.char_at(offset) else {
+        return InputResult::Message("NUL".to_string());
+    };
+    let shown = crate::ui::list::substitute(c, true).unwrap_or_else(|| c.to_string());
+    let mut utf8 = [0; 4];
+    let bytes: Vec<String> = c.encode_utf8(&mut utf8).bytes().map(|b| format!("{:02x}", b)).collect();
+    InputResult::Message(format!(
+        "<{}> U+{:04X}, UTF-8 {}, byte {}",
+        shown,
+        c as u32,
+        bytes.join(" "),
+        offset
+    ))
 }
 
 // Helper methods for NormalMode
 impl NormalMode {
-    /// Handle text object selection (iw, aw, i{, a{, etc.)
-    fn handle_text_object(&mut self, inner: bool, ctx: &mut EditorContext) -> Result<()> {
-        use crossterm::event;
-        use std::time::Duration;
-        
-        // Wait for next key to determine which text object
-        if let Ok(true) = event::poll(Duration::from_millis(500)) {
-            if let Ok(Event::Key(key)) = event::read() {
-                let text_object = match key.code {
-                    KeyCode::Char('w') => Some(super::operator::TextObject::Word { inner }),
-                    KeyCode::Char('"') => Some(super::operator::TextObject::Quotes { inner }),
-                    KeyCode::Char('{') | KeyCode::Char('}') => Some(super::operator::TextObject::Braces { inner }),
-                    KeyCode::Char('[') | KeyCode::Char(']') => Some(super::operator::TextObject::Brackets { inner }),
-                    _ => None,
-                };
-                
-                if let Some(obj) = text_object {
-                    if let Some(pending) = ctx.pending_operator.take() {
-                        let motion = Motion::TextObject(obj);
-                        self.execute_operator(pending.operator, motion, ctx)?;
-                    }
-                }
+    /// A structural move, or with an operator pending, the nodes it covers.
+    /// What can't be done, as when the index hasn't got that far, is said
+    /// rather than treated as an error.
+    fn structural_motion(&mut self, action: StructuralNavAction, count: Option<usize>, ctx: &mut EditorContext) -> Result<InputResult> {
+        let Some(pending) = ctx.pending_operator.take() else {
+            return Ok(InputResult::StructuralNav(action, count.unwrap_or(1)));
+        };
+        let motion = Motion::Structural(action, pending.times(count));
+        match self.execute_operator(pending.operator, motion, ctx) {
+            Ok(()) => Ok(InputResult::Handled),
+            Err(e) => {
+                self.selected_register = None;
+                Ok(InputResult::Message(e.to_string()))
             }
         }
+    }
+
+    /// Handle text object selection (iw, aw, i{, a{, etc.), `count` being
+    /// the one typed after the operator. A key that isn't a text object
+    /// cancels the operator.
+    fn handle_text_object(&mut self, inner: bool, count: Option<usize>, ctx: &mut EditorContext) -> Result<()> {
+        let Some(pending) = ctx.pending_operator.take() else {
+            return Ok(());
+        };
+        // The next key says which text object
+        if let Some(key) = ctx.keys.next_key() {
+            let text_object = match key.code {
+                KeyCode::Char(c) => super::operator::TextObject::from_key(c, inner),
+                _ => None,
+            };
+            
+            if let Some(obj) = text_object {
+                let motion = Motion::TextObject(obj, pending.times(count));
+                return self.execute_operator(pending.operator, motion, ctx);
+            }
+        }
+        self.selected_register = None;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::cursor::Cursor;
+    use crate::buffer::Buffer;
+    use crate::edit::undo::UndoStack;
+    use crate::mode::{KeyQueue, RegisterMap};
+    use crate::parser::{StructuralIndex, Tokenizer};
+    use crate::ui::fold::FoldSet;
+
+    /// Normal mode over a buffer, fed keys the way the app replays them
+    struct Editor {
+        normal: NormalMode,
+        buffer: Buffer,
+        cursor: Cursor,
+        mode: Mode,
+        undo_stack: UndoStack,
+        register_map: RegisterMap,
+        pending_operator: Option<PendingOperator>,
+        index: Option<StructuralIndex>,
+        smart_comma: bool,
+    }
+
+    impl Editor {
+        fn new(text: &str, at: usize) -> Self {
+            let buffer = Buffer::from_text(text);
+            let mut cursor = Cursor::new();
+            cursor.byte_offset = at;
+            cursor.line = buffer.byte_offset_to_line(at);
+            Self {
+                normal: NormalMode::new(),
+                buffer,
+                cursor,
+                mode: Mode::Normal,
+                undo_stack: UndoStack::new(),
+                register_map: RegisterMap::new(),
+                pending_operator: None,
+                index: None,
+                smart_comma: false,
+            }
+        }
+
+        /// With the text indexed as it is at the start
+        fn indexed(text: &str, at: usize) -> Self {
+            let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+            Self { index: Some(index), ..Self::new(text, at) }
+        }
+
+        /// Type `keys`, one character each, with `<` standing for Esc and
+        /// `^` making the next one a Ctrl key
+        fn type_keys(&mut self, keys: &str) {
+            let mut chars = keys.chars();
+            let keys: Vec<KeyEvent> = std::iter::from_fn(|| {
+                    Some(match chars.next()? {
+                        '<' => KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE),
+                        '^' => KeyEvent::new(KeyCode::Char(chars.next()?), KeyModifiers::CONTROL),
+                        c => KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE),
+                    })
+                })
+                .collect();
+            let mut queue = KeyQueue::new();
+            queue.unread(&keys);
+            while let Some(key) = queue.next_key() {
+                let folds = FoldSet::new().lines(&self.buffer);
+                let ctx = EditorContext {
+                    buffer: &mut self.buffer,
+                    cursor: &mut self.cursor,
+                    mode: &mut self.mode,
+                    undo_stack: &mut self.undo_stack,
+                    register_map: &mut self.register_map,
+                    pending_operator: &mut self.pending_operator,
+                    structural_index: self.index.as_ref(),
+                    folds: &folds,
+                    keys: &mut queue,
+                    format_paste: None,
+                    smart_comma: self.smart_comma,
+                };
+                self.normal.handle_key(key, ctx).unwrap();
+            }
+        }
+
+        fn text(&self) -> String {
+            self.buffer.slice(0..self.buffer.len_bytes())
+        }
+    }
+
+    const WORDS: &str = "one two three four five six seven eight";
+
+    #[test]
+    fn test_counts_multiply() {
+        let mut editor = Editor::new(WORDS, 0);
+        editor.type_keys("2d3w");
+        assert_eq!(editor.text(), " seven eight");
+        assert_eq!(editor.pending_operator, None);
+
+        let mut editor = Editor::new(WORDS, 0);
+        editor.type_keys("d2w");
+        assert_eq!(editor.text(), " three four five six seven eight");
+
+        let mut editor = Editor::new("a\nb\nc\nd\ne\n", 0);
+        editor.type_keys("dd");
+        assert_eq!(editor.text(), "b\nc\nd\ne\n");
+        editor.type_keys("2d2d");
+        assert_eq!(editor.text(), "");
+    }
+
+    #[test]
+    fn test_text_object_counts() {
+        let text = r#"{"a": {"b": [1, {"c": 2}]}, "d": 3}"#;
+        let mut editor = Editor::new(text, text.find('2').unwrap());
+        editor.type_keys("d2i{");
+        assert_eq!(editor.text(), r#"{"a": {}, "d": 3}"#);
+
+        let mut editor = Editor::new(text, text.find('2').unwrap());
+        editor.type_keys("2di[");
+        assert_eq!(editor.text(), r#"{"a": {"b": [1, {"c": 2}]}, "d": 3}"#);
+        editor.type_keys("di[");
+        assert_eq!(editor.text(), r#"{"a": {"b": []}, "d": 3}"#);
+
+        // ib takes whichever pair is nearest, a> an angle-bracket one
+        let text = r#"{"f": "g(x, [y])", "t": "Vec<u8>"}"#;
+        let mut editor = Editor::new(text, text.find('x').unwrap());
+        editor.type_keys("dib");
+        assert_eq!(editor.text(), r#"{"f": "g()", "t": "Vec<u8>"}"#);
+        editor.type_keys("d2ab");
+        assert_eq!(editor.text(), "");
+        let mut editor = Editor::new(text, text.find('>').unwrap());
+        editor.type_keys("da>");
+        assert_eq!(editor.text(), r#"{"f": "g(x, [y])", "t": "Vec"}"#);
+
+        let mut editor = Editor::new(WORDS, 0);
+        editor.type_keys("y2aw");
+        assert_eq!(editor.register_map.get(None).as_deref(), Some("one two "));
+        assert_eq!(editor.text(), WORDS);
+    }
+
+    #[test]
+    fn test_structural_motions() {
+        let text = r#"{"a": 1, "b": {"c": [1, 2, 3, 4], "d": true}, "e": null}"#;
+        let two = text.find('2').unwrap();
+        let b = text.find("\"b\"").unwrap();
+        let run = |at: usize, keys: &str| {
+            let mut editor = Editor::indexed(text, at);
+            editor.type_keys(keys);
+            assert_eq!(editor.pending_operator, None, "{}", keys);
+            assert_eq!(editor.mode == Mode::Insert, keys.starts_with('c') && editor.text() != text, "{}", keys);
+            (editor.text(), editor.register_map.get(None).unwrap_or_default())
+        };
+        let edited = |from: &str, to: &str| text.replacen(from, to, 1);
+
+        // Through the next or previous sibling, with the commas
+        assert_eq!(run(two, "d^j").0, edited("1, 2, 3, 4", "1, 4"));
+        assert_eq!(run(two, "d^k").0, edited("1, 2, 3, 4", "3, 4"));
+        assert_eq!(run(two, "c^j").0, edited("1, 2, 3, 4", "1, , 4"));
+        assert_eq!(run(two, "y^j"), (text.to_string(), "2, 3".to_string()));
+        assert_eq!(run(two, "y^k").1, "1, 2");
+        assert_eq!(run(two + 3, "d^j").0, edited("1, 2, 3, 4", "1, 2"));
+
+        // The parent, key and all
+        let array = r#""c": [1, 2, 3, 4]"#;
+        assert_eq!(run(two, "d^h").0, edited(r#""c": [1, 2, 3, 4], "#, ""));
+        assert_eq!(run(two, "c^h").0, edited(array, ""));
+        assert_eq!(run(two, "y^h").1, array);
+        assert_eq!(run(b, "y^h").1, text);
+
+        // The first child
+        assert_eq!(run(b, "d^l").0, edited(r#""c": [1, 2, 3, 4], "#, ""));
+        assert_eq!(run(b, "c^l").0, edited(array, ""));
+        assert_eq!(run(b, "y^l").1, array);
+        // A value has none; nothing happens
+        assert_eq!(run(two, "d^l"), (text.to_string(), String::new()));
+
+        // Counts go further
+        assert_eq!(run(two - 3, "2d^j").0, edited("1, 2, 3, 4", "4"));
+        assert_eq!(run(two, "d2^h").0, edited(r#""b": {"c": [1, 2, 3, 4], "d": true}, "#, ""));
+        assert_eq!(run(two, "9y^j").1, "2, 3, 4");
+
+        // Without an index nothing is taken
+        let mut editor = Editor::new(text, two);
+        editor.type_keys("d^j");
+        assert_eq!(editor.text(), text);
+    }
+
+    #[test]
+    fn test_smart_comma() {
+        let text = "{\n  \"a\": 1,\n  \"b\": 2\n}\n";
+        let at = |s: &str| text.find(s).unwrap();
+        let run = |at: usize, keys: &str| {
+            let mut editor = Editor { smart_comma: true, ..Editor::indexed(text, at) };
+            editor.type_keys(keys);
+            editor
+        };
+
+        // o after the last member adds its comma, after another just opens
+        let editor = run(at("\"b"), "o");
+        assert_eq!(editor.text(), "{\n  \"a\": 1,\n  \"b\": 2,\n  \"\": \n}\n");
+        assert_eq!(editor.cursor.byte_offset, at("2") + ",\n  \"".len() + 1);
+        assert_eq!(run(at("\"a"), "o").text(), "{\n  \"a\": 1,\n  \"\": \n  \"b\": 2\n}\n");
+        // Not a member's line
+        assert_eq!(run(0, "o").text(), "{\n\n  \"a\": 1,\n  \"b\": 2\n}\n");
+
+        // dd on the last member takes the comma left before it
+        let mut editor = run(at("\"b"), "dd");
+        assert_eq!(editor.text(), "{\n  \"a\": 1\n}\n");
+        editor.type_keys("u");
+        assert_eq!(editor.text(), text);
+        assert_eq!(run(at("\"a"), "dd").text(), "{\n  \"b\": 2\n}\n");
+
+        // Off, or without an index, nothing extra happens
+        let mut editor = Editor::indexed(text, at("\"b"));
+        editor.type_keys("dd");
+        assert_eq!(editor.text(), "{\n  \"a\": 1,\n}\n");
+        let mut editor = Editor { smart_comma: true, ..Editor::new(text, at("\"b")) };
+        editor.type_keys("o");
+        assert_eq!(editor.text(), "{\n  \"a\": 1,\n  \"b\": 2\n\n}\n");
+    }
+
+    #[test]
+    fn test_cancel() {
+        // Esc drops the operator and its count; the next w only moves
+        let mut editor = Editor::new(WORDS, 0);
+        editor.type_keys("2d3<");
+        assert_eq!(editor.pending_operator, None);
+        assert_eq!(editor.normal.count, None);
+        editor.type_keys("w");
+        assert_eq!(editor.text(), WORDS);
+        assert_eq!(editor.cursor.byte_offset, 3);
+
+        // So does a key that can't finish it, without doing its own thing
+        let mut editor = Editor::new(WORDS, 0);
+        editor.type_keys("dxw");
+        assert_eq!(editor.text(), WORDS);
+        assert_eq!(editor.cursor.byte_offset, 3);
+
+        // Another operator's key doesn't make a line operation
+        let mut editor = Editor::new("a\nb\n", 0);
+        editor.type_keys("yd");
+        assert_eq!(editor.text(), "a\nb\n");
+        assert_eq!(editor.register_map.get(None).unwrap_or_default(), "");
+
+        // Nor does a text object key that isn't one
+        let mut editor = Editor::new(WORDS, 0);
+        editor.type_keys("dizw");
+        assert_eq!(editor.text(), WORDS);
+        assert_eq!(editor.cursor.byte_offset, 3);
+    }
+
+    #[test]
+    fn test_pending_display() {
+        let mut editor = Editor::new(WORDS, 0);
+        editor.type_keys("\"a2d3");
+        let pending = editor.pending_operator.unwrap();
+        assert_eq!(editor.normal.pending_display(&pending), "\"a2d3");
+        editor.type_keys("<y");
+        assert_eq!(editor.normal.pending_display(&editor.pending_operator.unwrap()), "y");
+    }
+
+    #[test]
+    fn test_paste_is_not_keys() {
+        let mut editor = Editor::new(WORDS, 0);
+        editor.type_keys("3");
+        assert!(matches!(editor.normal.handle_paste("dd"), InputResult::NotHandled));
+        editor.type_keys("x");
+        assert_eq!(editor.text(), "ne two three four five six seven eight");
+
+        editor.normal.paste_behavior = PasteBehavior::Ignore;
+        assert!(matches!(editor.normal.handle_paste("dw"), InputResult::Message(_)));
+        assert_eq!(editor.text(), "ne two three four five six seven eight");
+    }
+}
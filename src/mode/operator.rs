@@ -1,4 +1,7 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+
+use super::StructuralNavAction;
+use crate::parser::{NodeKind, StructuralIndex};
 
 /// Vim-style operators
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -19,8 +22,11 @@ pub enum Motion {
     Word(WordMotion, usize),
     /// Move to line position: 0, $, ^
     LinePosition(LinePosition),
-    /// Text object: iw, aw, i", a"
-    TextObject(TextObject),
+    /// Text object: iw, aw, i", a", ib, with a count: 2i{ is the object
+    /// around the one the cursor is in
+    TextObject(TextObject, usize),
+    /// Structural move: Ctrl-j, Ctrl-k, Ctrl-h, Ctrl-l, over whole nodes
+    Structural(StructuralNavAction, usize),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -50,8 +56,29 @@ pub enum TextObject {
     SingleQuotes { inner: bool }, // i', a'
     Braces { inner: bool },     // i{, a{ (JSON object)
     Brackets { inner: bool },   // i[, a[ (JSON array)
+    Angles { inner: bool },     // i<, a< (on one line, as in a string)
+    Pair { inner: bool },       // ib, ab: the nearest of {}, [] and ()
 }
 
+impl TextObject {
+    /// The object typed as `key` after i or a
+    pub fn from_key(key: char, inner: bool) -> Option<Self> {
+        match key {
+            'w' => Some(TextObject::Word { inner }),
+            '"' => Some(TextObject::Quotes { inner }),
+            '{' | '}' => Some(TextObject::Braces { inner }),
+            '[' | ']' => Some(TextObject::Brackets { inner }),
+            '<' | '>' => Some(TextObject::Angles { inner }),
+            'b' => Some(TextObject::Pair { inner }),
+            _ => None,
+        }
+    }
+}
+
+/// Bytes scanned either way from the cursor for an ib pair the structural
+/// index can't answer for
+const PAIR_SCAN_LIMIT: usize = 1 << 20;
+
 /// Result of applying an operator to a motion
 #[derive(Debug, Clone)]
 pub struct OperatorResult {
@@ -59,16 +86,42 @@ pub struct OperatorResult {
     pub text: String,
 }
 
+impl Operator {
+    /// The key that types it
+    pub fn key(self) -> char {
+        match self {
+            Operator::Delete => 'd',
+            Operator::Change => 'c',
+            Operator::Yank => 'y',
+        }
+    }
+}
+
 /// A pending operator waiting for a motion
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PendingOperator {
     pub operator: Operator,
+    /// Count typed before the operator, 1 if none was
     pub count: usize,
 }
 
+impl PendingOperator {
+    /// How many times the motion goes: the counts before and after the
+    /// operator multiply, so 2d3w deletes six words
+    pub fn times(&self, count: Option<usize>) -> usize {
+        self.count.saturating_mul(count.unwrap_or(1))
+    }
+}
+
 impl Motion {
     /// Calculate the byte range this motion covers from a starting position
     pub fn calculate_range(&self, buffer: &crate::buffer::Buffer, start_offset: usize) -> Result<std::ops::Range<usize>> {
+        self.calculate_range_in(buffer, None, start_offset)
+    }
+
+    /// As `calculate_range`, with the structural index, if there is one, to
+    /// find the containers around the start from
+    pub fn calculate_range_in(&self, buffer: &crate::buffer::Buffer, index: Option<&StructuralIndex>, start_offset: usize) -> Result<std::ops::Range<usize>> {
         match self {
             Motion::Char(dir, count) => {
                 let end = match dir {
@@ -109,101 +162,85 @@ impl Motion {
                     }
                 }
             }
-            Motion::TextObject(obj) => {
-                Self::calculate_text_object_range(buffer, start_offset, obj)
+            Motion::TextObject(obj, count) => {
+                Self::calculate_text_object_range(buffer, index, start_offset, obj, *count)
+            }
+            Motion::Structural(action, count) => {
+                let Some(index) = index else {
+                    bail!("Structure isn't indexed yet");
+                };
+                crate::edit::structural::motion_span(index, start_offset, *action, *count)
             }
         }
     }
     
     fn calculate_word_range(buffer: &crate::buffer::Buffer, start: usize, motion: &WordMotion, count: usize) -> Result<std::ops::Range<usize>> {
-        let text = buffer.slice(0..buffer.len_bytes());
-        let chars: Vec<char> = text.chars().collect();
-        let mut byte_pos = start;
-        let mut char_idx = text[..start].chars().count();
+        let mut text = TextWindow::new(buffer, start);
+        let mut pos = start;
         
         for _ in 0..count {
             match motion {
                 WordMotion::Start => {
                     // Find next word start
-                    while char_idx < chars.len() && !chars[char_idx].is_alphanumeric() && chars[char_idx] != '_' {
-                        byte_pos += chars[char_idx].len_utf8();
-                        char_idx += 1;
-                    }
-                    while char_idx < chars.len() && (chars[char_idx].is_alphanumeric() || chars[char_idx] == '_') {
-                        byte_pos += chars[char_idx].len_utf8();
-                        char_idx += 1;
-                    }
+                    text.skip_forward(&mut pos, |c| !is_word_char(c));
+                    text.skip_forward(&mut pos, is_word_char);
                 }
                 WordMotion::End => {
                     // Find next word end
-                    if char_idx < chars.len() {
-                        byte_pos += chars[char_idx].len_utf8();
-                        char_idx += 1;
-                    }
-                    while char_idx < chars.len() && !chars[char_idx].is_alphanumeric() && chars[char_idx] != '_' {
-                        byte_pos += chars[char_idx].len_utf8();
-                        char_idx += 1;
-                    }
-                    while char_idx < chars.len() && (chars[char_idx].is_alphanumeric() || chars[char_idx] == '_') {
-                        byte_pos += chars[char_idx].len_utf8();
-                        char_idx += 1;
-                    }
-                    byte_pos = byte_pos.saturating_sub(1);
+                    text.skip_forward_once(&mut pos);
+                    text.skip_forward(&mut pos, |c| !is_word_char(c));
+                    text.skip_forward(&mut pos, is_word_char);
                 }
                 WordMotion::BackStart => {
                     // Find previous word start
-                    if char_idx > 0 {
-                        char_idx -= 1;
-                        byte_pos = byte_pos.saturating_sub(chars[char_idx].len_utf8());
-                    }
-                    while char_idx > 0 && !chars[char_idx].is_alphanumeric() && chars[char_idx] != '_' {
-                        char_idx -= 1;
-                        byte_pos = byte_pos.saturating_sub(chars[char_idx].len_utf8());
+                    text.skip_back_once(&mut pos);
+                    while pos > 0 && text.char_at(pos).is_some_and(|c| !is_word_char(c)) {
+                        text.skip_back_once(&mut pos);
                     }
-                    while char_idx > 0 && (chars[char_idx].is_alphanumeric() || chars[char_idx] == '_') {
-                        char_idx -= 1;
-                        byte_pos = byte_pos.saturating_sub(chars[char_idx].len_utf8());
+                    while pos > 0 && text.char_at(pos).is_some_and(is_word_char) {
+                        text.skip_back_once(&mut pos);
                     }
                 }
             }
         }
+        // e stops a byte short of where the scan ended, once per count
+        if *motion == WordMotion::End {
+            pos = pos.saturating_sub(count);
+        }
         
-        Ok(start.min(byte_pos)..start.max(byte_pos))
+        Ok(start.min(pos)..start.max(pos))
     }
     
-    fn calculate_text_object_range(buffer: &crate::buffer::Buffer, start: usize, obj: &TextObject) -> Result<std::ops::Range<usize>> {
+    fn calculate_text_object_range(buffer: &crate::buffer::Buffer, index: Option<&StructuralIndex>, start: usize, obj: &TextObject, count: usize) -> Result<std::ops::Range<usize>> {
+        let mut text = TextWindow::new(buffer, start);
         match obj {
             TextObject::Word { inner } => {
-                let text = buffer.slice(0..buffer.len_bytes());
-                let chars: Vec<char> = text.chars().collect();
-                let char_idx = text[..start].chars().count();
-                
                 // Find word boundaries
-                let mut word_start = char_idx;
-                while word_start > 0 && (chars[word_start - 1].is_alphanumeric() || chars[word_start - 1] == '_') {
-                    word_start -= 1;
-                }
-                
-                let mut word_end = char_idx;
-                while word_end < chars.len() && (chars[word_end].is_alphanumeric() || chars[word_end] == '_') {
-                    word_end += 1;
+                let mut word_start = start;
+                while text.char_before(word_start).is_some_and(is_word_char) {
+                    text.skip_back_once(&mut word_start);
                 }
                 
+                let mut word_end = start;
+                text.skip_forward(&mut word_end, is_word_char);
                 if !inner {
                     // 'aw' includes trailing whitespace
-                    while word_end < chars.len() && chars[word_end].is_whitespace() {
-                        word_end += 1;
+                    text.skip_forward(&mut word_end, char::is_whitespace);
+                }
+                // A count takes in the words that follow
+                for _ in 1..count {
+                    text.skip_forward(&mut word_end, |c| !is_word_char(c));
+                    text.skip_forward(&mut word_end, is_word_char);
+                    if !inner {
+                        text.skip_forward(&mut word_end, char::is_whitespace);
                     }
                 }
                 
-                let byte_start = text[..word_start].len();
-                let byte_end = text[..word_end].len();
-                Ok(byte_start..byte_end)
+                Ok(word_start..word_end)
             }
             TextObject::Quotes { inner } => {
-                let text = buffer.slice(0..buffer.len_bytes());
                 // Find enclosing quotes
-                if let Some(range) = Self::find_enclosing_quotes(&text, start, '"', *inner) {
+                if let Some(range) = Self::find_enclosing_quotes(&mut text, start, '"', *inner) {
                     Ok(range)
                 } else {
                     Ok(start..start)
@@ -211,41 +248,140 @@ impl Motion {
             }
             TextObject::Braces { inner } | TextObject::Brackets { inner } => {
                 // Find enclosing {} or []
-                let text = buffer.slice(0..buffer.len_bytes());
                 let open_char = if matches!(obj, TextObject::Braces { .. }) { '{' } else { '[' };
                 let close_char = if matches!(obj, TextObject::Braces { .. }) { '}' } else { ']' };
                 
-                if let Some(range) = Self::find_matching_brackets(&text, start, open_char, close_char, *inner) {
-                    Ok(range)
+                // A count of n is the nth pair out from the cursor
+                let mut pair = Self::find_matching_brackets(&mut text, start, open_char, close_char);
+                for _ in 1..count {
+                    let Some(range) = pair.clone() else { break };
+                    pair = text.char_before(range.start)
+                        .and_then(|c| Self::find_matching_brackets(&mut text, range.start - c.len_utf8(), open_char, close_char));
+                }
+                match pair {
+                    // Exclude brackets
+                    Some(range) if *inner => Ok(range.start + 1..range.end - 1),
+                    Some(range) => Ok(range),
+                    None => Ok(start..start),
+                }
+            }
+            TextObject::Angles { inner } | TextObject::Pair { inner } => {
+                let angles = matches!(obj, TextObject::Angles { .. });
+                let find = |text: &mut TextWindow, pos, own| if angles {
+                    Self::find_pair(text, pos, ('<', '>'), PAIR_SCAN_LIMIT, true, own)
                 } else {
-                    Ok(start..start)
+                    Self::find_nearest_pair(text, index, pos, own)
+                };
+                // A delimiter under the cursor is its own pair's; going out
+                // a level, the one before the pair is just a byte on the way
+                let mut pair = find(&mut text, start, true);
+                for _ in 1..count {
+                    let Some(range) = pair.clone() else { break };
+                    pair = text.char_before(range.start)
+                        .and_then(|c| find(&mut text, range.start - c.len_utf8(), false));
+                }
+                match pair {
+                    Some(range) if *inner => Ok(range.start + 1..range.end - 1),
+                    Some(range) => Ok(range),
+                    None => Ok(start..start),
                 }
             }
             _ => Ok(start..start), // Other text objects not yet implemented
         }
     }
+
+    /// The innermost of the `{}`, `[]` and `()` pairs around `pos`,
+    /// delimiters included. Objects and arrays come from the structural
+    /// index where it has them closed and unedited, so brackets in strings
+    /// don't count; otherwise, and for parentheses, which only strings
+    /// hold, from a scan of the text nearby.
+    fn find_nearest_pair(text: &mut TextWindow, index: Option<&StructuralIndex>, pos: usize, own: bool) -> Option<std::ops::Range<usize>> {
+        let indexed = index.and_then(|index| {
+            let mut id = index.node_id_at(pos);
+            while let Some(current) = id {
+                let node = index.get(current)?;
+                let container = matches!(node.kind, NodeKind::Object | NodeKind::Array);
+                if container && (own || node.end - 1 != pos) {
+                    let usable = index.is_closed(current) && !index.touches_dirty(node.start..node.end);
+                    return usable.then_some(node.start..node.end);
+                }
+                id = node.parent;
+            }
+            None
+        });
+        let containers = match indexed {
+            Some(range) => vec![Some(range)],
+            None => vec![
+                Self::find_pair(text, pos, ('{', '}'), PAIR_SCAN_LIMIT, false, own),
+                Self::find_pair(text, pos, ('[', ']'), PAIR_SCAN_LIMIT, false, own),
+            ],
+        };
+        containers.into_iter()
+            .chain([Self::find_pair(text, pos, ('(', ')'), PAIR_SCAN_LIMIT, false, own)])
+            .flatten()
+            .max_by_key(|range| range.start)
+    }
+
+    /// The innermost `open`..`close` pair around `pos`, delimiters
+    /// included, looking no further than `limit` bytes either way, and for
+    /// `within_line` not past the line. With `own`, a closer under the
+    /// cursor is taken as its own pair's rather than as one nested inside.
+    fn find_pair(text: &mut TextWindow, pos: usize, (open, close): (char, char), limit: usize, within_line: bool, own: bool) -> Option<std::ops::Range<usize>> {
+        let mut depth = 0;
+        let mut at = pos;
+        if own && text.char_at(pos) == Some(close) {
+            at -= text.char_before(pos)?.len_utf8();
+        }
+        let start = loop {
+            match text.char_at(at) {
+                Some(c) if c == close => depth += 1,
+                Some(c) if c == open => {
+                    if depth == 0 {
+                        break at;
+                    }
+                    depth -= 1;
+                }
+                Some('\n') if within_line => return None,
+                _ => {}
+            }
+            if pos - at >= limit {
+                return None;
+            }
+            at -= text.char_before(at)?.len_utf8();
+        };
+
+        let mut depth = 0;
+        let mut at = start;
+        let end = loop {
+            let c = text.char_at(at)?;
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    break at;
+                }
+            } else if c == '\n' && within_line || at.saturating_sub(pos) >= limit {
+                return None;
+            }
+            at += c.len_utf8();
+        };
+        Some(start..end + close.len_utf8())
+    }
     
-    fn find_enclosing_quotes(text: &str, pos: usize, quote: char, inner: bool) -> Option<std::ops::Range<usize>> {
-        let bytes: Vec<u8> = text.bytes().collect();
-        
+    fn find_enclosing_quotes(text: &mut TextWindow, pos: usize, quote: char, inner: bool) -> Option<std::ops::Range<usize>> {
         // Find opening quote before pos
         let mut start = pos;
-        while start > 0 && bytes[start - 1] != quote as u8 {
-            start -= 1;
-        }
-        if start == 0 || bytes[start - 1] != quote as u8 {
-            return None;
+        while text.char_before(start).is_some_and(|c| c != quote) {
+            text.skip_back_once(&mut start);
         }
+        text.char_before(start)?;
         start -= 1; // Include opening quote
         
         // Find closing quote after pos
         let mut end = pos;
-        while end < bytes.len() && bytes[end] != quote as u8 {
-            end += 1;
-        }
-        if end >= bytes.len() || bytes[end] != quote as u8 {
-            return None;
-        }
+        text.skip_forward(&mut end, |c| c != quote);
+        text.char_at(end)?;
         end += 1; // Include closing quote
         
         if inner {
@@ -256,57 +392,387 @@ impl Motion {
         }
     }
     
-    fn find_matching_brackets(text: &str, pos: usize, open: char, close: char, inner: bool) -> Option<std::ops::Range<usize>> {
-        let chars: Vec<char> = text.chars().collect();
-        let byte_to_char: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
-        let char_pos = text[..pos.min(text.len())].chars().count();
-        
-        // Find opening bracket by searching backward
+    /// The innermost `open`..`close` pair around `pos`, brackets included
+    fn find_matching_brackets(text: &mut TextWindow, pos: usize, open: char, close: char) -> Option<std::ops::Range<usize>> {
+        // Find opening bracket by searching backward, from the character
+        // under the cursor
         let mut depth = 0;
-        let mut start_char = None;
-        for i in (0..=char_pos).rev() {
-            if i < chars.len() {
-                if chars[i] == close {
-                    depth += 1;
-                } else if chars[i] == open {
+        let mut at = pos;
+        let start = loop {
+            match text.char_at(at) {
+                Some(c) if c == close => depth += 1,
+                Some(c) if c == open => {
                     if depth == 0 {
-                        start_char = Some(i);
-                        break;
+                        break at;
                     }
                     depth -= 1;
                 }
+                _ => {}
             }
-        }
-        
-        let start_char = start_char?;
+            at -= text.char_before(at)?.len_utf8();
+        };
         
         // Find closing bracket by searching forward
         depth = 0;
-        let mut end_char = None;
-        for i in start_char..chars.len() {
-            if chars[i] == open {
+        let mut at = start;
+        let end = loop {
+            let c = text.char_at(at)?;
+            if c == open {
                 depth += 1;
-            } else if chars[i] == close {
+            } else if c == close {
                 depth -= 1;
                 if depth == 0 {
-                    end_char = Some(i);
-                    break;
+                    break at;
                 }
             }
-        }
-        
-        let end_char = end_char?;
-        
-        // Convert char indices to byte indices
-        let start_byte = byte_to_char.get(start_char).copied().unwrap_or(0);
-        let end_byte = byte_to_char.get(end_char + 1).copied().unwrap_or(text.len());
+            at += c.len_utf8();
+        };
         
-        if inner {
-            // Exclude brackets
-            let inner_start = byte_to_char.get(start_char + 1).copied().unwrap_or(start_byte);
-            Some(inner_start..byte_to_char.get(end_char).copied().unwrap_or(end_byte))
-        } else {
-            Some(start_byte..end_byte)
+        Some(start..end + 1)
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// How much text a `TextWindow` reads at first, and at least how much more
+/// each time a scan runs off either end
+const WINDOW_BLOCK: usize = 4096;
+
+/// The text around a position, read from the buffer in blocks as a scan
+/// reaches either end, so a motion copies only what it crosses rather than
+/// the whole document. Each extension at least doubles the window, so long
+/// scans stay linear.
+struct TextWindow<'a> {
+    buffer: &'a crate::buffer::Buffer,
+    /// Document offset of `text`
+    start: usize,
+    text: String,
+}
+
+impl<'a> TextWindow<'a> {
+    fn new(buffer: &'a crate::buffer::Buffer, pos: usize) -> Self {
+        let start = buffer.floor_char_boundary(pos.saturating_sub(WINDOW_BLOCK));
+        let end = buffer.floor_char_boundary(pos.saturating_add(WINDOW_BLOCK));
+        Self { buffer, start, text: buffer.slice(start..end) }
+    }
+    
+    fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+    
+    /// The character starting at document offset `pos`
+    fn char_at(&mut self, pos: usize) -> Option<char> {
+        while pos >= self.end() {
+            if !self.extend_forward() {
+                return None;
+            }
+        }
+        self.text.get(pos.checked_sub(self.start)?..)?.chars().next()
+    }
+    
+    /// The character ending at document offset `pos`
+    fn char_before(&mut self, pos: usize) -> Option<char> {
+        while pos <= self.start {
+            if !self.extend_back() {
+                return None;
+            }
+        }
+        self.text.get(..pos.checked_sub(self.start)?)?.chars().next_back()
+    }
+    
+    /// Move `pos` past the characters from it that match `matches`
+    fn skip_forward(&mut self, pos: &mut usize, matches: impl Fn(char) -> bool) {
+        while let Some(c) = self.char_at(*pos).filter(|&c| matches(c)) {
+            *pos += c.len_utf8();
+        }
+    }
+    
+    fn skip_forward_once(&mut self, pos: &mut usize) {
+        if let Some(c) = self.char_at(*pos) {
+            *pos += c.len_utf8();
+        }
+    }
+    
+    fn skip_back_once(&mut self, pos: &mut usize) {
+        if let Some(c) = self.char_before(*pos) {
+            *pos -= c.len_utf8();
+        }
+    }
+    
+    fn extend_forward(&mut self) -> bool {
+        let end = self.end();
+        let grow = self.text.len().max(WINDOW_BLOCK);
+        let new_end = self.buffer.floor_char_boundary(end.saturating_add(grow));
+        if new_end <= end {
+            return false;
+        }
+        self.text.push_str(&self.buffer.slice(end..new_end));
+        true
+    }
+    
+    fn extend_back(&mut self) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        let grow = self.text.len().max(WINDOW_BLOCK);
+        let new_start = self.buffer.floor_char_boundary(self.start.saturating_sub(grow));
+        let mut text = self.buffer.slice(new_start..self.start);
+        text.push_str(&self.text);
+        self.text = text;
+        self.start = new_start;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+
+    /// Word motions as they were computed over a copy of the whole document
+    fn whole_text_word_range(text: &str, start: usize, motion: WordMotion, count: usize) -> std::ops::Range<usize> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut byte_pos = start;
+        let mut char_idx = text[..start].chars().count();
+        let word = |c: char| c.is_alphanumeric() || c == '_';
+        for _ in 0..count {
+            match motion {
+                WordMotion::Start => {
+                    while char_idx < chars.len() && !word(chars[char_idx]) {
+                        byte_pos += chars[char_idx].len_utf8();
+                        char_idx += 1;
+                    }
+                    while char_idx < chars.len() && word(chars[char_idx]) {
+                        byte_pos += chars[char_idx].len_utf8();
+                        char_idx += 1;
+                    }
+                }
+                WordMotion::End => {
+                    if char_idx < chars.len() {
+                        byte_pos += chars[char_idx].len_utf8();
+                        char_idx += 1;
+                    }
+                    while char_idx < chars.len() && !word(chars[char_idx]) {
+                        byte_pos += chars[char_idx].len_utf8();
+                        char_idx += 1;
+                    }
+                    while char_idx < chars.len() && word(chars[char_idx]) {
+                        byte_pos += chars[char_idx].len_utf8();
+                        char_idx += 1;
+                    }
+                    byte_pos = byte_pos.saturating_sub(1);
+                }
+                WordMotion::BackStart => {
+                    if char_idx > 0 {
+                        char_idx -= 1;
+                        byte_pos = byte_pos.saturating_sub(chars[char_idx].len_utf8());
+                    }
+                    while char_idx > 0 && !word(chars[char_idx]) {
+                        char_idx -= 1;
+                        byte_pos = byte_pos.saturating_sub(chars[char_idx].len_utf8());
+                    }
+                    while char_idx > 0 && word(chars[char_idx]) {
+                        char_idx -= 1;
+                        byte_pos = byte_pos.saturating_sub(chars[char_idx].len_utf8());
+                    }
+                }
+            }
+        }
+        start.min(byte_pos)..start.max(byte_pos)
+    }
+
+    /// iw/aw and i"/a" as they were computed over the whole document
+    fn whole_text_object(text: &str, start: usize, obj: TextObject) -> std::ops::Range<usize> {
+        let word = |c: char| c.is_alphanumeric() || c == '_';
+        match obj {
+            TextObject::Word { inner } => {
+                let chars: Vec<char> = text.chars().collect();
+                let char_idx = text[..start].chars().count();
+                let mut word_start = char_idx;
+                while word_start > 0 && word(chars[word_start - 1]) {
+                    word_start -= 1;
+                }
+                let mut word_end = char_idx;
+                while word_end < chars.len() && word(chars[word_end]) {
+                    word_end += 1;
+                }
+                if !inner {
+                    while word_end < chars.len() && chars[word_end].is_whitespace() {
+                        word_end += 1;
+                    }
+                }
+                let byte = |i: usize| text.char_indices().nth(i).map_or(text.len(), |(b, _)| b);
+                byte(word_start)..byte(word_end)
+            }
+            TextObject::Quotes { inner } => {
+                let bytes = text.as_bytes();
+                let mut open = start;
+                while open > 0 && bytes[open - 1] != b'"' {
+                    open -= 1;
+                }
+                let mut close = start;
+                while close < bytes.len() && bytes[close] != b'"' {
+                    close += 1;
+                }
+                if open == 0 || close >= bytes.len() {
+                    return start..start;
+                }
+                if inner { open..close } else { open - 1..close + 1 }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    const FIXTURES: &[&str] = &[
+        "{\"name\": \"test\", \"value\": 123}",
+        "[\n  {\"id\": 1, \"tags\": [\"a_b\", \"c\"]},\n  {\"id\": 22, \"note\": \"two words\"}\n]\n",
+        "{\"héllo\": \"wörld ñ\", \"日本\": \"語\", \"x\": \"a\\\"b\"}\n",
+        "  \n\n  foo  bar_baz\t42 \n",
+    ];
+
+    #[test]
+    fn test_word_motions_match_whole_text() {
+        for text in FIXTURES {
+            let buffer = Buffer::from_text(text);
+            for (start, _) in text.char_indices() {
+                for motion in [WordMotion::Start, WordMotion::End, WordMotion::BackStart] {
+                    for count in 1..=3 {
+                        let range = Motion::Word(motion, count).calculate_range(&buffer, start).unwrap();
+                        assert_eq!(range, whole_text_word_range(text, start, motion, count), "{:?}{} at {} in {:?}", motion, count, start, text);
+                    }
+                }
+                for obj in [
+                    TextObject::Word { inner: true },
+                    TextObject::Word { inner: false },
+                    TextObject::Quotes { inner: true },
+                    TextObject::Quotes { inner: false },
+                ] {
+                    let range = Motion::TextObject(obj, 1).calculate_range(&buffer, start).unwrap();
+                    assert_eq!(range, whole_text_object(text, start, obj), "{:?} at {} in {:?}", obj, start, text);
+                }
+            }
         }
     }
+
+    #[test]
+    fn test_text_object_counts() {
+        let text = "{\"a\": {\"b\": [1, {\"c\": 2}]}}";
+        let buffer = Buffer::from_text(text);
+        let at = text.find('2').unwrap();
+        let braces = |inner, count| {
+            let range = Motion::TextObject(TextObject::Braces { inner }, count).calculate_range(&buffer, at).unwrap();
+            &text[range]
+        };
+        assert_eq!(braces(true, 1), "\"c\": 2");
+        assert_eq!(braces(false, 2), "{\"b\": [1, {\"c\": 2}]}");
+        assert_eq!(braces(true, 3), &text[1..text.len() - 1]);
+        // Past the outermost pair there's nothing to take
+        assert_eq!(braces(true, 4), "");
+        let words = Buffer::from_text("one two three four");
+        let range = Motion::TextObject(TextObject::Word { inner: false }, 2).calculate_range(&words, 1).unwrap();
+        assert_eq!(range, 0.."one two ".len());
+    }
+
+    /// The text `obj` takes from `at`, found with `index` if given
+    fn object<'a>(text: &'a str, index: Option<&StructuralIndex>, obj: TextObject, count: usize, at: usize) -> &'a str {
+        let buffer = Buffer::from_text(text);
+        &text[Motion::TextObject(obj, count).calculate_range_in(&buffer, index, at).unwrap()]
+    }
+
+    #[test]
+    fn test_nearest_pair() {
+        let text = r#"{"f": "g(x, [y])", "a": [1, {"b": [2, 3]}]}"#;
+        let index = StructuralIndex::from_tokens(&crate::parser::Tokenizer::new(text.to_string()).tokenize_all());
+        let pair = |inner, count, at| {
+            let obj = TextObject::Pair { inner };
+            let found = object(text, Some(&index), obj, count, at);
+            // The index and the scan agree wherever no bracket is in a string
+            if !text[..at].contains("g(") || text[at..].starts_with('"') {
+                assert_eq!(found, object(text, None, obj, count, at), "at {}", at);
+            }
+            found
+        };
+        // Whichever is closest, going out a level per count
+        let two = text.find('2').unwrap();
+        assert_eq!(pair(true, 1, two), "2, 3");
+        assert_eq!(pair(false, 2, two), r#"{"b": [2, 3]}"#);
+        assert_eq!(pair(false, 3, two), r#"[1, {"b": [2, 3]}]"#);
+        assert_eq!(pair(true, 4, two), &text[1..text.len() - 1]);
+        assert_eq!(pair(true, 5, two), "");
+        // On a delimiter, the pair it belongs to
+        let open = text.find("[2").unwrap();
+        assert_eq!(pair(false, 1, open), "[2, 3]");
+        assert_eq!(pair(false, 1, open + "[2, 3".len()), "[2, 3]");
+        assert_eq!(pair(false, 2, open + "[2, 3".len()), r#"{"b": [2, 3]}"#);
+        assert_eq!(pair(false, 1, 0), text);
+        assert_eq!(pair(false, 1, text.len() - 1), text);
+        // Beside a closed pair, not in it
+        assert_eq!(object("[[1], [2]]", None, TextObject::Pair { inner: true }, 1, 4), "[1], [2]");
+
+        // In a string, parentheses count; the index doesn't see the
+        // bracket, the scan without it does
+        let y = text.find('y').unwrap();
+        assert_eq!(pair(true, 1, y), "x, [y]");
+        assert_eq!(object(text, None, TextObject::Pair { inner: true }, 1, y), "y");
+        let x = text.find('x').unwrap();
+        assert_eq!(pair(false, 1, x), "(x, [y])");
+        assert_eq!(pair(false, 2, x), text);
+    }
+
+    #[test]
+    fn test_angles() {
+        let text = "{\"t\": \"Map<K, Vec<V>>\", \"u\": \"<\n>\"}";
+        let angles = |inner, count, at| object(text, None, TextObject::Angles { inner }, count, at);
+        let v = text.find("V>").unwrap();
+        assert_eq!(angles(true, 1, v), "V");
+        assert_eq!(angles(false, 2, v), "<K, Vec<V>>");
+        assert_eq!(angles(true, 3, v), "");
+        // On either delimiter
+        let open = text.find("<K").unwrap();
+        assert_eq!(angles(true, 1, open), "K, Vec<V>");
+        assert_eq!(angles(true, 1, text.find(">>").unwrap()), "V");
+        assert_eq!(angles(true, 1, text.find(">>").unwrap() + 1), "K, Vec<V>");
+        // Not across lines, nor outside a pair
+        assert_eq!(angles(false, 1, text.find("<\n").unwrap()), "");
+        assert_eq!(angles(false, 1, 1), "");
+    }
+
+    #[test]
+    fn test_pair_index_edited() {
+        // Once an edit dirties the container the text is scanned instead
+        let text = "[1, [2, 3], 4]";
+        let mut index = StructuralIndex::from_tokens(&crate::parser::Tokenizer::new(text.to_string()).tokenize_all());
+        let three = text.find('3').unwrap();
+        assert_eq!(object(text, Some(&index), TextObject::Pair { inner: true }, 1, three), "2, 3");
+        let edited = "[1, [2, (3)], 4]";
+        index.apply_change(&crate::buffer::TextChange { offset: three, removed: 1, inserted: 3 });
+        assert_eq!(object(edited, Some(&index), TextObject::Pair { inner: true }, 1, three + 1), "3");
+    }
+
+    #[test]
+    fn test_word_motion_on_large_file() {
+        // Past the size where files are read lazily rather than loaded
+        let path = std::env::temp_dir().join(format!("jim_word_motion_{}.json", std::process::id()));
+        let record = "{\"id\": 12345, \"name\": \"item\", \"value\": 3.25},\n";
+        let text = format!("[\n{}{}]\n", record.repeat(100 * 1024 * 1024 / record.len()), record.trim_end_matches(",\n"));
+        std::fs::write(&path, &text).unwrap();
+        drop(text);
+        let mut buffer = Buffer::new();
+        buffer.load_file(path.to_str().unwrap()).unwrap();
+
+        let middle = buffer.line_to_byte_offset(buffer.line_count() / 2);
+        let started = std::time::Instant::now();
+        let forward = Motion::Word(WordMotion::Start, 1).calculate_range(&buffer, middle).unwrap();
+        let back = Motion::Word(WordMotion::BackStart, 1).calculate_range(&buffer, middle).unwrap();
+        let elapsed = started.elapsed();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(forward, middle..middle + "{\"id".len());
+        // b stops on the character before the word, as it always has
+        assert_eq!(back, middle - ".25},\n".len()..middle);
+        // Copying the file would take far longer than this
+        assert!(elapsed < std::time::Duration::from_millis(20), "took {:?}", elapsed);
+    }
 }
@@ -1,5 +1,16 @@
 use std::collections::HashMap;
 
+use super::clipboard;
+
+/// The black hole register: text sent to it is dropped, and it holds
+/// nothing to put
+pub const BLACK_HOLE: char = '_';
+
+/// Whether `name` can follow " to pick a register
+pub fn is_register(name: char) -> bool {
+    matches!(name, 'a'..='z' | 'A'..='Z' | '0' | '-' | '+' | '*' | BLACK_HOLE)
+}
+
 /// Register system for yank/delete/paste operations
 #[derive(Debug, Clone)]
 pub struct RegisterMap {
@@ -11,6 +22,11 @@ pub struct RegisterMap {
     last_yank: String,
     /// Small delete register (- register, <1 line)
     small_delete: String,
+    /// Last text sent to the system clipboard (+ and * registers), pasted
+    /// when no clipboard tool can read it back
+    clipboard: String,
+    /// Clipboard text the app has yet to send to the terminal
+    outgoing: Option<String>,
 }
 
 impl RegisterMap {
@@ -20,14 +36,19 @@ impl RegisterMap {
             unnamed: String::new(),
             last_yank: String::new(),
             small_delete: String::new(),
+            clipboard: String::new(),
+            outgoing: None,
         }
     }
     
-    /// Store text in a register
+    /// Store text in a register. Yanks also go to 0 and deletes of less
+    /// than a line to -, so a delete after a yank leaves the yank in 0.
     pub fn set(&mut self, register: Option<char>, text: String, is_yank: bool) {
         match register {
-            Some(reg) if ('a'..='z').contains(&reg) || ('A'..='Z').contains(&reg) => {
-                if ('A'..='Z').contains(&reg) {
+            Some(BLACK_HOLE) => return,
+            Some(reg) if clipboard::is_clipboard(reg) => self.set_clipboard(text.clone()),
+            Some(reg) if reg.is_ascii_alphabetic() => {
+                if reg.is_ascii_uppercase() {
                     // Uppercase appends to register
                     let lower = reg.to_ascii_lowercase();
                     let existing = self.registers.get(&lower).cloned().unwrap_or_default();
@@ -55,9 +76,13 @@ impl RegisterMap {
     /// Get text from a register
     pub fn get(&self, register: Option<char>) -> Option<String> {
         match register {
+            Some(BLACK_HOLE) => None,
             Some('0') => Some(self.last_yank.clone()),
             Some('-') => Some(self.small_delete.clone()),
-            Some(reg) if ('a'..='z').contains(&reg) || ('A'..='Z').contains(&reg) => {
+            Some(reg) if clipboard::is_clipboard(reg) => {
+                clipboard::paste_with_tool().ok().or_else(|| Some(self.clipboard.clone()))
+            }
+            Some(reg) if reg.is_ascii_alphabetic() => {
                 let lower = reg.to_ascii_lowercase();
                 self.registers.get(&lower).cloned()
             }
@@ -65,6 +90,29 @@ impl RegisterMap {
         }
     }
     
+    /// Put over a selection: the text of `register` to put in its place,
+    /// after which the `replaced` text is in the unnamed register as a
+    /// delete would leave it, with 0 still the last yank. None when the
+    /// register is empty, leaving everything as it was.
+    pub fn put_over(&mut self, register: Option<char>, replaced: String) -> Option<String> {
+        let text = self.get(register).filter(|text| !text.is_empty())?;
+        self.set(None, replaced, false);
+        Some(text)
+    }
+    
+    /// Put `text` on the system clipboard only, leaving the other registers
+    /// alone
+    pub fn set_clipboard(&mut self, text: String) {
+        self.clipboard = text.clone();
+        self.outgoing = Some(text);
+    }
+    
+    /// Text put in the clipboard registers since the last call, for the app
+    /// to send on
+    pub fn take_outgoing(&mut self) -> Option<String> {
+        self.outgoing.take()
+    }
+    
     /// Get the unnamed register (default)
     pub fn get_unnamed(&self) -> String {
         self.unnamed.clone()
@@ -76,3 +124,57 @@ impl Default for RegisterMap {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(registers: &RegisterMap, name: Option<char>) -> String {
+        registers.get(name).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_black_hole() {
+        let mut registers = RegisterMap::new();
+        registers.set(None, "kept\n".to_string(), true);
+        registers.set(None, "small".to_string(), false);
+        // "_d and "_y
+        registers.set(Some(BLACK_HOLE), "gone\n".to_string(), false);
+        registers.set(Some(BLACK_HOLE), "gone".to_string(), false);
+        registers.set(Some(BLACK_HOLE), "gone".to_string(), true);
+        assert_eq!(get(&registers, None), "small");
+        assert_eq!(get(&registers, Some('0')), "kept\n");
+        assert_eq!(get(&registers, Some('-')), "small");
+        assert_eq!(registers.get(Some(BLACK_HOLE)), None);
+        assert!(is_register(BLACK_HOLE));
+        assert!(!is_register('!'));
+    }
+
+    #[test]
+    fn test_yank_survives_delete() {
+        // yy, then dd elsewhere: p puts the deleted line, "0p the yank
+        let mut registers = RegisterMap::new();
+        registers.set(None, "yanked\n".to_string(), true);
+        registers.set(None, "deleted\n".to_string(), false);
+        assert_eq!(get(&registers, None), "deleted\n");
+        assert_eq!(get(&registers, Some('0')), "yanked\n");
+    }
+
+    #[test]
+    fn test_put_over() {
+        // yiw, then p over one selection and "0p over the next
+        let mut registers = RegisterMap::new();
+        registers.set(None, "new".to_string(), true);
+        assert_eq!(registers.put_over(None, "first".to_string()).as_deref(), Some("new"));
+        assert_eq!(get(&registers, None), "first");
+        assert_eq!(get(&registers, Some('0')), "new");
+        assert_eq!(registers.put_over(Some('0'), "second".to_string()).as_deref(), Some("new"));
+        assert_eq!(get(&registers, None), "second");
+        assert_eq!(get(&registers, Some('0')), "new");
+
+        // Nothing to put changes nothing
+        assert_eq!(registers.put_over(Some('q'), "third".to_string()), None);
+        assert_eq!(registers.put_over(Some(BLACK_HOLE), "third".to_string()), None);
+        assert_eq!(get(&registers, None), "second");
+    }
+}
@@ -1,7 +1,9 @@
 use anyhow::Result;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use super::{EditorContext, InputResult, ModeHandler, Mode};
+use super::{AppCommand, EditorContext, InputResult, ModeHandler, Mode, NodeOp};
+use super::operator::{Motion, TextObject};
+use super::register::is_register;
 use crate::edit::EditOperations;
 
 /// Visual mode handler - visual selection
@@ -10,6 +12,8 @@ pub struct VisualMode {
     pub selection_start: usize,
     /// Whether this is line-wise visual mode
     pub line_wise: bool,
+    /// Register chosen with " for the next d, y or c
+    selected_register: Option<char>,
 }
 
 impl VisualMode {
@@ -17,6 +21,7 @@ impl VisualMode {
         Self {
             selection_start: start_offset,
             line_wise,
+            selected_register: None,
         }
     }
     
@@ -47,11 +52,13 @@ impl ModeHandler for VisualMode {
             }
             (KeyCode::Char('j'), KeyModifiers::NONE) | (KeyCode::Down, _) => {
                 ctx.cursor.move_down(ctx.buffer);
+                ctx.folds.skip_down(ctx.cursor, ctx.buffer);
                 ctx.cursor.sync_byte_offset(ctx.buffer);
                 Ok(InputResult::Handled)
             }
             (KeyCode::Char('k'), KeyModifiers::NONE) | (KeyCode::Up, _) => {
                 ctx.cursor.move_up(ctx.buffer);
+                ctx.folds.skip_up(ctx.cursor, ctx.buffer);
                 ctx.cursor.sync_byte_offset(ctx.buffer);
                 Ok(InputResult::Handled)
             }
@@ -68,9 +75,24 @@ impl ModeHandler for VisualMode {
                 Ok(InputResult::Handled)
             }
             (KeyCode::Char('$'), KeyModifiers::NONE) => {
-                let line_length = ctx.buffer.get_line(ctx.cursor.line).chars().count();
-                ctx.cursor.move_to_line_end(line_length);
-                ctx.cursor.sync_byte_offset(ctx.buffer);
+                ctx.cursor.move_end_of_line(ctx.buffer);
+                Ok(InputResult::Handled)
+            }
+            
+            // Text objects - iw, a", ib and the rest select the object
+            // around the cursor
+            (KeyCode::Char(which @ ('i' | 'a')), KeyModifiers::NONE) => {
+                let object = match ctx.keys.next_key().map(|key| key.code) {
+                    Some(KeyCode::Char(c)) => TextObject::from_key(c, which == 'i'),
+                    _ => None,
+                };
+                if let Some(object) = object {
+                    let motion = Motion::TextObject(object, 1);
+                    let range = motion.calculate_range_in(ctx.buffer, ctx.structural_index, ctx.cursor.byte_offset)?;
+                    self.selection_start = range.start;
+                    ctx.cursor.byte_offset = range.end;
+                    (ctx.cursor.line, ctx.cursor.col) = ctx.buffer.offset_to_line_col(range.end);
+                }
                 Ok(InputResult::Handled)
             }
             
@@ -81,6 +103,42 @@ impl ModeHandler for VisualMode {
                 Ok(InputResult::Handled)
             }
             
+            // Command line for the selection, :'<,'>
+            (KeyCode::Char(':'), _) => Ok(InputResult::ModeSwitch(Mode::Command)),
+            
+            // Register for the operator that follows
+            (KeyCode::Char('"'), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                if let Some(KeyEvent { code: KeyCode::Char(name), .. }) = ctx.keys.next_key() {
+                    match name {
+                        '"' => self.selected_register = None,
+                        name if is_register(name) => self.selected_register = Some(name),
+                        _ => {}
+                    }
+                }
+                Ok(InputResult::Handled)
+            }
+            
+            // gq - wrap the string the selection is in; gJ - join its lines
+            // without touching the spaces
+            (KeyCode::Char('g'), KeyModifiers::NONE) => match ctx.keys.next_key().map(|key| key.code) {
+                Some(KeyCode::Char('q')) => {
+                    let (start, end) = self.get_selection_range(ctx.cursor.byte_offset);
+                    let op = NodeOp::Rewrap { selection: Some(start..end) };
+                    Ok(InputResult::Command(AppCommand::Node { op, register: None }))
+                }
+                Some(KeyCode::Char('J')) => {
+                    let (start, end) = self.get_selection_range(ctx.cursor.byte_offset);
+                    Ok(InputResult::Command(AppCommand::Join { count: 0, raw: true, selection: Some(start..end) }))
+                }
+                _ => Ok(InputResult::NotHandled),
+            },
+            
+            // J - join the selected lines
+            (KeyCode::Char('J'), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                let (start, end) = self.get_selection_range(ctx.cursor.byte_offset);
+                Ok(InputResult::Command(AppCommand::Join { count: 0, raw: false, selection: Some(start..end) }))
+            }
+            
             // Operators on selection
             (KeyCode::Char('d'), KeyModifiers::NONE) | (KeyCode::Char('x'), KeyModifiers::NONE) => {
                 let cursor_offset = ctx.cursor.byte_offset;
@@ -88,25 +146,12 @@ impl ModeHandler for VisualMode {
                 let text = ctx.buffer.slice(start..end);
                 
                 let edit = EditOperations::delete(ctx.buffer, ctx.cursor, start, end)?;
-                ctx.register_map.set(None, text, false);
+                ctx.register_map.set(self.selected_register, text, false);
                 ctx.undo_stack.push(edit);
                 
                 // Move cursor to start of selection
                 ctx.cursor.byte_offset = start;
-                let line = ctx.buffer.byte_offset_to_line(start);
-                ctx.cursor.line = line;
-                let line_start = ctx.buffer.line_to_byte_offset(line);
-                let offset_in_line = start - line_start;
-                let line_text = ctx.buffer.get_line(line);
-                ctx.cursor.col = line_text.chars()
-                    .take_while(|_| {
-                        let bytes: usize = line_text.chars()
-                            .take(ctx.cursor.col)
-                            .map(|c| c.len_utf8())
-                            .sum();
-                        bytes < offset_in_line
-                    })
-                    .count();
+                (ctx.cursor.line, ctx.cursor.col) = ctx.buffer.offset_to_line_col(start);
                 
                 Ok(InputResult::ModeSwitch(Mode::Normal))
             }
@@ -115,24 +160,26 @@ impl ModeHandler for VisualMode {
                 let cursor_offset = ctx.cursor.byte_offset;
                 let (start, end) = self.get_selection_range(cursor_offset);
                 let text = ctx.buffer.slice(start..end);
-                ctx.register_map.set(None, text, true);
+                ctx.register_map.set(self.selected_register, text, true);
                 
                 // Move cursor to start of selection
                 ctx.cursor.byte_offset = start;
-                let line = ctx.buffer.byte_offset_to_line(start);
-                ctx.cursor.line = line;
-                let line_start = ctx.buffer.line_to_byte_offset(line);
-                let offset_in_line = start - line_start;
-                let line_text = ctx.buffer.get_line(line);
-                ctx.cursor.col = line_text.chars()
-                    .take_while(|_| {
-                        let bytes: usize = line_text.chars()
-                            .take(ctx.cursor.col)
-                            .map(|c| c.len_utf8())
-                            .sum();
-                        bytes < offset_in_line
-                    })
-                    .count();
+                (ctx.cursor.line, ctx.cursor.col) = ctx.buffer.offset_to_line_col(start);
+                
+                Ok(InputResult::ModeSwitch(Mode::Normal))
+            }
+            
+            // Put over the selection; what it replaces goes to the unnamed
+            // register, leaving the yank in 0 for the next put
+            (KeyCode::Char('p'), KeyModifiers::NONE) | (KeyCode::Char('P'), KeyModifiers::SHIFT) => {
+                let cursor_offset = ctx.cursor.byte_offset;
+                let (start, end) = self.get_selection_range(cursor_offset);
+                let replaced = ctx.buffer.slice(start..end);
+                let Some(text) = ctx.register_map.put_over(self.selected_register, replaced) else {
+                    return Ok(InputResult::Message("Nothing in register".to_string()));
+                };
+                let edit = EditOperations::replace(ctx.buffer, ctx.cursor, start, end, &text)?;
+                ctx.undo_stack.push(edit);
                 
                 Ok(InputResult::ModeSwitch(Mode::Normal))
             }
@@ -143,25 +190,12 @@ impl ModeHandler for VisualMode {
                 let text = ctx.buffer.slice(start..end);
                 
                 let edit = EditOperations::delete(ctx.buffer, ctx.cursor, start, end)?;
-                ctx.register_map.set(None, text, false);
+                ctx.register_map.set(self.selected_register, text, false);
                 ctx.undo_stack.push(edit);
                 
                 // Move cursor to start and enter insert mode
                 ctx.cursor.byte_offset = start;
-                let line = ctx.buffer.byte_offset_to_line(start);
-                ctx.cursor.line = line;
-                let line_start = ctx.buffer.line_to_byte_offset(line);
-                let offset_in_line = start - line_start;
-                let line_text = ctx.buffer.get_line(line);
-                ctx.cursor.col = line_text.chars()
-                    .take_while(|_| {
-                        let bytes: usize = line_text.chars()
-                            .take(ctx.cursor.col)
-                            .map(|c| c.len_utf8())
-                            .sum();
-                        bytes < offset_in_line
-                    })
-                    .count();
+                (ctx.cursor.line, ctx.cursor.col) = ctx.buffer.offset_to_line_col(start);
                 
                 Ok(InputResult::ModeSwitch(Mode::Insert))
             }
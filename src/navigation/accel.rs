@@ -0,0 +1,88 @@
+//! Key repeat acceleration for structural motions. Holding Ctrl-j through
+//! an array of a hundred thousand elements would otherwise take as many key
+//! repeats: instead every `DOUBLE_AFTER` repeats of the same motion, each
+//! within `REPEAT_INTERVAL` of the one before, double its step, up to the
+//! `:set nav-accel` maximum. A pause or any other key starts again at one.
+
+use std::time::{Duration, Instant};
+
+use crate::mode::StructuralNavAction;
+
+/// Longer than a terminal's key repeat, shorter than pressing a key again
+pub const REPEAT_INTERVAL: Duration = Duration::from_millis(150);
+
+/// Repeats at each step before it doubles
+pub const DOUBLE_AFTER: usize = 8;
+
+/// The largest step, unless set otherwise
+pub const DEFAULT_MAX_STEP: usize = 64;
+
+/// Whether holding down `action` speeds it up: moves along a line of
+/// siblings, keys, values or records, but not in or out a level
+pub fn accelerates(action: StructuralNavAction) -> bool {
+    matches!(
+        action,
+        StructuralNavAction::NextSibling
+            | StructuralNavAction::PrevSibling
+            | StructuralNavAction::NextKey
+            | StructuralNavAction::PrevKey
+            | StructuralNavAction::NextValue
+            | StructuralNavAction::PrevValue
+            | StructuralNavAction::NextRecord
+            | StructuralNavAction::PrevRecord
+    )
+}
+
+/// The motion being repeated and how many times
+#[derive(Debug, Clone, Default)]
+pub struct Acceleration {
+    last: Option<(StructuralNavAction, Instant)>,
+    repeats: usize,
+}
+
+impl Acceleration {
+    /// How far `action`, pressed at `now`, moves: 1, 2, 4, ... up to `max`
+    pub fn step(&mut self, action: StructuralNavAction, now: Instant, max: usize) -> usize {
+        let repeated = self
+            .last
+            .is_some_and(|(last, at)| last == action && now.saturating_duration_since(at) <= REPEAT_INTERVAL);
+        self.repeats = if repeated { self.repeats + 1 } else { 0 };
+        self.last = Some((action, now));
+        let doublings = (self.repeats / DOUBLE_AFTER).min(usize::BITS as usize - 1);
+        (1usize << doublings).min(max.max(1))
+    }
+
+    /// Something else was pressed
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step() {
+        let mut accel = Acceleration::default();
+        let start = Instant::now();
+        let tick = Duration::from_millis(30);
+        let steps: Vec<usize> = (0..40).map(|n| accel.step(StructuralNavAction::NextSibling, start + tick * n, 8)).collect();
+        assert_eq!(&steps[..9], [1, 1, 1, 1, 1, 1, 1, 1, 2]);
+        assert_eq!(steps[16], 4);
+        // Capped
+        assert_eq!(steps[39], 8);
+
+        // A pause, another motion or another key starts again
+        let later = start + tick * 40 + REPEAT_INTERVAL * 2;
+        assert_eq!(accel.step(StructuralNavAction::NextSibling, later, 8), 1);
+        for n in 1..20 {
+            accel.step(StructuralNavAction::NextSibling, later + tick * n, 8);
+        }
+        assert_eq!(accel.step(StructuralNavAction::PrevSibling, later + tick * 20, 8), 1);
+        accel.reset();
+        assert_eq!(accel.step(StructuralNavAction::PrevSibling, later + tick * 21, 8), 1);
+        // A maximum of 1 turns it off
+        assert_eq!((0..40).map(|n| accel.step(StructuralNavAction::PrevSibling, later + tick * (22 + n), 1)).max(), Some(1));
+    }
+}
@@ -0,0 +1,200 @@
+//! Bookmarks: notes left on nodes with :bookmark add, listed by
+//! :bookmark list and marked in the gutter. A bookmark is kept by the path
+//! to its node, so a session can find it again after the file is
+//! reformatted; while the file is open it's a byte offset that follows
+//! edits, and its path is worked out again before it's saved.
+
+use crate::buffer::{Buffer, TextChange};
+use crate::parser::StructuralIndex;
+
+use super::path::{self, Resolved};
+
+/// Gutter sign of a bookmarked line
+pub const SIGN: char = '*';
+
+/// Where a bookmark's path leads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Place {
+    /// Start of its node
+    Found(usize),
+    /// Not looked up yet, or in a part of the file not indexed yet
+    Pending,
+    /// The path isn't in the document any more
+    Unresolved,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bookmark {
+    /// As json_path writes it, e.g. `$.users[3]`
+    pub path: String,
+    pub note: String,
+    pub place: Place,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Bookmarks {
+    entries: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bookmarks read back from a session as paths and notes, to be
+    /// looked up once the index reaches them
+    pub fn from_saved(saved: Vec<(String, String)>) -> Self {
+        let entries = saved.into_iter().map(|(path, note)| Bookmark { path, note, place: Place::Pending }).collect();
+        Self { entries }
+    }
+
+    /// Paths and notes to keep in a session
+    pub fn saved(&self) -> Vec<(String, String)> {
+        self.entries.iter().map(|bookmark| (bookmark.path.clone(), bookmark.note.clone())).collect()
+    }
+
+    pub fn entries(&self) -> &[Bookmark] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Bookmark the node at `offset` reached by `path`. One already on it
+    /// takes the new note; returns whether there was one.
+    pub fn add(&mut self, path: String, note: String, offset: usize) -> bool {
+        let existing = self.entries.iter_mut().find(|bookmark| bookmark.place == Place::Found(offset) || bookmark.path == path);
+        match existing {
+            Some(bookmark) => {
+                *bookmark = Bookmark { path, note, place: Place::Found(offset) };
+                true
+            }
+            None => {
+                self.entries.push(Bookmark { path, note, place: Place::Found(offset) });
+                false
+            }
+        }
+    }
+
+    /// Take away the bookmark on the node at `offset`
+    pub fn remove(&mut self, offset: usize) -> Option<Bookmark> {
+        let i = self.entries.iter().position(|bookmark| bookmark.place == Place::Found(offset))?;
+        Some(self.entries.remove(i))
+    }
+
+    /// Offsets of the bookmarks found in the document
+    pub fn offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.entries.iter().filter_map(|bookmark| match bookmark.place {
+            Place::Found(offset) => Some(offset),
+            _ => None,
+        })
+    }
+
+    /// Whether any bookmark is waiting for the index
+    pub fn has_pending(&self) -> bool {
+        self.entries.iter().any(|bookmark| bookmark.place == Place::Pending)
+    }
+
+    /// Look up the pending bookmarks in `index`. With the whole file
+    /// indexed (`complete`), those still not found are unresolved. Returns
+    /// whether any changed.
+    pub fn resolve(&mut self, index: &StructuralIndex, buffer: &Buffer, complete: bool) -> bool {
+        let mut changed = false;
+        for bookmark in self.entries.iter_mut().filter(|bookmark| bookmark.place == Place::Pending) {
+            let place = match path::parse_path(&bookmark.path).map(|segments| path::resolve(index, buffer, &segments)) {
+                Ok(Resolved::Found(node)) => index.get(node).map_or(Place::Unresolved, |node| Place::Found(node.start)),
+                Ok(Resolved::Pending) if !complete => continue,
+                _ => Place::Unresolved,
+            };
+            bookmark.place = place;
+            changed = true;
+        }
+        changed
+    }
+
+    /// Look every bookmark up again by path, as after a reload
+    pub fn unresolve(&mut self) {
+        for bookmark in &mut self.entries {
+            bookmark.place = Place::Pending;
+        }
+    }
+
+    /// Work out the paths of the bookmarks found again, from an index of
+    /// the text as it is now, so edits that moved them are kept. Those in
+    /// a part not indexed yet keep the path they had.
+    pub fn refresh_paths(&mut self, index: &StructuralIndex, buffer: &Buffer) {
+        for bookmark in &mut self.entries {
+            if let Place::Found(offset) = bookmark.place {
+                if index.node_id_at(offset).is_some() {
+                    bookmark.path = path::json_path(index, buffer, offset);
+                }
+            }
+        }
+    }
+
+    /// Move found bookmarks along with an edit
+    pub fn apply_change(&mut self, change: &TextChange) {
+        for bookmark in &mut self.entries {
+            if let Place::Found(offset) = &mut bookmark.place {
+                *offset = change.shift_offset(*offset);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    fn index(text: &str) -> StructuralIndex {
+        StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all())
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut bookmarks = Bookmarks::new();
+        assert!(!bookmarks.add("$.a".to_string(), "first".to_string(), 6));
+        assert!(!bookmarks.add("$.b".to_string(), String::new(), 20));
+        // Again on the same node: a new note, not a second bookmark
+        assert!(bookmarks.add("$.a".to_string(), "second".to_string(), 6));
+        assert_eq!(bookmarks.len(), 2);
+        assert_eq!(bookmarks.entries()[0].note, "second");
+
+        bookmarks.apply_change(&TextChange { offset: 0, removed: 0, inserted: 3 });
+        assert_eq!(bookmarks.offsets().collect::<Vec<_>>(), [9, 23]);
+        assert_eq!(bookmarks.remove(6), None);
+        assert_eq!(bookmarks.remove(9).map(|bookmark| bookmark.path), Some("$.a".to_string()));
+        assert_eq!(bookmarks.saved(), [("$.b".to_string(), String::new())]);
+    }
+
+    #[test]
+    fn test_resolve_by_path() {
+        // Saved against one layout, found again in another
+        let text = "{\n  \"users\": [\n    {\"name\": \"a\"},\n    {\"name\": \"b\"}\n  ]\n}";
+        let saved = vec![
+            ("$.users[1].name".to_string(), "check".to_string()),
+            ("$.users[5]".to_string(), "gone".to_string()),
+        ];
+        let mut bookmarks = Bookmarks::from_saved(saved);
+        let buffer = Buffer::from_text(text);
+        let index = index(text);
+        assert!(bookmarks.resolve(&index, &buffer, true));
+        let places: Vec<_> = bookmarks.entries().iter().map(|bookmark| bookmark.place.clone()).collect();
+        assert_eq!(places, [Place::Found(text.find("\"b\"").unwrap()), Place::Unresolved]);
+        assert!(!bookmarks.has_pending());
+        assert!(!bookmarks.resolve(&index, &buffer, true));
+
+        // An element put in ahead of it changes its path
+        let text = "{\"users\": [{\"name\": \"z\"}, {\"name\": \"a\"}, {\"name\": \"b\"}]}";
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.add("$.users[1].name".to_string(), String::new(), text.find("\"b\"").unwrap());
+        bookmarks.refresh_paths(&self::index(text), &Buffer::from_text(text));
+        assert_eq!(bookmarks.entries()[0].path, "$.users[2].name");
+    }
+}
@@ -0,0 +1,193 @@
+//! :extract: every value at a path with wildcards in it, `.items[].id` for
+//! the id of each item. Values are found by walking the structural index
+//! in document order as it's built, a node at a time, so a large file is
+//! gone through while it's still being indexed and nothing but the count
+//! is kept of what's been passed.
+
+use anyhow::{bail, Result};
+
+use super::path::{self, Segment};
+use crate::buffer::Buffer;
+use crate::parser::decode;
+use crate::parser::node::{NodeId, NodeInfo};
+use crate::parser::{NodeKind, StructuralIndex};
+
+/// One step of an :extract path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Exact(Segment),
+    /// `[]` or `[*]`: every element of an array
+    AnyElement,
+    /// `.*`: every value of an object
+    AnyMember,
+}
+
+/// A path whose steps may match more than one value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    steps: Vec<Step>,
+}
+
+impl Pattern {
+    /// Parse a path as :keys! and json_path write them, with `[]`, `[*]`
+    /// or `.*` standing for any element or member. In JSON Lines it
+    /// matches in every record.
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let mut steps = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        let mut quoted = false;
+        while let Some(c) = rest.chars().next() {
+            let wildcard = match () {
+                _ if quoted => None,
+                _ if rest.starts_with("[]") => Some((Step::AnyElement, 2)),
+                _ if rest.starts_with("[*]") => Some((Step::AnyElement, 3)),
+                _ if rest.starts_with(".*") => Some((Step::AnyMember, 2)),
+                _ => None,
+            };
+            if let Some((step, len)) = wildcard {
+                steps.extend(path::parse_path(&std::mem::take(&mut literal))?.into_iter().map(Step::Exact));
+                steps.push(step);
+                rest = &rest[len..];
+                continue;
+            }
+            // Quotes in a key like ["a[]"] hide what's inside them
+            if c == '"' && !literal.ends_with('\\') {
+                quoted = !quoted;
+            }
+            literal.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        steps.extend(path::parse_path(&literal)?.into_iter().map(Step::Exact));
+        if steps.is_empty() {
+            bail!("Usage: :extract path, e.g. .items[].id");
+        }
+        Ok(Self { steps })
+    }
+
+    /// Whether `node` is at the end of the first `len` steps
+    fn matches(&self, index: &StructuralIndex, buffer: &Buffer, node: &NodeInfo, id: NodeId, len: usize) -> bool {
+        if node.depth as usize != len || index.is_key_node(id) {
+            return false;
+        }
+        let mut id = id;
+        for step in self.steps[..len].iter().rev() {
+            let Some(parent) = index.parent(id) else {
+                return false;
+            };
+            let found = match (step, index.kind(parent)) {
+                (Step::AnyElement, Some(NodeKind::Array)) | (Step::AnyMember, Some(NodeKind::Object)) => true,
+                (Step::Exact(Segment::Index(n)), Some(NodeKind::Array)) => index.nth_child(parent, *n) == Some(id),
+                (Step::Exact(Segment::Key(name)), Some(NodeKind::Object)) => index
+                    .prev_sibling(id)
+                    .and_then(|key| index.get(key))
+                    .is_some_and(|key| decode::decode_string(&buffer.slice(key.start..key.end)).ok().as_deref() == Some(name)),
+                _ => false,
+            };
+            if !found {
+                return false;
+            }
+            id = parent;
+        }
+        true
+    }
+
+    /// Steps up to and including the last wildcard: what matches them is
+    /// one record, which the rest of the path may or may not be found in
+    fn record_len(&self) -> Option<usize> {
+        self.steps.iter().rposition(|step| !matches!(step, Step::Exact(_))).map(|last| last + 1)
+    }
+}
+
+/// An :extract going through the index as far as it's been built
+#[derive(Debug, Clone)]
+pub struct Extraction {
+    pattern: Pattern,
+    /// The next node to look at
+    next: NodeId,
+    /// Values found
+    pub values: usize,
+    /// Records the path was looked for in (with no wildcard, none)
+    pub records: usize,
+}
+
+impl Extraction {
+    pub fn new(pattern: Pattern) -> Self {
+        Self { pattern, next: 0, values: 0, records: 0 }
+    }
+
+    /// Records the rest of the path wasn't in
+    pub fn skipped(&self) -> usize {
+        self.records.saturating_sub(self.values)
+    }
+
+    /// Go on through the index, handing each value found to `found`, until
+    /// it says to stop or a value isn't indexed to its end yet. Whether
+    /// every node indexed so far has been looked at.
+    pub fn advance(&mut self, index: &StructuralIndex, buffer: &Buffer, mut found: impl FnMut(&NodeInfo) -> bool) -> bool {
+        let record_len = self.pattern.record_len();
+        let len = self.pattern.steps.len();
+        while self.next < index.len() {
+            let id = self.next;
+            let Some(node) = index.get(id) else { break };
+            let value = self.pattern.matches(index, buffer, &node, id, len);
+            // A container still open has more of it to come
+            if value && node.is_container() && !index.is_closed(id) {
+                return false;
+            }
+            if record_len.is_some_and(|record| self.pattern.matches(index, buffer, &node, id, record)) {
+                self.records += 1;
+            }
+            self.next += 1;
+            if value {
+                self.values += 1;
+                if !found(&node) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    fn extract(text: &str, path: &str) -> (Vec<String>, usize) {
+        let buffer = Buffer::from_text(text);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let mut extraction = Extraction::new(Pattern::parse(path).unwrap());
+        let mut values = Vec::new();
+        assert!(extraction.advance(&index, &buffer, |node| {
+            values.push(buffer.slice(node.start..node.end));
+            true
+        }));
+        (values, extraction.skipped())
+    }
+
+    #[test]
+    fn test_parse() {
+        let steps = |path| Pattern::parse(path).unwrap().steps;
+        assert_eq!(
+            steps("$.items[].id"),
+            [Step::Exact(Segment::Key("items".into())), Step::AnyElement, Step::Exact(Segment::Key("id".into()))],
+        );
+        assert_eq!(steps(".a[*][2].*"), [Step::Exact(Segment::Key("a".into())), Step::AnyElement, Step::Exact(Segment::Index(2)), Step::AnyMember]);
+        assert_eq!(steps("[\"x[]\"]"), [Step::Exact(Segment::Key("x[]".into()))]);
+        assert!(Pattern::parse("").is_err());
+        assert!(Pattern::parse(".a[x]").is_err());
+    }
+
+    #[test]
+    fn test_extract() {
+        let text = r#"{"items": [{"id": 1}, {"name": "id"}, {"id": {"n": [2]}}, {"id": "three"}], "id": 0}"#;
+        assert_eq!(extract(text, ".items[].id"), (vec!["1".into(), "{\"n\": [2]}".into(), "\"three\"".into()], 1));
+        assert_eq!(extract(text, ".items[2].id.n[]").0, ["2"]);
+        assert_eq!(extract(text, ".items[].*").0.len(), 4);
+        assert_eq!(extract(text, ".id"), (vec!["0".into()], 0));
+        assert_eq!(extract(text, ".missing[].id"), (vec![], 0));
+    }
+}
@@ -0,0 +1,247 @@
+//! :filter - hide the elements of an array that don't match a predicate
+//! such as `.status == "failed"` by folding them away. A predicate is a
+//! path into the element, an operator and a literal, nothing more.
+
+use std::cmp::Ordering;
+use std::ops::Range;
+use std::sync::atomic;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::path::{self, Segment};
+use super::search::Literal;
+use crate::buffer::Buffer;
+use crate::parser::decode;
+use crate::parser::node::NodeId;
+use crate::parser::{NodeKind, StructuralIndex, Tokenizer};
+use crate::ui::tasks::Progress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Operators by spelling, longest first so `<=` isn't read as `<`
+const OPERATORS: &[(&str, Op)] =
+    &[("==", Op::Eq), ("!=", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt)];
+
+/// `path op literal`, tested against each element of an array
+#[derive(Debug, Clone, PartialEq)]
+pub struct Predicate {
+    path: Vec<Segment>,
+    op: Op,
+    literal: Literal,
+}
+
+impl Predicate {
+    /// Parse e.g. `.status == "failed"`, `.user.age >= 18` or `. != null`
+    /// (the element itself). The literal reads as it does for :value.
+    pub fn parse(text: &str) -> Result<Self> {
+        let (at, spelling, op) = find_operator(text)
+            .ok_or_else(|| anyhow!("Expected ==, !=, <, <=, > or >= in {}", text.trim()))?;
+        let literal = text[at + spelling.len()..].trim();
+        if literal.is_empty() {
+            bail!("Expected a value after {}", spelling);
+        }
+        let path = match text[..at].trim() {
+            "." => Vec::new(),
+            path if path.starts_with(['.', '[']) => path::parse_path(path)?,
+            path => bail!("Expected a path like .status, not {}", path),
+        };
+        Ok(Self { path, op, literal: Literal::parse(literal) })
+    }
+
+    /// Whether `element` passes. A path the element doesn't have reads
+    /// as null.
+    pub fn matches(&self, index: &StructuralIndex, buffer: &Buffer, element: NodeId) -> bool {
+        let mut node = Some(element);
+        for segment in &self.path {
+            node = node.and_then(|node| path::member(index, buffer, node, segment));
+        }
+        let ordering = match node {
+            Some(node) => compare(index, buffer, node, &self.literal),
+            None => (self.literal == Literal::Null).then_some(Ordering::Equal),
+        };
+        match self.op {
+            Op::Eq => ordering == Some(Ordering::Equal),
+            Op::Ne => ordering != Some(Ordering::Equal),
+            Op::Lt => ordering == Some(Ordering::Less),
+            Op::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Op::Gt => ordering == Some(Ordering::Greater),
+            Op::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+/// The first operator outside a quoted key
+fn find_operator(text: &str) -> Option<(usize, &'static str, Op)> {
+    let (mut quoted, mut escaped) = (false, false);
+    for (at, c) in text.char_indices() {
+        match c {
+            '\\' if quoted => {
+                escaped = !escaped;
+                continue;
+            }
+            '"' if !escaped => quoted = !quoted,
+            _ if !quoted => {
+                if let Some(&(spelling, op)) = OPERATORS.iter().find(|(spelling, _)| text[at..].starts_with(spelling)) {
+                    return Some((at, spelling, op));
+                }
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    None
+}
+
+/// How the value of `node` orders against `literal`; None when they're
+/// different types
+fn compare(index: &StructuralIndex, buffer: &Buffer, node: NodeId, literal: &Literal) -> Option<Ordering> {
+    let info = index.get(node)?;
+    let text = buffer.slice(info.start..info.end);
+    match (info.kind, literal) {
+        (NodeKind::Number, Literal::Number(value)) => Some(decode::decode_number(&text)?.total_cmp(value)),
+        (NodeKind::String, Literal::String(value)) => Some(decode::decode_string(&text).ok()?.as_str().cmp(value.as_str())),
+        (NodeKind::Boolean, Literal::Bool(value)) => Some((text == "true").cmp(value)),
+        (NodeKind::Null, Literal::Null) => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+/// What a filter hides in an array
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filtered {
+    /// Runs of failing elements, as whole lines of the array's text (up to
+    /// the newline of the last), with how many elements each holds
+    pub runs: Vec<(Range<usize>, usize)>,
+    pub total: usize,
+    /// Failing elements on lines they share with passing ones, or with the
+    /// brackets, which stay in view
+    pub kept: usize,
+}
+
+impl Filtered {
+    pub fn hidden(&self) -> usize {
+        self.runs.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// Test each element of the array `text` against `predicate`. Only whole
+/// lines can be folded away, so a run of failing elements is hidden when
+/// nothing else shares its first and last lines. `progress` counts the
+/// bytes of the array gone through; cancelled, it stops there.
+pub fn filter_array(text: &str, predicate: &Predicate, progress: &Progress) -> Filtered {
+    let buffer = Buffer::from_text(text);
+    let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).with_comments(true).tokenize_all());
+    let mut filtered = Filtered::default();
+    let Some(array) = index.nth_root(0).filter(|&root| index.kind(root) == Some(NodeKind::Array)) else {
+        return filtered;
+    };
+
+    let mut run: Option<(Range<usize>, usize)> = None;
+    let end_run = |run: &mut Option<(Range<usize>, usize)>, filtered: &mut Filtered| {
+        let Some((span, count)) = run.take() else { return };
+        let line_start = text[..span.start].rfind('\n').map_or(0, |at| at + 1);
+        let line_end = text[span.end..].find('\n').map_or(text.len(), |at| span.end + at);
+        let after = text[span.end..line_end].trim();
+        if text[line_start..span.start].trim().is_empty() && (after.is_empty() || after == ",") {
+            filtered.runs.push((line_start..line_end, count));
+        } else {
+            filtered.kept += count;
+        }
+    };
+    progress.total.store(text.len(), atomic::Ordering::Relaxed);
+    for element in index.child_iter(array) {
+        if progress.is_cancelled() {
+            break;
+        }
+        filtered.total += 1;
+        let Some(node) = index.get(element) else { continue };
+        progress.done.store(node.end, atomic::Ordering::Relaxed);
+        if predicate.matches(&index, &buffer, element) {
+            end_run(&mut run, &mut filtered);
+        } else {
+            run = Some(match run.take() {
+                Some((span, count)) => (span.start..node.end, count + 1),
+                None => (node.start..node.end, 1),
+            });
+        }
+    }
+    end_run(&mut run, &mut filtered);
+    filtered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JOBS: &str = r#"[
+  {"id": 1, "status": "failed", "took": 3.5},
+  {"id": 2, "status": "ok", "took": 1},
+  {"id": 3, "status": "ok"},
+  {
+    "id": 4,
+    "status": "failed",
+    "took": 12
+  },
+  {"id": 5, "status": "ok", "took": 2}, {"id": 6, "status": "failed"},
+  {"id": 7, "status": "ok", "retried": true}
+]"#;
+
+    /// The ids of the elements still shown
+    fn shown(predicate: &str) -> Vec<usize> {
+        let filtered = filter_array(JOBS, &Predicate::parse(predicate).unwrap(), &Progress::default());
+        JOBS.lines()
+            .enumerate()
+            .filter(|(line, _)| {
+                let start = JOBS.lines().take(*line).map(|l| l.len() + 1).sum::<usize>();
+                !filtered.runs.iter().any(|(span, _)| span.start <= start && start < span.end)
+            })
+            .flat_map(|(_, line)| line.split("\"id\": ").skip(1))
+            .filter_map(|rest| rest.split(',').next()?.trim().parse().ok())
+            .collect()
+    }
+
+    #[test]
+    fn test_predicates() {
+        // 5 fails but shares its line with 6
+        assert_eq!(shown(r#".status == "failed""#), [1, 4, 5, 6]);
+        assert_eq!(shown(".took >= 3"), [1, 4]);
+        assert_eq!(shown(".took < 3"), [2, 5, 6, 7]);
+        // A missing member is null
+        assert_eq!(shown(".took == null"), [3, 4, 5, 6, 7]);
+        assert_eq!(shown(".retried != null"), [7]);
+        assert_eq!(shown(".retried == true"), [7]);
+        assert_eq!(shown(r#"["id"] == 2"#), [2]);
+        assert_eq!(shown(". != null").len(), 7);
+    }
+
+    #[test]
+    fn test_runs() {
+        let filtered = filter_array(JOBS, &Predicate::parse(r#".status == "ok""#).unwrap(), &Progress::default());
+        assert_eq!(filtered.total, 7);
+        // 1 and 4 go on their own; 6 shares a line with 5, which passes
+        assert_eq!(filtered.runs.iter().map(|(_, count)| *count).collect::<Vec<_>>(), [1, 1]);
+        assert_eq!((filtered.hidden(), filtered.kept), (2, 1));
+        let (span, _) = &filtered.runs[1];
+        assert!(JOBS[span.clone()].starts_with("  {\n    \"id\": 4"));
+        assert!(JOBS[span.clone()].ends_with("  },"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Predicate::parse(".status").is_err());
+        assert!(Predicate::parse(".status ==").is_err());
+        assert!(Predicate::parse("status == 1").is_err());
+        // Operators inside a quoted key don't count
+        let predicate = Predicate::parse(r#"["a<b"] < 2"#).unwrap();
+        assert_eq!(predicate.path, [Segment::Key("a<b".to_string())]);
+        assert_eq!(predicate.op, Op::Lt);
+    }
+}
@@ -0,0 +1,110 @@
+//! The jump list: where the cursor was before each long jump, to go back
+//! through with Ctrl-o and forward again with Tab (Ctrl-i), as in Vim.
+//! Entries are byte offsets and aren't moved by edits; callers clamp them
+//! to the buffer.
+
+/// Most places remembered; the oldest goes first
+pub const MAX_JUMPS: usize = 100;
+
+#[derive(Debug, Clone, Default)]
+pub struct JumpList {
+    offsets: Vec<usize>,
+    /// Entry Ctrl-o or Tab last went to; the length while not walking
+    /// the list
+    current: usize,
+}
+
+impl JumpList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// A jump is leaving `from`. Entries stepped back over are dropped,
+    /// and an older entry for the same place moves up to the newest.
+    pub fn push(&mut self, from: usize) {
+        self.offsets.truncate(self.current);
+        self.offsets.retain(|&offset| offset != from);
+        self.offsets.push(from);
+        if self.offsets.len() > MAX_JUMPS {
+            self.offsets.remove(0);
+        }
+        self.current = self.offsets.len();
+    }
+
+    /// Ctrl-o from `at`: the place `count` jumps back, or None with none
+    /// that far. Leaving the newest end remembers `at`, for Tab to return.
+    pub fn back(&mut self, at: usize, count: usize) -> Option<usize> {
+        if self.current == self.offsets.len() {
+            if self.offsets.last() != Some(&at) {
+                self.push(at);
+            }
+            self.current = self.offsets.len() - 1;
+        }
+        self.current = self.current.checked_sub(count.max(1))?;
+        Some(self.offsets[self.current])
+    }
+
+    /// Tab: the place `count` entries forward again, if Ctrl-o went back
+    pub fn forward(&mut self, count: usize) -> Option<usize> {
+        let next = self.current + count.max(1);
+        let &offset = self.offsets.get(next)?;
+        self.current = next;
+        Some(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_back_and_forward() {
+        let mut jumps = JumpList::new();
+        assert_eq!(jumps.back(5, 1), None);
+        jumps.push(10);
+        jumps.push(20);
+        jumps.push(30);
+        // At 40 now: back through 30 and 20, then forward to 40 again
+        assert_eq!(jumps.back(40, 1), Some(30));
+        assert_eq!(jumps.back(30, 1), Some(20));
+        assert_eq!(jumps.forward(1), Some(30));
+        assert_eq!(jumps.forward(1), Some(40));
+        assert_eq!(jumps.forward(1), None);
+        assert_eq!(jumps.back(40, 3), Some(10));
+        assert_eq!(jumps.back(10, 1), None);
+
+        // A jump from the middle drops what was ahead
+        jumps.push(15);
+        assert_eq!(jumps.len(), 1);
+        assert_eq!(jumps.back(50, 1), Some(15));
+        assert_eq!(jumps.forward(1), Some(50));
+    }
+
+    #[test]
+    fn test_duplicates_and_limit() {
+        let mut jumps = JumpList::new();
+        for offset in [1, 2, 1] {
+            jumps.push(offset);
+        }
+        assert_eq!(jumps.len(), 2);
+        // Going back from where the last jump left doesn't stay put
+        assert_eq!(jumps.back(1, 1), Some(2));
+
+        let mut jumps = JumpList::new();
+        for offset in 0..MAX_JUMPS + 10 {
+            jumps.push(offset);
+        }
+        assert_eq!(jumps.len(), MAX_JUMPS);
+        // Going back remembers where from, which pushes out one more
+        assert_eq!(jumps.back(1000, MAX_JUMPS), None);
+        assert_eq!(jumps.back(1000, MAX_JUMPS - 1), Some(11));
+    }
+}
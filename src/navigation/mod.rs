@@ -1,2 +1,417 @@
-// Navigation module - Phase 0 Week 3
-// To be implemented: structural navigation and cursor management
+//! Structural navigation: where each motion lands in the index
+
+pub mod accel;
+pub mod path;
+pub mod extract;
+pub mod filter;
+pub mod search;
+pub mod jumps;
+pub mod outline;
+pub mod bookmarks;
+
+use std::collections::HashMap;
+
+use crate::mode::StructuralNavAction;
+use crate::parser::node::NodeId;
+use crate::parser::{ChildCount, NodeKind, StructuralIndex};
+
+/// The node a structural motion lands on. Sibling, parent and child moves
+/// go from `current`, the node the last motion landed on or the one under
+/// the cursor; key, value and record moves search from the cursor's
+/// `offset`. Errors aren't nodes, so their motions land nowhere.
+///
+/// Motions are repeated `count` times, each from where the last landed,
+/// stopping early at the end of the line of siblings (or keys, or records).
+/// The child-by-position motions take no count.
+pub fn target(
+    index: &StructuralIndex,
+    action: StructuralNavAction,
+    current: Option<NodeId>,
+    offset: usize,
+    count: usize,
+) -> Option<NodeId> {
+    match action {
+        StructuralNavAction::LastChild => last_member(index, container(index, current?)?),
+        StructuralNavAction::NthChild(n) => nth_member(index, container(index, current?)?, n.checked_sub(1)?),
+        StructuralNavAction::ArrayIndex(n) => index.nth_child(array_element(index, current?)?.0, n.checked_sub(1)?),
+        _ => {
+            let (mut current, mut offset, mut target) = (current, offset, None);
+            for _ in 0..count.max(1) {
+                let Some(next) = step(index, action, current, offset) else {
+                    break;
+                };
+                (current, offset, target) = (Some(next), index.get(next)?.start, Some(next));
+            }
+            target
+        }
+    }
+}
+
+/// One repetition of a counted motion
+fn step(
+    index: &StructuralIndex,
+    action: StructuralNavAction,
+    current: Option<NodeId>,
+    offset: usize,
+) -> Option<NodeId> {
+    match action {
+        StructuralNavAction::NextSibling => index.next_sibling(current?),
+        StructuralNavAction::PrevSibling => index.prev_sibling(current?),
+        StructuralNavAction::Parent => index.parent(current?),
+        StructuralNavAction::FirstChild => index.first_child(current?),
+        StructuralNavAction::NextKey => index.next_key(offset),
+        StructuralNavAction::PrevKey => index.prev_key(offset),
+        StructuralNavAction::NextValue => index.next_value(offset),
+        StructuralNavAction::PrevValue => index.prev_value(offset),
+        StructuralNavAction::NextRecord => index.next_root(offset),
+        StructuralNavAction::PrevRecord => index.prev_root(offset),
+        StructuralNavAction::NextError
+        | StructuralNavAction::PrevError
+        | StructuralNavAction::NextDiagnostic
+        | StructuralNavAction::PrevDiagnostic
+        | StructuralNavAction::NextDuplicate
+        | StructuralNavAction::PrevDuplicate
+        | StructuralNavAction::NextChange
+        | StructuralNavAction::PrevChange
+        | StructuralNavAction::LastChild
+        | StructuralNavAction::NthChild(_)
+        | StructuralNavAction::ArrayIndex(_) => None,
+    }
+}
+
+/// The container a child-by-position motion picks from: the node itself,
+/// or for a scalar the container it's in
+fn container(index: &StructuralIndex, node: NodeId) -> Option<NodeId> {
+    match index.kind(node)? {
+        NodeKind::Object | NodeKind::Array => Some(node),
+        _ => index.parent(node),
+    }
+}
+
+/// The `n`th (0-based) member of a container; an object's member is its key
+fn nth_member(index: &StructuralIndex, container: NodeId, n: usize) -> Option<NodeId> {
+    index.child_iter(container).filter(|&id| index.starts_member(id)).nth(n)
+}
+
+fn last_member(index: &StructuralIndex, container: NodeId) -> Option<NodeId> {
+    std::iter::successors(index.last_child(container), |&id| index.prev_sibling(id)).find(|&id| index.starts_member(id))
+}
+
+/// The array around a node and the element of it the node is in: the
+/// node itself when it's an array element, or its nearest ancestor that is
+pub fn array_element(index: &StructuralIndex, node: NodeId) -> Option<(NodeId, NodeId)> {
+    let mut element = node;
+    loop {
+        let parent = index.parent(element)?;
+        if index.kind(parent)? == NodeKind::Array {
+            return Some((parent, element));
+        }
+        element = parent;
+    }
+}
+
+/// Most containers whose counts are remembered at once
+const REMEMBERED_ARRAYS: usize = 64;
+
+/// Positions of elements in their arrays and members in their objects, for
+/// the status bar and the `item 3/10` after a motion. Counting a child's
+/// place in a big container means walking the sibling links back to the
+/// start, so each container remembers the child last counted and the next
+/// count walks from there: stepping along even an object of 200,000 keys
+/// costs a step.
+#[derive(Debug, Default)]
+pub struct ItemPositions {
+    /// What the positions were counted against; any change of it (an
+    /// edit, a rebuilt index) forgets them
+    stamp: u64,
+    arrays: HashMap<NodeId, Counted>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Counted {
+    element: NodeId,
+    position: usize,
+    count: ChildCount,
+    /// Index length when the elements were counted. An array whose end
+    /// isn't indexed yet is counted again once the index grows.
+    index_len: usize,
+}
+
+impl ItemPositions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where `element` stands among the members of `array`, 1-based, and
+    /// how many members there are, counted against an index marked `stamp`
+    pub fn position(&mut self, index: &StructuralIndex, array: NodeId, element: NodeId, stamp: u64) -> (usize, ChildCount) {
+        if stamp != self.stamp || self.arrays.len() >= REMEMBERED_ARRAYS {
+            self.arrays.clear();
+            self.stamp = stamp;
+        }
+        let known = self.arrays.get(&array).copied();
+        let position = known
+            .and_then(|known| walk_siblings(index, known.element, known.position, element))
+            .unwrap_or_else(|| index.members_through(element));
+        let count = match known {
+            Some(known) if known.count.complete || known.index_len == index.len() => known.count,
+            _ => index.child_count(array).unwrap_or(ChildCount { count: 0, complete: false }),
+        };
+        self.arrays.insert(array, Counted { element, position, count, index_len: index.len() });
+        (position.max(1), count)
+    }
+
+    /// Where a node stands among its container's members, 1-based, and how
+    /// many members there are. An object's key and value share a position.
+    pub fn member(&mut self, index: &StructuralIndex, node: NodeId, stamp: u64) -> Option<(usize, ChildCount)> {
+        let parent = index.parent(node)?;
+        Some(self.position(index, parent, node, stamp))
+    }
+}
+
+/// Members starting at or before `to`, as members_through counts them,
+/// walking the sibling links from `from` with `position` of them. Ids
+/// follow document order, which gives the direction. None when the two
+/// aren't siblings.
+fn walk_siblings(index: &StructuralIndex, from: NodeId, position: usize, to: NodeId) -> Option<usize> {
+    let (mut current, mut position) = (from, position);
+    let forward = to > from;
+    while current != to {
+        if forward {
+            current = index.next_sibling(current).filter(|&next| next <= to)?;
+            position += usize::from(index.starts_member(current));
+        } else {
+            position = position.checked_sub(usize::from(index.starts_member(current)))?;
+            current = index.prev_sibling(current).filter(|&prev| prev >= to)?;
+        }
+    }
+    Some(position)
+}
+
+/// Whether `action` needs only the structure near the cursor, rather than
+/// errors, changes or counts from the whole document
+pub fn is_local(action: StructuralNavAction) -> bool {
+    !matches!(
+        action,
+        StructuralNavAction::NextError
+            | StructuralNavAction::PrevError
+            | StructuralNavAction::NextDiagnostic
+            | StructuralNavAction::PrevDiagnostic
+            | StructuralNavAction::NextChange
+            | StructuralNavAction::PrevChange
+            | StructuralNavAction::NextDuplicate
+            | StructuralNavAction::PrevDuplicate
+            | StructuralNavAction::ArrayIndex(_)
+    )
+}
+
+/// Whether `action` moves from the current node rather than the cursor
+pub fn is_relative(action: StructuralNavAction) -> bool {
+    matches!(
+        action,
+        StructuralNavAction::NextSibling
+            | StructuralNavAction::PrevSibling
+            | StructuralNavAction::Parent
+            | StructuralNavAction::FirstChild
+            | StructuralNavAction::LastChild
+            | StructuralNavAction::NthChild(_)
+            | StructuralNavAction::ArrayIndex(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    /// Follow `actions` from `offset` the way the editor does, returning
+    /// the text at each place the cursor lands
+    fn walk(text: &str, offset: usize, actions: &[StructuralNavAction]) -> Vec<String> {
+        let counted: Vec<_> = actions.iter().map(|&action| (action, 1)).collect();
+        walk_counted(text, offset, &counted)
+    }
+
+    fn walk_counted(text: &str, offset: usize, actions: &[(StructuralNavAction, usize)]) -> Vec<String> {
+        let tokens = Tokenizer::new(text.to_string()).tokenize_all();
+        let index = StructuralIndex::from_tokens(&tokens);
+        let (mut current, mut offset) = (None, offset);
+        actions
+            .iter()
+            .map(|&(action, count)| {
+                if is_relative(action) {
+                    current = current.or_else(|| index.node_id_at(offset));
+                }
+                let target = target(&index, action, current, offset, count);
+                if let Some((id, node)) = target.and_then(|id| Some((id, index.get(id)?))) {
+                    current = Some(id);
+                    offset = node.start;
+                }
+                text[offset..].chars().take(6).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_motions() {
+        use StructuralNavAction::*;
+        let text = r#"{"a": [10, 20, {"b": true}], "c": null}"#;
+        assert_eq!(
+            walk(
+                text,
+                0,
+                &[FirstChild, NextSibling, FirstChild, NextSibling, NextSibling, FirstChild, Parent, Parent],
+            ),
+            [r#""a": ["#, "[10, 2", "10, 20", "20, {\"", r#"{"b": "#, r#""b": t"#, r#"{"b": "#, "[10, 2"],
+        );
+        // Past the last sibling, and back
+        let twenty = text.find("20").unwrap();
+        assert_eq!(
+            walk(text, twenty, &[NextSibling, NextSibling, PrevSibling]),
+            [r#"{"b": "#, r#"{"b": "#, "20, {\""],
+        );
+        // Keys and values search from the cursor
+        assert_eq!(
+            walk(text, 0, &[NextKey, NextKey, NextKey, PrevKey]),
+            [r#""a": ["#, r#""b": t"#, r#""c": n"#, r#""b": t"#],
+        );
+        assert_eq!(walk(text, 0, &[NextValue, NextValue]), ["[10, 2", "10, 20"]);
+        assert_eq!(walk(text, 5, &[PrevValue]), [r#"{"a": "#]);
+        // Records are top-level values
+        let lines = "{\"x\": 1}\n{\"x\": 2}\n";
+        assert_eq!(walk(lines, 2, &[NextRecord, NextRecord, PrevRecord]), ["{\"x\": ", "{\"x\": ", "{\"x\": "]);
+        // Errors aren't nodes; the cursor stays put
+        assert_eq!(walk(text, 3, &[NextError]), [r#"": [10"#]);
+    }
+
+    #[test]
+    fn test_counted_and_positional_motions() {
+        use StructuralNavAction::*;
+        let text = "[1, 2, 3, 4, 5, 6]";
+        let one = text.find('1').unwrap();
+        assert_eq!(walk_counted(text, one, &[(NextSibling, 3), (PrevSibling, 2)]), ["4, 5, ", "2, 3, "]);
+        // A count past the end goes as far as it can
+        assert_eq!(walk_counted(text, one, &[(NextSibling, 100)]), ["6]"]);
+        // From a scalar, the container it's in; from a container, its own
+        assert_eq!(walk(text, one, &[LastChild, NthChild(2), NthChild(7), NthChild(0)]), ["6]", "2, 3, ", "2, 3, ", "2, 3, "]);
+        assert_eq!(walk(text, 0, &[NthChild(5)]), ["5, 6]"]);
+        assert_eq!(walk_counted(text, 0, &[(NthChild(5), 3)]), ["5, 6]"]);
+
+        // Object members are their keys
+        let text = r#"{"a": 1, "b": [true], "c": null}"#;
+        assert_eq!(walk(text, 0, &[LastChild, NthChild(2)]), [r#""c": n"#, r#""b": ["#]);
+        let nested = r#"{"a": {"b": {"c": 1}}}"#;
+        let c = nested.find("1").unwrap();
+        assert_eq!(walk_counted(nested, c, &[(Parent, 2)]), [r#"{"b": "#]);
+
+        // Elements of the array around the node, from deep inside one
+        let text = r#"[{"a": 1}, {"b": [2, 3]}, {"c": 4}]"#;
+        let four = text.find('4').unwrap();
+        assert_eq!(walk(text, four, &[ArrayIndex(1), ArrayIndex(3), ArrayIndex(4)]), [r#"{"a": "#, r#"{"c": "#, r#"{"c": "#]);
+        // The nearest array: inside [2, 3], that one
+        let two = text.find('2').unwrap();
+        assert_eq!(walk(text, two, &[ArrayIndex(2), ArrayIndex(3)]), ["3]}, {", "3]}, {"]);
+        assert_eq!(walk(r#"{"a": 1}"#, 6, &[ArrayIndex(1)]), ["1}"]);
+    }
+
+    #[test]
+    fn test_item_positions() {
+        let text = format!("[{}]", (0..1000).map(|n| format!("[{}]", n)).collect::<Vec<_>>().join(", "));
+        let tokens = Tokenizer::new(text.clone()).tokenize_all();
+        let index = StructuralIndex::from_tokens(&tokens);
+        let mut positions = ItemPositions::new();
+        let element = |n: usize| index.nth_child(0, n).unwrap();
+        let complete = |count| ChildCount { count, complete: true };
+
+        // Inside an element, the element's place
+        let inner = index.first_child(element(500)).unwrap();
+        assert_eq!(array_element(&index, inner), Some((element(500), inner)));
+        assert_eq!(array_element(&index, element(500)), Some((0, element(500))));
+        assert_eq!(array_element(&index, 0), None);
+
+        // Stepping both ways from the last one counted
+        for n in (0..1000).step_by(7).chain((0..1000).rev().step_by(3)) {
+            assert_eq!(positions.position(&index, 0, element(n), 0), (n + 1, complete(1000)));
+        }
+        assert_eq!(positions.position(&index, element(500), inner, 0), (1, complete(1)));
+        assert_eq!(positions.position(&index, 0, element(999), 1), (1000, complete(1000)));
+    }
+
+    #[test]
+    fn test_member_position() {
+        let text = r#"{"a": 1, "b": [10, 20, 30]}"#;
+        let tokens = Tokenizer::new(text.to_string()).tokenize_all();
+        let index = StructuralIndex::from_tokens(&tokens);
+        let mut positions = ItemPositions::new();
+        let mut position = |offset| {
+            let (position, count) = positions.member(&index, index.node_id_at(offset)?, 0)?;
+            Some(format!("{}/{}", position, count))
+        };
+        assert_eq!(position(text.find("30").unwrap()), Some("3/3".to_string()));
+        assert_eq!(position(text.find("\"b\"").unwrap()), Some("2/2".to_string()));
+        assert_eq!(position(text.find('[').unwrap()), Some("2/2".to_string()));
+        assert_eq!(position(text.find('1').unwrap()), Some("1/2".to_string()));
+        assert_eq!(position(0), None);
+
+        // Keys and values of a wide object, in a new index
+        let text = format!("{{{}}}", (0..5000).map(|n| format!("\"k{}\": {}", n, n)).collect::<Vec<_>>().join(", "));
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.clone()).tokenize_all());
+        let complete = ChildCount { count: 5000, complete: true };
+        let mut key = index.first_child(0);
+        for n in 0..5000 {
+            let id = key.unwrap();
+            assert_eq!(positions.member(&index, id, 1), Some((n + 1, complete)));
+            assert_eq!(positions.member(&index, index.next_sibling(id).unwrap(), 1), Some((n + 1, complete)));
+            key = index.next_key(index.get(id).unwrap().start);
+        }
+        assert_eq!(key, None);
+        assert_eq!(positions.member(&index, index.first_child(0).unwrap(), 1), Some((1, complete)));
+
+        // A stray node kept by error recovery goes with the member before
+        // it, and the ones after keep their places
+        let text = r#"{"a": 1, @ "b": 2, "c": 3}"#;
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        assert_eq!((index.child_iter(0).count(), index.errors().is_empty()), (7, false));
+        let complete = ChildCount { count: 3, complete: true };
+        assert_eq!(index.child_count(0), Some(complete));
+        assert_eq!(index.member_iter(0).count(), 3);
+        for (needle, n) in [("\"a\"", 1), ("1", 1), ("\"b\"", 2), ("2", 2), ("\"c\"", 3), ("3", 3)] {
+            let id = index.node_id_at(text.find(needle).unwrap()).unwrap();
+            assert_eq!(positions.member(&index, id, 2), Some((n, complete)), "{}", needle);
+            assert_eq!(ItemPositions::new().member(&index, id, 0), Some((n, complete)), "{}", needle);
+        }
+        assert_eq!(nth_member(&index, 0, 2).and_then(|id| index.get(id)).map(|node| node.start), text.find("\"c\""));
+        assert_eq!(last_member(&index, 0), nth_member(&index, 0, 2));
+    }
+
+    #[test]
+    fn test_navigate_before_reindex() {
+        use crate::buffer::TextChange;
+        use StructuralNavAction::*;
+        let mut text = String::from(r#"{"a": "x", "b": [1, 2], "c": 3}"#);
+        let mut index = StructuralIndex::from_tokens(&Tokenizer::new(text.clone()).tokenize_all());
+        let mut edit = |index: &mut StructuralIndex, offset: usize, removed: usize, inserted: &str| {
+            text.replace_range(offset..offset + removed, inserted);
+            index.apply_change(&TextChange { offset, removed, inserted: inserted.len() });
+            index.recheck_strings(|span| text[span].to_string());
+            text.clone()
+        };
+        let land = |index: &StructuralIndex, text: &str, from: &str, action| {
+            let current = index.node_id_at(text.find(from).unwrap());
+            let target = target(index, action, current, 0, 1)?;
+            Some((index.lands_in_dirty(target), text[index.get(target)?.start..].chars().take(4).collect::<String>()))
+        };
+
+        // Typed into a string: motions past it land where the nodes are now
+        let now = edit(&mut index, 8, 0, "yz");
+        assert_eq!(land(&index, &now, "\"a\"", NextSibling), Some((false, "\"xyz".to_string())));
+        assert_eq!(land(&index, &now, "\"xyz", NextSibling), Some((false, "\"b\":".to_string())));
+
+        // A value replaced by a structure: landing on it is refused, but
+        // the siblings either side are still where the index says
+        let two = now.find('2').unwrap();
+        let now = edit(&mut index, two, 1, "[2, 3]");
+        assert_eq!(land(&index, &now, "1,", NextSibling), Some((true, "[2, ".to_string())));
+        assert_eq!(land(&index, &now, "[1,", NextSibling), Some((false, "\"c\":".to_string())));
+        assert_eq!(land(&index, &now, "1,", Parent), Some((false, "[1, ".to_string())));
+    }
+}
@@ -0,0 +1,182 @@
+//! :outline - the major sections of a document to jump between: the keys
+//! of the top-level object and of the objects under them, or for a
+//! top-level array its elements in groups. Built from what's indexed so
+//! far; sections whose end isn't indexed yet say so.
+
+use crate::buffer::Buffer;
+use crate::parser::node::NodeId;
+use crate::parser::{NodeKind, StructuralIndex};
+
+use super::path::key_segment;
+
+/// Elements to an outline entry in a long array
+pub const GROUP: usize = 1000;
+
+/// Added to the label of a section whose end isn't indexed yet
+pub const NOT_INDEXED: &str = " (not yet indexed)";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineEntry {
+    /// Path from the top, e.g. `.users.address` or `[1000…1999]`
+    pub label: String,
+    /// The key, or the first element of a group
+    pub node: NodeId,
+    /// Whether the whole section is indexed
+    pub indexed: bool,
+}
+
+impl OutlineEntry {
+    /// The label as the picker shows it
+    pub fn display(&self) -> String {
+        if self.indexed {
+            self.label.clone()
+        } else {
+            format!("{}{}", self.label, NOT_INDEXED)
+        }
+    }
+}
+
+/// The outline of the first top-level value, at most `limit` entries, in
+/// document order. Arrays under the top-level keys appear as groups once
+/// they're longer than one.
+pub fn outline(index: &StructuralIndex, buffer: &Buffer, limit: usize) -> Vec<OutlineEntry> {
+    let mut entries = Vec::new();
+    let Some(root) = index.nth_root(0) else {
+        return entries;
+    };
+    match index.kind(root) {
+        Some(NodeKind::Object) => {
+            for (label, key, value) in members(index, buffer, root, "") {
+                if entries.len() >= limit {
+                    break;
+                }
+                let indexed = value.is_some_and(|value| is_indexed(index, value));
+                entries.push(OutlineEntry { label: label.clone(), node: key, indexed });
+                match value.and_then(|value| Some((value, index.kind(value)?))) {
+                    Some((value, NodeKind::Object)) => {
+                        let room = limit.saturating_sub(entries.len());
+                        for (label, key, value) in members(index, buffer, value, &label).take(room) {
+                            let indexed = value.is_some_and(|value| is_indexed(index, value));
+                            entries.push(OutlineEntry { label, node: key, indexed });
+                        }
+                    }
+                    Some((value, NodeKind::Array)) => {
+                        let groups = groups(index, value, &label);
+                        if groups.len() > 1 {
+                            entries.extend(groups);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Some(NodeKind::Array) => entries = groups(index, root, ""),
+        _ => {}
+    }
+    entries.truncate(limit);
+    entries
+}
+
+/// Whether a value and everything in it is indexed
+fn is_indexed(index: &StructuralIndex, node: NodeId) -> bool {
+    match index.kind(node) {
+        Some(NodeKind::Object | NodeKind::Array) => index.is_closed(node),
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// The members of `object`: the path to each, its key and its value if
+/// that's indexed yet
+fn members<'a>(
+    index: &'a StructuralIndex,
+    buffer: &'a Buffer,
+    object: NodeId,
+    prefix: &'a str,
+) -> impl Iterator<Item = (String, NodeId, Option<NodeId>)> + 'a {
+    index.member_iter(object).filter_map(move |(key, value)| {
+        let node = index.get(key)?;
+        let label = format!("{}{}", prefix, key_segment(&buffer.slice(node.start..node.end)));
+        Some((label, key, value))
+    })
+}
+
+/// The elements of `array` in runs of `GROUP`, each entry leading to the
+/// first of its run. The last run of an array not indexed to its end is
+/// labelled as if it were full.
+fn groups(index: &StructuralIndex, array: NodeId, prefix: &str) -> Vec<OutlineEntry> {
+    let closed = index.is_closed(array);
+    let mut entries = Vec::new();
+    let mut count = 0;
+    for (n, element) in index.child_iter(array).enumerate() {
+        if n % GROUP == 0 {
+            entries.push(OutlineEntry { label: String::new(), node: element, indexed: true });
+        }
+        count = n + 1;
+    }
+    for (group, entry) in entries.iter_mut().enumerate() {
+        let first = group * GROUP;
+        let full = first + GROUP <= count;
+        let last = if full || !closed { first + GROUP - 1 } else { count - 1 };
+        entry.label = format!("{}[{}…{}]", prefix, first, last);
+        entry.indexed = full || closed;
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{OpenContainers, Tokenizer};
+
+    fn labels(text: &str) -> Vec<String> {
+        let buffer = Buffer::from_text(text);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        outline(&index, &buffer, 100).iter().map(OutlineEntry::display).collect()
+    }
+
+    #[test]
+    fn test_object_outline() {
+        let long: Vec<String> = (0..2500).map(|n| n.to_string()).collect();
+        let text = format!(
+            r#"{{"meta": {{"version": 2, "first name": "x"}}, "items": [{}], "short": [1, 2], "n": 1}}"#,
+            long.join(", ")
+        );
+        assert_eq!(
+            labels(&text),
+            [
+                ".meta",
+                ".meta.version",
+                r#".meta["first name"]"#,
+                ".items",
+                ".items[0…999]",
+                ".items[1000…1999]",
+                ".items[2000…2499]",
+                ".short",
+                ".n",
+            ]
+        );
+        assert!(labels("[]").is_empty());
+        assert!(labels("42").is_empty());
+    }
+
+    #[test]
+    fn test_partly_indexed() {
+        // An array of 2,500 objects, indexed a little past its 1,500th
+        let elements: Vec<String> = (0..2500).map(|n| format!("{{\"n\": {}}}", n)).collect();
+        let text = format!("[{}]", elements.join(", "));
+        let tokens = Tokenizer::new(text.clone()).tokenize_all();
+        let cut = text.find("{\"n\": 1500}").unwrap() + 5;
+        let tokens: Vec<_> = tokens.into_iter().filter(|token| token.end <= cut).collect();
+        let mut open = OpenContainers::default();
+        let mut index = StructuralIndex::new();
+        assert!(index.apply_chunk(StructuralIndex::build_chunk(&tokens, &mut open, 0)));
+
+        let buffer = Buffer::from_text(&text);
+        let entries = outline(&index, &buffer, 100);
+        let shown: Vec<String> = entries.iter().map(OutlineEntry::display).collect();
+        assert_eq!(shown, ["[0…999]", "[1000…1999] (not yet indexed)"]);
+        assert_eq!(index.get(entries[1].node).map(|node| node.start), text.find("{\"n\": 1000}"));
+        assert_eq!(outline(&index, &buffer, 1).len(), 1);
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::buffer::Buffer;
+use crate::parser::decode;
+use crate::parser::node::NodeId;
+use crate::parser::structural_index::Children;
+use crate::parser::{NodeKind, StructuralIndex};
+
+/// How many keys deep :keys! lists paths
+pub const KEY_PATH_DEPTH: usize = 4;
+
+/// JSONPath-style location of the value at `offset`, e.g. `$.users[3].name`.
+/// A key counts as its member. Array positions are found by walking back
+/// through siblings, so callers showing this every frame should cache it.
+pub fn json_path(index: &StructuralIndex, buffer: &Buffer, offset: usize) -> String {
+    let mut segments = Vec::new();
+    let mut node = index.node_id_at(offset);
+    while let Some(id) = node {
+        let Some(parent) = index.parent(id) else { break };
+        match index.kind(parent) {
+            Some(NodeKind::Object) => {
+                let key = if index.is_key_node(id) {
+                    Some(id)
+                } else {
+                    index.prev_sibling(id).filter(|&key| index.is_key_node(key))
+                };
+                if let Some(key) = key.and_then(|key| index.get(key)) {
+                    segments.push(key_segment(&buffer.slice(key.start..key.end)));
+                }
+            }
+            Some(NodeKind::Array) => {
+                let position = std::iter::successors(index.prev_sibling(id), |&s| index.prev_sibling(s)).count();
+                segments.push(format!("[{}]", position));
+            }
+            _ => {}
+        }
+        node = Some(parent);
+    }
+    segments.push("$".to_string());
+    segments.reverse();
+    segments.concat()
+}
+
+/// `.name` for keys that read as identifiers, `["quoted key"]` otherwise
+pub(crate) fn key_segment(raw: &str) -> String {
+    let name = raw.strip_prefix('"').and_then(|k| k.strip_suffix('"')).unwrap_or(raw);
+    let identifier = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if identifier {
+        format!(".{}", name)
+    } else {
+        format!("[{}]", raw)
+    }
+}
+
+/// One step of a parsed path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Write `segments` as a path `parse_path` reads back:
+/// `$.users[0]["first name"]`
+pub fn format_path(segments: &[Segment]) -> String {
+    let mut path = "$".to_string();
+    for segment in segments {
+        match segment {
+            Segment::Key(name) => path.push_str(&key_segment(&decode::encode_string(name))),
+            Segment::Index(n) => path.push_str(&format!("[{}]", n)),
+        }
+    }
+    path
+}
+
+/// Parse a path as `json_path` writes it: `$.data.items[42]`,
+/// `.users[0]["first name"]`, or the same without the leading `$` or dot
+pub fn parse_path(path: &str) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let path = path.trim();
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    if !rest.is_empty() && !rest.starts_with(['.', '[']) {
+        let end = rest.find(['.', '[']).unwrap_or(rest.len());
+        segments.push(Segment::Key(rest[..end].to_string()));
+        rest = &rest[end..];
+    }
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                bail!("Empty key in {}", path);
+            }
+            segments.push(Segment::Key(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix("[\"") {
+            // A quoted key runs to the first unescaped quote
+            let mut escaped = false;
+            let end = after.char_indices()
+                .find(|&(_, c)| {
+                    let closes = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    closes
+                })
+                .map(|(i, _)| i)
+                .ok_or_else(|| anyhow!("Unterminated key in {}", path))?;
+            let key = decode::decode_string(&format!("\"{}\"", &after[..end])).map_err(|e| anyhow!("Bad key in {}: {}", path, e))?;
+            segments.push(Segment::Key(key));
+            rest = after[end + 1..].strip_prefix(']').ok_or_else(|| anyhow!("Expected ] in {}", path))?;
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(|| anyhow!("Expected ] in {}", path))?;
+            let position = after[..end].trim().parse().map_err(|_| anyhow!("Bad index [{}] in {}", &after[..end], path))?;
+            segments.push(Segment::Index(position));
+            rest = &after[end + 1..];
+        } else {
+            bail!("Can't read {} in {}", rest, path);
+        }
+    }
+    Ok(segments)
+}
+
+/// Where a path leads in a document that may be only partly indexed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolved {
+    Found(NodeId),
+    /// The path needs a part of the document not indexed yet
+    Pending,
+    /// The path isn't in the document; why not
+    Missing(String),
+}
+
+/// Follow `segments` from the first top-level value, keys matched after
+/// decoding escapes
+pub fn resolve(index: &StructuralIndex, buffer: &Buffer, segments: &[Segment]) -> Resolved {
+    let Some(mut node) = index.nth_root(0) else {
+        return Resolved::Pending;
+    };
+    let mut walked = "$".to_string();
+    for segment in segments {
+        let kind = index.kind(node);
+        let found = match (segment, kind) {
+            (Segment::Key(_), Some(NodeKind::Object)) | (Segment::Index(_), Some(NodeKind::Array)) => {
+                member(index, buffer, node, segment)
+            }
+            (_, Some(kind)) => return Resolved::Missing(format!("{} is {} {}", walked, article(kind), kind_word(kind))),
+            (_, None) => return Resolved::Pending,
+        };
+        let Some(found) = found else {
+            if !index.is_closed(node) {
+                return Resolved::Pending;
+            }
+            return Resolved::Missing(match segment {
+                Segment::Key(name) => format!("No key {} in {}", decode::encode_string(name), walked),
+                Segment::Index(n) => format!("No element [{}] in {} ({} elements)", n, walked, index.child_iter(node).count()),
+            });
+        };
+        match segment {
+            Segment::Key(name) => walked.push_str(&key_segment(&decode::encode_string(name))),
+            Segment::Index(n) => walked.push_str(&format!("[{}]", n)),
+        }
+        node = found;
+    }
+    Resolved::Found(node)
+}
+
+/// The member of container `node` that `segment` names, if it has one
+pub fn member(index: &StructuralIndex, buffer: &Buffer, node: NodeId, segment: &Segment) -> Option<NodeId> {
+    match (segment, index.kind(node)?) {
+        (Segment::Key(name), NodeKind::Object) => index.member_iter(node)
+            .find(|&(key, _)| {
+                index.get(key).is_some_and(|key| decode::decode_string(&buffer.slice(key.start..key.end)).ok().as_deref() == Some(name))
+            })
+            .and_then(|(_, value)| value),
+        (Segment::Index(n), NodeKind::Array) => index.nth_child(node, *n),
+        _ => None,
+    }
+}
+
+/// The keys of `object` as written, decoded where they can be, with
+/// where each starts
+pub fn object_keys<'a>(index: &'a StructuralIndex, buffer: &'a Buffer, object: NodeId) -> impl Iterator<Item = (String, usize)> + 'a {
+    index.member_iter(object).map(|(key, _)| key).filter_map(|key| {
+        let key = index.get(key)?;
+        let raw = buffer.slice(key.start..key.end);
+        Some((decode::decode_string(&raw).ok().unwrap_or(raw), key.start))
+    })
+}
+
+/// Every distinct path to a key, up to `depth` keys deep, in document
+/// order with where the first of each starts: `.users[].name` stands for
+/// the name of any user. Walked lazily, so taking the first few doesn't
+/// visit the whole document.
+pub fn key_paths<'a>(index: &'a StructuralIndex, buffer: &'a Buffer, depth: usize) -> KeyPaths<'a> {
+    KeyPaths { index, buffer, depth, roots: 0, stack: Vec::new(), seen: HashSet::new() }
+}
+
+pub struct KeyPaths<'a> {
+    index: &'a StructuralIndex,
+    buffer: &'a Buffer,
+    depth: usize,
+    /// Next top-level value to walk
+    roots: usize,
+    /// The containers being walked, with their path and how many keys
+    /// deep they are
+    stack: Vec<(Children<'a>, String, usize)>,
+    seen: HashSet<String>,
+}
+
+impl KeyPaths<'_> {
+    /// Walk into `node` next if it's a container and not too deep
+    fn enter(&mut self, node: NodeId, path: String, depth: usize) {
+        if depth < self.depth && matches!(self.index.kind(node), Some(NodeKind::Object | NodeKind::Array)) {
+            self.stack.push((self.index.child_iter(node), path, depth));
+        }
+    }
+}
+
+impl Iterator for KeyPaths<'_> {
+    type Item = (String, usize);
+
+    fn next(&mut self) -> Option<(String, usize)> {
+        loop {
+            let Some((children, path, depth)) = self.stack.last_mut() else {
+                let root = self.index.nth_root(self.roots)?;
+                self.roots += 1;
+                self.enter(root, String::new(), 0);
+                continue;
+            };
+            let Some(child) = children.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let (path, depth) = (path.clone(), *depth);
+            if !self.index.is_key_node(child) {
+                // An array element; the values of keys are entered below
+                self.enter(child, format!("{}[]", path), depth);
+                continue;
+            }
+            let Some(key) = self.index.get(child) else { continue };
+            let path = format!("{}{}", path, key_segment(&self.buffer.slice(key.start..key.end)));
+            // Step past the value, to walk it before the next key
+            let value = self.stack.last_mut().and_then(|(children, _, _)| children.next());
+            if let Some(value) = value {
+                self.enter(value, path.clone(), depth + 1);
+            }
+            if self.seen.insert(path.clone()) {
+                return Some((path, key.start));
+            }
+        }
+    }
+}
+
+fn kind_word(kind: NodeKind) -> &'static str {
+    match kind {
+        NodeKind::Object => "object",
+        NodeKind::Array => "array",
+        NodeKind::String | NodeKind::Key => "string",
+        NodeKind::Number => "number",
+        NodeKind::Boolean => "boolean",
+        NodeKind::Null => "null",
+        NodeKind::Unknown | NodeKind::Error => "error",
+    }
+}
+
+fn article(kind: NodeKind) -> &'static str {
+    match kind {
+        NodeKind::Object | NodeKind::Array | NodeKind::Unknown | NodeKind::Error => "an",
+        _ => "a",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    #[test]
+    fn test_json_path() {
+        let text = r#"{"users": [{"name": "a"}, {"first name": "b", "id": 7}], "n": 1}"#;
+        let buffer = Buffer::from_text(text);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let at = |needle: &str| json_path(&index, &buffer, text.find(needle).unwrap());
+        assert_eq!(at("{\"users\""), "$");
+        assert_eq!(at("\"users\""), "$.users");
+        assert_eq!(at("[{"), "$.users");
+        assert_eq!(at("\"a\""), "$.users[0].name");
+        assert_eq!(at("\"b\""), "$.users[1][\"first name\"]");
+        assert_eq!(at("7"), "$.users[1].id");
+        assert_eq!(at("1}"), "$.n");
+    }
+
+    #[test]
+    fn test_parse_and_resolve() {
+        let key = |k: &str| Segment::Key(k.to_string());
+        assert_eq!(parse_path("$").unwrap(), []);
+        assert_eq!(parse_path(".data.items[42]").unwrap(), [key("data"), key("items"), Segment::Index(42)]);
+        assert_eq!(parse_path("data[0][\"a \\\"b\\\"\"].c").unwrap(), [key("data"), Segment::Index(0), key("a \"b\""), key("c")]);
+        assert!(parse_path("$.a[x]").is_err());
+        assert!(parse_path("$..a").is_err());
+
+        let text = r#"{"data": {"items": [10, 20], "a b": true}}"#;
+        let buffer = Buffer::from_text(text);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let at = |path: &str| match resolve(&index, &buffer, &parse_path(path).unwrap()) {
+            Resolved::Found(id) => Ok(buffer.slice(index.get(id).unwrap().start..index.get(id).unwrap().end)),
+            other => Err(other),
+        };
+        assert_eq!(at("$.data.items[1]"), Ok("20".to_string()));
+        assert_eq!(at("data[\"a b\"]"), Ok("true".to_string()));
+        assert_eq!(at("$.data.items[2]"), Err(Resolved::Missing("No element [2] in $.data.items (2 elements)".to_string())));
+        assert_eq!(at("$.data.nope"), Err(Resolved::Missing("No key \"nope\" in $.data".to_string())));
+        assert_eq!(at("$.data.items.x"), Err(Resolved::Missing("$.data.items is an array".to_string())));
+
+        // The rest of an unclosed container may still be coming
+        let partial = r#"{"data": [1, 2"#;
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(partial.to_string()).tokenize_all());
+        assert_eq!(resolve(&index, &Buffer::from_text(partial), &parse_path("$.data[5]").unwrap()), Resolved::Pending);
+    }
+
+    #[test]
+    fn test_key_paths() {
+        let text = r#"{"users": [{"name": "a", "tags": [{"x": 1}]}, {"name": "b", "first name": {"a": {"b": 1}}}], "n\u0041": 1}"#;
+        let buffer = Buffer::from_text(text);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let paths: Vec<_> = key_paths(&index, &buffer, 3).collect();
+        let names: Vec<&str> = paths.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(names, [
+            ".users",
+            ".users[].name",
+            ".users[].tags",
+            ".users[].tags[].x",
+            ".users[][\"first name\"]",
+            ".users[][\"first name\"].a",
+            "[\"n\\u0041\"]",
+        ]);
+        // Each where it first appears
+        assert_eq!(paths[1].1, text.find("\"name\"").unwrap());
+        assert_eq!(key_paths(&index, &buffer, 1).count(), 2);
+
+        let keys: Vec<_> = object_keys(&index, &buffer, index.nth_root(0).unwrap()).collect();
+        assert_eq!(keys, [("users".to_string(), 1), ("nA".to_string(), text.find("\"n\\").unwrap())]);
+    }
+}
@@ -0,0 +1,412 @@
+//! Search for / and n/N, over the whole buffer or kept within a span (:/
+//! searches the node under the cursor, :'<,'>/ the selection), and for
+//! :key and :value, which look for nodes in the structural index rather
+//! than text
+
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use crate::buffer::{Buffer, TextChange};
+use crate::parser::decode::{self, DecodedNumber};
+use crate::parser::node::{NodeId, NodeInfo};
+use crate::parser::{NodeKind, StructuralIndex};
+
+/// The span a scoped search stays in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scope {
+    pub span: Range<usize>,
+    /// What the span is, for messages: "node" or "selection"
+    pub name: &'static str,
+}
+
+/// What a search looks for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// The pattern as text, anywhere
+    Text,
+    /// :key name - object keys named the pattern, or starting with it when
+    /// it ends in *
+    Key,
+    /// :value literal - scalars equal to the literal
+    Value(Literal),
+}
+
+/// The value :value compares with
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    String(String),
+    Number(DecodedNumber),
+    Bool(bool),
+    Null,
+}
+
+impl Literal {
+    /// Numbers, true, false and null stand for themselves and a quoted
+    /// literal for the string it escapes; anything else is a string as typed
+    pub fn parse(text: &str) -> Self {
+        match text {
+            "true" => return Literal::Bool(true),
+            "false" => return Literal::Bool(false),
+            "null" => return Literal::Null,
+            _ => {}
+        }
+        match decode::decode_number(text) {
+            Some(number) => Literal::Number(number),
+            None => Literal::String(decode::decode_string(text).unwrap_or_else(|_| text.to_string())),
+        }
+    }
+}
+
+/// The last search, which n and N repeat
+#[derive(Debug, Clone, PartialEq)]
+pub struct Search {
+    pub pattern: String,
+    pub scope: Option<Scope>,
+    pub target: Target,
+    /// Nodes found so far by a :key or :value search, in document order
+    hits: Vec<NodeId>,
+    /// Nodes before this one have been checked
+    scanned: NodeId,
+    /// The index generation and buffer revision `hits` were found in
+    stamp: Option<(u64, u64)>,
+}
+
+/// Where a search landed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub offset: usize,
+    /// Went past the end (or start) and came round
+    pub wrapped: bool,
+    /// Which match of how many, counted for scoped and node searches only
+    pub position: Option<(usize, usize)>,
+    /// The count covers only the part of the file indexed so far
+    pub partial: bool,
+}
+
+/// How a :key or :value search went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeSearch {
+    Found(Match),
+    NotFound,
+    /// Nothing in the indexed part of the file; the rest has to be indexed
+    /// to go on
+    NeedsIndex,
+}
+
+impl Search {
+    pub fn new(pattern: String, scope: Option<Scope>) -> Self {
+        Self { pattern, scope, target: Target::Text, hits: Vec::new(), scanned: 0, stamp: None }
+    }
+
+    /// :key name
+    pub fn key(name: String) -> Self {
+        Self { target: Target::Key, ..Self::new(name, None) }
+    }
+
+    /// :value literal
+    pub fn value(literal: String) -> Self {
+        Self { target: Target::Value(Literal::parse(&literal)), ..Self::new(literal, None) }
+    }
+
+    /// Whether this searches the index rather than the text
+    pub fn is_structural(&self) -> bool {
+        self.target != Target::Text
+    }
+
+    /// The next match after `from` (before it, going backward), wrapping
+    /// round. A scoped search never leaves its span.
+    pub fn find(&self, buffer: &Buffer, from: usize, forward: bool) -> Option<Match> {
+        let Some(scope) = &self.scope else {
+            return self.find_everywhere(buffer, from, forward);
+        };
+        let text = buffer.slice(scope.span.clone());
+        let starts: Vec<usize> = text.match_indices(self.pattern.as_str()).map(|(at, _)| scope.span.start + at).collect();
+        if starts.is_empty() {
+            return None;
+        }
+        let (index, wrapped) = if forward {
+            match starts.iter().position(|&at| at > from) {
+                Some(index) => (index, false),
+                None => (0, true),
+            }
+        } else {
+            match starts.iter().rposition(|&at| at < from) {
+                Some(index) => (index, false),
+                None => (starts.len() - 1, true),
+            }
+        };
+        Some(Match { offset: starts[index], wrapped, position: Some((index + 1, starts.len())), partial: false })
+    }
+
+    /// Check the nodes indexed since the last call for :key and :value.
+    /// `stamp` is the index generation and buffer revision; when either
+    /// changes the nodes are all checked again.
+    pub fn scan(&mut self, index: &StructuralIndex, buffer: &Buffer, stamp: (u64, u64)) {
+        if self.stamp != Some(stamp) || self.scanned > index.len() {
+            self.hits.clear();
+            self.scanned = 0;
+            self.stamp = Some(stamp);
+        }
+        let found: Vec<NodeId> = (self.scanned..index.len()).filter(|&id| self.matches(index, buffer, id)).collect();
+        self.hits.extend(found);
+        self.scanned = index.len();
+    }
+
+    /// The next node found by `scan` after `from` (before it, going
+    /// backward). Wrapping round needs the whole file indexed, so until
+    /// it is (`complete`) running out asks for more.
+    pub fn find_node(&self, index: &StructuralIndex, from: usize, forward: bool, complete: bool) -> NodeSearch {
+        let start = |id: &NodeId| index.get(*id).map_or(0, |node| node.start);
+        let (at, wrapped) = if forward {
+            let next = self.hits.partition_point(|id| start(id) <= from);
+            if next < self.hits.len() {
+                (next, false)
+            } else {
+                (0, true)
+            }
+        } else {
+            match self.hits.partition_point(|id| start(id) < from).checked_sub(1) {
+                Some(previous) => (previous, false),
+                None => (self.hits.len().wrapping_sub(1), true),
+            }
+        };
+        if wrapped && !complete {
+            return NodeSearch::NeedsIndex;
+        }
+        match self.hits.get(at) {
+            Some(id) => NodeSearch::Found(Match {
+                offset: start(id),
+                wrapped,
+                position: Some((at + 1, self.hits.len())),
+                partial: !complete,
+            }),
+            None => NodeSearch::NotFound,
+        }
+    }
+
+    /// Whether node `id` is what a :key or :value search looks for
+    fn matches(&self, index: &StructuralIndex, buffer: &Buffer, id: NodeId) -> bool {
+        let Some(node) = index.get(id) else { return false };
+        let text = || buffer.slice(node.start..node.end);
+        match (&self.target, node.kind) {
+            (Target::Key, NodeKind::String) if is_key(index, buffer, id, &node) => {
+                let Some(name) = decode::decode_string(&text()).ok() else { return false };
+                match self.pattern.strip_suffix('*') {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => name == self.pattern,
+                }
+            }
+            (Target::Value(Literal::String(value)), NodeKind::String) => {
+                !is_key(index, buffer, id, &node) && decode::decode_string(&text()).ok().as_ref() == Some(value)
+            }
+            (Target::Value(Literal::Number(value)), NodeKind::Number) => {
+                decode::decode_number(&text()).is_some_and(|number| number.total_cmp(value) == Ordering::Equal)
+            }
+            (Target::Value(Literal::Bool(value)), NodeKind::Boolean) => text() == value.to_string(),
+            (Target::Value(Literal::Null), NodeKind::Null) => true,
+            _ => false,
+        }
+    }
+
+    fn find_everywhere(&self, buffer: &Buffer, from: usize, forward: bool) -> Option<Match> {
+        let (next, wrapped) = if forward {
+            (buffer.find(&self.pattern, from + 1), buffer.find(&self.pattern, 0))
+        } else {
+            (buffer.rfind(&self.pattern, from), buffer.rfind(&self.pattern, buffer.len_bytes()))
+        };
+        match next {
+            Some(offset) => Some(Match { offset, wrapped: false, position: None, partial: false }),
+            None => wrapped.map(|offset| Match { offset, wrapped: true, position: None, partial: false }),
+        }
+    }
+
+    /// Keep the scope on its text across an edit; an edit inside it widens
+    /// or narrows it
+    pub fn apply_change(&mut self, change: &TextChange) {
+        let Some(scope) = &mut self.scope else { return };
+        let old_end = change.offset + change.removed;
+        let map = |at: usize, inside: usize| {
+            if at <= change.offset {
+                at
+            } else if at >= old_end {
+                at - change.removed + change.inserted
+            } else {
+                inside
+            }
+        };
+        scope.span = map(scope.span.start, change.offset)..map(scope.span.end, change.offset + change.inserted);
+    }
+
+    /// What the status bar says about `found`
+    pub fn describe(&self, found: &Match) -> String {
+        let wrapped = if !found.wrapped {
+            ""
+        } else if self.scope.is_some() || self.is_structural() {
+            ", wrapped"
+        } else {
+            " (search wrapped)"
+        };
+        let so_far = if found.partial { " so far" } else { "" };
+        match (&self.target, &self.scope, found.position) {
+            (Target::Text, Some(scope), Some((n, total))) => format!("match {} of {} (in {}){}", n, total, scope.name, wrapped),
+            (Target::Text, _, _) => format!("/{}{}", self.pattern, wrapped),
+            (_, _, Some((n, total))) => format!("{}: match {} of {}{}{}", self.title(), n, total, so_far, wrapped),
+            _ => self.title(),
+        }
+    }
+
+    /// The command that started a :key or :value search
+    pub fn title(&self) -> String {
+        match self.target {
+            Target::Text => format!("/{}", self.pattern),
+            Target::Key => format!(":key {}", self.pattern),
+            Target::Value(_) => format!(":value {}", self.pattern),
+        }
+    }
+
+    /// The message when nothing matches
+    pub fn not_found(&self) -> String {
+        match (&self.target, &self.scope) {
+            (Target::Key, _) => format!("No key named {}", self.pattern),
+            (Target::Value(_), _) => format!("No value equal to {}", self.pattern),
+            (Target::Text, Some(scope)) => format!("Pattern not found in {}: {}", scope.name, self.pattern),
+            (Target::Text, None) => format!("Pattern not found: {}", self.pattern),
+        }
+    }
+}
+
+/// Whether string node `id` is an object key. Looks for the colon after it
+/// rather than counting siblings, which is slow in a wide object.
+fn is_key(index: &StructuralIndex, buffer: &Buffer, id: NodeId, node: &NodeInfo) -> bool {
+    if node.parent.and_then(|parent| index.kind(parent)) != Some(NodeKind::Object) {
+        return false;
+    }
+    let after = buffer.slice(node.end..node.end + 64);
+    match after.trim_start().chars().next() {
+        // A comment (JSONC) or a long run of whitespace: count after all
+        Some('/') | None => index.is_key_node(id),
+        Some(c) => c == ':',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = r#"[{"id": 1, "tag": "x"}, {"id": 2, "tag": "x", "x": "x"}, {"x": 3}]"#;
+
+    /// The second record
+    fn scoped() -> Search {
+        let start = TEXT.find("{\"id\": 2").unwrap();
+        let end = TEXT.find("}, {\"x\"").unwrap() + 1;
+        Search::new("\"x\"".to_string(), Some(Scope { span: start..end, name: "node" }))
+    }
+
+    #[test]
+    fn test_whole_buffer() {
+        let buffer = Buffer::from_text(TEXT);
+        let search = Search::new("\"x\"".to_string(), None);
+        let first = search.find(&buffer, 0, true).unwrap();
+        assert_eq!((first.offset, first.wrapped), (18, false));
+        let last = search.find(&buffer, 0, false).unwrap();
+        assert_eq!((last.offset, last.wrapped), (58, true));
+        assert_eq!(search.find(&buffer, 58, true).map(|m| m.offset), Some(18));
+        assert_eq!(search.describe(&last), "/\"x\" (search wrapped)");
+        assert!(Search::new("nope".to_string(), None).find(&buffer, 0, true).is_none());
+    }
+
+    #[test]
+    fn test_scoped() {
+        let buffer = Buffer::from_text(TEXT);
+        let search = scoped();
+        // From before the node, the first match in it; the ones around it
+        // are never reached
+        let first = search.find(&buffer, 0, true).unwrap();
+        assert_eq!((first.offset, first.position), (41, Some((1, 3))));
+        assert_eq!(search.describe(&first), "match 1 of 3 (in node)");
+        let second = search.find(&buffer, 41, true).unwrap();
+        assert_eq!((second.offset, second.position), (46, Some((2, 3))));
+        let third = search.find(&buffer, 46, true).unwrap();
+        let wrapped = search.find(&buffer, third.offset, true).unwrap();
+        assert_eq!((wrapped.offset, wrapped.wrapped), (41, true));
+        assert_eq!(search.describe(&wrapped), "match 1 of 3 (in node), wrapped");
+        let back = search.find(&buffer, 41, false).unwrap();
+        assert_eq!((back.offset, back.position), (third.offset, Some((3, 3))));
+
+        let missing = Search::new("id\": 1".to_string(), search.scope.clone());
+        assert!(missing.find(&buffer, 0, true).is_none());
+        assert_eq!(missing.not_found(), "Pattern not found in node: id\": 1");
+    }
+
+    const RECORD: &str = r#"{"id": 1, "ids": ["id", 1.0], "name": "i\u0064", "nested": {"id": 10e-1, "x": null, "ok": true}}"#;
+
+    /// Where each node found by `search` starts, and the message for the first
+    fn found(mut search: Search) -> (Vec<&'static str>, String) {
+        let buffer = Buffer::from_text(RECORD);
+        let index = StructuralIndex::from_tokens(&crate::parser::Tokenizer::new(RECORD.to_string()).tokenize_all());
+        search.scan(&index, &buffer, (1, 1));
+        search.scan(&index, &buffer, (1, 1));
+        let mut starts = Vec::new();
+        let mut from = 0;
+        while let NodeSearch::Found(found) = search.find_node(&index, from, true, true) {
+            if found.wrapped {
+                break;
+            }
+            starts.push(&RECORD[found.offset..found.offset + 6]);
+            from = found.offset;
+        }
+        let first = match search.find_node(&index, 0, true, true) {
+            NodeSearch::Found(found) => search.describe(&found),
+            _ => search.not_found(),
+        };
+        (starts, first)
+    }
+
+    #[test]
+    fn test_keys_and_values() {
+        // Keys only, by their unescaped name or a prefix
+        let (starts, message) = found(Search::key("id".to_string()));
+        assert_eq!(starts, ["\"id\": ", "\"id\": "]);
+        assert_eq!(message, ":key id: match 1 of 2");
+        assert_eq!(found(Search::key("id*".to_string())).0.len(), 3);
+        assert_eq!(found(Search::key("nope".to_string())).1, "No key named nope");
+
+        // Numbers compare as numbers, strings after unescaping and never as keys
+        assert_eq!(found(Search::value("1".to_string())).0, ["1, \"id", "1.0], ", "10e-1,"]);
+        assert_eq!(found(Search::value("id".to_string())).0, ["\"id\", ", "\"i\\u00"]);
+        assert_eq!(found(Search::value("\"id\"".to_string())).0.len(), 2);
+        assert_eq!(found(Search::value("null".to_string())).0, ["null, "]);
+        assert_eq!(found(Search::value("true".to_string())).0, ["true}}"]);
+        assert_eq!(found(Search::value("nope".to_string())).1, "No value equal to nope");
+    }
+
+    #[test]
+    fn test_partly_indexed() {
+        let buffer = Buffer::from_text(RECORD);
+        let index = StructuralIndex::from_tokens(&crate::parser::Tokenizer::new(RECORD.to_string()).tokenize_all());
+        let mut search = Search::key("id".to_string());
+        search.scan(&index, &buffer, (1, 1));
+        // Past the last key found, or back before the first, the rest of the
+        // file has to be indexed before coming round
+        let NodeSearch::Found(first) = search.find_node(&index, 0, true, false) else { panic!() };
+        assert_eq!(search.describe(&first), ":key id: match 1 of 2 so far");
+        assert_eq!(search.find_node(&index, RECORD.len(), true, false), NodeSearch::NeedsIndex);
+        assert_eq!(search.find_node(&index, 0, false, false), NodeSearch::NeedsIndex);
+        let NodeSearch::Found(last) = search.find_node(&index, 0, false, true) else { panic!() };
+        assert_eq!((last.position, last.wrapped), (Some((2, 2)), true));
+        // A new stamp starts over
+        search.scan(&StructuralIndex::default(), &buffer, (2, 1));
+        assert_eq!(search.find_node(&index, 0, true, true), NodeSearch::NotFound);
+    }
+
+    #[test]
+    fn test_scope_follows_edits() {
+        let mut search = scoped();
+        let span = search.scope.clone().unwrap().span;
+        // Before the node it shifts, inside it grows, after it stays
+        search.apply_change(&TextChange { offset: 1, removed: 0, inserted: 2 });
+        search.apply_change(&TextChange { offset: span.start + 3, removed: 2, inserted: 5 });
+        search.apply_change(&TextChange { offset: span.end + 10, removed: 1, inserted: 0 });
+        assert_eq!(search.scope.unwrap().span, span.start + 2..span.end + 5);
+    }
+}
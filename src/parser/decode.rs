@@ -0,0 +1,280 @@
+//! Reading scalar literals as the values they stand for, and writing
+//! strings back as literals. Everything that interprets a number or a
+//! string in the document (sorting, :value, :filter, :stats, :diff, the
+//! schema check) goes through here, so they all agree on what a literal
+//! means.
+
+use std::cmp::Ordering;
+use std::fmt::Write;
+
+use super::error::EscapeError;
+
+/// A JSON number as written: whole numbers stay exact, however long
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedNumber {
+    /// A whole number without fraction or exponent that fits an i64
+    Int(i64),
+    /// A whole number too big for an i64, as its digits (with any `-`)
+    BigInt(String),
+    /// Anything with a fraction or exponent. Exponents past f64's range
+    /// give infinity or zero.
+    Float(f64),
+}
+
+impl DecodedNumber {
+    /// The value as an f64, rounded where it has to be
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            DecodedNumber::Int(n) => *n as f64,
+            DecodedNumber::BigInt(digits) => digits.parse().unwrap_or(f64::NAN),
+            DecodedNumber::Float(n) => *n,
+        }
+    }
+
+    /// Whether the value is whole, as JSON Schema's "integer" counts it:
+    /// `1.0` and `1e3` are
+    pub fn is_integer(&self) -> bool {
+        match self {
+            DecodedNumber::Int(_) | DecodedNumber::BigInt(_) => true,
+            DecodedNumber::Float(n) => n.is_finite() && n.fract() == 0.0,
+        }
+    }
+
+    /// Order by value, whole numbers exactly; equal values in different
+    /// spellings (`1`, `1.0`) compare equal
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (DecodedNumber::Int(a), DecodedNumber::Int(b)) => a.cmp(b),
+            (DecodedNumber::BigInt(a), DecodedNumber::BigInt(b)) => compare_digits(a, b),
+            (a, b) => a.as_f64().total_cmp(&b.as_f64()),
+        }
+    }
+}
+
+/// Compare two whole numbers written out in full, without leading zeros
+fn compare_digits(a: &str, b: &str) -> Ordering {
+    match (a.strip_prefix('-'), b.strip_prefix('-')) {
+        (Some(a), Some(b)) => compare_digits(b, a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.len().cmp(&b.len()).then_with(|| a.cmp(b)),
+    }
+}
+
+/// A number literal as the value it stands for; None unless it's spelled
+/// the way JSON spells numbers (no `+`, leading zeros, bare `.` or `inf`)
+pub fn decode_number(literal: &str) -> Option<DecodedNumber> {
+    let bytes = literal.as_bytes();
+    let mut i = usize::from(bytes.first() == Some(&b'-'));
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while bytes.get(*i).is_some_and(u8::is_ascii_digit) {
+            *i += 1;
+        }
+        *i - start
+    };
+    let whole = digits(&mut i);
+    if whole == 0 || whole > 1 && bytes[i - whole] == b'0' {
+        return None;
+    }
+    let mut integer = true;
+    if bytes.get(i) == Some(&b'.') {
+        i += 1;
+        if digits(&mut i) == 0 {
+            return None;
+        }
+        integer = false;
+    }
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        i += 1;
+        if matches!(bytes.get(i), Some(b'+' | b'-')) {
+            i += 1;
+        }
+        if digits(&mut i) == 0 {
+            return None;
+        }
+        integer = false;
+    }
+    if i != bytes.len() {
+        return None;
+    }
+    if !integer {
+        return literal.parse().ok().map(DecodedNumber::Float);
+    }
+    Some(match literal.parse() {
+        Ok(n) => DecodedNumber::Int(n),
+        Err(_) => DecodedNumber::BigInt(literal.to_string()),
+    })
+}
+
+/// `value` as a JSON string literal, quotes included
+pub fn encode_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The text a JSON string literal (quotes included) stands for
+pub fn decode_string(literal: &str) -> Result<String, EscapeError> {
+    let inner = literal
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|_| literal.len() >= 2)
+        .ok_or(EscapeError::NotQuoted)?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.char_indices().map(|(i, c)| (i + 1, c));
+    while let Some((offset, c)) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        // A backslash just before the closing quote escapes it, so the
+        // literal never closed
+        let Some((_, escape)) = chars.next() else {
+            return Err(EscapeError::NotQuoted);
+        };
+        let c = match escape {
+            '"' => '"',
+            '\\' => '\\',
+            '/' => '/',
+            'b' => '\u{08}',
+            'f' => '\u{0c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let high = hex4(&mut chars).ok_or(EscapeError::BadUnicode(offset))?;
+                match high {
+                    0xd800..=0xdbff => {
+                        // A surrogate pair spells one code point
+                        let low = match (chars.next(), chars.next()) {
+                            (Some((_, '\\')), Some((at, 'u'))) => hex4(&mut chars).ok_or(EscapeError::BadUnicode(at - 1))?,
+                            _ => return Err(EscapeError::LoneSurrogate(offset)),
+                        };
+                        if !(0xdc00..=0xdfff).contains(&low) {
+                            return Err(EscapeError::LoneSurrogate(offset));
+                        }
+                        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+                            .ok_or(EscapeError::LoneSurrogate(offset))?
+                    }
+                    0xdc00..=0xdfff => return Err(EscapeError::LoneSurrogate(offset)),
+                    _ => char::from_u32(high).ok_or(EscapeError::BadUnicode(offset))?,
+                }
+            }
+            escape => return Err(EscapeError::UnknownEscape { offset, escape }),
+        };
+        out.push(c);
+    }
+    Ok(out)
+}
+
+fn hex4(chars: &mut impl Iterator<Item = (usize, char)>) -> Option<u32> {
+    (0..4).try_fold(0, |value, _| Some(value << 4 | chars.next()?.1.to_digit(16)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_round_trip() {
+        for value in ["plain", "say \"hi\"", "back\\slash", "tab\tnew\nline", "\u{1}", "\0", "é 🎉", ""] {
+            assert_eq!(decode_string(&encode_string(value)).as_deref(), Ok(value), "{:?}", encode_string(value));
+        }
+        assert_eq!(encode_string("a\"b\n\u{1f}\u{8}\u{c}\r"), r#""a\"b\n\u001f\b\f\r""#);
+        assert_eq!(encode_string("/\u{7f}"), "\"/\u{7f}\"");
+    }
+
+    #[test]
+    fn test_decode_escapes() {
+        assert_eq!(decode_string(r#""\"\\\/\b\f\n\r\t""#).as_deref(), Ok("\"\\/\u{8}\u{c}\n\r\t"));
+        assert_eq!(decode_string(r#""\u0000""#).as_deref(), Ok("\0"));
+        assert_eq!(decode_string(r#""\u00e9\u00E9""#).as_deref(), Ok("éé"));
+        assert_eq!(decode_string(r#""é\ud83c\udf89\/""#).as_deref(), Ok("é🎉/"));
+        assert_eq!(decode_string(r#""\uD834\uDD1E""#).as_deref(), Ok("𝄞"));
+        assert_eq!(decode_string(r#""\uffff""#).as_deref(), Ok("\u{ffff}"));
+        // Raw text, unusual as it is, comes through as it is
+        assert_eq!(decode_string("\"tab\there\"").as_deref(), Ok("tab\there"));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        for (bad, error) in [
+            (r#""\x""#, EscapeError::UnknownEscape { offset: 1, escape: 'x' }),
+            (r#""ab\'""#, EscapeError::UnknownEscape { offset: 3, escape: '\'' }),
+            (r#""\u12""#, EscapeError::BadUnicode(1)),
+            (r#""\u12g4""#, EscapeError::BadUnicode(1)),
+            (r#""\ud83c""#, EscapeError::LoneSurrogate(1)),
+            (r#""\ud83c x""#, EscapeError::LoneSurrogate(1)),
+            (r#""\ud83c\n""#, EscapeError::LoneSurrogate(1)),
+            (r#""\ud83cA""#, EscapeError::LoneSurrogate(1)),
+            (r#""\ud83c\u00""#, EscapeError::BadUnicode(7)),
+            (r#""\udc00""#, EscapeError::LoneSurrogate(1)),
+            (r#""\""#, EscapeError::NotQuoted),
+            ("\"open", EscapeError::NotQuoted),
+            ("\"", EscapeError::NotQuoted),
+            ("bare", EscapeError::NotQuoted),
+            ("", EscapeError::NotQuoted),
+        ] {
+            assert_eq!(decode_string(bad), Err(error), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_decode_number() {
+        use DecodedNumber::*;
+        assert_eq!(decode_number("0"), Some(Int(0)));
+        assert_eq!(decode_number("-0"), Some(Int(0)));
+        assert_eq!(decode_number("42"), Some(Int(42)));
+        assert_eq!(decode_number("-9223372036854775808"), Some(Int(i64::MIN)));
+        assert_eq!(decode_number("9223372036854775807"), Some(Int(i64::MAX)));
+        assert_eq!(decode_number("9223372036854775808"), Some(BigInt("9223372036854775808".to_string())));
+        assert_eq!(decode_number("-123456789012345678901234567890"), Some(BigInt("-123456789012345678901234567890".to_string())));
+        assert_eq!(decode_number("1.5"), Some(Float(1.5)));
+        assert_eq!(decode_number("-0.0"), Some(Float(-0.0)));
+        assert_eq!(decode_number("1e3"), Some(Float(1000.0)));
+        assert_eq!(decode_number("2.5E-3"), Some(Float(0.0025)));
+        assert_eq!(decode_number("1e+2"), Some(Float(100.0)));
+        // Exponents past what an f64 holds
+        assert_eq!(decode_number("1e400"), Some(Float(f64::INFINITY)));
+        assert_eq!(decode_number("-1e99999999999"), Some(Float(f64::NEG_INFINITY)));
+        assert_eq!(decode_number("1e-400"), Some(Float(0.0)));
+
+        for bad in ["", "-", "+1", "01", "-01", "00", "1.", ".5", "1e", "1e+", "1.e3", "0x10", "inf", "NaN", "1 ", " 1", "1_000", "١"] {
+            assert_eq!(decode_number(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_number_semantics() {
+        let n = |text| decode_number(text).unwrap();
+        assert!(n("3").is_integer() && n("3.0").is_integer() && n("3e2").is_integer());
+        assert!(n("99999999999999999999").is_integer());
+        assert!(!n("3.5").is_integer() && !n("1e400").is_integer());
+        assert_eq!(n("99999999999999999999").as_f64(), 1e20);
+
+        // Exact where an f64 would round the two to the same value
+        assert_eq!(n("9007199254740993").total_cmp(&n("9007199254740992")), Ordering::Greater);
+        assert_eq!(n("100000000000000000001").total_cmp(&n("100000000000000000000")), Ordering::Greater);
+        assert_eq!(n("-100000000000000000001").total_cmp(&n("-100000000000000000000")), Ordering::Less);
+        assert_eq!(n("-100000000000000000000").total_cmp(&n("99999999999999999999")), Ordering::Less);
+        assert_eq!(n("1").total_cmp(&n("1.0")), Ordering::Equal);
+        assert_eq!(n("2").total_cmp(&n("1e400")), Ordering::Less);
+    }
+}
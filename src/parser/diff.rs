@@ -0,0 +1,315 @@
+//! Structural diff between two documents for :diff. Objects are matched by
+//! key and arrays by position, so formatting and key order make no
+//! difference; only values that differ are reported.
+
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+
+use super::decode;
+use super::node::NodeId;
+use super::{NodeKind, StructuralIndex, Tokenizer};
+use crate::buffer::TextChange;
+use crate::navigation::path::key_segment;
+pub use crate::ui::tasks::Progress;
+
+/// Nesting below which subtrees are compared as a whole, reported as one
+/// change at this depth if they differ anywhere
+pub const MAX_DEPTH: u32 = 64;
+/// Changes reported before the diff stops
+pub const MAX_CHANGES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Only in this document
+    Added,
+    /// Only in the other one
+    Removed,
+    /// In both, with different values
+    Changed,
+}
+
+impl ChangeKind {
+    /// Gutter and summary sign
+    pub fn sign(self) -> char {
+        match self {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+            ChangeKind::Changed => '~',
+        }
+    }
+}
+
+/// One difference, located in this document: an added member or changed
+/// value by its span, a removed one by the closing bracket of the
+/// container it's missing from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub span: Range<usize>,
+    pub path: String,
+    /// The other document's value, shortened, for changes and removals
+    pub other: Option<String>,
+}
+
+impl Change {
+    /// `~ $.a.b (other: 2)`
+    pub fn describe(&self) -> String {
+        match &self.other {
+            Some(other) => format!("{} {} (other: {})", self.kind.sign(), self.path, other),
+            None => format!("{} {}", self.kind.sign(), self.path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diff {
+    /// In document order
+    pub changes: Vec<Change>,
+    /// Stopped at MAX_CHANGES, or cancelled
+    pub truncated: bool,
+}
+
+/// One side of a diff
+pub struct Side<'a> {
+    pub index: &'a StructuralIndex,
+    pub text: &'a str,
+}
+
+impl<'a> Side<'a> {
+    fn node(&self, id: NodeId) -> Option<(NodeKind, Range<usize>)> {
+        let node = self.index.get(id)?;
+        Some((node.kind, node.start..node.end.min(self.text.len())))
+    }
+
+    fn literal(&self, id: NodeId) -> &'a str {
+        self.node(id).map_or("", |(_, span)| &self.text[span])
+    }
+
+    /// Object members as (decoded key, key node, value node)
+    fn members(&self, id: NodeId) -> Vec<(String, NodeId, NodeId)> {
+        self.index.member_iter(id)
+            .filter_map(|(key, value)| {
+                let raw = self.literal(key);
+                Some((decode::decode_string(raw).ok().unwrap_or_else(|| raw.to_string()), key, value?))
+            })
+            .collect()
+    }
+
+    /// Nodes in `id`'s subtree
+    fn size(&self, id: NodeId) -> usize {
+        self.node(id).map_or(0, |(_, span)| self.index.nodes_starting_in(span).len())
+    }
+}
+
+/// Compare `ours` against `other`, top-level value by top-level value
+pub fn diff(ours: &Side, other: &Side, progress: &Progress) -> Diff {
+    progress.total.store(ours.index.len(), Ordering::Relaxed);
+    let cancel = &progress.cancel;
+    let progress = &progress.done;
+    let mut diff = Diff::default();
+    let roots = ours.index.root_count().max(other.index.root_count());
+    let mut pending: Vec<(Option<NodeId>, Option<NodeId>, String, u32)> = (0..roots)
+        .rev()
+        .map(|n| {
+            let path = if roots > 1 { format!("#{} $", n + 1) } else { "$".to_string() };
+            (ours.index.nth_root(n), other.index.nth_root(n), path, 0)
+        })
+        .collect();
+    while let Some((a, b, path, depth)) = pending.pop() {
+        if diff.changes.len() >= MAX_CHANGES || cancel.load(Ordering::Relaxed) {
+            diff.truncated = true;
+            break;
+        }
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) => (a, b),
+            (Some(a), None) => {
+                progress.fetch_add(ours.size(a), Ordering::Relaxed);
+                if let Some((_, span)) = ours.node(a) {
+                    diff.changes.push(Change { kind: ChangeKind::Added, span, path, other: None });
+                }
+                continue;
+            }
+            (None, Some(b)) => {
+                let end = ours.text.len();
+                diff.changes.push(Change { kind: ChangeKind::Removed, span: end..end, path, other: Some(shorten(other.literal(b))) });
+                continue;
+            }
+            (None, None) => continue,
+        };
+        progress.fetch_add(1, Ordering::Relaxed);
+        let (Some((kind, span)), Some((other_kind, _))) = (ours.node(a), other.node(b)) else {
+            continue;
+        };
+        let changed = Change { kind: ChangeKind::Changed, span: span.clone(), path: path.clone(), other: Some(shorten(other.literal(b))) };
+        if kind != other_kind {
+            progress.fetch_add(ours.size(a).saturating_sub(1), Ordering::Relaxed);
+            diff.changes.push(changed);
+            continue;
+        }
+        if depth >= MAX_DEPTH && matches!(kind, NodeKind::Object | NodeKind::Array) {
+            progress.fetch_add(ours.size(a).saturating_sub(1), Ordering::Relaxed);
+            if !same_subtree(ours, a, other, b) {
+                diff.changes.push(changed);
+            }
+            continue;
+        }
+        // The bracket that closes `a`, where members only `b` has are shown
+        let closer = span.end.saturating_sub(1)..span.end;
+        match kind {
+            NodeKind::Object => {
+                let mine = ours.members(a);
+                let theirs = other.members(b);
+                let mut next = Vec::new();
+                for (key, key_id, value) in &mine {
+                    let member_path = format!("{}{}", path, key_segment(ours.literal(*key_id)));
+                    match theirs.iter().find(|(k, _, _)| k == key) {
+                        Some(&(_, _, other_value)) => {
+                            progress.fetch_add(1, Ordering::Relaxed);
+                            next.push((Some(*value), Some(other_value), member_path, depth + 1));
+                        }
+                        None => {
+                            let start = ours.node(*key_id).map_or(span.start, |(_, key)| key.start);
+                            let end = ours.node(*value).map_or(start, |(_, value)| value.end);
+                            progress.fetch_add(ours.size(*value) + 1, Ordering::Relaxed);
+                            diff.changes.push(Change { kind: ChangeKind::Added, span: start..end, path: member_path, other: None });
+                        }
+                    }
+                }
+                for (key, key_id, value) in &theirs {
+                    if !mine.iter().any(|(k, _, _)| k == key) {
+                        let member_path = format!("{}{}", path, key_segment(other.literal(*key_id)));
+                        diff.changes.push(Change { kind: ChangeKind::Removed, span: closer.clone(), path: member_path, other: Some(shorten(other.literal(*value))) });
+                    }
+                }
+                pending.extend(next.into_iter().rev());
+            }
+            NodeKind::Array => {
+                let mine: Vec<NodeId> = ours.index.child_iter(a).collect();
+                let theirs: Vec<NodeId> = other.index.child_iter(b).collect();
+                let mut next = Vec::new();
+                for i in 0..mine.len().max(theirs.len()) {
+                    let element_path = format!("{}[{}]", path, i);
+                    match (mine.get(i), theirs.get(i)) {
+                        (None, Some(&value)) => {
+                            diff.changes.push(Change { kind: ChangeKind::Removed, span: closer.clone(), path: element_path, other: Some(shorten(other.literal(value))) });
+                        }
+                        (mine, theirs) => next.push((mine.copied(), theirs.copied(), element_path, depth + 1)),
+                    }
+                }
+                pending.extend(next.into_iter().rev());
+            }
+            NodeKind::String | NodeKind::Key => {
+                let decoded = |side: &Side, id| decode::decode_string(side.literal(id)).ok();
+                if decoded(ours, a) != decoded(other, b) {
+                    diff.changes.push(changed);
+                }
+            }
+            NodeKind::Number => {
+                let number = |side: &Side, id| decode::decode_number(side.literal(id));
+                let same = match (number(ours, a), number(other, b)) {
+                    (Some(mine), Some(theirs)) => mine.total_cmp(&theirs) == std::cmp::Ordering::Equal,
+                    _ => ours.literal(a) == other.literal(b),
+                };
+                if !same {
+                    diff.changes.push(changed);
+                }
+            }
+            _ => {
+                if ours.literal(a) != other.literal(b) {
+                    diff.changes.push(changed);
+                }
+            }
+        }
+    }
+    diff.changes.sort_by_key(|c| c.span.start);
+    diff
+}
+
+/// Whether two subtrees hold the same values in the same order, formatting
+/// aside
+fn same_subtree(ours: &Side, a: NodeId, other: &Side, b: NodeId) -> bool {
+    let (Some((_, mine)), Some((_, theirs))) = (ours.node(a), other.node(b)) else {
+        return false;
+    };
+    let mine = ours.index.nodes_starting_in(mine);
+    let theirs = other.index.nodes_starting_in(theirs);
+    mine.len() == theirs.len() && mine.zip(theirs).all(|(x, y)| {
+        let (Some((kind, _)), Some((other_kind, _))) = (ours.node(x), other.node(y)) else {
+            return false;
+        };
+        kind == other_kind && (matches!(kind, NodeKind::Object | NodeKind::Array) || ours.literal(x) == other.literal(y))
+    })
+}
+
+/// Values longer than this are cut short in descriptions
+const SHORT_VALUE: usize = 40;
+
+/// A value's text on one line, cut short with `…`
+fn shorten(literal: &str) -> String {
+    let flat: String = literal.split_whitespace().collect::<Vec<_>>().join(" ");
+    match flat.char_indices().nth(SHORT_VALUE) {
+        Some((cut, _)) => format!("{}…", &flat[..cut]),
+        None => flat,
+    }
+}
+
+/// Index `text` from scratch, for a background thread
+pub fn index_text(text: &str) -> StructuralIndex {
+    StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).with_comments(true).tokenize_all())
+}
+
+/// Keep changes on their text across an edit: those after it shift, those
+/// it touches are dropped
+pub fn apply_change(changes: &mut Vec<Change>, change: &TextChange) {
+    changes.retain_mut(|c| match change.shift_span(&c.span) {
+        Some(span) => {
+            c.span = span;
+            true
+        }
+        None => false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(ours: &str, other: &str) -> Vec<String> {
+        let (a, b) = (index_text(ours), index_text(other));
+        let progress = Progress::default();
+        let diff = diff(&Side { index: &a, text: ours }, &Side { index: &b, text: other }, &progress);
+        assert_eq!(progress.percent(), 100);
+        assert_eq!(progress.done.load(Ordering::Relaxed), a.len());
+        diff.changes.iter().map(|c| format!("{} @{}", c.describe(), &ours[c.span.clone()])).collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        // Key order and formatting don't count, nor do escapes or number spelling
+        assert!(changes(r#"{"a": 1, "b": [true, "\u0041"]}"#, "{\n  \"b\": [true, \"A\"],\n  \"a\": 1.0\n}").is_empty());
+        assert_eq!(
+            changes(r#"{"a": 1, "b": [1, 2, 3], "c": {"d": null}, "new": "x"}"#, r#"{"a": 2, "b": [1, 3], "c": {"d": null, "gone": [1,   2]}}"#),
+            [
+                "~ $.a (other: 2) @1",
+                "~ $.b[1] (other: 3) @2",
+                "+ $.b[2] @3",
+                "- $.c.gone (other: [1, 2]) @}",
+                "+ $.new @\"new\": \"x\"",
+            ]
+        );
+        assert_eq!(changes("[1]", "[1, {\"k\": 2}]"), ["- $[1] (other: {\"k\": 2}) @]"]);
+        assert_eq!(changes(r#"{"a b": [1]}"#, r#"{"a b": {}}"#), [r#"~ $["a b"] (other: {}) @[1]"#]);
+        // JSON Lines: record by record
+        assert_eq!(changes("{\"n\": 1}\n{\"n\": 2}", "{\"n\": 1}\n{\"n\": 3}"), ["~ #2 $.n (other: 3) @2"]);
+    }
+
+    #[test]
+    fn test_apply_change() {
+        let change = |start, end| Change { kind: ChangeKind::Changed, span: start..end, path: String::new(), other: None };
+        let mut changes = vec![change(0, 2), change(5, 8), change(10, 12)];
+        apply_change(&mut changes, &TextChange { offset: 9, removed: 0, inserted: 2 });
+        assert_eq!(changes, vec![change(0, 2), change(5, 8), change(12, 14)]);
+        apply_change(&mut changes, &TextChange { offset: 4, removed: 2, inserted: 0 });
+        assert_eq!(changes, vec![change(0, 2), change(10, 12)]);
+    }
+}
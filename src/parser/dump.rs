@@ -0,0 +1,352 @@
+//! Non-interactive output for tooling: every node of a document as NDJSON
+//! (`jim --dump-index`) or an outline of its keys (`jim --outline`). The
+//! file is tokenized a block at a time and nodes are written as they
+//! complete, so memory stays proportional to the nesting depth however big
+//! the file is.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::decode;
+use super::node::NodeId;
+use super::token::TokenKind;
+use super::{NodeKind, Token, Tokenizer};
+use crate::navigation::path::key_segment;
+
+/// Bytes read and tokenized at a time
+const BLOCK_BYTES: usize = 1 << 20;
+
+/// Outline values longer than this are cut short
+const OUTLINE_VALUE: usize = 60;
+
+/// A node as the stream sees it. Ids, spans, depths and parents are the
+/// ones `StructuralIndex` gives the same document; object keys are `Key`
+/// nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamNode {
+    pub id: NodeId,
+    pub kind: NodeKind,
+    pub start: usize,
+    pub end: usize,
+    pub depth: u32,
+    pub parent: Option<NodeId>,
+    /// As `json_path` writes it; a key has its member's path
+    pub path: String,
+    /// Element number in an array, member number in an object
+    pub position: usize,
+    /// Where the last segment of `path` starts
+    label_at: usize,
+}
+
+impl StreamNode {
+    /// The last segment of the path: `.name`, `[3]`, or `$` at the top
+    pub fn label(&self) -> &str {
+        &self.path[self.label_at..]
+    }
+}
+
+/// What the stream reports, in document order
+pub enum Event<'a> {
+    /// A key or scalar value, with its text
+    Scalar(&'a StreamNode, &'a [u8]),
+    /// A container opening; its end isn't known yet
+    Open(&'a StreamNode),
+    /// A container closing, with how many child nodes it had
+    Close(&'a StreamNode, usize),
+}
+
+struct OpenNode {
+    node: StreamNode,
+    children: usize,
+    /// Path and label start of the member whose key was just seen
+    member: Option<(String, usize)>,
+}
+
+/// Turns tokens into node events, keeping only the open containers
+#[derive(Default)]
+pub struct NodeStream {
+    stack: Vec<OpenNode>,
+    next_id: NodeId,
+    /// End of the last token, where containers left open are closed
+    frontier: usize,
+}
+
+impl NodeStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the next token, whose text is `text`
+    pub fn push<F>(&mut self, token: &Token, text: &[u8], visit: &mut F) -> io::Result<()>
+    where
+        F: FnMut(Event) -> io::Result<()>,
+    {
+        let kind = match token.kind {
+            TokenKind::BraceOpen => NodeKind::Object,
+            TokenKind::BracketOpen => NodeKind::Array,
+            TokenKind::String => NodeKind::String,
+            TokenKind::Number => NodeKind::Number,
+            TokenKind::True | TokenKind::False => NodeKind::Boolean,
+            TokenKind::Null => NodeKind::Null,
+            TokenKind::BraceClose | TokenKind::BracketClose => {
+                self.frontier = token.end;
+                if let Some(mut open) = self.stack.pop() {
+                    open.node.end = token.end;
+                    visit(Event::Close(&open.node, open.children))?;
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        self.frontier = token.end;
+        let id = self.next_id;
+        self.next_id += 1;
+        let depth = self.stack.len() as u32;
+        let (kind, parent, path, label_at, position) = match self.stack.last_mut() {
+            None => (kind, None, "$".to_string(), 0, 0),
+            Some(open) => {
+                let nth = open.children;
+                open.children += 1;
+                let parent = Some(open.node.id);
+                let base = &open.node.path;
+                match open.node.kind {
+                    NodeKind::Object if nth % 2 == 0 => {
+                        let path = format!("{}{}", base, key_segment(&String::from_utf8_lossy(text)));
+                        open.member = Some((path.clone(), base.len()));
+                        (NodeKind::Key, parent, path, base.len(), nth / 2)
+                    }
+                    NodeKind::Object => {
+                        let (path, label_at) = open.member.take().unwrap_or_else(|| (base.clone(), base.len()));
+                        (kind, parent, path, label_at, nth / 2)
+                    }
+                    _ => (kind, parent, format!("{}[{}]", base, nth), base.len(), nth),
+                }
+            }
+        };
+        let node = StreamNode { id, kind, start: token.start, end: token.end, depth, parent, path, position, label_at };
+        if matches!(kind, NodeKind::Object | NodeKind::Array) {
+            self.stack.push(OpenNode { node, children: 0, member: None });
+            if let Some(open) = self.stack.last() {
+                visit(Event::Open(&open.node))?;
+            }
+            Ok(())
+        } else {
+            visit(Event::Scalar(&node, text))
+        }
+    }
+
+    /// End of input: close whatever is still open at the last token
+    pub fn finish<F>(&mut self, visit: &mut F) -> io::Result<()>
+    where
+        F: FnMut(Event) -> io::Result<()>,
+    {
+        while let Some(mut open) = self.stack.pop() {
+            open.node.end = self.frontier;
+            visit(Event::Close(&open.node, open.children))?;
+        }
+        Ok(())
+    }
+}
+
+/// Feed `input` through a `NodeStream` a block at a time. `progress` gets
+/// the bytes read so far after each block.
+pub fn stream<R, F>(mut input: R, visit: &mut F, mut progress: impl FnMut(usize)) -> Result<()>
+where
+    R: Read,
+    F: FnMut(Event) -> io::Result<()>,
+{
+    // Comments are skipped whatever the format, as the index does for JSONC
+    let mut tokenizer = Tokenizer::streaming().with_comments(true);
+    let mut nodes = NodeStream::new();
+    let mut block = vec![0; BLOCK_BYTES];
+    let mut read = 0;
+    loop {
+        let n = input.read(&mut block)?;
+        if n == 0 {
+            tokenizer.finish();
+        } else {
+            tokenizer.feed(&block[..n]);
+            read += n;
+        }
+        while let Some(token) = tokenizer.next_token() {
+            nodes.push(&token, tokenizer.token_bytes(&token), visit)?;
+        }
+        if n == 0 {
+            break;
+        }
+        progress(read);
+    }
+    nodes.finish(visit)?;
+    Ok(())
+}
+
+/// `jim --dump-index`: one JSON object per node, written as each node
+/// completes (a container after its contents). Returns the node count.
+pub fn dump_index(input: impl Read, out: &mut impl Write, progress: impl FnMut(usize)) -> Result<usize> {
+    let mut count = 0;
+    let mut write = |node: &StreamNode| {
+        count += 1;
+        let parent = node.parent.map_or("null".to_string(), |p| p.to_string());
+        writeln!(
+            out,
+            "{{\"id\": {}, \"kind\": \"{}\", \"start\": {}, \"end\": {}, \"depth\": {}, \"parent\": {}, \"path\": {}}}",
+            node.id,
+            node.kind.to_string().to_lowercase(),
+            node.start,
+            node.end,
+            node.depth,
+            parent,
+            decode::encode_string(&node.path),
+        )
+    };
+    stream(input, &mut |event| match event {
+        Event::Scalar(node, _) | Event::Close(node, _) => write(node),
+        Event::Open(_) => Ok(()),
+    }, progress)?;
+    Ok(count)
+}
+
+/// `jim --outline`: keys and values down to `max_depth`, indented. Only
+/// the first element of each array is shown, followed by how many more
+/// there are, so the outline reads as the document's shape.
+pub fn outline(input: impl Read, out: &mut impl Write, max_depth: u32, progress: impl FnMut(usize)) -> Result<()> {
+    // Depth of the shallowest array element past the first being skipped
+    let mut hidden: Option<u32> = None;
+    fn line(out: &mut impl Write, node: &StreamNode, value: &str) -> io::Result<()> {
+        let label = node.label().strip_prefix('.').unwrap_or(node.label());
+        writeln!(out, "{}{}: {}", "  ".repeat(node.depth as usize), label, value)
+    }
+    stream(input, &mut |event| {
+        let node = match &event {
+            Event::Scalar(node, _) | Event::Open(node) | Event::Close(node, _) => *node,
+        };
+        if hidden.is_some_and(|depth| node.depth > depth || node.depth == depth && !matches!(event, Event::Close(..))) {
+            return Ok(());
+        }
+        hidden = None;
+        let is_element = |node: &StreamNode| node.label().starts_with('[') && !node.label().starts_with("[\"");
+        if is_element(node) && node.position > 0 && !matches!(event, Event::Close(..)) {
+            hidden = Some(node.depth);
+            return Ok(());
+        }
+        if node.depth > max_depth {
+            return Ok(());
+        }
+        match event {
+            Event::Scalar(node, _) if node.kind == NodeKind::Key => Ok(()),
+            Event::Scalar(node, text) => line(out, node, &shorten(&String::from_utf8_lossy(text))),
+            Event::Open(node) => line(out, node, if node.kind == NodeKind::Object { "object" } else { "array" }),
+            Event::Close(node, children) if node.kind == NodeKind::Array && children > 1 && node.depth < max_depth => {
+                writeln!(out, "{}… {} more", "  ".repeat(node.depth as usize + 1), children - 1)
+            }
+            Event::Close(..) => Ok(()),
+        }
+    }, progress)?;
+    Ok(())
+}
+
+fn shorten(value: &str) -> String {
+    match value.char_indices().nth(OUTLINE_VALUE) {
+        Some((cut, _)) => format!("{}…", &value[..cut]),
+        None => value.to_string(),
+    }
+}
+
+/// Open `path` for one of the modes above, with its size for progress
+pub fn open(path: &Path) -> Result<(File, u64)> {
+    let file = File::open(path).with_context(|| format!("Can't open {}", path.display()))?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::navigation::path::json_path;
+    use crate::parser::StructuralIndex;
+
+    fn nodes(text: &str, block: usize) -> Vec<StreamNode> {
+        let mut tokenizer = Tokenizer::streaming().with_comments(true);
+        let mut stream = NodeStream::new();
+        let mut found = Vec::new();
+        let mut visit = |event: Event| {
+            if let Event::Scalar(node, _) | Event::Close(node, _) = event {
+                found.push(node.clone());
+            }
+            Ok(())
+        };
+        for piece in text.as_bytes().chunks(block) {
+            tokenizer.feed(piece);
+            while let Some(token) = tokenizer.next_token() {
+                stream.push(&token, tokenizer.token_bytes(&token), &mut visit).unwrap();
+            }
+        }
+        tokenizer.finish();
+        while let Some(token) = tokenizer.next_token() {
+            stream.push(&token, tokenizer.token_bytes(&token), &mut visit).unwrap();
+        }
+        stream.finish(&mut visit).unwrap();
+        found.sort_by_key(|node| node.id);
+        found
+    }
+
+    /// The stream agrees with the index built the usual way, node for node
+    #[test]
+    fn test_matches_index() {
+        let documents = [
+            r#"{"users": [{"name": "a", "tags": []}, {"first name": "b", "id": 7}], "n": 1.5e3, "ok": true}"#,
+            "[1, [2, [3, {\"deep\": null}]], \"x\"]",
+            "{\"a\": 1}\n{\"a\": [2]}\n",
+            "{\"a\": [1, 2",
+        ];
+        for text in documents {
+            let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+            let buffer = Buffer::from_text(text);
+            for block in [3, 1000] {
+                let streamed = nodes(text, block);
+                assert_eq!(streamed.len(), index.len(), "{}", text);
+                for node in &streamed {
+                    let info = index.get(node.id).unwrap();
+                    let kind = if index.is_key_node(node.id) { NodeKind::Key } else { info.kind };
+                    assert_eq!((node.kind, node.start, node.end, node.depth, node.parent), (kind, info.start, info.end, info.depth, info.parent), "{}", text);
+                    assert_eq!(node.path, json_path(&index, &buffer, node.start), "{}", text);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dump_index() {
+        let mut out = Vec::new();
+        let count = dump_index(r#"{"a b": [true]}"#.as_bytes(), &mut out, |_| {}).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "{\"id\": 1, \"kind\": \"key\", \"start\": 1, \"end\": 6, \"depth\": 1, \"parent\": 0, \"path\": \"$[\\\"a b\\\"]\"}\n",
+                "{\"id\": 3, \"kind\": \"boolean\", \"start\": 9, \"end\": 13, \"depth\": 2, \"parent\": 2, \"path\": \"$[\\\"a b\\\"][0]\"}\n",
+                "{\"id\": 2, \"kind\": \"array\", \"start\": 8, \"end\": 14, \"depth\": 1, \"parent\": 0, \"path\": \"$[\\\"a b\\\"]\"}\n",
+                "{\"id\": 0, \"kind\": \"object\", \"start\": 0, \"end\": 15, \"depth\": 0, \"parent\": null, \"path\": \"$\"}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_outline() {
+        let text = r#"{"users": [{"name": "a", "id": 1}, {"name": "b", "id": 2}, {"name": "c"}], "meta": {"v": 2}}"#;
+        let render = |max_depth| {
+            let mut out = Vec::new();
+            outline(text.as_bytes(), &mut out, max_depth, |_| {}).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            render(u32::MAX),
+            "$: object\n  users: array\n    [0]: object\n      name: \"a\"\n      id: 1\n    … 2 more\n  meta: object\n    v: 2\n"
+        );
+        assert_eq!(render(1), "$: object\n  users: array\n  meta: object\n");
+    }
+}
@@ -0,0 +1,202 @@
+//! Duplicate object keys: legal JSON, but almost always a bug in whatever
+//! wrote it, since readers disagree on which of the values wins. Keys are
+//! checked as the structural index is built, a node at a time in document
+//! order, against the names already seen in each object still open around
+//! them. Only those objects' names are kept, and each key is one lookup,
+//! so an object with hundreds of thousands of keys stays linear.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::decode;
+use super::node::NodeId;
+use super::{NodeKind, StructuralIndex};
+use crate::buffer::TextChange;
+
+/// Shown in the gutter on a line with a duplicate key
+pub const SIGN: char = '!';
+
+/// How duplicate keys are reported (:set dupkeys)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DupKeys {
+    /// Counted with the structural errors
+    Error,
+    #[default]
+    Warn,
+    /// Not looked for
+    Ignore,
+}
+
+impl DupKeys {
+    /// The setting :set dupkeys names
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(DupKeys::Error),
+            "warn" => Some(DupKeys::Warn),
+            "ignore" => Some(DupKeys::Ignore),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DupKeys::Error => "error",
+            DupKeys::Warn => "warn",
+            DupKeys::Ignore => "ignore",
+        }
+    }
+}
+
+/// A key its object already had
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateKey {
+    /// The key, quotes and all
+    pub span: Range<usize>,
+    /// Where the first key with the name starts
+    pub first: usize,
+    /// The name, decoded
+    pub name: String,
+}
+
+/// An object the scan is inside
+#[derive(Debug, Clone)]
+struct OpenObject {
+    id: NodeId,
+    depth: u32,
+    /// Children passed, keys and values: a key is at an even place. Kept
+    /// here since walking back along the siblings to find out would make
+    /// a wide object quadratic
+    children: usize,
+    /// Each name and where it was first
+    names: HashMap<String, usize>,
+}
+
+/// The check for duplicate keys, as far through the index as it's got
+#[derive(Debug, Clone, Default)]
+pub struct DuplicateScan {
+    /// The next node to look at
+    next: NodeId,
+    /// The objects around it with members seen so far, outermost first
+    open: Vec<OpenObject>,
+    /// Duplicates found, in document order
+    pub found: Vec<DuplicateKey>,
+}
+
+impl DuplicateScan {
+    /// Look at every node indexed since the last call, given the text of a
+    /// span as it is now
+    pub fn advance(&mut self, index: &StructuralIndex, text: impl Fn(Range<usize>) -> String) {
+        while let Some(node) = index.get(self.next) {
+            self.next += 1;
+            // Objects at this depth or deeper have ended before this node
+            while self.open.last().is_some_and(|object| object.depth >= node.depth) {
+                self.open.pop();
+            }
+            let Some(parent) = node.parent.filter(|&parent| index.kind(parent) == Some(NodeKind::Object)) else {
+                continue;
+            };
+            if self.open.last().is_none_or(|object| object.id != parent) {
+                let depth = node.depth.saturating_sub(1);
+                self.open.push(OpenObject { id: parent, depth, children: 0, names: HashMap::new() });
+            }
+            let Some(object) = self.open.last_mut() else { continue };
+            object.children += 1;
+            if object.children % 2 == 0 || node.kind != NodeKind::String {
+                continue;
+            }
+            let literal = text(node.start..node.end);
+            let name = decode::decode_string(&literal).unwrap_or(literal);
+            match object.names.get(&name) {
+                Some(&first) => self.found.push(DuplicateKey { span: node.start..node.end, first, name }),
+                None => {
+                    object.names.insert(name, node.start);
+                }
+            }
+        }
+    }
+
+    /// Keep what's been found in place across an edit: those after it
+    /// shift, those it touches are dropped until the index is rebuilt
+    pub fn apply_change(&mut self, change: &TextChange) {
+        self.found.retain_mut(|duplicate| match change.shift_span(&duplicate.span) {
+            Some(span) => {
+                duplicate.span = span;
+                duplicate.first = change.shift_offset(duplicate.first);
+                true
+            }
+            None => false,
+        });
+        for object in &mut self.open {
+            object.names.values_mut().for_each(|first| *first = change.shift_offset(*first));
+        }
+    }
+
+    /// The duplicate `count` places after the one at or before `offset`
+    /// (before it, going back), with its place among them all
+    pub fn step(&self, offset: usize, forward: bool, count: usize) -> Option<(usize, &DuplicateKey)> {
+        let position = if forward {
+            let after = self.found.partition_point(|d| d.span.start <= offset);
+            (after + count.max(1) - 1).min(self.found.len().saturating_sub(1))
+        } else {
+            self.found.partition_point(|d| d.span.start < offset).saturating_sub(count.max(1))
+        };
+        self.found
+            .get(position)
+            .filter(|d| if forward { d.span.start > offset } else { d.span.start < offset })
+            .map(|d| (position, d))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    fn scan(text: &str) -> Vec<(String, usize, usize)> {
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let mut scan = DuplicateScan::default();
+        scan.advance(&index, |span| text[span].to_string());
+        scan.found.iter().map(|d| (d.name.clone(), d.span.start, d.first)).collect()
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let text = r#"{"id": 1, "a": {"id": 2, "b": [{"id": 3}, {"id": 4, "id": 5}], "id": 6}, "id": 7}"#;
+        let at = |from: usize, what: &str| from + text[from..].find(what).unwrap();
+        let inner = at(0, r#"{"id": 4"#) + 1;
+        let nested = at(0, r#"{"id": 2"#) + 1;
+        assert_eq!(scan(text), [
+            ("id".to_string(), at(inner + 1, r#""id""#), inner),
+            ("id".to_string(), at(0, r#""id": 6"#), nested),
+            ("id".to_string(), at(0, r#""id": 7"#), 1),
+        ]);
+        // The same name in sibling objects, or as a value, isn't a duplicate
+        assert!(scan(r#"[{"a": "a"}, {"a": 1}]"#).is_empty());
+    }
+
+    #[test]
+    fn test_wide_object() {
+        let mut text = String::from("{");
+        for n in 0..200_000 {
+            text.push_str(&format!("\"k{}\": {}, ", n % 100_000, n));
+        }
+        text.push_str("\"end\": 0}");
+        let found = scan(&text);
+        assert_eq!(found.len(), 100_000);
+        assert_eq!(found[0].2, 1);
+    }
+
+    #[test]
+    fn test_step_and_change() {
+        let text = r#"{"a": 1, "a": 2, "a": 3}"#;
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let mut scan = DuplicateScan::default();
+        scan.advance(&index, |span| text[span].to_string());
+        assert_eq!(scan.step(0, true, 1).map(|(n, d)| (n, d.span.start)), Some((0, 9)));
+        assert_eq!(scan.step(9, true, 1).map(|(n, d)| (n, d.span.start)), Some((1, 17)));
+        assert_eq!(scan.step(17, true, 1), None);
+        assert_eq!(scan.step(17, false, 1).map(|(n, _)| n), Some(0));
+        scan.apply_change(&TextChange { offset: 0, removed: 0, inserted: 2 });
+        assert_eq!((scan.found[0].span.start, scan.found[0].first), (11, 3));
+    }
+}
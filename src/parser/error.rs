@@ -0,0 +1,79 @@
+use thiserror::Error;
+
+/// What is wrong with the document structure at a given offset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum StructuralErrorKind {
+    #[error("unexpected '{0}' with no open container")]
+    UnexpectedCloser(char),
+    #[error("expected '{expected}' but found '{found}'")]
+    MismatchedCloser { expected: char, found: char },
+    #[error("'{0}' is never closed")]
+    UnclosedContainer(char),
+    #[error("missing comma between values")]
+    MissingComma,
+    #[error("missing colon after object key")]
+    MissingColon,
+    #[error("missing value")]
+    MissingValue,
+    #[error("expected an object key")]
+    ExpectedKey,
+    #[error("unexpected comma")]
+    UnexpectedComma,
+    #[error("trailing comma before '{0}'")]
+    TrailingComma(char),
+    #[error("unexpected colon")]
+    UnexpectedColon,
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("unterminated string starting here")]
+    UnterminatedString,
+    #[error("unterminated comment starting here")]
+    UnterminatedComment,
+    #[error("trailing content after document end")]
+    TrailingContent,
+    #[error("nested deeper than {0} levels; not indexed past here")]
+    TooDeep(u32),
+    #[error("more than {0} nodes; not indexed past here")]
+    TooManyNodes(usize),
+}
+
+/// Why a string literal couldn't be decoded; offsets are bytes into the
+/// literal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum EscapeError {
+    #[error("not a quoted string")]
+    NotQuoted,
+    #[error("unknown escape '\\{escape}' at byte {offset}")]
+    UnknownEscape { offset: usize, escape: char },
+    #[error("\\u needs four hex digits at byte {0}")]
+    BadUnicode(usize),
+    #[error("unpaired surrogate at byte {0}")]
+    LoneSurrogate(usize),
+}
+
+/// A structural problem found while building the index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("{kind} at byte {offset}")]
+pub struct StructuralError {
+    pub offset: usize,
+    pub kind: StructuralErrorKind,
+}
+
+impl StructuralErrorKind {
+    /// Whether the tokenizer found it, in text that isn't JSON at all,
+    /// rather than the grammar in tokens that are
+    pub fn is_syntax(self) -> bool {
+        matches!(self, Self::InvalidToken | Self::UnterminatedString | Self::UnterminatedComment)
+    }
+}
+
+impl StructuralError {
+    pub fn new(offset: usize, kind: StructuralErrorKind) -> Self {
+        Self { offset, kind }
+    }
+
+    /// Human-readable description, without the position
+    pub fn message(&self) -> String {
+        self.kind.to_string()
+    }
+}
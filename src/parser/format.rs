@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use super::structural_index::StructuralIndex;
+
+/// How a document is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocumentFormat {
+    /// A single JSON value
+    #[default]
+    Json,
+    /// JSON Lines / NDJSON: one JSON value per line
+    JsonLines,
+    /// JSON with comments and trailing commas (tsconfig, VS Code settings)
+    Jsonc,
+}
+
+impl DocumentFormat {
+    /// Pick the format from the file extension, falling back to sniffing a
+    /// sample from the start of the file
+    pub fn detect(path: Option<&Path>, sample: &str) -> Self {
+        path.and_then(Self::from_extension)
+            .unwrap_or_else(|| Self::sniff(sample))
+    }
+
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" | "ldjson" => Some(DocumentFormat::JsonLines),
+            "jsonc" | "json5" => Some(DocumentFormat::Jsonc),
+            "json" => Some(DocumentFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// JSON Lines if the sample's complete lines are at least two separate,
+    /// well-formed values (a pretty-printed document fails on its first line)
+    pub fn sniff(sample: &str) -> Self {
+        let complete = match sample.rfind('\n') {
+            Some(end) => &sample[..=end],
+            None => return DocumentFormat::Json,
+        };
+        
+        let index = StructuralIndex::from_records(complete);
+        let records = complete.lines().filter(|line| !line.trim().is_empty()).count();
+        if records >= 2 && index.root_count() == records && index.errors().is_empty() {
+            DocumentFormat::JsonLines
+        } else {
+            DocumentFormat::Json
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DocumentFormat::Json => "JSON",
+            DocumentFormat::JsonLines => "JSON Lines",
+            DocumentFormat::Jsonc => "JSONC",
+        }
+    }
+
+    /// Whether comments and trailing commas are tolerated
+    pub fn is_relaxed(&self) -> bool {
+        *self == DocumentFormat::Jsonc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_by_extension() {
+        assert_eq!(DocumentFormat::detect(Some(Path::new("a.jsonl")), "{}"), DocumentFormat::JsonLines);
+        assert_eq!(DocumentFormat::detect(Some(Path::new("a.NDJSON")), "{}"), DocumentFormat::JsonLines);
+        assert_eq!(DocumentFormat::detect(Some(Path::new("a.json")), "{}\n{}\n"), DocumentFormat::Json);
+        assert_eq!(DocumentFormat::detect(Some(Path::new("settings.jsonc")), "{}"), DocumentFormat::Jsonc);
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(DocumentFormat::sniff("{\"a\": 1}\n{\"a\": 2}\n{\"a\""), DocumentFormat::JsonLines);
+        assert_eq!(DocumentFormat::sniff("[1, 2]\n"), DocumentFormat::Json);
+        assert_eq!(DocumentFormat::sniff("{\n  \"a\": 1\n}\n"), DocumentFormat::Json);
+        assert_eq!(DocumentFormat::sniff("[\n1,\n2\n]\n"), DocumentFormat::Json);
+        assert_eq!(DocumentFormat::sniff("{\"a\": 1}"), DocumentFormat::Json);
+    }
+}
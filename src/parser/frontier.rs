@@ -0,0 +1,174 @@
+//! How far the structural index reaches into the file, and how far it's
+//! been asked to go. The index is built front to back in chunks, so asking
+//! for a line past the frontier starts a run of chunks out to it; asking
+//! for one already covered does nothing.
+
+use std::ops::Range;
+
+/// Lines indexed past the line a request needs, at the least, so nearby
+/// motions don't have to wait for another chunk
+pub const INDEX_LOOKAHEAD: usize = 5000;
+
+/// Lines ahead of the cursor that navigation wants indexed before it moves
+pub const INDEX_MARGIN: usize = 1000;
+
+/// Most lines handed to the parser thread at once. A jump deep into a huge
+/// file is indexed as a run of these, so progress can be shown and the run
+/// cancelled between (and within) chunks.
+pub const INDEX_CHUNK_LINES: usize = 100_000;
+
+#[derive(Debug, Clone, Default)]
+pub struct IndexFrontier {
+    /// Lines indexed, from the top
+    indexed: usize,
+    /// Line the indexer keeps requesting chunks up to
+    target: usize,
+    /// Line the current run of chunks started at, for the progress percentage
+    from: usize,
+    runs: usize,
+    rebuilds: usize,
+}
+
+impl IndexFrontier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn indexed(&self) -> usize {
+        self.indexed
+    }
+
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    pub fn from(&self) -> usize {
+        self.from
+    }
+
+    /// Whether the first `line` lines of a file of `total` are indexed
+    pub fn covers(&self, line: usize, total: usize) -> bool {
+        self.indexed >= line.min(total)
+    }
+
+    /// Ask for the index to reach `line`, and some way past it. True when
+    /// that takes more chunks. The way past grows with the frontier, so
+    /// walking through the file starts a number of runs logarithmic in how
+    /// far it goes rather than one every few thousand lines.
+    pub fn extend(&mut self, line: usize, total: usize) -> bool {
+        if self.covers(line, total) {
+            return false;
+        }
+        if self.target <= self.indexed {
+            self.from = self.indexed;
+            self.runs += 1;
+        }
+        let lookahead = self.indexed.max(INDEX_LOOKAHEAD);
+        self.target = self.target.max(line.saturating_add(lookahead).min(total));
+        true
+    }
+
+    /// The lines of the next chunk to index, if the target isn't reached
+    pub fn next_chunk(&self) -> Option<Range<usize>> {
+        (self.indexed < self.target).then(|| self.indexed..(self.indexed + INDEX_CHUNK_LINES).min(self.target))
+    }
+
+    /// A chunk up to `end` was merged into the index
+    pub fn advance(&mut self, end: usize) {
+        self.indexed = end;
+    }
+
+    /// Stop after the chunk on its way, if any
+    pub fn stop(&mut self) {
+        self.target = self.target.min(self.indexed);
+    }
+
+    /// The file now has `total` lines
+    pub fn truncate(&mut self, total: usize) {
+        self.indexed = self.indexed.min(total);
+    }
+
+    /// The index was thrown away, to be built again from the top
+    pub fn reset(&mut self) {
+        self.indexed = 0;
+        self.target = 0;
+        self.rebuilds += 1;
+    }
+
+    /// Runs of chunks started to push the frontier on
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+
+    /// Times the index was thrown away and built again
+    pub fn rebuilds(&self) -> usize {
+        self.rebuilds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Index every chunk asked for
+    fn index_all(frontier: &mut IndexFrontier) {
+        while let Some(chunk) = frontier.next_chunk() {
+            frontier.advance(chunk.end);
+        }
+    }
+
+    #[test]
+    fn test_navigation_within_index() {
+        let total = 1_000_000;
+        let mut frontier = IndexFrontier::new();
+        assert!(frontier.extend(10_000, total));
+        assert_eq!(frontier.next_chunk(), Some(0..15_000));
+        index_all(&mut frontier);
+        assert_eq!(frontier.runs(), 1);
+
+        // Moving about inside what's indexed asks for nothing more
+        for line in (0..14_000 - INDEX_MARGIN).chain((0..5000).rev()) {
+            assert!(!frontier.extend(line + INDEX_MARGIN, total));
+            assert_eq!(frontier.next_chunk(), None);
+        }
+        assert_eq!((frontier.runs(), frontier.rebuilds()), (1, 0));
+
+        // Asking again while chunks are on their way moves the target only
+        assert!(frontier.extend(20_000, total));
+        assert!(frontier.extend(20_100, total));
+        assert_eq!(frontier.runs(), 2);
+        assert_eq!(frontier.from(), 15_000);
+    }
+
+    #[test]
+    fn test_runs_grow_with_the_frontier() {
+        // Holding down a motion through a big file, a line at a time
+        let total = 10_000_000;
+        let mut frontier = IndexFrontier::new();
+        for line in 0..total {
+            if frontier.extend(line + INDEX_MARGIN, total) {
+                index_all(&mut frontier);
+            }
+        }
+        assert_eq!(frontier.indexed(), total);
+        assert!(frontier.runs() <= 12, "{} runs", frontier.runs());
+        assert_eq!(frontier.rebuilds(), 0);
+
+        frontier.reset();
+        assert_eq!((frontier.indexed(), frontier.rebuilds()), (0, 1));
+    }
+
+    #[test]
+    fn test_stop_and_truncate() {
+        let mut frontier = IndexFrontier::new();
+        frontier.extend(300_000, 1_000_000);
+        let chunk = frontier.next_chunk().unwrap();
+        assert_eq!(chunk, 0..INDEX_CHUNK_LINES);
+        frontier.advance(chunk.end);
+        frontier.stop();
+        assert_eq!(frontier.next_chunk(), None);
+        frontier.truncate(50_000);
+        assert_eq!(frontier.indexed(), 50_000);
+        assert!(frontier.covers(50_000, 50_000));
+    }
+}
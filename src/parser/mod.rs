@@ -3,9 +3,39 @@ pub mod tokenizer;
 pub mod parser_thread;
 pub mod node;
 pub mod structural_index;
+pub mod error;
+pub mod format;
+pub mod decode;
+pub mod schema;
+pub mod diff;
+pub mod dump;
+pub mod frontier;
+pub mod scan;
+pub mod dupkeys;
+pub mod window;
+pub mod yaml;
+pub mod shape;
 
-pub use token::Token;
+pub use token::{Token, TokenIssue};
 pub use tokenizer::Tokenizer;
-pub use parser_thread::ParserThread;
-pub use node::{NodeInfo, NodeKind};
-pub use structural_index::StructuralIndex;
+pub use parser_thread::{ChunkText, IndexRequest, ParserResponse, ParserThread};
+pub use node::{ChildCount, NodeInfo, NodeKind};
+pub use structural_index::{IndexChunk, IndexLimits, OpenContainers, Staleness, StructuralIndex};
+pub use error::{EscapeError, StructuralError, StructuralErrorKind};
+pub use format::DocumentFormat;
+pub use frontier::IndexFrontier;
+
+/// Items laid out as tests/generate_test_data.rs writes them, for tests
+#[cfg(test)]
+pub(crate) fn fixture(items: usize) -> String {
+    let mut text = String::from("[\n");
+    for i in 0..items {
+        text.push_str(&format!(
+            "  {{\n    \"id\": {},\n    \"name\": \"item_{}\",\n    \"data\": \"{}\",\n    \"timestamp\": {},\n    \"nested\": {{\n      \"level\": 1,\n      \"value\": {},\n      \"tags\": [\"tag1\", \"tag2\", \"tag3\"]\n    }}\n  }}",
+            i, i, "x".repeat(100), i * 1000, i * 2
+        ));
+        text.push_str(if i + 1 < items { ",\n" } else { "\n" });
+    }
+    text.push_str("]\n");
+    text
+}
@@ -39,13 +39,42 @@ pub enum ParseStatus {
 
 pub type NodeId = usize;
 
-#[derive(Debug, Clone)]
+/// Number of members in a container: items of an array, keys of an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChildCount {
+    pub count: usize,
+    /// False while the container's closer hasn't been indexed, in which case
+    /// `count` is only a lower bound
+    pub complete: bool,
+}
+
+impl fmt::Display for ChildCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Group digits in threes: 38112 -> 38,112
+        let digits = self.count.to_string();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", digit)?;
+        }
+        if !self.complete {
+            write!(f, "+")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct NodeInfo {
     pub kind: NodeKind,
     pub start: usize,        // byte offset (inclusive)
     pub end: usize,          // byte offset (exclusive)
-    pub depth: u8,           // nesting depth
+    pub depth: u32,          // nesting depth
     pub parent: Option<NodeId>,
+    pub first_child: Option<NodeId>,
+    pub next_sibling: Option<NodeId>,
+    pub prev_sibling: Option<NodeId>,
     pub status: ParseStatus,
 }
 
@@ -54,7 +83,7 @@ impl NodeInfo {
         kind: NodeKind,
         start: usize,
         end: usize,
-        depth: u8,
+        depth: u32,
         parent: Option<NodeId>,
     ) -> Self {
         Self {
@@ -63,6 +92,9 @@ impl NodeInfo {
             end,
             depth,
             parent,
+            first_child: None,
+            next_sibling: None,
+            prev_sibling: None,
             status: ParseStatus::Parsed,
         }
     }
@@ -75,6 +107,10 @@ impl NodeInfo {
         self.end - self.start
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
     pub fn is_container(&self) -> bool {
         matches!(self.kind, NodeKind::Object | NodeKind::Array)
     }
@@ -1,24 +1,75 @@
 use crossbeam::channel::{bounded, Sender, Receiver};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::ops::Range;
+use std::sync::Arc;
 use std::thread;
+use memmap2::Mmap;
 use crate::parser::{Tokenizer, Token};
+use crate::parser::node::NodeId;
+use crate::parser::scan::{fast_tokens, SCAN_THRESHOLD};
+use crate::parser::structural_index::{IndexChunk, OpenContainers, StructuralIndex};
+use crate::ui::perf;
 
 #[derive(Debug, Clone)]
 pub enum ParserMessage {
     Parse(String),  // JSON content to parse
+    Index(IndexRequest),  // Extend a structural index by one chunk
     Shutdown,
 }
 
+/// The text of a chunk: copied out of the buffer, or shared with it as a
+/// range of the file's memory map when no edit touches those lines
+#[derive(Debug, Clone)]
+pub enum ChunkText {
+    Owned(Vec<u8>),
+    Mapped(Arc<Mmap>, Range<usize>),
+}
+
+impl ChunkText {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ChunkText::Owned(text) => text,
+            ChunkText::Mapped(map, range) => &map[range.clone()],
+        }
+    }
+}
+
+/// A byte range of the document to index, plus the state needed to resume
+/// where the previous chunk stopped
+#[derive(Debug, Clone)]
+pub struct IndexRequest {
+    pub text: ChunkText,
+    pub base_offset: usize,      // absolute offset of text[0]
+    pub open: OpenContainers,    // containers left open by the previous chunk
+    pub first_id: NodeId,        // current length of the index being extended
+    pub records: bool,           // JSON Lines: every line is a separate record
+    pub generation: u64,         // echoed back so results for a discarded index can be ignored
+}
+
 #[derive(Debug, Clone)]
 pub enum ParserResponse {
     Tokens(Vec<Token>),
     Progress(f32),  // Progress percentage (0.0 to 1.0)
+    Indexed { chunk: Box<IndexChunk>, open: OpenContainers, generation: u64 },
+    /// The chunk was given up on after `cancel_index`
+    IndexCancelled { generation: u64 },
     Complete,
     Error(String),
 }
 
+/// Shared with the worker: how far it has got through the chunk being
+/// indexed, and whether to give up on it. Read every frame, so it's kept
+/// out of the response channel.
+#[derive(Debug, Default)]
+struct IndexControl {
+    bytes_done: AtomicUsize,
+    cancel: AtomicBool,
+}
+
 pub struct ParserThread {
     cmd_tx: Sender<ParserMessage>,
     resp_rx: Receiver<ParserResponse>,
+    control: Arc<IndexControl>,
     handle: Option<thread::JoinHandle<()>>,
 }
 
@@ -26,14 +77,17 @@ impl ParserThread {
     pub fn new() -> Self {
         let (cmd_tx, cmd_rx) = bounded::<ParserMessage>(100);
         let (resp_tx, resp_rx) = bounded::<ParserResponse>(100);
+        let control = Arc::new(IndexControl::default());
 
+        let worker_control = Arc::clone(&control);
         let handle = thread::spawn(move || {
-            Self::parser_worker(cmd_rx, resp_tx);
+            Self::parser_worker(cmd_rx, resp_tx, &worker_control);
         });
 
         Self {
             cmd_tx,
             resp_rx,
+            control,
             handle: Some(handle),
         }
     }
@@ -41,6 +95,7 @@ impl ParserThread {
     fn parser_worker(
         cmd_rx: Receiver<ParserMessage>,
         resp_tx: Sender<ParserResponse>,
+        control: &IndexControl,
     ) {
         loop {
             match cmd_rx.recv() {
@@ -69,6 +124,9 @@ impl ParserThread {
                     let _ = resp_tx.send(ParserResponse::Tokens(tokens));
                     let _ = resp_tx.send(ParserResponse::Complete);
                 }
+                Ok(ParserMessage::Index(request)) => {
+                    Self::index_chunk(request, &resp_tx, control);
+                }
                 Ok(ParserMessage::Shutdown) => {
                     break;
                 }
@@ -79,12 +137,79 @@ impl ParserThread {
         }
     }
 
+    fn index_chunk(request: IndexRequest, resp_tx: &Sender<ParserResponse>, control: &IndexControl) {
+        let IndexRequest { text, base_offset, mut open, first_id, records, generation } = request;
+        let mut timing = perf::scoped("index chunk");
+        timing.bytes(text.as_bytes().len());
+        
+        if records {
+            let mut chunk = IndexChunk::new(first_id);
+            chunk.push_records(text.as_bytes(), base_offset, &mut open);
+            let _ = resp_tx.send(ParserResponse::Indexed { chunk: Box::new(chunk), open, generation });
+            return;
+        }
+        
+        // Big chunks of strict JSON are scanned first, a block at a time,
+        // and only what the scan isn't sure of goes through the tokenizer
+        if !open.is_relaxed() && text.as_bytes().len() >= SCAN_THRESHOLD {
+            if let Some(tokens) = fast_tokens(text.as_bytes(), base_offset, open.depth()) {
+                control.bytes_done.store(text.as_bytes().len(), Ordering::Relaxed);
+                if control.cancel.load(Ordering::Relaxed) {
+                    let _ = resp_tx.send(ParserResponse::IndexCancelled { generation });
+                    return;
+                }
+                let chunk = StructuralIndex::build_chunk(&tokens, &mut open, first_id);
+                let _ = resp_tx.send(ParserResponse::Indexed { chunk: Box::new(chunk), open, generation });
+                return;
+            }
+        }
+        
+        let mut tokenizer = Tokenizer::resume(text.as_bytes(), base_offset, open.depth())
+            .with_comments(open.is_relaxed());
+        let mut tokens = Vec::new();
+        
+        while let Some(token) = tokenizer.next_token() {
+            tokens.push(token);
+            
+            if tokens.len() % 10_000 == 0 {
+                control.bytes_done.store(token.end - base_offset, Ordering::Relaxed);
+                if control.cancel.load(Ordering::Relaxed) {
+                    let _ = resp_tx.send(ParserResponse::IndexCancelled { generation });
+                    return;
+                }
+            }
+        }
+        
+        let chunk = StructuralIndex::build_chunk(&tokens, &mut open, first_id);
+        let _ = resp_tx.send(ParserResponse::Indexed { chunk: Box::new(chunk), open, generation });
+    }
+
     pub fn parse(&self, content: String) -> Result<(), String> {
         self.cmd_tx
             .send(ParserMessage::Parse(content))
             .map_err(|e| format!("Failed to send parse message: {}", e))
     }
 
+    /// Queue a chunk for indexing; the result arrives as `ParserResponse::Indexed`
+    pub fn index(&self, request: IndexRequest) -> Result<(), String> {
+        self.control.bytes_done.store(0, Ordering::Relaxed);
+        self.control.cancel.store(false, Ordering::Relaxed);
+        self.cmd_tx
+            .send(ParserMessage::Index(request))
+            .map_err(|e| format!("Failed to send index message: {}", e))
+    }
+
+    /// Bytes of the chunk being indexed that have been tokenized so far
+    pub fn index_bytes_done(&self) -> usize {
+        self.control.bytes_done.load(Ordering::Relaxed)
+    }
+
+    /// Ask the worker to drop the chunk being indexed; it answers with
+    /// `ParserResponse::IndexCancelled`, or `Indexed` if it had finished
+    pub fn cancel_index(&self) {
+        self.control.cancel.store(true, Ordering::Relaxed);
+    }
+
     pub fn try_recv_response(&self) -> Option<ParserResponse> {
         self.resp_rx.try_recv().ok()
     }
@@ -140,4 +265,75 @@ mod tests {
         
         parser.shutdown();
     }
+
+    #[test]
+    fn test_index_in_chunks() {
+        let first = "[{\"a\": 1},\n";
+        let second = "{\"b\": [2, 3]}]\n";
+        let json = format!("{}{}", first, second);
+        let mut tokenizer = Tokenizer::new(json.clone());
+        let expected = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        
+        // The first chunk read where it is in a mapped file, the second copied
+        let path = std::env::temp_dir().join(format!("jim_test_index_chunks_{}.json", std::process::id()));
+        std::fs::write(&path, &json).unwrap();
+        let map = Arc::new(unsafe { Mmap::map(&std::fs::File::open(&path).unwrap()).unwrap() });
+        std::fs::remove_file(&path).ok();
+        let chunks = [
+            (ChunkText::Mapped(Arc::clone(&map), 0..first.len()), 0),
+            (ChunkText::Owned(second.as_bytes().to_vec()), first.len()),
+        ];
+        
+        let parser = ParserThread::new();
+        let mut index = StructuralIndex::new();
+        let mut open = OpenContainers::default();
+        
+        for (text, base_offset) in chunks {
+            parser.index(IndexRequest {
+                text,
+                base_offset,
+                open: open.clone(),
+                first_id: index.len(),
+                records: false,
+                generation: 0,
+            }).unwrap();
+            
+            loop {
+                match parser.resp_rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+                    ParserResponse::Indexed { chunk, open: next_open, .. } => {
+                        assert!(index.apply_chunk(*chunk));
+                        open = next_open;
+                        break;
+                    }
+                    _ => continue,
+                }
+            }
+        }
+        
+        assert!(index.nodes().eq(expected.nodes()));
+        parser.shutdown();
+    }
+
+    #[test]
+    fn test_cancel_index() {
+        let parser = ParserThread::new();
+        let text = "[1, 2, 3],\n".repeat(100_000);
+        // Cancelled before the worker starts, so it stops at the first check
+        parser.control.cancel.store(true, Ordering::Relaxed);
+        parser.cmd_tx.send(ParserMessage::Index(IndexRequest {
+            text: ChunkText::Owned(text.into_bytes()),
+            base_offset: 0,
+            open: OpenContainers::default(),
+            first_id: 0,
+            records: false,
+            generation: 7,
+        })).unwrap();
+        
+        match parser.resp_rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            ParserResponse::IndexCancelled { generation } => assert_eq!(generation, 7),
+            other => panic!("expected a cancellation, got {:?}", other),
+        }
+        assert!(parser.index_bytes_done() > 0);
+        parser.shutdown();
+    }
 }
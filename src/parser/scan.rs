@@ -0,0 +1,441 @@
+//! A fast first pass over large documents. Rather than stepping through
+//! the text a byte at a time the way the tokenizer does, the scanner looks
+//! at 64 bytes at once, as bit masks: which are quotes, backslashes and
+//! structural characters. Carrying just two bits from one block to the
+//! next (inside a string or not, and whether the block ended on an
+//! unfinished escape), it finds which quotes are real and which brackets,
+//! colons and commas lie outside strings, without branching on the text.
+//! This is the first stage of simdjson, with the masks built eight bytes
+//! to a word rather than with explicit SIMD.
+//!
+//! `fast_tokens` turns what the scanner finds back into exactly the tokens
+//! the tokenizer would give, whitespace aside, handing the short stretches between
+//! structural characters (numbers, literals, whitespace) and strings with
+//! escapes in them to the tokenizer. Anything it isn't sure of, such as a
+//! stray byte outside a string, gives up, and the caller tokenizes the
+//! whole text instead, so errors are always reported the same way.
+
+use super::token::{Token, TokenKind};
+use super::Tokenizer;
+
+/// Text below this size is tokenized directly; the scan only pays off once
+/// there's enough of it
+pub const SCAN_THRESHOLD: usize = 1024 * 1024;
+
+/// A byte the scanner picks out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuralKind {
+    BraceOpen,
+    BraceClose,
+    BracketOpen,
+    BracketClose,
+    Colon,
+    Comma,
+    /// A quote that opens or closes a string, rather than an escaped one
+    Quote,
+    /// A backslash inside a string starting an escape
+    Escape,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StructuralChar {
+    pub offset: usize,
+    pub kind: StructuralKind,
+}
+
+/// Every structural character of `text`, in order: brackets, colons and
+/// commas outside strings, the quotes around strings and the backslashes
+/// beginning escapes in them
+pub fn scan_structure(text: &[u8]) -> Vec<StructuralChar> {
+    structure(text).collect()
+}
+
+/// The same, found as they're asked for, for text too big to hold them all
+pub fn structure(text: &[u8]) -> impl Iterator<Item = StructuralChar> + '_ {
+    Structure::new(text)
+}
+
+/// Masks of the bytes of a block that equal `quote`, `backslash` or one of
+/// the structural characters, worked out eight bytes at a time
+fn classify(block: &[u8; 64]) -> (u64, u64, u64) {
+    let (mut quote, mut backslash, mut structural) = (0u64, 0u64, 0u64);
+    for (i, word) in block.chunks_exact(8).enumerate() {
+        let word = u64::from_le_bytes(word.try_into().unwrap());
+        // '[' and ']' differ from '{' and '}' only in the 0x20 bit
+        let folded = word | 0x2020_2020_2020_2020;
+        let is_structural = equal_bytes(folded, b'{')
+            | equal_bytes(folded, b'}')
+            | equal_bytes(word, b':')
+            | equal_bytes(word, b',');
+        quote |= pack(equal_bytes(word, b'"')) << (i * 8);
+        backslash |= pack(equal_bytes(word, b'\\')) << (i * 8);
+        structural |= pack(is_structural) << (i * 8);
+    }
+    (quote, backslash, structural)
+}
+
+/// The top bit of each byte of `word` that equals `byte`
+fn equal_bytes(word: u64, byte: u8) -> u64 {
+    const LOW: u64 = 0x7F7F_7F7F_7F7F_7F7F;
+    let x = word ^ (0x0101_0101_0101_0101 * byte as u64);
+    // A byte's top bit ends up clear only where the byte was zero
+    !(((x & LOW) + LOW) | x) & !LOW
+}
+
+/// Gather the top bits of the bytes of `word` into its low eight bits
+fn pack(high_bits: u64) -> u64 {
+    (high_bits >> 7).wrapping_mul(0x0102_0408_1020_4080) >> 56
+}
+
+/// The bytes escaped by a backslash before them. A run of backslashes
+/// escapes every other one, and its last one the byte after, if the run
+/// is odd; `carry` says the previous block ended on such a run.
+fn escaped(backslash: u64, carry: &mut u64) -> u64 {
+    const EVEN: u64 = 0x5555_5555_5555_5555;
+    let backslash = backslash & !*carry;
+    let follows_escape = (backslash << 1) | *carry;
+    // Runs starting on an odd bit; adding the run to its start carries
+    // past its end, flipping the parity of the bits after
+    let odd_starts = backslash & !EVEN & !follows_escape;
+    let (even_starts, overflow) = odd_starts.overflowing_add(backslash);
+    *carry = overflow as u64;
+    let invert = even_starts << 1;
+    (EVEN ^ invert) & follows_escape
+}
+
+/// Each bit set when an odd number of bits at or below it are: with the
+/// quotes, the bytes inside strings (with the opening quote but not the
+/// closing one)
+fn prefix_xor(mut bits: u64) -> u64 {
+    bits ^= bits << 1;
+    bits ^= bits << 2;
+    bits ^= bits << 4;
+    bits ^= bits << 8;
+    bits ^= bits << 16;
+    bits ^= bits << 32;
+    bits
+}
+
+/// The scan as an iterator, a block at a time
+struct Structure<'a> {
+    text: &'a [u8],
+    /// Start of the block `pending` belongs to
+    block: usize,
+    /// Structural characters of the block not yet returned
+    pending: u64,
+    next_block: usize,
+    /// All ones when the block before ended inside a string
+    in_string: u64,
+    /// 1 when the block before ended on an unfinished escape
+    escape_carry: u64,
+}
+
+impl<'a> Structure<'a> {
+    fn new(text: &'a [u8]) -> Self {
+        Self { text, block: 0, pending: 0, next_block: 0, in_string: 0, escape_carry: 0 }
+    }
+
+    /// Work out the masks of the next block; false at the end of the text
+    fn load_block(&mut self) -> bool {
+        let start = self.next_block;
+        if start >= self.text.len() {
+            return false;
+        }
+        let end = (start + 64).min(self.text.len());
+        let (quote, backslash, structural) = match self.text[start..end].try_into() {
+            Ok(block) => classify(block),
+            Err(_) => {
+                // The last, short block, padded out with spaces
+                let mut block = [b' '; 64];
+                block[..end - start].copy_from_slice(&self.text[start..end]);
+                classify(&block)
+            }
+        };
+        let escaped = escaped(backslash, &mut self.escape_carry);
+        let quote = quote & !escaped;
+        let in_string = prefix_xor(quote) ^ self.in_string;
+        self.in_string = ((in_string as i64) >> 63) as u64;
+
+        let escapes = backslash & !escaped & in_string;
+        self.pending = (structural & !in_string) | quote | escapes;
+        self.block = start;
+        self.next_block = end;
+        true
+    }
+
+    /// Offset of the next structural character
+    fn next_offset(&mut self) -> Option<usize> {
+        while self.pending == 0 {
+            if !self.load_block() {
+                return None;
+            }
+        }
+        let offset = self.block + self.pending.trailing_zeros() as usize;
+        self.pending &= self.pending - 1;
+        Some(offset)
+    }
+}
+
+impl Iterator for Structure<'_> {
+    type Item = StructuralChar;
+
+    fn next(&mut self) -> Option<StructuralChar> {
+        let offset = self.next_offset()?;
+        let kind = match self.text[offset] {
+            b'{' => StructuralKind::BraceOpen,
+            b'}' => StructuralKind::BraceClose,
+            b'[' => StructuralKind::BracketOpen,
+            b']' => StructuralKind::BracketClose,
+            b':' => StructuralKind::Colon,
+            b',' => StructuralKind::Comma,
+            b'"' => StructuralKind::Quote,
+            _ => StructuralKind::Escape,
+        };
+        Some(StructuralChar { offset, kind })
+    }
+}
+
+/// The tokens the tokenizer would give for `text`, starting at absolute
+/// byte `base_offset` inside `depth` open containers, found by scanning
+/// first. Whitespace is left out, as the index has no use for it. None
+/// when the text has something only the tokenizer should deal with: a
+/// byte that can't start a token, an unterminated string or a literal
+/// running into a bracket. Comments aren't recognized.
+pub fn fast_tokens(text: &[u8], base_offset: usize, mut depth: u32) -> Option<Vec<Token>> {
+    let mut tokens = Vec::with_capacity(text.len() / 4);
+    let mut structure = Structure::new(text);
+    let mut pos = 0;
+    while let Some(offset) = structure.next_offset() {
+        gap_tokens(text, pos..offset, base_offset, depth, &mut tokens)?;
+        let start = base_offset + offset;
+        let single = |kind, depth| Token::new(kind, start, start + 1, depth);
+        let token = match text[offset] {
+            b'{' => {
+                depth += 1;
+                single(TokenKind::BraceOpen, depth - 1)
+            }
+            b'[' => {
+                depth += 1;
+                single(TokenKind::BracketOpen, depth - 1)
+            }
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                single(TokenKind::BraceClose, depth)
+            }
+            b']' => {
+                depth = depth.saturating_sub(1);
+                single(TokenKind::BracketClose, depth)
+            }
+            b':' => single(TokenKind::Colon, depth),
+            b',' => single(TokenKind::Comma, depth),
+            b'"' => {
+                // Up to the closing quote; escapes in between need checking
+                let mut has_escape = false;
+                let end = loop {
+                    let next = structure.next_offset()?;
+                    match text[next] {
+                        b'"' => break next + 1,
+                        _ => has_escape = true,
+                    }
+                };
+                if has_escape {
+                    Tokenizer::resume(&text[offset..end], start, depth).next_token()?
+                } else {
+                    Token::new(TokenKind::String, start, base_offset + end, depth)
+                }
+            }
+            // Escapes are only found inside strings, which the arm above
+            // steps over
+            _ => return None,
+        };
+        pos = token.end - base_offset;
+        tokens.push(token);
+    }
+    gap_tokens(text, pos..text.len(), base_offset, depth, &mut tokens)?;
+    Some(tokens)
+}
+
+/// Tokens between two structural characters: whitespace, numbers and
+/// literals, which the tokenizer reads the same here as in the whole text,
+/// unless it finds something invalid
+fn gap_tokens(text: &[u8], gap: std::ops::Range<usize>, base_offset: usize, depth: u32, tokens: &mut Vec<Token>) -> Option<()> {
+    if gap.is_empty() {
+        return Some(());
+    }
+    // Nearly every gap is spaces, or a plain number or literal between them
+    let is_space = |b: &u8| matches!(b, b' ' | b'\n' | b'\r' | b'\t');
+    let bytes = &text[gap.clone()];
+    let Some(first) = bytes.iter().position(|b| !is_space(b)) else {
+        return Some(());
+    };
+    let last = bytes.iter().rposition(|b| !is_space(b)).unwrap_or(first);
+    if let Some(kind) = scalar_kind(&bytes[first..=last]) {
+        let start = base_offset + gap.start;
+        tokens.push(Token::new(kind, start + first, start + last + 1, depth));
+        return Some(());
+    }
+    let mut tokenizer = Tokenizer::resume(&text[gap.clone()], base_offset + gap.start, depth);
+    while let Some(token) = tokenizer.next_token() {
+        match token.kind {
+            TokenKind::Invalid => return None,
+            TokenKind::Whitespace => {}
+            _ => tokens.push(token),
+        }
+    }
+    Some(())
+}
+
+/// What `text` is if it's exactly one literal or one number written the
+/// way JSON spells them, which the tokenizer reads as a single token
+fn scalar_kind(text: &[u8]) -> Option<TokenKind> {
+    match text {
+        b"true" => return Some(TokenKind::True),
+        b"false" => return Some(TokenKind::False),
+        b"null" => return Some(TokenKind::Null),
+        _ => {}
+    }
+    let digits = |text: &[u8]| text.iter().take_while(|b| b.is_ascii_digit()).count();
+    let mut rest = text.strip_prefix(b"-").unwrap_or(text);
+    match digits(rest) {
+        0 => return None,
+        n if n > 1 && rest[0] == b'0' => return None,
+        n => rest = &rest[n..],
+    }
+    if let Some(fraction) = rest.strip_prefix(b".") {
+        match digits(fraction) {
+            0 => return None,
+            n => rest = &fraction[n..],
+        }
+    }
+    if let Some(exponent) = rest.strip_prefix(b"e").or_else(|| rest.strip_prefix(b"E")) {
+        let exponent = exponent.strip_prefix(b"+").or_else(|| exponent.strip_prefix(b"-")).unwrap_or(exponent);
+        match digits(exponent) {
+            0 => return None,
+            n => rest = &exponent[n..],
+        }
+    }
+    rest.is_empty().then_some(TokenKind::Number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::StructuralIndex;
+    use proptest::prelude::*;
+
+    fn tokenize(text: &[u8], base_offset: usize, depth: u32) -> Vec<Token> {
+        Tokenizer::resume(text, base_offset, depth)
+            .tokenize_all()
+            .into_iter()
+            .filter(|token| token.kind != TokenKind::Whitespace)
+            .collect()
+    }
+
+    /// The scan either gives up or agrees with the tokenizer exactly
+    fn check(text: &[u8]) -> bool {
+        match fast_tokens(text, 7, 2) {
+            Some(tokens) => {
+                assert_eq!(tokens, tokenize(text, 7, 2), "for {:?}", String::from_utf8_lossy(text));
+                true
+            }
+            None => false,
+        }
+    }
+
+    const FIXTURES: &[&str] = &[
+        "",
+        "   \n",
+        r#"{"key": "value", "n": -12.5e+3, "list": [true, false, null, {}], "nested": {"a": [[]]}}"#,
+        r#"["quote \" inside", "backslash \\", "\\\"", "unicode é 😀", "bad \x escape", "\u12"]"#,
+        "[\"caf\u{e9}\", \"\u{65e5}\u{672c}\", 1]",
+        r#"{"a":1,"b":[1,2,3],"c":"x,y:{z}"}"#,
+        "[1, 2,\n 3]\n",
+        "[1 2 \"a\" \"b\"]",
+        "{\"unclosed\": [1, 2",
+        "]]}} extra closers",
+        "[01, 1., -, 1e, 1e+]",
+    ];
+
+    #[test]
+    fn test_fixtures() {
+        for fixture in FIXTURES {
+            check(fixture.as_bytes());
+        }
+        // Valid documents never need the fallback
+        for fixture in &FIXTURES[..8] {
+            assert!(check(fixture.as_bytes()), "fell back on {}", fixture);
+        }
+        // A stray byte, an unterminated string, a literal into a bracket
+        assert!(!check(b"[1, x]"));
+        assert!(!check(b"[\"open"));
+        assert!(!check(b"[tru]"));
+    }
+
+    #[test]
+    fn test_scan_structure() {
+        let text = br#"{"a\"b": [1, "x\\"], "c": "{,}"}"#;
+        let kinds: String = scan_structure(text)
+            .iter()
+            .map(|c| match c.kind {
+                StructuralKind::Quote => '"',
+                StructuralKind::Escape => '\\',
+                _ => text[c.offset] as char,
+            })
+            .collect();
+        assert_eq!(kinds, r#"{"\":[,"\"],"":""}"#);
+    }
+
+    #[test]
+    fn test_block_boundaries() {
+        // Strings and runs of backslashes across the 64-byte blocks, at
+        // every alignment
+        for pad in 0..70 {
+            for slashes in 0..5 {
+                let text = format!("[{}\"{}{}\", 1]", " ".repeat(pad), "a".repeat(pad % 7), "\\\\".repeat(slashes));
+                assert!(check(text.as_bytes()), "fell back on {}", text);
+                let text = format!("[{}\"{}\\\"\", \"]\"]", " ".repeat(pad), "\\\\".repeat(slashes));
+                assert!(check(text.as_bytes()), "fell back on {}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_document() {
+        let record = "  {\"id\": 1, \"name\": \"it\\\"em\", \"tags\": [\"a\", \"b\"], \"price\": 19.99, \"ok\": true},\n";
+        let text = format!("[\n{}  null\n]\n", record.repeat(20_000));
+        let tokens = fast_tokens(text.as_bytes(), 0, 0).unwrap();
+        let expected = tokenize(text.as_bytes(), 0, 0);
+        assert_eq!(tokens, expected);
+        assert!(StructuralIndex::from_tokens(&tokens).nodes().eq(StructuralIndex::from_tokens(&expected).nodes()));
+    }
+
+    fn json_value() -> impl Strategy<Value = serde_json::Value> {
+        let leaf = prop_oneof![
+            Just(serde_json::Value::Null),
+            any::<bool>().prop_map(serde_json::Value::Bool),
+            any::<i64>().prop_map(|n| n.into()),
+            any::<f64>().prop_filter("finite", |f| f.is_finite()).prop_map(|f| f.into()),
+            ".{0,12}".prop_map(serde_json::Value::String),
+        ];
+        leaf.prop_recursive(4, 64, 6, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..6).prop_map(serde_json::Value::Array),
+                prop::collection::btree_map(".{0,6}", inner, 0..6)
+                    .prop_map(|map| serde_json::Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_valid_documents(value in json_value(), pretty in any::<bool>()) {
+            let text = if pretty { serde_json::to_string_pretty(&value) } else { serde_json::to_string(&value) }.unwrap();
+            prop_assert!(check(text.as_bytes()), "fell back on {}", text);
+        }
+
+        #[test]
+        fn test_any_bytes(text in "[\\[\\]{}:,\" \\\\\\nabtruefalsn0-9.eE+\\-\u{e9}]{0,150}") {
+            check(text.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! JSON Schema validation, for a draft-07 subset: `type`, `required`,
+//! `properties`, `items`, `enum`, `minimum` and `maximum`. Other keywords
+//! are ignored. The document is walked through its structural index, so
+//! every violation comes with the byte span of the value at fault.
+
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::decode;
+use super::node::NodeId;
+use super::token::TokenKind;
+use super::{NodeKind, StructuralIndex, Token, Tokenizer};
+use crate::buffer::TextChange;
+
+/// Deepest nesting `Value::parse` takes; parsing recurses, so deeper
+/// text is refused rather than risking the stack
+pub const MAX_VALUE_DEPTH: usize = 256;
+
+/// A JSON value, for schemas and `enum` comparisons
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Parse one JSON value; comments are allowed, trailing text isn't
+    pub fn parse(text: &str) -> Result<Self> {
+        let tokens: Vec<Token> = Tokenizer::new(text.to_string())
+            .with_comments(true)
+            .tokenize_all()
+            .into_iter()
+            .filter(|t| !matches!(t.kind, TokenKind::Whitespace | TokenKind::Comment))
+            .collect();
+        let mut tokens = tokens.iter().peekable();
+        let value = Self::parse_tokens(text, &mut tokens, 0)?;
+        if let Some(extra) = tokens.next() {
+            bail!("Unexpected {} at byte {}", extra.kind, extra.start);
+        }
+        Ok(value)
+    }
+
+    fn parse_tokens<'a>(text: &str, tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a Token>>, depth: usize) -> Result<Self> {
+        let token = tokens.next().ok_or_else(|| anyhow!("Unexpected end of JSON"))?;
+        if depth >= MAX_VALUE_DEPTH && matches!(token.kind, TokenKind::BracketOpen | TokenKind::BraceOpen) {
+            bail!("Nested deeper than {} levels at byte {}", MAX_VALUE_DEPTH, token.start);
+        }
+        let literal = &text[token.start..token.end];
+        let value = match token.kind {
+            TokenKind::Null => Value::Null,
+            TokenKind::True => Value::Bool(true),
+            TokenKind::False => Value::Bool(false),
+            TokenKind::Number => Value::Number(decode::decode_number(literal).ok_or_else(|| anyhow!("Bad number {}", literal))?.as_f64()),
+            TokenKind::String => Value::String(decode::decode_string(literal).map_err(|e| anyhow!("Bad string {}: {}", literal, e))?),
+            TokenKind::BracketOpen => {
+                let mut items = Vec::new();
+                if tokens.next_if(|t| t.kind == TokenKind::BracketClose).is_none() {
+                    loop {
+                        items.push(Self::parse_tokens(text, tokens, depth + 1)?);
+                        match tokens.next().map(|t| t.kind) {
+                            Some(TokenKind::Comma) => continue,
+                            Some(TokenKind::BracketClose) => break,
+                            _ => bail!("Expected , or ] in array"),
+                        }
+                    }
+                }
+                Value::Array(items)
+            }
+            TokenKind::BraceOpen => {
+                let mut members = Vec::new();
+                if tokens.next_if(|t| t.kind == TokenKind::BraceClose).is_none() {
+                    loop {
+                        let key = match Self::parse_tokens(text, tokens, depth + 1)? {
+                            Value::String(key) => key,
+                            _ => bail!("Expected a string key in object"),
+                        };
+                        if tokens.next().map(|t| t.kind) != Some(TokenKind::Colon) {
+                            bail!("Expected : after key \"{}\"", key);
+                        }
+                        members.push((key, Self::parse_tokens(text, tokens, depth + 1)?));
+                        match tokens.next().map(|t| t.kind) {
+                            Some(TokenKind::Comma) => continue,
+                            Some(TokenKind::BraceClose) => break,
+                            _ => bail!("Expected , or }} in object"),
+                        }
+                    }
+                }
+                Value::Object(members)
+            }
+            kind => bail!("Unexpected {} at byte {}", kind, token.start),
+        };
+        Ok(value)
+    }
+
+    /// The member `key` of an object
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+/// One compiled schema (or subschema)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    /// `false`: nothing is valid here
+    reject: bool,
+    types: Option<Vec<String>>,
+    required: Vec<String>,
+    properties: Vec<(String, Schema)>,
+    items: Option<Box<Schema>>,
+    allowed: Option<Vec<Value>>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+}
+
+impl Schema {
+    /// Compile a schema from its JSON text
+    pub fn parse(text: &str) -> Result<Self> {
+        Self::from_value(&Value::parse(text)?)
+    }
+
+    fn from_value(value: &Value) -> Result<Self> {
+        let members = match value {
+            Value::Bool(allow) => return Ok(Self { reject: !allow, ..Self::default() }),
+            Value::Object(_) => value,
+            _ => bail!("A schema must be an object or a boolean"),
+        };
+        let types = match members.get("type") {
+            None => None,
+            Some(Value::String(name)) => Some(vec![name.clone()]),
+            Some(Value::Array(names)) => Some(names.iter().map(|name| match name {
+                Value::String(name) => Ok(name.clone()),
+                _ => bail!("\"type\" names must be strings"),
+            }).collect::<Result<_>>()?),
+            Some(_) => bail!("\"type\" must be a string or an array of strings"),
+        };
+        let required = match members.get("required") {
+            None => Vec::new(),
+            Some(Value::Array(names)) => names.iter().map(|name| match name {
+                Value::String(name) => Ok(name.clone()),
+                _ => bail!("\"required\" names must be strings"),
+            }).collect::<Result<_>>()?,
+            Some(_) => bail!("\"required\" must be an array"),
+        };
+        let properties = match members.get("properties") {
+            None => Vec::new(),
+            Some(Value::Object(properties)) => properties.iter()
+                .map(|(name, schema)| Ok((name.clone(), Self::from_value(schema)?)))
+                .collect::<Result<_>>()?,
+            Some(_) => bail!("\"properties\" must be an object"),
+        };
+        let items = members.get("items").map(Self::from_value).transpose()?.map(Box::new);
+        let allowed = match members.get("enum") {
+            None => None,
+            Some(Value::Array(values)) => Some(values.clone()),
+            Some(_) => bail!("\"enum\" must be an array"),
+        };
+        let number = |keyword| match members.get(keyword) {
+            None => Ok(None),
+            Some(Value::Number(n)) => Ok(Some(*n)),
+            Some(_) => Err(anyhow!("\"{}\" must be a number", keyword)),
+        };
+        Ok(Self {
+            reject: false,
+            types,
+            required,
+            properties,
+            items,
+            allowed,
+            minimum: number("minimum")?,
+            maximum: number("maximum")?,
+        })
+    }
+}
+
+/// A value that breaks the schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+/// Check every top-level value of the indexed `text` against `schema`.
+/// Diagnostics come back in document order.
+pub fn validate(schema: &Schema, index: &StructuralIndex, text: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut pending: Vec<(NodeId, &Schema)> = (0..index.root_count())
+        .rev()
+        .filter_map(|n| index.nth_root(n))
+        .map(|root| (root, schema))
+        .collect();
+    while let Some((id, schema)) = pending.pop() {
+        let Some(node) = index.get(id) else { continue };
+        let literal = &text[node.start.min(text.len())..node.end.min(text.len())];
+        let mut report = |message: String| diagnostics.push(Diagnostic { span: node.start..node.end, message });
+        if schema.reject {
+            report("No value is allowed here".to_string());
+            continue;
+        }
+        if let Some(types) = &schema.types {
+            let found = type_name(node.kind, literal);
+            let matches = types.iter().any(|t| t == found || t == "number" && found == "integer");
+            if !matches {
+                report(format!("Expected {}, found {}", types.join(" or "), found));
+                continue;
+            }
+        }
+        if let Some(allowed) = &schema.allowed {
+            if !Value::parse(literal).is_ok_and(|value| allowed.contains(&value)) {
+                report("Not one of the allowed values".to_string());
+            }
+        }
+        if node.kind == NodeKind::Number {
+            let value = decode::decode_number(literal).map_or(f64::NAN, |n| n.as_f64());
+            if let Some(minimum) = schema.minimum.filter(|&min| value < min) {
+                report(format!("{} is less than the minimum {}", literal, minimum));
+            }
+            if let Some(maximum) = schema.maximum.filter(|&max| value > max) {
+                report(format!("{} is more than the maximum {}", literal, maximum));
+            }
+        }
+        match node.kind {
+            NodeKind::Object => {
+                let members: Vec<(String, NodeId)> = index.member_iter(id)
+                    .filter_map(|(key, value)| {
+                        let key = index.get(key)?;
+                        let name = decode::decode_string(&text[key.start..key.end.min(text.len())]).ok()?;
+                        Some((name, value?))
+                    })
+                    .collect();
+                for name in &schema.required {
+                    if !members.iter().any(|(key, _)| key == name) {
+                        let brace = node.start..node.start + 1;
+                        diagnostics.push(Diagnostic { span: brace, message: format!("Missing required property \"{}\"", name) });
+                    }
+                }
+                for (name, value) in members.iter().rev() {
+                    if let Some((_, property)) = schema.properties.iter().find(|(key, _)| key == name) {
+                        pending.push((*value, property));
+                    }
+                }
+            }
+            NodeKind::Array => {
+                if let Some(items) = &schema.items {
+                    let children: Vec<NodeId> = index.child_iter(id).collect();
+                    pending.extend(children.into_iter().rev().map(|child| (child, items.as_ref())));
+                }
+            }
+            _ => {}
+        }
+    }
+    diagnostics.sort_by_key(|d| d.span.start);
+    diagnostics
+}
+
+/// The schema type a node is, with whole numbers as integers
+fn type_name(kind: NodeKind, literal: &str) -> &'static str {
+    match kind {
+        NodeKind::Object => "object",
+        NodeKind::Array => "array",
+        NodeKind::String | NodeKind::Key => "string",
+        NodeKind::Number if decode::decode_number(literal).is_some_and(|n| n.is_integer()) => "integer",
+        NodeKind::Number => "number",
+        NodeKind::Boolean => "boolean",
+        NodeKind::Null => "null",
+        NodeKind::Unknown | NodeKind::Error => "invalid JSON",
+    }
+}
+
+/// Index and validate `text` from scratch, for a background thread
+pub fn validate_text(schema: &Schema, text: &str) -> Vec<Diagnostic> {
+    let tokens = Tokenizer::new(text.to_string()).with_comments(true).tokenize_all();
+    validate(schema, &StructuralIndex::from_tokens(&tokens), text)
+}
+
+/// Keep diagnostics in place across an edit: those after it shift, those
+/// it touches are dropped until the next validation
+pub fn apply_change(diagnostics: &mut Vec<Diagnostic>, change: &TextChange) {
+    diagnostics.retain_mut(|d| match change.shift_span(&d.span) {
+        Some(span) => {
+            d.span = span;
+            true
+        }
+        None => false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"{
+        "type": "object",
+        "required": ["id", "name"],
+        "properties": {
+            "id": {"type": "integer", "minimum": 1},
+            "name": {"type": "string"},
+            "tags": {"type": "array", "items": {"enum": ["a", "b"]}},
+            "score": {"type": ["number", "null"], "maximum": 10}
+        }
+    }"#;
+
+    fn check(text: &str) -> Vec<(String, String)> {
+        let schema = Schema::parse(SCHEMA).unwrap();
+        validate_text(&schema, text)
+            .into_iter()
+            .map(|d| (text[d.span].to_string(), d.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid() {
+        assert!(check(r#"{"id": 3, "name": "x", "tags": ["a", "b"], "score": 9.5}"#).is_empty());
+        assert!(check(r#"{"id": 1, "name": "", "score": null, "other": [1]}"#).is_empty());
+        // One document per line, each checked
+        assert!(check("{\"id\": 1, \"name\": \"a\"}\n{\"id\": 2, \"name\": \"b\"}").is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        let found = check(r#"{"id": 0, "name": 5, "tags": ["a", "c"], "score": 11}"#);
+        let expected = [
+            ("0", "0 is less than the minimum 1"),
+            ("5", "Expected string, found integer"),
+            ("\"c\"", "Not one of the allowed values"),
+            ("11", "11 is more than the maximum 10"),
+        ];
+        let expected: Vec<(String, String)> = expected.iter().map(|&(a, b)| (a.to_string(), b.to_string())).collect();
+        assert_eq!(found, expected);
+
+        assert_eq!(check(r#"{"id": 2.5}"#), vec![
+            ("{".to_string(), "Missing required property \"name\"".to_string()),
+            ("2.5".to_string(), "Expected integer, found number".to_string()),
+        ]);
+        assert_eq!(check("[1]"), vec![("[1]".to_string(), "Expected object, found array".to_string())]);
+        let reject = Schema::parse(r#"{"items": false}"#).unwrap();
+        assert_eq!(validate_text(&reject, "[1, 2]").len(), 2);
+        assert!(Schema::parse(r#"{"type": 3}"#).is_err());
+        assert!(Schema::parse(r#"{"type": "object",}"#).is_err());
+        // Too deep to parse by recursion: refused, not a stack overflow
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert!(Value::parse(&deep).is_err());
+        let nested = format!("{}{}", "[".repeat(MAX_VALUE_DEPTH), "]".repeat(MAX_VALUE_DEPTH));
+        assert!(Value::parse(&nested).is_ok());
+    }
+
+    #[test]
+    fn test_apply_change() {
+        let diagnostic = |start, end| Diagnostic { span: start..end, message: String::new() };
+        let mut diagnostics = vec![diagnostic(0, 2), diagnostic(5, 8), diagnostic(10, 12)];
+        apply_change(&mut diagnostics, &TextChange { offset: 6, removed: 1, inserted: 3 });
+        assert_eq!(diagnostics, vec![diagnostic(0, 2), diagnostic(12, 14)]);
+        apply_change(&mut diagnostics, &TextChange { offset: 2, removed: 0, inserted: 1 });
+        assert_eq!(diagnostics, vec![diagnostic(0, 2), diagnostic(13, 15)]);
+    }
+}
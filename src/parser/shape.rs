@@ -0,0 +1,195 @@
+//! The document's top-level shape, shown once it's loaded and by Ctrl-g:
+//! `Array of 1,204,133 Objects`, `Object with 14 keys`. The root is told by
+//! its first byte, and its members counted from the commas (or colons)
+//! one level inside it, found by the scanner rather than the tokenizer, so
+//! a file of several GB takes well under a second. An array's members are
+//! told apart by the first byte after each comma, which is all it takes to
+//! see that they're all objects. A JSON Lines file is counted in records.
+
+use std::sync::atomic::Ordering;
+
+use super::scan::{self, StructuralKind};
+use super::NodeKind;
+use crate::ui::format::NumberFormat;
+use crate::ui::tasks::Progress;
+
+/// How often the scan reports progress and looks to see if it's cancelled
+const REPORT_EVERY: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// With the kind all its elements have, if they agree
+    Array { elements: usize, of: Option<NodeKind> },
+    Object { keys: usize },
+    /// A string, number, literal or something that isn't JSON
+    Scalar(NodeKind),
+    /// Non-blank lines of a JSON Lines file
+    Records(usize),
+}
+
+impl Shape {
+    /// `Array of 1,204,133 Objects`, counted as `numbers` groups digits
+    pub fn describe(&self, numbers: NumberFormat) -> String {
+        let plural = |n: usize, one: &str| if n == 1 { one.to_string() } else { format!("{}s", one) };
+        match *self {
+            Shape::Array { elements: 0, .. } => "empty Array".to_string(),
+            Shape::Array { elements, of: Some(kind) } => {
+                format!("Array of {} {}", numbers.count(elements), plural(elements, &kind.to_string()))
+            }
+            Shape::Array { elements, of: None } => format!("Array of {} {}", numbers.count(elements), plural(elements, "value")),
+            Shape::Object { keys } => format!("Object with {} {}", numbers.count(keys), plural(keys, "key")),
+            Shape::Scalar(kind) => kind.to_string(),
+            Shape::Records(records) => format!("{} {}", numbers.count(records), plural(records, "record")),
+        }
+    }
+}
+
+/// What a value starting with `byte` is
+fn kind_of(byte: u8) -> NodeKind {
+    match byte {
+        b'{' => NodeKind::Object,
+        b'[' => NodeKind::Array,
+        b'"' => NodeKind::String,
+        b'-' | b'0'..=b'9' => NodeKind::Number,
+        b't' | b'f' => NodeKind::Boolean,
+        b'n' => NodeKind::Null,
+        _ => NodeKind::Unknown,
+    }
+}
+
+/// The first byte at or after `from` that isn't whitespace
+fn next_byte(text: &[u8], from: usize) -> Option<u8> {
+    text.get(from..)?.iter().copied().find(|byte| !byte.is_ascii_whitespace())
+}
+
+/// The shape of the JSON document `text`. None for a blank one, or when
+/// cancelled. A root left unclosed is counted as far as it goes.
+pub fn detect(text: &[u8], progress: &Progress) -> Option<Shape> {
+    progress.total.store(text.len(), Ordering::Relaxed);
+    let start = text.iter().position(|byte| !byte.is_ascii_whitespace())?;
+    let array = match text[start] {
+        b'[' => true,
+        b'{' => false,
+        byte => return Some(Shape::Scalar(kind_of(byte))),
+    };
+    let mut members = 0;
+    let mut of: Option<Option<NodeKind>> = None;
+    // An element starts after the bracket and each comma, unless the
+    // array ends there instead (empty, or a trailing comma)
+    let mut element_at = |members: &mut usize, from: usize| match next_byte(text, from) {
+        None | Some(b']') => {}
+        Some(byte) => {
+            *members += 1;
+            let kind = kind_of(byte);
+            of = Some(match of {
+                None => Some(kind),
+                Some(of) => of.filter(|&of| of == kind),
+            });
+        }
+    };
+    if array {
+        element_at(&mut members, start + 1);
+    }
+    let mut depth = 0usize;
+    let mut report = REPORT_EVERY;
+    for ch in scan::structure(&text[start..]) {
+        if ch.offset >= report {
+            progress.done.store(start + ch.offset, Ordering::Relaxed);
+            if progress.is_cancelled() {
+                return None;
+            }
+            report = ch.offset + REPORT_EVERY;
+        }
+        match ch.kind {
+            StructuralKind::BraceOpen | StructuralKind::BracketOpen => depth += 1,
+            StructuralKind::BraceClose | StructuralKind::BracketClose => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            StructuralKind::Comma if depth == 1 && array => element_at(&mut members, start + ch.offset + 1),
+            StructuralKind::Colon if depth == 1 && !array => members += 1,
+            _ => {}
+        }
+    }
+    progress.done.store(text.len(), Ordering::Relaxed);
+    Some(match array {
+        true => Shape::Array { elements: members, of: of.flatten() },
+        false => Shape::Object { keys: members },
+    })
+}
+
+/// The records of the JSON Lines text `text`: its lines that aren't blank.
+/// None when cancelled.
+pub fn count_records(text: &[u8], progress: &Progress) -> Option<Shape> {
+    progress.total.store(text.len(), Ordering::Relaxed);
+    let mut records = 0;
+    // Whether the line so far has anything but whitespace on it
+    let mut content = false;
+    for (i, block) in text.chunks(REPORT_EVERY).enumerate() {
+        if progress.is_cancelled() {
+            return None;
+        }
+        progress.done.store(i * REPORT_EVERY, Ordering::Relaxed);
+        for &byte in block {
+            if byte == b'\n' {
+                records += usize::from(content);
+                content = false;
+            } else if !byte.is_ascii_whitespace() {
+                content = true;
+            }
+        }
+    }
+    progress.done.store(text.len(), Ordering::Relaxed);
+    Some(Shape::Records(records + usize::from(content)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(text: &str) -> Option<String> {
+        detect(text.as_bytes(), &Progress::default()).map(|shape| shape.describe(NumberFormat::Comma))
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(shape("[{\"a\": [1, 2]}, {\"b\": \"x,y\"}, {}]").unwrap(), "Array of 3 Objects");
+        assert_eq!(shape("\n  [1, \"two\", 3]").unwrap(), "Array of 3 values");
+        assert_eq!(shape("[[1], [2, 3]]").unwrap(), "Array of 2 Arrays");
+        assert_eq!(shape("[ null ]").unwrap(), "Array of 1 Null");
+        assert_eq!(shape("[1, 2, ]").unwrap(), "Array of 2 Numbers");
+        assert_eq!(shape("[ ]").unwrap(), "empty Array");
+        assert_eq!(shape("{\"a\": {\"b\": 1, \"c\": 2}, \"d:e\": [3]}").unwrap(), "Object with 2 keys");
+        assert_eq!(shape("{\"only\": true}").unwrap(), "Object with 1 key");
+        assert_eq!(shape("  \"text\"").unwrap(), "String");
+        assert_eq!(shape(" \n "), None);
+
+        // Unclosed, and with more after the root
+        assert_eq!(shape("[{}, {}, {").unwrap(), "Array of 3 Objects");
+        assert_eq!(shape("[1, 2] [3, 4, 5]").unwrap(), "Array of 2 Numbers");
+    }
+
+    #[test]
+    fn test_large_array() {
+        let text = format!("[{}]", vec!["{\"id\": 1, \"tags\": [\"a\", \"b\"]}"; 100_000].join(",\n"));
+        let progress = Progress::default();
+        assert_eq!(detect(text.as_bytes(), &progress), Some(Shape::Array { elements: 100_000, of: Some(NodeKind::Object) }));
+        assert_eq!(progress.percent(), 100);
+        assert_eq!(Shape::Array { elements: 1_204_133, of: Some(NodeKind::Object) }.describe(NumberFormat::Comma), "Array of 1,204,133 Objects");
+
+        let progress = Progress::default();
+        progress.cancel.store(true, Ordering::Relaxed);
+        assert_eq!(detect(text.as_bytes(), &progress), None);
+    }
+
+    #[test]
+    fn test_count_records() {
+        let records = |text: &str| count_records(text.as_bytes(), &Progress::default()).unwrap();
+        assert_eq!(records("{\"a\": 1}\n{\"a\": 2}\n"), Shape::Records(2));
+        assert_eq!(records("{\"a\": 1}\n\n  \n{\"a\": 2}"), Shape::Records(2));
+        assert_eq!(records(""), Shape::Records(0));
+        assert_eq!(Shape::Records(1).describe(NumberFormat::Plain), "1 record");
+    }
+}
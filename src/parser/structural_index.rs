@@ -1,395 +1,1298 @@
-use super::node::{NodeId, NodeInfo, NodeKind};
-use super::token::{Token, TokenKind};
-use std::collections::HashMap;
+use super::node::{ChildCount, NodeId, NodeInfo, NodeKind, ParseStatus};
+use super::error::{StructuralError, StructuralErrorKind};
+use super::token::{Token, TokenIssue, TokenKind};
+use super::tokenizer::Tokenizer;
+use crate::buffer::TextChange;
+use std::ops::Range;
 
-#[derive(Debug, Clone)]
+/// Sentinel for "no node" in the packed u32 link arrays
+const NONE: u32 = u32::MAX;
+
+/// Bit in the packed kind byte set once a container's matching closer is seen
+const CLOSED: u8 = 0x40;
+
+/// Bit in the packed kind byte set on a string taken as an object key:
+/// one the grammar expected a key at, or one a colon follows
+const KEY: u8 = 0x80;
+
+/// Documented memory budget per indexed node, checked by the tests.
+/// Current layout: start + end (8 each), depth, parent and three links
+/// (4 each), kind/status (1) = 37 bytes.
+pub const BYTES_PER_NODE_BUDGET: usize = 40;
+
+/// Levels of nesting indexed by default
+pub const DEFAULT_MAX_DEPTH: u32 = 10_000;
+
+/// Memory the index may take by default, in MB
+pub const DEFAULT_MAX_SIZE_MB: usize = 500;
+
+/// Longest edited string read back after an edit to check it's still one
+/// string; a longer one is taken to have changed the structure
+const MAX_RECHECKED_STRING: usize = 64 * 1024;
+
+/// How far indexing goes into a pathological document, such as a million
+/// opening brackets. Containers nested deeper than `max_depth` are skipped
+/// along with everything in them; after `max_nodes` nodes nothing more is
+/// indexed. Either way the text stays editable, only structure past the
+/// limit is missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexLimits {
+    pub max_depth: u32,
+    pub max_nodes: usize,
+}
+
+impl IndexLimits {
+    /// Limits letting the index grow to about `size_mb` MB
+    pub fn new(max_depth: u32, size_mb: usize) -> Self {
+        Self { max_depth, max_nodes: size_mb * 1024 * 1024 / BYTES_PER_NODE_BUDGET }
+    }
+}
+
+impl Default for IndexLimits {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_DEPTH, DEFAULT_MAX_SIZE_MB)
+    }
+}
+
+/// Structural index stored as parallel arrays (struct-of-arrays) so that a
+/// node costs a fixed ~37 bytes with no padding or per-node allocation.
+/// Nodes are stored in document order, so `starts` is sorted and offset
+/// lookups are a binary search.
+#[derive(Debug, Clone, Default)]
 pub struct StructuralIndex {
-    nodes: Vec<NodeInfo>,
-    // Quick lookup: byte offset -> node index
-    offset_map: HashMap<usize, NodeId>,
+    starts: Vec<u64>,
+    ends: Vec<u64>,
+    // Low 4 bits: NodeKind, bits 4-5: ParseStatus, bit 6: CLOSED, bit 7: KEY
+    kinds: Vec<u8>,
+    depths: Vec<u32>,
+    parents: Vec<u32>,
+    first_children: Vec<u32>,
+    next_siblings: Vec<u32>,
+    prev_siblings: Vec<u32>,
+    // Top-level nodes in document order (one per record in JSON Lines)
+    roots: Vec<u32>,
+    errors: Vec<StructuralError>,
+    // How many of the errors are syntax errors, text that isn't JSON
+    syntax_errors: usize,
+    warnings: Vec<StructuralError>,
+    // Spans edited since the index was built, sorted and disjoint; nodes
+    // touching them may have the wrong extent or kind until a reindex
+    dirty: Vec<Range<usize>>,
+    // Whether an edit may have changed the shape of the tree, i.e. it
+    // wasn't inside a string, or left that string reading as more than one
+    reshaped: bool,
+}
+
+/// How far the index can be trusted after edits, as the status bar shows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Staleness {
+    /// Built from the text as it is
+    Clean,
+    /// Edited only inside strings: every node is where the index says,
+    /// the edited strings just have new lengths
+    Shifted,
+    /// Edited in ways that may have changed the structure: nodes away from
+    /// the dirty spans are still right, those touching them may not be
+    DirtyRanges,
+    /// Thrown away after edits and being built again. The index doesn't
+    /// know this about itself; the editor does, from the indexer's progress.
+    Rebuilding,
+}
+
+impl Staleness {
+    /// The status bar's mark: `⟳` while rebuilding, `!` while navigation
+    /// may be approximate
+    pub fn badge(self) -> Option<&'static str> {
+        match self {
+            Staleness::Clean | Staleness::Shifted => None,
+            Staleness::DirtyRanges => Some("!"),
+            Staleness::Rebuilding => Some("⟳"),
+        }
+    }
+}
+
+fn pack_id(id: Option<NodeId>) -> u32 {
+    id.map_or(NONE, |id| id as u32)
+}
+
+fn unpack_id(id: u32) -> Option<NodeId> {
+    (id != NONE).then_some(id as NodeId)
+}
+
+fn pack_kind(kind: NodeKind, status: ParseStatus) -> u8 {
+    let kind_bits = match kind {
+        NodeKind::Object => 0,
+        NodeKind::Array => 1,
+        NodeKind::String => 2,
+        NodeKind::Number => 3,
+        NodeKind::Boolean => 4,
+        NodeKind::Null => 5,
+        NodeKind::Key => 6,
+        NodeKind::Unknown => 7,
+        NodeKind::Error => 8,
+    };
+    let status_bits = match status {
+        ParseStatus::Unparsed => 0,
+        ParseStatus::Parsing => 1,
+        ParseStatus::Parsed => 2,
+        ParseStatus::Invalid => 3,
+    };
+    kind_bits | (status_bits << 4)
+}
+
+/// Whether `text` is one string, quotes and all, with no quote inside it
+/// left unescaped
+fn reads_as_one_string(text: &str) -> bool {
+    let Some(inner) = text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) else {
+        return false;
+    };
+    let mut escaped = false;
+    for byte in inner.bytes() {
+        match (escaped, byte) {
+            (true, _) => escaped = false,
+            (false, b'\\') => escaped = true,
+            (false, b'"' | b'\n') => return false,
+            _ => {}
+        }
+    }
+    !escaped
+}
+
+fn unpack_kind(bits: u8) -> (NodeKind, ParseStatus) {
+    let kind = match bits & 0x0f {
+        0 => NodeKind::Object,
+        1 => NodeKind::Array,
+        2 => NodeKind::String,
+        3 => NodeKind::Number,
+        4 => NodeKind::Boolean,
+        5 => NodeKind::Null,
+        6 => NodeKind::Key,
+        7 => NodeKind::Unknown,
+        _ => NodeKind::Error,
+    };
+    let status = match (bits >> 4) & 0x3 {
+        0 => ParseStatus::Unparsed,
+        1 => ParseStatus::Parsing,
+        2 => ParseStatus::Parsed,
+        _ => ParseStatus::Invalid,
+    };
+    (kind, status)
 }
 
 impl StructuralIndex {
     pub fn new() -> Self {
-        Self {
-            nodes: Vec::new(),
-            offset_map: HashMap::new(),
-        }
+        Self::default()
+    }
+
+    /// Append a node, returning its id
+    fn push_node(&mut self, node: &NodeInfo) -> NodeId {
+        let id = self.starts.len();
+        self.starts.push(node.start as u64);
+        self.ends.push(node.end as u64);
+        self.kinds.push(pack_kind(node.kind, node.status));
+        self.depths.push(node.depth);
+        self.parents.push(pack_id(node.parent));
+        self.first_children.push(pack_id(node.first_child));
+        self.next_siblings.push(pack_id(node.next_sibling));
+        self.prev_siblings.push(pack_id(node.prev_sibling));
+        id
     }
 
     /// Build index from token stream
     pub fn from_tokens(tokens: &[Token]) -> Self {
         let mut index = Self::new();
-        let mut stack: Vec<(NodeId, NodeKind, usize, u32)> = Vec::new(); // (id, kind, start, depth)
+        let mut open = OpenContainers::default();
+        index.extend_from_tokens(tokens, &mut open);
+        index.finish(&open);
+        index.shrink_to_fit();
+        index
+    }
+
+    /// Build an index over JSONC text: comments are skipped and trailing
+    /// commas recorded as warnings
+    pub fn from_tokens_relaxed(tokens: &[Token]) -> Self {
+        let mut index = Self::new();
+        let mut open = OpenContainers::relaxed();
+        index.extend_from_tokens(tokens, &mut open);
+        index.finish(&open);
+        index.shrink_to_fit();
+        index
+    }
+
+    /// Build an index over JSON Lines text, one root per record
+    pub fn from_records(text: &str) -> Self {
+        let mut index = Self::new();
+        let mut open = OpenContainers::default();
+        let mut chunk = IndexChunk::new(0);
+        chunk.push_records(text.as_bytes(), 0, &mut open);
+        index.apply_chunk(chunk);
+        index.finish(&open);
+        index.shrink_to_fit();
+        index
+    }
+
+    /// Index tokens from the middle of a container whose opening wasn't
+    /// read, as for a window into a part of the document the index hasn't
+    /// reached. The tokens start with one of its members, just after a
+    /// comma; the container stands in as node 0 from `start`, and takes
+    /// the first closer of its kind that nothing after `start` opened.
+    pub fn from_members(tokens: &[Token], start: usize, is_object: bool, relaxed: bool) -> Self {
+        let mut index = Self::new();
+        let kind = if is_object { NodeKind::Object } else { NodeKind::Array };
+        let id = index.push_node(&NodeInfo::new(kind, start, start, 0, None));
+        index.roots.push(id as u32);
+        let mut open = if relaxed { OpenContainers::relaxed() } else { OpenContainers::default() };
+        let expect = if is_object { Expect::Key } else { Expect::Value };
+        open.stack.push(OpenContainer { id, start, is_object, last_child: None, expect });
+        open.last_root = Some(id);
+        index.extend_from_tokens(tokens, &mut open);
+        index.shrink_to_fit();
+        index
+    }
+
+    /// Append nodes for a further run of tokens. `open` carries the
+    /// containers left unclosed by the previous run and is updated in place,
+    /// so a document can be indexed in consecutive chunks with the same
+    /// result as indexing it in one go.
+    pub fn extend_from_tokens(&mut self, tokens: &[Token], open: &mut OpenContainers) {
+        let chunk = Self::build_chunk(tokens, open, self.len());
+        self.apply_chunk(chunk);
+    }
+
+    /// Build the nodes for a run of tokens without access to the index they
+    /// will be appended to, so the work can happen on another thread.
+    /// `first_id` is the length of the target index; links back into
+    /// existing nodes are recorded as patches applied by `apply_chunk`.
+    pub fn build_chunk(tokens: &[Token], open: &mut OpenContainers, first_id: NodeId) -> IndexChunk {
+        let mut chunk = IndexChunk::new(first_id);
+        chunk.push_tokens(tokens, open);
+        chunk
+    }
+
+    /// Number of top-level nodes (records, in JSON Lines)
+    pub fn root_count(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Id of the `n`th top-level node
+    pub fn nth_root(&self, n: usize) -> Option<NodeId> {
+        self.roots.get(n).map(|&id| id as NodeId)
+    }
+
+    /// First top-level node starting after the offset
+    pub fn next_root(&self, from_offset: usize) -> Option<NodeId> {
+        let idx = self.roots.partition_point(|&r| self.starts[r as usize] <= from_offset as u64);
+        self.nth_root(idx)
+    }
+
+    /// Last top-level node starting before the offset
+    pub fn prev_root(&self, from_offset: usize) -> Option<NodeId> {
+        let idx = self.roots.partition_point(|&r| self.starts[r as usize] < from_offset as u64);
+        self.nth_root(idx.checked_sub(1)?)
+    }
+
+    /// Top-level node containing a node, and its position among the roots
+    pub fn root_of(&self, node_id: NodeId) -> Option<(usize, NodeId)> {
+        let mut root = node_id;
+        while let Some(parent) = self.parent(root) {
+            root = parent;
+        }
+        let position = self.roots.binary_search(&(root as u32)).ok()?;
+        Some((position, root))
+    }
+
+    /// Record the containers still open once the whole document has been
+    /// indexed as unclosed, and give back the room the arrays grew into
+    /// chunk by chunk, which can be nearly as much again
+    pub fn finish(&mut self, open: &OpenContainers) {
+        // Past the node limit, whether they close is unknown
+        let unclosed = if open.stopped { &[][..] } else { &open.stack[..] };
+        for container in unclosed {
+            let closer = if container.is_object { '}' } else { ']' };
+            self.errors.push(StructuralError::new(container.start, StructuralErrorKind::UnclosedContainer(closer)));
+        }
+        self.errors.sort_by_key(|e| e.offset);
+        self.shrink_to_fit();
+    }
+
+    /// Structural errors found so far, in document order
+    pub fn errors(&self) -> &[StructuralError] {
+        &self.errors
+    }
+
+    /// Problems that don't make the document invalid in the current mode
+    /// (trailing commas in JSONC), and where an index limit was reached
+    pub fn warnings(&self) -> &[StructuralError] {
+        &self.warnings
+    }
+
+    /// How many of the errors are in text that isn't JSON at all: stray
+    /// characters, bad words and strings or comments never closed
+    pub fn syntax_error_count(&self) -> usize {
+        self.syntax_errors
+    }
+
+    pub fn first_error(&self) -> Option<&StructuralError> {
+        self.errors.first()
+    }
+
+    /// First error strictly after the given offset
+    pub fn next_error(&self, from_offset: usize) -> Option<&StructuralError> {
+        let idx = self.errors.partition_point(|e| e.offset <= from_offset);
+        self.errors.get(idx)
+    }
+
+    /// Last error strictly before the given offset
+    pub fn prev_error(&self, from_offset: usize) -> Option<&StructuralError> {
+        let idx = self.errors.partition_point(|e| e.offset < from_offset);
+        idx.checked_sub(1).map(|i| &self.errors[i])
+    }
+
+    /// Append a chunk built by `build_chunk`. Returns false (and leaves the
+    /// index untouched) if the chunk was built against a different length,
+    /// e.g. because the index was reset while the chunk was in flight.
+    pub fn apply_chunk(&mut self, chunk: IndexChunk) -> bool {
+        if chunk.first_id != self.len() {
+            return false;
+        }
         
-        for token in tokens {
-            match token.kind {
-                TokenKind::BraceOpen => {
-                    let node_id = index.nodes.len();
-                    let parent = stack.last().map(|(id, _, _, _)| *id);
-                    
-                    // Create placeholder node (will update end when we see closing brace)
-                    let node = NodeInfo::new(
-                        NodeKind::Object,
-                        token.start,
-                        token.end, // Temporary, will update
-                        token.depth as u8,
-                        parent,
-                    );
-                    
-                    index.nodes.push(node);
-                    index.offset_map.insert(token.start, node_id);
-                    stack.push((node_id, NodeKind::Object, token.start, token.depth));
-                }
-                
-                TokenKind::BracketOpen => {
-                    let node_id = index.nodes.len();
-                    let parent = stack.last().map(|(id, _, _, _)| *id);
-                    
-                    let node = NodeInfo::new(
-                        NodeKind::Array,
-                        token.start,
-                        token.end, // Temporary
-                        token.depth as u8,
-                        parent,
-                    );
-                    
-                    index.nodes.push(node);
-                    index.offset_map.insert(token.start, node_id);
-                    stack.push((node_id, NodeKind::Array, token.start, token.depth));
-                }
-                
-                TokenKind::BraceClose | TokenKind::BracketClose => {
-                    if let Some((node_id, _, _, _)) = stack.pop() {
-                        // Update the end position of the container
-                        if let Some(node) = index.nodes.get_mut(node_id) {
-                            node.end = token.end;
-                        }
-                    }
-                }
-                
-                TokenKind::String => {
-                    let node_id = index.nodes.len();
-                    let parent = stack.last().map(|(id, _, _, _)| *id);
-                    
-                    let node = NodeInfo::new(
-                        NodeKind::String,
-                        token.start,
-                        token.end,
-                        token.depth as u8,
-                        parent,
-                    );
-                    
-                    index.nodes.push(node);
-                    index.offset_map.insert(token.start, node_id);
-                }
-                
-                TokenKind::Number => {
-                    let node_id = index.nodes.len();
-                    let parent = stack.last().map(|(id, _, _, _)| *id);
-                    
-                    let node = NodeInfo::new(
-                        NodeKind::Number,
-                        token.start,
-                        token.end,
-                        token.depth as u8,
-                        parent,
-                    );
-                    
-                    index.nodes.push(node);
-                    index.offset_map.insert(token.start, node_id);
-                }
-                
-                TokenKind::True | TokenKind::False => {
-                    let node_id = index.nodes.len();
-                    let parent = stack.last().map(|(id, _, _, _)| *id);
-                    
-                    let node = NodeInfo::new(
-                        NodeKind::Boolean,
-                        token.start,
-                        token.end,
-                        token.depth as u8,
-                        parent,
-                    );
-                    
-                    index.nodes.push(node);
-                    index.offset_map.insert(token.start, node_id);
-                }
-                
-                TokenKind::Null => {
-                    let node_id = index.nodes.len();
-                    let parent = stack.last().map(|(id, _, _, _)| *id);
-                    
-                    let node = NodeInfo::new(
-                        NodeKind::Null,
-                        token.start,
-                        token.end,
-                        token.depth as u8,
-                        parent,
-                    );
-                    
-                    index.nodes.push(node);
-                    index.offset_map.insert(token.start, node_id);
+        let IndexChunk { nodes, patches, errors, warnings, .. } = chunk;
+        self.syntax_errors += errors.iter().filter(|error| error.kind.is_syntax()).count();
+        self.errors.extend(errors);
+        self.warnings.extend(warnings);
+        self.roots.extend_from_slice(&nodes.roots);
+        self.starts.extend_from_slice(&nodes.starts);
+        self.ends.extend_from_slice(&nodes.ends);
+        self.kinds.extend_from_slice(&nodes.kinds);
+        self.depths.extend_from_slice(&nodes.depths);
+        self.parents.extend_from_slice(&nodes.parents);
+        self.first_children.extend_from_slice(&nodes.first_children);
+        self.next_siblings.extend_from_slice(&nodes.next_siblings);
+        self.prev_siblings.extend_from_slice(&nodes.prev_siblings);
+        
+        for patch in patches {
+            match patch {
+                LinkPatch::End(id, end) => self.ends[id] = end,
+                LinkPatch::Close(id, end) => {
+                    self.ends[id] = end;
+                    self.kinds[id] |= CLOSED;
                 }
-                
-                _ => {} // Ignore whitespace, colons, commas
+                LinkPatch::Key(id) => self.mark_key(id),
+                LinkPatch::NextSibling(id, next) => self.next_siblings[id] = next,
+                LinkPatch::FirstChild(id, child) => self.first_children[id] = child,
             }
         }
-        
-        index
+        true
     }
 
-    /// Find node at byte offset using binary search
-    pub fn node_at(&self, offset: usize) -> Option<&NodeInfo> {
-        // Binary search for node containing offset
-        self.nodes
-            .binary_search_by(|node| {
-                if offset < node.start {
-                    std::cmp::Ordering::Greater
-                } else if offset >= node.end {
-                    std::cmp::Ordering::Less
-                } else {
-                    std::cmp::Ordering::Equal
-                }
+    /// Keep the index in place across an edit: offsets after it shift, and
+    /// the text it touched is marked dirty until the document is reindexed
+    pub fn apply_change(&mut self, change: &TextChange) {
+        let old_end = change.offset + change.removed;
+        let new_end = change.offset + change.inserted;
+        let in_string = self.node_id_at(change.offset)
+            .and_then(|id| self.get(id))
+            .is_some_and(|node| {
+                matches!(node.kind, NodeKind::String | NodeKind::Key) && node.start < change.offset && old_end < node.end
+            });
+        self.reshaped |= !in_string;
+        let shift = |x: usize| x + change.inserted - change.removed;
+        // A start inside the removed text moves to where it was; an end
+        // inside it takes in whatever replaced it. Text inserted at a
+        // boundary goes after a node ending there and before one starting
+        // there, except that an empty node (its text deleted) takes it in.
+        let map_start = |start: usize, end: usize| {
+            if start < change.offset || (start == change.offset && end == start && change.removed == 0) {
+                start
+            } else if start >= old_end {
+                shift(start)
+            } else {
+                change.offset
+            }
+        };
+        let map_end = |start: usize, end: usize| {
+            if end < change.offset || (end == change.offset && start < end) {
+                end
+            } else if end >= old_end {
+                shift(end)
+            } else {
+                new_end
+            }
+        };
+
+        // Only nodes ending after the change start can move: those starting
+        // from it on, found by offset, and the ancestors of the last node
+        // before it, which are the only earlier ones reaching that far. A
+        // keystroke in a large document doesn't go through all of it.
+        let first = self.starts.partition_point(|&start| (start as usize) < change.offset);
+        let around: Vec<NodeId> = std::iter::successors(first.checked_sub(1), |&id| self.parent(id)).collect();
+        for id in around.into_iter().chain(first..self.len()) {
+            let (start, end) = (self.starts[id] as usize, self.ends[id] as usize);
+            if end < change.offset {
+                continue;
+            }
+            let (start, end) = (map_start(start, end), map_end(start, end));
+            self.starts[id] = start as u64;
+            self.ends[id] = end.max(start) as u64;
+        }
+        for error in self.errors.iter_mut().chain(self.warnings.iter_mut()) {
+            error.offset = map_start(error.offset, error.offset + 1);
+        }
+
+        let mut spans: Vec<Range<usize>> =
+            self.dirty.iter().map(|span| map_start(span.start, span.end)..map_end(span.start, span.end)).collect();
+        let at = spans.partition_point(|span| span.start < change.offset);
+        spans.insert(at, change.offset..new_end);
+        self.dirty.clear();
+        for span in spans {
+            match self.dirty.last_mut() {
+                Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+                _ => self.dirty.push(span),
+            }
+        }
+    }
+
+    /// Whether anything was edited since the index was built
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// How the index stands against the text, short of being rebuilt
+    pub fn staleness(&self) -> Staleness {
+        match (self.is_dirty(), self.reshaped) {
+            (false, _) => Staleness::Clean,
+            (true, false) => Staleness::Shifted,
+            (true, true) => Staleness::DirtyRanges,
+        }
+    }
+
+    /// After edits that were all inside strings, check each edited string
+    /// still reads as one, given the text of a span as it is now: a quote
+    /// typed into a string, or a backslash taken from before one, changes
+    /// the structure even though the edit was between the quotes
+    pub fn recheck_strings(&mut self, text: impl Fn(Range<usize>) -> String) {
+        if self.reshaped || self.dirty.is_empty() {
+            return;
+        }
+        self.reshaped = self.dirty.iter().any(|span| {
+            self.node_id_at(span.start).and_then(|id| self.get(id)).is_none_or(|node| {
+                node.end - node.start > MAX_RECHECKED_STRING || !reads_as_one_string(&text(node.start..node.end))
             })
-            .ok()
-            .and_then(|idx| self.nodes.get(idx))
+        });
     }
 
-    /// Get node by ID
-    pub fn get(&self, id: NodeId) -> Option<&NodeInfo> {
-        self.nodes.get(id)
+    /// Whether landing on `node` could put the cursor somewhere other than
+    /// where the node now starts: its start is in text edited since the
+    /// index was built, in a way that may have changed the structure
+    pub fn lands_in_dirty(&self, node: NodeId) -> bool {
+        self.reshaped && self.get(node).is_some_and(|node| self.touches_dirty(node.start..node.start))
     }
 
-    /// Find next sibling at same depth
-    pub fn next_sibling(&self, node_id: NodeId) -> Option<NodeId> {
-        let node = self.nodes.get(node_id)?;
-        let depth = node.depth;
-        let parent = node.parent;
-        
-        // Find next node with same depth and parent
-        for (idx, n) in self.nodes.iter().enumerate().skip(node_id + 1) {
-            if n.depth == depth && n.parent == parent {
-                return Some(idx);
-            }
-            // Stop if we've left the parent container
-            if n.depth < depth {
-                break;
+    /// The spans edited since the index was built, in document order
+    pub fn dirty_spans(&self) -> &[Range<usize>] {
+        &self.dirty
+    }
+
+    /// Whether `range` touches text edited since the index was built
+    pub fn touches_dirty(&self, range: Range<usize>) -> bool {
+        let at = self.dirty.partition_point(|span| span.end < range.start);
+        self.dirty.get(at).is_some_and(|span| span.start <= range.end)
+    }
+
+    /// Release spare capacity in the node arrays
+    fn shrink_to_fit(&mut self) {
+        self.starts.shrink_to_fit();
+        self.ends.shrink_to_fit();
+        self.kinds.shrink_to_fit();
+        self.depths.shrink_to_fit();
+        self.parents.shrink_to_fit();
+        self.first_children.shrink_to_fit();
+        self.next_siblings.shrink_to_fit();
+        self.prev_siblings.shrink_to_fit();
+        self.roots.shrink_to_fit();
+    }
+
+    /// Find the id of the innermost node containing the byte offset
+    pub fn node_id_at(&self, offset: usize) -> Option<NodeId> {
+        // Last node starting at or before the offset; the containing node is
+        // either that node or one of its ancestors
+        let mut current = self.starts.partition_point(|&s| s <= offset as u64).checked_sub(1);
+        while let Some(id) = current {
+            if (offset as u64) < self.ends[id] {
+                return Some(id);
             }
+            current = self.parent(id);
         }
-        
         None
     }
 
-    /// Find previous sibling at same depth
-    pub fn prev_sibling(&self, node_id: NodeId) -> Option<NodeId> {
-        let node = self.nodes.get(node_id)?;
-        let depth = node.depth;
-        let parent = node.parent;
-        
-        // Search backwards
-        for idx in (0..node_id).rev() {
-            let n = &self.nodes[idx];
-            if n.depth == depth && n.parent == parent {
-                return Some(idx);
-            }
-            // Stop if we've left the parent container
-            if n.depth < depth {
-                break;
-            }
+    /// Whether a container was closed by its matching delimiter
+    pub fn is_closed(&self, node_id: NodeId) -> bool {
+        self.kinds.get(node_id).is_some_and(|&bits| bits & CLOSED != 0)
+    }
+
+    /// For an offset on a container's opening or closing delimiter, the
+    /// offset of its partner
+    pub fn matching_delimiter(&self, offset: usize) -> Option<usize> {
+        let id = self.node_id_at(offset)?;
+        if !self.is_closed(id) {
+            return None;
         }
-        
-        None
+        let (start, end) = (self.starts[id] as usize, self.ends[id] as usize);
+        if offset == start {
+            Some(end - 1)
+        } else if offset == end - 1 {
+            Some(start)
+        } else {
+            None
+        }
+    }
+
+    /// Find node at byte offset using binary search over node starts
+    pub fn node_at(&self, offset: usize) -> Option<NodeInfo> {
+        self.get(self.node_id_at(offset)?)
+    }
+
+    /// Get node by ID (materialized from the packed arrays)
+    pub fn get(&self, id: NodeId) -> Option<NodeInfo> {
+        (id < self.len()).then(|| self.node(id))
+    }
+
+    /// Materialize a node known to be in bounds
+    fn node(&self, id: NodeId) -> NodeInfo {
+        let (kind, status) = unpack_kind(self.kinds[id]);
+        NodeInfo {
+            kind,
+            start: self.starts[id] as usize,
+            end: self.ends[id] as usize,
+            depth: self.depths[id],
+            parent: unpack_id(self.parents[id]),
+            first_child: unpack_id(self.first_children[id]),
+            next_sibling: unpack_id(self.next_siblings[id]),
+            prev_sibling: unpack_id(self.prev_siblings[id]),
+            status,
+        }
+    }
+
+    /// Kind of a node without materializing the rest of it
+    pub fn kind(&self, id: NodeId) -> Option<NodeKind> {
+        self.kinds.get(id).map(|&bits| unpack_kind(bits).0)
+    }
+
+    /// Find next sibling (O(1) via precomputed links)
+    pub fn next_sibling(&self, node_id: NodeId) -> Option<NodeId> {
+        unpack_id(*self.next_siblings.get(node_id)?)
+    }
+
+    /// Find previous sibling (O(1) via precomputed links)
+    pub fn prev_sibling(&self, node_id: NodeId) -> Option<NodeId> {
+        unpack_id(*self.prev_siblings.get(node_id)?)
     }
 
     /// Get parent node
     pub fn parent(&self, node_id: NodeId) -> Option<NodeId> {
-        self.nodes.get(node_id)?.parent
+        unpack_id(*self.parents.get(node_id)?)
     }
     
     /// Get first child of a container node
     pub fn first_child(&self, node_id: NodeId) -> Option<NodeId> {
-        let node = self.nodes.get(node_id)?;
-        let child_depth = node.depth + 1;
+        unpack_id(*self.first_children.get(node_id)?)
+    }
+
+    /// Last child of a container node, following sibling links
+    pub fn last_child(&self, node_id: NodeId) -> Option<NodeId> {
+        self.child_iter(node_id).last()
+    }
+
+    /// The `n`th (0-based) child of a container node, following sibling links
+    pub fn nth_child(&self, node_id: NodeId, n: usize) -> Option<NodeId> {
+        self.child_iter(node_id).nth(n)
+    }
+
+    /// Iterate the direct children of a container node, following sibling
+    /// links. Nothing is collected, so stepping through an object of a
+    /// million keys costs no more per step than one of ten.
+    pub fn child_iter(&self, node_id: NodeId) -> Children<'_> {
+        Children {
+            index: self,
+            next: self.first_child(node_id),
+        }
+    }
+
+    /// Iterate the members of an object as its key and the value after it,
+    /// by the keys tagged while indexing rather than by counting children,
+    /// so a member missing its value doesn't pair every key after it with
+    /// the wrong one
+    pub fn member_iter(&self, object: NodeId) -> Members<'_> {
+        Members {
+            children: self.child_iter(object),
+            pending: None,
+        }
+    }
+
+    /// Member count of a container, following sibling links. A container
+    /// whose closer hasn't been indexed yet reports a lower bound.
+    pub fn child_count(&self, node_id: NodeId) -> Option<ChildCount> {
+        if !matches!(self.kind(node_id)?, NodeKind::Object | NodeKind::Array) {
+            return None;
+        }
+        Some(ChildCount {
+            count: self.child_iter(node_id).filter(|&id| self.starts_member(id)).count(),
+            complete: self.is_closed(node_id),
+        })
+    }
+
+    /// Whether a node starts one of its container's members: any element
+    /// of an array, but only the key of an object's member. Counting these
+    /// rather than pairing an object's children keeps a stray node left by
+    /// error recovery from moving every member after it.
+    pub fn starts_member(&self, node_id: NodeId) -> bool {
+        self.is_key_node(node_id) || self.parent(node_id).and_then(|parent| self.kind(parent)) != Some(NodeKind::Object)
+    }
+
+    /// How many of its container's members start at or before a node,
+    /// walking back along the sibling links: its 1-based position, unless
+    /// it comes before an object's first key
+    pub fn members_through(&self, node_id: NodeId) -> usize {
+        std::iter::successors(Some(node_id), |&id| self.prev_sibling(id)).filter(|&id| self.starts_member(id)).count()
+    }
+
+    /// Bytes spanned by a node and everything nested inside it
+    pub fn subtree_size_bytes(&self, node_id: NodeId) -> Option<usize> {
+        let start = *self.starts.get(node_id)?;
+        Some((self.ends[node_id] - start) as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// Iterate all nodes in document order
+    pub fn nodes(&self) -> impl DoubleEndedIterator<Item = NodeInfo> + ExactSizeIterator + '_ {
+        (0..self.len()).map(move |id| self.node(id))
+    }
+
+    /// Approximate heap memory used by the index, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.starts.capacity() * std::mem::size_of::<u64>()
+            + self.ends.capacity() * std::mem::size_of::<u64>()
+            + self.kinds.capacity()
+            + (self.depths.capacity()
+                + self.parents.capacity()
+                + self.first_children.capacity()
+                + self.next_siblings.capacity()
+                + self.prev_siblings.capacity())
+                * std::mem::size_of::<u32>()
+            + self.roots.capacity() * std::mem::size_of::<u32>()
+            + (self.errors.capacity() + self.warnings.capacity()) * std::mem::size_of::<StructuralError>()
+    }
+
+    /// Index of the first node starting strictly after `offset`
+    /// (nodes are stored in document order, so starts are sorted)
+    fn first_node_after(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&s| s <= offset as u64)
+    }
+
+    /// Number of nodes starting strictly before `offset`
+    fn nodes_before(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&s| s < offset as u64)
+    }
+
+    /// Position of a node among its siblings (0-based), found by walking
+    /// back along the sibling links
+    pub fn sibling_position(&self, node_id: NodeId) -> usize {
+        let mut pos = 0;
+        let mut current = self.prev_sibling(node_id);
+        while let Some(id) = current {
+            pos += 1;
+            current = self.prev_sibling(id);
+        }
+        pos
+    }
+
+    /// Ids of the nodes starting within a byte range, in document order
+    pub fn nodes_starting_in(&self, range: std::ops::Range<usize>) -> std::ops::Range<NodeId> {
+        self.nodes_before(range.start)..self.nodes_before(range.end)
+    }
+
+    /// Check if a node is an object key, as tagged while indexing: a string
+    /// where the object expected a key, or one followed by a colon. Unlike
+    /// counting positions, this holds past malformed members.
+    pub fn is_key_node(&self, node_id: NodeId) -> bool {
+        self.kinds.get(node_id).is_some_and(|&bits| bits & KEY != 0)
+    }
+
+    /// Tag a string as an object key
+    fn mark_key(&mut self, node_id: NodeId) {
+        if self.kind(node_id) == Some(NodeKind::String) {
+            self.kinds[node_id] |= KEY;
+        }
+    }
+    
+    /// Find the next key node after the given offset
+    /// (strings tagged as object keys while indexing)
+    pub fn next_key(&self, from_offset: usize) -> Option<NodeId> {
+        (self.first_node_after(from_offset)..self.len()).find(|&idx| self.is_key_node(idx))
+    }
+    
+    /// Find the previous key node before the given offset
+    pub fn prev_key(&self, from_offset: usize) -> Option<NodeId> {
+        (0..self.nodes_before(from_offset)).rev().find(|&idx| self.is_key_node(idx))
+    }
+    
+    /// Find the next value node after the given offset
+    /// Values can be any node type that's not a key
+    pub fn next_value(&self, from_offset: usize) -> Option<NodeId> {
+        (self.first_node_after(from_offset)..self.len()).find(|&idx| self.is_value_node(idx))
+    }
+    
+    /// Find the previous value node before the given offset
+    pub fn prev_value(&self, from_offset: usize) -> Option<NodeId> {
+        (0..self.nodes_before(from_offset)).rev().find(|&idx| self.is_value_node(idx))
+    }
+    
+    /// Check if a node is a value (not a key in an object)
+    fn is_value_node(&self, node_id: NodeId) -> bool {
+        if node_id >= self.len() {
+            return false;
+        }
         
-        // Find first node with depth = parent_depth + 1 and parent = node_id
-        for (idx, n) in self.nodes.iter().enumerate().skip(node_id + 1) {
-            if n.parent == Some(node_id) && n.depth == child_depth {
-                return Some(idx);
+        match self.parent(node_id).and_then(|p| self.kind(p)) {
+            // In objects, whatever follows a key; a stray token between
+            // members belongs to neither
+            Some(NodeKind::Object) => {
+                !self.is_key_node(node_id) && self.prev_sibling(node_id).is_some_and(|prev| self.is_key_node(prev))
             }
-            // Stop if we've left the container
-            if n.depth <= node.depth {
+            // All children of arrays are values
+            Some(kind) => kind == NodeKind::Array,
+            // Top-level nodes are values
+            None => true,
+        }
+    }
+}
+
+/// Nodes built off-thread for appending to a `StructuralIndex`
+#[derive(Debug, Clone)]
+pub struct IndexChunk {
+    first_id: NodeId,
+    // New nodes, with links already expressed as absolute ids
+    nodes: StructuralIndex,
+    // Updates to nodes that existed before the chunk
+    patches: Vec<LinkPatch>,
+    errors: Vec<StructuralError>,
+    warnings: Vec<StructuralError>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LinkPatch {
+    End(NodeId, u64),
+    Close(NodeId, u64),
+    Key(NodeId),
+    NextSibling(NodeId, u32),
+    FirstChild(NodeId, u32),
+}
+
+impl IndexChunk {
+    /// An empty chunk to be appended to an index of length `first_id`
+    pub fn new(first_id: NodeId) -> Self {
+        Self {
+            first_id,
+            nodes: StructuralIndex::new(),
+            patches: Vec::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Add nodes for a run of tokens, continuing from `open`
+    pub fn push_tokens(&mut self, tokens: &[Token], open: &mut OpenContainers) {
+        if open.stopped {
+            return;
+        }
+        let mut frontier = tokens.last().map(|token| token.end);
+        // The error node of the last invalid token, and where it ends
+        let mut last_invalid: Option<(NodeId, usize)> = None;
+        for token in tokens {
+            // Inside a container past the depth limit: only find its end
+            if open.skipping > 0 {
+                match token.kind {
+                    TokenKind::BraceOpen | TokenKind::BracketOpen => open.skipping += 1,
+                    TokenKind::BraceClose | TokenKind::BracketClose => open.skipping -= 1,
+                    _ => {}
+                }
+                continue;
+            }
+            // Anything but whitespace once the document has ended
+            let blank = matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment);
+            if !blank && open.stack.is_empty() && open.last_root.is_some() && !open.documents && !open.trailing {
+                open.trailing = true;
+                self.errors.push(StructuralError::new(token.start, StructuralErrorKind::TrailingContent));
+            }
+            let kind = match token.kind {
+                TokenKind::BraceOpen => NodeKind::Object,
+                TokenKind::BracketOpen => NodeKind::Array,
+                TokenKind::String => NodeKind::String,
+                TokenKind::Number => NodeKind::Number,
+                TokenKind::True | TokenKind::False => NodeKind::Boolean,
+                TokenKind::Null => NodeKind::Null,
+                TokenKind::BraceClose | TokenKind::BracketClose => {
+                    open.close(token, self);
+                    continue;
+                }
+                TokenKind::Comma => {
+                    open.check_grammar(Grammar::Comma, token.start, &mut self.errors);
+                    continue;
+                }
+                TokenKind::Colon => {
+                    // Whatever the grammar made of it, a string before a
+                    // colon is a key
+                    if let Some(key) = open.stack.last().filter(|c| c.is_object).and_then(|c| c.last_child) {
+                        self.mark_key(key);
+                    }
+                    open.check_grammar(Grammar::Colon, token.start, &mut self.errors);
+                    continue;
+                }
+                TokenKind::Invalid if token.issue == TokenIssue::UnterminatedComment => {
+                    self.errors.push(StructuralError::new(token.start, StructuralErrorKind::UnterminatedComment));
+                    continue;
+                }
+                TokenKind::Invalid => {
+                    // A bad word comes in pieces (the `fo` and `o` of `foo`),
+                    // which make one node
+                    if let Some((id, _)) = last_invalid.filter(|&(_, end)| end == token.start) {
+                        self.set_end(id, token.end);
+                        last_invalid = Some((id, token.end));
+                        continue;
+                    }
+                    let kind = match token.issue {
+                        TokenIssue::UnterminatedString => StructuralErrorKind::UnterminatedString,
+                        _ => StructuralErrorKind::InvalidToken,
+                    };
+                    self.errors.push(StructuralError::new(token.start, kind));
+                    NodeKind::Error
+                }
+                TokenKind::Whitespace | TokenKind::Comment => continue,
+            };
+            
+            let node_id = self.first_id + self.nodes.len();
+            if node_id >= open.limits.max_nodes {
+                open.limit_reached(StructuralError::new(token.start, StructuralErrorKind::TooManyNodes(open.limits.max_nodes)), self);
+                open.stopped = true;
+                frontier = Some(token.start);
                 break;
             }
+            
+            let is_key = if kind == NodeKind::Error {
+                // Let it stand in for whatever was expected so one bad
+                // token doesn't cascade into grammar errors
+                open.check_grammar(Grammar::Value { string: true }, token.start, &mut Vec::new());
+                last_invalid = Some((node_id, token.end));
+                false
+            } else {
+                let string = kind == NodeKind::String;
+                open.check_grammar(Grammar::Value { string }, token.start, &mut self.errors) && string
+            };
+            
+            let is_container = matches!(kind, NodeKind::Object | NodeKind::Array);
+            if is_container && open.stack.len() as u32 >= open.limits.max_depth {
+                // Stands in as a value in its parent, with nothing in it indexed
+                open.limit_reached(StructuralError::new(token.start, StructuralErrorKind::TooDeep(open.limits.max_depth)), self);
+                open.skipping = 1;
+                continue;
+            }
+            
+            let parent = open.stack.last().map(|c| c.id);
+            
+            // Containers get a temporary end, updated when we see the closing token.
+            // Depth follows the container stack so it always agrees with the parent links.
+            let mut node = NodeInfo::new(kind, token.start, token.end, open.stack.len() as u32, parent);
+            if token.issue != TokenIssue::None {
+                node.status = ParseStatus::Invalid;
+            }
+            
+            // Link into the parent's child list (or the list of top-level roots)
+            let prev = match open.stack.last_mut() {
+                Some(container) => container.last_child.replace(node_id),
+                None => open.last_root.replace(node_id),
+            };
+            node.prev_sibling = prev;
+            match prev {
+                Some(prev_id) => self.set_next_sibling(prev_id, node_id),
+                None => {
+                    if let Some(parent_id) = parent {
+                        self.set_first_child(parent_id, node_id);
+                    }
+                }
+            }
+            
+            let local = self.nodes.push_node(&node);
+            if is_key {
+                self.nodes.kinds[local] |= KEY;
+            }
+            if parent.is_none() {
+                self.nodes.roots.push(node_id as u32);
+            }
+            
+            if is_container {
+                open.stack.push(OpenContainer {
+                    id: node_id,
+                    start: token.start,
+                    is_object: kind == NodeKind::Object,
+                    last_child: None,
+                    expect: Expect::First,
+                });
+            }
         }
         
-        None
+        // Containers still open extend at least to the indexed frontier
+        if let Some(frontier) = frontier {
+            for container in &open.stack {
+                self.set_end(container.id, frontier);
+            }
+        }
     }
 
-    /// Get all children of a container node
-    pub fn children(&self, node_id: NodeId) -> Vec<NodeId> {
-        let node = match self.nodes.get(node_id) {
-            Some(n) if n.is_container() => n,
-            _ => return Vec::new(),
-        };
-        
-        let mut children = Vec::new();
-        let depth = node.depth;
-        
-        for (idx, n) in self.nodes.iter().enumerate().skip(node_id + 1) {
-            // Stop when we exit this container
-            if n.start >= node.end {
-                break;
+    /// Add nodes for JSON Lines text starting at absolute `base_offset`:
+    /// each line is its own record, so a malformed line can't swallow the
+    /// ones after it
+    pub fn push_records(&mut self, text: &[u8], base_offset: usize, open: &mut OpenContainers) {
+        open.documents = true;
+        let mut line_start = 0;
+        for line in text.split_inclusive(|&b| b == b'\n') {
+            let offset = base_offset + line_start;
+            let mut tokenizer = Tokenizer::resume(line, offset, open.depth())
+                .with_comments(open.relaxed);
+            self.push_tokens(&tokenizer.tokenize_all(), open);
+            line_start += line.len();
+            if line.ends_with(b"\n") {
+                self.end_record(open, base_offset + line_start - 1);
             }
-            // Only direct children (depth = parent depth + 1)
-            if n.depth == depth + 1 {
-                children.push(idx);
+        }
+    }
+
+    /// End a record (a line in JSON Lines): anything still open at `offset`
+    /// is reported as unclosed and closed there, so the next record starts
+    /// again at the top level
+    pub fn end_record(&mut self, open: &mut OpenContainers, offset: usize) {
+        if open.stopped {
+            return;
+        }
+        open.skipping = 0;
+        for container in open.stack.drain(..) {
+            let closer = if container.is_object { '}' } else { ']' };
+            self.errors.push(StructuralError::new(container.start, StructuralErrorKind::UnclosedContainer(closer)));
+            self.set_end(container.id, offset);
+        }
+    }
+
+    /// Number of nodes the chunk adds
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn set_end(&mut self, id: NodeId, end: usize) {
+        match id.checked_sub(self.first_id) {
+            Some(local) => self.nodes.ends[local] = end as u64,
+            None => self.patches.push(LinkPatch::End(id, end as u64)),
+        }
+    }
+
+    /// Set the end of a container closed by its matching delimiter
+    fn close_node(&mut self, id: NodeId, end: usize) {
+        match id.checked_sub(self.first_id) {
+            Some(local) => {
+                self.nodes.ends[local] = end as u64;
+                self.nodes.kinds[local] |= CLOSED;
             }
+            None => self.patches.push(LinkPatch::Close(id, end as u64)),
+        }
+    }
+
+    fn mark_key(&mut self, id: NodeId) {
+        match id.checked_sub(self.first_id) {
+            Some(local) => self.nodes.mark_key(local),
+            None => self.patches.push(LinkPatch::Key(id)),
+        }
+    }
+
+    fn set_next_sibling(&mut self, id: NodeId, next: NodeId) {
+        match id.checked_sub(self.first_id) {
+            Some(local) => self.nodes.next_siblings[local] = next as u32,
+            None => self.patches.push(LinkPatch::NextSibling(id, next as u32)),
+        }
+    }
+
+    fn set_first_child(&mut self, id: NodeId, child: NodeId) {
+        match id.checked_sub(self.first_id) {
+            Some(local) => self.nodes.first_children[local] = child as u32,
+            None => self.patches.push(LinkPatch::FirstChild(id, child as u32)),
+        }
+    }
+}
+
+/// Containers left open at the end of an indexed chunk, needed to resume
+/// indexing where the previous chunk stopped
+#[derive(Debug, Clone, Default)]
+pub struct OpenContainers {
+    stack: Vec<OpenContainer>,
+    last_root: Option<NodeId>,
+    // JSONC: comments allowed, trailing commas are warnings
+    relaxed: bool,
+    limits: IndexLimits,
+    // Depth inside a container skipped for being past the depth limit
+    skipping: u32,
+    // Set once the node limit is reached; nothing after is indexed
+    stopped: bool,
+    // Where a limit was first reached
+    limit_hit: Option<StructuralError>,
+    // Documents may follow one another (JSON Lines records, :split-docs),
+    // so what comes after the first isn't trailing content
+    documents: bool,
+    // Whether content after the end of the document was reported
+    trailing: bool,
+}
+
+#[derive(Debug, Clone)]
+struct OpenContainer {
+    id: NodeId,
+    start: usize,
+    is_object: bool,
+    last_child: Option<NodeId>,
+    expect: Expect,
+}
+
+/// What may come next inside an open container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    First,          // just opened: first member or the closer
+    Key,            // object, after a comma
+    Colon,          // object, after a key
+    Value,          // after a colon, or after a comma in an array
+    CommaOrClose,   // after a complete member
+}
+
+/// Grammar-relevant token, for checking what is expected
+#[derive(Debug, Clone, Copy)]
+enum Grammar {
+    Value { string: bool },
+    Comma,
+    Colon,
+}
+
+impl OpenContainers {
+    /// Starting state for a JSONC document (comments and trailing commas)
+    pub fn relaxed() -> Self {
+        Self {
+            relaxed: true,
+            ..Self::default()
         }
-        
-        children
     }
 
-    pub fn len(&self) -> usize {
-        self.nodes.len()
+    pub fn is_relaxed(&self) -> bool {
+        self.relaxed
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.nodes.is_empty()
+    /// The same starting state, indexing no further than `limits`
+    pub fn with_limits(self, limits: IndexLimits) -> Self {
+        Self { limits, ..self }
     }
 
-    pub fn nodes(&self) -> &[NodeInfo] {
-        &self.nodes
+    /// The same starting state for documents one after another, as a file
+    /// of concatenated JSON values is read
+    pub fn documents(self) -> Self {
+        Self { documents: true, ..self }
     }
-    
-    /// Find the next key node after the given offset
-    /// Keys are String nodes that are direct children of Object nodes
-    pub fn next_key(&self, from_offset: usize) -> Option<NodeId> {
-        // Find all String nodes that are object keys (odd-indexed children of objects)
-        for (idx, node) in self.nodes.iter().enumerate() {
-            if node.start <= from_offset {
-                continue;
-            }
-            
-            // Check if this is a key (String node in an object)
-            if node.kind == NodeKind::String {
-                if let Some(parent_id) = node.parent {
-                    if let Some(parent) = self.nodes.get(parent_id) {
-                        if parent.kind == NodeKind::Object {
-                            // In JSON objects, keys are strings at even positions among siblings
-                            // Check if this is at a key position
-                            let siblings: Vec<_> = self.children(parent_id);
-                            if let Some(pos) = siblings.iter().position(|&id| id == idx) {
-                                if pos % 2 == 0 {
-                                    return Some(idx);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        None
+
+    /// Nesting depth at the end of the indexed chunk
+    pub fn depth(&self) -> u32 {
+        self.stack.len() as u32 + self.skipping
     }
-    
-    /// Find the previous key node before the given offset
-    pub fn prev_key(&self, from_offset: usize) -> Option<NodeId> {
-        let mut result = None;
+
+    /// Where indexing first ran into a limit, if it has
+    pub fn limit_hit(&self) -> Option<StructuralError> {
+        self.limit_hit
+    }
+
+    fn limit_reached(&mut self, warning: StructuralError, chunk: &mut IndexChunk) {
+        self.limit_hit.get_or_insert(warning);
+        chunk.warnings.push(warning);
+    }
+
+    /// Advance the innermost container's grammar state, recording an error
+    /// (and recovering as sensibly as possible) if the token doesn't fit.
+    /// Returns whether the token was taken as an object key.
+    fn check_grammar(&mut self, token: Grammar, offset: usize, errors: &mut Vec<StructuralError>) -> bool {
+        use StructuralErrorKind::*;
+        let mut error = |kind| errors.push(StructuralError::new(offset, kind));
         
-        for (idx, node) in self.nodes.iter().enumerate() {
-            if node.start >= from_offset {
-                break;
+        let Some(container) = self.stack.last_mut() else {
+            // Top level: only whole values make sense
+            match token {
+                Grammar::Comma => error(UnexpectedComma),
+                Grammar::Colon => error(UnexpectedColon),
+                Grammar::Value { .. } => {}
             }
-            
-            // Check if this is a key
-            if node.kind == NodeKind::String {
-                if let Some(parent_id) = node.parent {
-                    if let Some(parent) = self.nodes.get(parent_id) {
-                        if parent.kind == NodeKind::Object {
-                            let siblings: Vec<_> = self.children(parent_id);
-                            if let Some(pos) = siblings.iter().position(|&id| id == idx) {
-                                if pos % 2 == 0 {
-                                    result = Some(idx);
-                                }
-                            }
-                        }
-                    }
+            return false;
+        };
+        
+        container.expect = if container.is_object {
+            match (container.expect, token) {
+                (Expect::First | Expect::Key, Grammar::Value { string: true }) => Expect::Colon,
+                (Expect::First | Expect::Key, Grammar::Value { string: false }) => {
+                    // Treat it as a malformed key and carry on
+                    error(ExpectedKey);
+                    Expect::Colon
+                }
+                (Expect::Colon, Grammar::Colon) => Expect::Value,
+                (Expect::Colon, Grammar::Value { .. }) => {
+                    error(MissingColon);
+                    Expect::CommaOrClose
+                }
+                (Expect::Colon | Expect::Value, Grammar::Comma) => {
+                    error(MissingValue);
+                    Expect::Key
+                }
+                (Expect::Value, Grammar::Value { .. }) => Expect::CommaOrClose,
+                (Expect::CommaOrClose, Grammar::Comma) => Expect::Key,
+                (Expect::CommaOrClose, Grammar::Value { string }) => {
+                    error(MissingComma);
+                    // A string here is most likely the next key
+                    if string { Expect::Colon } else { Expect::CommaOrClose }
+                }
+                (expect, Grammar::Comma) => {
+                    error(UnexpectedComma);
+                    expect
+                }
+                (expect, Grammar::Colon) => {
+                    error(UnexpectedColon);
+                    expect
                 }
             }
-        }
-        result
-    }
-    
-    /// Find the next value node after the given offset
-    /// Values can be any node type that's not a key
-    pub fn next_value(&self, from_offset: usize) -> Option<NodeId> {
-        for (idx, node) in self.nodes.iter().enumerate() {
-            if node.start <= from_offset {
-                continue;
-            }
-            
-            // Check if this is a value node
-            if self.is_value_node(idx) {
-                return Some(idx);
+        } else {
+            match (container.expect, token) {
+                (Expect::CommaOrClose, Grammar::Comma) => Expect::Value,
+                (Expect::CommaOrClose, Grammar::Value { .. }) => {
+                    error(MissingComma);
+                    Expect::CommaOrClose
+                }
+                (_, Grammar::Value { .. }) => Expect::CommaOrClose,
+                (expect, Grammar::Comma) => {
+                    error(UnexpectedComma);
+                    expect
+                }
+                (Expect::CommaOrClose, Grammar::Colon) => {
+                    // Most likely a typo for a comma
+                    error(UnexpectedColon);
+                    Expect::Value
+                }
+                (expect, Grammar::Colon) => {
+                    error(UnexpectedColon);
+                    expect
+                }
             }
-        }
-        None
+        };
+        container.is_object && container.expect == Expect::Colon && matches!(token, Grammar::Value { .. })
     }
-    
-    /// Find the previous value node before the given offset
-    pub fn prev_value(&self, from_offset: usize) -> Option<NodeId> {
-        let mut result = None;
+
+    /// Close the container matching a closing token. A closer that doesn't
+    /// match the innermost container closes the nearest one it does match
+    /// (if any), so a single stray bracket doesn't derail everything after it.
+    fn close(&mut self, token: &Token, chunk: &mut IndexChunk) {
+        use StructuralErrorKind::*;
+        let is_object = token.kind == TokenKind::BraceClose;
+        let found = if is_object { '}' } else { ']' };
         
-        for (idx, node) in self.nodes.iter().enumerate() {
-            if node.start >= from_offset {
-                break;
-            }
-            
-            if self.is_value_node(idx) {
-                result = Some(idx);
-            }
-        }
-        result
-    }
-    
-    /// Check if a node is a value (not a key in an object)
-    fn is_value_node(&self, node_id: NodeId) -> bool {
-        let node = match self.nodes.get(node_id) {
-            Some(n) => n,
-            None => return false,
+        let Some(matching) = self.stack.iter().rposition(|c| c.is_object == is_object) else {
+            chunk.errors.push(StructuralError::new(token.start, UnexpectedCloser(found)));
+            return;
         };
         
-        // If parent is an object, check if this is an odd-indexed child (value position)
-        if let Some(parent_id) = node.parent {
-            if let Some(parent) = self.nodes.get(parent_id) {
-                if parent.kind == NodeKind::Object {
-                    let siblings: Vec<_> = self.children(parent_id);
-                    if let Some(pos) = siblings.iter().position(|&id| id == node_id) {
-                        // Odd positions are values in objects
-                        return pos % 2 == 1;
-                    }
-                } else if parent.kind == NodeKind::Array {
-                    // All children of arrays are values
-                    return true;
+        if matching + 1 < self.stack.len() {
+            let expected = if self.stack[self.stack.len() - 1].is_object { '}' } else { ']' };
+            chunk.errors.push(StructuralError::new(token.start, MismatchedCloser { expected, found }));
+        } else {
+            let container = &self.stack[matching];
+            let kind = match (container.is_object, container.expect) {
+                (true, Expect::Key) | (false, Expect::Value) => Some(TrailingComma(found)),
+                (true, Expect::Colon) => Some(MissingColon),
+                (true, Expect::Value) => Some(MissingValue),
+                _ => None,
+            };
+            match kind {
+                // Trailing commas are allowed, but still worth pointing out, in JSONC
+                Some(kind @ TrailingComma(_)) if self.relaxed => {
+                    chunk.warnings.push(StructuralError::new(token.start, kind));
                 }
+                Some(kind) => chunk.errors.push(StructuralError::new(token.start, kind)),
+                None => {}
             }
         }
         
-        // Top-level nodes are values
-        node.parent.is_none()
+        // Containers inside the matched one end here too, but unclosed
+        for container in self.stack.drain(matching + 1..) {
+            chunk.set_end(container.id, token.end);
+        }
+        if let Some(container) = self.stack.pop() {
+            chunk.close_node(container.id, token.end);
+        }
     }
 }
 
-impl Default for StructuralIndex {
-    fn default() -> Self {
-        Self::new()
+/// Iterator over the direct children of a node
+pub struct Children<'a> {
+    index: &'a StructuralIndex,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.index.next_sibling(current);
+        Some(current)
+    }
+}
+
+/// An object's members, from `StructuralIndex::member_iter`: each key with
+/// its value, or None for a key with none before the next
+pub struct Members<'a> {
+    children: Children<'a>,
+    // A key taken as the value of the one before, to start the next member
+    pending: Option<NodeId>,
+}
+
+impl Iterator for Members<'_> {
+    type Item = (NodeId, Option<NodeId>);
+
+    fn next(&mut self) -> Option<(NodeId, Option<NodeId>)> {
+        let index = self.children.index;
+        let key = match self.pending.take() {
+            Some(key) => key,
+            None => self.children.by_ref().find(|&id| index.is_key_node(id))?,
+        };
+        match self.children.next() {
+            Some(next) if index.is_key_node(next) => {
+                self.pending = Some(next);
+                Some((key, None))
+            }
+            value => Some((key, value)),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parser::Tokenizer;
+    use crate::parser::StructuralErrorKind;
 
     #[test]
     fn test_index_from_simple_object() {
@@ -430,7 +1333,6 @@ mod tests {
         
         // Find first number (1)
         let first_num_id = index.nodes()
-            .iter()
             .position(|n| n.kind == NodeKind::Number)
             .unwrap();
         
@@ -442,4 +1344,666 @@ mod tests {
         let next_node = index.get(next.unwrap()).unwrap();
         assert_eq!(next_node.kind, NodeKind::Number);
     }
+
+    /// Linear-scan implementations the index used before sibling links
+    /// were precomputed, kept to verify the links are equivalent
+    mod legacy {
+        use super::*;
+
+        pub fn next_sibling(index: &StructuralIndex, node_id: NodeId) -> Option<NodeId> {
+            let node = index.get(node_id)?;
+            for (idx, n) in index.nodes().enumerate().skip(node_id + 1) {
+                if n.depth == node.depth && n.parent == node.parent {
+                    return Some(idx);
+                }
+                if n.depth < node.depth {
+                    break;
+                }
+            }
+            None
+        }
+
+        pub fn prev_sibling(index: &StructuralIndex, node_id: NodeId) -> Option<NodeId> {
+            let node = index.get(node_id)?;
+            for idx in (0..node_id).rev() {
+                let n = index.get(idx)?;
+                if n.depth == node.depth && n.parent == node.parent {
+                    return Some(idx);
+                }
+                if n.depth < node.depth {
+                    break;
+                }
+            }
+            None
+        }
+
+        pub fn first_child(index: &StructuralIndex, node_id: NodeId) -> Option<NodeId> {
+            let node = index.get(node_id)?;
+            for (idx, n) in index.nodes().enumerate().skip(node_id + 1) {
+                if n.parent == Some(node_id) && n.depth == node.depth + 1 {
+                    return Some(idx);
+                }
+                if n.depth <= node.depth {
+                    break;
+                }
+            }
+            None
+        }
+
+        pub fn children(index: &StructuralIndex, node_id: NodeId) -> Vec<NodeId> {
+            let node = match index.get(node_id) {
+                Some(n) if n.is_container() => n,
+                _ => return Vec::new(),
+            };
+            let mut children = Vec::new();
+            for (idx, n) in index.nodes().enumerate().skip(node_id + 1) {
+                if n.start >= node.end {
+                    break;
+                }
+                if n.depth == node.depth + 1 {
+                    children.push(idx);
+                }
+            }
+            children
+        }
+    }
+
+    const FIXTURES: &[&str] = &[
+        r#"{"key": "value"}"#,
+        r#"[1, 2, 3]"#,
+        r#"{"a": [1, 2]}"#,
+        r#"{"users": [{"id": 1, "name": "Alice"}, {"id": 2, "tags": ["x", "y"]}], "count": 2}"#,
+        r#"[[[]], {}, [{"deep": {"deeper": [true, false, null]}}], "tail"]"#,
+        r#"{"empty_obj": {}, "empty_arr": [], "n": -1.5e3}"#,
+    ];
+
+    #[test]
+    fn test_links_match_linear_scan() {
+        for json in FIXTURES {
+            let mut tokenizer = Tokenizer::new(json.to_string());
+            let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+            
+            for id in 0..index.len() {
+                assert_eq!(index.next_sibling(id), legacy::next_sibling(&index, id), "next_sibling({}) in {}", id, json);
+                assert_eq!(index.prev_sibling(id), legacy::prev_sibling(&index, id), "prev_sibling({}) in {}", id, json);
+                assert_eq!(index.first_child(id), legacy::first_child(&index, id), "first_child({}) in {}", id, json);
+                assert_eq!(index.child_iter(id).collect::<Vec<_>>(), legacy::children(&index, id), "children({}) in {}", id, json);
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_value_navigation() {
+        let json = r#"{"a": 1, "b": {"c": [2, 3]}, "d": "e"}"#;
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        let text_of = |id: Option<NodeId>| id.map(|id| {
+            let n = index.get(id).unwrap();
+            &json[n.start..n.end]
+        });
+        
+        assert_eq!(text_of(index.next_key(0)), Some(r#""a""#));
+        assert_eq!(text_of(index.next_key(1)), Some(r#""b""#));
+        assert_eq!(text_of(index.next_key(10)), Some(r#""c""#));
+        assert_eq!(text_of(index.prev_key(json.len())), Some(r#""d""#));
+        assert_eq!(text_of(index.next_value(1)), Some("1"));
+        assert_eq!(text_of(index.next_value(json.find("\"c\"").unwrap())), Some("[2, 3]"));
+        assert_eq!(text_of(index.prev_value(json.len())), Some(r#""e""#));
+    }
+
+    #[test]
+    fn test_key_value_past_invalid_token() {
+        // The stray token and the missing comma each throw off counting
+        // children in pairs; keys stay keys and values stay values after them
+        for json in [
+            r#"{"a": 1, @@ "b": 2, "c": [3], "d": "e"}"#,
+            r#"{"a": 1 @@, "b": 2, "c": [3], "d": "e"}"#,
+            r#"{"a": 1 "b": 2, "c": [3] "d": "e"}"#,
+        ] {
+            let mut tokenizer = Tokenizer::new(json.to_string());
+            let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+            let classified = |id: NodeId| {
+                let n = index.get(id).unwrap();
+                (&json[n.start..n.end], index.is_key_node(id), index.is_value_node(id))
+            };
+            let children: Vec<_> = index.child_iter(0).map(classified).filter(|(text, ..)| *text != "@@").collect();
+            assert_eq!(children, [
+                (r#""a""#, true, false), ("1", false, true),
+                (r#""b""#, true, false), ("2", false, true),
+                (r#""c""#, true, false), ("[3]", false, true),
+                (r#""d""#, true, false), (r#""e""#, false, true),
+            ], "{}", json);
+            if let Some(stray) = json.find("@@") {
+                let stray = index.node_id_at(stray).unwrap();
+                assert!(!index.is_key_node(stray) && !index.is_value_node(stray), "{}", json);
+            }
+
+            let keys: Vec<_> = std::iter::successors(index.next_key(0), |&id| index.next_key(index.get(id).unwrap().start))
+                .map(|id| classified(id).0)
+                .collect();
+            assert_eq!(keys, [r#""a""#, r#""b""#, r#""c""#, r#""d""#], "{}", json);
+            let d = json.find(r#""d""#).unwrap();
+            assert_eq!(index.next_value(d).map(|id| classified(id).0), Some(r#""e""#), "{}", json);
+
+            let members: Vec<_> = index.member_iter(0).map(|(key, value)| (classified(key).0, value.map(|id| classified(id).0))).collect();
+            assert_eq!(members, [
+                (r#""a""#, Some("1")), (r#""b""#, Some("2")), (r#""c""#, Some("[3]")), (r#""d""#, Some(r#""e""#)),
+            ], "{}", json);
+        }
+
+        // A key with no value before the next
+        let json = r#"{"a": , "b": 2, "c":}"#;
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        let members: Vec<_> = index.member_iter(0).map(|(key, value)| (index.get(key).unwrap().start, value.is_some())).collect();
+        assert_eq!(members, [(1, false), (8, true), (16, false)]);
+    }
+
+    #[test]
+    fn test_key_tagged_across_chunks() {
+        // The colon making a string a key may come in the next chunk
+        let json = r#"{"a": 1, @@ "b": 2}"#;
+        let tokens = Tokenizer::new(json.to_string()).tokenize_all();
+        let colon = tokens.iter().rposition(|t| t.kind == TokenKind::Colon).unwrap();
+        let mut index = StructuralIndex::new();
+        let mut open = OpenContainers::default();
+        index.extend_from_tokens(&tokens[..colon], &mut open);
+        index.extend_from_tokens(&tokens[colon..], &mut open);
+        index.finish(&open);
+
+        let b = index.node_id_at(json.find(r#""b""#).unwrap()).unwrap();
+        assert!(index.is_key_node(b));
+        assert!(index.is_value_node(index.next_sibling(b).unwrap()));
+    }
+
+    #[test]
+    fn test_node_at_matches_containment_scan() {
+        for json in FIXTURES {
+            let mut tokenizer = Tokenizer::new(json.to_string());
+            let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+            
+            for offset in 0..=json.len() {
+                // Innermost containing node is the last one in document order
+                let expected = index.nodes().rposition(|n| n.contains(offset));
+                assert_eq!(index.node_id_at(offset), expected, "node_id_at({}) in {}", offset, json);
+            }
+        }
+    }
+
+    #[test]
+    fn test_memory_usage_within_budget() {
+        // Indexed chunk by chunk, as the editor does, so the arrays grow
+        // the way they do there
+        let (index, _) = index_in_chunks(&crate::parser::fixture(2000), 10, IndexLimits::default());
+        
+        assert!(index.len() > 40_000);
+        let per_node = index.memory_usage() / index.len();
+        assert!(per_node <= BYTES_PER_NODE_BUDGET, "{} bytes per node exceeds budget of {}", per_node, BYTES_PER_NODE_BUDGET);
+    }
+
+    #[test]
+    #[ignore = "needs tests/medium.json from `cargo run --bin generate_test_data`"]
+    fn test_memory_usage_on_generated_fixture() {
+        use std::io::BufRead;
+        
+        // The 100MB fixture tests/generate_test_data.rs writes, indexed in
+        // blocks of whole lines as the editor does, so only the index
+        // itself is held
+        let file = std::fs::File::open("tests/medium.json").expect("tests/medium.json, from generate_test_data");
+        let mut reader = std::io::BufReader::new(file);
+        let mut index = StructuralIndex::new();
+        let mut open = OpenContainers::default();
+        let mut block = Vec::new();
+        let mut base = 0;
+        loop {
+            block.clear();
+            while block.len() < 4 << 20 && reader.read_until(b'\n', &mut block).unwrap() > 0 {}
+            if block.is_empty() {
+                break;
+            }
+            let mut tokenizer = Tokenizer::resume(block.as_slice(), base, open.depth());
+            let tokens: Vec<Token> = std::iter::from_fn(|| tokenizer.next_token()).collect();
+            index.extend_from_tokens(&tokens, &mut open);
+            base += block.len();
+        }
+        index.finish(&open);
+        
+        assert!(index.errors().is_empty());
+        assert!(index.len() > 5_000_000, "{} nodes", index.len());
+        let per_node = index.memory_usage() / index.len();
+        assert!(per_node <= BYTES_PER_NODE_BUDGET, "{} bytes per node exceeds budget of {}", per_node, BYTES_PER_NODE_BUDGET);
+    }
+
+    #[test]
+    fn test_deeply_nested_links() {
+        const DEPTH: usize = 1000;
+        let json = format!("{}{}", "[".repeat(DEPTH), "]".repeat(DEPTH));
+        let mut tokenizer = Tokenizer::new(json);
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        
+        assert_eq!(index.len(), DEPTH);
+        for id in 0..DEPTH {
+            let node = index.get(id).unwrap();
+            assert_eq!(node.depth as usize, id);
+            assert_eq!(node.end, 2 * DEPTH - id);
+            assert_eq!(index.next_sibling(id), None);
+            if id + 1 < DEPTH {
+                assert_eq!(index.first_child(id), Some(id + 1));
+                assert_eq!(index.parent(id + 1), Some(id));
+            } else {
+                assert_eq!(index.first_child(id), None);
+            }
+        }
+        assert_eq!(index.node_id_at(DEPTH - 1), Some(DEPTH - 1));
+        assert_eq!(index.node_id_at(DEPTH), Some(DEPTH - 1));
+    }
+
+    #[test]
+    fn test_extend_matches_one_shot() {
+        let json = FIXTURES.join("\n");
+        let mut tokenizer = Tokenizer::new(json.clone());
+        let one_shot = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        
+        // Split at every line boundary, as the app does when indexing lazily
+        for (split, _) in json.match_indices('\n') {
+            let split = split + 1;
+            let mut open = OpenContainers::default();
+            let mut index = StructuralIndex::new();
+            
+            let mut first = Tokenizer::new(json[..split].to_string());
+            index.extend_from_tokens(&first.tokenize_all(), &mut open);
+            let mut rest = Tokenizer::resume(json.as_bytes()[split..].to_vec(), split, open.depth());
+            index.extend_from_tokens(&rest.tokenize_all(), &mut open);
+            
+            index.finish(&open);
+            assert!(index.nodes().eq(one_shot.nodes()), "split at {}", split);
+            assert_eq!(index.errors(), one_shot.errors(), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_malformed_string_marked_invalid() {
+        let json = r#"{"ok": "fine", "bad": "\q"}"#;
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        
+        let invalid: Vec<&str> = index.nodes()
+            .filter(|n| n.status == ParseStatus::Invalid)
+            .map(|n| &json[n.start..n.end])
+            .collect();
+        assert_eq!(invalid, vec![r#""\q""#]);
+    }
+
+    fn errors_of(json: &str) -> Vec<(usize, StructuralErrorKind)> {
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        index.errors().iter().map(|e| (e.offset, e.kind)).collect()
+    }
+
+    #[test]
+    fn test_well_formed_documents_have_no_errors() {
+        for json in FIXTURES {
+            assert_eq!(errors_of(json), vec![], "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_truncated_documents() {
+        use StructuralErrorKind::*;
+        assert_eq!(errors_of(r#"{"a": [1, 2"#), vec![(0, UnclosedContainer('}')), (6, UnclosedContainer(']'))]);
+        assert_eq!(errors_of(r#"{"a": "hel"#), vec![(0, UnclosedContainer('}')), (6, UnterminatedString)]);
+        assert_eq!(errors_of(r#"{"a":"#), vec![(0, UnclosedContainer('}'))]);
+    }
+
+    #[test]
+    fn test_trailing_content() {
+        use StructuralErrorKind::*;
+        // Reported once, where it starts
+        assert_eq!(errors_of("{\"a\": 1}\n{\"b\": 2}\n[3]\n"), vec![(9, TrailingContent)]);
+        assert_eq!(errors_of("{\"a\": 1} %\n"), vec![(9, TrailingContent), (9, InvalidToken)]);
+        assert_eq!(errors_of("1 2"), vec![(2, TrailingContent)]);
+        // Whitespace after the end is fine
+        assert_eq!(errors_of("{\"a\": 1}\n\n  \n"), vec![]);
+
+        // Read as documents one after another, each is a root of its own
+        let json = "{\"a\": 1}\n{\"b\": 2}\n";
+        let mut index = StructuralIndex::new();
+        let mut open = OpenContainers::default().documents();
+        index.extend_from_tokens(&Tokenizer::new(json.to_string()).tokenize_all(), &mut open);
+        index.finish(&open);
+        assert_eq!((index.errors(), index.root_count()), (&[][..], 2));
+        assert!(StructuralIndex::from_records(json).errors().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_tokens_as_error_nodes() {
+        use StructuralErrorKind::*;
+        fn nodes(json: &str) -> Vec<(NodeKind, &str)> {
+            let index = StructuralIndex::from_tokens(&Tokenizer::new(json.to_string()).tokenize_all());
+            index.nodes().map(|n| (n.kind, &json[n.start..n.end])).collect()
+        }
+
+        // An unterminated string at the end runs to it
+        let json = "[1, \"abc\n";
+        assert_eq!(nodes(json)[2], (NodeKind::Error, "\"abc\n"));
+        assert_eq!(errors_of(json), vec![(0, UnclosedContainer(']')), (4, UnterminatedString)]);
+
+        // A bare word is one node standing for the value, so the members
+        // after it still pair up
+        let json = r#"{"a": foo, "b": 1}"#;
+        assert_eq!(nodes(json)[1..], [
+            (NodeKind::String, r#""a""#),
+            (NodeKind::Error, "foo"),
+            (NodeKind::String, r#""b""#),
+            (NodeKind::Number, "1"),
+        ]);
+        assert_eq!(errors_of(json), vec![(6, InvalidToken)]);
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(json.to_string()).tokenize_all());
+        assert!(index.is_key_node(3));
+        assert_eq!(index.syntax_error_count(), 1);
+        assert!(StructuralErrorKind::InvalidToken.is_syntax() && !MissingComma.is_syntax());
+    }
+
+    #[test]
+    fn test_comma_errors() {
+        use StructuralErrorKind::*;
+        assert_eq!(errors_of("[1,, 2]"), vec![(3, UnexpectedComma)]);
+        assert_eq!(errors_of(r#"{"a": 1,, "b": 2}"#), vec![(8, UnexpectedComma)]);
+        assert_eq!(errors_of("[1, 2,]"), vec![(6, TrailingComma(']'))]);
+        assert_eq!(errors_of(r#"{"a": 1,}"#), vec![(8, TrailingComma('}'))]);
+        assert_eq!(errors_of("[1 2]"), vec![(3, MissingComma)]);
+        assert_eq!(errors_of(r#"{"a": 1 "b": 2}"#), vec![(8, MissingComma)]);
+    }
+
+    #[test]
+    fn test_colon_and_key_errors() {
+        use StructuralErrorKind::*;
+        assert_eq!(errors_of("[1: 2]"), vec![(2, UnexpectedColon)]);
+        assert_eq!(errors_of(r#"{"a" 1}"#), vec![(5, MissingColon)]);
+        assert_eq!(errors_of(r#"{"a"}"#), vec![(4, MissingColon)]);
+        assert_eq!(errors_of(r#"{"a":}"#), vec![(5, MissingValue)]);
+        assert_eq!(errors_of("{1: 2}"), vec![(1, ExpectedKey)]);
+    }
+
+    #[test]
+    fn test_closer_errors() {
+        use StructuralErrorKind::*;
+        assert_eq!(errors_of("}{]]"), vec![(0, UnexpectedCloser('}')), (1, UnclosedContainer('}')), (2, UnexpectedCloser(']')), (3, UnexpectedCloser(']'))]);
+        assert_eq!(errors_of(r#"{"a": [1}"#), vec![(8, MismatchedCloser { expected: ']', found: '}' })]);
+        
+        // The stray closer still closes the object it matches
+        let json = r#"{"a": [1}"#;
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        assert_eq!(index.get(0).unwrap().end, json.len());
+        assert_eq!(index.next_error(0).map(|e| e.offset), Some(8));
+        assert_eq!(index.prev_error(8), None);
+    }
+
+    #[test]
+    fn test_records_have_separate_roots() {
+        use StructuralErrorKind::*;
+        let text = "{\"a\": 1}\n{\"b\": [2\n[3]\n\n\"four\"\n";
+        let index = StructuralIndex::from_records(text);
+        
+        assert_eq!(index.root_count(), 4);
+        let roots: Vec<&str> = (0..index.root_count())
+            .map(|n| index.get(index.nth_root(n).unwrap()).unwrap())
+            .map(|n| &text[n.start..n.end])
+            .collect();
+        assert_eq!(roots, vec!["{\"a\": 1}", "{\"b\": [2", "[3]", "\"four\""]);
+        
+        // The broken second record is reported and closed at its line end
+        let errors: Vec<_> = index.errors().iter().map(|e| (e.offset, e.kind)).collect();
+        assert_eq!(errors, vec![(9, UnclosedContainer('}')), (15, UnclosedContainer(']'))]);
+        
+        let three = text.find("3").unwrap();
+        assert_eq!(index.root_of(index.node_id_at(three).unwrap()), Some((2, index.nth_root(2).unwrap())));
+    }
+
+    #[test]
+    fn test_relaxed_mode_comments_and_trailing_commas() {
+        use StructuralErrorKind::*;
+        let json = "{\n  // comment\n  \"a\": [1, 2,], /* b */\n}";
+        let mut tokenizer = Tokenizer::new(json.to_string()).with_comments(true);
+        let index = StructuralIndex::from_tokens_relaxed(&tokenizer.tokenize_all());
+        
+        assert!(index.errors().is_empty());
+        let warnings: Vec<_> = index.warnings().iter().map(|e| e.kind).collect();
+        assert_eq!(warnings, vec![TrailingComma(']'), TrailingComma('}')]);
+        let kinds: Vec<NodeKind> = index.nodes().map(|n| n.kind).collect();
+        assert_eq!(kinds, vec![NodeKind::Object, NodeKind::String, NodeKind::Array, NodeKind::Number, NodeKind::Number]);
+    }
+
+    #[test]
+    fn test_matching_delimiter() {
+        let json = r#"{"a": [[1], {"b": []}], "s": "[x]"}"#;
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        
+        for (open, close) in [(0, json.len() - 1), (6, 21), (7, 9), (12, 20), (18, 19)] {
+            assert_eq!(index.matching_delimiter(open), Some(close), "open {}", open);
+            assert_eq!(index.matching_delimiter(close), Some(open), "close {}", close);
+        }
+        // Not on a delimiter, or a bracket inside a string
+        assert_eq!(index.matching_delimiter(8), None);
+        assert_eq!(index.matching_delimiter(json.find("[x]").unwrap()), None);
+        
+        // Unclosed containers have no partner
+        let mut tokenizer = Tokenizer::new("[1, [2]".to_string());
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        assert_eq!(index.matching_delimiter(0), None);
+        assert_eq!(index.matching_delimiter(4), Some(6));
+    }
+
+    #[test]
+    fn test_child_count() {
+        let json = r#"{"a": [1, 2, 3], "b": {}, "c": [[]]}"#;
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        let count = |offset| index.node_id_at(offset).and_then(|id| index.child_count(id));
+        
+        assert_eq!(count(0), Some(ChildCount { count: 3, complete: true }));
+        assert_eq!(count(6), Some(ChildCount { count: 3, complete: true }));
+        assert_eq!(count(22), Some(ChildCount { count: 0, complete: true }));
+        assert_eq!(count(31), Some(ChildCount { count: 1, complete: true }));
+        assert_eq!(count(7), None);
+        assert_eq!(index.subtree_size_bytes(0), Some(json.len()));
+        
+        // Only part of the array has been indexed
+        let partial = "[1, 2, 3, 4";
+        let mut tokenizer = Tokenizer::new(partial.to_string());
+        let mut index = StructuralIndex::default();
+        index.extend_from_tokens(&tokenizer.tokenize_all(), &mut OpenContainers::default());
+        let count = index.child_count(0).unwrap();
+        assert_eq!((count.count, count.complete), (4, false));
+        assert_eq!(count.to_string(), "4+");
+        assert_eq!(ChildCount { count: 38112, complete: true }.to_string(), "38,112");
+    }
+
+    #[test]
+    fn test_last_and_nth_child() {
+        let json = r#"[10, [], 30, {"a": 1}]"#;
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        let index = StructuralIndex::from_tokens(&tokenizer.tokenize_all());
+        let start = |id: Option<NodeId>| id.and_then(|id| index.get(id)).map(|n| n.start);
+
+        assert_eq!(start(index.last_child(0)), Some(13));
+        assert_eq!(start(index.nth_child(0, 0)), Some(1));
+        assert_eq!(start(index.nth_child(0, 2)), Some(9));
+        assert_eq!(index.nth_child(0, 4), None);
+        let empty = index.node_id_at(5).unwrap();
+        assert_eq!((index.last_child(empty), index.nth_child(empty, 0)), (None, None));
+        assert_eq!(index.last_child(1), None);
+        assert_eq!(index.sibling_position(index.last_child(0).unwrap()), 3);
+    }
+
+    #[test]
+    fn test_apply_change() {
+        let index_of = |text: &str| StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let spans = |index: &StructuralIndex| -> Vec<(NodeKind, usize, usize)> {
+            (0..index.len()).filter_map(|id| index.get(id)).map(|n| (n.kind, n.start, n.end)).collect()
+        };
+
+        // Edits that keep the structure leave the index as a reindex would
+        let mut text = String::from(r#"{"a": 1, "b": ["xy", null]}"#);
+        let mut index = index_of(&text);
+        assert!(!index.is_dirty());
+        for (offset, removed, inserted) in [(17, 0, "abc"), (6, 1, "123"), (0, 0, "  "), (19, 0, " ")] {
+            text.replace_range(offset..offset + removed, inserted);
+            index.apply_change(&TextChange { offset, removed, inserted: inserted.len() });
+            assert_eq!(spans(&index), spans(&index_of(&text)), "after editing {:?}", text);
+        }
+        assert_eq!(text, r#"  {"a": 123, "b": [ "xabcy", null]}"#);
+        assert_eq!(index.dirty_spans(), [0..2, 8..11, 19..20, 22..25]);
+        assert!(index.touches_dirty(11..15));
+        assert!(!index.touches_dirty(12..18));
+
+        // Nesting around the edit grows with it; earlier nodes that end
+        // before it are left as they are
+        let mut text = String::from(r#"[{"a": [1]}, {"b": [2, {"c": "xy"}]}, 3] [4]"#);
+        let mut index = index_of(&text);
+        for (offset, removed, inserted) in [(31, 0, "abc"), (33, 2, ""), (8, 1, "10")] {
+            text.replace_range(offset..offset + removed, inserted);
+            index.apply_change(&TextChange { offset, removed, inserted: inserted.len() });
+            assert_eq!(spans(&index), spans(&index_of(&text)), "after editing {:?}", text);
+        }
+
+        // Errors move with the text after them
+        let mut index = index_of("[1, 2");
+        assert_eq!(index.first_error().map(|e| e.offset), Some(0));
+        index.apply_change(&TextChange { offset: 0, removed: 0, inserted: 3 });
+        assert_eq!(index.first_error().map(|e| e.offset), Some(3));
+        assert_eq!(index.get(0).map(|n| (n.start, n.end)), Some((3, 8)));
+    }
+
+    #[test]
+    fn test_staleness() {
+        let index_of = |text: &str| StructuralIndex::from_tokens(&Tokenizer::new(text.to_string()).tokenize_all());
+        let edit = |index: &mut StructuralIndex, text: &mut String, offset: usize, removed: usize, inserted: &str| {
+            text.replace_range(offset..offset + removed, inserted);
+            index.apply_change(&TextChange { offset, removed, inserted: inserted.len() });
+            index.recheck_strings(|span| text[span].to_string());
+        };
+
+        // Typing inside a string only shifts what's after it
+        let mut text = String::from(r#"{"a": "xy", "b": [1, 2]}"#);
+        let mut index = index_of(&text);
+        assert_eq!(index.staleness(), Staleness::Clean);
+        edit(&mut index, &mut text, 8, 0, "abc");
+        assert_eq!(index.staleness(), Staleness::Shifted);
+        edit(&mut index, &mut text, 2, 0, "k");
+        assert_eq!(index.staleness(), Staleness::Shifted);
+        assert!((0..index.len()).all(|id| !index.lands_in_dirty(id)));
+
+        // A quote typed into a string splits it
+        edit(&mut index, &mut text, 9, 0, "\"");
+        assert_eq!(text, r#"{"ka": "x"abcy", "b": [1, 2]}"#);
+        assert_eq!(index.staleness(), Staleness::DirtyRanges);
+
+        // So does taking away the backslash before an escaped quote
+        let mut text = String::from(r#"["a\"b", 1]"#);
+        let mut index = index_of(&text);
+        edit(&mut index, &mut text, 3, 1, "");
+        assert_eq!(index.staleness(), Staleness::DirtyRanges);
+
+        // Edits outside strings may change the structure; only nodes
+        // starting in what they touched are off limits
+        let mut text = String::from("[1, 2, 3]");
+        let mut index = index_of(&text);
+        edit(&mut index, &mut text, 4, 1, "[2");
+        assert_eq!(index.staleness(), Staleness::DirtyRanges);
+        let landable: Vec<bool> = (0..index.len()).map(|id| !index.lands_in_dirty(id)).collect();
+        assert_eq!(landable, [true, true, false, true]);
+
+        assert_eq!(Staleness::Shifted.badge(), None);
+        assert_eq!(Staleness::DirtyRanges.badge(), Some("!"));
+        assert_eq!(Staleness::Rebuilding.badge(), Some("⟳"));
+    }
+
+    #[test]
+    fn test_reads_as_one_string() {
+        assert!(reads_as_one_string(r#""a b""#));
+        assert!(reads_as_one_string(r#""a \" b""#));
+        assert!(reads_as_one_string(r#""""#));
+        assert!(!reads_as_one_string(r#"""#));
+        assert!(!reads_as_one_string(r#""a" "b""#));
+        assert!(!reads_as_one_string(r#""a\""#));
+        assert!(!reads_as_one_string("\"a\nb\""));
+    }
+
+    /// Index `text` in `chunks` pieces of about the same number of tokens
+    fn index_in_chunks(text: &str, chunks: usize, limits: IndexLimits) -> (StructuralIndex, OpenContainers) {
+        let tokens = Tokenizer::new(text.to_string()).tokenize_all();
+        let mut open = OpenContainers::default().with_limits(limits);
+        let mut index = StructuralIndex::new();
+        for piece in tokens.chunks(tokens.len().div_ceil(chunks).max(1)) {
+            assert!(index.apply_chunk(StructuralIndex::build_chunk(piece, &mut open, index.len())));
+        }
+        index.finish(&open);
+        (index, open)
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        use StructuralErrorKind::*;
+        let limits = IndexLimits { max_depth: 3, max_nodes: usize::MAX };
+        // The part nested too deep stands in as one value in its parent,
+        // split across chunks or not
+        let text = r#"[1, [[{"a": [[], {"b": 2}]}]], 3, [{}]]"#;
+        for chunks in [1, 3, 7] {
+            let (index, open) = index_in_chunks(text, chunks, limits);
+            assert!(index.errors().is_empty(), "in {} chunks: {:?}", chunks, index.errors());
+            let warnings: Vec<_> = index.warnings().iter().map(|e| (e.offset, e.kind)).collect();
+            assert_eq!(warnings, [(6, TooDeep(3))]);
+            assert_eq!(open.limit_hit().map(|e| e.offset), Some(6));
+            assert_eq!(open.depth(), 0);
+            let depths: Vec<_> = index.nodes().map(|n| (n.kind, n.depth)).collect();
+            assert_eq!(depths, [
+                (NodeKind::Array, 0), (NodeKind::Number, 1), (NodeKind::Array, 1), (NodeKind::Array, 2),
+                (NodeKind::Number, 1), (NodeKind::Array, 1), (NodeKind::Object, 2),
+            ]);
+            assert_eq!(index.child_iter(0).count(), 4);
+        }
+
+        // A million opening brackets: the stack and the index stop at the limit
+        let text = "[".repeat(1_000_000);
+        let (index, _) = index_in_chunks(&text, 10, IndexLimits::default());
+        assert_eq!(index.len(), DEFAULT_MAX_DEPTH as usize);
+        assert_eq!(index.errors().len(), DEFAULT_MAX_DEPTH as usize);
+        assert_eq!(index.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_node_limit() {
+        let limits = IndexLimits { max_depth: DEFAULT_MAX_DEPTH, max_nodes: 5 };
+        let text = "[1, 2, [3, 4, 5], 6]";
+        for chunks in [1, 4] {
+            let (index, open) = index_in_chunks(text, chunks, limits);
+            assert_eq!(index.len(), 5);
+            // Whether anything closes past the limit isn't known, so it's not an error
+            assert!(index.errors().is_empty());
+            assert_eq!(index.warnings().iter().map(|e| (e.offset, e.kind)).collect::<Vec<_>>(), [(11, StructuralErrorKind::TooManyNodes(5))]);
+            assert_eq!(open.limit_hit().map(|e| e.offset), Some(11));
+            assert_eq!(index.get(0).map(|n| n.end), Some(11));
+        }
+        assert_eq!(IndexLimits::new(100, 1).max_nodes, 1024 * 1024 / BYTES_PER_NODE_BUDGET);
+
+        // Generated junk of brackets, commas and scalars never panics, and
+        // never indexes past the limits
+        let mut seed = 7u64;
+        let pieces = ["[", "{", "]", "}", ",", ":", "1", "\"x\"", "null", " "];
+        for _ in 0..50 {
+            let text: String = (0..2000)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    pieces[(seed >> 33) as usize % pieces.len()]
+                })
+                .collect();
+            let limits = IndexLimits { max_depth: 8, max_nodes: 300 };
+            let (index, _) = index_in_chunks(&text, 5, limits);
+            assert!(index.len() <= 300);
+            assert!(index.nodes().all(|n| n.depth <= 8));
+        }
+    }
 }
@@ -14,6 +14,7 @@ pub enum TokenKind {
     False,          // false
     Null,           // null
     Whitespace,     // spaces, newlines, tabs
+    Comment,        // // line or /* block */ comment (relaxed mode only)
     Invalid,        // parse error
 }
 
@@ -32,17 +33,44 @@ impl fmt::Display for TokenKind {
             TokenKind::False => write!(f, "false"),
             TokenKind::Null => write!(f, "null"),
             TokenKind::Whitespace => write!(f, "Whitespace"),
+            TokenKind::Comment => write!(f, "Comment"),
             TokenKind::Invalid => write!(f, "Invalid"),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Problem found inside an otherwise well-delimited token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenIssue {
+    #[default]
+    None,
+    InvalidEscape,      // backslash followed by something other than "\/bfnrtu
+    BadUnicodeEscape,   // \u not followed by 4 hex digits
+    LoneSurrogate,      // \uD800-\uDFFF without its other half
+    UnterminatedString, // Invalid: a string the input ends inside
+    UnterminatedComment, // Invalid: a block comment the input ends inside
+}
+
+impl fmt::Display for TokenIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenIssue::None => write!(f, "no issue"),
+            TokenIssue::InvalidEscape => write!(f, "invalid escape sequence"),
+            TokenIssue::BadUnicodeEscape => write!(f, "malformed \\u escape"),
+            TokenIssue::LoneSurrogate => write!(f, "unpaired UTF-16 surrogate"),
+            TokenIssue::UnterminatedString => write!(f, "unterminated string"),
+            TokenIssue::UnterminatedComment => write!(f, "unterminated comment"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Token {
     pub kind: TokenKind,
     pub start: usize,    // byte offset
     pub end: usize,      // byte offset (exclusive)
     pub depth: u32,      // nesting depth
+    pub issue: TokenIssue,
 }
 
 impl Token {
@@ -52,9 +80,15 @@ impl Token {
             start,
             end,
             depth,
+            issue: TokenIssue::None,
         }
     }
 
+    pub fn with_issue(mut self, issue: TokenIssue) -> Self {
+        self.issue = issue;
+        self
+    }
+
     pub fn len(&self) -> usize {
         self.end - self.start
     }
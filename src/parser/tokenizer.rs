@@ -1,4 +1,6 @@
-use super::token::{Token, TokenKind};
+use std::borrow::Cow;
+
+use super::token::{Token, TokenIssue, TokenKind};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(dead_code)]
@@ -12,33 +14,98 @@ enum State {
     InNull(u8),     // Position in "null"
 }
 
-pub struct Tokenizer {
-    input: Vec<u8>,
+/// Lexes JSON from bytes it owns, or borrows: `from_slice` tokenizes a
+/// region of a file's memory map without copying it out first
+pub struct Tokenizer<'a> {
+    input: Cow<'a, [u8]>,
     pos: usize,
     #[allow(dead_code)]
     state: State,
     depth: u32,
+    /// Absolute byte offset of `input[0]`, added to every token position
+    base_offset: usize,
+    /// False while streaming and more input may still be fed
+    complete: bool,
+    /// Accept // and /* */ comments (JSONC)
+    comments: bool,
 }
 
-impl Tokenizer {
+impl<'a> Tokenizer<'a> {
     pub fn new(input: String) -> Self {
+        Self::from_bytes(input.into_bytes())
+    }
+
+    pub fn from_bytes(input: Vec<u8>) -> Self {
+        Self::resume(input, 0, 0)
+    }
+
+    /// Tokenize `input` where it is, without a copy
+    pub fn from_slice(input: &'a [u8]) -> Self {
+        Self::resume(input, 0, 0)
+    }
+
+    /// Continue tokenizing a document from the middle: `input`, owned or
+    /// borrowed, starts at absolute byte `base_offset`, inside `depth` open
+    /// containers
+    pub fn resume(input: impl Into<Cow<'a, [u8]>>, base_offset: usize, depth: u32) -> Self {
         Self {
-            input: input.into_bytes(),
+            input: input.into(),
             pos: 0,
             state: State::Start,
-            depth: 0,
+            depth,
+            base_offset,
+            complete: true,
+            comments: false,
         }
     }
 
-    pub fn from_bytes(input: Vec<u8>) -> Self {
+    /// Create a tokenizer whose input arrives in pieces via `feed`. A token
+    /// cut off at the end of the fed input is held back until more input
+    /// arrives or `finish` is called, so the token stream is the same no
+    /// matter where the input was split.
+    pub fn streaming() -> Self {
         Self {
-            input,
-            pos: 0,
-            state: State::Start,
-            depth: 0,
+            complete: false,
+            ..Self::from_bytes(Vec::new())
         }
     }
 
+    /// Accept `//` and `/* */` comments, emitted as `Comment` tokens.
+    /// Strict JSON (no comments) is the default.
+    pub fn with_comments(mut self, comments: bool) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    /// Append more input to a streaming tokenizer
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // Drop what has already been tokenized so the buffer stays small
+        let input = self.input.to_mut();
+        input.drain(..self.pos);
+        input.extend_from_slice(bytes);
+        self.base_offset += self.pos;
+        self.pos = 0;
+    }
+
+    /// The text of a token `next_token` returned, while it's still
+    /// buffered: until the next `feed`
+    pub fn token_bytes(&self, token: &Token) -> &[u8] {
+        let start = token.start.saturating_sub(self.base_offset).min(self.input.len());
+        let end = token.end.saturating_sub(self.base_offset).clamp(start, self.input.len());
+        &self.input[start..end]
+    }
+
+    /// Mark the end of input: a held-back partial token is emitted as-is
+    pub fn finish(&mut self) {
+        self.complete = true;
+    }
+
+    /// True when `next_token` returned `None` only because the input ended
+    /// mid-stream; feed more bytes (or `finish`) and call it again
+    pub fn needs_input(&self) -> bool {
+        !self.complete
+    }
+
     fn peek(&self) -> Option<u8> {
         if self.pos < self.input.len() {
             Some(self.input[self.pos])
@@ -78,28 +145,87 @@ impl Tokenizer {
     fn tokenize_string(&mut self, start: usize) -> Token {
         // Consume opening quote
         self.advance();
+        let mut issue = TokenIssue::None;
         
         loop {
             match self.advance() {
                 Some(b'"') => {
                     // End of string
-                    return Token::new(TokenKind::String, start, self.pos, self.depth);
+                    return Token::new(TokenKind::String, start, self.pos, self.depth).with_issue(issue);
                 }
                 Some(b'\\') => {
-                    // Escape sequence - consume next character
-                    self.advance();
+                    // Escapes are rare, so validation stays off the common path
+                    let escape_issue = self.validate_escape();
+                    if issue == TokenIssue::None {
+                        issue = escape_issue;
+                    }
                 }
                 Some(_) => {
                     // Regular character, continue
                 }
                 None => {
                     // Unexpected end of input
-                    return Token::new(TokenKind::Invalid, start, self.pos, self.depth);
+                    return Token::new(TokenKind::Invalid, start, self.pos, self.depth)
+                        .with_issue(TokenIssue::UnterminatedString);
+                }
+            }
+        }
+    }
+
+    /// Check the escape sequence following a backslash, consuming it
+    fn validate_escape(&mut self) -> TokenIssue {
+        match self.peek() {
+            Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => {
+                self.advance();
+                TokenIssue::None
+            }
+            Some(b'u') => {
+                self.advance();
+                let Some(unit) = self.read_hex4() else {
+                    return TokenIssue::BadUnicodeEscape;
+                };
+                match unit {
+                    // High surrogate: must be followed by \uDC00-\uDFFF
+                    0xD800..=0xDBFF => {
+                        let rest = &self.input[self.pos..];
+                        if rest.len() >= 2 && rest[0] == b'\\' && rest[1] == b'u' {
+                            let save = self.pos;
+                            self.pos += 2;
+                            match self.read_hex4() {
+                                Some(0xDC00..=0xDFFF) => return TokenIssue::None,
+                                Some(_) => {}
+                                None => return TokenIssue::BadUnicodeEscape,
+                            }
+                            // Leave the following escape to be checked on its own
+                            self.pos = save;
+                        }
+                        TokenIssue::LoneSurrogate
+                    }
+                    0xDC00..=0xDFFF => TokenIssue::LoneSurrogate,
+                    _ => TokenIssue::None,
                 }
             }
+            // End of input is reported by the caller as an unterminated string
+            None => TokenIssue::None,
+            Some(_) => {
+                self.advance();
+                TokenIssue::InvalidEscape
+            }
         }
     }
 
+    /// Consume exactly four hex digits, stopping early (without consuming
+    /// the offending byte) if one is missing
+    fn read_hex4(&mut self) -> Option<u16> {
+        let mut value = 0u16;
+        for _ in 0..4 {
+            let digit = (self.peek()? as char).to_digit(16)?;
+            self.advance();
+            value = value * 16 + digit as u16;
+        }
+        Some(value)
+    }
+
     fn tokenize_number(&mut self, start: usize) -> Token {
         // Consume optional minus
         if self.peek() == Some(b'-') {
@@ -155,6 +281,36 @@ impl Tokenizer {
         }
     }
 
+    fn tokenize_comment(&mut self, start: usize) -> Token {
+        // Consume the slash
+        self.advance();
+        
+        match self.advance() {
+            Some(b'/') => {
+                // Line comment runs up to (not including) the newline
+                while let Some(ch) = self.peek() {
+                    if ch == b'\n' {
+                        break;
+                    }
+                    self.advance();
+                }
+                Token::new(TokenKind::Comment, start, self.pos, self.depth)
+            }
+            Some(b'*') => {
+                let mut prev = 0;
+                while let Some(ch) = self.advance() {
+                    if prev == b'*' && ch == b'/' {
+                        return Token::new(TokenKind::Comment, start, self.pos, self.depth);
+                    }
+                    prev = ch;
+                }
+                // Unterminated block comment
+                Token::new(TokenKind::Invalid, start, self.pos, self.depth).with_issue(TokenIssue::UnterminatedComment)
+            }
+            _ => Token::new(TokenKind::Invalid, start, self.pos, self.depth),
+        }
+    }
+
     fn match_keyword(&mut self, start: usize, keyword: &[u8], kind: TokenKind) -> Token {
         for &expected in keyword {
             match self.advance() {
@@ -166,11 +322,32 @@ impl Tokenizer {
     }
 
     pub fn next_token(&mut self) -> Option<Token> {
-        // Skip whitespace
-        if let Some(ws_token) = self.skip_whitespace() {
-            return Some(ws_token);
+        let mut token = self.scan_token()?;
+        token.start += self.base_offset;
+        token.end += self.base_offset;
+        Some(token)
+    }
+
+    fn scan_token(&mut self) -> Option<Token> {
+        let token = match self.skip_whitespace() {
+            Some(ws_token) => ws_token,
+            None => self.scan_non_whitespace()?,
+        };
+        
+        // Whitespace, numbers, comments and unterminated strings or keywords that run
+        // into the end of the input could continue in the next piece
+        if !self.complete
+            && self.pos == self.input.len()
+            && matches!(token.kind, TokenKind::Whitespace | TokenKind::Number | TokenKind::Comment | TokenKind::Invalid)
+        {
+            self.pos = token.start;
+            return None;
         }
+        
+        Some(token)
+    }
 
+    fn scan_non_whitespace(&mut self) -> Option<Token> {
         let start = self.pos;
         let ch = self.peek()?;
 
@@ -208,6 +385,7 @@ impl Tokenizer {
             b't' => self.match_keyword(start, b"true", TokenKind::True),
             b'f' => self.match_keyword(start, b"false", TokenKind::False),
             b'n' => self.match_keyword(start, b"null", TokenKind::Null),
+            b'/' if self.comments => self.tokenize_comment(start),
             _ => {
                 self.advance();
                 Token::new(TokenKind::Invalid, start, self.pos, self.depth)
@@ -325,4 +503,133 @@ mod tests {
             assert_eq!(tokens[0].kind, TokenKind::Number, "Failed for input: {}", input);
         }
     }
+
+    #[test]
+    fn test_streaming_split_anywhere() {
+        let input = r#"{"name": "a\"b\\", "n": [-12.5e+3, 0, 7], "t": true, "f": false, "z": null}  "#;
+        let mut tokenizer = Tokenizer::new(input.to_string());
+        let expected = tokenizer.tokenize_all();
+        
+        for split in 0..=input.len() {
+            let mut tokenizer = Tokenizer::streaming();
+            tokenizer.feed(&input.as_bytes()[..split]);
+            let mut tokens = tokenizer.tokenize_all();
+            tokenizer.feed(&input.as_bytes()[split..]);
+            tokens.extend(tokenizer.tokenize_all());
+            tokenizer.finish();
+            tokens.extend(tokenizer.tokenize_all());
+            
+            assert_eq!(tokens, expected, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_streaming_holds_partial_token() {
+        let mut tokenizer = Tokenizer::streaming();
+        tokenizer.feed(br#"["abc"#);
+        assert_eq!(tokenizer.next_token().map(|t| t.kind), Some(TokenKind::BracketOpen));
+        assert_eq!(tokenizer.next_token(), None);
+        assert!(tokenizer.needs_input());
+        
+        tokenizer.feed(br#"def"]"#);
+        let string = tokenizer.next_token().unwrap();
+        assert_eq!((string.kind, string.start, string.end), (TokenKind::String, 1, 9));
+        
+        // An unterminated string is only reported once the input is finished
+        tokenizer.feed(br#""tail"#);
+        assert_eq!(tokenizer.next_token().map(|t| t.kind), Some(TokenKind::BracketClose));
+        assert_eq!(tokenizer.next_token(), None);
+        tokenizer.finish();
+        assert_eq!(tokenizer.next_token().map(|t| t.kind), Some(TokenKind::Invalid));
+    }
+
+    fn string_issue(input: &str) -> (TokenKind, TokenIssue) {
+        let mut tokenizer = Tokenizer::new(input.to_string());
+        let token = tokenizer.next_token().unwrap();
+        assert_eq!(token.end, input.len(), "token should span {}", input);
+        (token.kind, token.issue)
+    }
+
+    #[test]
+    fn test_valid_escapes() {
+        for input in [r#""\" \\ \/ \b \f \n \r \t""#, r#""é€""#, r#""😀""#] {
+            assert_eq!(string_issue(input), (TokenKind::String, TokenIssue::None), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_invalid_escape() {
+        assert_eq!(string_issue(r#""\x41""#), (TokenKind::String, TokenIssue::InvalidEscape));
+        assert_eq!(string_issue(r#""\'""#), (TokenKind::String, TokenIssue::InvalidEscape));
+    }
+
+    #[test]
+    fn test_bad_unicode_escape() {
+        assert_eq!(string_issue(r#""\u12""#), (TokenKind::String, TokenIssue::BadUnicodeEscape));
+        assert_eq!(string_issue(r#""\uXYZW""#), (TokenKind::String, TokenIssue::BadUnicodeEscape));
+        assert_eq!(string_issue(r#""\ud83d\u12""#), (TokenKind::String, TokenIssue::BadUnicodeEscape));
+    }
+
+    #[test]
+    fn test_lone_surrogates() {
+        assert_eq!(string_issue(r#""\ud83d""#), (TokenKind::String, TokenIssue::LoneSurrogate));
+        assert_eq!(string_issue(r#""\ud83dx""#), (TokenKind::String, TokenIssue::LoneSurrogate));
+        assert_eq!(string_issue(r#""\ud83dA""#), (TokenKind::String, TokenIssue::LoneSurrogate));
+        assert_eq!(string_issue(r#""\ude00""#), (TokenKind::String, TokenIssue::LoneSurrogate));
+    }
+
+    fn kinds_relaxed(input: &str) -> Vec<TokenKind> {
+        let mut tokenizer = Tokenizer::new(input.to_string()).with_comments(true);
+        tokenizer.tokenize_all().iter()
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .map(|t| t.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_comments_rejected_in_strict_mode() {
+        let mut tokenizer = Tokenizer::new("// hi".to_string());
+        assert_eq!(tokenizer.next_token().map(|t| t.kind), Some(TokenKind::Invalid));
+    }
+
+    #[test]
+    fn test_line_comment() {
+        assert_eq!(kinds_relaxed("1 // one\n2"), vec![TokenKind::Number, TokenKind::Comment, TokenKind::Number]);
+        
+        let mut tokenizer = Tokenizer::new("// a \"string\" {\n".to_string()).with_comments(true);
+        let comment = tokenizer.next_token().unwrap();
+        assert_eq!((comment.kind, comment.end), (TokenKind::Comment, 15));
+    }
+
+    #[test]
+    fn test_block_comment() {
+        assert_eq!(kinds_relaxed("[/* a\n * b */1/**/]"), vec![
+            TokenKind::BracketOpen,
+            TokenKind::Comment,
+            TokenKind::Number,
+            TokenKind::Comment,
+            TokenKind::BracketClose,
+        ]);
+        assert_eq!(kinds_relaxed("/* never closed"), vec![TokenKind::Invalid]);
+        assert_eq!(kinds_relaxed("/x"), vec![TokenKind::Invalid]);
+    }
+
+    #[test]
+    fn test_streaming_split_inside_comment() {
+        let input = "{/* block */\"a\": 1 // line\n}";
+        let mut tokenizer = Tokenizer::new(input.to_string()).with_comments(true);
+        let expected = tokenizer.tokenize_all();
+        
+        for split in 0..=input.len() {
+            let mut tokenizer = Tokenizer::streaming().with_comments(true);
+            tokenizer.feed(&input.as_bytes()[..split]);
+            let mut tokens = tokenizer.tokenize_all();
+            tokenizer.feed(&input.as_bytes()[split..]);
+            tokens.extend(tokenizer.tokenize_all());
+            tokenizer.finish();
+            tokens.extend(tokenizer.tokenize_all());
+            
+            assert_eq!(tokens, expected, "split at {}", split);
+        }
+    }
 }
@@ -0,0 +1,220 @@
+//! Indexing just the text around the cursor, for structural moves deep in
+//! a file the index hasn't reached: it's built from the top, so after `G`
+//! in a multi-GB file the rest would take minutes, and may run past the
+//! index's size limit first. A window reads about `WINDOW_BYTES` either
+//! side of the cursor and starts indexing at the most likely place a member
+//! of a container begins, such as an array element after `},` at the least
+//! indentation in sight. What's outside the window, the container's start
+//! and everything above it included, is unknown, so the result is
+//! approximate and the editor says so.
+
+use std::ops::Range;
+
+use super::format::DocumentFormat;
+use super::node::NodeId;
+use super::structural_index::{IndexChunk, OpenContainers, StructuralIndex};
+use super::tokenizer::Tokenizer;
+
+/// Bytes read either side of the cursor
+pub const WINDOW_BYTES: usize = 1 << 20;
+
+/// The container around a window's first member, when it stands in as
+/// node 0 of the window's index
+pub const WINDOW_CONTAINER: NodeId = 0;
+
+#[derive(Debug, Clone)]
+pub struct IndexWindow {
+    /// The bytes read
+    pub span: Range<usize>,
+    /// Where indexing started
+    pub sync: usize,
+    pub index: StructuralIndex,
+    /// Whether node 0 stands in for a container whose start wasn't read;
+    /// not so for JSON Lines, whose records are indexed as they are
+    pub contained: bool,
+}
+
+impl IndexWindow {
+    /// Index `text`, which starts at a line start at byte `base` of the
+    /// document, from the best place to start at or before byte `cursor`.
+    /// None when nothing in it looks like where a member starts.
+    pub fn build(text: &[u8], base: usize, cursor: usize, format: DocumentFormat) -> Option<Self> {
+        let span = base..base + text.len();
+        let before = cursor.checked_sub(base)?.min(text.len());
+        let relaxed = format.is_relaxed();
+        if format == DocumentFormat::JsonLines {
+            // Every line starts a record
+            let mut open = OpenContainers::default();
+            let mut chunk = IndexChunk::new(0);
+            chunk.push_records(text, base, &mut open);
+            let mut index = StructuralIndex::new();
+            index.apply_chunk(chunk);
+            return Some(Self { span, sync: base, index, contained: false });
+        }
+        let (sync, is_object) = sync_point(text, before)?;
+        let tokens = Tokenizer::resume(&text[sync..], base + sync, 1).with_comments(relaxed).tokenize_all();
+        let index = StructuralIndex::from_members(&tokens, base + sync, is_object, relaxed);
+        Some(Self { span, sync: base + sync, index, contained: true })
+    }
+
+    /// Whether moves from `offset` can go on using this window: it's
+    /// inside, and not so near an edge that the next member may be past it.
+    /// `len` is the document's length, which the window may reach.
+    pub fn reaches(&self, offset: usize, len: usize) -> bool {
+        let margin = WINDOW_BYTES / 2;
+        (self.span.start == 0 || offset >= self.span.start + margin)
+            && (self.span.end >= len || offset + margin <= self.span.end)
+            && offset >= self.sync
+    }
+
+    /// Whether landing on `node` would go somewhere the window doesn't know
+    /// about: the start of the container it stands in for
+    pub fn is_outside(&self, node: NodeId) -> bool {
+        self.contained && node == WINDOW_CONTAINER
+    }
+}
+
+/// Where in `text` a member of a container most plausibly starts, no later
+/// than `before`, and whether the container is an object. A member starts
+/// after a comma that follows a complete value: `{` or `[` after `},` or
+/// `],` is an array element, and a string followed by a colon an object's
+/// key. Of those, the one at the start of a line with the least indentation
+/// is the shallowest; minified text, with no lines to go by, starts at the
+/// first. Array elements win ties.
+fn sync_point(text: &[u8], before: usize) -> Option<(usize, bool)> {
+    let mut best: Option<((usize, bool), usize)> = None;
+    for pos in 0..before.min(text.len()) {
+        let byte = text[pos];
+        if !matches!(byte, b'{' | b'[' | b'"') {
+            continue;
+        }
+        let Some(comma) = prev_solid(text, pos).filter(|&comma| text[comma] == b',') else {
+            continue;
+        };
+        let Some(value_end) = prev_solid(text, comma) else {
+            continue;
+        };
+        let is_object = match byte {
+            b'{' | b'[' if matches!(text[value_end], b'}' | b']') => false,
+            b'"' if ends_value(text[value_end]) && is_key(text, pos) => true,
+            _ => continue,
+        };
+        let rank = (indent(text, pos).unwrap_or(usize::MAX), is_object);
+        if best.is_none_or(|(best, _)| rank < best) {
+            best = Some((rank, pos));
+        }
+        // Nothing can start a line with less than no indentation
+        if rank == (0, false) {
+            break;
+        }
+    }
+    best.map(|((_, is_object), pos)| (pos, is_object))
+}
+
+/// The last byte before `pos` that isn't whitespace
+fn prev_solid(text: &[u8], pos: usize) -> Option<usize> {
+    text[..pos].iter().rposition(|byte| !byte.is_ascii_whitespace())
+}
+
+/// Whether a value can end with `byte`
+fn ends_value(byte: u8) -> bool {
+    matches!(byte, b'"' | b'}' | b']' | b'e' | b'l') || byte.is_ascii_digit()
+}
+
+/// Whether the string starting at `pos` is followed by a colon
+fn is_key(text: &[u8], pos: usize) -> bool {
+    let mut escaped = false;
+    for (i, &byte) in text.iter().enumerate().skip(pos + 1) {
+        match (escaped, byte) {
+            (true, _) => escaped = false,
+            (false, b'\\') => escaped = true,
+            (false, b'\n') => return false,
+            (false, b'"') => {
+                return text[i + 1..].iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b':');
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Columns of indentation before `pos`, when nothing else comes before it
+/// on its line
+fn indent(text: &[u8], pos: usize) -> Option<usize> {
+    let line_start = text[..pos].iter().rposition(|&byte| byte == b'\n').map_or(0, |newline| newline + 1);
+    text[line_start..pos].iter().all(|&byte| byte == b' ' || byte == b'\t').then_some(pos - line_start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mode::StructuralNavAction;
+    use crate::navigation;
+    use crate::parser::fixture;
+
+    /// The window around `cursor` as the editor reads it, from a line start
+    fn window(text: &str, cursor: usize, format: DocumentFormat) -> IndexWindow {
+        let from = cursor.saturating_sub(WINDOW_BYTES);
+        let base = text[..from].rfind('\n').map_or(0, |newline| newline + 1);
+        let end = (cursor + WINDOW_BYTES).min(text.len());
+        IndexWindow::build(&text.as_bytes()[base..end], base, cursor, format).unwrap()
+    }
+
+    /// Where a move from `offset` lands in the window, as the text there
+    fn land(text: &str, window: &IndexWindow, action: StructuralNavAction, offset: usize) -> Option<String> {
+        let current = window.index.node_id_at(offset);
+        let target = navigation::target(&window.index, action, current, offset, 1)?;
+        let node = window.index.get(target)?;
+        Some(text[node.start..node.end.min(node.start + 20)].to_string())
+    }
+
+    #[test]
+    fn test_end_of_array() {
+        // About 5 MB: the window reads well under half of it
+        let items = 20_000;
+        let text = fixture(items);
+        let last = text.rfind("  {\n    \"id\": ").unwrap();
+        let cursor = last + 8;
+        let window = window(&text, cursor, DocumentFormat::Json);
+        assert!(window.span.start > text.len() / 2);
+        assert!(window.index.len() < StructuralIndex::from_tokens(&Tokenizer::new(text.clone()).tokenize_all()).len() / 2);
+
+        // From the last item's key up to the item and along its siblings
+        assert_eq!(land(&text, &window, StructuralNavAction::Parent, cursor).as_deref(), Some("{\n    \"id\": 19999,\n "));
+        let before = text[..last].rfind("  {\n").unwrap() + 2;
+        assert_eq!(land(&text, &window, StructuralNavAction::PrevSibling, last + 2).as_deref(), Some("{\n    \"id\": 19998,\n "));
+        assert_eq!(land(&text, &window, StructuralNavAction::NextSibling, before).as_deref(), Some("{\n    \"id\": 19999,\n "));
+        assert_eq!(land(&text, &window, StructuralNavAction::NextSibling, last + 2), None);
+        // The array is closed in the window, but where it starts isn't known
+        let array = window.index.parent(window.index.node_id_at(last + 2).unwrap()).unwrap();
+        assert!(window.is_outside(array));
+        assert!(window.index.is_closed(array));
+
+        assert!(window.reaches(cursor, text.len()));
+        assert!(!window.reaches(window.span.start + 10, text.len()));
+    }
+
+    #[test]
+    fn test_sync_point() {
+        // The shallowest member starting a line wins, arrays over objects
+        let text = b"x\"],\n    \"b\": 1,\n  {\"a\": 1},\n  {\"a\": 2}";
+        assert_eq!(sync_point(text, text.len()), Some((text.len() - 8, false)));
+        let text = b"1,\n    {\"a\": 1},\n  \"k\": [1, 2],\n  \"l\": 3";
+        assert_eq!(sync_point(text, text.len()), Some((text.len() - 21, true)));
+        // A string in an array isn't a key
+        assert_eq!(sync_point(b"\"a\",\n  \"b\",\n  \"c\"", 16), None);
+        // Minified: the first that fits
+        let text = br#"0},{"a":[1],"b":{"c":2}},{"a":3}"#;
+        assert_eq!(sync_point(text, text.len()), Some((3, false)));
+        // Nothing before the cursor
+        assert_eq!(sync_point(b"{\"a\": 1}", 8), None);
+    }
+
+    #[test]
+    fn test_records() {
+        let text = "{\"a\": 1}\n{\"a\": 2}\n{\"a\": 3}\n";
+        let window = window(text, 12, DocumentFormat::JsonLines);
+        assert!(!window.is_outside(0));
+        assert_eq!(land(text, &window, StructuralNavAction::NextSibling, 9).as_deref(), Some("{\"a\": 3}"));
+    }
+}
@@ -0,0 +1,689 @@
+//! YAML read as JSON, so a YAML file can be looked through with the same
+//! navigation. Only a safe subset is read: block mappings and sequences,
+//! flow collections on one line or several, plain and quoted scalars,
+//! block scalars and comments. Anchors, aliases, tags, complex keys and
+//! more than one document are refused rather than guessed at.
+
+use std::path::Path;
+
+use thiserror::Error;
+
+use super::decode::{decode_number, encode_string};
+
+/// Why a YAML document couldn't be converted, at a 1-based line
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("YAML line {line}: {message}")]
+pub struct YamlError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Whether `path` is named as a YAML file
+pub fn is_yaml_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("yaml") || extension.eq_ignore_ascii_case("yml"))
+}
+
+fn error<T>(number: usize, message: &str) -> Result<T, YamlError> {
+    Err(YamlError { line: number + 1, message: message.to_string() })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    /// As JSON writes it
+    Number(String),
+    String(String),
+    Seq(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+/// The JSON form of a YAML document, indented by two spaces, with keys
+/// in the order they were written
+pub fn to_json(yaml: &str) -> Result<String, YamlError> {
+    let value = Parser::new(yaml).document()?;
+    let mut out = String::new();
+    write_json(&value, 0, &mut out);
+    out.push('\n');
+    Ok(out)
+}
+
+fn write_json(value: &Value, indent: usize, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => out.push_str(n),
+        Value::String(s) => out.push_str(&encode_string(s)),
+        Value::Seq(items) if items.is_empty() => out.push_str("[]"),
+        Value::Map(entries) if entries.is_empty() => out.push_str("{}"),
+        Value::Seq(items) => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(&" ".repeat(indent + 2));
+                write_json(item, indent + 2, out);
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&" ".repeat(indent));
+            out.push(']');
+        }
+        Value::Map(entries) => {
+            out.push_str("{\n");
+            for (i, (key, value)) in entries.iter().enumerate() {
+                out.push_str(&" ".repeat(indent + 2));
+                out.push_str(&encode_string(key));
+                out.push_str(": ");
+                write_json(value, indent + 2, out);
+                out.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&" ".repeat(indent));
+            out.push('}');
+        }
+    }
+}
+
+/// A line with something on it besides a comment
+#[derive(Debug, Clone, Copy)]
+struct Line<'a> {
+    /// 0-based, into the raw lines
+    number: usize,
+    indent: usize,
+    /// After the indent, without a comment or trailing spaces
+    text: &'a str,
+}
+
+struct Parser<'a> {
+    raw: Vec<&'a str>,
+    lines: Vec<Line<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(yaml: &'a str) -> Self {
+        let raw: Vec<&str> = yaml.lines().collect();
+        let lines = raw.iter()
+            .enumerate()
+            .filter_map(|(number, line)| {
+                let text = strip_comment(line).trim_end();
+                let trimmed = text.trim_start_matches(' ');
+                (!trimmed.is_empty()).then(|| Line { number, indent: text.len() - trimmed.len(), text: trimmed })
+            })
+            .collect();
+        Self { raw, lines, pos: 0 }
+    }
+
+    /// The next line, unless it's a document marker
+    fn peek(&self) -> Option<Line<'a>> {
+        self.lines.get(self.pos).copied().filter(|line| !is_marker(line))
+    }
+
+    fn document(&mut self) -> Result<Value, YamlError> {
+        if let Some(first) = self.lines.first().copied() {
+            if first.indent == 0 && first.text.starts_with('%') {
+                return error(first.number, "directives aren't supported");
+            }
+            if first.indent == 0 && first.text.starts_with("--- ") {
+                return error(first.number, "put the document on the line after ---");
+            }
+            if first.indent == 0 && first.text == "---" {
+                self.pos = 1;
+            }
+        }
+        let value = self.node(0)?;
+        match self.lines.get(self.pos) {
+            None => Ok(value),
+            Some(line) if line.text == "..." && self.pos + 1 == self.lines.len() => Ok(value),
+            Some(line) if is_marker(line) => error(line.number, "only one document is supported"),
+            Some(line) => error(line.number, "this line doesn't fit the structure above it"),
+        }
+    }
+
+    /// The node starting at the next line, if it's indented at least `min`
+    fn node(&mut self, min: usize) -> Result<Value, YamlError> {
+        let Some(line) = self.peek().filter(|line| line.indent >= min) else {
+            return Ok(Value::Null);
+        };
+        if line.text.starts_with('\t') {
+            return error(line.number, "tabs can't indent YAML");
+        }
+        if is_item(line.text) {
+            return self.sequence(line.indent);
+        }
+        if split_key(line.text, line.number)?.is_some() {
+            return self.mapping(line.indent);
+        }
+        self.pos += 1;
+        self.value(line.text, line, min)
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Value, YamlError> {
+        let mut entries = Vec::new();
+        while let Some(line) = self.peek().filter(|line| line.indent >= indent) {
+            if line.indent > indent {
+                return error(line.number, "unexpected indentation");
+            }
+            let Some((key, rest)) = split_key(line.text, line.number)? else {
+                return error(line.number, "expected `key: value`");
+            };
+            self.pos += 1;
+            let value = match rest {
+                "" => self.child(indent, true)?,
+                rest => self.value(rest, line, indent + 1)?,
+            };
+            entries.push((key, value));
+        }
+        Ok(Value::Map(entries))
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Value, YamlError> {
+        let mut items = Vec::new();
+        while let Some(line) = self.peek().filter(|line| line.indent >= indent) {
+            if line.indent > indent {
+                return error(line.number, "unexpected indentation");
+            }
+            if !is_item(line.text) {
+                break;
+            }
+            let rest = line.text[1..].trim_start_matches(' ');
+            if rest.is_empty() {
+                self.pos += 1;
+                items.push(self.child(indent, false)?);
+                continue;
+            }
+            let column = indent + line.text.len() - rest.len();
+            if is_item(rest) || split_key(rest, line.number)?.is_some() {
+                // A block starting on the dash's line reads as if it began
+                // on a line of its own, at the same column
+                self.lines[self.pos] = Line { number: line.number, indent: column, text: rest };
+                items.push(self.node(column)?);
+            } else {
+                self.pos += 1;
+                items.push(self.value(rest, line, indent + 1)?);
+            }
+        }
+        Ok(Value::Seq(items))
+    }
+
+    /// What a `key:` or `-` with nothing after it holds: the indented block
+    /// below, or for a key, a sequence at the key's own indent
+    fn child(&mut self, indent: usize, key: bool) -> Result<Value, YamlError> {
+        match self.peek() {
+            Some(next) if next.indent > indent => self.node(next.indent),
+            Some(next) if key && next.indent == indent && is_item(next.text) => self.sequence(indent),
+            _ => Ok(Value::Null),
+        }
+    }
+
+    /// A value written after a key or dash, or on a line of its own.
+    /// Lines indented at least `min` below a plain scalar continue it.
+    fn value(&mut self, text: &'a str, line: Line<'a>, min: usize) -> Result<Value, YamlError> {
+        match text.chars().next() {
+            Some('&' | '*' | '!') => error(line.number, "anchors, aliases and tags aren't supported"),
+            Some('|' | '>') => self.block_scalar(text, line.number, min),
+            Some('[' | '{') => {
+                let mut text = text.to_string();
+                while flow_depth(&text) > 0 {
+                    let Some(next) = self.peek() else {
+                        return error(line.number, "unclosed flow collection");
+                    };
+                    text.push(' ');
+                    text.push_str(next.text);
+                    self.pos += 1;
+                }
+                let mut flow = Flow { text: &text, pos: 0, number: line.number };
+                let value = flow.value(false)?;
+                flow.skip_spaces();
+                if flow.pos < text.len() {
+                    return error(line.number, "unexpected text after a flow collection");
+                }
+                Ok(value)
+            }
+            Some('"' | '\'') => {
+                let (value, rest) = quoted(text, line.number)?;
+                if !rest.trim().is_empty() {
+                    return error(line.number, "unexpected text after a quoted string");
+                }
+                Ok(Value::String(value))
+            }
+            _ => {
+                let mut plain = text.to_string();
+                while let Some(next) = self.peek() {
+                    if next.indent < min || is_item(next.text) || split_key(next.text, next.number)?.is_some() {
+                        break;
+                    }
+                    plain.push(' ');
+                    plain.push_str(next.text);
+                    self.pos += 1;
+                }
+                Ok(scalar(&plain))
+            }
+        }
+    }
+
+    /// A `|` (literal) or `>` (folded) scalar: the lines below indented at
+    /// least `min`, with the chomping and indent the header gives
+    fn block_scalar(&mut self, header: &str, number: usize, min: usize) -> Result<Value, YamlError> {
+        let literal = header.starts_with('|');
+        let (mut strip, mut keep, mut indent) = (false, false, None);
+        for c in header[1..].chars() {
+            match c {
+                '-' => strip = true,
+                '+' => keep = true,
+                '1'..='9' => indent = Some(min.max(1) - 1 + c as usize - '0' as usize),
+                _ => return error(number, "bad block scalar header"),
+            }
+        }
+        let mut lines = Vec::new();
+        let mut end = number + 1;
+        for (i, raw) in self.raw.iter().enumerate().skip(number + 1) {
+            let trimmed = raw.trim_start_matches(' ');
+            let line_indent = raw.len() - trimmed.len();
+            if trimmed.trim().is_empty() {
+                lines.push("");
+                end = i + 1;
+                continue;
+            }
+            if line_indent < min || line_indent < *indent.get_or_insert(line_indent) {
+                break;
+            }
+            lines.push(&raw[indent.unwrap_or(0)..]);
+            end = i + 1;
+        }
+        while self.lines.get(self.pos).is_some_and(|line| line.number < end) {
+            self.pos += 1;
+        }
+        let trailing = lines.iter().rev().take_while(|line| line.is_empty()).count();
+        lines.truncate(lines.len() - trailing);
+
+        let mut text = String::new();
+        if literal {
+            text = lines.join("\n");
+        } else {
+            // Lines fold into spaces, except around blank and more indented ones
+            let mut blanks = 0;
+            let mut last: Option<bool> = None;
+            for line in &lines {
+                if line.is_empty() {
+                    blanks += 1;
+                    continue;
+                }
+                let plain = !line.starts_with(' ');
+                if let Some(last_plain) = last {
+                    let joined = last_plain && plain;
+                    match blanks {
+                        0 if joined => text.push(' '),
+                        _ => text.push_str(&"\n".repeat(blanks + usize::from(!joined))),
+                    }
+                }
+                text.push_str(line);
+                blanks = 0;
+                last = Some(plain);
+            }
+        }
+        if !lines.is_empty() && !strip {
+            text.push('\n');
+        }
+        if keep {
+            text.push_str(&"\n".repeat(trailing));
+        }
+        Ok(Value::String(text))
+    }
+}
+
+/// A flow collection, `[...]` or `{...}`, as one string
+struct Flow<'t> {
+    text: &'t str,
+    pos: usize,
+    number: usize,
+}
+
+impl Flow<'_> {
+    fn skip_spaces(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_spaces();
+        let found = self.text[self.pos..].starts_with(c);
+        if found {
+            self.pos += c.len_utf8();
+        }
+        found
+    }
+
+    /// A value, or a key when `key`: plain keys end at `: ` too
+    fn value(&mut self, key: bool) -> Result<Value, YamlError> {
+        self.skip_spaces();
+        let rest = &self.text[self.pos..];
+        match rest.chars().next() {
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                while !self.eat(']') {
+                    items.push(self.value(false)?);
+                    if !self.eat(',') && !self.text[self.pos..].starts_with(']') {
+                        return error(self.number, "expected , or ] in a flow sequence");
+                    }
+                }
+                Ok(Value::Seq(items))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut entries = Vec::new();
+                while !self.eat('}') {
+                    let key = match self.value(true)? {
+                        Value::String(key) | Value::Number(key) => key,
+                        Value::Null => "null".to_string(),
+                        Value::Bool(b) => b.to_string(),
+                        _ => return error(self.number, "complex keys aren't supported"),
+                    };
+                    let value = if self.eat(':') { self.value(false)? } else { Value::Null };
+                    entries.push((key, value));
+                    if !self.eat(',') && !self.text[self.pos..].starts_with('}') {
+                        return error(self.number, "expected , or } in a flow mapping");
+                    }
+                }
+                Ok(Value::Map(entries))
+            }
+            Some('"' | '\'') => {
+                let (value, after) = quoted(rest, self.number)?;
+                self.pos += rest.len() - after.len();
+                Ok(Value::String(value))
+            }
+            Some('&' | '*' | '!') => error(self.number, "anchors, aliases and tags aren't supported"),
+            _ => {
+                let bytes = rest.as_bytes();
+                let end = (0..bytes.len())
+                    .find(|&i| match bytes[i] {
+                        b',' | b']' | b'}' => true,
+                        b':' => key && bytes.get(i + 1).is_none_or(|next| b" ,]}".contains(next)),
+                        _ => false,
+                    })
+                    .unwrap_or(bytes.len());
+                let plain = rest[..end].trim();
+                if plain.is_empty() {
+                    return error(self.number, "missing value in a flow collection");
+                }
+                self.pos += end;
+                Ok(if key { Value::String(plain.to_string()) } else { scalar(plain) })
+            }
+        }
+    }
+}
+
+fn is_marker(line: &Line) -> bool {
+    line.indent == 0 && (line.text == "---" || line.text.starts_with("--- ") || line.text == "...")
+}
+
+fn is_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// The key of a `key: value` line and what follows it
+fn split_key(text: &str, number: usize) -> Result<Option<(String, &str)>, YamlError> {
+    if text == "?" || text.starts_with("? ") {
+        return error(number, "complex keys aren't supported");
+    }
+    let (key, after) = match text.chars().next() {
+        Some('"' | '\'') => match quoted(text, number) {
+            Ok(quoted) => quoted,
+            Err(_) => return Ok(None),
+        },
+        Some('[' | '{') => return Ok(None),
+        _ => {
+            let bytes = text.as_bytes();
+            let Some(colon) = (0..bytes.len()).find(|&i| bytes[i] == b':' && bytes.get(i + 1).is_none_or(|&next| next == b' ')) else {
+                return Ok(None);
+            };
+            let key = text[..colon].trim_end();
+            if key.starts_with(['&', '*', '!']) {
+                return error(number, "anchors, aliases and tags aren't supported");
+            }
+            (key.to_string(), &text[colon..])
+        }
+    };
+    match after.trim_start_matches(' ').strip_prefix(':') {
+        Some(rest) if rest.is_empty() || rest.starts_with(' ') => Ok(Some((key, rest.trim_start_matches(' ')))),
+        _ => Ok(None),
+    }
+}
+
+/// A quoted scalar at the start of `text`, and the text after it
+fn quoted(text: &str, number: usize) -> Result<(String, &str), YamlError> {
+    let quote = text.chars().next().unwrap_or('"');
+    let mut value = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' if quote == '\'' => {
+                if text[i + 1..].starts_with('\'') {
+                    chars.next();
+                    value.push('\'');
+                } else {
+                    return Ok((value, &text[i + 1..]));
+                }
+            }
+            '"' if quote == '"' => return Ok((value, &text[i + 1..])),
+            '\\' if quote == '"' => {
+                let escaped = match chars.next().map(|(_, c)| c) {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some('b') => '\u{08}',
+                    Some('f') => '\u{0c}',
+                    Some('e') => '\u{1b}',
+                    Some(c @ ('"' | '\\' | '/' | ' ')) => c,
+                    Some(c @ ('x' | 'u' | 'U')) => {
+                        let digits = match c { 'x' => 2, 'u' => 4, _ => 8 };
+                        let hex: String = chars.by_ref().take(digits).map(|(_, c)| c).collect();
+                        let code = u32::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == digits);
+                        match code.and_then(char::from_u32) {
+                            Some(c) => c,
+                            None => return error(number, "bad escape in a quoted string"),
+                        }
+                    }
+                    _ => return error(number, "bad escape in a quoted string"),
+                };
+                value.push(escaped);
+            }
+            c => value.push(c),
+        }
+    }
+    error(number, "unterminated quoted string (strings over several lines aren't supported)")
+}
+
+/// `line` up to a comment: a `#` at the start or after a space, outside quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut prev = ' ';
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match quote {
+            Some('"') if c == '\\' => {
+                chars.next();
+            }
+            Some('\'') if c == '\'' && chars.peek().is_some_and(|&(_, next)| next == '\'') => {
+                chars.next();
+            }
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '#' && matches!(prev, ' ' | '\t') => return &line[..i],
+            None if matches!(c, '"' | '\'') && matches!(prev, ' ' | '\t' | '[' | '{' | ',') => quote = Some(c),
+            None => {}
+        }
+        prev = c;
+    }
+    line
+}
+
+/// How many flow collections are left open at the end of `text`
+fn flow_depth(text: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut prev = ' ';
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some('"') if c == '\\' => {
+                chars.next();
+            }
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                '"' | '\'' if matches!(prev, ' ' | '[' | '{' | ',') => quote = Some(c),
+                _ => {}
+            },
+        }
+        prev = c;
+    }
+    depth
+}
+
+/// A plain scalar as null, a boolean, a number or a string (YAML 1.2's
+/// core schema)
+fn scalar(text: &str) -> Value {
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => Value::Null,
+        "true" | "True" | "TRUE" => Value::Bool(true),
+        "false" | "False" | "FALSE" => Value::Bool(false),
+        _ => number(text).map_or_else(|| Value::String(text.to_string()), Value::Number),
+    }
+}
+
+/// A YAML number as a JSON literal: `+1`, `007`, `0x1f`, `.5` and `1.` are
+/// numbers in YAML but not in JSON
+fn number(text: &str) -> Option<String> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text.strip_prefix('+').unwrap_or(text)),
+    };
+    for (prefix, radix) in [("0x", 16), ("0o", 8)] {
+        if let Some(rest) = digits.strip_prefix(prefix) {
+            let valid = !rest.is_empty() && rest.chars().all(|c| c.is_digit(radix));
+            return valid.then(|| u128::from_str_radix(rest, radix).ok()).flatten().map(|n| format!("{}{}", sign, n));
+        }
+    }
+    let (mantissa, exponent) = match digits.find(['e', 'E']) {
+        Some(i) => (&digits[..i], Some(&digits[i + 1..])),
+        None => (digits, None),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !all_digits(whole) || !all_digits(fraction) {
+        return None;
+    }
+    if let Some(exponent) = exponent {
+        let unsigned = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if unsigned.is_empty() || !all_digits(unsigned) {
+            return None;
+        }
+    }
+    let whole = whole.trim_start_matches('0');
+    let literal = format!(
+        "{}{}{}{}",
+        sign,
+        if whole.is_empty() { "0" } else { whole },
+        if fraction.is_empty() { String::new() } else { format!(".{}", fraction) },
+        exponent.map_or(String::new(), |exponent| format!("e{}", exponent))
+    );
+    decode_number(&literal).is_some().then_some(literal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(yaml: &str) -> serde_json::Value {
+        serde_json::from_str(&to_json(yaml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_block_collections() {
+        let yaml = "\
+---
+# settings
+name: jim   # the editor
+tags:
+- json
+- \"yaml: maybe\"
+servers:
+  - host: a.example
+    ports: [80, 443]
+  - host: 'b''s'
+    ports:
+      - 22
+empty:
+nested: {a: 1, b: [true, ~], 'c d': \"e\\tf\"}
+multi: [1,
+  2]
+";
+        assert_eq!(json(yaml), serde_json::json!({
+            "name": "jim",
+            "tags": ["json", "yaml: maybe"],
+            "servers": [
+                {"host": "a.example", "ports": [80, 443]},
+                {"host": "b's", "ports": [22]},
+            ],
+            "empty": null,
+            "nested": {"a": 1, "b": [true, null], "c d": "e\tf"},
+            "multi": [1, 2],
+        }));
+        // Keys stay in the order they were written
+        assert!(to_json("b: 1\na: 2\n").unwrap().starts_with("{\n  \"b\": 1,\n  \"a\": 2\n}"));
+    }
+
+    #[test]
+    fn test_scalars() {
+        let yaml = "[1, -2.5, +3, 007, 0x1f, .5, 1e3, 1.2.3, .inf, yes, Null, FALSE, a b, 'x #y']";
+        assert_eq!(json(yaml), serde_json::json!([
+            1, -2.5, 3, 7, 31, 0.5, 1e3, "1.2.3", ".inf", "yes", null, false, "a b", "x #y",
+        ]));
+        assert_eq!(json("- - a\n  - b\n- c\n"), serde_json::json!([["a", "b"], "c"]));
+        assert_eq!(json("text: a long\n  plain line\n"), serde_json::json!({"text": "a long plain line"}));
+    }
+
+    #[test]
+    fn test_block_scalars() {
+        let yaml = "\
+literal: |
+  one
+    two
+
+folded: >-
+  one
+  two
+
+  three
+keep: |+
+  x
+
+end: 1
+";
+        assert_eq!(json(yaml), serde_json::json!({
+            "literal": "one\n  two\n",
+            "folded": "one two\nthree",
+            "keep": "x\n\n",
+            "end": 1,
+        }));
+    }
+
+    #[test]
+    fn test_errors_give_the_line() {
+        let line = |yaml: &str| to_json(yaml).unwrap_err().line;
+        assert_eq!(line("a: 1\nb: &x 2\n"), 2);
+        assert_eq!(line("a: 1\n  b: 2\n"), 2);
+        assert_eq!(line("a: 1\n---\nb: 2\n"), 2);
+        assert_eq!(line("a:\n  - 1\n  b: 2\n"), 3);
+        assert_eq!(line("a: \"open\n"), 1);
+        assert_eq!(line("a: [1, 2\nb: 3\n"), 1);
+        assert_eq!(
+            to_json("x: *ref\n").unwrap_err().to_string(),
+            "YAML line 1: anchors, aliases and tags aren't supported"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Sessions: where a file was left, kept per file under
+//! ~/.local/state/jim/sessions and put back when the file is opened again
+//! unchanged. A session is a small JSON object; readers skip fields they
+//! don't know and fill in ones that are missing, so older and newer
+//! versions read each other's files.
+
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::config;
+use crate::edit::undofile::FileStamp;
+use crate::parser::decode;
+use crate::parser::schema::Value;
+
+/// Written into every session; older ones are read all the same
+pub const VERSION: u32 = 1;
+
+/// Everything a session keeps about a file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Session {
+    /// The file as it was when the session was written
+    pub stamp: Option<FileStamp>,
+    pub line: usize,
+    pub col: usize,
+    /// First line and column in view
+    pub top: usize,
+    pub left: usize,
+    /// Closed folds, as byte ranges
+    pub folds: Vec<Range<usize>>,
+    /// Options that belong to the file, as :set arguments
+    pub options: Vec<String>,
+    /// Bookmarks as paths and notes, kept even when the file changes
+    pub bookmarks: Vec<(String, String)>,
+}
+
+impl Session {
+    pub fn to_json(&self) -> String {
+        let mut out = format!("{{\n  \"version\": {},\n", VERSION);
+        if let Some(stamp) = self.stamp {
+            out.push_str(&format!(
+                "  \"file\": {{\"size\": {}, \"modified\": [{}, {}]}},\n",
+                stamp.size, stamp.modified.0, stamp.modified.1
+            ));
+        }
+        out.push_str(&format!("  \"cursor\": {{\"line\": {}, \"col\": {}}},\n", self.line, self.col));
+        out.push_str(&format!("  \"view\": {{\"top\": {}, \"left\": {}}},\n", self.top, self.left));
+        let folds: Vec<String> = self.folds.iter().map(|fold| format!("[{}, {}]", fold.start, fold.end)).collect();
+        out.push_str(&format!("  \"folds\": [{}],\n", folds.join(", ")));
+        let options: Vec<String> = self.options.iter().map(|option| decode::encode_string(option)).collect();
+        out.push_str(&format!("  \"options\": [{}],\n", options.join(", ")));
+        let bookmarks: Vec<String> = self.bookmarks.iter()
+            .map(|(path, note)| format!("{{\"path\": {}, \"note\": {}}}", decode::encode_string(path), decode::encode_string(note)))
+            .collect();
+        out.push_str(&format!("  \"bookmarks\": [{}]\n}}\n", bookmarks.join(", ")));
+        out
+    }
+
+    /// Read a session back. Only text that isn't a JSON object is an
+    /// error; a field that is missing or of the wrong type keeps its
+    /// default.
+    pub fn parse(text: &str) -> Result<Self> {
+        let value = Value::parse(text)?;
+        if !matches!(value, Value::Object(_)) {
+            bail!("A session is a JSON object");
+        }
+        let number = |outer: &str, inner: &str| value.get(outer).and_then(|v| v.get(inner)).and_then(whole);
+        let file = value.get("file");
+        let modified = match file.and_then(|file| file.get("modified")) {
+            Some(Value::Array(parts)) => match parts[..] {
+                [ref secs, ref nanos] => whole(secs).zip(whole(nanos)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let stamp = file.and_then(|file| file.get("size")).and_then(whole).zip(modified).map(|(size, (secs, nanos))| {
+            FileStamp { size: size as u64, modified: (secs as u64, nanos as u32) }
+        });
+        let folds = match value.get("folds") {
+            Some(Value::Array(folds)) => folds.iter()
+                .filter_map(|fold| match fold {
+                    Value::Array(ends) if ends.len() == 2 => Some(whole(&ends[0])?..whole(&ends[1])?),
+                    _ => None,
+                })
+                .filter(|fold| fold.start < fold.end)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let options = match value.get("options") {
+            Some(Value::Array(options)) => options.iter()
+                .filter_map(|option| match option {
+                    Value::String(option) => Some(option.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let bookmarks = match value.get("bookmarks") {
+            Some(Value::Array(bookmarks)) => bookmarks.iter()
+                .filter_map(|bookmark| match (bookmark.get("path")?, bookmark.get("note")) {
+                    (Value::String(path), Some(Value::String(note))) => Some((path.clone(), note.clone())),
+                    (Value::String(path), _) => Some((path.clone(), String::new())),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            stamp,
+            line: number("cursor", "line").unwrap_or(0),
+            col: number("cursor", "col").unwrap_or(0),
+            top: number("view", "top").unwrap_or(0),
+            left: number("view", "left").unwrap_or(0),
+            folds,
+            options,
+            bookmarks,
+        })
+    }
+}
+
+/// A non-negative whole number
+fn whole(value: &Value) -> Option<usize> {
+    match *value {
+        Value::Number(n) if n >= 0.0 && n.fract() == 0.0 && n <= usize::MAX as f64 => Some(n as usize),
+        _ => None,
+    }
+}
+
+/// Where the session of `file` is kept
+pub fn session_path(file: &Path) -> Option<PathBuf> {
+    config::state_file("sessions", file)
+}
+
+/// Write `session` to `path`, aside first and renamed so a crash never
+/// leaves half a file
+pub fn write(session: &Session, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("Can't create {}", dir.display()))?;
+    }
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, session.to_json()).with_context(|| format!("Can't write {}", temp.display()))?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Session> {
+    let text = fs::read_to_string(path).with_context(|| format!("Can't read {}", path.display()))?;
+    Session::parse(&text).with_context(|| format!("Bad session {}", path.display()))
+}
+
+/// Keep `session` for `file`, stamped with the file as it is on disk now
+pub fn save(mut session: Session, file: &Path) -> Result<()> {
+    let path = session_path(file).context("No home directory for the session")?;
+    session.stamp = Some(FileStamp::of(file)?);
+    write(&session, &path)
+}
+
+/// The bookmarks kept for `file`, whether or not it has changed since;
+/// they're found again by path
+pub fn bookmarks(file: &Path) -> Vec<(String, String)> {
+    session_path(file).and_then(|path| read(&path).ok()).map_or_else(Vec::new, |session| session.bookmarks)
+}
+
+/// The session kept for `file`, if there is one and the file hasn't
+/// changed since
+pub fn load(file: &Path) -> Option<Session> {
+    let session = read(&session_path(file)?).ok()?;
+    (session.stamp == Some(FileStamp::of(file).ok()?)).then_some(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session {
+            stamp: Some(FileStamp { size: 2_000_000_000, modified: (1_700_000_000, 5) }),
+            line: 120,
+            col: 7,
+            top: 100,
+            left: 4,
+            folds: vec![10..200, 300..400],
+            options: vec!["nowrap".to_string(), "statusline= %f \"%l\"".to_string()],
+            bookmarks: vec![("$.users[3][\"first name\"]".to_string(), "odd \"record\"".to_string())],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = session().to_json();
+        assert!(json.starts_with("{\n  \"version\": 1,\n"));
+        assert_eq!(Session::parse(&json).unwrap(), session());
+        assert_eq!(Session::parse(&Session::default().to_json()).unwrap(), Session::default());
+    }
+
+    #[test]
+    fn test_missing_and_unknown_fields() {
+        // An older session without folds or a view, and one from a newer
+        // version with fields this one doesn't know
+        let old = Session::parse(r#"{"version": 0, "cursor": {"line": 3}, "options": ["list", 5]}"#).unwrap();
+        assert_eq!(old, Session { line: 3, options: vec!["list".to_string()], ..Session::default() });
+        let newer = r#"{"version": 9, "cursor": {"line": 1, "col": 2, "byte": 9}, "marks": {"a": 4},
+            "folds": [[5, 9], [9, 2], "x", [1], [12, 20]], "file": {"size": 3, "modified": [1]}}"#;
+        let newer = Session::parse(newer).unwrap();
+        assert_eq!((newer.line, newer.col), (1, 2));
+        assert_eq!(newer.folds, [5..9, 12..20]);
+        assert_eq!(newer.stamp, None);
+        let bookmarks = r#"{"bookmarks": [{"path": "$.a", "note": "x"}, {"path": "$.b"}, {"note": "y"}, 3]}"#;
+        let bookmarks = Session::parse(bookmarks).unwrap().bookmarks;
+        assert_eq!(bookmarks, [("$.a".to_string(), "x".to_string()), ("$.b".to_string(), String::new())]);
+        // Wrong types fall back too; only a non-object is refused
+        assert_eq!(Session::parse(r#"{"cursor": {"line": -1, "col": 2.5}}"#).unwrap(), Session::default());
+        assert!(Session::parse("[1]").is_err());
+        assert!(Session::parse("{\"cursor\":").is_err());
+    }
+}
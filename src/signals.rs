@@ -0,0 +1,64 @@
+//! A last-ditch SIGBUS handler. Reading a memory-mapped file that another
+//! program has truncated raises SIGBUS, which would otherwise kill the
+//! editor with the terminal still raw and on the alternate screen. The
+//! buffer checks for truncation every frame, but a read can still land
+//! between the file shrinking and the next check.
+
+#[cfg(unix)]
+mod imp {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::OnceLock;
+
+    /// The terminal settings from before raw mode
+    static TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+    static CURSOR_SHAPE: AtomicBool = AtomicBool::new(false);
+
+    /// Mouse reporting off, cursor visible, main screen
+    const RESTORE: &[u8] = b"\x1b[?1000l\x1b[?1002l\x1b[?1003l\x1b[?1006l\x1b[?1015l\x1b[?25h\x1b[?1049l";
+    const DEFAULT_CURSOR: &[u8] = b"\x1b[0 q";
+    const MESSAGE: &[u8] = b"jim: the file was truncated while being read (SIGBUS)\n";
+
+    pub fn install(cursor_shape: bool) {
+        // SAFETY: tcgetattr only writes the termios it's given, and the
+        // handler does nothing but async-signal-safe calls
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) == 0 {
+                let _ = TERMIOS.set(termios);
+            }
+            CURSOR_SHAPE.store(cursor_shape, Ordering::SeqCst);
+            let mut action = std::mem::zeroed::<libc::sigaction>();
+            action.sa_sigaction = on_sigbus as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGBUS, &action, std::ptr::null_mut());
+        }
+    }
+
+    extern "C" fn on_sigbus(_: libc::c_int) {
+        // SAFETY: only async-signal-safe calls, on memory set up before
+        // the handler was installed
+        unsafe {
+            if let Some(termios) = TERMIOS.get() {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+            }
+            if CURSOR_SHAPE.load(Ordering::SeqCst) {
+                libc::write(libc::STDOUT_FILENO, DEFAULT_CURSOR.as_ptr().cast(), DEFAULT_CURSOR.len());
+            }
+            libc::write(libc::STDOUT_FILENO, RESTORE.as_ptr().cast(), RESTORE.len());
+            libc::write(libc::STDERR_FILENO, MESSAGE.as_ptr().cast(), MESSAGE.len());
+            // Die of the signal as if there had been no handler
+            libc::signal(libc::SIGBUS, libc::SIG_DFL);
+            libc::raise(libc::SIGBUS);
+        }
+    }
+}
+
+/// Restore the terminal as it is now (call before raw mode) if SIGBUS
+/// arrives, then die of it as usual. `cursor_shape` says whether to put
+/// back the terminal's own cursor shape.
+pub fn install_sigbus_handler(cursor_shape: bool) {
+    #[cfg(unix)]
+    imp::install(cursor_shape);
+    #[cfg(not(unix))]
+    let _ = cursor_shape;
+}
@@ -0,0 +1,129 @@
+//! What the terminal can show: colors, characters beyond ASCII and cursor
+//! shapes. Worked out from the environment at startup, since the terminals
+//! that lack these are the ones least likely to answer a query about them.
+
+use ratatui::buffer::Buffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Colors, rather than only bold, reverse and the like
+    pub color: bool,
+    /// Characters beyond ASCII in the interface
+    pub unicode: bool,
+    /// Cursor shapes to tell the modes apart
+    pub cursor_shape: bool,
+}
+
+impl Capabilities {
+    /// The plainest terminal, as --no-color asks for
+    pub fn plain() -> Self {
+        Self { color: false, unicode: false, cursor_shape: false }
+    }
+
+    pub fn detect() -> Self {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    /// Capabilities going by the environment variables `var` looks up:
+    /// NO_COLOR turns colors off, TERM=dumb everything, and a locale that
+    /// isn't UTF-8 the characters beyond ASCII
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let dumb = term == "dumb";
+        let no_color = var("NO_COLOR").is_some_and(|value| !value.is_empty());
+        // The first of these that's set decides; with none set, assume UTF-8
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter().find_map(|name| var(name).filter(|value| !value.is_empty()));
+        let utf8 = locale.is_none_or(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        });
+        // The Linux console and the old hardware terminals ignore the
+        // sequence, or worse, print it
+        let shapes = !matches!(term.as_str(), "dumb" | "linux" | "cons25") && !term.starts_with("vt");
+        Self { color: !dumb && !no_color, unicode: !dumb && utf8, cursor_shape: shapes }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self { color: true, unicode: true, cursor_shape: true }
+    }
+}
+
+/// ASCII stand-ins for the characters the interface draws with, one cell
+/// each
+const ASCII: &[(&str, &str)] = &[
+    ("…", "."),
+    ("⋯", "~"),
+    ("▸", ">"),
+    ("▾", "v"),
+    ("✓", "+"),
+    ("✗", "x"),
+    ("↑", "^"),
+    ("↓", "v"),
+    ("→", ">"),
+    ("·", "."),
+    ("─", "-"),
+    ("━", "="),
+    ("│", "|"),
+    ("┃", "#"),
+    ("┌", "+"),
+    ("┐", "+"),
+    ("└", "+"),
+    ("┘", "+"),
+    ("├", "+"),
+    ("┤", "+"),
+    ("┬", "+"),
+    ("┴", "+"),
+    ("┼", "+"),
+];
+
+/// Swap the interface characters in a drawn frame for their ASCII
+/// stand-ins. The document's own text is left as it is, except where it
+/// happens to use one of the same characters.
+pub fn asciify(buffer: &mut Buffer) {
+    for cell in buffer.content.iter_mut() {
+        if let Some(&(_, ascii)) = ASCII.iter().find(|&&(glyph, _)| glyph == cell.symbol()) {
+            cell.set_symbol(ascii);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::layout::Rect;
+    use ratatui::widgets::{Block, Borders, Widget};
+
+    fn env(vars: &[(&str, &str)]) -> Capabilities {
+        Capabilities::from_env(|name| vars.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string()))
+    }
+
+    #[test]
+    fn test_from_env() {
+        assert_eq!(env(&[("TERM", "xterm-256color"), ("LANG", "en_US.UTF-8")]), Capabilities::default());
+        assert_eq!(env(&[]), Capabilities::default());
+        assert_eq!(env(&[("TERM", "dumb"), ("LANG", "en_US.UTF-8")]), Capabilities::plain());
+        let no_color = env(&[("TERM", "xterm"), ("NO_COLOR", "1")]);
+        assert_eq!(no_color, Capabilities { color: false, ..Capabilities::default() });
+        // An empty NO_COLOR doesn't count
+        assert!(env(&[("NO_COLOR", "")]).color);
+        // LC_ALL wins over LANG
+        assert!(!env(&[("LC_ALL", "C"), ("LANG", "en_US.UTF-8")]).unicode);
+        assert!(env(&[("LC_CTYPE", "de_DE.utf8")]).unicode);
+        assert!(!env(&[("TERM", "linux")]).cursor_shape);
+        assert!(!env(&[("TERM", "vt100")]).cursor_shape);
+    }
+
+    #[test]
+    fn test_asciify() {
+        let mut buffer = Buffer::empty(Rect::new(0, 0, 12, 3));
+        Block::default().borders(Borders::ALL).render(buffer.area, &mut buffer);
+        buffer.set_string(1, 1, "▸ é… ✓", ratatui::style::Style::default());
+        asciify(&mut buffer);
+        let rows: Vec<String> = (0..3)
+            .map(|y| (0..12).map(|x| buffer[(x, y)].symbol().to_string()).collect())
+            .collect();
+        assert_eq!(rows, ["+----------+", "|> é. +    |", "+----------+"]);
+    }
+}
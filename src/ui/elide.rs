@@ -0,0 +1,154 @@
+use std::ops::Range;
+
+use crate::parser::ChildCount;
+
+/// Part of a screen row when long strings are elided
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Piece {
+    /// Bytes of the line drawn as they are
+    Text(Range<usize>),
+    /// Bytes of a string left out, drawn as `label`
+    Marker { hidden: Range<usize>, label: String },
+}
+
+impl Piece {
+    fn width(&self, text: &str) -> usize {
+        match self {
+            Piece::Text(range) => text[range.clone()].chars().count(),
+            Piece::Marker { label, .. } => label.chars().count(),
+        }
+    }
+}
+
+/// "…(+183,402 chars)"
+pub fn marker_label(hidden_chars: usize) -> String {
+    format!("…(+{} chars)", ChildCount { count: hidden_chars, complete: true })
+}
+
+/// Lay out a row of `width` columns from byte `start` of `text`, keeping
+/// the first `threshold` characters of each long string and standing a
+/// marker in for the rest, up to the closing quote. `long_string(range)`
+/// gives the first string (quotes included) overlapping `range` that
+/// should be elided. Strings whose marker wouldn't be shorter than what it
+/// hides are drawn in full.
+pub fn plan(
+    text: &str,
+    start: usize,
+    width: usize,
+    threshold: usize,
+    long_string: impl Fn(Range<usize>) -> Option<Range<usize>>,
+) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut pos = start;
+    let mut remaining = width;
+    while remaining > 0 && pos < text.len() {
+        let end = pos + byte_len(&text[pos..], remaining);
+        let mut from = pos;
+        let elided = loop {
+            let Some(string) = long_string(from..end) else { break None };
+            // Keep the opening quote and `threshold` characters
+            let keep = string.start + 1 + byte_len(&text[string.start + 1..], threshold);
+            let close = string.end.saturating_sub(1).max(keep);
+            let hidden_chars = text[keep..close].chars().count();
+            // Strings already passed, or not worth it
+            if close > pos && hidden_chars > marker_label(hidden_chars).chars().count() {
+                break Some((keep, close, hidden_chars));
+            }
+            from = string.end;
+            if from >= end {
+                break None;
+            }
+        };
+        let Some((keep, close, hidden_chars)) = elided.filter(|&(keep, _, _)| keep < end) else {
+            pieces.push(Piece::Text(pos..end));
+            break;
+        };
+        if keep > pos {
+            let piece = Piece::Text(pos..keep);
+            remaining -= piece.width(text);
+            pieces.push(piece);
+        }
+        let marker = Piece::Marker { hidden: keep.max(pos)..close, label: marker_label(hidden_chars) };
+        remaining = remaining.saturating_sub(marker.width(text));
+        pieces.push(marker);
+        pos = close;
+    }
+    pieces
+}
+
+/// Screen column of byte `offset` in a row laid out by `plan`, or None when
+/// it's hidden or off the row. `measure` gives the columns a run of text
+/// takes, which :set list can make more than its characters.
+pub fn column_of(pieces: &[Piece], text: &str, offset: usize, measure: impl Fn(&str) -> usize) -> Option<usize> {
+    let mut col = 0;
+    for piece in pieces {
+        match piece {
+            Piece::Text(range) if range.contains(&offset) => return Some(col + measure(&text[range.start..offset])),
+            Piece::Text(range) => col += measure(&text[range.clone()]),
+            Piece::Marker { hidden, .. } if hidden.contains(&offset) => return None,
+            Piece::Marker { label, .. } => col += label.chars().count(),
+        }
+    }
+    None
+}
+
+/// Bytes taken by the first `chars` characters of `text`
+fn byte_len(text: &str, chars: usize) -> usize {
+    text.char_indices().nth(chars).map_or(text.len(), |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strings in `text` longer than 10 bytes, quotes included
+    fn strings(text: &str) -> impl Fn(Range<usize>) -> Option<Range<usize>> + '_ {
+        move |range: Range<usize>| {
+            let mut at = 0;
+            while let Some(open) = text[at..].find('"').map(|i| at + i) {
+                let close = open + 1 + text[open + 1..].find('"')?;
+                if close + 1 > range.start && open < range.end && close - open > 10 {
+                    return Some(open..close + 1);
+                }
+                at = close + 1;
+            }
+            None
+        }
+    }
+
+    fn chars(text: &str) -> usize {
+        text.chars().count()
+    }
+
+    fn draw(text: &str, pieces: &[Piece]) -> String {
+        pieces.iter()
+            .map(|piece| match piece {
+                Piece::Text(range) => text[range.clone()].to_string(),
+                Piece::Marker { label, .. } => label.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan() {
+        let blob = "A".repeat(200_000);
+        let text = format!(r#"{{"a": "{}", "b": "short", "c": 1}}"#, blob);
+        let pieces = plan(&text, 0, 80, 4, strings(&text));
+        assert_eq!(draw(&text, &pieces), r#"{"a": "AAAA…(+199,996 chars)", "b": "short", "c": 1}"#);
+        let hidden_start = 7 + 4;
+        assert_eq!(column_of(&pieces, &text, hidden_start - 1, chars), Some(hidden_start - 1));
+        assert_eq!(column_of(&pieces, &text, hidden_start + 5, chars), None);
+        // The closing quote comes right after the marker
+        let close = 7 + blob.len();
+        assert_eq!(column_of(&pieces, &text, close, chars), Some(hidden_start + 17));
+
+        // Nothing more once the marker reaches the edge of the row
+        assert_eq!(draw(&text, &plan(&text, 0, 20, 4, strings(&text))), r#"{"a": "AAAA…(+199,996 chars)"#);
+        // Scrolled into the hidden part
+        let pieces = plan(&text, 100, 24, 4, strings(&text));
+        assert_eq!(draw(&text, &pieces), r#"…(+199,996 chars)", "b":"#);
+        // Too short to be worth eliding
+        let text = r#"["abcdefghijklmnop"]"#;
+        assert_eq!(draw(text, &plan(text, 0, 80, 4, strings(text))), text);
+    }
+}
@@ -0,0 +1,407 @@
+use std::ops::Range;
+
+use crate::buffer::{Buffer, Cursor, TextChange};
+
+/// Closed folds, each the byte range of a container from its opening to
+/// just past its closing delimiter. Keeping byte ranges rather than node ids
+/// lets folds outlive a rebuilt index.
+#[derive(Debug, Clone, Default)]
+pub struct FoldSet {
+    // Sorted by start; nested folds are kept so they reappear closed
+    // when their parent is opened
+    folds: Vec<Range<usize>>,
+    // Whole lines of array elements hidden by :filter, sorted, with how
+    // many elements each run holds
+    hidden: Vec<(Range<usize>, usize)>,
+    // Containers nested past :set maxrenderdepth, sorted, and those of
+    // them opened since, which aren't closed again as the index grows
+    deep: Vec<Range<usize>>,
+    opened_deep: Vec<Range<usize>>,
+}
+
+impl FoldSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.folds.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.folds.len()
+    }
+
+    /// The closed folds, sorted by start
+    pub fn ranges(&self) -> &[Range<usize>] {
+        &self.folds
+    }
+
+    pub fn is_closed(&self, range: &Range<usize>) -> bool {
+        self.folds.binary_search_by_key(&range.start, |f| f.start).is_ok_and(|i| self.folds[i] == *range)
+    }
+
+    /// Close a fold over `range`. Returns false if it was already closed.
+    pub fn close(&mut self, range: Range<usize>) -> bool {
+        match self.folds.binary_search_by_key(&range.start, |f| f.start) {
+            Ok(i) if self.folds[i] == range => false,
+            Ok(i) => {
+                self.folds[i] = range;
+                true
+            }
+            Err(i) => {
+                self.folds.insert(i, range);
+                true
+            }
+        }
+    }
+
+    /// Open the fold over `range`. Returns false if it wasn't closed.
+    pub fn open(&mut self, range: &Range<usize>) -> bool {
+        let before = self.folds.len();
+        self.folds.retain(|f| f != range);
+        self.folds.len() != before
+    }
+
+    pub fn toggle(&mut self, range: Range<usize>) {
+        if !self.open(&range) {
+            self.close(range);
+        }
+    }
+
+    /// Open every fold that hides `offset`, e.g. a jump or search target,
+    /// and show any filtered-out elements around it
+    pub fn open_containing(&mut self, offset: usize) -> bool {
+        let before = self.folds.len() + self.hidden.len();
+        self.folds.retain(|f| !(f.start < offset && offset < f.end));
+        self.hidden.retain(|(run, _)| !(run.start <= offset && offset < run.end));
+        let opened = self.open_deep(|f| f.start < offset && offset < f.end);
+        self.folds.len() + self.hidden.len() != before || opened
+    }
+
+    /// Fold a container nested past :set maxrenderdepth, unless it was
+    /// opened before
+    pub fn close_deep(&mut self, range: Range<usize>) {
+        if self.opened_deep.contains(&range) {
+            return;
+        }
+        if let Err(i) = self.deep.binary_search_by_key(&range.start, |f| f.start) {
+            self.deep.insert(i, range);
+        }
+    }
+
+    /// Drop the folds :set maxrenderdepth made, opened or not
+    pub fn clear_deep(&mut self) {
+        self.deep.clear();
+        self.opened_deep.clear();
+    }
+
+    /// Open the :set maxrenderdepth folds starting on `line`, as moving
+    /// onto them does with :set depthexpand
+    pub fn open_deep_on_line(&mut self, buffer: &Buffer, line: usize) -> bool {
+        let from = buffer.line_to_byte_offset(line);
+        let to = buffer.line_to_byte_offset(line + 1);
+        let first = self.deep.partition_point(|f| f.start < from);
+        if self.deep.get(first).is_none_or(|f| f.start >= to) {
+            return false;
+        }
+        self.open_deep(|f| (from..to).contains(&f.start))
+    }
+
+    /// Open the :set maxrenderdepth folds `which` picks, for good
+    fn open_deep(&mut self, which: impl Fn(&Range<usize>) -> bool) -> bool {
+        let (opened, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.deep).into_iter().partition(|f| which(f));
+        self.deep = kept;
+        let any = !opened.is_empty();
+        self.opened_deep.extend(opened);
+        any
+    }
+
+    /// Hide runs of lines for :filter, replacing those hidden before
+    pub fn hide(&mut self, runs: Vec<(Range<usize>, usize)>) {
+        self.hidden = runs;
+    }
+
+    /// Show everything :filter hid. Returns false if nothing was hidden.
+    pub fn unhide(&mut self) -> bool {
+        !std::mem::take(&mut self.hidden).is_empty()
+    }
+
+    /// How many elements :filter hides
+    pub fn hidden_count(&self) -> usize {
+        self.hidden.iter().map(|(_, count)| count).sum()
+    }
+
+    /// Open the folds whose opening delimiter is on `line`
+    pub fn open_on_line(&mut self, buffer: &Buffer, line: usize) -> bool {
+        let before = self.folds.len();
+        self.folds.retain(|f| buffer.byte_offset_to_line(f.start) != line);
+        let opened = self.open_deep_on_line(buffer, line);
+        self.folds.len() != before || opened
+    }
+
+    /// Open `range` and every fold nested inside it
+    pub fn open_within(&mut self, range: &Range<usize>) -> bool {
+        let before = self.folds.len();
+        self.folds.retain(|f| !(range.start <= f.start && f.end <= range.end));
+        let opened = self.open_deep(|f| range.start <= f.start && f.end <= range.end);
+        self.folds.len() != before || opened
+    }
+
+    /// zR: open every fold, those :set maxrenderdepth made included
+    pub fn open_all(&mut self) -> bool {
+        let any = !self.folds.is_empty() || !self.deep.is_empty();
+        self.folds.clear();
+        self.open_deep(|_| true);
+        any
+    }
+
+    pub fn clear(&mut self) {
+        self.folds.clear();
+        self.clear_deep();
+    }
+
+    /// Shift folds after an edit and drop the ones it touched
+    pub fn apply_change(&mut self, change: &TextChange) {
+        let shift = |range: &mut Range<usize>| change.shift_span(range).map(|span| *range = span).is_some();
+        self.folds.retain_mut(|f| shift(f));
+        self.hidden.retain_mut(|(run, _)| shift(run));
+        self.deep.retain_mut(|f| shift(f));
+        self.opened_deep.retain_mut(|f| shift(f));
+    }
+
+    /// Resolve the folds to the line spans they hide in `buffer`
+    pub fn lines(&self, buffer: &Buffer) -> FoldedLines {
+        let mut folds: Vec<(&Range<usize>, Option<usize>)> = self.folds.iter().chain(&self.deep).map(|fold| (fold, None)).collect();
+        folds.extend(self.hidden.iter().map(|(run, count)| (run, Some(*count))));
+        folds.sort_by_key(|(fold, _)| fold.start);
+
+        let mut spans: Vec<FoldSpan> = Vec::new();
+        for (fold, hidden) in folds {
+            let start_line = buffer.byte_offset_to_line(fold.start);
+            let end_line = buffer.byte_offset_to_line(fold.end.saturating_sub(1));
+            // A container on one line has nothing to fold, but a filtered
+            // element on one line is still replaced by its marker
+            if end_line < start_line || (end_line == start_line && hidden.is_none()) {
+                continue;
+            }
+            // Folds nested in (or sharing a line with) an earlier one merge into it
+            if let Some(last) = spans.last_mut() {
+                if start_line <= last.end_line {
+                    if end_line > last.end_line {
+                        last.end_line = end_line;
+                        last.end = fold.end;
+                    }
+                    continue;
+                }
+            }
+            spans.push(FoldSpan { start_line, end_line, start: fold.start, end: fold.end, hidden });
+        }
+        FoldedLines { spans }
+    }
+}
+
+/// An outermost closed fold: drawn as one summary row on `start_line`,
+/// hiding the lines after it up to and including `end_line`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldSpan {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub start: usize,
+    pub end: usize,
+    /// For elements hidden by :filter, how many
+    pub hidden: Option<usize>,
+}
+
+/// Closed folds as line spans, for drawing and for line-wise motions
+#[derive(Debug, Clone, Default)]
+pub struct FoldedLines {
+    spans: Vec<FoldSpan>,
+}
+
+impl FoldedLines {
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// The fold covering `line`, whether as its summary row or hidden
+    pub fn span_at(&self, line: usize) -> Option<&FoldSpan> {
+        let i = self.spans.partition_point(|s| s.start_line <= line);
+        self.spans[..i].last().filter(|s| line <= s.end_line)
+    }
+
+    /// Whether `line` is hidden inside a closed fold
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.span_at(line).is_some_and(|s| line > s.start_line)
+    }
+
+    /// The line whose row shows `line`
+    pub fn visible_line(&self, line: usize) -> usize {
+        match self.span_at(line) {
+            Some(span) => span.start_line,
+            None => line,
+        }
+    }
+
+    /// The line drawn on the row after the one showing `line`
+    pub fn next_visible(&self, line: usize) -> usize {
+        match self.span_at(line) {
+            Some(span) => span.end_line + 1,
+            None => line + 1,
+        }
+    }
+
+    /// The line drawn on the row before the one showing `line`
+    pub fn prev_visible(&self, line: usize) -> Option<usize> {
+        let line = self.visible_line(line);
+        (line > 0).then(|| self.visible_line(line - 1))
+    }
+
+    /// After moving down onto a hidden line, continue past the fold
+    pub fn skip_down(&self, cursor: &mut Cursor, buffer: &Buffer) {
+        if let Some(span) = self.span_at(cursor.line).filter(|_| self.is_hidden(cursor.line)) {
+            let line = if span.end_line + 1 < buffer.line_count() {
+                span.end_line + 1
+            } else {
+                span.start_line
+            };
+            cursor.move_to_line(buffer, line);
+        }
+    }
+
+    /// After moving up onto a hidden line, stop on the fold's summary row
+    pub fn skip_up(&self, cursor: &mut Cursor, buffer: &Buffer) {
+        if self.is_hidden(cursor.line) {
+            cursor.move_to_line(buffer, self.visible_line(cursor.line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = "{\n  \"a\": [\n    1,\n    2\n  ],\n  \"b\": {\n    \"c\": 3\n  }\n}\n";
+
+    fn range_of(opener: &str, closer: char) -> Range<usize> {
+        let start = JSON.find(opener).unwrap();
+        let end = start + JSON[start..].find(closer).unwrap() + 1;
+        start..end
+    }
+
+    #[test]
+    fn test_folded_lines() {
+        let buffer = Buffer::from_text(JSON);
+        let mut folds = FoldSet::new();
+        folds.close(range_of("[", ']'));
+        let lines = folds.lines(&buffer);
+
+        assert_eq!(lines.span_at(1).map(|s| (s.start_line, s.end_line)), Some((1, 4)));
+        assert!(!lines.is_hidden(1));
+        assert!(lines.is_hidden(3));
+        assert_eq!(lines.next_visible(1), 5);
+        assert_eq!(lines.prev_visible(5), Some(1));
+        assert_eq!(lines.prev_visible(1), Some(0));
+
+        let mut cursor = Cursor::from_line_col(2, 0);
+        lines.skip_down(&mut cursor, &buffer);
+        assert_eq!(cursor.line, 5);
+        let mut cursor = Cursor::from_line_col(4, 0);
+        lines.skip_up(&mut cursor, &buffer);
+        assert_eq!(cursor.line, 1);
+    }
+
+    #[test]
+    fn test_nested_folds_merge() {
+        let buffer = Buffer::from_text(JSON);
+        let mut folds = FoldSet::new();
+        folds.close(range_of("[", ']'));
+        folds.close(0..JSON.trim_end().len());
+
+        let lines = folds.lines(&buffer);
+        assert_eq!(lines.span_at(3).map(|s| (s.start_line, s.end_line)), Some((0, 8)));
+
+        // Opening the outer fold leaves the inner one closed
+        folds.toggle(0..JSON.trim_end().len());
+        assert_eq!(folds.len(), 1);
+        assert!(folds.is_closed(&range_of("[", ']')));
+        assert!(folds.open_within(&(0..JSON.len())));
+        assert!(folds.is_empty());
+    }
+
+    #[test]
+    fn test_filter_runs() {
+        let buffer = Buffer::from_text(JSON);
+        let mut folds = FoldSet::new();
+        // The line holding 1, and the lines of the "b" member
+        let one = JSON.find("    1,").unwrap();
+        let b = JSON.find("  \"b\"").unwrap();
+        folds.hide(vec![(one..one + 6, 1), (b..JSON.len() - 3, 2)]);
+        assert_eq!(folds.hidden_count(), 3);
+
+        let lines = folds.lines(&buffer);
+        assert_eq!(lines.span_at(2).map(|s| (s.start_line, s.end_line, s.hidden)), Some((2, 2, Some(1))));
+        assert_eq!(lines.span_at(6).map(|s| (s.start_line, s.end_line, s.hidden)), Some((5, 7, Some(2))));
+        assert_eq!(lines.next_visible(2), 3);
+
+        // A closed fold inside a run is drawn as part of it
+        folds.close(range_of("{\n    \"c\"", '}'));
+        assert_eq!(folds.lines(&buffer).span_at(6).and_then(|s| s.hidden), Some(2));
+
+        // Edits move runs; a jump into one shows it again
+        folds.apply_change(&TextChange { offset: 0, removed: 0, inserted: 1 });
+        assert!(folds.open_containing(one + 3));
+        assert_eq!(folds.hidden_count(), 2);
+        assert!(folds.unhide());
+        assert!(!folds.unhide());
+    }
+
+    #[test]
+    fn test_folds_follow_edits() {
+        let array = range_of("[", ']');
+        let object = range_of("{\n    \"c\"", '}');
+        let mut folds = FoldSet::new();
+        folds.close(array.clone());
+        folds.close(object.clone());
+
+        // An edit before both folds shifts them
+        folds.apply_change(&TextChange { offset: 0, removed: 0, inserted: 2 });
+        assert!(folds.is_closed(&(array.start + 2..array.end + 2)));
+
+        // An edit inside the array drops only that fold
+        folds.apply_change(&TextChange { offset: array.start + 5, removed: 1, inserted: 0 });
+        assert!(!folds.is_closed(&(array.start + 2..array.end + 2)));
+        assert!(folds.is_closed(&(object.start + 1..object.end + 1)));
+
+        // Typing right after a fold's closer leaves it alone
+        folds.apply_change(&TextChange { offset: object.end + 1, removed: 0, inserted: 1 });
+        assert_eq!(folds.len(), 1);
+        assert!(folds.open_containing(object.start + 3));
+    }
+
+    #[test]
+    fn test_deep_folds() {
+        let buffer = Buffer::from_text(JSON);
+        let array = range_of("[", ']');
+        let object = range_of("{\n    \"c\"", '}');
+        let mut folds = FoldSet::new();
+        folds.close_deep(array.clone());
+        folds.close_deep(object.clone());
+        assert!(folds.is_empty());
+        let lines = folds.lines(&buffer);
+        assert_eq!(lines.span_at(3).map(|s| (s.start_line, s.end_line)), Some((1, 4)));
+        assert_eq!(lines.next_visible(1), 5);
+
+        // Once opened, growing the index doesn't close it again
+        assert!(folds.open_deep_on_line(&buffer, 1));
+        assert!(!folds.open_deep_on_line(&buffer, 1));
+        folds.close_deep(array.clone());
+        assert!(folds.lines(&buffer).span_at(3).is_none());
+
+        assert!(folds.open_all());
+        assert!(folds.lines(&buffer).span_at(6).is_none());
+        folds.clear_deep();
+        folds.close_deep(array);
+        assert!(folds.lines(&buffer).span_at(3).is_some());
+    }
+}
@@ -0,0 +1,131 @@
+//! Numbers as the status bar, the perf overlay and messages show them:
+//! counts with their digits grouped in threes, and sizes in binary units.
+//! How digits are grouped follows :set numberformat, since some people read
+//! messages with scripts that want plain numbers.
+
+use crate::parser::ChildCount;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberFormat {
+    /// 12345678
+    Plain,
+    /// 12,345,678
+    #[default]
+    Comma,
+    /// 12_345_678
+    Underscore,
+}
+
+const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+impl NumberFormat {
+    /// The format :set numberformat names
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(NumberFormat::Plain),
+            "comma" => Some(NumberFormat::Comma),
+            "underscore" => Some(NumberFormat::Underscore),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NumberFormat::Plain => "plain",
+            NumberFormat::Comma => "comma",
+            NumberFormat::Underscore => "underscore",
+        }
+    }
+
+    fn separator(self) -> Option<char> {
+        match self {
+            NumberFormat::Plain => None,
+            NumberFormat::Comma => Some(','),
+            NumberFormat::Underscore => Some('_'),
+        }
+    }
+
+    /// `n` with its digits grouped: 38112 -> 38,112
+    pub fn count(self, n: usize) -> String {
+        let digits = n.to_string();
+        let Some(separator) = self.separator() else {
+            return digits;
+        };
+        let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                out.push(separator);
+            }
+            out.push(digit);
+        }
+        out
+    }
+
+    /// A count of members, with a `+` while it's only a lower bound
+    pub fn child_count(self, count: ChildCount) -> String {
+        let mut out = self.count(count.count);
+        if !count.complete {
+            out.push('+');
+        }
+        out
+    }
+
+    /// `bytes` in the largest binary unit it makes at least one of, to two
+    /// places: 2.45 MB, 1,536.00 TB
+    pub fn size(self, bytes: usize) -> String {
+        if bytes < 1024 {
+            return format!("{} B", bytes);
+        }
+        let mut value = bytes as f64 / 1024.0;
+        let mut unit = 0;
+        // What would round to 1024.00 of a unit is one of the next
+        while value >= 1023.995 && unit + 1 < UNITS.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        let fixed = format!("{:.2}", value);
+        let (whole, fraction) = fixed.split_once('.').unwrap_or((&fixed, "00"));
+        let whole = whole.parse().map_or_else(|_| whole.to_string(), |whole| self.count(whole));
+        format!("{}.{} {}", whole, fraction, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count() {
+        assert_eq!(NumberFormat::Comma.count(0), "0");
+        assert_eq!(NumberFormat::Comma.count(999), "999");
+        assert_eq!(NumberFormat::Comma.count(1000), "1,000");
+        assert_eq!(NumberFormat::Comma.count(12_345_678), "12,345,678");
+        assert_eq!(NumberFormat::Underscore.count(123_456), "123_456");
+        assert_eq!(NumberFormat::Plain.count(12_345_678), "12345678");
+        let partial = ChildCount { count: 38112, complete: false };
+        assert_eq!(NumberFormat::Comma.child_count(partial), "38,112+");
+        assert_eq!(NumberFormat::Plain.child_count(ChildCount { complete: true, ..partial }), "38112");
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(NumberFormat::Comma.size(0), "0 B");
+        assert_eq!(NumberFormat::Comma.size(1023), "1023 B");
+        assert_eq!(NumberFormat::Comma.size(1024), "1.00 KB");
+        assert_eq!(NumberFormat::Comma.size(2_569_011), "2.45 MB");
+        assert_eq!(NumberFormat::Comma.size(3 << 30), "3.00 GB");
+        // Past the largest unit the digits are grouped
+        assert_eq!(NumberFormat::Comma.size(1536 << 40), "1,536.00 TB");
+        assert_eq!(NumberFormat::Plain.size(1536 << 40), "1536.00 TB");
+        // Just short of a megabyte rounds up to one
+        assert_eq!(NumberFormat::Comma.size(1024 * 1024 - 1), "1.00 MB");
+    }
+
+    #[test]
+    fn test_parse() {
+        for format in [NumberFormat::Plain, NumberFormat::Comma, NumberFormat::Underscore] {
+            assert_eq!(NumberFormat::parse(format.name()), Some(format));
+        }
+        assert_eq!(NumberFormat::parse("dot"), None);
+    }
+}
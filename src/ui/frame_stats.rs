@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How many frames the performance overlay averages over
+pub const FRAME_SAMPLES: usize = 60;
+
+/// Average and 99th percentile frame time, in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    pub avg_ms: f64,
+    pub p99_ms: f64,
+}
+
+/// Times of the last `FRAME_SAMPLES` frames, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct FrameTimes {
+    times: VecDeque<Duration>,
+    /// Reused for sorting, so the overlay doesn't allocate every frame
+    scratch: Vec<Duration>,
+}
+
+impl FrameTimes {
+    pub fn new() -> Self {
+        Self { times: VecDeque::with_capacity(FRAME_SAMPLES + 1), scratch: Vec::with_capacity(FRAME_SAMPLES) }
+    }
+
+    /// Record a frame, forgetting the oldest once there are enough
+    pub fn push(&mut self, time: Duration) {
+        if self.times.len() == FRAME_SAMPLES {
+            self.times.pop_front();
+        }
+        self.times.push_back(time);
+    }
+
+    /// Stats over the frames recorded so far, zero before the first
+    pub fn frame_stats(&mut self) -> FrameStats {
+        if self.times.is_empty() {
+            return FrameStats::default();
+        }
+        let sum: Duration = self.times.iter().sum();
+        let avg_ms = sum.as_micros() as f64 / self.times.len() as f64 / 1000.0;
+
+        self.scratch.clear();
+        self.scratch.extend(self.times.iter().copied());
+        self.scratch.sort_unstable();
+        let idx = (self.scratch.len() as f64 * 0.99) as usize;
+        let p99_ms = self.scratch.get(idx).unwrap_or(&Duration::ZERO).as_micros() as f64 / 1000.0;
+        FrameStats { avg_ms, p99_ms }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_stats() {
+        let mut times = FrameTimes::new();
+        assert_eq!(times.frame_stats(), FrameStats::default());
+
+        // Fewer than a full window: with this few frames the p99 is the
+        // slowest one
+        for ms in [4, 2, 6] {
+            times.push(Duration::from_millis(ms));
+        }
+        let stats = times.frame_stats();
+        assert_eq!(stats.avg_ms, 4.0);
+        assert_eq!(stats.p99_ms, 6.0);
+
+        // Only the last 60 count
+        for _ in 0..FRAME_SAMPLES {
+            times.push(Duration::from_millis(10));
+        }
+        assert_eq!(times.frame_stats(), FrameStats { avg_ms: 10.0, p99_ms: 10.0 });
+    }
+}
@@ -0,0 +1,88 @@
+/// Line number column drawn before each row of text, then the sign column
+/// when there's one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gutter {
+    /// Columns taken by the gutter, including the space before the text
+    pub width: u16,
+    /// Vim-style hybrid numbering: absolute on the cursor row, distance elsewhere
+    pub relative: bool,
+    /// False for a gutter kept only for signs, with numbering off
+    pub numbers: bool,
+    /// Whether the last `SIGN_WIDTH` columns are the sign column
+    pub signs: bool,
+}
+
+/// A sign and the space after it
+pub const SIGN_WIDTH: u16 = 2;
+
+impl Gutter {
+    /// Size the gutter for the largest line number in the buffer
+    pub fn new(line_count: usize, relative: bool) -> Self {
+        let digits = line_count.max(1).ilog10() as u16 + 1;
+        Self {
+            width: digits.max(3) + 1,
+            relative,
+            numbers: true,
+            signs: false,
+        }
+    }
+
+    /// Just the sign column, for when line numbers are off
+    pub fn signs() -> Self {
+        Self { width: SIGN_WIDTH, relative: false, numbers: false, signs: true }
+    }
+
+    /// The gutter with the sign column after the numbers
+    pub fn with_signs(self) -> Self {
+        match self.signs {
+            true => self,
+            false => Self { width: self.width + SIGN_WIDTH, signs: true, ..self },
+        }
+    }
+
+    /// Label for a row showing buffer `line` (0-based), up to the sign
+    /// column. Distances are counted in rows so a closed fold counts once.
+    pub fn label(&self, line: usize, row: usize, cursor_row: Option<usize>) -> String {
+        let width = (self.width - if self.signs { SIGN_WIDTH } else { 0 }) as usize;
+        if !self.numbers {
+            return " ".repeat(width);
+        }
+        let number = match cursor_row {
+            Some(cursor_row) if self.relative && row != cursor_row => row.abs_diff(cursor_row),
+            _ => line + 1,
+        };
+        format!("{:>width$} ", number, width = width - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_width_follows_line_count() {
+        assert_eq!(Gutter::new(0, false).width, 4);
+        assert_eq!(Gutter::new(999, false).width, 4);
+        assert_eq!(Gutter::new(1000, false).width, 5);
+        assert_eq!(Gutter::new(10_000_000, false).width, 9);
+    }
+
+    #[test]
+    fn test_labels() {
+        let absolute = Gutter::new(120, false);
+        assert_eq!(absolute.label(0, 0, Some(3)), "  1 ");
+        assert_eq!(absolute.label(119, 5, Some(3)), "120 ");
+
+        let relative = Gutter::new(120, true);
+        assert_eq!(relative.label(41, 3, Some(3)), " 42 ");
+        assert_eq!(relative.label(39, 1, Some(3)), "  2 ");
+        // A fold between rows 3 and 4 still reads as one row away
+        assert_eq!(relative.label(60, 4, Some(3)), "  1 ");
+        assert_eq!(relative.label(7, 0, None), "  8 ");
+
+        assert_eq!(Gutter::signs().label(41, 3, Some(3)), "");
+        let signs = absolute.with_signs();
+        assert_eq!((signs.width, signs.label(0, 0, Some(3)).as_str()), (6, "  1 "));
+        assert_eq!(signs.with_signs(), signs);
+    }
+}
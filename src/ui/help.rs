@@ -0,0 +1,201 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::mode::command::COMMANDS;
+use crate::mode::{operator_help, Continuation, Keymap, Mode};
+
+/// One row of the help screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HelpLine {
+    Heading(String),
+    Entry { keys: String, description: String },
+    /// A line of a debug view, shown as is
+    Text(String),
+    Blank,
+}
+
+impl HelpLine {
+    fn text(&self) -> String {
+        match self {
+            HelpLine::Heading(title) => title.clone(),
+            HelpLine::Entry { keys, description } => format!("{} {}", keys, description),
+            HelpLine::Text(text) => text.clone(),
+            HelpLine::Blank => String::new(),
+        }
+    }
+}
+
+/// The :help pane: every key and command, generated from the keymap so
+/// remapped keys show as they are, scrolled and searched like a pager.
+/// The :tokens and :nodes debug views are read in the same pane.
+#[derive(Debug, Clone)]
+pub struct HelpView {
+    /// Shown in the border and the status bar
+    pub title: String,
+    lines: Vec<HelpLine>,
+    /// First line shown
+    pub scroll: usize,
+    /// Search being typed after `/`
+    pub query: Option<String>,
+    /// Last search, for n/N and highlighting
+    pub search: Option<String>,
+}
+
+impl HelpView {
+    pub fn new(keymap: &Keymap) -> Self {
+        let commands = COMMANDS.iter().map(|&(keys, description)| Continuation {
+            keys: format!(":{}", keys),
+            description: description.to_string(),
+        });
+        let sections = [
+            ("Normal mode", keymap.bindings(Mode::Normal)),
+            ("Operators and registers", operator_help()),
+            ("Insert mode", keymap.bindings(Mode::Insert)),
+            ("Commands", commands.collect()),
+        ];
+        let mut lines = Vec::new();
+        for (title, entries) in sections {
+            if !lines.is_empty() {
+                lines.push(HelpLine::Blank);
+            }
+            lines.push(HelpLine::Heading(title.to_string()));
+            lines.extend(entries.into_iter().map(|c| HelpLine::Entry { keys: c.keys, description: c.description }));
+        }
+        Self::scratch("Help", lines)
+    }
+
+    /// A read-only pane of `lines` under `title`
+    pub fn scratch(title: &str, lines: Vec<HelpLine>) -> Self {
+        Self { title: title.to_string(), lines, scroll: 0, query: None, search: None }
+    }
+
+    /// Put in `lines` afresh, keeping the scroll position where it can
+    pub fn replace_lines(&mut self, lines: Vec<HelpLine>, height: usize) {
+        self.lines = lines;
+        self.scroll_by(0, height);
+    }
+
+    pub fn lines(&self) -> &[HelpLine] {
+        &self.lines
+    }
+
+    /// Width of the widest key column entry
+    pub fn key_width(&self) -> usize {
+        self.lines.iter()
+            .filter_map(|line| match line {
+                HelpLine::Entry { keys, .. } => Some(keys.chars().count()),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Whether `line` contains the last search, ignoring case
+    pub fn is_match(&self, line: &HelpLine) -> bool {
+        self.search.as_ref().is_some_and(|search| line.text().to_lowercase().contains(&search.to_lowercase()))
+    }
+
+    /// Handle a key with `height` rows showing; false once the help closes
+    pub fn handle_key(&mut self, key: KeyEvent, height: usize) -> bool {
+        if let Some(query) = &mut self.query {
+            match key.code {
+                KeyCode::Esc => self.query = None,
+                KeyCode::Enter => {
+                    self.search = self.query.take().filter(|query| !query.is_empty()).or(self.search.take());
+                    self.find(true, height);
+                }
+                KeyCode::Backspace if query.is_empty() => self.query = None,
+                KeyCode::Backspace => {
+                    query.pop();
+                }
+                KeyCode::Char(c) => query.push(c),
+                _ => {}
+            }
+            return true;
+        }
+        let half = (height / 2).max(1);
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('q') | KeyCode::Esc | KeyCode::F(1), _) => return false,
+            (KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter, _) => self.scroll_by(1, height),
+            (KeyCode::Char('k') | KeyCode::Up, _) => self.scroll = self.scroll.saturating_sub(1),
+            (KeyCode::Char('d'), KeyModifiers::CONTROL) => self.scroll_by(half, height),
+            (KeyCode::Char('u'), KeyModifiers::CONTROL) => self.scroll = self.scroll.saturating_sub(half),
+            (KeyCode::PageDown | KeyCode::Char(' '), _) => self.scroll_by(height, height),
+            (KeyCode::PageUp, _) => self.scroll = self.scroll.saturating_sub(height),
+            (KeyCode::Char('g') | KeyCode::Home, _) => self.scroll = 0,
+            (KeyCode::Char('G') | KeyCode::End, _) => self.scroll_by(usize::MAX, height),
+            (KeyCode::Char('/'), _) => self.query = Some(String::new()),
+            (KeyCode::Char('n'), _) => self.find(true, height),
+            (KeyCode::Char('N'), _) => self.find(false, height),
+            _ => {}
+        }
+        true
+    }
+
+    fn scroll_by(&mut self, lines: usize, height: usize) {
+        let last = self.lines.len().saturating_sub(height);
+        self.scroll = self.scroll.saturating_add(lines).min(last);
+    }
+
+    /// Scroll the next (or previous) line matching the search to the top,
+    /// wrapping around the end
+    fn find(&mut self, forward: bool, height: usize) {
+        let len = self.lines.len();
+        let found = (1..=len)
+            .map(|step| if forward { (self.scroll + step) % len } else { (self.scroll + len - step % len) % len })
+            .find(|&i| self.is_match(&self.lines[i]));
+        if let Some(line) = found {
+            self.scroll = 0;
+            self.scroll_by(line, height);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mode::Binding;
+
+    fn press(help: &mut HelpView, keys: &str) {
+        for c in keys.chars() {
+            let code = if c == '\n' { KeyCode::Enter } else { KeyCode::Char(c) };
+            help.handle_key(KeyEvent::new(code, KeyModifiers::NONE), 5);
+        }
+    }
+
+    fn entry<'a>(help: &'a HelpView, description: &str) -> Option<&'a str> {
+        help.lines().iter().find_map(|line| match line {
+            HelpLine::Entry { keys, description: d } if d == description => Some(keys.as_str()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_generated_from_keymap() {
+        let help = HelpView::new(&Keymap::new());
+        assert_eq!(entry(&help, "structural next sibling"), Some("<C-j>"));
+        assert_eq!(entry(&help, "next sibling"), Some("]j"));
+        assert_eq!(entry(&help, "delete line"), Some("dd"));
+        assert_eq!(entry(&help, "this help"), Some(":h, help"));
+
+        let mut keymap = Keymap::new();
+        keymap.map(Mode::Normal, "gs", Binding::parse("structural_next_sibling").unwrap()).unwrap();
+        keymap.map(Mode::Normal, "<C-j>", Binding::parse(":w").unwrap()).unwrap();
+        let help = HelpView::new(&keymap);
+        assert_eq!(entry(&help, "structural next sibling"), Some("gs"));
+        assert_eq!(entry(&help, ":w"), Some("<C-j>"));
+    }
+
+    #[test]
+    fn test_search_and_close() {
+        let mut help = HelpView::new(&Keymap::new());
+        press(&mut help, "/FOLD\n");
+        let first = help.scroll;
+        assert!(help.is_match(&help.lines()[first]));
+        press(&mut help, "n");
+        assert!(help.scroll > first);
+        press(&mut help, "N");
+        assert_eq!(help.scroll, first);
+        assert!(help.handle_key(KeyEvent::new(KeyCode::Char('j'), KeyModifiers::NONE), 5));
+        assert!(!help.handle_key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE), 5));
+    }
+}
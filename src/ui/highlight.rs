@@ -0,0 +1,321 @@
+use ratatui::style::Style;
+use ratatui::text::{Line, Span};
+
+use super::theme::Theme;
+use crate::parser::node::{NodeId, ParseStatus};
+use crate::parser::{NodeKind, StructuralIndex, TokenIssue, Tokenizer};
+
+/// Colorize `text`, a line or part of one starting at byte `base` of the
+/// document, from the nodes the index has for that range: one span per
+/// token, with keys styled apart from string values. Only what falls in the
+/// range is looked at, however long the line.
+pub fn colorize_from_index(
+    index: &StructuralIndex,
+    text: &str,
+    base: usize,
+    comments: bool,
+    theme: &Theme,
+) -> Line<'static> {
+    let end = base + text.len();
+    // (start, end, style) in document bytes, clipped to the range
+    let mut tokens: Vec<(usize, usize, Style)> = Vec::new();
+
+    // Nodes that began before the range: a scalar cut by a horizontal
+    // scroll, or containers whose closer lands inside it
+    let mut ancestor = index.node_id_at(base);
+    while let Some(id) = ancestor {
+        let Some(node) = index.get(id) else { break };
+        if node.start < base {
+            if node.is_container() {
+                if index.is_closed(id) && node.end <= end {
+                    tokens.push((node.end - 1, node.end, theme.punctuation));
+                }
+            } else {
+                tokens.push((base, node.end.min(end), scalar_style(index, id, text, base, theme)));
+            }
+        }
+        ancestor = node.parent;
+    }
+
+    for id in index.nodes_starting_in(base..end) {
+        let Some(node) = index.get(id) else { break };
+        if node.is_container() {
+            tokens.push((node.start, node.start + 1, theme.punctuation));
+            if index.is_closed(id) && node.end <= end {
+                tokens.push((node.end - 1, node.end, theme.punctuation));
+            }
+        } else {
+            tokens.push((node.start, node.end.min(end), scalar_style(index, id, text, base, theme)));
+        }
+    }
+    tokens.sort_unstable_by_key(|&(start, _, _)| start);
+
+    let mut spans = Vec::with_capacity(tokens.len() * 2);
+    let mut pos = base;
+    for (start, stop, style) in tokens {
+        if start < pos {
+            continue;
+        }
+        if start > pos {
+            push_gap(&mut spans, &text[pos - base..start - base], comments, theme);
+        }
+        spans.push(Span::styled(text[start - base..stop - base].to_string(), style));
+        pos = stop;
+    }
+    if pos < end {
+        push_gap(&mut spans, &text[pos - base..], comments, theme);
+    }
+    Line::from(spans)
+}
+
+fn scalar_style(index: &StructuralIndex, id: NodeId, text: &str, base: usize, theme: &Theme) -> Style {
+    let Some(node) = index.get(id) else { return theme.text };
+    match node.kind {
+        NodeKind::String if node.status == ParseStatus::Invalid => theme.string.patch(theme.invalid),
+        NodeKind::String if is_key(index, id, node.end, text, base) => theme.key,
+        NodeKind::String => theme.string,
+        NodeKind::Number => theme.number,
+        NodeKind::Boolean => theme.boolean,
+        NodeKind::Null => theme.null,
+        NodeKind::Key => theme.key,
+        NodeKind::Error => theme.error.patch(theme.invalid),
+        _ => theme.text,
+    }
+}
+
+/// A string is a key when a colon follows it. Looking at the text is
+/// cheaper than counting siblings, which is the fallback when the colon
+/// isn't in view.
+fn is_key(index: &StructuralIndex, id: NodeId, node_end: usize, text: &str, base: usize) -> bool {
+    if index.parent(id).and_then(|p| index.kind(p)) != Some(NodeKind::Object) {
+        return false;
+    }
+    let after = text.as_bytes().get(node_end.saturating_sub(base)..).unwrap_or(&[]);
+    match after.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b':') => true,
+        Some(_) => false,
+        None => index.is_key_node(id),
+    }
+}
+
+/// Text between tokens: whitespace, commas, colons and (in JSONC) comments
+fn push_gap(spans: &mut Vec<Span<'static>>, gap: &str, comments: bool, theme: &Theme) {
+    let mut rest = gap;
+    while !rest.is_empty() {
+        let comment_at = if comments { rest.find("/*").into_iter().chain(rest.find("//")).min() } else { None };
+        let plain_end = comment_at.unwrap_or(rest.len());
+        for part in rest[..plain_end].split_inclusive(':') {
+            match part.strip_suffix(':') {
+                Some(before) => {
+                    if !before.is_empty() {
+                        spans.push(Span::styled(before.to_string(), theme.text));
+                    }
+                    spans.push(Span::styled(":".to_string(), theme.colon));
+                }
+                None => spans.push(Span::styled(part.to_string(), theme.text)),
+            }
+        }
+        rest = &rest[plain_end..];
+        if rest.is_empty() {
+            break;
+        }
+        // A line comment runs to the end of the gap, a block comment to its */
+        let comment_end = if rest.starts_with("//") {
+            rest.len()
+        } else {
+            rest[2..].find("*/").map_or(rest.len(), |i| i + 4)
+        };
+        spans.push(Span::styled(rest[..comment_end].to_string(), theme.comment));
+        rest = &rest[comment_end..];
+    }
+}
+
+/// Whether a string literal has an invalid escape or unpaired surrogate
+fn string_has_issue(literal: &str) -> bool {
+    let mut tokenizer = Tokenizer::new(literal.to_string());
+    tokenizer.next_token().is_some_and(|t| t.issue != TokenIssue::None)
+}
+
+/// Lexer-based colorizer for a single line, used where the structural index
+/// doesn't reach. Rescans every character and can't tell keys from values.
+pub fn colorize_json_line(line: &str, comments: bool, theme: &Theme) -> Line<'static> {
+    let mut spans = Vec::new();
+    let chars: Vec<char> = line.chars().collect();
+    let mut pos = 0;
+    
+    while pos < chars.len() {
+        let ch = chars[pos];
+        match ch {
+            '{' | '}' | '[' | ']' => {
+                spans.push(Span::styled(ch.to_string(), theme.punctuation));
+                pos += 1;
+            }
+            '"' => {
+                // Find matching quote
+                let start = pos;
+                pos += 1;
+                while pos < chars.len() && chars[pos] != '"' {
+                    if chars[pos] == '\\' {
+                        pos += 1; // Skip escaped char
+                    }
+                    pos += 1;
+                }
+                if pos < chars.len() {
+                    pos += 1; // Include closing quote
+                }
+                let string: String = chars[start..pos].iter().collect();
+                let mut style = theme.string;
+                // Only strings with escapes can be malformed
+                if string.contains('\\') && string_has_issue(&string) {
+                    style = style.patch(theme.invalid);
+                }
+                spans.push(Span::styled(string, style));
+            }
+            '0'..='9' | '-' => {
+                // Number
+                let start = pos;
+                while pos < chars.len() && 
+                      (chars[pos].is_ascii_digit() || 
+                       chars[pos] == '.' || 
+                       chars[pos] == '-' ||
+                       chars[pos] == 'e' ||
+                       chars[pos] == 'E' ||
+                       chars[pos] == '+') {
+                    pos += 1;
+                }
+                let number: String = chars[start..pos].iter().collect();
+                spans.push(Span::styled(number, theme.number));
+            }
+            't' if pos + 4 <= chars.len() => {
+                let word: String = chars[pos..pos+4].iter().collect();
+                if word == "true" {
+                    spans.push(Span::styled(word, theme.boolean));
+                    pos += 4;
+                } else {
+                    spans.push(Span::styled(ch.to_string(), theme.text));
+                    pos += 1;
+                }
+            }
+            'f' if pos + 5 <= chars.len() => {
+                let word: String = chars[pos..pos+5].iter().collect();
+                if word == "false" {
+                    spans.push(Span::styled(word, theme.boolean));
+                    pos += 5;
+                } else {
+                    spans.push(Span::styled(ch.to_string(), theme.text));
+                    pos += 1;
+                }
+            }
+            'n' if pos + 4 <= chars.len() => {
+                let word: String = chars[pos..pos+4].iter().collect();
+                if word == "null" {
+                    spans.push(Span::styled(word, theme.null));
+                    pos += 4;
+                } else {
+                    spans.push(Span::styled(ch.to_string(), theme.text));
+                    pos += 1;
+                }
+            }
+            '/' if comments && matches!(chars.get(pos + 1), Some('/') | Some('*')) => {
+                // Comment: a line comment runs to the end of the line, a block
+                // comment to its closing */ (or the end of this line)
+                let start = pos;
+                if chars[pos + 1] == '/' {
+                    pos = chars.len();
+                } else {
+                    pos += 2;
+                    while pos < chars.len() && !(chars[pos] == '/' && chars[pos - 1] == '*' && pos > start + 2) {
+                        pos += 1;
+                    }
+                    pos = (pos + 1).min(chars.len());
+                }
+                let comment: String = chars[start..pos].iter().collect();
+                spans.push(Span::styled(comment, theme.comment));
+            }
+            ':' => {
+                spans.push(Span::styled(ch.to_string(), theme.colon));
+                pos += 1;
+            }
+            _ => {
+                spans.push(Span::styled(ch.to_string(), theme.text));
+                pos += 1;
+            }
+        }
+    }
+    
+    Line::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::style::Color;
+
+    fn index_for(json: &str, relaxed: bool) -> StructuralIndex {
+        let mut tokenizer = Tokenizer::new(json.to_string()).with_comments(relaxed);
+        let tokens = tokenizer.tokenize_all();
+        if relaxed {
+            StructuralIndex::from_tokens_relaxed(&tokens)
+        } else {
+            StructuralIndex::from_tokens(&tokens)
+        }
+    }
+
+    fn styled(line: &Line) -> Vec<(String, Style)> {
+        line.spans.iter().map(|s| (s.content.to_string(), s.style)).collect()
+    }
+
+    #[test]
+    fn test_keys_and_values() {
+        let json = r#"{"name": "jim", "tags": ["a", 1, true, null]}"#;
+        let index = index_for(json, false);
+        let theme = Theme::dark();
+        let line = colorize_from_index(&index, json, 0, false, &theme);
+
+        let text: String = line.spans.iter().map(|s| s.content.as_ref()).collect();
+        assert_eq!(text, json);
+        let spans = styled(&line);
+        assert!(spans.contains(&("\"name\"".to_string(), theme.key)));
+        assert!(spans.contains(&("\"jim\"".to_string(), theme.string)));
+        assert!(spans.contains(&("\"tags\"".to_string(), theme.key)));
+        assert!(spans.contains(&("\"a\"".to_string(), theme.string)));
+        assert!(spans.contains(&("true".to_string(), Style::default().fg(Color::Cyan))));
+        assert!(spans.contains(&("]".to_string(), theme.punctuation)));
+    }
+
+    #[test]
+    fn test_slice_and_multiline() {
+        let json = "{\n  \"long\": \"abcdefgh\",\n  \"n\": [1,\n 2]\n}\n";
+        let index = index_for(json, false);
+        let theme = Theme::dark();
+
+        // Each line on its own, including closers of containers opened earlier
+        let second = json.find("  \"n\"").unwrap();
+        let third = json.find(" 2]").unwrap();
+        let line = colorize_from_index(&index, &json[third..third + 3], third, false, &theme);
+        assert_eq!(styled(&line), vec![
+            (" ".to_string(), theme.text),
+            ("2".to_string(), Style::default().fg(Color::Yellow)),
+            ("]".to_string(), theme.punctuation),
+        ]);
+        let line = colorize_from_index(&index, &json[second..third - 1], second, false, &theme);
+        assert_eq!(styled(&line)[1], ("\"n\"".to_string(), theme.key));
+
+        // A slice starting in the middle of a string value
+        let mid = json.find("defgh").unwrap();
+        let line = colorize_from_index(&index, &json[mid..mid + 7], mid, false, &theme);
+        assert_eq!(styled(&line)[0], ("defgh\"".to_string(), theme.string));
+    }
+
+    #[test]
+    fn test_comments_in_gaps() {
+        let json = "[1, /* two */ 2] // done";
+        let index = index_for(json, true);
+        let theme = Theme::dark();
+        let line = colorize_from_index(&index, json, 0, true, &theme);
+        let spans = styled(&line);
+        assert!(spans.contains(&("/* two */".to_string(), theme.comment)));
+        assert!(spans.contains(&("// done".to_string(), theme.comment)));
+        assert!(spans.contains(&("2".to_string(), Style::default().fg(Color::Yellow))));
+    }
+}
@@ -0,0 +1,175 @@
+use std::ops::Range;
+
+use super::help::HelpLine;
+use crate::buffer::Buffer;
+use crate::parser::token::TokenKind;
+use crate::parser::{NodeKind, StructuralIndex, TokenIssue, Tokenizer};
+
+/// Tokens :tokens lists before giving up on a long minified line
+const LISTED_TOKENS: usize = 5000;
+
+/// Nodes :nodes lists either side of the cursor's, in document order
+const NODES_AROUND: usize = 20;
+
+/// Which debug view a pane shows, for refreshing it with `r`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inspect {
+    /// :tokens - the tokens of the lines in view
+    Tokens,
+    /// :nodes - the indexed nodes around the cursor
+    Nodes,
+    /// :profile - the operations timed lately
+    Profile,
+    /// :tasks - the work running in the background
+    Tasks,
+}
+
+impl Inspect {
+    pub fn title(self) -> &'static str {
+        match self {
+            Inspect::Tokens => "Tokens",
+            Inspect::Nodes => "Nodes",
+            Inspect::Profile => "Profile",
+            Inspect::Tasks => "Tasks",
+        }
+    }
+}
+
+/// The :tokens pane for `lines` of the buffer: each token the tokenizer
+/// finds there, with its byte range and depth. Whitespace is left out. The
+/// depth the lines start at comes from the index, as far as it reaches.
+pub fn tokens(index: Option<&StructuralIndex>, buffer: &Buffer, lines: Range<usize>, comments: bool) -> Vec<HelpLine> {
+    let start = buffer.line_to_byte_offset(lines.start);
+    let end = buffer.line_to_byte_offset(lines.end).max(start);
+    let depth = index.map_or(0, |index| depth_at(index, start));
+    let text = buffer.slice(start..end);
+    let mut out = vec![HelpLine::Heading(format!(
+        "lines {}-{}, bytes {}..{}, starting at depth {}",
+        lines.start + 1,
+        lines.end.max(lines.start + 1),
+        start,
+        start + text.len(),
+        depth
+    ))];
+    let mut tokenizer = Tokenizer::resume(text.as_bytes(), start, depth).with_comments(comments);
+    let mut listed = 0;
+    while let Some(token) = tokenizer.next_token() {
+        if token.kind == TokenKind::Whitespace {
+            continue;
+        }
+        if listed == LISTED_TOKENS {
+            out.push(HelpLine::Text(format!("(stopped after {} tokens)", LISTED_TOKENS)));
+            break;
+        }
+        let issue = match token.issue {
+            TokenIssue::None => String::new(),
+            issue => format!("  {}", issue),
+        };
+        out.push(HelpLine::Text(format!(
+            "{:<12} {:<24} depth {}{}",
+            format!("{:?}", token.kind),
+            format!("{}..{}", token.start, token.end),
+            token.depth,
+            issue
+        )));
+        listed += 1;
+    }
+    out
+}
+
+/// The :nodes pane for the cursor at `offset`: the node under it and its
+/// ancestors, then the nodes indexed just before and after it. Each line
+/// has the id, kind, span, depth and parent, and the span of the key in
+/// front of an object member's value. `>` marks the cursor's node.
+pub fn nodes(index: &StructuralIndex, offset: usize) -> Vec<HelpLine> {
+    let Some(id) = index.node_id_at(offset) else {
+        return vec![HelpLine::Heading(format!("byte {}: no node indexed here", offset))];
+    };
+    let mut ancestors = vec![id];
+    while let Some(parent) = ancestors.last().and_then(|&id| index.parent(id)) {
+        ancestors.push(parent);
+    }
+    let mut out = vec![HelpLine::Heading(format!("byte {}: node {} and its ancestors", offset, id))];
+    out.extend(ancestors.iter().rev().filter_map(|&node| node_line(index, node, id)));
+    let around = id.saturating_sub(NODES_AROUND)..(id + NODES_AROUND + 1).min(index.len());
+    out.push(HelpLine::Blank);
+    out.push(HelpLine::Heading(format!("nodes {}-{} of {} indexed", around.start, around.end - 1, index.len())));
+    out.extend(around.filter_map(|node| node_line(index, node, id)));
+    out
+}
+
+fn node_line(index: &StructuralIndex, id: usize, cursor: usize) -> Option<HelpLine> {
+    let node = index.get(id)?;
+    let end = if node.is_container() && !index.is_closed(id) { "?".to_string() } else { node.end.to_string() };
+    let parent = node.parent.map_or("-".to_string(), |parent| parent.to_string());
+    // A value's key is its previous sibling; a key has none of its own
+    let key = node.prev_sibling
+        .filter(|&prev| index.kind(prev) == Some(NodeKind::Key) || index.is_key_node(prev))
+        .filter(|_| node.kind != NodeKind::Key && !index.is_key_node(id))
+        .and_then(|prev| index.get(prev))
+        .map_or("-".to_string(), |key| format!("{}..{}", key.start, key.end));
+    Some(HelpLine::Text(format!(
+        "{}{:>8} {:<8} {:<24} depth {:<3} parent {:<8} key {}",
+        if id == cursor { '>' } else { ' ' },
+        id,
+        node.kind.to_string(),
+        format!("{}..{}", node.start, end),
+        node.depth,
+        parent,
+        key
+    )))
+}
+
+/// Containers open around `offset`, as far as the index knows them
+fn depth_at(index: &StructuralIndex, offset: usize) -> u32 {
+    let mut current = index.node_id_at(offset);
+    while let Some(id) = current {
+        let Some(node) = index.get(id) else { break };
+        if node.is_container() && node.start < offset {
+            return node.depth + 1;
+        }
+        current = node.parent;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: &[HelpLine]) -> Vec<String> {
+        lines.iter()
+            .map(|line| match line {
+                HelpLine::Heading(text) | HelpLine::Text(text) => text.trim_end().to_string(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tokens_start_at_the_indexed_depth() {
+        let json = "{\n  \"a\": [1,\n    true]\n}";
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(json.to_string()).tokenize_all());
+        let buffer = Buffer::from_text(json);
+        assert_eq!(text(&tokens(Some(&index), &buffer, 2..3, false)), vec![
+            "lines 3-3, bytes 13..23, starting at depth 2",
+            "True         17..21                   depth 2",
+            "BracketClose 21..22                   depth 1",
+        ]);
+    }
+
+    #[test]
+    fn test_nodes_around_the_cursor() {
+        let json = r#"{"a": [1, 2]}"#;
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(json.to_string()).tokenize_all());
+        let lines = text(&nodes(&index, json.find('2').unwrap()));
+        assert_eq!(lines[0], "byte 10: node 4 and its ancestors");
+        assert_eq!(&lines[1..4], [
+            "        0 Object   0..13                    depth 0   parent -        key -",
+            "        2 Array    6..12                    depth 1   parent 0        key 1..4",
+            ">       4 Number   10..11                   depth 2   parent 2        key -",
+        ]);
+        assert_eq!(lines[5], "nodes 0-4 of 5 indexed");
+        assert_eq!(lines[7], "        1 String   1..4                     depth 1   parent 0        key -");
+    }
+}
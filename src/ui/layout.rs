@@ -0,0 +1,155 @@
+use ratatui::layout::{Alignment, Rect};
+use ratatui::widgets::Paragraph;
+use ratatui::Frame;
+
+/// Narrowest terminal the editor is drawn in: the border plus a few columns
+/// of text
+pub const MIN_WIDTH: u16 = 12;
+
+/// Shortest terminal the editor is drawn in: one row of text between the
+/// borders, and the status bar
+pub const MIN_HEIGHT: u16 = 4;
+
+/// Columns the outline leaves the text pane before it's dropped
+const MIN_TEXT_WIDTH: u16 = 20;
+
+/// Rows the location list leaves above it before it's dropped
+const MIN_TEXT_HEIGHT: u16 = 5;
+
+/// Where each part of the screen goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenLayout {
+    /// Outline pane, border included, when it's open and there's room
+    pub tree: Option<Rect>,
+    /// Location list pane across the bottom, border included, likewise
+    pub list: Option<Rect>,
+    /// Text pane including its border
+    pub text_block: Rect,
+    /// Line numbers, inside the border (zero width when hidden)
+    pub gutter: Rect,
+    /// Where the text itself is drawn
+    pub content: Rect,
+    pub status: Rect,
+}
+
+impl ScreenLayout {
+    /// Lay out a terminal of `area`, or None when it's too small to use.
+    /// `list_height` is the location list's, 0 while it's closed.
+    pub fn new(area: Rect, show_tree: bool, list_height: u16, gutter_width: u16) -> Option<Self> {
+        if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+            return None;
+        }
+        let mut main = Rect { height: area.height - 1, ..area };
+        let list = (list_height > 0 && main.height >= list_height + MIN_TEXT_HEIGHT).then(|| {
+            main.height -= list_height;
+            Rect { y: main.y + main.height, height: list_height, ..main }
+        });
+        let status = Rect { y: area.y + area.height - 1, height: 1, ..area };
+
+        let tree_width = (main.width / 3).clamp(20, 48);
+        let (tree, text_block) = if show_tree && main.width >= tree_width + MIN_TEXT_WIDTH {
+            let tree = Rect { width: tree_width, ..main };
+            (Some(tree), Rect { x: main.x + tree_width, width: main.width - tree_width, ..main })
+        } else {
+            (None, main)
+        };
+
+        let (gutter, content) = text_pane(text_block, gutter_width)?;
+        Some(Self { tree, list, text_block, gutter, content, status })
+    }
+}
+
+/// Gutter and text areas inside a bordered text window, or None when it
+/// has no room inside the border
+pub fn text_pane(block: Rect, gutter_width: u16) -> Option<(Rect, Rect)> {
+    if block.width < 3 || block.height < 3 {
+        return None;
+    }
+    let inner = Rect {
+        x: block.x + 1,
+        y: block.y + 1,
+        width: block.width - 2,
+        height: block.height - 2,
+    };
+    // Line numbers only when they leave room for some text
+    let gutter_width = if gutter_width < inner.width { gutter_width } else { 0 };
+    Some((
+        Rect { width: gutter_width, ..inner },
+        Rect { x: inner.x + gutter_width, width: inner.width - gutter_width, ..inner },
+    ))
+}
+
+/// Placeholder for a terminal below `MIN_WIDTH` x `MIN_HEIGHT`
+pub fn render_too_small(frame: &mut Frame, area: Rect) {
+    if area.width == 0 || area.height == 0 {
+        return;
+    }
+    let text = if area.width >= 17 { "Window too small" } else { "Too small" };
+    let row = Rect { y: area.y + area.height / 2, height: 1, ..area };
+    frame.render_widget(Paragraph::new(text).alignment(Alignment::Center), row);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn draw(width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| {
+            let area = frame.area();
+            match ScreenLayout::new(area, true, 0, 4) {
+                Some(layout) => assert!(layout.content.width > 0),
+                None => render_too_small(frame, area),
+            }
+        }).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer.content.iter().map(|cell| cell.symbol()).collect()
+    }
+
+    #[test]
+    fn test_tiny_terminals() {
+        assert_eq!(ScreenLayout::new(Rect::new(0, 0, 1, 1), false, 0, 4), None);
+        assert_eq!(ScreenLayout::new(Rect::new(0, 0, 80, 2), true, 0, 4), None);
+        assert_eq!(draw(1, 1).chars().count(), 1);
+        assert!(draw(80, 2).contains("Window too small"));
+        assert!(draw(10, 3).contains("Too small"));
+    }
+
+    #[test]
+    fn test_layout() {
+        let layout = ScreenLayout::new(Rect::new(0, 0, 80, 24), false, 0, 5).unwrap();
+        assert_eq!(layout.tree, None);
+        assert_eq!(layout.status, Rect::new(0, 23, 80, 1));
+        assert_eq!(layout.gutter, Rect::new(1, 1, 5, 21));
+        assert_eq!(layout.content, Rect::new(6, 1, 73, 21));
+
+        let layout = ScreenLayout::new(Rect::new(0, 0, 90, 24), true, 0, 5).unwrap();
+        assert_eq!(layout.tree, Some(Rect::new(0, 0, 30, 23)));
+        assert_eq!(layout.content, Rect::new(36, 1, 53, 21));
+
+        // Not enough room for the outline, then for the gutter
+        let layout = ScreenLayout::new(Rect::new(0, 0, 30, 4), true, 0, 5).unwrap();
+        assert_eq!(layout.tree, None);
+        assert_eq!(layout.content, Rect::new(6, 1, 23, 1));
+        let layout = ScreenLayout::new(Rect::new(0, 0, 12, 4), false, 0, 10).unwrap();
+        assert_eq!(layout.gutter.width, 0);
+        assert_eq!(layout.content, Rect::new(1, 1, 10, 1));
+    }
+
+    #[test]
+    fn test_list_pane() {
+        // Under the outline and the text, above the status bar
+        let layout = ScreenLayout::new(Rect::new(0, 0, 90, 24), true, 10, 5).unwrap();
+        assert_eq!(layout.list, Some(Rect::new(0, 13, 90, 10)));
+        assert_eq!(layout.tree, Some(Rect::new(0, 0, 30, 13)));
+        assert_eq!(layout.content, Rect::new(36, 1, 53, 11));
+        assert_eq!(layout.status, Rect::new(0, 23, 90, 1));
+
+        // Dropped rather than squeezing the text out
+        let layout = ScreenLayout::new(Rect::new(0, 0, 80, 14), false, 10, 5).unwrap();
+        assert_eq!(layout.list, None);
+        assert_eq!(layout.content.height, 11);
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::ops::{Range, RangeInclusive};
+
+use ratatui::text::Line;
+
+/// Colorized slices of lines kept from one frame to the next, so an idle
+/// screen isn't lexed again. Entries are keyed by line number and where the
+/// slice starts, and checked against the line's document offset and the
+/// slice's end. Whatever changes how every line is colored (the theme, the
+/// format, the structural index) goes into the stamp given to
+/// `begin_frame`, and a new stamp clears the lot.
+///
+/// Each slice is also kept as it was last drawn, with the cursor's brackets,
+/// the selection and the rest of the `Overlay` on it, so a row nothing
+/// changed on builds no spans at all.
+#[derive(Debug, Clone)]
+pub struct LineCache<S> {
+    stamp: Option<S>,
+    lines: HashMap<(usize, usize), Entry>,
+    frame: u64,
+    hits: usize,
+    misses: usize,
+    built: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    base: usize,
+    end: usize,
+    line: Line<'static>,
+    drawn: Option<(Overlay, Line<'static>)>,
+    frame: u64,
+}
+
+/// What's drawn over a colorized slice, in offsets into the slice: the
+/// bytes highlighted for the cursor's delimiter and its partner, values
+/// that break the schema, the visual selection and `:set list`. A row is
+/// drawn again only when this or the slice changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Overlay {
+    pub marks: Vec<usize>,
+    pub diagnostics: Vec<Range<usize>>,
+    pub selection: Option<Range<usize>>,
+    pub list: bool,
+}
+
+impl<S> Default for LineCache<S> {
+    fn default() -> Self {
+        Self { stamp: None, lines: HashMap::new(), frame: 0, hits: 0, misses: 0, built: 0 }
+    }
+}
+
+impl<S: PartialEq> LineCache<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start drawing a frame colored according to `stamp`
+    pub fn begin_frame(&mut self, stamp: S) {
+        if self.stamp.as_ref() != Some(&stamp) {
+            self.lines.clear();
+            self.stamp = Some(stamp);
+        }
+        self.frame += 1;
+    }
+
+    /// Forget lines that weren't drawn in the frame just finished, so the
+    /// cache holds about a screenful
+    pub fn end_frame(&mut self) {
+        let frame = self.frame;
+        self.lines.retain(|_, entry| entry.frame == frame);
+    }
+
+    /// The bytes `range` of line `line_idx`, which starts at document byte
+    /// `base`, colorized by `colorize` unless a frame already did
+    pub fn get_or_insert_with(
+        &mut self,
+        line_idx: usize,
+        base: usize,
+        range: Range<usize>,
+        colorize: impl FnOnce() -> Line<'static>,
+    ) -> Line<'static> {
+        let frame = self.frame;
+        if let Some(entry) = self.lines.get_mut(&(line_idx, range.start)) {
+            if entry.base == base && entry.end == range.end {
+                entry.frame = frame;
+                self.hits += 1;
+                return entry.line.clone();
+            }
+        }
+        self.misses += 1;
+        let line = colorize();
+        self.lines.insert((line_idx, range.start), Entry { base, end: range.end, line: line.clone(), drawn: None, frame });
+        line
+    }
+
+    /// The slice as `draw` puts `overlay` over its colorized line, built
+    /// again only when the overlay differs from the last frame's or the
+    /// slice had to be colorized afresh
+    pub fn drawn_or_insert_with(
+        &mut self,
+        line_idx: usize,
+        base: usize,
+        range: Range<usize>,
+        overlay: Overlay,
+        colorize: impl FnOnce() -> Line<'static>,
+        draw: impl FnOnce(Line<'static>) -> Line<'static>,
+    ) -> Line<'static> {
+        let key = (line_idx, range.start);
+        if let Some(entry) = self.lines.get_mut(&key) {
+            if let Some((drawn_over, drawn)) = entry.drawn.as_ref().filter(|_| entry.base == base && entry.end == range.end) {
+                if *drawn_over == overlay {
+                    entry.frame = self.frame;
+                    self.hits += 1;
+                    return drawn.clone();
+                }
+            }
+        }
+        let line = self.get_or_insert_with(line_idx, base, range, colorize);
+        let entry = self.lines.get_mut(&key).expect("inserted above");
+        self.built += 1;
+        let drawn = draw(line);
+        entry.drawn = Some((overlay, drawn.clone()));
+        drawn
+    }
+
+    /// Drop line `line_idx` and everything below it, after an edit there
+    pub fn invalidate_from(&mut self, line_idx: usize) {
+        self.lines.retain(|&(line, _), _| line < line_idx);
+    }
+
+    /// After an edit that changed `lines` (as they are now) and moved the
+    /// text after it by `delta` bytes, drop those lines and expect the ones
+    /// below at their new offsets. Had the edit joined or split lines,
+    /// those below no longer start where expected and miss.
+    pub fn apply_edit(&mut self, lines: RangeInclusive<usize>, delta: isize) {
+        self.lines.retain(|&(line, _), _| !lines.contains(&line));
+        for (&(line, _), entry) in self.lines.iter_mut() {
+            if line > *lines.end() {
+                entry.base = entry.base.saturating_add_signed(delta);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Slices colorized so far because the cache didn't have them
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Slices found in the cache
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Rows drawn over afresh rather than reused whole
+    pub fn built(&self) -> usize {
+        self.built
+    }
+
+    /// Slices held
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(cache: &mut LineCache<u32>, stamp: u32, rows: &[(usize, usize, Range<usize>)]) {
+        cache.begin_frame(stamp);
+        for (line, base, range) in rows {
+            cache.get_or_insert_with(*line, *base, range.clone(), || Line::from("x"));
+        }
+        cache.end_frame();
+    }
+
+    #[test]
+    fn test_reuse_and_invalidation() {
+        let mut cache = LineCache::new();
+        let rows = [(0, 0, 0..10), (1, 11, 0..4), (2, 16, 0..8)];
+        draw(&mut cache, 1, &rows);
+        draw(&mut cache, 1, &rows);
+        assert_eq!(cache.misses(), 3);
+        assert_eq!(cache.hits(), 3);
+        assert_eq!(cache.len(), 3);
+
+        // An edit on line 1 shifts what follows
+        cache.invalidate_from(1);
+        draw(&mut cache, 1, &[(0, 0, 0..10), (1, 11, 0..5), (2, 17, 0..8)]);
+        assert_eq!(cache.misses(), 5);
+        // A slice further along the line, and a line moved down the file
+        draw(&mut cache, 1, &[(0, 0, 2..10), (1, 11, 0..5), (2, 20, 0..8)]);
+        assert_eq!(cache.misses(), 7);
+        // A new stamp, e.g. another theme
+        draw(&mut cache, 2, &[(1, 11, 0..5)]);
+        assert_eq!(cache.misses(), 8);
+        // Lines scrolled off are forgotten
+        draw(&mut cache, 2, &[(0, 0, 2..10)]);
+        assert_eq!(cache.misses(), 9);
+    }
+
+    fn frame(cache: &mut LineCache<u32>, rows: &[(usize, usize, Overlay)]) {
+        cache.begin_frame(1);
+        for (line, base, overlay) in rows {
+            cache.drawn_or_insert_with(*line, *base, 0..4, overlay.clone(), || Line::from("x"), |line| line);
+        }
+        cache.end_frame();
+    }
+
+    #[test]
+    fn test_drawn_rows_reused() {
+        let mut cache = LineCache::new();
+        let plain = Overlay::default();
+        let cursor = Overlay { marks: vec![0], ..Overlay::default() };
+        frame(&mut cache, &[(0, 0, cursor.clone()), (1, 5, plain.clone()), (2, 10, plain.clone())]);
+        assert_eq!((cache.misses(), cache.built()), (3, 3));
+
+        // The cursor's bracket moves down a line: two rows drawn again,
+        // neither colorized
+        frame(&mut cache, &[(0, 0, plain.clone()), (1, 5, cursor.clone()), (2, 10, plain.clone())]);
+        assert_eq!((cache.misses(), cache.built()), (3, 5));
+
+        // A byte typed on line 1: only it is colorized again
+        cache.apply_edit(1..=1, 1);
+        frame(&mut cache, &[(0, 0, plain.clone()), (1, 5, cursor.clone()), (2, 11, plain.clone())]);
+        assert_eq!((cache.misses(), cache.built()), (4, 6));
+
+        // A line split in two
+        cache.apply_edit(1..=2, 1);
+        frame(&mut cache, &[(0, 0, plain.clone()), (1, 5, cursor), (2, 8, plain)]);
+        assert_eq!((cache.misses(), cache.built()), (6, 8));
+    }
+}
@@ -0,0 +1,95 @@
+use ratatui::style::Style;
+use ratatui::text::{Line, Span};
+
+/// What `:set list` draws for a character, or None to draw it as is.
+/// `trailing` says whether a space is part of the line's trailing run.
+pub fn substitute(c: char, trailing: bool) -> Option<String> {
+    match c {
+        '\t' => Some("→".to_string()),
+        ' ' if trailing => Some("·".to_string()),
+        '\u{FEFF}' => Some("<BOM>".to_string()),
+        '\0'..='\u{1F}' => Some(format!("^{}", (c as u8 ^ 0x40) as char)),
+        '\u{7F}' => Some("^?".to_string()),
+        // C1 controls, and characters that take no room on screen
+        '\u{80}'..='\u{9F}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{2028}'..='\u{202E}'
+        | '\u{2060}'..='\u{2064}' => Some(format!("<{:04X}>", c as u32)),
+        _ => None,
+    }
+}
+
+/// Whether :set list draws `c` in the special style rather than as
+/// whitespace
+fn is_special(c: char) -> bool {
+    !matches!(c, '\t' | ' ')
+}
+
+/// Byte offset in `text` where its trailing spaces start
+pub fn trailing_start(text: &str) -> usize {
+    text.trim_end_matches(' ').len()
+}
+
+/// Redraw a colorized slice of a line for :set list. `trailing_from` is
+/// the byte offset in the slice where the line's trailing spaces start.
+/// Only what's drawn changes, never the text itself.
+pub fn apply(line: Line<'static>, trailing_from: usize, whitespace: Style, special: Style) -> Line<'static> {
+    let mut spans = Vec::with_capacity(line.spans.len());
+    let mut pos = 0;
+    for span in line.spans {
+        let text = span.content.as_ref();
+        if !text.chars().any(|c| substitute(c, true).is_some()) {
+            pos += text.len();
+            spans.push(span);
+            continue;
+        }
+        let mut plain = String::new();
+        for (i, c) in text.char_indices() {
+            match substitute(c, pos + i >= trailing_from) {
+                Some(shown) => {
+                    if !plain.is_empty() {
+                        spans.push(Span::styled(std::mem::take(&mut plain), span.style));
+                    }
+                    let style = if is_special(c) { special } else { whitespace };
+                    spans.push(Span::styled(shown, span.style.patch(style)));
+                }
+                None => plain.push(c),
+            }
+        }
+        if !plain.is_empty() {
+            spans.push(Span::styled(plain, span.style));
+        }
+        pos += text.len();
+    }
+    Line::from(spans)
+}
+
+/// Columns :set list adds to `text` beyond one per character, for putting
+/// the cursor after a `^X` or `<BOM>`
+pub fn extra_width(text: &str) -> usize {
+    text.chars()
+        .filter_map(|c| substitute(c, false))
+        .map(|shown| shown.chars().count() - 1)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shown(line: &Line) -> String {
+        line.spans.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        let text = "\u{FEFF}{\t\"a\u{200B}\": 1,\u{1}  ";
+        let line = Line::from(vec![Span::raw(text.to_string())]);
+        let line = apply(line, trailing_start(text), Style::default(), Style::default());
+        assert_eq!(shown(&line), "<BOM>{→\"a<200B>\": 1,^A··");
+        // Plain lines are left alone
+        let line = apply(Line::from("{\"a b\": 1}"), 10, Style::default(), Style::default());
+        assert_eq!(line.spans.len(), 1);
+        assert_eq!(extra_width("\u{FEFF}\ta\u{1}"), 4 + 1);
+    }
+}
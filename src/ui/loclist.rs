@@ -0,0 +1,253 @@
+//! The location list: places found somewhere in the file by :validate, a
+//! schema check, :grep or :g/pattern/, :extract, or bookmarks, shown by
+//! :copen as a pane under the text and gone through with ]q and [q. Like
+//! the rest of the state kept by byte offset, entries follow edits; their
+//! lines and columns are worked out again where they're shown.
+
+use std::ops::Range;
+
+use crate::buffer::TextChange;
+
+/// Rows the pane takes, its border included
+pub const PANE_HEIGHT: u16 = 10;
+
+/// Most entries a list holds; :grep stops collecting there
+pub const MAX_LOCATIONS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub byte_offset: usize,
+    /// Line and character column of `byte_offset`, as of when they were
+    /// last worked out
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+    pub severity: Severity,
+}
+
+/// What filled the list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Validate,
+    /// Kept up to date by each schema check while it's the list shown
+    Schema,
+    Grep(String),
+    /// Kept up to date as bookmarks are added and found
+    Bookmarks,
+    /// The path :extract was given
+    Extract(String),
+    /// :diffsaved's changed lines
+    DiffSaved,
+}
+
+impl Source {
+    pub fn title(&self) -> String {
+        match self {
+            Source::Validate => "validate".to_string(),
+            Source::Schema => "schema".to_string(),
+            Source::Grep(pattern) => format!("grep {}", pattern),
+            Source::Bookmarks => "bookmarks".to_string(),
+            Source::Extract(path) => format!("extract {}", path),
+            Source::DiffSaved => "changes since save".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LocationList {
+    source: Source,
+    locations: Vec<Location>,
+    /// Entry last jumped to, which ]q and [q go on from
+    current: Option<usize>,
+    /// Row highlighted in the pane
+    pub selected: usize,
+    pub scroll: usize,
+}
+
+impl LocationList {
+    /// A list of `locations`, put in document order
+    pub fn new(source: Source, mut locations: Vec<Location>) -> Self {
+        locations.sort_by_key(|location| location.byte_offset);
+        Self { source, locations, current: None, selected: 0, scroll: 0 }
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    pub fn locations(&self) -> &[Location] {
+        &self.locations
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// The entry `count` on from the current one (the first, forward, with
+    /// none yet), stopping at either end; None when already there
+    pub fn step(&mut self, forward: bool, count: usize) -> Option<usize> {
+        let last = self.locations.len().checked_sub(1)?;
+        let count = count.max(1);
+        let target = match (self.current, forward) {
+            (None, true) => (count - 1).min(last),
+            (None, false) => return None,
+            (Some(current), true) if current < last => (current + count).min(last),
+            (Some(current), false) if current > 0 => current.saturating_sub(count),
+            _ => return None,
+        };
+        self.select(target);
+        Some(target)
+    }
+
+    /// Make entry `i` the current one, as Enter in the pane does
+    pub fn select(&mut self, i: usize) {
+        if i < self.locations.len() {
+            self.current = Some(i);
+            self.selected = i;
+        }
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.locations.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// Scroll so the selected row shows in a pane `height` rows tall
+    pub fn scroll_to_selected(&mut self, height: usize) {
+        self.scroll = super::scroll_to_keep(self.selected, self.scroll, height);
+    }
+
+    /// Keep entries on their text across an edit
+    pub fn apply_change(&mut self, change: &TextChange) {
+        for location in &mut self.locations {
+            location.byte_offset = change.shift_offset(location.byte_offset);
+        }
+    }
+
+    /// Keep entries inside a buffer of `len` bytes, as after a reload
+    pub fn clamp(&mut self, len: usize) {
+        for location in &mut self.locations {
+            location.byte_offset = location.byte_offset.min(len);
+        }
+    }
+
+    /// Work out the lines and columns of `entries` again with `position`,
+    /// for the rows about to be drawn or the entry about to be jumped to
+    pub fn relocate(&mut self, entries: Range<usize>, position: impl Fn(usize) -> (usize, usize)) {
+        let end = entries.end.min(self.locations.len());
+        for location in self.locations.get_mut(entries.start.min(end)..end).into_iter().flatten() {
+            (location.line, location.col) = position(location.byte_offset);
+        }
+    }
+}
+
+/// The line `text` cut down to show beside an entry: without its
+/// indentation or newline, at most `width` characters
+pub fn snippet(text: &str, width: usize) -> String {
+    let text = text.trim();
+    if text.chars().nth(width).is_none() {
+        return text.to_string();
+    }
+    match width.checked_sub(1) {
+        Some(kept) => format!("{}…", text.chars().take(kept).collect::<String>()),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(offsets: &[usize]) -> LocationList {
+        let locations = offsets
+            .iter()
+            .map(|&byte_offset| Location { byte_offset, line: 0, col: byte_offset, message: String::new(), severity: Severity::Info })
+            .collect();
+        LocationList::new(Source::Grep("x".to_string()), locations)
+    }
+
+    fn offsets(list: &LocationList) -> Vec<usize> {
+        list.locations().iter().map(|location| location.byte_offset).collect()
+    }
+
+    #[test]
+    fn test_step() {
+        // Sorted into document order whatever order they were found in
+        let mut list = list(&[30, 10, 20, 40]);
+        assert_eq!(offsets(&list), [10, 20, 30, 40]);
+        assert_eq!(list.step(false, 1), None);
+        assert_eq!(list.step(true, 1), Some(0));
+        assert_eq!(list.step(true, 2), Some(2));
+        // A count past the end stops at the last, and then goes no further
+        assert_eq!(list.step(true, 5), Some(3));
+        assert_eq!(list.step(true, 1), None);
+        assert_eq!(list.step(false, 1), Some(2));
+        assert_eq!(list.step(false, 10), Some(0));
+        assert_eq!(list.step(false, 1), None);
+        assert_eq!(list.current(), Some(0));
+
+        // Enter on a row goes on from there
+        list.select(2);
+        assert_eq!(list.step(true, 1), Some(3));
+        assert_eq!(list.selected, 3);
+        list.select(10);
+        assert_eq!(list.current(), Some(3));
+
+        let mut empty = LocationList::new(Source::Validate, Vec::new());
+        assert_eq!(empty.step(true, 1), None);
+        empty.move_selection(5);
+        assert_eq!(empty.selected, 0);
+    }
+
+    #[test]
+    fn test_edits_and_clamping() {
+        let mut list = list(&[5, 10, 15, 20]);
+        // Grow the text before all but the first
+        list.apply_change(&TextChange { offset: 8, removed: 0, inserted: 4 });
+        assert_eq!(offsets(&list), [5, 14, 19, 24]);
+        // Delete around the second and third: both land where it was
+        list.apply_change(&TextChange { offset: 12, removed: 9, inserted: 2 });
+        assert_eq!(offsets(&list), [5, 12, 12, 17]);
+        // Text cut short under them, as by a reload
+        list.clamp(10);
+        assert_eq!(offsets(&list), [5, 10, 10, 10]);
+
+        list.relocate(1..10, |offset| (offset / 4, offset % 4));
+        let positions: Vec<_> = list.locations().iter().map(|location| (location.line, location.col)).collect();
+        assert_eq!(positions, [(0, 5), (2, 2), (2, 2), (2, 2)]);
+        list.relocate(7..9, |_| unreachable!());
+    }
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(snippet("    \"name\": \"x\",\n", 40), "\"name\": \"x\",");
+        assert_eq!(snippet("abcdef", 4), "abc…");
+        assert_eq!(snippet("ééé", 3), "ééé");
+        assert_eq!(snippet("abc", 0), "");
+    }
+}
@@ -0,0 +1,135 @@
+use std::ops::Range;
+
+use crate::buffer::Buffer;
+use crate::parser::token::TokenKind;
+use crate::parser::{StructuralIndex, Tokenizer};
+
+/// Lines scanned on either side of the cursor when the index can't answer
+const SCAN_WINDOW_LINES: usize = 500;
+
+/// Find the delimiter paired with the one at `offset`.
+///
+/// The structural index answers for closed containers it covers. Otherwise a
+/// bounded window around the offset is tokenized, so brackets inside strings
+/// and comments are never paired.
+pub fn find_match(index: Option<&StructuralIndex>, buffer: &Buffer, offset: usize) -> Option<usize> {
+    if let Some(found) = index.and_then(|index| index.matching_delimiter(offset)) {
+        return Some(found);
+    }
+    if !matches!(buffer.char_at(offset), Some('{' | '}' | '[' | ']')) {
+        return None;
+    }
+
+    let line = buffer.byte_offset_to_line(offset);
+    let first_line = line.saturating_sub(SCAN_WINDOW_LINES);
+    let window = buffer.get_visible_lines(first_line, line - first_line + SCAN_WINDOW_LINES + 1);
+    scan_matching_delimiter(window.as_bytes(), buffer.line_to_byte_offset(first_line), offset)
+}
+
+/// Offsets to highlight for the cursor: the delimiter under it and its
+/// partner, limited to the visible byte range
+pub fn highlights(
+    index: Option<&StructuralIndex>,
+    buffer: &Buffer,
+    cursor_offset: usize,
+    visible: Range<usize>,
+) -> Vec<usize> {
+    match find_match(index, buffer, cursor_offset) {
+        Some(partner) => [cursor_offset, partner]
+            .into_iter()
+            .filter(|offset| visible.contains(offset))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Pair delimiters in `window` (which starts at byte `window_offset` of the
+/// document, on a line boundary) and return the partner of `target`
+fn scan_matching_delimiter(window: &[u8], window_offset: usize, target: usize) -> Option<usize> {
+    let mut tokenizer = Tokenizer::resume(window.to_vec(), window_offset, 0).with_comments(true);
+    let mut stack: Vec<(usize, TokenKind)> = Vec::new();
+    // Stack height at which the target opener sits, once seen
+    let mut target_depth = None;
+
+    while let Some(token) = tokenizer.next_token() {
+        let opener = match token.kind {
+            TokenKind::BraceOpen | TokenKind::BracketOpen => {
+                if token.start == target {
+                    target_depth = Some(stack.len());
+                }
+                stack.push((token.start, token.kind));
+                continue;
+            }
+            TokenKind::BraceClose => TokenKind::BraceOpen,
+            TokenKind::BracketClose => TokenKind::BracketOpen,
+            _ => continue,
+        };
+        // Skip stray closers instead of unwinding past their opener
+        if stack.last().map(|&(_, kind)| kind) != Some(opener) {
+            if token.start == target {
+                return None;
+            }
+            continue;
+        }
+        let (start, _) = stack.pop()?;
+        if token.start == target {
+            return Some(start);
+        }
+        if target_depth == Some(stack.len()) {
+            return Some(token.start);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_for(json: &str) -> StructuralIndex {
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        StructuralIndex::from_tokens(&tokenizer.tokenize_all())
+    }
+
+    #[test]
+    fn test_nested_arrays() {
+        let json = "[[1, [2]], [\"]\"]]";
+        let buffer = Buffer::from_text(json);
+        let index = index_for(json);
+
+        for (open, close) in [(0, 16), (1, 8), (5, 7), (11, 15)] {
+            assert_eq!(find_match(Some(&index), &buffer, open), Some(close));
+            assert_eq!(find_match(Some(&index), &buffer, close), Some(open));
+            assert_eq!(find_match(None, &buffer, open), Some(close));
+            assert_eq!(find_match(None, &buffer, close), Some(open));
+        }
+        // The bracket inside the string is not a delimiter
+        assert_eq!(find_match(None, &buffer, 13), None);
+        assert_eq!(find_match(Some(&index), &buffer, 2), None);
+    }
+
+    #[test]
+    fn test_match_off_screen() {
+        let json = "{\n  \"a\": 1,\n  \"b\": 2\n}\n";
+        let buffer = Buffer::from_text(json);
+        let index = index_for(json);
+        let close = json.rfind('}').unwrap();
+
+        assert_eq!(find_match(Some(&index), &buffer, 0), Some(close));
+        // Only the first two lines are visible
+        let visible = 0..buffer.line_to_byte_offset(2);
+        assert_eq!(highlights(Some(&index), &buffer, 0, visible.clone()), vec![0]);
+        assert_eq!(highlights(Some(&index), &buffer, 3, visible), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_fallback_scan_skips_comments() {
+        let json = "{\n  // ]}\n  \"a\": [1]\n}\n";
+        let buffer = Buffer::from_text(json);
+        let close = json.rfind('}').unwrap();
+        let open = json.find('[').unwrap();
+
+        assert_eq!(find_match(None, &buffer, 0), Some(close));
+        assert_eq!(find_match(None, &buffer, open), Some(open + 2));
+    }
+}
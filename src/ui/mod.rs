@@ -1 +1,56 @@
 pub mod viewport;
+pub mod matchparen;
+pub mod fold;
+pub mod tree;
+pub mod gutter;
+pub mod signs;
+pub mod highlight;
+pub mod preview;
+pub mod theme;
+pub mod mouse;
+pub mod layout;
+pub mod scrollbar;
+pub mod window;
+pub mod help;
+pub mod statusline;
+pub mod list;
+pub mod loclist;
+pub mod elide;
+pub mod line_cache;
+pub mod frame_stats;
+pub mod string_editor;
+pub mod stats;
+pub mod inspect;
+pub mod picker;
+pub mod capabilities;
+pub mod perf;
+pub mod tasks;
+pub mod redraw;
+pub mod format;
+pub mod prompt;
+pub mod terminal;
+
+/// Where a list scrolled to `scroll` should scroll to so row `selected`
+/// shows among `height` rows. A pane with no rows doesn't scroll.
+pub fn scroll_to_keep(selected: usize, scroll: usize, height: usize) -> usize {
+    if selected < scroll {
+        selected
+    } else if height > 0 && selected >= scroll + height {
+        selected + 1 - height
+    } else {
+        scroll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scroll_to_keep() {
+        assert_eq!(scroll_to_keep(3, 5, 10), 3);
+        assert_eq!(scroll_to_keep(7, 5, 10), 5);
+        assert_eq!(scroll_to_keep(15, 5, 10), 6);
+        assert_eq!(scroll_to_keep(15, 5, 0), 5);
+    }
+}
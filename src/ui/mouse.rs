@@ -0,0 +1,134 @@
+use ratatui::layout::{Position, Rect};
+
+/// What one row of the text pane shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenRow {
+    /// Buffer line drawn on the row (the first line of a closed fold)
+    pub line: usize,
+    /// Which of the line's rows this is when wrapping, 0 otherwise
+    pub wrapped_row: usize,
+}
+
+/// Where the last frame put the text and the outline, for turning mouse
+/// positions back into buffer lines and tree rows
+#[derive(Debug, Clone, Default)]
+pub struct ScreenMap {
+    /// Text pane, after the border and gutter
+    pub text: Rect,
+    /// One entry per drawn row, from the top of `text`
+    pub rows: Vec<ScreenRow>,
+    /// First character column shown when lines aren't wrapped
+    pub start_col: usize,
+    pub wrap: bool,
+    /// Inside of the outline pane, when it's open
+    pub tree: Option<Rect>,
+    /// Outline row drawn at the top of `tree`
+    pub tree_scroll: usize,
+    /// Each text window, border included, when the view is split
+    pub windows: Vec<Rect>,
+}
+
+impl ScreenMap {
+    /// Buffer line and character column under a cell of the text pane.
+    /// Clicks below the last line land on it; the column isn't clamped to
+    /// the line's length.
+    pub fn text_position(&self, x: u16, y: u16) -> Option<(usize, usize)> {
+        let area = self.text;
+        if x < area.x || x >= area.x + area.width || y < area.y || y >= area.y + area.height {
+            return None;
+        }
+        self.position_in_text(x - area.x, y - area.y)
+    }
+
+    /// Like `text_position`, but cells outside the pane count as its
+    /// nearest edge, so a drag past the border keeps selecting
+    pub fn text_position_clamped(&self, x: u16, y: u16) -> Option<(usize, usize)> {
+        let area = self.text;
+        if area.width == 0 || area.height == 0 {
+            return None;
+        }
+        let x = x.clamp(area.x, area.x + area.width - 1);
+        let y = y.clamp(area.y, area.y + area.height - 1);
+        self.position_in_text(x - area.x, y - area.y)
+    }
+
+    fn position_in_text(&self, col: u16, row: u16) -> Option<(usize, usize)> {
+        let screen_row = self.rows.get(row as usize).or_else(|| self.rows.last())?;
+        let col = if self.wrap {
+            screen_row.wrapped_row * self.text.width as usize + col as usize
+        } else {
+            self.start_col + col as usize
+        };
+        Some((screen_row.line, col))
+    }
+
+    /// Outline row under a cell of the outline pane
+    pub fn tree_row(&self, x: u16, y: u16) -> Option<usize> {
+        let area = self.tree?;
+        let inside = x >= area.x && x < area.x + area.width && y >= area.y && y < area.y + area.height;
+        inside.then(|| self.tree_scroll + (y - area.y) as usize)
+    }
+
+    /// Text window a cell belongs to
+    pub fn window_at(&self, x: u16, y: u16) -> Option<usize> {
+        self.windows.iter().position(|area| area.contains(Position { x, y }))
+    }
+
+    /// Whether a cell is anywhere over the outline, border included
+    pub fn over_tree(&self, x: u16) -> bool {
+        self.tree.is_some_and(|area| x < area.x + area.width + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(lines: &[(usize, usize)]) -> Vec<ScreenRow> {
+        lines.iter().map(|&(line, wrapped_row)| ScreenRow { line, wrapped_row }).collect()
+    }
+
+    #[test]
+    fn test_text_position() {
+        let map = ScreenMap {
+            text: Rect::new(5, 1, 20, 3),
+            rows: rows(&[(10, 0), (11, 0), (15, 0)]),
+            start_col: 8,
+            ..ScreenMap::default()
+        };
+        assert_eq!(map.text_position(5, 1), Some((10, 8)));
+        assert_eq!(map.text_position(9, 3), Some((15, 12)));
+        // Border, gutter and status bar
+        assert_eq!(map.text_position(4, 1), None);
+        assert_eq!(map.text_position(25, 1), None);
+        assert_eq!(map.text_position(5, 4), None);
+        // Dragging off the bottom right corner
+        assert_eq!(map.text_position_clamped(60, 30), Some((15, 27)));
+
+        // Short files leave rows empty below the last line
+        let map = ScreenMap { rows: rows(&[(0, 0)]), ..map };
+        assert_eq!(map.text_position(6, 3), Some((0, 9)));
+    }
+
+    #[test]
+    fn test_wrapped_and_tree_positions() {
+        let map = ScreenMap {
+            text: Rect::new(30, 1, 10, 4),
+            rows: rows(&[(3, 1), (3, 2), (4, 0)]),
+            start_col: 99,
+            wrap: true,
+            tree: Some(Rect::new(1, 1, 27, 4)),
+            tree_scroll: 6,
+            windows: vec![Rect::new(29, 0, 12, 3), Rect::new(29, 3, 12, 3)],
+        };
+        assert_eq!(map.text_position(32, 1), Some((3, 12)));
+        assert_eq!(map.text_position(30, 2), Some((3, 20)));
+        assert_eq!(map.text_position(30, 3), Some((4, 0)));
+        assert_eq!(map.tree_row(1, 2), Some(7));
+        assert_eq!(map.tree_row(29, 2), None);
+        assert!(map.over_tree(28));
+        assert!(!map.over_tree(30));
+        assert_eq!(map.window_at(35, 4), Some(1));
+        assert_eq!(map.window_at(5, 4), None);
+    }
+}
@@ -0,0 +1,308 @@
+//! The performance overlay's (F12) figures beyond frame times: memory,
+//! caches, the index and pending work. Some of these cost a system call
+//! or a walk to gather, so they're sampled a few times a second rather
+//! than every frame. Also the timings of named operations, on any thread,
+//! that :profile lists.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often the overlay gathers its figures
+pub const REFRESH: Duration = Duration::from_millis(250);
+
+/// Memory the process holds in RAM. Where the current figure can't be had,
+/// the most it has ever held stands in for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resident {
+    pub bytes: usize,
+    pub peak: bool,
+}
+
+/// One gathering of the figures. The cache counts are running totals;
+/// `PerfMetrics` turns them into a hit rate over the last interval.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PerfSample {
+    pub resident: Option<Resident>,
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+    /// Lines in the cache
+    pub cache_lines: usize,
+    /// Estimated heap use of the structural index
+    pub index_bytes: usize,
+    /// Runs of chunks started to index further into the file
+    pub index_runs: usize,
+    /// Times the index was thrown away and built again from the top
+    pub index_rebuilds: usize,
+    /// Edited lines held in the overlay of a large file until saved
+    pub pending_edits: usize,
+    pub undo_depth: usize,
+    pub last_save: Option<Duration>,
+}
+
+/// The figures last gathered, and when
+#[derive(Debug, Clone, Default)]
+pub struct PerfMetrics {
+    sample: PerfSample,
+    hit_rate: Option<f64>,
+    taken: Option<Instant>,
+}
+
+impl PerfMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gather the figures with `collect` unless they were gathered less
+    /// than `REFRESH` ago. True when it did.
+    pub fn refresh(&mut self, now: Instant, collect: impl FnOnce() -> PerfSample) -> bool {
+        if self.taken.is_some_and(|taken| now.saturating_duration_since(taken) < REFRESH) {
+            return false;
+        }
+        let sample = collect();
+        // Counters can go backwards when the cache is replaced; start over
+        let hits = sample.cache_hits.checked_sub(self.sample.cache_hits);
+        let misses = sample.cache_misses.checked_sub(self.sample.cache_misses);
+        if let (Some(hits), Some(misses)) = (hits, misses) {
+            if hits + misses > 0 {
+                self.hit_rate = Some(hits as f64 / (hits + misses) as f64);
+            }
+        }
+        self.sample = sample;
+        self.taken = Some(now);
+        true
+    }
+
+    pub fn sample(&self) -> &PerfSample {
+        &self.sample
+    }
+
+    /// Share of cache lookups that hit since the previous sample; the last
+    /// known rate while nothing is being drawn
+    pub fn hit_rate(&self) -> Option<f64> {
+        self.hit_rate
+    }
+}
+
+/// Memory the process holds in RAM right now, or failing that at most
+pub fn resident_memory() -> Option<Resident> {
+    #[cfg(target_os = "linux")]
+    if let Some(bytes) = std::fs::read_to_string("/proc/self/statm").ok().and_then(|statm| parse_statm(&statm, page_size())) {
+        return Some(Resident { bytes, peak: false });
+    }
+    peak_memory().map(|bytes| Resident { bytes, peak: true })
+}
+
+/// The resident size from /proc/self/statm, whose second field counts pages
+pub fn parse_statm(statm: &str, page_size: usize) -> Option<usize> {
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * page_size)
+}
+
+#[cfg(target_os = "linux")]
+fn page_size() -> usize {
+    // SAFETY: sysconf only reads a system setting
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+#[cfg(unix)]
+fn peak_memory() -> Option<usize> {
+    // SAFETY: getrusage only writes the struct it's given
+    let usage = unsafe {
+        let mut usage = std::mem::zeroed::<libc::rusage>();
+        (libc::getrusage(libc::RUSAGE_SELF, &mut usage) == 0).then_some(usage)?
+    };
+    // Bytes on macOS, kilobytes elsewhere
+    let scale = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    Some(usage.ru_maxrss as usize * scale)
+}
+
+#[cfg(not(unix))]
+fn peak_memory() -> Option<usize> {
+    None
+}
+
+/// Timed operations :profile keeps; older ones are dropped
+pub const PROFILE_LENGTH: usize = 200;
+
+/// An operation timed with `scoped`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timed {
+    pub name: &'static str,
+    /// How much it worked through, where that's known
+    pub bytes: Option<usize>,
+    pub start: Instant,
+    pub took: Duration,
+    /// How many timed operations on its thread it ran inside
+    pub depth: usize,
+    /// Whether it ran off the main thread
+    pub background: bool,
+}
+
+static PROFILE: Mutex<VecDeque<Timed>> = Mutex::new(VecDeque::new());
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Times an operation until it's dropped, then records it for :profile
+#[must_use = "the operation is timed until this is dropped"]
+pub struct Scoped {
+    name: &'static str,
+    bytes: Option<usize>,
+    start: Instant,
+    depth: usize,
+}
+
+/// Start timing the operation `name`, e.g.
+/// `let _timing = perf::scoped("save");`
+pub fn scoped(name: &'static str) -> Scoped {
+    let depth = DEPTH.with(|depth| depth.replace(depth.get() + 1));
+    Scoped { name, bytes: None, start: Instant::now(), depth }
+}
+
+impl Scoped {
+    /// Note how many bytes the operation works through
+    pub fn bytes(&mut self, bytes: usize) {
+        self.bytes = Some(bytes);
+    }
+}
+
+impl Drop for Scoped {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(self.depth));
+        let timed = Timed {
+            name: self.name,
+            bytes: self.bytes,
+            start: self.start,
+            took: self.start.elapsed(),
+            depth: self.depth,
+            background: std::thread::current().name() != Some("main"),
+        };
+        if let Ok(mut profile) = PROFILE.lock() {
+            if profile.len() == PROFILE_LENGTH {
+                profile.pop_front();
+            }
+            profile.push_back(timed);
+        }
+    }
+}
+
+/// The operations timed lately, in the order they finished
+pub fn recent() -> Vec<Timed> {
+    PROFILE.lock().map(|profile| profile.iter().cloned().collect()).unwrap_or_default()
+}
+
+/// :profile's lines for `ops`, in the order they started so what ran
+/// inside another is indented under it: how long ago, how long it took,
+/// and the size it worked on
+pub fn report(ops: &[Timed], now: Instant, size: impl Fn(usize) -> String) -> Vec<String> {
+    let mut ops: Vec<&Timed> = ops.iter().collect();
+    ops.sort_by_key(|op| op.start);
+    ops.iter()
+        .map(|op| {
+            let ago = now.saturating_duration_since(op.start + op.took);
+            let name = format!("{}{}", "  ".repeat(op.depth), op.name);
+            let mut line = format!("{:>8} ago {:>10}  {:<24}", format_duration(ago), format_duration(op.took), name);
+            if let Some(bytes) = op.bytes {
+                line.push_str(&format!(" {}", size(bytes)));
+            }
+            if op.background {
+                line.push_str(" (background)");
+            }
+            line.trim_end().to_string()
+        })
+        .collect()
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    match duration.as_secs_f64() {
+        secs if secs >= 1.0 => format!("{:.2} s", secs),
+        secs if secs >= 0.001 => format!("{:.1} ms", secs * 1e3),
+        secs => format!("{:.0} µs", secs * 1e6),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(hits: usize, misses: usize) -> PerfSample {
+        PerfSample { cache_hits: hits, cache_misses: misses, undo_depth: hits, ..PerfSample::default() }
+    }
+
+    #[test]
+    fn test_refresh() {
+        let mut metrics = PerfMetrics::new();
+        let start = Instant::now();
+        assert!(metrics.refresh(start, || sample(0, 10)));
+        // Nothing looked up in the cache before
+        assert_eq!(metrics.hit_rate(), Some(0.0));
+
+        // Too soon: not gathered at all
+        assert!(!metrics.refresh(start + REFRESH / 2, || panic!("gathered too soon")));
+        assert!(metrics.refresh(start + REFRESH, || sample(30, 20)));
+        assert_eq!(metrics.sample().undo_depth, 30);
+        // 30 hits and 10 misses since the last sample
+        assert_eq!(metrics.hit_rate(), Some(0.75));
+
+        // Idle: the rate stays
+        assert!(metrics.refresh(start + REFRESH * 2, || sample(30, 20)));
+        assert_eq!(metrics.hit_rate(), Some(0.75));
+        // A new cache starts counting again
+        assert!(metrics.refresh(start + REFRESH * 3, || sample(5, 0)));
+        assert_eq!(metrics.hit_rate(), Some(0.75));
+        assert!(metrics.refresh(start + REFRESH * 4, || sample(10, 0)));
+        assert_eq!(metrics.hit_rate(), Some(1.0));
+    }
+
+    #[test]
+    fn test_parse_statm() {
+        assert_eq!(parse_statm("5000 1200 300 100 0 900 0\n", 4096), Some(1200 * 4096));
+        assert_eq!(parse_statm("5000", 4096), None);
+        assert_eq!(parse_statm("", 4096), None);
+    }
+
+    #[test]
+    fn test_resident_memory() {
+        if cfg!(unix) {
+            assert!(resident_memory().is_some_and(|resident| resident.bytes > 0));
+        }
+    }
+
+    #[test]
+    fn test_scoped_nesting() {
+        {
+            let mut outer = scoped("test outer");
+            outer.bytes(10);
+            let _inner = scoped("test inner");
+        }
+        drop(scoped("test after"));
+        let ops = recent();
+        let find = |name| ops.iter().rev().find(|op| op.name == name).unwrap();
+        assert_eq!((find("test outer").depth, find("test outer").bytes), (0, Some(10)));
+        assert_eq!(find("test inner").depth, 1);
+        // Back at the top once the outer operation is done
+        assert_eq!(find("test after").depth, 0);
+        assert!(find("test inner").took <= find("test outer").took);
+    }
+
+    #[test]
+    fn test_report() {
+        let now = Instant::now();
+        let start = now - Duration::from_secs(3);
+        let op = |name, start, took, depth, bytes| Timed { name, bytes, start, took, depth, background: depth == 0 };
+        let ops = [
+            op("search", start + Duration::from_millis(20), Duration::from_micros(40), 1, None),
+            op("save", start, Duration::from_millis(1500), 0, Some(2048)),
+        ];
+        assert_eq!(report(&ops, now, |bytes| format!("{} B", bytes)), vec![
+            "  1.50 s ago     1.50 s  save                     2048 B (background)",
+            "  2.98 s ago      40 µs    search",
+        ]);
+    }
+}
@@ -0,0 +1,273 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::parser::node::ChildCount;
+
+/// Most candidates a picker holds; past this it says it's showing the
+/// first of them
+pub const MAX_CANDIDATES: usize = 10_000;
+
+/// What a key did to a picker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickerOutcome {
+    Picking,
+    Chosen,
+    Cancel,
+}
+
+/// A fuzzy finder overlay: a query line over the candidates matching it,
+/// best first. Each candidate carries a value of its own for whoever
+/// opened the picker to act on.
+#[derive(Debug, Clone)]
+pub struct Picker<T> {
+    pub title: String,
+    items: Vec<(String, T)>,
+    /// Whether candidates past `MAX_CANDIDATES` were left out
+    truncated: bool,
+    pub query: String,
+    /// Indices into `items` matching the query, best first
+    matches: Vec<usize>,
+    /// Selected row of `matches`
+    pub selected: usize,
+    /// First row of `matches` shown
+    pub scroll: usize,
+}
+
+impl<T> Picker<T> {
+    /// Take candidates from `items` until `MAX_CANDIDATES`, so a huge
+    /// source is never walked to its end
+    pub fn new(title: impl Into<String>, items: impl IntoIterator<Item = (String, T)>) -> Self {
+        let mut items = items.into_iter();
+        let taken: Vec<_> = items.by_ref().take(MAX_CANDIDATES).collect();
+        let truncated = items.next().is_some();
+        let matches = (0..taken.len()).collect();
+        Self { title: title.into(), items: taken, truncated, query: String::new(), matches, selected: 0, scroll: 0 }
+    }
+
+    /// Replace the candidates, for a source that grows while the picker
+    /// is open. The query and the selected row stay.
+    pub fn set_items(&mut self, items: impl IntoIterator<Item = (String, T)>) {
+        let (selected, scroll) = (self.selected, self.scroll);
+        let mut items = items.into_iter();
+        self.items = items.by_ref().take(MAX_CANDIDATES).collect();
+        self.truncated = items.next().is_some();
+        let query = std::mem::take(&mut self.query);
+        self.set_query(query);
+        self.select(selected);
+        self.scroll = scroll.min(self.selected);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// How many candidates match the query
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// e.g. "12/340", or "12/10,000 (showing first 10,000)"
+    pub fn counts(&self) -> String {
+        let grouped = |count| ChildCount { count, complete: true };
+        let (shown, total) = (grouped(self.matches.len()), grouped(self.items.len()));
+        if self.truncated {
+            format!("{}/{} (showing first {})", shown, total, total)
+        } else {
+            format!("{}/{}", shown, total)
+        }
+    }
+
+    /// The matching candidates from row `from`, with their row
+    pub fn rows(&self, from: usize) -> impl Iterator<Item = (usize, &str)> + '_ {
+        self.matches.iter().enumerate().skip(from).map(|(row, &i)| (row, self.items[i].0.as_str()))
+    }
+
+    /// The value of the selected candidate
+    pub fn chosen(&self) -> Option<&T> {
+        self.matches.get(self.selected).map(|&i| &self.items[i].1)
+    }
+
+    /// Take the value of the selected candidate, for when the picker is
+    /// done with
+    pub fn take_chosen(&mut self) -> Option<T> {
+        let &i = self.matches.get(self.selected)?;
+        Some(self.items.swap_remove(i).1)
+    }
+
+    /// Handle a key with `height` rows of candidates showing
+    pub fn handle_key(&mut self, key: KeyEvent, height: usize) -> PickerOutcome {
+        let height = height.max(1);
+        match (key.code, key.modifiers) {
+            (KeyCode::Esc, _) | (KeyCode::Char('c'), KeyModifiers::CONTROL) => return PickerOutcome::Cancel,
+            (KeyCode::Enter, _) if self.chosen().is_some() => return PickerOutcome::Chosen,
+            (KeyCode::Down, _) | (KeyCode::Char('n' | 'j'), KeyModifiers::CONTROL) => self.select(self.selected + 1),
+            (KeyCode::Up, _) | (KeyCode::Char('p' | 'k'), KeyModifiers::CONTROL) => {
+                self.select(self.selected.saturating_sub(1))
+            }
+            (KeyCode::PageDown, _) => self.select(self.selected + height),
+            (KeyCode::PageUp, _) => self.select(self.selected.saturating_sub(height)),
+            (KeyCode::Char('u'), KeyModifiers::CONTROL) => self.set_query(String::new()),
+            (KeyCode::Backspace, _) => {
+                let mut query = self.query.clone();
+                query.pop();
+                self.set_query(query);
+            }
+            (KeyCode::Char(_), m) if m.contains(KeyModifiers::CONTROL) => {}
+            (KeyCode::Char(c), _) => self.set_query(format!("{}{}", self.query, c)),
+            _ => {}
+        }
+        self.scroll_to_selected(height);
+        PickerOutcome::Picking
+    }
+
+    /// Replace the query and rank the candidates against it. Ties keep
+    /// the shorter candidate first, then the order they came in.
+    pub fn set_query(&mut self, query: String) {
+        let mut scored: Vec<(i64, usize)> = self.items.iter()
+            .enumerate()
+            .filter_map(|(i, (text, _))| Some((score(&query, text)?, i)))
+            .collect();
+        scored.sort_by_key(|&(score, i)| (-score, self.items[i].0.len(), i));
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.query = query;
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    fn select(&mut self, row: usize) {
+        self.selected = row.min(self.matches.len().saturating_sub(1));
+    }
+
+    /// Scroll so the selection is among `height` rows shown
+    pub fn scroll_to_selected(&mut self, height: usize) {
+        self.scroll = super::scroll_to_keep(self.selected, self.scroll, height);
+    }
+}
+
+/// Whether `c` in a query matches `d` in a candidate: ignoring case
+/// unless the query has capitals of its own
+fn same(c: char, d: char, smart: bool) -> bool {
+    if smart { c == d } else { c == d || c.to_lowercase().eq(d.to_lowercase()) }
+}
+
+/// Whether the candidate character at `i` starts a word
+fn word_start(chars: &[char], i: usize) -> bool {
+    match i.checked_sub(1).map(|prev| chars[prev]) {
+        None => true,
+        Some(prev) => !prev.is_alphanumeric() || (prev.is_lowercase() && chars[i].is_uppercase()),
+    }
+}
+
+/// Where the characters of `query` fall in `candidate`, taken in order
+/// as early as they appear; None unless all of them do
+pub fn positions(query: &str, candidate: &str) -> Option<Vec<usize>> {
+    let smart = query.chars().any(char::is_uppercase);
+    let mut found = Vec::new();
+    let mut chars = candidate.chars().enumerate();
+    for c in query.chars() {
+        let (i, _) = chars.by_ref().find(|&(_, d)| same(c, d, smart))?;
+        found.push(i);
+    }
+    Some(found)
+}
+
+/// How well `query` matches `candidate` as a subsequence, higher being
+/// better: characters at word starts and runs of consecutive characters
+/// count for more, gaps count against. None if it doesn't match at all.
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let found = positions(query, candidate)?;
+    let chars: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut last: Option<usize> = None;
+    for &i in &found {
+        score += 1;
+        if word_start(&chars, i) {
+            score += 8;
+        }
+        match last {
+            Some(last) if i == last + 1 => score += 5,
+            Some(last) => score -= (i - last - 1).min(5) as i64,
+            None => score -= i.min(5) as i64,
+        }
+        last = Some(i);
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(picker: &mut Picker<usize>, code: KeyCode) -> PickerOutcome {
+        picker.handle_key(KeyEvent::new(code, KeyModifiers::NONE), 3)
+    }
+
+    fn ranked(picker: &Picker<usize>) -> Vec<&str> {
+        picker.rows(0).map(|(_, text)| text).collect()
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score("", "anything"), Some(0));
+        assert_eq!(score("xyz", "name"), None);
+        assert_eq!(score("nmae", "name"), None);
+        assert_eq!(positions("nm", "userName"), Some(vec![4, 6]));
+        // Word starts and runs beat scattered characters
+        assert!(score("un", ".user.name").unwrap() > score("un", ".sunny").unwrap());
+        assert!(score("name", ".name").unwrap() > score("name", ".nxaxmxe").unwrap());
+        assert!(score("fn", "firstName").unwrap() > score("fn", "fine").unwrap());
+        // Capitals in the query make it case sensitive
+        assert!(score("id", "ID").is_some());
+        assert!(score("ID", "id").is_none());
+    }
+
+    #[test]
+    fn test_filter_and_choose() {
+        let keys = [".id", ".user.name", ".user.id", ".sunny", ".status"];
+        let mut picker = Picker::new("Keys", keys.iter().enumerate().map(|(i, k)| (k.to_string(), i)));
+        assert_eq!(picker.counts(), "5/5");
+        for c in "us".chars() {
+            press(&mut picker, KeyCode::Char(c));
+        }
+        assert_eq!(ranked(&picker), [".user.id", ".user.name", ".status"]);
+        press(&mut picker, KeyCode::Char('i'));
+        assert_eq!(ranked(&picker), [".user.id"]);
+        press(&mut picker, KeyCode::Backspace);
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Down);
+        assert_eq!(picker.chosen(), Some(&4));
+        assert_eq!(press(&mut picker, KeyCode::Enter), PickerOutcome::Chosen);
+        press(&mut picker, KeyCode::Char('q'));
+        assert_eq!(picker.chosen(), None);
+        assert_eq!(press(&mut picker, KeyCode::Enter), PickerOutcome::Picking);
+        assert_eq!(press(&mut picker, KeyCode::Esc), PickerOutcome::Cancel);
+    }
+
+    #[test]
+    fn test_set_items() {
+        let mut picker = Picker::new("Outline", [("[0…999] (not yet indexed)".to_string(), 0)]);
+        press(&mut picker, KeyCode::Char('9'));
+        picker.set_items((0..3).map(|i| (format!("[{}…{}]", i * 1000, i * 1000 + 999), i)));
+        assert_eq!(picker.query, "9");
+        assert_eq!(ranked(&picker), ["[0…999]", "[1000…1999]", "[2000…2999]"]);
+        press(&mut picker, KeyCode::Down);
+        picker.set_items((0..2).map(|i| (format!("[{}…{}]", i * 1000, i * 1000 + 999), i)));
+        assert_eq!(picker.chosen(), Some(&1));
+    }
+
+    #[test]
+    fn test_capped_and_scrolled() {
+        let mut picker = Picker::new("Keys", (0..).map(|i| (format!("key{}", i), i)));
+        assert_eq!(picker.len(), MAX_CANDIDATES);
+        assert_eq!(picker.counts(), "10,000/10,000 (showing first 10,000)");
+        press(&mut picker, KeyCode::PageDown);
+        press(&mut picker, KeyCode::Down);
+        assert_eq!((picker.selected, picker.scroll), (4, 2));
+        press(&mut picker, KeyCode::PageUp);
+        press(&mut picker, KeyCode::Up);
+        assert_eq!((picker.selected, picker.scroll), (0, 0));
+    }
+}
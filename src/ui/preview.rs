@@ -0,0 +1,300 @@
+use crate::buffer::Buffer;
+use crate::parser::node::NodeId;
+use crate::parser::token::TokenKind;
+use crate::parser::{ChildCount, NodeKind, StructuralIndex, Tokenizer};
+
+/// Pretty-printed lines kept for the popup; the rest are only counted
+const PREVIEW_LINES: usize = 200;
+
+/// Source bytes read for the preview, however large the value
+const PREVIEW_BYTES: usize = 256 * 1024;
+
+const INDENT: &str = "  ";
+
+/// Source bytes read for a one-line preview, however large the object
+const ONE_LINE_BYTES: usize = 4 * 1024;
+
+/// Characters of a value a one-line preview shows before cutting it short
+const ONE_LINE_VALUE: usize = 40;
+
+/// A value pretty-printed for the K popup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValuePreview {
+    /// The key the value belongs to, or the value's kind
+    pub title: String,
+    /// Leading lines of the pretty-printed value
+    pub lines: Vec<String>,
+    /// Lines the whole value takes when pretty-printed
+    pub total_lines: usize,
+}
+
+impl ValuePreview {
+    /// Build a preview of the value at `offset`. On a key, that's the value
+    /// after it.
+    pub fn at(index: &StructuralIndex, buffer: &Buffer, offset: usize) -> Option<Self> {
+        let id = index.node_id_at(offset)?;
+        let (title, value) = if index.is_key_node(id) {
+            let key = index.get(id)?;
+            (buffer.slice(key.start..key.end), index.next_sibling(id)?)
+        } else {
+            (kind_name(index.kind(id)?).to_string(), id)
+        };
+        let node = index.get(value)?;
+        let text = buffer.slice(node.start..node.end.min(node.start + PREVIEW_BYTES));
+        Some(Self {
+            title,
+            lines: pretty_print(&text, PREVIEW_LINES),
+            total_lines: pretty_line_count(index, value),
+        })
+    }
+
+    /// The lines to draw in `rows` rows, ending with a "… N more lines"
+    /// marker when the value doesn't fit
+    pub fn visible_lines(&self, rows: usize) -> Vec<String> {
+        if self.total_lines <= rows && self.lines.len() >= self.total_lines {
+            return self.lines.clone();
+        }
+        let shown = rows.saturating_sub(1).min(self.lines.len());
+        let mut lines = self.lines[..shown].to_vec();
+        let remaining = ChildCount { count: self.total_lines - shown, complete: true };
+        lines.push(format!("… {} more lines", remaining));
+        lines
+    }
+}
+
+/// The object `id` on one line of at most `width` characters, for
+/// :set nodepreview: `{id: 4512, name: "foo", tags: […], …}`. Keys that
+/// read as names go unquoted, long values are cut short and containers
+/// inside are left closed, so only the first few members are read.
+pub fn one_line(index: &StructuralIndex, buffer: &Buffer, id: NodeId, width: usize) -> Option<String> {
+    if index.kind(id)? != NodeKind::Object {
+        return None;
+    }
+    let mut out = String::from("{");
+    let mut budget = ONE_LINE_BYTES;
+    let mut children = index.child_iter(id);
+    let mut more = false;
+    while let Some(key) = children.next() {
+        let Some(value_id) = children.next() else { break };
+        let (Some(key), Some(value)) = (index.get(key), index.get(value_id)) else {
+            break;
+        };
+        if out.chars().count() >= width || budget == 0 {
+            more = true;
+            break;
+        }
+        let raw = buffer.slice(key.start..key.end.min(key.start + budget));
+        budget = budget.saturating_sub(raw.len());
+        let value = match value.kind {
+            NodeKind::Object | NodeKind::Array => {
+                let (open, close) = if value.kind == NodeKind::Object { ('{', '}') } else { ('[', ']') };
+                if index.first_child(value_id).is_none() && index.is_closed(value_id) {
+                    format!("{}{}", open, close)
+                } else {
+                    format!("{}…{}", open, close)
+                }
+            }
+            _ => {
+                let text = buffer.slice(value.start..value.end.min(value.start + (4 * ONE_LINE_VALUE).min(budget)));
+                budget = budget.saturating_sub(text.len());
+                shorten(&text, value.end - value.start > text.len())
+            }
+        };
+        if out.len() > 1 {
+            out.push_str(", ");
+        }
+        out.push_str(&format!("{}: {}", bare_key(&raw), value));
+    }
+    more |= children.next().is_some();
+    out.push_str(if more { ", …}" } else { "}" });
+    if out.chars().count() > width {
+        out = format!("{}…}}", out.chars().take(width.saturating_sub(2)).collect::<String>());
+    }
+    Some(out)
+}
+
+/// A key without its quotes when it reads as a name
+fn bare_key(raw: &str) -> String {
+    let name = raw.strip_prefix('"').and_then(|k| k.strip_suffix('"')).unwrap_or(raw);
+    let identifier = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if identifier { name.to_string() } else { raw.to_string() }
+}
+
+/// A value's text cut to `ONE_LINE_VALUE` characters, closing its quote
+/// if it's a string; `cut` when only part of it was read
+fn shorten(text: &str, cut: bool) -> String {
+    if !cut && text.chars().nth(ONE_LINE_VALUE).is_none() {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(ONE_LINE_VALUE - 1).collect();
+    if text.starts_with('"') { format!("{}…\"", kept) } else { format!("{}…", kept) }
+}
+
+pub(crate) fn kind_name(kind: NodeKind) -> &'static str {
+    match kind {
+        NodeKind::Object => "object",
+        NodeKind::Array => "array",
+        NodeKind::String => "string",
+        NodeKind::Number => "number",
+        NodeKind::Boolean => "boolean",
+        NodeKind::Null => "null",
+        NodeKind::Key => "key",
+        NodeKind::Unknown | NodeKind::Error => "value",
+    }
+}
+
+/// Lines a node takes when pretty-printed: one to open, one per member and
+/// one to close each non-empty container. Counted from the index so huge
+/// values needn't be formatted.
+fn pretty_line_count(index: &StructuralIndex, id: NodeId) -> usize {
+    let Some(node) = index.get(id) else { return 0 };
+    let mut lines = 1;
+    for inner in index.nodes_starting_in(node.start..node.end) {
+        if let Some(count) = index.child_count(inner).filter(|c| c.count > 0) {
+            lines += count.count + 1;
+        }
+    }
+    lines
+}
+
+/// Pretty-print JSON text with two-space indentation, stopping after
+/// `max_lines` lines. Comments are dropped; text cut off mid-value is
+/// printed as far as it goes.
+pub fn pretty_print(text: &str, max_lines: usize) -> Vec<String> {
+    let mut tokenizer = Tokenizer::new(text.to_string()).with_comments(true);
+    let mut tokens = std::iter::from_fn(|| tokenizer.next_token())
+        .filter(|t| !matches!(t.kind, TokenKind::Whitespace | TokenKind::Comment))
+        .peekable();
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut depth = 0usize;
+
+    while lines.len() < max_lines {
+        let Some(token) = tokens.next() else { break };
+        if line.is_empty() {
+            // A closer sits one level out from the members before it
+            let level = match token.kind {
+                TokenKind::BraceClose | TokenKind::BracketClose => depth.saturating_sub(1),
+                _ => depth,
+            };
+            line.push_str(&INDENT.repeat(level));
+        }
+        match token.kind {
+            TokenKind::BraceOpen | TokenKind::BracketOpen => {
+                let close = if token.kind == TokenKind::BraceOpen {
+                    TokenKind::BraceClose
+                } else {
+                    TokenKind::BracketClose
+                };
+                line.push_str(&text[token.start..token.end]);
+                if tokens.next_if(|t| t.kind == close).is_some() {
+                    line.push_str(if close == TokenKind::BraceClose { "}" } else { "]" });
+                } else {
+                    depth += 1;
+                    lines.push(std::mem::take(&mut line));
+                }
+            }
+            TokenKind::BraceClose | TokenKind::BracketClose => {
+                if !line.trim().is_empty() {
+                    // A member left without a trailing comma
+                    lines.push(std::mem::take(&mut line));
+                    line.push_str(&INDENT.repeat(depth.saturating_sub(1)));
+                }
+                depth = depth.saturating_sub(1);
+                line.push_str(&text[token.start..token.end]);
+            }
+            TokenKind::Comma => {
+                line.push(',');
+                lines.push(std::mem::take(&mut line));
+            }
+            TokenKind::Colon => line.push_str(": "),
+            _ => line.push_str(&text[token.start..token.end]),
+        }
+    }
+    if !line.is_empty() && lines.len() < max_lines {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(json: &str) -> (StructuralIndex, Buffer) {
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        (StructuralIndex::from_tokens(&tokenizer.tokenize_all()), Buffer::from_text(json))
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        let json = r#"{"a": [1, 2], "b": {}, "c": {"d": null}}"#;
+        assert_eq!(
+            pretty_print(json, 100),
+            vec![
+                "{",
+                "  \"a\": [",
+                "    1,",
+                "    2",
+                "  ],",
+                "  \"b\": {},",
+                "  \"c\": {",
+                "    \"d\": null",
+                "  }",
+                "}",
+            ]
+        );
+        assert_eq!(pretty_print(json, 3).len(), 3);
+        assert_eq!(pretty_print("\"x\"", 10), vec!["\"x\""]);
+    }
+
+    #[test]
+    fn test_preview_of_key() {
+        let json = r#"{"name": "jim", "tags": ["a", "b", []], "n": 1}"#;
+        let (index, buffer) = setup(json);
+
+        let preview = ValuePreview::at(&index, &buffer, json.find("\"tags\"").unwrap()).unwrap();
+        assert_eq!(preview.title, "\"tags\"");
+        assert_eq!(preview.lines, vec!["[", "  \"a\",", "  \"b\",", "  []", "]"]);
+        assert_eq!(preview.total_lines, 5);
+
+        // The whole document, from its opening brace
+        let preview = ValuePreview::at(&index, &buffer, 0).unwrap();
+        assert_eq!(preview.title, "object");
+        assert_eq!(preview.total_lines, pretty_print(json, 100).len());
+    }
+
+    #[test]
+    fn test_one_line() {
+        let json = r#"{"id": 4512, "name": "foo", "first name": null, "tags": ["a"], "meta": {}, "n": 1}"#;
+        let (index, buffer) = setup(json);
+        assert_eq!(
+            one_line(&index, &buffer, 0, 100).unwrap(),
+            r#"{id: 4512, name: "foo", "first name": null, tags: […], meta: {}, n: 1}"#,
+        );
+        // Cut to the width, ending with what's left out
+        assert_eq!(one_line(&index, &buffer, 0, 30).unwrap(), r#"{id: 4512, name: "foo", "fir…}"#);
+        assert_eq!(one_line(&index, &buffer, 0, 30).unwrap().chars().count(), 30);
+        assert!(one_line(&index, &buffer, index.node_id_at(json.find('[').unwrap()).unwrap(), 100).is_none());
+
+        // A long string is cut short with its quote closed
+        let json = format!(r#"{{"text": "{}", "n": 1}}"#, "x".repeat(500));
+        let (index, buffer) = setup(&json);
+        assert_eq!(one_line(&index, &buffer, 0, 100).unwrap(), format!(r#"{{text: "{}…", n: 1}}"#, "x".repeat(38)));
+    }
+
+    #[test]
+    fn test_truncated_preview() {
+        let json = format!("[{}]", (0..5000).map(|n| n.to_string()).collect::<Vec<_>>().join(","));
+        let (index, buffer) = setup(&json);
+
+        let preview = ValuePreview::at(&index, &buffer, 0).unwrap();
+        assert_eq!(preview.lines.len(), PREVIEW_LINES);
+        assert_eq!(preview.total_lines, 5002);
+        let lines = preview.visible_lines(10);
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[9], "… 4,993 more lines");
+        assert_eq!(preview.visible_lines(10_000).len(), PREVIEW_LINES + 1);
+    }
+}
@@ -0,0 +1,128 @@
+//! A question asked in the status line before doing something that can't
+//! be taken back, like quitting with unsaved changes: "Save changes? (y)es
+//! (n)o (c)ancel". While it's up it gets every key; a choice's key answers
+//! it, Esc or Ctrl-C cancel it, and anything else is ignored. Each choice
+//! carries a value of its own for whoever asked to act on.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// What a key did to a prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptOutcome<T> {
+    Waiting,
+    Answered(T),
+    Cancel,
+}
+
+#[derive(Debug, Clone)]
+pub struct Choice<T> {
+    /// Typed in either case
+    pub key: char,
+    /// Shown with the key's letter in brackets, e.g. `yes` for `(y)es`
+    pub label: &'static str,
+    pub answer: T,
+}
+
+#[derive(Debug, Clone)]
+pub struct Prompt<T> {
+    pub question: String,
+    pub choices: Vec<Choice<T>>,
+}
+
+impl<T: Copy> Prompt<T> {
+    pub fn new(question: impl Into<String>, choices: Vec<Choice<T>>) -> Self {
+        Self { question: question.into(), choices }
+    }
+
+    pub fn handle_key(&self, key: KeyEvent) -> PromptOutcome<T> {
+        match key.code {
+            KeyCode::Esc => PromptOutcome::Cancel,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => PromptOutcome::Cancel,
+            KeyCode::Char(c) => self.choices
+                .iter()
+                .find(|choice| choice.key.eq_ignore_ascii_case(&c))
+                .map_or(PromptOutcome::Waiting, |choice| PromptOutcome::Answered(choice.answer)),
+            _ => PromptOutcome::Waiting,
+        }
+    }
+
+    /// The line to show, in pieces, each with whether it's a choice's key
+    /// and so drawn highlighted: "Save changes? (", "y", ")es (", "n", ...
+    pub fn pieces(&self) -> Vec<(String, bool)> {
+        let mut pieces = Vec::new();
+        let mut plain = format!("{} ", self.question);
+        for (i, choice) in self.choices.iter().enumerate() {
+            if i > 0 {
+                plain.push(' ');
+            }
+            let at = choice.label.char_indices().find(|&(_, c)| c.eq_ignore_ascii_case(&choice.key));
+            let (before, key, after) = match at {
+                Some((at, c)) => (&choice.label[..at], c, &choice.label[at + c.len_utf8()..]),
+                None => ("", choice.key, choice.label),
+            };
+            plain.push_str(before);
+            plain.push('(');
+            pieces.push((std::mem::take(&mut plain), false));
+            pieces.push((key.to_string(), true));
+            plain.push(')');
+            plain.push_str(after);
+        }
+        pieces.push((plain, false));
+        pieces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Answer {
+        Yes,
+        No,
+        Cancel,
+    }
+
+    fn prompt() -> Prompt<Answer> {
+        Prompt::new("Save changes?", vec![
+            Choice { key: 'y', label: "yes", answer: Answer::Yes },
+            Choice { key: 'n', label: "no", answer: Answer::No },
+            Choice { key: 'c', label: "cancel", answer: Answer::Cancel },
+        ])
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_answers() {
+        let prompt = prompt();
+        assert_eq!(prompt.handle_key(key(KeyCode::Char('y'))), PromptOutcome::Answered(Answer::Yes));
+        assert_eq!(prompt.handle_key(key(KeyCode::Char('N'))), PromptOutcome::Answered(Answer::No));
+        assert_eq!(prompt.handle_key(key(KeyCode::Char('c'))), PromptOutcome::Answered(Answer::Cancel));
+        // Esc and Ctrl-C always cancel; other keys wait for an answer
+        assert_eq!(prompt.handle_key(key(KeyCode::Esc)), PromptOutcome::Cancel);
+        assert_eq!(prompt.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)), PromptOutcome::Cancel);
+        assert_eq!(prompt.handle_key(key(KeyCode::Char('x'))), PromptOutcome::Waiting);
+        assert_eq!(prompt.handle_key(key(KeyCode::Enter)), PromptOutcome::Waiting);
+        assert_eq!(prompt.handle_key(key(KeyCode::Down)), PromptOutcome::Waiting);
+    }
+
+    #[test]
+    fn test_pieces() {
+        let line: String = prompt().pieces().into_iter().map(|(text, _)| text).collect();
+        assert_eq!(line, "Save changes? (y)es (n)o (c)ancel");
+        let keys: Vec<String> = prompt().pieces().into_iter().filter(|&(_, key)| key).map(|(text, _)| text).collect();
+        assert_eq!(keys, ["y", "n", "c"]);
+
+        // A key in the middle of its label, or not in it at all
+        let prompt = Prompt::new("Overwrite?", vec![
+            Choice { key: 'a', label: "all", answer: 0 },
+            Choice { key: 'k', label: "skip", answer: 1 },
+            Choice { key: 'x', label: "none", answer: 2 },
+        ]);
+        let line: String = prompt.pieces().into_iter().map(|(text, _)| text).collect();
+        assert_eq!(line, "Overwrite? (a)ll s(k)ip (x)none");
+    }
+}
@@ -0,0 +1,163 @@
+//! When to draw a frame. The screen is drawn again only when something
+//! may have changed it: input, a background job at work, a timer coming
+//! due or the once-a-second tick that keeps the FPS and clock-driven
+//! parts of the status bar current. In between, the event loop sleeps.
+//!
+//! The terminal library's poll only wakes for input, so background jobs
+//! can't wake it themselves. While one runs the loop polls at frame rate
+//! to pick up what it sends, which also keeps progress bars moving; once
+//! they're all done it sleeps until input or the next deadline.
+
+use std::time::{Duration, Instant};
+
+/// How long to wait for input between frames while a background job runs
+pub const FRAME: Duration = Duration::from_millis(16);
+
+/// The longest the screen goes without being drawn
+pub const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Redraw {
+    dirty: bool,
+    busy: bool,
+    last_draw: Option<Instant>,
+    draws: u64,
+}
+
+impl Redraw {
+    /// The first frame is always due
+    pub fn new() -> Self {
+        Self { dirty: true, busy: false, last_draw: None, draws: 0 }
+    }
+
+    /// Something changed what's on screen
+    pub fn mark(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Whether background work is in progress. Every frame is drawn while
+    /// it is, and one more once it stops, to show the finished state.
+    pub fn set_busy(&mut self, busy: bool) {
+        if busy || self.busy {
+            self.dirty = true;
+        }
+        self.busy = busy;
+    }
+
+    /// Whether to draw a frame at `now`
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.dirty || self.last_draw.is_none_or(|last| now.saturating_duration_since(last) >= TICK)
+    }
+
+    /// A frame was drawn at `now`
+    pub fn drew(&mut self, now: Instant) {
+        self.dirty = false;
+        self.last_draw = Some(now);
+        self.draws += 1;
+    }
+
+    /// Frames drawn so far
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    /// How long to wait for input at `now`: no time at all with a frame
+    /// due, a frame's time while busy, otherwise until the first of
+    /// `deadlines` or the next tick
+    pub fn timeout(&self, now: Instant, deadlines: impl IntoIterator<Item = Instant>) -> Duration {
+        if self.is_due(now) {
+            return Duration::ZERO;
+        }
+        let tick = self.last_draw.map_or(now, |last| last + TICK);
+        let wake = deadlines.into_iter().fold(tick, Instant::min);
+        let wait = wake.saturating_duration_since(now);
+        if self.busy {
+            wait.min(FRAME)
+        } else {
+            wait
+        }
+    }
+
+    /// Waking at `now` without input: a deadline that has come means a
+    /// timer went off, which may change the screen
+    pub fn wake(&mut self, now: Instant, deadlines: impl IntoIterator<Item = Instant>) {
+        if deadlines.into_iter().any(|deadline| deadline <= now) {
+            self.dirty = true;
+        }
+    }
+}
+
+impl Default for Redraw {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run an event loop on a simulated clock until `end`, drawing when
+    /// due and waiting as long as `timeout` says. `input` are the times
+    /// keys arrive, `busy` when background work runs and `deadlines`
+    /// timers going off.
+    fn simulate(
+        redraw: &mut Redraw,
+        start: Instant,
+        end: Duration,
+        input: &[Duration],
+        busy: std::ops::Range<Duration>,
+        deadlines: &[Duration],
+    ) {
+        let mut now = start;
+        let mut input = input.iter().map(|&at| start + at).peekable();
+        while now < start + end {
+            redraw.set_busy(busy.contains(&(now - start)));
+            if redraw.is_due(now) {
+                redraw.drew(now);
+            }
+            let pending: Vec<Instant> = deadlines.iter().map(|&at| start + at).filter(|&at| at > now).collect();
+            let wait = redraw.timeout(now, pending.iter().copied());
+            assert!(wait > Duration::ZERO, "spinning at {:?}", now - start);
+            match input.peek() {
+                Some(&key) if key <= now + wait => {
+                    now = key.max(now);
+                    input.next();
+                    redraw.mark();
+                }
+                _ => {
+                    now += wait;
+                    redraw.wake(now, pending);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_idle() {
+        // Ten idle seconds: the first frame, then one a tick
+        let mut redraw = Redraw::new();
+        simulate(&mut redraw, Instant::now(), Duration::from_secs(10), &[], Duration::ZERO..Duration::ZERO, &[]);
+        assert_eq!(redraw.draws(), 10);
+    }
+
+    #[test]
+    fn test_input_and_deadlines() {
+        // A frame per key, and one when a message expires
+        let ms = Duration::from_millis;
+        let mut redraw = Redraw::new();
+        let keys = [ms(100), ms(200), ms(300)];
+        simulate(&mut redraw, Instant::now(), ms(1990), &keys, ms(0)..ms(0), &[ms(1500)]);
+        assert_eq!(redraw.draws(), 1 + 3 + 1 + 1);
+    }
+
+    #[test]
+    fn test_busy() {
+        // Frame rate while a job runs for half a second, and one more after
+        let ms = Duration::from_millis;
+        let mut redraw = Redraw::new();
+        simulate(&mut redraw, Instant::now(), ms(990), &[], ms(0)..ms(500), &[]);
+        let busy_frames = 500 / FRAME.as_millis() as u64;
+        assert!((busy_frames..=busy_frames + 2).contains(&redraw.draws()), "{} draws", redraw.draws());
+    }
+}
@@ -0,0 +1,88 @@
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Style;
+
+/// Thumb of a scrollbar `track` rows tall, as (first row, rows), for a view
+/// showing `visible` of `total` lines from `start`. The thumb is at least one
+/// row, and only touches the ends when the view does.
+pub fn thumb(track: u16, start: usize, visible: usize, total: usize) -> (u16, u16) {
+    let track_len = track as usize;
+    if total <= visible || track_len == 0 {
+        return (0, track);
+    }
+    let len = (visible * track_len / total).clamp(1, track_len);
+    let travel = track_len - len;
+    let scrollable = total - visible;
+    let offset = if start == 0 {
+        0
+    } else if start >= scrollable {
+        travel
+    } else {
+        (start * travel / scrollable).clamp(1, travel.saturating_sub(1).max(1))
+    };
+    (offset as u16, len as u16)
+}
+
+/// Vim's ruler position: "All", "Top", "Bot" or how far down the view is
+pub fn position_label(start: usize, visible: usize, total: usize) -> String {
+    let above = start;
+    let below = total.saturating_sub(start + visible);
+    match (above, below) {
+        (0, 0) => "All".to_string(),
+        (0, _) => "Top".to_string(),
+        (_, 0) => "Bot".to_string(),
+        _ => format!("{}%", above * 100 / (above + below)),
+    }
+}
+
+/// Draw a scrollbar down `area` (one column wide), with a tick for each
+/// line in `marks`
+pub fn render(
+    buf: &mut Buffer,
+    area: Rect,
+    view: (usize, usize, usize),
+    marks: &[usize],
+    track_style: Style,
+    thumb_style: Style,
+    mark_style: Style,
+) {
+    let (start, visible, total) = view;
+    let (thumb_start, thumb_len) = thumb(area.height, start, visible, total);
+    for row in 0..area.height {
+        let on_thumb = (thumb_start..thumb_start + thumb_len).contains(&row);
+        let (symbol, style) = if on_thumb { ("┃", thumb_style) } else { ("│", track_style) };
+        buf[(area.x, area.y + row)].set_symbol(symbol).set_style(style);
+    }
+    for &line in marks {
+        let row = (line * area.height as usize / total.max(1)).min(area.height as usize - 1);
+        buf[(area.x, area.y + row as u16)].set_symbol("━").set_style(mark_style);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thumb() {
+        // Everything fits
+        assert_eq!(thumb(20, 0, 30, 10), (0, 20));
+        // 10M lines: a one-row thumb that only reaches the ends at the ends
+        assert_eq!(thumb(20, 0, 40, 10_000_000), (0, 1));
+        assert_eq!(thumb(20, 40, 40, 10_000_000), (1, 1));
+        assert_eq!(thumb(20, 5_000_000, 40, 10_000_000), (9, 1));
+        assert_eq!(thumb(20, 9_999_950, 40, 10_000_000), (18, 1));
+        assert_eq!(thumb(20, 9_999_960, 40, 10_000_000), (19, 1));
+        // Half the file visible
+        assert_eq!(thumb(10, 50, 50, 100), (5, 5));
+    }
+
+    #[test]
+    fn test_position_label() {
+        assert_eq!(position_label(0, 40, 30), "All");
+        assert_eq!(position_label(0, 40, 100), "Top");
+        assert_eq!(position_label(60, 40, 100), "Bot");
+        assert_eq!(position_label(30, 40, 100), "50%");
+        assert_eq!(position_label(3_700_000, 40, 10_000_040), "37%");
+    }
+}
@@ -0,0 +1,261 @@
+//! The sign column: a cell before the text of each line marking what there
+//! is to know about it - a structural error, a :diff change, a duplicate
+//! key, a bookmark. Signs are kept by byte offset, so they move with edits
+//! as everything else kept by offset does, and only turned into lines for
+//! the rows being drawn. A line with several shows the one with the highest
+//! priority.
+
+use std::ops::Range;
+
+use ratatui::style::Style;
+
+use crate::buffer::{Buffer, TextChange};
+use crate::parser::diff::ChangeKind;
+use super::theme::Theme;
+
+/// What a sign marks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignKind {
+    /// A structural error
+    Error,
+    /// A structural warning: a trailing comma in JSONC, an index limit
+    Warning,
+    /// A line :diff found added, removed or changed
+    Diff(ChangeKind),
+    /// A key its object already had, counted as an error or a warning
+    DuplicateKey { error: bool },
+    Bookmark,
+}
+
+impl SignKind {
+    pub fn glyph(self) -> char {
+        match self {
+            SignKind::Error => 'E',
+            SignKind::Warning => 'W',
+            SignKind::Diff(kind) => kind.sign(),
+            SignKind::DuplicateKey { .. } => crate::parser::dupkeys::SIGN,
+            SignKind::Bookmark => crate::navigation::bookmarks::SIGN,
+        }
+    }
+
+    pub fn style(self, theme: &Theme) -> Style {
+        match self {
+            SignKind::Error | SignKind::DuplicateKey { error: true } => theme.error,
+            SignKind::Warning | SignKind::DuplicateKey { error: false } => theme.hint,
+            SignKind::Diff(ChangeKind::Added) => theme.diff_added,
+            SignKind::Diff(ChangeKind::Removed) => theme.diff_removed,
+            SignKind::Diff(ChangeKind::Changed) => theme.diff_changed,
+            SignKind::Bookmark => theme.bookmark,
+        }
+    }
+
+    /// The priority it's placed with unless there's reason otherwise:
+    /// errors over :diff changes over duplicate keys over warnings over
+    /// bookmarks
+    pub fn priority(self) -> u8 {
+        match self {
+            SignKind::Error => 50,
+            SignKind::Diff(_) => 40,
+            SignKind::DuplicateKey { .. } => 30,
+            SignKind::Warning => 20,
+            SignKind::Bookmark => 10,
+        }
+    }
+}
+
+/// When the sign column is drawn (:set signcolumn)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignColumn {
+    /// While there's a sign anywhere in the buffer
+    #[default]
+    Auto,
+    Yes,
+    No,
+}
+
+impl SignColumn {
+    /// The setting :set signcolumn names
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(SignColumn::Auto),
+            "yes" => Some(SignColumn::Yes),
+            "no" => Some(SignColumn::No),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SignColumn::Auto => "auto",
+            SignColumn::Yes => "yes",
+            SignColumn::No => "no",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sign {
+    offset: usize,
+    kind: SignKind,
+    priority: u8,
+}
+
+/// The signs placed in a buffer, in document order
+#[derive(Debug, Default)]
+pub struct Signs {
+    placed: Vec<Sign>,
+}
+
+impl Signs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark the line holding byte `at` with a sign of `kind`
+    pub fn place(&mut self, at: usize, kind: SignKind, priority: u8) {
+        let i = self.placed.partition_point(|sign| sign.offset <= at);
+        self.placed.insert(i, Sign { offset: at, kind, priority });
+    }
+
+    /// Mark the lines holding each of `offsets`, in any order, with a sign
+    /// of `kind`, sorting once rather than finding each one its place
+    pub fn place_all(&mut self, offsets: impl IntoIterator<Item = usize>, kind: SignKind, priority: u8) {
+        self.placed.extend(offsets.into_iter().map(|offset| Sign { offset, kind, priority }));
+        self.placed.sort_by_key(|sign| sign.offset);
+    }
+
+    /// Take away every sign of `kinds`
+    pub fn clear(&mut self, kinds: &[SignKind]) {
+        self.placed.retain(|sign| !kinds.contains(&sign.kind));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.placed.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.placed.len()
+    }
+
+    /// Move the signs after an edit along with the text; one on a byte the
+    /// edit took out goes with it
+    pub fn apply_change(&mut self, change: &TextChange) {
+        let end = change.offset + change.removed;
+        self.placed.retain_mut(|sign| {
+            if (change.offset..end).contains(&sign.offset) {
+                return false;
+            }
+            sign.offset = change.shift_offset(sign.offset);
+            true
+        });
+    }
+
+    /// The signs to show on `lines` of `buffer`
+    pub fn lines(&self, buffer: &Buffer, lines: Range<usize>) -> LineSigns {
+        let mut shown = LineSigns::new(lines.clone());
+        let from = buffer.line_to_byte_offset(lines.start);
+        let to = if lines.end < buffer.line_count() { buffer.line_to_byte_offset(lines.end) } else { usize::MAX };
+        let first = self.placed.partition_point(|sign| sign.offset < from);
+        for sign in self.placed[first..].iter().take_while(|sign| sign.offset < to) {
+            shown.offer(buffer.byte_offset_to_line(sign.offset), sign.kind, sign.priority);
+        }
+        shown
+    }
+}
+
+/// The sign each of a run of lines shows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineSigns {
+    lines: Range<usize>,
+    shown: Vec<Option<(SignKind, u8)>>,
+}
+
+impl LineSigns {
+    pub fn new(lines: Range<usize>) -> Self {
+        Self { shown: vec![None; lines.len()], lines }
+    }
+
+    /// Show `kind` on `line` if nothing of the same or a higher priority is
+    /// there already. Lines outside the run are passed over.
+    pub fn offer(&mut self, line: usize, kind: SignKind, priority: u8) {
+        if !self.lines.contains(&line) {
+            return;
+        }
+        let shown = &mut self.shown[line - self.lines.start];
+        if shown.is_none_or(|(_, shown)| priority > shown) {
+            *shown = Some((kind, priority));
+        }
+    }
+
+    pub fn get(&self, line: usize) -> Option<SignKind> {
+        let at = line.checked_sub(self.lines.start)?;
+        self.shown.get(at).copied().flatten().map(|(kind, _)| kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shown(signs: &Signs, buffer: &Buffer) -> Vec<Option<char>> {
+        let shown = signs.lines(buffer, 0..buffer.line_count());
+        (0..buffer.line_count()).map(|line| shown.get(line).map(SignKind::glyph)).collect()
+    }
+
+    #[test]
+    fn test_priority() {
+        let buffer = Buffer::from_text("[\n  1,\n  2\n]\n");
+        let mut signs = Signs::new();
+        signs.place(4, SignKind::Bookmark, SignKind::Bookmark.priority());
+        signs.place(3, SignKind::Error, SignKind::Error.priority());
+        signs.place(5, SignKind::Warning, SignKind::Warning.priority());
+        signs.place(9, SignKind::Bookmark, 10);
+        signs.place(8, SignKind::Warning, 10);
+        // A tie goes to the first on the line
+        assert_eq!(shown(&signs, &buffer), [None, Some('E'), Some('W'), None, None]);
+
+        // Offered at draw time, against what's placed
+        let mut lines = signs.lines(&buffer, 1..3);
+        lines.offer(1, SignKind::Diff(ChangeKind::Changed), 40);
+        lines.offer(2, SignKind::Diff(ChangeKind::Added), 40);
+        lines.offer(3, SignKind::Bookmark, 10);
+        assert_eq!((lines.get(0), lines.get(1), lines.get(2), lines.get(3)), (None, Some(SignKind::Error), Some(SignKind::Diff(ChangeKind::Added)), None));
+
+        signs.clear(&[SignKind::Error, SignKind::Warning]);
+        assert_eq!(shown(&signs, &buffer), [None, Some('*'), Some('*'), None, None]);
+        assert_eq!(signs.len(), 2);
+
+        // Placed all at once, out of order, they land as one at a time would
+        signs.place_all([8, 3], SignKind::Error, SignKind::Error.priority());
+        assert_eq!(shown(&signs, &buffer), [None, Some('E'), Some('E'), None, None]);
+        assert_eq!(signs.len(), 4);
+    }
+
+    #[test]
+    fn test_shift_on_edit() {
+        let mut buffer = Buffer::from_text("[\n  1,\n  2,\n  3\n]\n");
+        let mut signs = Signs::new();
+        for (at, kind) in [(4, SignKind::Error), (9, SignKind::Warning), (14, SignKind::Bookmark)] {
+            signs.place(at, kind, kind.priority());
+        }
+        assert_eq!(shown(&signs, &buffer), [None, Some('E'), Some('W'), Some('*'), None, None]);
+
+        // Two lines put in before them
+        buffer.insert(2, "  0,\n  0,\n").unwrap();
+        for change in buffer.drain_deltas() {
+            signs.apply_change(&change);
+        }
+        assert_eq!(shown(&signs, &buffer), [None, None, None, Some('E'), Some('W'), Some('*'), None, None]);
+
+        // The line with the warning taken out, and the sign with it; an
+        // edit on the error's line just before it leaves it there
+        let line = buffer.line_to_byte_offset(4);
+        buffer.delete(line, line + 5).unwrap();
+        buffer.insert(line - 3, "1").unwrap();
+        for change in buffer.drain_deltas() {
+            signs.apply_change(&change);
+        }
+        assert_eq!(buffer.slice(0..buffer.len_bytes()), "[\n  0,\n  0,\n  11,\n  3\n]\n");
+        assert_eq!(shown(&signs, &buffer), [None, None, None, Some('E'), Some('*'), None, None]);
+    }
+}
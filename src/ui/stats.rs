@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use super::format::NumberFormat;
+use super::preview::{kind_name, pretty_print, ValuePreview};
+use crate::buffer::Buffer;
+use crate::parser::decode;
+use crate::parser::node::NodeId;
+use crate::parser::{NodeKind, StructuralIndex};
+
+/// Keys listed by name; the rest are only counted
+const LISTED_KEYS: usize = 100;
+
+/// Kinds in the order :stats counts them
+const KINDS: [NodeKind; 6] = [
+    NodeKind::Object,
+    NodeKind::Array,
+    NodeKind::String,
+    NodeKind::Number,
+    NodeKind::Boolean,
+    NodeKind::Null,
+];
+
+/// :stats for the value at `offset` (after the key, on a key), shown in the
+/// K popup. A container whose end isn't indexed yet is counted as far as
+/// the index goes, and says so. The title gives its size as `numbers`
+/// writes it; the statistics themselves are JSON, so their numbers are plain.
+pub fn stats(index: &StructuralIndex, buffer: &Buffer, offset: usize, numbers: NumberFormat) -> Option<ValuePreview> {
+    let mut id = index.node_id_at(offset)?;
+    if index.is_key_node(id) {
+        id = index.next_sibling(id)?;
+    }
+    let json = stats_json(index, buffer, id)?;
+    let lines = pretty_print(&json, usize::MAX);
+    let partial = index.kind(id).is_some_and(|kind| matches!(kind, NodeKind::Object | NodeKind::Array)) && !index.is_closed(id);
+    let size = numbers.size(index.subtree_size_bytes(id).unwrap_or(0));
+    Some(ValuePreview {
+        title: if partial { format!("stats: {} (indexed part)", size) } else { format!("stats: {}", size) },
+        total_lines: lines.len(),
+        lines,
+    })
+}
+
+/// The statistics for node `id` as a JSON object
+fn stats_json(index: &StructuralIndex, buffer: &Buffer, id: NodeId) -> Option<String> {
+    let node = index.get(id)?;
+    let mut counts = [0usize; KINDS.len()];
+    let mut keys = 0;
+    let mut depth = 0;
+    for inner in index.nodes_starting_in(node.start..node.end) {
+        let Some(info) = index.get(inner) else { continue };
+        depth = depth.max(info.depth - node.depth);
+        if index.is_key_node(inner) {
+            keys += 1;
+        } else if let Some(slot) = KINDS.iter().position(|&kind| kind == info.kind) {
+            counts[slot] += 1;
+        }
+    }
+
+    let mut out = String::from("{");
+    let _ = write!(out, "\"kind\": \"{}\", \"bytes\": {}, \"depth\": {}, \"nodes\": {{", kind_name(node.kind), node.end - node.start, depth);
+    let counted = KINDS.iter().zip(counts).filter(|&(_, count)| count > 0);
+    let counted: Vec<String> = counted.map(|(&kind, count)| format!("\"{}\": {}", kind_name(kind), count)).collect();
+    out.push_str(&counted.join(", "));
+    if keys > 0 {
+        let _ = write!(out, "{}\"key\": {}", if counted.is_empty() { "" } else { ", " }, keys);
+    }
+    out.push('}');
+
+    match node.kind {
+        NodeKind::Array => {
+            let elements: Vec<NodeId> = index.child_iter(id).collect();
+            let _ = write!(out, ", \"elements\": {}", elements.len());
+            let numbers: Option<Vec<f64>> = elements.iter()
+                .map(|&e| index.get(e).filter(|n| n.kind == NodeKind::Number))
+                .map(|n| n.and_then(|n| decode::decode_number(&buffer.slice(n.start..n.end))).map(|n| n.as_f64()))
+                .collect();
+            if let Some(numbers) = numbers.filter(|n| !n.is_empty()) {
+                let min = numbers.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+                let _ = write!(out, ", \"min\": {}, \"max\": {}, \"mean\": {}", min, max, mean);
+            }
+        }
+        NodeKind::Object => {
+            let names: Vec<String> = index.member_iter(id)
+                .map(|(key, _)| key)
+                .filter_map(|key| index.get(key))
+                .map(|key| buffer.slice(key.start..key.end))
+                .collect();
+            let listed = &names[..names.len().min(LISTED_KEYS)];
+            let _ = write!(out, ", \"keys\": [{}]", listed.join(", "));
+            if names.len() > listed.len() {
+                let _ = write!(out, ", \"more keys\": {}", names.len() - listed.len());
+            }
+            let mut seen = HashSet::new();
+            let mut duplicates = Vec::new();
+            for name in &names {
+                let decoded = decode::decode_string(name).ok().unwrap_or_else(|| name.clone());
+                if !seen.insert(decoded.clone()) && !duplicates.contains(&decoded) {
+                    duplicates.push(decoded);
+                }
+            }
+            if !duplicates.is_empty() {
+                let duplicates: Vec<String> = duplicates.iter().map(|name| decode::encode_string(name)).collect();
+                let _ = write!(out, ", \"duplicate keys\": [{}]", duplicates.join(", "));
+            }
+        }
+        _ => {}
+    }
+    if matches!(node.kind, NodeKind::Object | NodeKind::Array) && !index.is_closed(id) {
+        out.push_str(", \"partial\": true");
+    }
+    out.push('}');
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    fn stats_of(json: &str, at: &str) -> String {
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(json.to_string()).tokenize_all());
+        let buffer = Buffer::from_text(json);
+        let id = index.node_id_at(json.find(at).unwrap()).unwrap();
+        stats_json(&index, &buffer, id).unwrap()
+    }
+
+    #[test]
+    fn test_stats() {
+        let json = r#"{"a": [1, 2.5, -3], "b": {"x": true, "y": null, "x": []}, "s": "t"}"#;
+        assert_eq!(
+            stats_of(json, "[1"),
+            r#"{"kind": "array", "bytes": 12, "depth": 1, "nodes": {"array": 1, "number": 3}, "elements": 3, "min": -3, "max": 2.5, "mean": 0.16666666666666666}"#
+        );
+        assert_eq!(
+            stats_of(json, "{\"x"),
+            r#"{"kind": "object", "bytes": 31, "depth": 1, "nodes": {"object": 1, "array": 1, "boolean": 1, "null": 1, "key": 3}, "keys": ["x", "y", "x"], "duplicate keys": ["x"]}"#
+        );
+        assert_eq!(stats_of(r#"["a", 1]"#, "["), r#"{"kind": "array", "bytes": 8, "depth": 1, "nodes": {"array": 1, "string": 1, "number": 1}, "elements": 2}"#);
+        assert!(stats_of(r#"{"open": [1, 2"#, "[").ends_with(r#""min": 1, "max": 2, "mean": 1.5, "partial": true}"#));
+    }
+
+    #[test]
+    fn test_stats_popup() {
+        let json = r#"{"k": [1, 2]}"#;
+        let index = StructuralIndex::from_tokens(&Tokenizer::new(json.to_string()).tokenize_all());
+        let preview = stats(&index, &Buffer::from_text(json), 1, NumberFormat::Comma).unwrap();
+        assert_eq!(preview.title, "stats: 6 B");
+        assert_eq!(preview.lines[1], "  \"kind\": \"array\",");
+        assert_eq!(preview.total_lines, preview.lines.len());
+    }
+}
@@ -0,0 +1,155 @@
+/// The built-in status bar. Placeholders for parts that are often empty
+/// (`%n`, `%i`, `%r`, `%e`, `%M`, `%S`) bring their own separators.
+pub const DEFAULT_STATUSLINE: &str = " %f%m (%s) | %t:%L | %l:%c %V%n%i%r%e |%M FPS: %F%S | F12: perf";
+
+/// The built-in status bar for a file whose long lines were split into
+/// rows, where line numbers say little: the cursor's byte offset instead
+pub const SPLIT_LINES_STATUSLINE: &str = " %f%m (%s) | byte %o %V%n%i%r%e |%M FPS: %F%S | F12: perf";
+
+use super::format::NumberFormat;
+
+/// What the status line placeholders stand for, already formatted
+#[derive(Debug, Clone, Default)]
+pub struct StatusInfo {
+    /// %f
+    pub file_name: String,
+    /// %m: " [+]" when modified
+    pub modified: String,
+    /// %s
+    pub file_size: String,
+    /// %p: JSON path of the value under the cursor
+    pub path: String,
+    /// %l and %c, 1-based
+    pub line: usize,
+    pub col: usize,
+    /// %o: byte offset of the cursor
+    pub offset: usize,
+    /// %t: first line shown, 1-based
+    pub top_line: usize,
+    /// %L
+    pub line_count: usize,
+    /// %V: All, Top, Bot or how far down the view is
+    pub view_position: String,
+    /// %P: how far through the file the cursor is
+    pub percent: usize,
+    /// %n: " | " and the kind and size of the node under the cursor
+    pub node: String,
+    /// %i: " | [item N / M]" for an element of an array
+    pub item: String,
+    /// %r: " | record N of M" in JSON Lines files
+    pub record: String,
+    /// %e: " | N errors"
+    pub errors: String,
+    /// %M: " MODE |" outside normal mode
+    pub mode: String,
+    /// %F
+    pub fps: f64,
+    /// %S: " | " and load, save or indexing progress
+    pub progress: String,
+    /// How %l, %c, %o, %t and %L group their digits
+    pub numbers: NumberFormat,
+}
+
+/// Fill in `template` and cut it to `width` columns. `%%` is a percent
+/// sign; unknown placeholders are kept as typed.
+pub fn format_status(template: &str, info: &StatusInfo, width: usize) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let Some(code) = chars.next() else {
+            out.push('%');
+            break;
+        };
+        match code {
+            '%' => out.push('%'),
+            'f' => out.push_str(&info.file_name),
+            'm' => out.push_str(&info.modified),
+            's' => out.push_str(&info.file_size),
+            'p' => out.push_str(&info.path),
+            'l' => out.push_str(&info.numbers.count(info.line)),
+            'c' => out.push_str(&info.numbers.count(info.col)),
+            'o' => out.push_str(&info.numbers.count(info.offset)),
+            't' => out.push_str(&info.numbers.count(info.top_line)),
+            'L' => out.push_str(&info.numbers.count(info.line_count)),
+            'V' => out.push_str(&info.view_position),
+            'P' => out.push_str(&format!("{}%", info.percent)),
+            'n' => out.push_str(&info.node),
+            'i' => out.push_str(&info.item),
+            'r' => out.push_str(&info.record),
+            'e' => out.push_str(&info.errors),
+            'M' => out.push_str(&info.mode),
+            'F' => out.push_str(&format!("{:.1}", info.fps)),
+            'S' => out.push_str(&info.progress),
+            other => {
+                out.push('%');
+                out.push(other);
+            }
+        }
+    }
+    truncate(out, width)
+}
+
+/// Cut `text` to `width` characters, marking the cut with an ellipsis
+fn truncate(text: String, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text;
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut cut: String = text.chars().take(width - 1).collect();
+    cut.push('…');
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> StatusInfo {
+        StatusInfo {
+            file_name: "data.json".into(),
+            modified: " [+]".into(),
+            file_size: "2.45 MB".into(),
+            path: "$.users[3]".into(),
+            line: 45,
+            col: 12,
+            offset: 1234,
+            top_line: 30,
+            line_count: 5678,
+            view_position: "Top".into(),
+            percent: 1,
+            fps: 60.0,
+            ..StatusInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_default_template() {
+        assert_eq!(
+            format_status(DEFAULT_STATUSLINE, &info(), 200),
+            " data.json [+] (2.45 MB) | 30:5,678 | 45:12 Top | FPS: 60.0 | F12: perf"
+        );
+        let plain = StatusInfo { numbers: NumberFormat::Plain, ..info() };
+        assert_eq!(format_status("%t:%L @%o", &plain, 80), "30:5678 @1234");
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let info = info();
+        assert_eq!(format_status("%p @%o %P", &info, 80), "$.users[3] @1,234 1%");
+        let item = StatusInfo { item: " | [item 4,512 / 100,000]".into(), ..info.clone() };
+        assert_eq!(format_status("%l%i", &item, 80), "45 | [item 4,512 / 100,000]");
+        // Escapes, unknown placeholders and a trailing percent sign
+        assert_eq!(format_status("100%% %q %l%", &info, 80), "100% %q 45%");
+        assert_eq!(format_status("", &info, 80), "");
+        // Too wide for the terminal
+        assert_eq!(format_status("%f %s", &info, 8), "data.js…");
+        assert_eq!(format_status("%f", &info, 9), "data.json");
+        assert_eq!(format_status("%f", &info, 0), "");
+    }
+}
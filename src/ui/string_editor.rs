@@ -0,0 +1,149 @@
+use std::ops::Range;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// What a key did to the :edit-string overlay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorOutcome {
+    Editing,
+    Save,
+    Cancel,
+}
+
+/// The :edit-string overlay: the decoded text of one string literal,
+/// edited as plain lines and escaped again when saved
+#[derive(Debug, Clone)]
+pub struct StringEditor {
+    /// Span of the literal being edited, quotes included
+    pub span: Range<usize>,
+    lines: Vec<String>,
+    /// Cursor line
+    pub row: usize,
+    /// Cursor column, in characters
+    pub col: usize,
+}
+
+impl StringEditor {
+    /// Edit `text`, the value of the literal at `span`, with the cursor at
+    /// its end
+    pub fn new(span: Range<usize>, text: &str) -> Self {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        let row = lines.len() - 1;
+        let col = lines[row].chars().count();
+        Self { span, lines, row, col }
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// The edited value, lines joined by newlines
+    pub fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> EditorOutcome {
+        match (key.code, key.modifiers) {
+            (KeyCode::Esc, _) => return EditorOutcome::Cancel,
+            (KeyCode::Char('s'), KeyModifiers::CONTROL) => return EditorOutcome::Save,
+            (KeyCode::Char(_), m) if m.contains(KeyModifiers::CONTROL) => {}
+            (KeyCode::Char(c), _) => self.insert(c),
+            (KeyCode::Tab, _) => self.insert('\t'),
+            (KeyCode::Enter, _) => {
+                let at = self.byte_col();
+                let rest = self.lines[self.row].split_off(at);
+                self.lines.insert(self.row + 1, rest);
+                self.row += 1;
+                self.col = 0;
+            }
+            (KeyCode::Backspace, _) if self.col > 0 => {
+                self.col -= 1;
+                let at = self.byte_col();
+                self.lines[self.row].remove(at);
+            }
+            (KeyCode::Backspace, _) if self.row > 0 => {
+                let line = self.lines.remove(self.row);
+                self.row -= 1;
+                self.col = self.line_len();
+                self.lines[self.row].push_str(&line);
+            }
+            (KeyCode::Delete, _) if self.col < self.line_len() => {
+                let at = self.byte_col();
+                self.lines[self.row].remove(at);
+            }
+            (KeyCode::Delete, _) if self.row + 1 < self.lines.len() => {
+                let line = self.lines.remove(self.row + 1);
+                self.lines[self.row].push_str(&line);
+            }
+            (KeyCode::Left, _) if self.col > 0 => self.col -= 1,
+            (KeyCode::Left, _) if self.row > 0 => {
+                self.row -= 1;
+                self.col = self.line_len();
+            }
+            (KeyCode::Right, _) if self.col < self.line_len() => self.col += 1,
+            (KeyCode::Right, _) if self.row + 1 < self.lines.len() => {
+                self.row += 1;
+                self.col = 0;
+            }
+            (KeyCode::Up, _) if self.row > 0 => {
+                self.row -= 1;
+                self.col = self.col.min(self.line_len());
+            }
+            (KeyCode::Down, _) if self.row + 1 < self.lines.len() => {
+                self.row += 1;
+                self.col = self.col.min(self.line_len());
+            }
+            (KeyCode::Home, _) => self.col = 0,
+            (KeyCode::End, _) => self.col = self.line_len(),
+            _ => {}
+        }
+        EditorOutcome::Editing
+    }
+
+    fn insert(&mut self, c: char) {
+        let at = self.byte_col();
+        self.lines[self.row].insert(at, c);
+        self.col += 1;
+    }
+
+    fn line_len(&self) -> usize {
+        self.lines[self.row].chars().count()
+    }
+
+    fn byte_col(&self) -> usize {
+        let line = &self.lines[self.row];
+        line.char_indices().nth(self.col).map_or(line.len(), |(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(editor: &mut StringEditor, keys: &[KeyCode]) -> EditorOutcome {
+        let mut outcome = EditorOutcome::Editing;
+        for &code in keys {
+            outcome = editor.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+        }
+        outcome
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut editor = StringEditor::new(3..10, "é🎉\nab");
+        assert_eq!((editor.row, editor.col), (1, 2));
+        press(&mut editor, &[KeyCode::Up, KeyCode::Char('x'), KeyCode::Left, KeyCode::Left, KeyCode::Enter]);
+        assert_eq!(editor.text(), "é\n🎉x\nab");
+        press(&mut editor, &[KeyCode::Backspace, KeyCode::End, KeyCode::Delete, KeyCode::Tab]);
+        assert_eq!(editor.text(), "é🎉x\tab");
+        press(&mut editor, &[KeyCode::Home, KeyCode::Left, KeyCode::Backspace]);
+        assert_eq!(editor.text(), "é🎉x\tab");
+        assert_eq!(press(&mut editor, &[KeyCode::Right, KeyCode::Backspace]), EditorOutcome::Editing);
+        assert_eq!(editor.text(), "🎉x\tab");
+
+        let save = KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL);
+        assert_eq!(editor.handle_key(save), EditorOutcome::Save);
+        assert_eq!(press(&mut editor, &[KeyCode::Esc]), EditorOutcome::Cancel);
+        assert_eq!(editor.span, 3..10);
+    }
+}
@@ -0,0 +1,270 @@
+//! Work running in the background: saves, indexing, schema checks,
+//! filters, diffs, checksums, the document's shape, conversions and pipes.
+//! Each has a `Progress` shared with the thread doing it, which the thread
+//! advances and checks for being cancelled. The status bar shows a spinner
+//! and the most recent task's progress, :tasks lists them all, and Ctrl-C
+//! cancels the most recent one that can be.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::perf::format_duration;
+
+/// The spinner's frames, one every `SPIN`
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const SPIN: Duration = Duration::from_millis(100);
+
+/// How far a task running on another thread has got
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// Units of work done, in whatever the task counts: bytes, nodes
+    pub done: AtomicUsize,
+    /// Units in all, or 0 while unknown
+    pub total: AtomicUsize,
+    /// Set to stop it where it is
+    pub cancel: AtomicBool,
+}
+
+impl Progress {
+    pub fn percent(&self) -> usize {
+        let total = self.total.load(Ordering::Relaxed).max(1);
+        (self.done.load(Ordering::Relaxed) * 100 / total).min(100)
+    }
+
+    /// Whether anything says how far along it is
+    pub fn is_known(&self) -> bool {
+        self.total.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+/// What a task is doing; there's at most one of each at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskKind {
+    Save,
+    Index,
+    Schema,
+    Filter,
+    Diff,
+    Checksum,
+    Shape,
+    Convert,
+    Pipe,
+}
+
+impl TaskKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            TaskKind::Save => "Saving",
+            TaskKind::Index => "Indexing",
+            TaskKind::Schema => "Checking schema",
+            TaskKind::Filter => "Filtering",
+            TaskKind::Diff => "Diffing",
+            TaskKind::Checksum => "Hashing",
+            TaskKind::Shape => "Measuring",
+            TaskKind::Convert => "Converting",
+            TaskKind::Pipe => "Piping",
+        }
+    }
+
+    /// The key that cancels it, if any. Esc is pressed too often by habit
+    /// to throw away a save; a schema check starts again after the next
+    /// edit anyway.
+    pub fn cancelled_by(self) -> Option<&'static str> {
+        match self {
+            TaskKind::Save => Some("Ctrl-C"),
+            TaskKind::Schema => None,
+            _ => Some("Esc"),
+        }
+    }
+}
+
+/// A task the editor is waiting on
+#[derive(Debug, Clone)]
+pub struct Task {
+    pub kind: TaskKind,
+    /// What it works on, for :tasks: a file name, a command
+    pub detail: String,
+    pub progress: Arc<Progress>,
+    pub started: Instant,
+    /// What happens once it's done, as the status bar says: `then quitting`
+    pub then: Option<&'static str>,
+}
+
+/// The background tasks running, oldest first
+#[derive(Debug, Default)]
+pub struct TaskManager {
+    tasks: Vec<Task>,
+}
+
+impl TaskManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a task with a fresh `Progress` to hand to its thread. One of
+    /// the same kind still tracked is taken to be over.
+    pub fn start(&mut self, kind: TaskKind, detail: impl Into<String>) -> Arc<Progress> {
+        let progress = Arc::new(Progress::default());
+        self.track(kind, detail, progress.clone());
+        progress
+    }
+
+    /// Track a task whose `Progress` was made elsewhere
+    pub fn track(&mut self, kind: TaskKind, detail: impl Into<String>, progress: Arc<Progress>) {
+        self.finish(kind);
+        self.tasks.push(Task { kind, detail: detail.into(), progress, started: Instant::now(), then: None });
+    }
+
+    /// Stop tracking a task, finished or dropped
+    pub fn finish(&mut self, kind: TaskKind) {
+        self.tasks.retain(|task| task.kind != kind);
+    }
+
+    pub fn get(&self, kind: TaskKind) -> Option<&Task> {
+        self.tasks.iter().find(|task| task.kind == kind)
+    }
+
+    pub fn get_mut(&mut self, kind: TaskKind) -> Option<&mut Task> {
+        self.tasks.iter_mut().find(|task| task.kind == kind)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// The task most worth showing: the one started last
+    pub fn foremost(&self) -> Option<&Task> {
+        self.tasks.last()
+    }
+
+    /// The most recently started task Ctrl-C cancels, which is anything
+    /// that can be, or with `esc` what Esc is shown cancelling
+    pub fn foremost_cancellable(&self, esc: bool) -> Option<TaskKind> {
+        self.tasks
+            .iter()
+            .rev()
+            .find(|task| task.kind.cancelled_by().is_some_and(|key| !esc || key == "Esc"))
+            .map(|task| task.kind)
+    }
+
+    /// Set a task's cancel flag and stop tracking it
+    pub fn cancel(&mut self, kind: TaskKind) -> Option<Task> {
+        let at = self.tasks.iter().position(|task| task.kind == kind)?;
+        let task = self.tasks.remove(at);
+        task.progress.cancel.store(true, Ordering::Relaxed);
+        Some(task)
+    }
+
+    /// The status bar's part: `⠹ Diffing… 40% (Esc cancels)`, with how
+    /// many others are running
+    pub fn status(&self, now: Instant) -> Option<String> {
+        let task = self.foremost()?;
+        let frame = now.saturating_duration_since(task.started).as_millis() / SPIN.as_millis();
+        let mut status = format!("{} {}…", SPINNER[frame as usize % SPINNER.len()], task.kind.label());
+        if task.progress.is_known() {
+            status.push_str(&format!(" {}%", task.progress.percent()));
+        }
+        if let Some(then) = task.then {
+            status.push_str(&format!(", {}", then));
+        }
+        if self.tasks.len() > 1 {
+            status.push_str(&format!(" (+{} more)", self.tasks.len() - 1));
+        }
+        if let Some(key) = task.kind.cancelled_by() {
+            status.push_str(&format!(" ({} cancels)", key));
+        }
+        Some(status)
+    }
+
+    /// The :tasks pane: each task, most recent first, with how long it has
+    /// run and how far it has got
+    pub fn list(&self, now: Instant) -> Vec<String> {
+        self.tasks
+            .iter()
+            .rev()
+            .map(|task| {
+                let percent = if task.progress.is_known() { format!("{}%", task.progress.percent()) } else { "…".to_string() };
+                let mut line = format!(
+                    "{:>10}  {:>4}  {:<16} {}",
+                    format_duration(now.saturating_duration_since(task.started)),
+                    percent,
+                    task.kind.label(),
+                    task.detail
+                );
+                if let Some(key) = task.kind.cancelled_by() {
+                    line = format!("{:<60} ({} cancels)", line.trim_end(), key);
+                }
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_reaches_the_thread() {
+        let mut tasks = TaskManager::new();
+        let progress = tasks.start(TaskKind::Checksum, "");
+        let worker = {
+            let progress = progress.clone();
+            std::thread::spawn(move || {
+                let mut done = 0;
+                loop {
+                    done += 1;
+                    progress.done.store(done, Ordering::Relaxed);
+                    if progress.is_cancelled() {
+                        return done;
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+        tasks.start(TaskKind::Schema, "");
+        // Esc passes over the schema check to the checksum
+        assert_eq!(tasks.foremost_cancellable(true), Some(TaskKind::Checksum));
+        assert!(tasks.cancel(TaskKind::Checksum).is_some());
+        assert!(worker.join().unwrap() > 0);
+        assert!(tasks.get(TaskKind::Checksum).is_none());
+        assert_eq!(tasks.foremost_cancellable(false), None);
+
+        // A save is for Ctrl-C alone
+        tasks.start(TaskKind::Save, "data.json");
+        assert_eq!(tasks.foremost_cancellable(true), None);
+        assert_eq!(tasks.foremost_cancellable(false), Some(TaskKind::Save));
+    }
+
+    #[test]
+    fn test_status() {
+        let mut tasks = TaskManager::new();
+        assert_eq!(tasks.status(Instant::now()), None);
+        let progress = tasks.start(TaskKind::Diff, "other.json");
+        let started = tasks.foremost().unwrap().started;
+        assert_eq!(tasks.status(started).unwrap(), "⠋ Diffing… (Esc cancels)");
+        progress.total.store(200, Ordering::Relaxed);
+        progress.done.store(80, Ordering::Relaxed);
+        assert_eq!(tasks.status(started + SPIN * 2).unwrap(), "⠹ Diffing… 40% (Esc cancels)");
+
+        let save = tasks.start(TaskKind::Save, "data.json");
+        save.total.store(10, Ordering::Relaxed);
+        tasks.get_mut(TaskKind::Save).unwrap().then = Some("then quitting");
+        let started = tasks.foremost().unwrap().started;
+        assert_eq!(tasks.status(started).unwrap(), "⠋ Saving… 0%, then quitting (+1 more) (Ctrl-C cancels)");
+
+        let list = tasks.list(started);
+        assert_eq!(list.len(), 2);
+        assert!(list[0].contains("Saving") && list[0].contains("data.json"), "{:?}", list);
+        assert!(list[1].contains("40%") && list[1].ends_with("(Esc cancels)"), "{:?}", list);
+    }
+}
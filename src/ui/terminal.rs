@@ -0,0 +1,82 @@
+//! Putting the terminal back as it was. Raw mode and the alternate screen
+//! have to be undone however the editor stops: a normal quit, an error
+//! returned from the main loop, or a panic, whose hook has to do it before
+//! the message is printed. Whichever gets there first restores; the rest
+//! find nothing left to do.
+
+use std::sync::{Arc, Mutex};
+
+type Restore = Box<dyn FnOnce() + Send>;
+
+/// Restores the terminal when dropped, or when asked to first
+pub struct TerminalGuard {
+    restore: Arc<Mutex<Option<Restore>>>,
+}
+
+impl TerminalGuard {
+    /// Guard a terminal that `restore` puts back; make it as soon as the
+    /// terminal is changed, before anything else can fail
+    pub fn new(restore: impl FnOnce() + Send + 'static) -> Self {
+        Self { restore: Arc::new(Mutex::new(Some(Box::new(restore)))) }
+    }
+
+    /// Restore now, unless that's been done already
+    pub fn restore(&self) {
+        restore_once(&self.restore);
+    }
+
+    /// Restoring from somewhere the guard can't go, like a panic hook
+    pub fn restorer(&self) -> impl Fn() + Send + Sync + 'static {
+        let restore = Arc::clone(&self.restore);
+        move || restore_once(&restore)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+fn restore_once(restore: &Mutex<Option<Restore>>) {
+    // A panic while restoring mustn't stop the next try from giving up
+    let restore = restore.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+    if let Some(restore) = restore {
+        restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_restores_once() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counted = || {
+            let count = Arc::clone(&count);
+            TerminalGuard::new(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+
+        drop(counted());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Restored early, as before returning an error, then dropped
+        let guard = counted();
+        guard.restore();
+        guard.restore();
+        drop(guard);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        // The panic hook gets there first, and may outlive the guard
+        let guard = counted();
+        let restorer = guard.restorer();
+        restorer();
+        drop(guard);
+        restorer();
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,384 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use ratatui::style::{Color, Modifier, Style};
+
+use crate::config::{config_dir, parse_entries, Value};
+
+/// Names of the built-in themes, for :set theme= and --theme
+pub const BUILTIN_THEMES: &[&str] = &["dark", "light", "monochrome"];
+
+/// Styles for everything the editor draws
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub name: String,
+    pub key: Style,
+    pub string: Style,
+    pub number: Style,
+    pub boolean: Style,
+    pub null: Style,
+    /// Braces, brackets
+    pub punctuation: Style,
+    pub colon: Style,
+    /// JSONC comments
+    pub comment: Style,
+    /// Whitespace, commas and anything unrecognized
+    pub text: Style,
+    /// Layered over a string with a bad escape
+    pub invalid: Style,
+    /// Nodes the index marked as errors
+    pub error: Style,
+    pub selection: Style,
+    pub status_bar: Style,
+    pub message_error: Style,
+    pub line_number: Style,
+    pub current_line_number: Style,
+    /// Layered over the delimiters of the container under the cursor
+    pub current_node: Style,
+    /// Summary of a closed fold
+    pub fold: Style,
+    /// De-emphasized text: "… N more" rows
+    pub hint: Style,
+    /// Floating windows
+    pub popup: Style,
+    /// Border of the pane with focus
+    pub focus_border: Style,
+    /// Tabs and trailing spaces under :set list
+    pub whitespace: Style,
+    /// Control characters, BOMs and zero-width characters under :set list
+    pub special: Style,
+    /// :diff signs in the gutter
+    pub diff_added: Style,
+    pub diff_removed: Style,
+    pub diff_changed: Style,
+    /// Bookmark signs in the gutter
+    pub bookmark: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        let fg = |color| Style::default().fg(color);
+        Self {
+            name: "dark".to_string(),
+            key: fg(Color::LightBlue).add_modifier(Modifier::BOLD),
+            string: fg(Color::Green),
+            number: fg(Color::Yellow),
+            boolean: fg(Color::Cyan),
+            null: fg(Color::Gray),
+            punctuation: fg(Color::Blue),
+            colon: fg(Color::Magenta),
+            comment: fg(Color::DarkGray),
+            text: fg(Color::White),
+            invalid: Style::default().add_modifier(Modifier::UNDERLINED).underline_color(Color::Red),
+            error: fg(Color::Red),
+            selection: Style::default().bg(Color::DarkGray),
+            status_bar: Style::default().bg(Color::DarkGray).fg(Color::White),
+            message_error: Style::default().bg(Color::Red).fg(Color::White),
+            line_number: fg(Color::DarkGray),
+            current_line_number: fg(Color::Yellow),
+            current_node: Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD),
+            fold: Style::default().fg(Color::Black).bg(Color::DarkGray),
+            hint: fg(Color::DarkGray),
+            popup: Style::default().bg(Color::Black),
+            focus_border: fg(Color::Cyan),
+            whitespace: fg(Color::DarkGray),
+            special: fg(Color::Black).bg(Color::Magenta),
+            diff_added: fg(Color::Green),
+            diff_removed: fg(Color::Red),
+            diff_changed: fg(Color::Yellow),
+            bookmark: fg(Color::Magenta),
+        }
+    }
+
+    pub fn light() -> Self {
+        let fg = |color| Style::default().fg(color);
+        Self {
+            name: "light".to_string(),
+            key: fg(Color::Blue).add_modifier(Modifier::BOLD),
+            string: fg(Color::Green),
+            number: fg(Color::Red),
+            boolean: fg(Color::Magenta),
+            null: fg(Color::DarkGray),
+            punctuation: fg(Color::DarkGray),
+            colon: fg(Color::DarkGray),
+            comment: fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+            text: fg(Color::Black),
+            invalid: Style::default().add_modifier(Modifier::UNDERLINED).underline_color(Color::Red),
+            error: fg(Color::Red).add_modifier(Modifier::BOLD),
+            selection: Style::default().bg(Color::Gray),
+            status_bar: Style::default().bg(Color::Gray).fg(Color::Black),
+            message_error: Style::default().bg(Color::Red).fg(Color::White),
+            line_number: fg(Color::DarkGray),
+            current_line_number: fg(Color::Black).add_modifier(Modifier::BOLD),
+            current_node: Style::default().bg(Color::Gray).add_modifier(Modifier::BOLD),
+            fold: Style::default().fg(Color::Black).bg(Color::Gray),
+            hint: fg(Color::DarkGray),
+            popup: Style::default().bg(Color::White).fg(Color::Black),
+            focus_border: fg(Color::Blue),
+            whitespace: fg(Color::Gray),
+            special: fg(Color::White).bg(Color::Magenta),
+            diff_added: fg(Color::Green),
+            diff_removed: fg(Color::Red),
+            diff_changed: fg(Color::Blue),
+            bookmark: fg(Color::Magenta),
+        }
+    }
+
+    /// No colors at all, only text attributes, for terminals without color
+    /// support (or NO_COLOR)
+    pub fn monochrome() -> Self {
+        let plain = Style::default();
+        let with = |modifier| Style::default().add_modifier(modifier);
+        Self {
+            name: "monochrome".to_string(),
+            key: with(Modifier::BOLD),
+            string: plain,
+            number: plain,
+            boolean: plain,
+            null: plain,
+            punctuation: plain,
+            colon: plain,
+            comment: with(Modifier::DIM),
+            text: plain,
+            invalid: with(Modifier::UNDERLINED),
+            error: with(Modifier::UNDERLINED),
+            selection: with(Modifier::REVERSED),
+            status_bar: with(Modifier::REVERSED),
+            message_error: with(Modifier::REVERSED | Modifier::BOLD),
+            line_number: with(Modifier::DIM),
+            current_line_number: with(Modifier::BOLD),
+            current_node: with(Modifier::REVERSED),
+            fold: with(Modifier::REVERSED),
+            hint: with(Modifier::DIM),
+            popup: plain,
+            focus_border: with(Modifier::BOLD),
+            whitespace: with(Modifier::DIM),
+            special: with(Modifier::REVERSED),
+            diff_added: with(Modifier::BOLD),
+            diff_removed: with(Modifier::BOLD),
+            diff_changed: with(Modifier::BOLD),
+            bookmark: with(Modifier::BOLD),
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "monochrome" | "mono" => Some(Self::monochrome()),
+            _ => None,
+        }
+    }
+
+    /// A built-in theme, or the config file's theme if it has that name.
+    /// Also returns any problems found in the config file.
+    pub fn named(name: &str) -> Result<(Self, Vec<String>)> {
+        if let Some(theme) = Self::builtin(name) {
+            return Ok((theme, Vec::new()));
+        }
+        if let Some(path) = Self::config_path().filter(|path| path.exists()) {
+            let (theme, warnings) = Self::load(&path)?;
+            if theme.name == name {
+                return Ok((theme, warnings));
+            }
+        }
+        bail!("Unknown theme: {} (built in: {})", name, BUILTIN_THEMES.join(", "))
+    }
+
+    /// The theme to start with: the one asked for on the command line, else
+    /// the config file's, else monochrome without `color` (NO_COLOR or a
+    /// dumb terminal) and dark otherwise. Problems are reported alongside a
+    /// usable theme rather than failing.
+    pub fn startup(requested: Option<&str>, color: bool) -> (Self, Vec<String>) {
+        if let Some(name) = requested {
+            return Self::named(name).unwrap_or_else(|e| (Self::dark(), vec![e.to_string()]));
+        }
+        if let Some(path) = Self::config_path().filter(|path| path.exists()) {
+            return Self::load(&path).unwrap_or_else(|e| (Self::dark(), vec![format!("{:#}", e)]));
+        }
+        if !color {
+            return (Self::monochrome(), Vec::new());
+        }
+        (Self::dark(), Vec::new())
+    }
+
+    /// ~/.config/jim/theme.toml, honoring XDG_CONFIG_HOME
+    pub fn config_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("theme.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<(Self, Vec<String>)> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read theme {}", path.display()))?;
+        Ok(Self::parse(&text))
+    }
+
+    /// Parse a theme file: `slot = "style"` lines, where a style is a
+    /// foreground color, optionally `on` a background color, plus any of
+    /// bold, dim, italic, underline and reverse. `base` names the built-in
+    /// theme the file starts from (dark by default) and `name` what to
+    /// call it. Slots that don't parse keep the base theme's style and are
+    /// reported rather than failing the whole file.
+    ///
+    /// ```toml
+    /// name = "solarized"
+    /// base = "dark"
+    /// key = "#268bd2 bold"
+    /// status-bar = "black on cyan"
+    /// ```
+    pub fn parse(text: &str) -> (Self, Vec<String>) {
+        // Tables are ignored, so slots can sit under [colors]
+        let (parsed, mut warnings) = parse_entries(text);
+        let mut entries = Vec::new();
+        for entry in parsed {
+            match entry.value {
+                Value::String(value) => entries.push((entry.line, entry.key, value)),
+                _ => warnings.push(format!("line {}: {}: expected a quoted style", entry.line, entry.key)),
+            }
+        }
+
+        let base = entries.iter().find(|(_, slot, _)| slot == "base").map(|(n, _, value)| (*n, value));
+        let mut theme = match base {
+            Some((n, name)) => Self::builtin(name).unwrap_or_else(|| {
+                warnings.push(format!("line {}: unknown base theme '{}'", n, name));
+                Self::dark()
+            }),
+            None => Self::dark(),
+        };
+        theme.name = "custom".to_string();
+
+        for (n, slot, value) in entries {
+            match slot.as_str() {
+                "base" => {}
+                "name" => theme.name = value,
+                _ => match theme.slot_mut(&slot) {
+                    Some(style) => match parse_style(&value) {
+                        Ok(parsed) => *style = parsed,
+                        Err(e) => warnings.push(format!("line {}: {}: {}", n, slot, e)),
+                    },
+                    None => warnings.push(format!("line {}: unknown slot '{}'", n, slot)),
+                },
+            }
+        }
+        (theme, warnings)
+    }
+
+    fn slot_mut(&mut self, slot: &str) -> Option<&mut Style> {
+        Some(match slot.replace('_', "-").as_str() {
+            "key" => &mut self.key,
+            "string" => &mut self.string,
+            "number" => &mut self.number,
+            "boolean" => &mut self.boolean,
+            "null" => &mut self.null,
+            "punctuation" => &mut self.punctuation,
+            "colon" => &mut self.colon,
+            "comment" => &mut self.comment,
+            "text" => &mut self.text,
+            "invalid" => &mut self.invalid,
+            "error" => &mut self.error,
+            "selection" => &mut self.selection,
+            "status-bar" => &mut self.status_bar,
+            "message-error" => &mut self.message_error,
+            "line-number" => &mut self.line_number,
+            "current-line-number" => &mut self.current_line_number,
+            "current-node" => &mut self.current_node,
+            "fold" => &mut self.fold,
+            "hint" => &mut self.hint,
+            "popup" => &mut self.popup,
+            "focus-border" => &mut self.focus_border,
+            "whitespace" => &mut self.whitespace,
+            "special" => &mut self.special,
+            "diff-added" => &mut self.diff_added,
+            "diff-removed" => &mut self.diff_removed,
+            "diff-changed" => &mut self.diff_changed,
+            "bookmark" => &mut self.bookmark,
+            _ => return None,
+        })
+    }
+}
+
+/// Parse "fg [on bg] [modifiers…]", e.g. "white on red bold"
+fn parse_style(value: &str) -> Result<Style> {
+    let mut style = Style::default();
+    let mut words = value.split_whitespace();
+    while let Some(word) = words.next() {
+        let modifier = match word.to_lowercase().as_str() {
+            "bold" => Modifier::BOLD,
+            "dim" => Modifier::DIM,
+            "italic" => Modifier::ITALIC,
+            "underline" | "underlined" => Modifier::UNDERLINED,
+            "reverse" | "reversed" => Modifier::REVERSED,
+            "on" => {
+                let Some(bg) = words.next() else { bail!("missing color after 'on'") };
+                style = style.bg(parse_color(bg)?);
+                continue;
+            }
+            _ => {
+                style = style.fg(parse_color(word)?);
+                continue;
+            }
+        };
+        style = style.add_modifier(modifier);
+    }
+    Ok(style)
+}
+
+fn parse_color(name: &str) -> Result<Color> {
+    match Color::from_str(name) {
+        Ok(color) => Ok(color),
+        Err(_) => bail!("invalid color '{}'", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_theme_file() {
+        let text = r##"
+# My theme
+name = "solarized"
+base = "light"
+
+[colors]
+key = "#268bd2 bold"
+string = "green"   # values
+status-bar = "black on cyan"
+line_number = "dark-gray dim"
+"##;
+        let (theme, warnings) = Theme::parse(text);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(theme.name, "solarized");
+        assert_eq!(theme.key, Style::default().fg(Color::Rgb(0x26, 0x8b, 0xd2)).add_modifier(Modifier::BOLD));
+        assert_eq!(theme.string, Style::default().fg(Color::Green));
+        assert_eq!(theme.status_bar, Style::default().fg(Color::Black).bg(Color::Cyan));
+        assert_eq!(theme.line_number, Style::default().fg(Color::DarkGray).add_modifier(Modifier::DIM));
+        // Slots the file leaves alone come from the base theme
+        assert_eq!(theme.number, Theme::light().number);
+    }
+
+    #[test]
+    fn test_invalid_color_falls_back() {
+        let text = "key = \"chartreuse\"\nnumber = \"red on\"\nstring = \"cyan\"\nbogus = \"red\"\n";
+        let (theme, warnings) = Theme::parse(text);
+        assert_eq!(theme.key, Theme::dark().key);
+        assert_eq!(theme.number, Theme::dark().number);
+        assert_eq!(theme.string, Style::default().fg(Color::Cyan));
+        assert_eq!(
+            warnings,
+            vec![
+                "line 1: key: invalid color 'chartreuse'",
+                "line 2: number: missing color after 'on'",
+                "line 4: unknown slot 'bogus'",
+            ]
+        );
+    }
+}
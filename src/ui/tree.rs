@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::buffer::Buffer;
+use crate::parser::node::NodeId;
+use crate::parser::{NodeKind, StructuralIndex};
+
+/// Children listed per page before a "more" row
+const PAGE_SIZE: usize = 100;
+
+/// Longest scalar preview shown next to a label, in characters
+const PREVIEW_CHARS: usize = 40;
+
+/// Which pane receives key presses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Focus {
+    #[default]
+    Text,
+    Tree,
+    /// The location list pane (:copen)
+    List,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeRowKind {
+    /// A node, and whether it has children to show
+    Node { id: NodeId, expandable: bool, expanded: bool },
+    /// Stand-in for children not listed yet (`parent` is None at the top level)
+    More { parent: Option<NodeId>, remaining: usize },
+}
+
+/// One line of the outline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeRow {
+    pub depth: usize,
+    pub label: String,
+    pub kind: TreeRowKind,
+}
+
+impl TreeRow {
+    pub fn node(&self) -> Option<NodeId> {
+        match self.kind {
+            TreeRowKind::Node { id, .. } => Some(id),
+            TreeRowKind::More { .. } => None,
+        }
+    }
+}
+
+/// Document outline built lazily from the structural index: only expanded
+/// containers are listed, a page of children at a time.
+#[derive(Debug, Clone, Default)]
+pub struct TreeView {
+    // Expanded containers and the pages of children they show, by start
+    // offset so they survive the index being rebuilt
+    expanded: HashSet<usize>,
+    pages: HashMap<usize, usize>,
+    root_pages: usize,
+    pub selected: usize,
+    pub scroll: usize,
+    // Rows as of the last refresh, and the index version they were built
+    // from; building walks every listed container's children
+    rows: Vec<TreeRow>,
+    built_for: Option<(usize, u64)>,
+    dirty: bool,
+}
+
+impl TreeView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rows as of the last `refresh`
+    pub fn rows(&self) -> &[TreeRow] {
+        &self.rows
+    }
+
+    /// Rebuild the rows if the tree changed or the index has grown.
+    /// `version` identifies the index contents, e.g. its length and generation.
+    pub fn refresh(&mut self, index: &StructuralIndex, buffer: &Buffer, version: (usize, u64)) {
+        if self.dirty || self.built_for != Some(version) {
+            self.rows = self.build_rows(index, buffer);
+            self.built_for = Some(version);
+            self.dirty = false;
+            self.move_selection(0);
+        }
+    }
+
+    /// Flatten the visible part of the tree into rows
+    fn build_rows(&self, index: &StructuralIndex, buffer: &Buffer) -> Vec<TreeRow> {
+        let mut rows = Vec::new();
+        let root_count = index.root_count();
+        let shown = root_count.min(self.root_pages.max(1) * PAGE_SIZE);
+        for n in 0..shown {
+            let Some(root) = index.nth_root(n) else { break };
+            let label = if root_count > 1 { format!("record {}", n + 1) } else { "root".to_string() };
+            self.push_node(&mut rows, index, buffer, root, label, 0);
+        }
+        if shown < root_count {
+            rows.push(TreeRow {
+                depth: 0,
+                label: more_label(root_count - shown),
+                kind: TreeRowKind::More { parent: None, remaining: root_count - shown },
+            });
+        }
+        rows
+    }
+
+    fn push_node(
+        &self,
+        rows: &mut Vec<TreeRow>,
+        index: &StructuralIndex,
+        buffer: &Buffer,
+        id: NodeId,
+        name: String,
+        depth: usize,
+    ) {
+        let Some(node) = index.get(id) else { return };
+        let count = index.child_count(id);
+        let expandable = count.is_some_and(|c| c.count > 0);
+        let expanded = expandable && self.expanded.contains(&node.start);
+        let label = match (node.kind, count) {
+            (NodeKind::Object, Some(count)) => format!("{} {{{} keys}}", name, count),
+            (NodeKind::Array, Some(count)) => format!("{} [{} items]", name, count),
+            _ => format!("{}: {}", name, preview(buffer, node.start, node.end)),
+        };
+        rows.push(TreeRow { depth, label, kind: TreeRowKind::Node { id, expandable, expanded } });
+        if !expanded {
+            return;
+        }
+
+        let limit = self.pages.get(&node.start).copied().unwrap_or(1) * PAGE_SIZE;
+        let is_object = node.kind == NodeKind::Object;
+        let mut children = index.child_iter(id);
+        let mut listed = 0;
+        while listed < limit {
+            let (name, child) = if is_object {
+                // Object children alternate key, value; list the values under their key
+                let Some(key) = children.next() else { break };
+                let name = index.get(key).map_or_else(String::new, |k| key_text(buffer, k.start, k.end));
+                match children.next() {
+                    Some(value) => (name, value),
+                    None => (name, key),
+                }
+            } else {
+                let Some(child) = children.next() else { break };
+                (format!("[{}]", listed), child)
+            };
+            self.push_node(rows, index, buffer, child, name, depth + 1);
+            listed += 1;
+        }
+        let total = count.map_or(listed, |c| c.count);
+        if listed < total {
+            rows.push(TreeRow {
+                depth: depth + 1,
+                label: more_label(total - listed),
+                kind: TreeRowKind::More { parent: Some(id), remaining: total - listed },
+            });
+        }
+    }
+
+    pub fn is_expanded(&self, index: &StructuralIndex, id: NodeId) -> bool {
+        index.get(id).is_some_and(|node| self.expanded.contains(&node.start))
+    }
+
+    pub fn expand(&mut self, index: &StructuralIndex, id: NodeId) {
+        if let Some(node) = index.get(id) {
+            self.dirty |= self.expanded.insert(node.start);
+        }
+    }
+
+    pub fn collapse(&mut self, index: &StructuralIndex, id: NodeId) {
+        if let Some(node) = index.get(id) {
+            self.expanded.remove(&node.start);
+            self.pages.remove(&node.start);
+            self.dirty = true;
+        }
+    }
+
+    /// List another page of a container's children (or of the top level)
+    pub fn show_more(&mut self, index: &StructuralIndex, parent: Option<NodeId>) {
+        match parent.and_then(|id| index.get(id)) {
+            Some(node) => *self.pages.entry(node.start).or_insert(1) += 1,
+            None => self.root_pages = self.root_pages.max(1) + 1,
+        }
+        self.dirty = true;
+    }
+
+    /// Move the selection by `delta` rows, clamped to the tree
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.rows.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// Select the deepest listed row on the path to the node at `offset`
+    pub fn select_offset(&mut self, index: &StructuralIndex, offset: usize) {
+        let rows = &self.rows;
+        let mut node = index.node_id_at(offset);
+        while let Some(id) = node {
+            if let Some(row) = rows.iter().position(|row| row.node() == Some(id)) {
+                self.selected = row;
+                return;
+            }
+            // A key's row is its value's
+            if index.kind(id) == Some(NodeKind::String)
+                && index.parent(id).and_then(|p| index.kind(p)) == Some(NodeKind::Object)
+            {
+                if let Some(row) = index
+                    .next_sibling(id)
+                    .and_then(|value| rows.iter().position(|row| row.node() == Some(value)))
+                {
+                    self.selected = row;
+                    return;
+                }
+            }
+            node = index.parent(id);
+        }
+    }
+
+    /// The row under the selection
+    pub fn selected_row(&self) -> Option<&TreeRow> {
+        self.rows.get(self.selected)
+    }
+
+    /// Scroll so the selected row is within a pane `height` rows tall
+    pub fn scroll_to_selected(&mut self, height: usize) {
+        self.scroll = super::scroll_to_keep(self.selected, self.scroll, height);
+    }
+}
+
+fn more_label(remaining: usize) -> String {
+    format!("… {} more", crate::parser::ChildCount { count: remaining, complete: true })
+}
+
+/// Source text of a scalar or key. Neither can span lines, so it's read
+/// from the line it starts on.
+fn source_text(buffer: &Buffer, start: usize, end: usize) -> String {
+    let line_idx = buffer.byte_offset_to_line(start);
+    let line = buffer.get_line(line_idx);
+    let col = start - buffer.line_to_byte_offset(line_idx);
+    let end = (col + (end - start)).min(line.len());
+    line.get(col..end).unwrap_or("").to_string()
+}
+
+fn key_text(buffer: &Buffer, start: usize, end: usize) -> String {
+    let text = source_text(buffer, start, end);
+    text.trim_matches('"').to_string()
+}
+
+fn preview(buffer: &Buffer, start: usize, end: usize) -> String {
+    let text = source_text(buffer, start, end);
+    if text.chars().count() > PREVIEW_CHARS {
+        let cut: String = text.chars().take(PREVIEW_CHARS).collect();
+        format!("{}…", cut)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Tokenizer;
+
+    fn setup(json: &str) -> (StructuralIndex, Buffer) {
+        let mut tokenizer = Tokenizer::new(json.to_string());
+        (StructuralIndex::from_tokens(&tokenizer.tokenize_all()), Buffer::from_text(json))
+    }
+
+    fn labels(rows: &[TreeRow]) -> Vec<(usize, &str)> {
+        rows.iter().map(|row| (row.depth, row.label.as_str())).collect()
+    }
+
+    #[test]
+    fn test_expand_object() {
+        let json = r#"{"name": "jim", "tags": ["a", "b"], "n": 1}"#;
+        let (index, buffer) = setup(json);
+        let mut tree = TreeView::new();
+
+        tree.refresh(&index, &buffer, (index.len(), 0));
+        assert_eq!(labels(tree.rows()), vec![(0, "root {3 keys}")]);
+
+        tree.expand(&index, 0);
+        let tags = index.node_id_at(json.find('[').unwrap()).unwrap();
+        tree.expand(&index, tags);
+        tree.refresh(&index, &buffer, (index.len(), 0));
+        assert_eq!(
+            labels(tree.rows()),
+            vec![
+                (0, "root {3 keys}"),
+                (1, "name: \"jim\""),
+                (1, "tags [2 items]"),
+                (2, "[0]: \"a\""),
+                (2, "[1]: \"b\""),
+                (1, "n: 1"),
+            ]
+        );
+
+        // The cursor on "b" selects its row; on the key "n" selects its value
+        tree.select_offset(&index, json.find("\"b\"").unwrap());
+        assert_eq!(tree.selected, 4);
+        tree.select_offset(&index, json.find("\"n\"").unwrap());
+        assert_eq!(tree.selected, 5);
+
+        // Collapsed parents fall back to the nearest listed ancestor
+        tree.collapse(&index, tags);
+        tree.refresh(&index, &buffer, (index.len(), 0));
+        tree.select_offset(&index, json.find("\"b\"").unwrap());
+        assert_eq!(tree.selected, 2);
+    }
+
+    #[test]
+    fn test_large_array_pages() {
+        let json = format!("[{}]", (0..250).map(|n| n.to_string()).collect::<Vec<_>>().join(","));
+        let (index, buffer) = setup(&json);
+        let mut tree = TreeView::new();
+        tree.expand(&index, 0);
+        tree.refresh(&index, &buffer, (index.len(), 0));
+
+        let rows = tree.rows();
+        assert_eq!(rows.len(), 1 + PAGE_SIZE + 1);
+        assert_eq!(rows[0].label, "root [250 items]");
+        assert_eq!(rows.last().unwrap().label, "… 150 more");
+        assert_eq!(rows.last().unwrap().kind, TreeRowKind::More { parent: Some(0), remaining: 150 });
+
+        tree.show_more(&index, Some(0));
+        tree.show_more(&index, Some(0));
+        tree.refresh(&index, &buffer, (index.len(), 0));
+        let rows = tree.rows();
+        assert_eq!(rows.len(), 1 + 250);
+        assert_eq!(rows.last().unwrap().label, "[249]: 249");
+    }
+}
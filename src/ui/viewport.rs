@@ -1,11 +1,54 @@
+#[derive(Debug, Clone)]
 pub struct Viewport {
     pub start_line: usize,
     pub height: usize,
+    /// First visible character column when lines aren't wrapped
+    pub start_col: usize,
+    /// Text columns available, after the gutter
+    pub width: usize,
+    /// Wrapped rows of `start_line` scrolled off the top (wrap mode only)
+    pub start_row: usize,
 }
 
+/// Columns kept between the cursor and the left/right edge when scrolling
+const SIDE_MARGIN: usize = 5;
+
 impl Viewport {
     pub fn new(start_line: usize, height: usize) -> Self {
-        Self { start_line, height }
+        Self { start_line, height, start_col: 0, width: 80, start_row: 0 }
+    }
+
+    /// Scroll horizontally so `col` is visible, with a few columns of margin
+    pub fn scroll_to_col(&mut self, col: usize) {
+        let width = self.width.max(1);
+        let margin = SIDE_MARGIN.min(width / 4);
+        if col < self.start_col + margin {
+            self.start_col = col.saturating_sub(margin);
+        } else if col + margin >= self.start_col + width {
+            self.start_col = col + margin + 1 - width;
+        }
+    }
+
+    pub fn scroll_left(&mut self, cols: usize) {
+        self.start_col = self.start_col.saturating_sub(cols);
+    }
+
+    pub fn scroll_right(&mut self, cols: usize) {
+        self.start_col = self.start_col.saturating_add(cols);
+    }
+
+    /// Range of columns shown
+    pub fn visible_cols(&self) -> std::ops::Range<usize> {
+        self.start_col..self.start_col + self.width.max(1)
+    }
+
+    /// The column nearest `col` that `scroll_to_col` would leave in place,
+    /// for keeping the cursor on screen after a manual scroll
+    pub fn clamp_col(&self, col: usize) -> usize {
+        let width = self.width.max(1);
+        let margin = SIDE_MARGIN.min(width / 4);
+        let low = if self.start_col == 0 { 0 } else { self.start_col + margin };
+        col.clamp(low, (self.start_col + width).saturating_sub(margin + 1).max(low))
     }
 
     pub fn scroll_down(&mut self) {
@@ -24,3 +67,31 @@ impl Viewport {
         self.start_line = self.start_line.saturating_sub(self.height / 2);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scroll_to_col() {
+        let mut viewport = Viewport::new(0, 10);
+        viewport.width = 40;
+
+        viewport.scroll_to_col(10);
+        assert_eq!(viewport.start_col, 0);
+        viewport.scroll_to_col(100);
+        assert_eq!(viewport.start_col, 66);
+        assert!(viewport.visible_cols().contains(&100));
+        viewport.scroll_to_col(70);
+        assert_eq!(viewport.start_col, 65);
+        viewport.scroll_to_col(0);
+        assert_eq!(viewport.start_col, 0);
+
+        // After a manual scroll the clamped column needs no further scrolling
+        viewport.scroll_right(30);
+        let col = viewport.clamp_col(2);
+        assert_eq!(col, 35);
+        viewport.scroll_to_col(col);
+        assert_eq!(viewport.start_col, 30);
+    }
+}
@@ -0,0 +1,209 @@
+use ratatui::layout::Rect;
+
+use crate::buffer::{Buffer, Cursor, TextChange};
+use crate::parser::node::NodeId;
+use crate::ui::viewport::Viewport;
+
+/// What each window keeps for itself; the buffer, undo history and index
+/// are shared
+#[derive(Debug, Clone)]
+pub struct View {
+    pub cursor: Cursor,
+    pub viewport: Viewport,
+    pub current_node_id: Option<NodeId>,
+    /// Byte offset of the first line shown, so edits elsewhere don't scroll
+    /// the window
+    pub top: usize,
+}
+
+impl View {
+    /// Shift the window's offsets over an edit made in another window
+    pub fn apply_change(&mut self, change: &TextChange) {
+        self.cursor.byte_offset = change.shift_offset(self.cursor.byte_offset);
+        self.top = change.shift_offset(self.top);
+        self.current_node_id = None;
+    }
+
+    /// Recompute lines and columns from the byte offsets after edits
+    pub fn resync(&mut self, buffer: &Buffer) {
+        let len = buffer.len_bytes();
+        self.cursor.move_to_offset(buffer, self.cursor.byte_offset.min(len));
+        self.viewport.start_line = buffer.byte_offset_to_line(self.top.min(len));
+        self.viewport.start_row = 0;
+    }
+}
+
+/// Ctrl-w directions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+/// The text windows, side by side (:vsplit) or stacked (:split). The
+/// focused window's state is live in the editor, so its entry here is only
+/// brought up to date when focus moves away.
+#[derive(Debug, Clone)]
+pub struct Windows {
+    views: Vec<View>,
+    active: usize,
+    vertical: bool,
+}
+
+impl Windows {
+    pub fn new(view: View) -> Self {
+        Self { views: vec![view], active: 0, vertical: false }
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.views.is_empty()
+    }
+
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Saved state of window `index` (stale for the active window)
+    pub fn view(&self, index: usize) -> &View {
+        &self.views[index]
+    }
+
+    /// Replace the saved state of window `index`
+    pub fn set_view(&mut self, index: usize, view: View) {
+        self.views[index] = view;
+    }
+
+    /// Every window except the focused one
+    pub fn inactive_mut(&mut self) -> impl Iterator<Item = &mut View> {
+        let active = self.active;
+        self.views.iter_mut().enumerate().filter(move |&(i, _)| i != active).map(|(_, view)| view)
+    }
+
+    /// Open a copy of the focused window above it (or to its left) and
+    /// focus the copy. Splitting the other way turns every window.
+    pub fn split(&mut self, vertical: bool, current: View) {
+        self.vertical = vertical;
+        self.views[self.active] = current.clone();
+        self.views.insert(self.active, current);
+    }
+
+    /// Close the focused window, returning the state of the one that takes
+    /// focus. The last window can't be closed.
+    pub fn close(&mut self) -> Option<View> {
+        if self.views.len() < 2 {
+            return None;
+        }
+        self.views.remove(self.active);
+        self.active = self.active.min(self.views.len() - 1);
+        Some(self.views[self.active].clone())
+    }
+
+    /// Move focus to window `index`, storing `current` as the state of the
+    /// window losing it and returning the state of the one gaining it
+    pub fn focus(&mut self, index: usize, current: View) -> Option<View> {
+        if index == self.active || index >= self.views.len() {
+            return None;
+        }
+        self.views[self.active] = current;
+        self.active = index;
+        Some(self.views[index].clone())
+    }
+
+    /// Window next to the focused one in `direction`, if any
+    pub fn neighbor(&self, direction: Direction) -> Option<usize> {
+        let (back, forward) = if self.vertical {
+            (Direction::Left, Direction::Right)
+        } else {
+            (Direction::Up, Direction::Down)
+        };
+        if direction == back {
+            self.active.checked_sub(1)
+        } else if direction == forward {
+            Some(self.active + 1).filter(|&i| i < self.views.len())
+        } else {
+            None
+        }
+    }
+
+    /// Share `area` between the windows, giving leftover cells to the last
+    pub fn areas(&self, area: Rect) -> Vec<Rect> {
+        let n = self.views.len() as u16;
+        let total = if self.vertical { area.width } else { area.height };
+        let size = total / n;
+        (0..n)
+            .map(|i| {
+                let start = i * size;
+                let len = if i + 1 == n { total - start } else { size };
+                if self.vertical {
+                    Rect { x: area.x + start, width: len, ..area }
+                } else {
+                    Rect { y: area.y + start, height: len, ..area }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(offset: usize) -> View {
+        View {
+            cursor: Cursor::from_byte_offset(offset),
+            viewport: Viewport::new(0, 10),
+            current_node_id: None,
+            top: 0,
+        }
+    }
+
+    #[test]
+    fn test_split_focus_close() {
+        let mut windows = Windows::new(view(0));
+        windows.split(true, view(5));
+        assert_eq!((windows.len(), windows.active()), (2, 0));
+        assert_eq!(windows.neighbor(Direction::Right), Some(1));
+        assert_eq!(windows.neighbor(Direction::Left), None);
+        assert_eq!(windows.neighbor(Direction::Down), None);
+
+        // The other window kept the state it was split from
+        let other = windows.focus(1, view(9)).unwrap();
+        assert_eq!(other.cursor.byte_offset, 5);
+        assert_eq!(windows.view(0).cursor.byte_offset, 9);
+        assert!(windows.focus(1, view(0)).is_none());
+
+        let remaining = windows.close().unwrap();
+        assert_eq!((windows.len(), windows.active()), (1, 0));
+        assert_eq!(remaining.cursor.byte_offset, 9);
+        assert!(windows.close().is_none());
+    }
+
+    #[test]
+    fn test_areas() {
+        let mut windows = Windows::new(view(0));
+        windows.split(false, view(0));
+        windows.split(false, view(0));
+        assert_eq!(
+            windows.areas(Rect::new(0, 0, 80, 23)),
+            vec![Rect::new(0, 0, 80, 7), Rect::new(0, 7, 80, 7), Rect::new(0, 14, 80, 9)]
+        );
+        windows.split(true, view(0));
+        assert_eq!(windows.areas(Rect::new(10, 0, 81, 5))[3], Rect::new(70, 0, 21, 5));
+    }
+
+    #[test]
+    fn test_edits_in_another_window() {
+        let mut view = view(20);
+        view.top = 10;
+        view.apply_change(&TextChange { offset: 0, removed: 0, inserted: 4 });
+        assert_eq!((view.cursor.byte_offset, view.top), (24, 14));
+        view.apply_change(&TextChange { offset: 12, removed: 20, inserted: 1 });
+        assert_eq!((view.cursor.byte_offset, view.top), (12, 12));
+    }
+}